@@ -10,7 +10,7 @@ fn main() {
     
     // Compile daemon_wrapper.c
     let output = Command::new("gcc")
-        .args(&[
+        .args([
             "-Wall", 
             "-Wextra", 
             "-O2",
@@ -0,0 +1,412 @@
+//! Attach sessions streaming a local or shadow instance's output
+
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use nhi::instance::InstanceManager;
+use nhi::process_manager::ProcessManager;
+use nhi::shadow_instance_manager::{InputDelivery, ShadowInstanceManager, LIVE_OUTPUT_RENEW_INTERVAL, SHADOW_INPUT_ACK_TIMEOUT};
+
+use crate::cli::CliState;
+use crate::ui::AttachUI;
+use uuid::Uuid;
+
+/// How often a non-foreground attach checks whether the process is still there
+const ATTACH_EXIT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+/// How long attach keeps showing output after the process exited before it detaches
+const ATTACH_EXIT_GRACE: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Notices when the attached process is gone, so a non-foreground attach can leave on its
+/// own once the process's last output had time to show
+struct ExitWatch {
+    exited_at: Option<tokio::time::Instant>,
+    last_check: tokio::time::Instant,
+}
+
+impl ExitWatch {
+    fn new() -> Self {
+        Self { exited_at: None, last_check: tokio::time::Instant::now() }
+    }
+
+    fn exited(&self) -> bool {
+        self.exited_at.is_some()
+    }
+
+    /// Whether the process has exited and the grace period after it is over
+    async fn should_detach(&mut self, uuid: &Uuid, process_manager: &ProcessManager) -> bool {
+        if self.exited_at.is_none() && self.last_check.elapsed() >= ATTACH_EXIT_POLL_INTERVAL {
+            self.last_check = tokio::time::Instant::now();
+            if process_manager.process_exited(uuid).await {
+                self.exited_at = Some(tokio::time::Instant::now());
+            }
+        }
+        self.exited_at.is_some_and(|at| at.elapsed() >= ATTACH_EXIT_GRACE)
+    }
+}
+
+/// Attach to an instance's output. In foreground mode the session ends when the
+/// process exits (Ctrl+C interrupts the program) and its exit status is returned.
+pub(crate) async fn enter_attach_mode(
+    instance_id: &str,
+    uuid: Uuid,
+    cli_state: &Arc<Mutex<CliState>>,
+    _instance_manager: &Arc<Mutex<InstanceManager>>,
+    process_manager: &Arc<ProcessManager>,
+    foreground: bool,
+) -> Result<Option<std::process::ExitStatus>, Box<dyn std::error::Error>> {
+    let mut ui = AttachUI::new()?;
+    ui.enter_attach_mode(instance_id)?;
+
+    // Get historical output and display it
+    if let Some(history) = process_manager.get_output_history(&uuid).await {
+        for line in &history {
+            ui.add_output_line(line.clone())?;
+        }
+    }
+
+    // Subscribe to real-time output
+    let mut output_receiver = process_manager.subscribe_to_output(&uuid).await;
+
+    // Set attached state
+    {
+        let mut state = cli_state.lock().await;
+        state.attached_instance = Some(instance_id.to_string());
+    }
+
+    let mut exit_status = None;
+    let mut exit_watch = ExitWatch::new();
+
+    // Main attach loop
+    loop {
+        // In foreground mode, leave once the process has exited and its output is drained
+        if foreground {
+            if let Some(status) = process_manager.try_exit_status(&uuid).await {
+                if let Some(ref mut receiver) = output_receiver {
+                    while let Ok(output) = receiver.try_recv() {
+                        ui.add_output_line(output)?;
+                    }
+                }
+                exit_status = Some(status);
+                break;
+            }
+        } else if exit_watch.should_detach(&uuid, process_manager).await {
+            if let Some(ref mut receiver) = output_receiver {
+                while let Ok(output) = receiver.try_recv() {
+                    ui.add_output_line(output)?;
+                }
+            }
+            ui.add_output_line("[process exited, detaching]".to_string())?;
+            break;
+        }
+
+        // Handle real-time output
+        if let Some(ref mut receiver) = output_receiver {
+            match receiver.try_recv() {
+                Ok(output) => {
+                    ui.add_output_line(output)?;
+                }
+                Err(tokio::sync::broadcast::error::TryRecvError::Empty) => {
+                    // No new output, continue
+                }
+                Err(tokio::sync::broadcast::error::TryRecvError::Lagged(skipped)) => {
+                    // The history still has every line; say so rather than skipping silently
+                    ui.add_output_line(format!(
+                        "[{} lines skipped: output is faster than the display (see --output-channel-capacity and logs)]",
+                        skipped
+                    ))?;
+                }
+                Err(tokio::sync::broadcast::error::TryRecvError::Closed) => {
+                    // Output stream closed, process might have ended
+                    ui.add_output_line("[Process output stream closed]".to_string())?;
+                    output_receiver = None;
+                }
+            }
+        }
+
+        // Handle user input
+        if let Some(input) = ui.handle_input()? {
+            if input == "detach" && foreground {
+                // The program owns the session; interrupt it like a foreground job
+                if let Some(pid) = process_manager.get_process_pid(&uuid).await {
+                    let _ = nix::sys::signal::kill(
+                        nix::unistd::Pid::from_raw(pid as i32),
+                        nix::sys::signal::Signal::SIGINT,
+                    );
+                }
+            } else if input == "detach" {
+                break;
+            } else {
+                // Forward input to process
+                if let Err(e) = process_manager.send_input(&uuid, input).await {
+                    ui.add_output_line(format!("[Error sending input: {}]", e))?;
+                }
+            }
+        }
+
+        // Small delay to prevent busy waiting
+        tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+    }
+
+    // Clean up
+    ui.exit_attach_mode()?;
+
+    // Clear attached state
+    {
+        let mut state = cli_state.lock().await;
+        state.attached_instance = None;
+        if let Some(task) = state.output_task.take() {
+            task.abort();
+        }
+    }
+
+    if exit_watch.exited() {
+        println!("Process of instance {} exited; detached", instance_id);
+    } else if !foreground {
+        println!("Detached from instance: {}", instance_id);
+    }
+    Ok(exit_status)
+}
+
+/// Foreground run without a terminal to attach (e.g. commands piped in by a script): print
+/// the program's output until it exits and return its exit status
+pub(crate) async fn wait_in_foreground(uuid: Uuid, process_manager: &Arc<ProcessManager>) -> Option<std::process::ExitStatus> {
+    if let Some(history) = process_manager.get_output_history(&uuid).await {
+        for line in &history {
+            println!("{}", line);
+        }
+    }
+    let mut output_receiver = process_manager.subscribe_to_output(&uuid).await;
+
+    loop {
+        if let Some(status) = process_manager.try_exit_status(&uuid).await {
+            if let Some(ref mut receiver) = output_receiver {
+                while let Ok(output) = receiver.try_recv() {
+                    println!("{}", output);
+                }
+            }
+            return Some(status);
+        }
+        if process_manager.process_exited(&uuid).await {
+            // A child that exited since the poll above keeps its status; anything else is not a
+            // child of this node, so there is no status to report
+            return process_manager.try_exit_status(&uuid).await;
+        }
+
+        match output_receiver.as_mut() {
+            Some(receiver) => match tokio::time::timeout(ATTACH_EXIT_POLL_INTERVAL, receiver.recv()).await {
+                Ok(Ok(output)) => println!("{}", output),
+                Ok(Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped))) => {
+                    println!("[{} lines skipped: output is faster than the display]", skipped)
+                }
+                Ok(Err(tokio::sync::broadcast::error::RecvError::Closed)) => output_receiver = None,
+                Err(_) => {}
+            },
+            None => tokio::time::sleep(ATTACH_EXIT_POLL_INTERVAL).await,
+        }
+    }
+}
+
+pub(crate) async fn enter_shadow_attach_mode(
+    instance_id: &str,
+    uuid: Uuid,
+    cli_state: &Arc<Mutex<CliState>>,
+    _instance_manager: &Arc<Mutex<InstanceManager>>,
+    shadow_manager: &Option<Arc<tokio::sync::RwLock<ShadowInstanceManager>>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let shadow_mgr = match shadow_manager {
+        Some(mgr) => mgr,
+        None => {
+            println!("Shadow management is not available (networking disabled)");
+            return Ok(());
+        }
+    };
+
+    // The instance runs on another node; ask it to stream output live instead of in batches
+    let live = shadow_mgr.read().await.request_live_output(uuid, true).await;
+
+    let mut ui = AttachUI::new()?;
+    let title = if live.is_ok() { "Live remote view" } else { "Shadow" };
+    ui.enter_attach_mode(&format!("{} ({})", instance_id, title))?;
+
+    // Track total bytes already displayed; the buffer itself may be trimmed
+    let mut last_displayed_total: u64 = 0;
+
+    // Get shadow instance info and display historical output
+    {
+        let shadow_mgr_read = shadow_mgr.read().await;
+        if let Some(shadow_info) = shadow_mgr_read.get_shadow_instance(uuid).await {
+            let source = shadow_info.source_node_id.to_string()[..8].to_uppercase();
+            match live {
+                Ok(_) => ui.add_output_line(format!("[Live remote view - Source Node: {}]", source))?,
+                Err(ref e) => ui.add_output_line(format!("[Shadow Instance - Source Node: {} - buffered output only: {}]", source, e))?,
+            }
+            ui.add_output_line(format!("[Last Sync: {}]",
+                shadow_info.last_sync_time.format("%H:%M:%S")))?;
+
+            // Display historical output from buffer
+            if !shadow_info.output_buffer.is_empty() {
+                let output_str = String::from_utf8_lossy(&shadow_info.output_buffer);
+                for line in output_str.lines() {
+                    if !line.is_empty() {
+                        ui.add_output_line(line.to_string())?;
+                    }
+                }
+            }
+            last_displayed_total = shadow_info.output_bytes_total;
+        }
+    }
+
+    // Set attached state
+    {
+        let mut state = cli_state.lock().await;
+        state.attached_instance = Some(instance_id.to_string());
+    }
+
+
+    // Forwarded inputs awaiting the source node's acknowledgement
+    let mut pending_inputs: Vec<(Uuid, String, std::time::Instant)> = Vec::new();
+    let mut live_renewed_at = std::time::Instant::now();
+
+    // Main loop for shadow attach mode
+    loop {
+        // Keep the source's live stream going; it lapses on its own if this session disappears
+        if live.is_ok() && live_renewed_at.elapsed() >= LIVE_OUTPUT_RENEW_INTERVAL {
+            if let Err(e) = shadow_mgr.read().await.request_live_output(uuid, true).await {
+                warn!("Failed to renew live output for {}: {}", instance_id, e);
+            }
+            live_renewed_at = std::time::Instant::now();
+        }
+
+        // Check for new shadow sync data and display new output
+        {
+            let shadow_mgr_read = shadow_mgr.read().await;
+            if let Some(shadow_info) = shadow_mgr_read.get_shadow_instance(uuid).await {
+                // Check if there's new output data
+                if shadow_info.output_bytes_total > last_displayed_total {
+                    // Get the new content (whatever is still buffered if we fell behind the trim)
+                    let new_bytes = (shadow_info.output_bytes_total - last_displayed_total) as usize;
+                    let start = shadow_info.output_buffer.len().saturating_sub(new_bytes);
+                    let new_output = String::from_utf8_lossy(&shadow_info.output_buffer[start..]);
+                    for line in new_output.lines() {
+                        if !line.is_empty() {
+                            ui.add_output_line(line.to_string())?;
+                        }
+                    }
+                    last_displayed_total = shadow_info.output_bytes_total;
+                }
+            }
+        }
+
+        // Report inputs the source node has acknowledged, or given up on
+        if !pending_inputs.is_empty() {
+            let shadow_mgr_read = shadow_mgr.read().await;
+            let mut still_pending = Vec::new();
+            for (input_id, input, sent_at) in pending_inputs.drain(..) {
+                match shadow_mgr_read.input_delivery(input_id).await {
+                    Some(InputDelivery::Delivered) => {
+                        ui.add_output_line(format!("[Shadow] Delivered: {}", input))?;
+                    }
+                    Some(InputDelivery::Rejected(reason)) => {
+                        ui.add_output_line(format!("[Shadow] Rejected by source: {} ({})", input, reason))?;
+                    }
+                    Some(InputDelivery::Pending) if sent_at.elapsed() < SHADOW_INPUT_ACK_TIMEOUT => {
+                        still_pending.push((input_id, input, sent_at));
+                    }
+                    _ => {
+                        shadow_mgr_read.forget_input(input_id).await;
+                        ui.add_output_line(format!("[Shadow] No acknowledgement from source for: {}", input))?;
+                    }
+                }
+            }
+            pending_inputs = still_pending;
+        }
+
+        // Handle user input; the input line is edited locally and sent on Enter
+        if let Some(input) = ui.handle_input()? {
+            if input == "detach" {
+                break;
+            } else {
+                // Forward input to the source node through shadow manager
+                let shadow_mgr_read = shadow_mgr.read().await;
+                match shadow_mgr_read.forward_input_to_source(uuid, input.clone()).await {
+                    Ok(input_id) => {
+                        ui.add_output_line(format!("[Shadow] Sent: {}", input))?;
+                        pending_inputs.push((input_id, input, std::time::Instant::now()));
+                    }
+                    Err(e) => {
+                        ui.add_output_line(format!("[Shadow] Failed to forward input: {}", e))?;
+                    }
+                }
+            }
+        }
+
+        // Small delay to prevent busy waiting
+        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    }
+
+    // Clean up
+    ui.exit_attach_mode()?;
+    if live.is_ok() {
+        if let Err(e) = shadow_mgr.read().await.request_live_output(uuid, false).await {
+            warn!("Failed to stop live output for {}: {}", instance_id, e);
+        }
+    }
+
+    // Clear attached state
+    {
+        let mut state = cli_state.lock().await;
+        state.attached_instance = None;
+        if let Some(task) = state.output_task.take() {
+            task.abort();
+        }
+    }
+
+    println!("Detached from shadow instance: {}", instance_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn attach_detaches_on_its_own_after_a_short_lived_process_exits() {
+        crate::test_support::use_scratch_dir();
+        let process_manager = ProcessManager::new();
+        let uuid = Uuid::new_v4();
+        process_manager
+            .start_process(uuid, "/bin/sh", &["-c".to_string(), "echo bye; sleep 0.3".to_string()], &std::path::PathBuf::from("/"))
+            .await
+            .unwrap();
+
+        let started = tokio::time::Instant::now();
+        let mut exit_watch = ExitWatch::new();
+        while !exit_watch.should_detach(&uuid, &process_manager).await {
+            assert!(started.elapsed() < std::time::Duration::from_secs(5), "attach never detached from the exited process");
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        assert!(exit_watch.exited());
+        // Not before the process exited and its last output had the grace period to arrive
+        assert!(started.elapsed() >= std::time::Duration::from_millis(300) + ATTACH_EXIT_GRACE);
+        assert_eq!(process_manager.get_output_history(&uuid).await.unwrap(), ["[STDOUT] bye"]);
+    }
+
+    #[tokio::test]
+    async fn attach_stays_while_the_process_runs() {
+        crate::test_support::use_scratch_dir();
+        let process_manager = ProcessManager::new();
+        let uuid = Uuid::new_v4();
+        process_manager.start_process(uuid, "sleep", &["30".to_string()], &std::path::PathBuf::from("/")).await.unwrap();
+
+        let mut exit_watch = ExitWatch::new();
+        let started = tokio::time::Instant::now();
+        while started.elapsed() < ATTACH_EXIT_POLL_INTERVAL * 3 {
+            assert!(!exit_watch.should_detach(&uuid, &process_manager).await);
+            tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        }
+        assert!(!exit_watch.exited());
+        process_manager.stop_process(&uuid).await.unwrap();
+    }
+}
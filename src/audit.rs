@@ -0,0 +1,177 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// A single audit record, written as one JSON line
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    pub action: String,
+    pub instance_id: Option<String>,
+    pub node_id: Option<String>,
+    pub outcome: String,
+    pub error: Option<String>,
+}
+
+/// Append-only JSON-lines audit log
+pub struct AuditLogger {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditLogger {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Path of the audit log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a record to the audit log
+    pub fn write_record(&self, record: &AuditRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(line.as_bytes())?;
+        file.flush()
+    }
+}
+
+/// Default audit log directory: the data directory, so `audit.log` sits beside the instances it
+/// records; log rotation only touches `logs/` and `purge` only `instance_*` directories
+pub fn default_audit_dir() -> PathBuf {
+    crate::instance::data_dir().to_path_buf()
+}
+
+/// Handle the managers record their actions through; records nothing unless opened (--audit-log)
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    logger: Option<Arc<AuditLogger>>,
+}
+
+impl AuditLog {
+    /// Write audit records to `audit.log` in the given directory
+    pub fn open(dir: &Path) -> std::io::Result<Self> {
+        let logger = AuditLogger::new(&dir.join("audit.log"))?;
+        info!("Audit log enabled at {}", logger.path().display());
+        Ok(Self { logger: Some(Arc::new(logger)) })
+    }
+
+    /// Record an action; `error` is None on success. Instances are always logged by their full ID.
+    pub fn record(&self, action: &str, instance_id: Option<Uuid>, node_id: Option<&str>, error: Option<&str>) {
+        let Some(ref logger) = self.logger else {
+            return;
+        };
+
+        let record = AuditRecord {
+            timestamp: Utc::now(),
+            action: action.to_string(),
+            instance_id: instance_id.map(|id| id.to_string()),
+            node_id: node_id.map(|s| s.to_string()),
+            outcome: if error.is_none() { "success" } else { "failure" }.to_string(),
+            error: error.map(|s| s.to_string()),
+        };
+
+        if let Err(e) = logger.write_record(&record) {
+            warn!("Failed to write audit record: {}", e);
+        }
+    }
+
+    /// Record the outcome of an operation returning a Result
+    pub fn record_result<T, E: std::fmt::Display>(
+        &self,
+        action: &str,
+        instance_id: Option<Uuid>,
+        node_id: Option<&str>,
+        result: &std::result::Result<T, E>,
+    ) {
+        match result {
+            Ok(_) => self.record(action, instance_id, node_id, None),
+            Err(e) => self.record(action, instance_id, node_id, Some(&e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_carry_the_full_instance_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let audit = AuditLog::open(dir.path()).unwrap();
+        let instance_id = Uuid::new_v4();
+
+        audit.record("restore", Some(instance_id), None, Some("restore failed"));
+
+        let log = std::fs::read_to_string(dir.path().join("audit.log")).unwrap();
+        let last: serde_json::Value = serde_json::from_str(log.lines().last().unwrap()).unwrap();
+        assert_eq!(last["instance_id"], instance_id.to_string());
+        assert_eq!(last["outcome"], "failure");
+        assert_eq!(last["error"], "restore failed");
+    }
+
+    #[test]
+    fn separate_logs_do_not_share_records() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (audit_a, audit_b) = (AuditLog::open(dir_a.path()).unwrap(), AuditLog::open(dir_b.path()).unwrap());
+
+        audit_a.record("start", Some(Uuid::new_v4()), None, None);
+        AuditLog::default().record("start", Some(Uuid::new_v4()), None, None);
+
+        assert_eq!(std::fs::read_to_string(dir_a.path().join("audit.log")).unwrap().lines().count(), 1);
+        assert!(std::fs::read_to_string(dir_b.path().join("audit.log")).unwrap().is_empty());
+        drop(audit_b);
+    }
+
+    #[test]
+    fn default_dir_is_the_data_dir() {
+        crate::test_support::use_scratch_dir();
+        assert_eq!(default_audit_dir(), crate::instance::data_dir());
+        assert!(default_audit_dir().is_absolute());
+    }
+
+    #[tokio::test]
+    async fn start_then_stop_writes_two_ordered_records() {
+        crate::test_support::use_scratch_dir();
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = crate::instance::InstanceManager::new();
+        manager.set_audit_log(AuditLog::open(dir.path()).unwrap());
+        let process_manager = Arc::new(crate::process_manager::ProcessManager::new());
+
+        let short_id = manager.start_instance("sleep".to_string(), vec!["30".to_string()], process_manager.clone()).await.unwrap();
+        manager.stop_instance(&short_id, process_manager).await.unwrap();
+        let instance_id = manager.get_instance_by_id(&short_id).unwrap().id.to_string();
+
+        let log = std::fs::read_to_string(dir.path().join("audit.log")).unwrap();
+        let records: Vec<serde_json::Value> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(records.len(), 2, "{}", log);
+        for (record, action) in records.iter().zip(["start", "stop"]) {
+            let fields: Vec<&str> = record.as_object().unwrap().keys().map(String::as_str).collect();
+            assert_eq!(fields, ["action", "error", "instance_id", "node_id", "outcome", "timestamp"]);
+            assert_eq!(record["action"], action);
+            assert_eq!(record["instance_id"], instance_id);
+            assert_eq!(record["outcome"], "success");
+            assert!(record["error"].is_null() && record["node_id"].is_null());
+        }
+        let timestamp = |record: &serde_json::Value| record["timestamp"].as_str().unwrap().parse::<DateTime<Utc>>().unwrap();
+        assert!(timestamp(&records[0]) <= timestamp(&records[1]));
+    }
+}
@@ -0,0 +1,413 @@
+use crate::audit::AuditLog;
+use crate::checkpoint_engine::{CheckpointEngine, CriuEngine, DumpPriority};
+use crate::criu_manager::CriuManager;
+use crate::instance::InstanceManager;
+use crate::message_protocol::{NetworkConfig, NodeId};
+use crate::migration_manager::MigrationManager;
+use crate::network_manager::NetworkManager;
+use crate::node_manager::NodeManager;
+use crate::process_manager::ProcessManager;
+use crate::shadow_instance_manager::{FailoverPolicy, ShadowInstanceManager};
+use crate::sudo_utils::PrivilegeCommand;
+use crate::types::{CheckpointStorage, OutputTimestamps, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Configuration for the core NHI managers
+pub struct NhiBuilder {
+    criu_path: PathBuf,
+    privilege_command: PrivilegeCommand,
+    dedup_checkpoints: bool,
+    output_timestamps: OutputTimestamps,
+    max_output_line_bytes: usize,
+    output_channel_capacity: usize,
+    checkpoint_key: Option<[u8; 32]>,
+    audit_log_dir: Option<PathBuf>,
+    engine: Option<Arc<dyn CheckpointEngine>>,
+    criu_dump_args: Vec<String>,
+    criu_restore_args: Vec<String>,
+    dump_priority: DumpPriority,
+}
+
+impl Default for NhiBuilder {
+    fn default() -> Self {
+        Self {
+            criu_path: PathBuf::from("./criu/bin/criu"),
+            privilege_command: PrivilegeCommand::Sudo,
+            dedup_checkpoints: false,
+            output_timestamps: OutputTimestamps::Off,
+            max_output_line_bytes: crate::process_manager::DEFAULT_MAX_OUTPUT_LINE_BYTES,
+            output_channel_capacity: crate::process_manager::DEFAULT_OUTPUT_CHANNEL_CAPACITY,
+            checkpoint_key: None,
+            audit_log_dir: None,
+            engine: None,
+            criu_dump_args: Vec::new(),
+            criu_restore_args: Vec::new(),
+            dump_priority: DumpPriority::default(),
+        }
+    }
+}
+
+impl NhiBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Path to the CRIU binary (ignored when a custom engine is set)
+    pub fn criu_path<P: Into<PathBuf>>(mut self, criu_path: P) -> Self {
+        self.criu_path = criu_path.into();
+        self
+    }
+
+    /// Wrap privileged CRIU calls in sudo (default: true); shorthand for `privilege_command`
+    pub fn use_sudo(mut self, use_sudo: bool) -> Self {
+        self.privilege_command = if use_sudo { PrivilegeCommand::Sudo } else { PrivilegeCommand::None };
+        self
+    }
+
+    /// Command privileged CRIU calls are wrapped in (default: sudo)
+    pub fn privilege_command(mut self, command: PrivilegeCommand) -> Self {
+        self.privilege_command = command;
+        self
+    }
+
+    /// Store checkpoint files in a per-instance content-addressed blob store (default: false)
+    pub fn dedup_checkpoints(mut self, dedup: bool) -> Self {
+        self.dedup_checkpoints = dedup;
+        self
+    }
+
+    /// Timestamp format for captured output of instances that do not choose one (default: off)
+    pub fn output_timestamps(mut self, timestamps: OutputTimestamps) -> Self {
+        self.output_timestamps = timestamps;
+        self
+    }
+
+    /// Encrypt checkpoint images at rest with this AES-256-GCM key (default: none)
+    pub fn checkpoint_key(mut self, key: Option<[u8; 32]>) -> Self {
+        self.checkpoint_key = key;
+        self
+    }
+
+    /// Split captured output lines longer than this many bytes (default: 1 MiB)
+    pub fn max_output_line_bytes(mut self, max: usize) -> Self {
+        self.max_output_line_bytes = max;
+        self
+    }
+
+    /// Output lines buffered per process for attached viewers before they start missing lines (default: 4096)
+    pub fn output_channel_capacity(mut self, capacity: usize) -> Self {
+        self.output_channel_capacity = capacity;
+        self
+    }
+
+    /// Write an audit log to `audit.log` in the given directory
+    pub fn audit_log_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.audit_log_dir = Some(dir.into());
+        self
+    }
+
+    /// Extra arguments appended to every CRIU dump, e.g. `--ext-unix-sk`; each must start with `-`
+    pub fn criu_dump_args(mut self, args: Vec<String>) -> Self {
+        self.criu_dump_args = args;
+        self
+    }
+
+    /// Extra arguments appended to every CRIU restore; recorded with each checkpoint so later restores reuse them
+    pub fn criu_restore_args(mut self, args: Vec<String>) -> Self {
+        self.criu_restore_args = args;
+        self
+    }
+
+    /// Run auto-sync CRIU dumps (and manual ones if `priority.manual`) under nice/ionice (default: unchanged)
+    pub fn dump_priority(mut self, priority: DumpPriority) -> Self {
+        self.dump_priority = priority;
+        self
+    }
+
+    /// Use a custom checkpoint engine instead of the CRIU binary
+    pub fn engine(mut self, engine: Arc<dyn CheckpointEngine>) -> Self {
+        self.engine = Some(engine);
+        self
+    }
+
+    /// Create the core managers; fails on an extra CRIU argument that is not an option or an
+    /// audit log directory that cannot be written
+    pub fn build(self) -> Result<Nhi> {
+        let _ = crate::instance::data_dir();
+        if self.checkpoint_key.is_some() {
+            info!("Checkpoint images are encrypted at rest ({})", crate::checkpoint_crypto::CIPHER);
+        }
+        if !self.dump_priority.wrapper_args().is_empty() {
+            info!(
+                "CRIU dumps of {} run under: {}",
+                if self.dump_priority.manual { "all checkpoints" } else { "auto-sync" },
+                self.dump_priority.wrapper_args().join(" ")
+            );
+        }
+
+        // Checked even with a custom engine, so a bad argument never goes unnoticed
+        let criu_engine = CriuEngine::new(&self.criu_path)
+            .with_privilege_command(self.privilege_command)
+            .with_extra_args(self.criu_dump_args, self.criu_restore_args)?
+            .with_dump_priority(self.dump_priority);
+        let engine: Arc<dyn CheckpointEngine> = match self.engine {
+            Some(engine) => engine,
+            None => Arc::new(criu_engine),
+        };
+        info!("Using {} checkpoint engine", engine.name());
+
+        let audit = match self.audit_log_dir {
+            Some(ref dir) => AuditLog::open(dir)?,
+            None => AuditLog::default(),
+        };
+        let storage = CheckpointStorage { dedup: self.dedup_checkpoints, key: self.checkpoint_key };
+
+        let mut instance_manager = InstanceManager::new();
+        instance_manager.set_audit_log(audit.clone());
+
+        let mut process_manager = ProcessManager::new();
+        process_manager.set_default_output_timestamps(self.output_timestamps);
+        process_manager.set_max_output_line_bytes(self.max_output_line_bytes);
+        process_manager.set_output_channel_capacity(self.output_channel_capacity);
+
+        let mut criu_manager = CriuManager::new_with_engine(engine);
+        criu_manager.set_storage(storage);
+
+        Ok(Nhi {
+            instance_manager: Arc::new(Mutex::new(instance_manager)),
+            process_manager: Arc::new(process_manager),
+            criu_manager: Arc::new(criu_manager),
+            node_manager: None,
+            shadow_manager: None,
+            migration_manager: None,
+            criu_path: self.criu_path,
+            privilege_command: self.privilege_command,
+            storage,
+            audit,
+        })
+    }
+}
+
+/// Tuning for the networked managers `Nhi::start_cluster` creates
+#[derive(Debug, Clone)]
+pub struct ClusterOptions {
+    pub image_streamer_path: PathBuf,
+    pub shadow_buffer_bytes: usize,
+    pub shadow_output_batch: Duration,
+    pub restore_timeout: Duration,
+    pub shadow_gc_grace: Duration,
+    pub instance_reconcile_interval: Option<Duration>, // None never re-advertises running instances
+    pub auto_failover: bool,
+    pub failover_policy: FailoverPolicy,
+    pub failover_grace: Duration,
+    pub incremental_max_age: Option<Duration>, // None always takes full migration dumps
+    pub sync_idle_threshold: u64,
+}
+
+impl Default for ClusterOptions {
+    fn default() -> Self {
+        Self {
+            image_streamer_path: PathBuf::from(crate::capabilities::DEFAULT_IMAGE_STREAMER_PATH),
+            shadow_buffer_bytes: crate::shadow_instance_manager::DEFAULT_SHADOW_OUTPUT_BUFFER_BYTES,
+            shadow_output_batch: Duration::from_millis(crate::shadow_instance_manager::DEFAULT_SHADOW_OUTPUT_BATCH_MS),
+            restore_timeout: Duration::from_secs(crate::shadow_instance_manager::DEFAULT_RESTORE_TIMEOUT_SECS),
+            shadow_gc_grace: Duration::from_secs(600),
+            instance_reconcile_interval: Some(Duration::from_secs(60)),
+            auto_failover: false,
+            failover_policy: FailoverPolicy::LowestNodeId,
+            failover_grace: Duration::from_secs(30),
+            incremental_max_age: Some(Duration::from_secs(60)),
+            sync_idle_threshold: 0,
+        }
+    }
+}
+
+/// Core managers for running instances locally, plus the networked ones once `start_cluster` ran
+#[derive(Clone)]
+pub struct Nhi {
+    pub instance_manager: Arc<Mutex<InstanceManager>>,
+    pub process_manager: Arc<ProcessManager>,
+    pub criu_manager: Arc<CriuManager>,
+    pub node_manager: Option<Arc<NodeManager>>,
+    pub shadow_manager: Option<Arc<RwLock<ShadowInstanceManager>>>,
+    pub migration_manager: Option<Arc<MigrationManager>>,
+    criu_path: PathBuf,
+    privilege_command: PrivilegeCommand,
+    storage: CheckpointStorage,
+    audit: AuditLog,
+}
+
+impl Nhi {
+    pub fn builder() -> NhiBuilder {
+        NhiBuilder::new()
+    }
+
+    /// Node manager advertising this host's capabilities and recording peers in the audit log
+    pub fn create_node_manager(&self, config: NetworkConfig, image_streamer_path: &Path) -> anyhow::Result<NodeManager> {
+        let capabilities = crate::capabilities::detect_capabilities(&self.criu_path, self.privilege_command, image_streamer_path);
+        let mut node_manager = NodeManager::new_with_capabilities(config, Some(capabilities))?;
+        node_manager.set_audit_log(self.audit.clone());
+        Ok(node_manager)
+    }
+
+    /// Shadow manager for `node_id`, restoring through the configured engine
+    pub fn create_shadow_manager(&self, node_id: NodeId) -> ShadowInstanceManager {
+        let mut shadow_manager = ShadowInstanceManager::new_with_engine(
+            node_id,
+            self.instance_manager.clone(),
+            self.process_manager.clone(),
+            self.criu_manager.engine().clone(),
+        );
+        shadow_manager.set_storage(self.storage);
+        shadow_manager.set_privilege_command(self.privilege_command);
+        shadow_manager.set_audit_log(self.audit.clone());
+        shadow_manager
+    }
+
+    /// Migration manager for `node_id`, dumping through the configured engine
+    pub fn create_migration_manager(&self, node_id: NodeId, network_manager: Arc<NetworkManager>) -> MigrationManager {
+        let mut migration_manager = MigrationManager::new_with_engine(
+            node_id,
+            network_manager,
+            self.instance_manager.clone(),
+            self.process_manager.clone(),
+            self.criu_manager.engine().clone(),
+        );
+        migration_manager.set_storage(self.storage);
+        migration_manager.set_audit_log(self.audit.clone());
+        migration_manager
+    }
+
+    /// Start networking and wire the node, shadow and migration managers together. If the
+    /// node cannot start, a standalone migration manager still serves local sync checkpoints
+    /// and the error is returned.
+    pub async fn start_cluster(&mut self, config: NetworkConfig, options: ClusterOptions) -> anyhow::Result<()> {
+        let outbound_queue_capacity = config.outbound_queue_capacity;
+        let started = match self.create_node_manager(config, &options.image_streamer_path) {
+            Ok(node_manager) => {
+                let node_manager = Arc::new(node_manager);
+                node_manager.start().await.map(|_| node_manager)
+            }
+            Err(e) => Err(e),
+        };
+        let node_manager = match started {
+            Ok(node_manager) => node_manager,
+            Err(e) => {
+                warn!("Creating standalone migration manager for checkpoint functionality");
+                let node_id = Uuid::new_v4();
+                let standalone_config = NetworkConfig {
+                    listen_addr: "127.0.0.1:0".parse().unwrap(),
+                    node_name: "standalone".to_string(),
+                    discovery_port: 0,
+                    heartbeat_interval_secs: 30,
+                    max_connections: 1,
+                    discovery_enabled: false,
+                    outbound_queue_capacity,
+                    ..NetworkConfig::default()
+                };
+                let migration_manager =
+                    self.create_migration_manager(node_id, Arc::new(NetworkManager::new(standalone_config, node_id)));
+                match migration_manager.start().await {
+                    Ok(()) => self.migration_manager = Some(Arc::new(migration_manager)),
+                    Err(e) => warn!("Failed to start standalone migration manager: {}", e),
+                }
+                return Err(e);
+            }
+        };
+        let node_id = node_manager.node_id();
+
+        let mut shadow_manager = self.create_shadow_manager(node_id);
+        shadow_manager.set_output_buffer_limit(options.shadow_buffer_bytes);
+        shadow_manager.set_output_batch_window(options.shadow_output_batch);
+        shadow_manager.set_restore_timeout(options.restore_timeout);
+        shadow_manager.set_network_sender(node_manager.network_manager().get_sender());
+        let shadow_manager = Arc::new(RwLock::new(shadow_manager));
+
+        let mut migration_manager = self.create_migration_manager(node_id, node_manager.network_manager().clone());
+        migration_manager.set_incremental_max_age(options.incremental_max_age);
+        migration_manager.set_sync_idle_threshold(options.sync_idle_threshold);
+        migration_manager.set_image_streamer_path(&options.image_streamer_path);
+        migration_manager.set_cluster_state(node_manager.cluster_state().clone());
+        migration_manager.set_shadow_manager(shadow_manager.clone());
+        let migration_manager = match migration_manager.start().await {
+            Ok(()) => Some(Arc::new(migration_manager)),
+            Err(e) => {
+                warn!("Failed to start migration manager: {}", e);
+                None
+            }
+        };
+
+        node_manager.set_shadow_manager(shadow_manager.clone()).await;
+        self.process_manager.set_shadow_manager(shadow_manager.clone()).await;
+        ShadowInstanceManager::start_gc_task(
+            shadow_manager.clone(),
+            node_manager.cluster_state().clone(),
+            Duration::from_secs(60),
+            options.shadow_gc_grace,
+        );
+        ShadowInstanceManager::start_stream_gap_task(shadow_manager.clone());
+        if let Some(interval) = options.instance_reconcile_interval {
+            ShadowInstanceManager::start_reconcile_task(shadow_manager.clone(), interval);
+        }
+        if options.auto_failover {
+            ShadowInstanceManager::start_failover_task(
+                shadow_manager.clone(),
+                node_manager.cluster_state().clone(),
+                node_manager.instance_registry().clone(),
+                Duration::from_secs(5),
+                options.failover_grace,
+                options.failover_policy,
+            );
+        }
+        if let Some(ref migration_manager) = migration_manager {
+            node_manager.set_migration_manager(migration_manager.clone()).await;
+        }
+
+        self.node_manager = Some(node_manager);
+        self.shadow_manager = Some(shadow_manager);
+        self.migration_manager = migration_manager;
+        Ok(())
+    }
+
+    /// Start a program as a new instance, returning its short ID
+    pub async fn start_instance(&self, program: &str, args: &[String]) -> Result<String> {
+        let mut manager = self.instance_manager.lock().await;
+        manager
+            .start_instance(program.to_string(), args.to_vec(), self.process_manager.clone())
+            .await
+    }
+
+    /// Stop a running instance
+    pub async fn stop_instance(&self, instance_id: &str) -> Result<()> {
+        let mut manager = self.instance_manager.lock().await;
+        manager.stop_instance(instance_id, self.process_manager.clone()).await
+    }
+
+    /// Checkpoint a running instance
+    pub async fn checkpoint_instance(&self, instance_id: &str, checkpoint_name: &str) -> Result<()> {
+        let mut manager = self.instance_manager.lock().await;
+        manager
+            .checkpoint_instance(
+                instance_id,
+                checkpoint_name,
+                false,
+                false,
+                self.criu_manager.clone(),
+                self.process_manager.clone(),
+            )
+            .await
+    }
+
+    /// Restore a checkpoint as a new instance, returning its short ID
+    pub async fn restore_instance(&self, checkpoint_name: &str) -> Result<String> {
+        let mut manager = self.instance_manager.lock().await;
+        manager
+            .restore_instance(checkpoint_name, self.criu_manager.clone(), self.process_manager.clone())
+            .await
+    }
+}
@@ -0,0 +1,89 @@
+use crate::checkpoint_engine::{CheckpointEngine, CriuEngine};
+use crate::sudo_utils::PrivilegeCommand;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Node can manage local instances
+pub const CAP_INSTANCE_MANAGEMENT: &str = "instance_management";
+/// CRIU binary is installed
+pub const CAP_CRIU: &str = "criu";
+/// `criu check` passes, so the kernel supports checkpointing
+pub const CAP_CRIU_CHECKPOINT: &str = "criu_checkpoint";
+/// `criu check` passes, so the kernel supports restoring
+pub const CAP_CRIU_RESTORE: &str = "criu_restore";
+/// criu-image-streamer binary is installed
+pub const CAP_IMAGE_STREAMER: &str = "criu_image_streamer";
+
+/// Default location of the criu-image-streamer binary
+pub const DEFAULT_IMAGE_STREAMER_PATH: &str = "./criu-image-streamer/target/release/criu-image-streamer";
+
+/// Probe this host and return the capabilities it can advertise; `criu check` runs through `privilege_command`
+pub fn detect_capabilities(criu_path: &Path, privilege_command: PrivilegeCommand, image_streamer_path: &Path) -> Vec<String> {
+    let mut capabilities = vec![CAP_INSTANCE_MANAGEMENT.to_string()];
+
+    let engine = CriuEngine::new(criu_path).with_privilege_command(privilege_command);
+    if engine.criu_path().exists() {
+        capabilities.push(CAP_CRIU.to_string());
+
+        match engine.check() {
+            Ok(output) if output.success => {
+                capabilities.push(CAP_CRIU_CHECKPOINT.to_string());
+                capabilities.push(CAP_CRIU_RESTORE.to_string());
+            }
+            Ok(output) => {
+                warn!("criu check failed, checkpoint/restore not advertised: {}", output.stderr.trim());
+            }
+            Err(e) => {
+                warn!("Could not run criu check, checkpoint/restore not advertised: {}", e);
+            }
+        }
+    } else {
+        warn!("CRIU binary not found at {:?}, checkpoint/restore not advertised", engine.criu_path());
+    }
+
+    if image_streamer_path.is_file() {
+        capabilities.push(CAP_IMAGE_STREAMER.to_string());
+    } else {
+        info!("criu-image-streamer not found at {:?}, migrations fall back to tar transfer", image_streamer_path);
+    }
+
+    info!("Detected node capabilities: {}", capabilities.join(", "));
+    capabilities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn has(capabilities: &[String], name: &str) -> bool {
+        capabilities.iter().any(|c| c == name)
+    }
+
+    #[test]
+    fn the_streamer_capability_follows_the_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let streamer = dir.path().join("criu-image-streamer");
+        std::fs::write(&streamer, "").unwrap();
+        let criu = Path::new("/bin/true");
+
+        assert!(has(&detect_capabilities(criu, PrivilegeCommand::None, &streamer), CAP_IMAGE_STREAMER));
+        assert!(!has(&detect_capabilities(criu, PrivilegeCommand::None, &dir.path().join("missing")), CAP_IMAGE_STREAMER));
+        // A directory at the path is not a streamer either
+        assert!(!has(&detect_capabilities(criu, PrivilegeCommand::None, dir.path()), CAP_IMAGE_STREAMER));
+    }
+
+    #[test]
+    fn checkpointing_is_advertised_only_when_criu_check_passes() {
+        let streamer = Path::new("/nonexistent/criu-image-streamer");
+
+        // Stand-ins for criu whose `check` passes and fails
+        let passing = detect_capabilities(Path::new("/bin/true"), PrivilegeCommand::None, streamer);
+        assert!(has(&passing, CAP_CRIU) && has(&passing, CAP_CRIU_CHECKPOINT) && has(&passing, CAP_CRIU_RESTORE), "{:?}", passing);
+
+        let failing = detect_capabilities(Path::new("/bin/false"), PrivilegeCommand::None, streamer);
+        assert!(has(&failing, CAP_CRIU) && !has(&failing, CAP_CRIU_CHECKPOINT), "{:?}", failing);
+
+        let missing = detect_capabilities(Path::new("/nonexistent/criu"), PrivilegeCommand::None, streamer);
+        assert_eq!(missing, vec![CAP_INSTANCE_MANAGEMENT.to_string()]);
+    }
+}
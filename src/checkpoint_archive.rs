@@ -0,0 +1,318 @@
+use crate::types::{CriuCliError, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Component, Path};
+use tracing::debug;
+
+/// Directory holding the base images of an incremental checkpoint; its files travel as "parent/<file>"
+pub const PARENT_IMAGES_DIR: &str = "parent";
+
+/// In a delta archive: the sender's name for the checkpoint the delta builds on
+pub const PARENT_REF_FILE: &str = "parent_ref";
+
+/// The sender's name for a received checkpoint, so later deltas can find their base
+pub const SOURCE_NAME_FILE: &str = "source_checkpoint";
+
+/// Name prefix of the sync checkpoints every shadow receives; only these can be sent as a delta base
+const DELTA_BASE_PREFIX: &str = "auto-sync-";
+
+/// Append one entry in the checkpoint framing: name length (u32 LE), name, data length (u32 LE), data.
+/// Every producer and consumer of checkpoint archives goes through this and `decode_entries`.
+pub fn encode_entry<W: Write>(writer: &mut W, name: &str, data: &[u8]) -> std::io::Result<()> {
+    let too_large = |what: &str| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} of {} exceeds 4 GiB", what, name));
+    let name_len = u32::try_from(name.len()).map_err(|_| too_large("name"))?;
+    let data_len = u32::try_from(data.len()).map_err(|_| too_large("data"))?;
+    writer.write_all(&name_len.to_le_bytes())?;
+    writer.write_all(name.as_bytes())?;
+    writer.write_all(&data_len.to_le_bytes())?;
+    writer.write_all(data)
+}
+
+/// Split framed entries back into (name, data). Truncated input and names that would
+/// escape the target directory are errors, not silently dropped entries.
+pub fn decode_entries(mut framed: &[u8]) -> Result<Vec<(String, &[u8])>> {
+    fn take<'a>(framed: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+        if framed.len() < len {
+            return Err(CriuCliError::IncompatibleCheckpoint("checkpoint archive is truncated".to_string()));
+        }
+        let (head, tail) = framed.split_at(len);
+        *framed = tail;
+        Ok(head)
+    }
+    fn take_len(framed: &mut &[u8]) -> Result<usize> {
+        let bytes = take(framed, 4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    let mut entries = Vec::new();
+    while !framed.is_empty() {
+        let name_len = take_len(&mut framed)?;
+        let name = std::str::from_utf8(take(&mut framed, name_len)?)
+            .map_err(|_| CriuCliError::IncompatibleCheckpoint("checkpoint archive holds a non-UTF-8 file name".to_string()))?
+            .to_string();
+        // Only "<file>", "parent/<file>", "parent/parent/<file>" and so on
+        let mut parts = Path::new(&name).components().rev();
+        let safe = matches!(parts.next(), Some(Component::Normal(file)) if file != PARENT_IMAGES_DIR)
+            && parts.all(|part| part == Component::Normal(PARENT_IMAGES_DIR.as_ref()));
+        if !safe {
+            return Err(CriuCliError::IncompatibleCheckpoint(format!("checkpoint archive holds an invalid file name {:?}", name)));
+        }
+        let data_len = take_len(&mut framed)?;
+        entries.push((name, take(&mut framed, data_len)?));
+    }
+    Ok(entries)
+}
+
+/// Frame every file of a checkpoint directory, sorted by name. The base images of an incremental
+/// dump are included as "parent/<file>", theirs as "parent/parent/<file>" and so on.
+pub fn pack_dir(checkpoint_dir: &Path) -> Result<Vec<u8>> {
+    let mut framed = Vec::new();
+    let mut dir = checkpoint_dir.to_path_buf();
+    let mut prefix = String::new();
+    loop {
+        let mut entries: Vec<_> = fs::read_dir(&dir)?.flatten().collect();
+        entries.sort_by_key(|entry| entry.file_name());
+        let mut parent_dir = None;
+        for entry in entries {
+            let path = entry.path();
+            if path.is_file() {
+                let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+                encode_entry(&mut framed, &name, &fs::read(&path)?)?;
+            } else if path.is_dir() && entry.file_name() == PARENT_IMAGES_DIR {
+                parent_dir = Some(path);
+            }
+        }
+        match parent_dir {
+            Some(parent_dir) => {
+                dir = parent_dir;
+                prefix.push_str(PARENT_IMAGES_DIR);
+                prefix.push('/');
+            }
+            None => break,
+        }
+    }
+    Ok(framed)
+}
+
+/// Frame a sync checkpoint for shadows that received its predecessors. A dump building on an
+/// earlier auto-sync names that base in `PARENT_REF_FILE` instead of carrying its images;
+/// other dumps are framed whole, like `pack_dir`.
+pub fn pack_delta(checkpoint_dir: &Path) -> Result<Vec<u8>> {
+    let base = parent_name(checkpoint_dir).filter(|base| base.starts_with(DELTA_BASE_PREFIX));
+    let sealed = checkpoint_dir.join(crate::checkpoint_crypto::SEALED_ARCHIVE).exists();
+    let mut framed = match base {
+        // A sealed archive holds its base images already
+        Some(_) if sealed => pack_files(checkpoint_dir)?,
+        Some(ref base) => {
+            let mut framed = pack_files(checkpoint_dir)?;
+            encode_entry(&mut framed, PARENT_REF_FILE, base.as_bytes())?;
+            framed
+        }
+        None => pack_dir(checkpoint_dir)?,
+    };
+    if let Some(name) = checkpoint_dir.file_name() {
+        encode_entry(&mut framed, SOURCE_NAME_FILE, name.to_string_lossy().as_bytes())?;
+    }
+    Ok(framed)
+}
+
+/// Frame the files directly inside a checkpoint directory, sorted by name
+fn pack_files(checkpoint_dir: &Path) -> Result<Vec<u8>> {
+    let mut framed = Vec::new();
+    let mut entries: Vec<_> = fs::read_dir(checkpoint_dir)?.flatten().collect();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        if entry.file_type()?.is_file() {
+            encode_entry(&mut framed, &entry.file_name().to_string_lossy(), &fs::read(entry.path())?)?;
+        }
+    }
+    Ok(framed)
+}
+
+/// Name of the checkpoint an incremental dump's `parent` link points at
+pub fn parent_name(checkpoint_dir: &Path) -> Option<String> {
+    let target = fs::read_link(checkpoint_dir.join(PARENT_IMAGES_DIR)).ok()?;
+    Some(target.file_name()?.to_string_lossy().into_owned())
+}
+
+/// Checkpoints in `checkpoints_dir` that another checkpoint links as its `parent`; deleting one
+/// would leave the incremental dumps built on it unrestorable
+pub fn referenced_bases(checkpoints_dir: &Path) -> HashSet<String> {
+    fs::read_dir(checkpoints_dir)
+        .map(|entries| entries.flatten().filter_map(|entry| parent_name(&entry.path())).collect())
+        .unwrap_or_default()
+}
+
+/// Link a received delta to this node's copy of its base, found by the sender's name among
+/// the checkpoints next to it. Fails if the base never arrived here.
+pub fn link_received_base(checkpoint_dir: &Path) -> Result<()> {
+    let base = match fs::read_to_string(checkpoint_dir.join(PARENT_REF_FILE)) {
+        Ok(base) => base,
+        Err(_) => return Ok(()),
+    };
+    let checkpoints_dir = checkpoint_dir.parent()
+        .ok_or_else(|| CriuCliError::IncompatibleCheckpoint(format!("{} has no parent directory", checkpoint_dir.display())))?;
+    let local = fs::read_dir(checkpoints_dir)?
+        .flatten()
+        .filter(|entry| entry.path() != checkpoint_dir)
+        .find(|entry| fs::read_to_string(entry.path().join(SOURCE_NAME_FILE)).is_ok_and(|name| name == base))
+        .ok_or_else(|| CriuCliError::CheckpointNotFound(format!("base {} of the received delta", base)))?;
+    std::os::unix::fs::symlink(Path::new("..").join(local.file_name()), checkpoint_dir.join(PARENT_IMAGES_DIR))?;
+    debug!("Linked received delta {:?} to base {:?}", checkpoint_dir, local.path());
+    Ok(())
+}
+
+/// Write framed entries into `target_dir`, creating `parent/` directories as needed.
+/// Returns the number of files written.
+pub fn unpack_into(framed: &[u8], target_dir: &Path) -> Result<usize> {
+    let entries = decode_entries(framed)?;
+    for (name, data) in &entries {
+        let path = target_dir.join(name);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, data)?;
+    }
+    Ok(entries.len())
+}
+
+/// `pack_dir` followed by gzip, the form checkpoints take on the wire
+pub fn compress_dir(checkpoint_dir: &Path) -> Result<Vec<u8>> {
+    let framed = pack_dir(checkpoint_dir)?;
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&framed)?;
+    Ok(encoder.finish()?)
+}
+
+/// `pack_delta` followed by gzip
+pub fn compress_delta(checkpoint_dir: &Path) -> Result<Vec<u8>> {
+    let framed = pack_delta(checkpoint_dir)?;
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&framed)?;
+    Ok(encoder.finish()?)
+}
+
+/// Inverse of `compress_dir` and `compress_delta`
+pub fn decompress_into(compressed: &[u8], target_dir: &Path) -> Result<usize> {
+    let mut framed = Vec::new();
+    GzDecoder::new(compressed).read_to_end(&mut framed)?;
+    unpack_into(&framed, target_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framed(names: &[&str]) -> Vec<u8> {
+        let mut framed = Vec::new();
+        for name in names {
+            encode_entry(&mut framed, name, b"data").unwrap();
+        }
+        framed
+    }
+
+    #[test]
+    fn decoding_accepts_only_files_and_parent_images() {
+        assert!(decode_entries(&framed(&["pages-1.img", "parent/pages-1.img", "parent/parent/core.img"])).is_ok());
+        for name in ["../escape", "parent/../../escape", "/etc/passwd", "other/file", "parent", "./file", ""] {
+            assert!(decode_entries(&framed(&[name])).is_err(), "accepted {:?}", name);
+        }
+    }
+
+    #[test]
+    fn sync_deltas_leave_the_base_images_behind_and_link_the_received_base() {
+        let source = tempfile::tempdir().unwrap();
+        let base = source.path().join("auto-sync-1");
+        let delta = source.path().join("auto-sync-2");
+        fs::create_dir_all(&base).unwrap();
+        fs::create_dir_all(&delta).unwrap();
+        fs::write(base.join("pages-1.img"), "base pages").unwrap();
+        fs::write(delta.join("pages-1.img"), "dirty pages").unwrap();
+        std::os::unix::fs::symlink("../auto-sync-1", delta.join(PARENT_IMAGES_DIR)).unwrap();
+        assert_eq!(referenced_bases(source.path()), HashSet::from(["auto-sync-1".to_string()]));
+
+        // The first sync goes out whole, later ones as deltas on it
+        let full = pack_delta(&base).unwrap();
+        let packed = pack_delta(&delta).unwrap();
+        let names: Vec<String> = decode_entries(&packed).unwrap().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["pages-1.img", PARENT_REF_FILE, SOURCE_NAME_FILE]);
+
+        let shadow = tempfile::tempdir().unwrap();
+        let received_base = shadow.path().join("sync-100");
+        let received_delta = shadow.path().join("sync-200");
+        unpack_into(&full, &received_base).unwrap();
+        unpack_into(&packed, &received_delta).unwrap();
+        link_received_base(&received_delta).unwrap();
+        assert_eq!(fs::read_to_string(received_delta.join("parent/pages-1.img")).unwrap(), "base pages");
+
+        // A shadow that missed the base can't use the delta
+        let missed = tempfile::tempdir().unwrap();
+        unpack_into(&packed, &missed.path().join("sync-200")).unwrap();
+        assert!(link_received_base(&missed.path().join("sync-200")).is_err());
+    }
+
+    /// Deterministic xorshift64*, so a failing seed reproduces
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn below(&mut self, bound: u64) -> usize {
+            (self.next() % bound) as usize
+        }
+    }
+
+    /// Up to 12 files with distinct names, some in nested parent image dirs, of 0 to 8 KiB each
+    fn random_files(rng: &mut Rng) -> std::collections::BTreeMap<String, Vec<u8>> {
+        const NAME_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789-_.";
+        let mut files = std::collections::BTreeMap::new();
+        for _ in 0..rng.below(13) {
+            let mut name = format!("{}/", PARENT_IMAGES_DIR).repeat(rng.below(3));
+            name.push(char::from(b'a' + rng.below(26) as u8));
+            for _ in 0..rng.below(20) {
+                name.push(char::from(NAME_CHARS[rng.below(NAME_CHARS.len() as u64)]));
+            }
+            let data = (0..rng.below(8193)).map(|_| rng.next() as u8).collect();
+            files.insert(name, data);
+        }
+        files
+    }
+
+    #[test]
+    fn random_file_sets_survive_a_round_trip_byte_for_byte() {
+        for seed in 1..=64u64 {
+            let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            let files = random_files(&mut rng);
+
+            let mut framed = Vec::new();
+            for (name, data) in &files {
+                encode_entry(&mut framed, name, data).unwrap();
+            }
+            let decoded: Vec<(String, Vec<u8>)> =
+                decode_entries(&framed).unwrap().into_iter().map(|(name, data)| (name, data.to_vec())).collect();
+            assert_eq!(decoded, files.clone().into_iter().collect::<Vec<_>>(), "seed {}", seed);
+
+            // Through the directory packing and gzip used on the wire
+            let source = tempfile::tempdir().unwrap();
+            for (name, data) in &files {
+                let path = source.path().join(name);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, data).unwrap();
+            }
+            let target = tempfile::tempdir().unwrap();
+            assert_eq!(decompress_into(&compress_dir(source.path()).unwrap(), target.path()).unwrap(), files.len(), "seed {}", seed);
+            for (name, data) in &files {
+                assert_eq!(&fs::read(target.path().join(name)).unwrap(), data, "seed {} file {}", seed, name);
+            }
+        }
+    }
+}
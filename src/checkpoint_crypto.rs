@@ -0,0 +1,261 @@
+use crate::checkpoint_archive::{decode_entries, encode_entry, PARENT_IMAGES_DIR};
+use crate::checkpoint_descriptor::{CheckpointDescriptor, DESCRIPTOR_FILE};
+use crate::types::{CriuCliError, Result};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Encrypted archive that replaces the image files of a sealed checkpoint
+pub const SEALED_ARCHIVE: &str = "checkpoint.enc";
+
+/// Cipher recorded in the descriptor of sealed checkpoints
+pub const CIPHER: &str = "aes-256-gcm";
+
+/// Files left in plaintext: they carry no process memory and are read before images are
+const PLAINTEXT_FILES: [&str; 2] = [DESCRIPTOR_FILE, "migration_metadata.json"];
+
+/// Parse a 256-bit key written as 64 hex digits
+pub fn parse_key(hex: &str) -> std::result::Result<[u8; 32], String> {
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err("checkpoint key must be 64 hex digits (256 bits)".to_string());
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| "checkpoint key must be 64 hex digits (256 bits)".to_string())?;
+    }
+    Ok(key)
+}
+
+/// Read a key file holding either 32 raw bytes or 64 hex digits
+pub fn load_key_file(path: &Path) -> Result<[u8; 32]> {
+    let contents = fs::read(path)?;
+    if let Ok(key) = <[u8; 32]>::try_from(contents.as_slice()) {
+        return Ok(key);
+    }
+    parse_key(&String::from_utf8_lossy(&contents))
+        .map_err(|e| CriuCliError::ParseError(format!("{}: {}", path.display(), e)))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Whether `checkpoint_dir` holds a sealed archive instead of image files
+pub fn is_sealed(checkpoint_dir: &Path) -> bool {
+    checkpoint_dir.join(SEALED_ARCHIVE).is_file()
+}
+
+/// Files to seal, as (archive name, path); base images of an incremental dump are
+/// named "parent/<file>", theirs "parent/parent/<file>" and so on
+fn collect_files(dir: &Path, prefix: &str, files: &mut Vec<(String, PathBuf)>) -> Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.flatten().collect();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Ok(metadata) = fs::symlink_metadata(entry.path()) else {
+            continue;
+        };
+        if metadata.is_file() {
+            if prefix.is_empty() && (PLAINTEXT_FILES.contains(&name.as_str()) || name == SEALED_ARCHIVE) {
+                continue;
+            }
+            files.push((format!("{}{}", prefix, name), entry.path()));
+        } else if metadata.is_dir() && name == PARENT_IMAGES_DIR {
+            collect_files(&entry.path(), &format!("{}{}/", prefix, PARENT_IMAGES_DIR), files)?;
+        }
+    }
+    Ok(())
+}
+
+/// Frame files in the shared checkpoint archive format
+fn pack(files: &[(String, PathBuf)]) -> Result<Vec<u8>> {
+    let mut packed = Vec::new();
+    for (name, path) in files {
+        encode_entry(&mut packed, name, &fs::read(path)?)?;
+    }
+    Ok(packed)
+}
+
+/// Encrypt the image files of a freshly dumped checkpoint into `checkpoint.enc` and remove
+/// the plaintext. The nonce goes into the descriptor, which is created if the dump path
+/// does not write one. Returns false without touching the directory when `key` is None.
+pub fn seal_checkpoint(checkpoint_dir: &Path, instance_id: Uuid, key: Option<[u8; 32]>) -> Result<bool> {
+    let Some(key) = key else {
+        return Ok(false);
+    };
+    seal_with_key(checkpoint_dir, instance_id, &key)?;
+    Ok(true)
+}
+
+fn seal_with_key(checkpoint_dir: &Path, instance_id: Uuid, key: &[u8; 32]) -> Result<()> {
+    let mut files = Vec::new();
+    collect_files(checkpoint_dir, "", &mut files)?;
+    let packed = pack(&files)?;
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    // The instance ID is authenticated too, so an archive cannot be passed off as another instance's
+    let sealed = cipher
+        .encrypt(&nonce, Payload { msg: &packed, aad: instance_id.as_bytes() })
+        .map_err(|_| CriuCliError::CriuError("Failed to encrypt checkpoint".to_string()))?;
+    fs::write(checkpoint_dir.join(SEALED_ARCHIVE), &sealed)?;
+
+    for (name, path) in &files {
+        if let Err(e) = fs::remove_file(path) {
+            warn!("Failed to remove plaintext {} from {:?}: {}", name, checkpoint_dir, e);
+        }
+    }
+    let parent_dir = checkpoint_dir.join(PARENT_IMAGES_DIR);
+    if fs::symlink_metadata(&parent_dir).is_ok_and(|metadata| metadata.is_dir()) {
+        fs::remove_dir_all(&parent_dir)?;
+    }
+
+    let mut descriptor = match CheckpointDescriptor::load(checkpoint_dir)? {
+        Some(descriptor) => descriptor,
+        None => CheckpointDescriptor::new(checkpoint_dir, instance_id, None, None)?,
+    };
+    descriptor.instance_id = instance_id;
+    descriptor.encryption = Some(CIPHER.to_string());
+    descriptor.encryption_nonce = Some(to_hex(&nonce));
+    descriptor.refresh_files(checkpoint_dir)?;
+    descriptor.save(checkpoint_dir)?;
+
+    info!("Sealed {} checkpoint files into {:?} ({} bytes)", files.len(), checkpoint_dir.join(SEALED_ARCHIVE), sealed.len());
+    Ok(())
+}
+
+/// Plaintext images extracted from a sealed checkpoint for the length of a restore;
+/// dropping it removes them again and leaves only the archive
+#[must_use]
+pub struct UnsealedCheckpoint {
+    checkpoint_dir: PathBuf,
+    files: Vec<PathBuf>,
+    /// Sealed checkpoint the `parent` link of an incremental dump points at
+    parent: Option<Box<UnsealedCheckpoint>>,
+}
+
+impl Drop for UnsealedCheckpoint {
+    fn drop(&mut self) {
+        for path in &self.files {
+            let _ = fs::remove_file(path);
+        }
+        // Only images packed into the archive; a `parent` link to another checkpoint stays
+        let parent_dir = self.checkpoint_dir.join(PARENT_IMAGES_DIR);
+        if fs::symlink_metadata(&parent_dir).is_ok_and(|metadata| metadata.is_dir()) {
+            let _ = fs::remove_dir_all(&parent_dir);
+        }
+        debug!("Removed {} decrypted files from {:?}", self.files.len(), self.checkpoint_dir);
+    }
+}
+
+/// Decrypt a sealed checkpoint in place so CRIU can read it, along with the sealed base
+/// its `parent` link points at. Returns None for checkpoints that were never sealed;
+/// fails if `key` is None or does not open the archive.
+pub fn unseal_checkpoint(checkpoint_dir: &Path, key: Option<[u8; 32]>) -> Result<Option<UnsealedCheckpoint>> {
+    if !is_sealed(checkpoint_dir) {
+        return Ok(None);
+    }
+    let descriptor = CheckpointDescriptor::load(checkpoint_dir)?
+        .ok_or_else(|| CriuCliError::IncompatibleCheckpoint(format!("{} without {}", SEALED_ARCHIVE, DESCRIPTOR_FILE)))?;
+    if descriptor.encryption.as_deref() != Some(CIPHER) {
+        return Err(CriuCliError::IncompatibleCheckpoint(format!(
+            "unsupported checkpoint encryption {}",
+            descriptor.encryption.as_deref().unwrap_or("(none)")
+        )));
+    }
+    let nonce = descriptor
+        .encryption_nonce
+        .as_deref()
+        .and_then(|hex| (hex.len() == 24 && hex.is_ascii()).then_some(hex))
+        .and_then(|hex| (0..12).map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()).collect::<Option<Vec<u8>>>())
+        .ok_or_else(|| CriuCliError::IncompatibleCheckpoint("missing or malformed encryption nonce".to_string()))?;
+    let key = key.ok_or_else(|| {
+        CriuCliError::IncompatibleCheckpoint("checkpoint is encrypted; start nhi with --checkpoint-key or --checkpoint-key-file".to_string())
+    })?;
+
+    let sealed = fs::read(checkpoint_dir.join(SEALED_ARCHIVE))?;
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let packed = cipher
+        .decrypt(Nonce::from_slice(&nonce), Payload { msg: &sealed, aad: descriptor.instance_id.as_bytes() })
+        .map_err(|_| CriuCliError::IncompatibleCheckpoint("checkpoint key does not match or the archive was modified".to_string()))?;
+
+    let mut unsealed = UnsealedCheckpoint { checkpoint_dir: checkpoint_dir.to_path_buf(), files: Vec::new(), parent: None };
+    for (name, data) in decode_entries(&packed)? {
+        let path = checkpoint_dir.join(&name);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, data)?;
+        unsealed.files.push(path);
+    }
+    debug!("Decrypted {} checkpoint files into {:?}", unsealed.files.len(), checkpoint_dir);
+
+    let parent_dir = checkpoint_dir.join(PARENT_IMAGES_DIR);
+    if fs::symlink_metadata(&parent_dir).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        unsealed.parent = unseal_checkpoint(&parent_dir, Some(key))?.map(Box::new);
+    }
+    Ok(Some(unsealed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn write_images(dir: &Path) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("inventory.img"), b"inventory").unwrap();
+        fs::write(dir.join("pages-1.img"), b"memory").unwrap();
+    }
+
+    #[test]
+    fn sealed_checkpoint_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        write_images(dir.path());
+        seal_with_key(dir.path(), Uuid::new_v4(), &KEY).unwrap();
+        assert!(is_sealed(dir.path()));
+        assert!(!dir.path().join("pages-1.img").exists());
+
+        let unsealed = unseal_checkpoint(dir.path(), Some(KEY)).unwrap().unwrap();
+        assert_eq!(fs::read(dir.path().join("inventory.img")).unwrap(), b"inventory");
+        assert_eq!(fs::read(dir.path().join("pages-1.img")).unwrap(), b"memory");
+        drop(unsealed);
+        assert!(!dir.path().join("pages-1.img").exists());
+        assert!(is_sealed(dir.path()));
+    }
+
+    #[test]
+    fn wrong_key_does_not_open_the_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        write_images(dir.path());
+        seal_with_key(dir.path(), Uuid::new_v4(), &KEY).unwrap();
+
+        assert!(matches!(unseal_checkpoint(dir.path(), Some([8; 32])), Err(CriuCliError::IncompatibleCheckpoint(_))));
+        assert!(matches!(unseal_checkpoint(dir.path(), None), Err(CriuCliError::IncompatibleCheckpoint(_))));
+        assert!(!dir.path().join("pages-1.img").exists());
+    }
+
+    #[test]
+    fn sealed_base_is_unsealed_through_the_parent_link() {
+        let checkpoints = tempfile::tempdir().unwrap();
+        let base = checkpoints.path().join("auto-sync-1");
+        let latest = checkpoints.path().join("auto-sync-2");
+        write_images(&base);
+        seal_with_key(&base, Uuid::new_v4(), &KEY).unwrap();
+        write_images(&latest);
+        std::os::unix::fs::symlink("../auto-sync-1", latest.join(PARENT_IMAGES_DIR)).unwrap();
+        seal_with_key(&latest, Uuid::new_v4(), &KEY).unwrap();
+
+        let unsealed = unseal_checkpoint(&latest, Some(KEY)).unwrap().unwrap();
+        assert!(latest.join(PARENT_IMAGES_DIR).join("pages-1.img").exists());
+        drop(unsealed);
+        assert!(!base.join("pages-1.img").exists());
+        assert!(fs::symlink_metadata(latest.join(PARENT_IMAGES_DIR)).unwrap().file_type().is_symlink());
+    }
+}
@@ -0,0 +1,187 @@
+use crate::types::{CriuCliError, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+/// Manifest written into each deduplicated checkpoint directory
+pub const MANIFEST_FILE: &str = "dedup_manifest.json";
+
+/// Shared blob store directory inside an instance directory
+pub const BLOBS_DIR: &str = "blobs";
+
+/// File name to content hash for one checkpoint directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DedupManifest {
+    pub files: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DedupStats {
+    pub files: usize,
+    pub new_blobs: usize,
+    pub bytes_saved: u64,
+}
+
+/// Blob store for a checkpoint at `instances/<instance>/checkpoints/<name>`
+fn blobs_dir_for(checkpoint_dir: &Path) -> Option<PathBuf> {
+    let checkpoints = checkpoint_dir.parent()?;
+    if checkpoints.file_name()? != "checkpoints" {
+        return None;
+    }
+    Some(checkpoints.parent()?.join(BLOBS_DIR))
+}
+
+pub(crate) fn hash_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn read_manifest(checkpoint_dir: &Path) -> Result<Option<DedupManifest>> {
+    let path = checkpoint_dir.join(MANIFEST_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&path)?;
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|e| CriuCliError::ParseError(format!("Invalid dedup manifest {}: {}", path.display(), e)))
+}
+
+/// Move every file of a checkpoint into the instance's blob store and hard-link it back,
+/// so files identical to earlier checkpoints share storage. Readers see ordinary files.
+pub fn dedup_checkpoint(checkpoint_dir: &Path) -> Result<DedupStats> {
+    let blobs_dir = blobs_dir_for(checkpoint_dir).ok_or_else(|| {
+        CriuCliError::ProcessError(format!("{} is not inside an instance checkpoints directory", checkpoint_dir.display()))
+    })?;
+    fs::create_dir_all(&blobs_dir)?;
+
+    let mut manifest = DedupManifest::default();
+    let mut stats = DedupStats::default();
+
+    for entry in fs::read_dir(checkpoint_dir)?.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name == MANIFEST_FILE || !entry.file_type()?.is_file() {
+            continue;
+        }
+
+        let hash = hash_file(&path)?;
+        let blob = blobs_dir.join(&hash);
+
+        if blob.exists() {
+            let size = entry.metadata()?.len();
+            let already_linked = fs::metadata(&blob)?.ino() == entry.metadata()?.ino();
+            if !already_linked {
+                fs::remove_file(&path)?;
+                link_or_copy(&blob, &path)?;
+                stats.bytes_saved += size;
+            }
+        } else {
+            // Keep the original under its blob name, then link it back into place
+            fs::hard_link(&path, &blob).or_else(|_| fs::copy(&path, &blob).map(|_| ()))?;
+            stats.new_blobs += 1;
+        }
+
+        manifest.files.insert(name, hash);
+        stats.files += 1;
+    }
+
+    let manifest_json = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| CriuCliError::ParseError(format!("Failed to serialize dedup manifest: {}", e)))?;
+    fs::write(checkpoint_dir.join(MANIFEST_FILE), manifest_json)?;
+
+    debug!("Deduplicated {:?}: {} files, {} new blobs, {} bytes shared",
+           checkpoint_dir, stats.files, stats.new_blobs, stats.bytes_saved);
+    Ok(stats)
+}
+
+/// Deduplicate a freshly written checkpoint if `enabled`; failures only warn since the checkpoint itself is intact
+pub fn dedup_if_enabled(checkpoint_dir: &Path, enabled: bool) {
+    if !enabled {
+        return;
+    }
+    match dedup_checkpoint(checkpoint_dir) {
+        Ok(stats) if stats.bytes_saved > 0 => {
+            info!("Checkpoint {:?}: {} bytes shared with earlier checkpoints", checkpoint_dir, stats.bytes_saved);
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Failed to deduplicate checkpoint {:?}: {}", checkpoint_dir, e),
+    }
+}
+
+/// Recreate any checkpoint files missing from a deduplicated checkpoint from the blob store.
+/// Checkpoints without a manifest are left untouched.
+pub fn reassemble_checkpoint(checkpoint_dir: &Path) -> Result<()> {
+    let manifest = match read_manifest(checkpoint_dir)? {
+        Some(manifest) => manifest,
+        None => return Ok(()),
+    };
+    let blobs_dir = blobs_dir_for(checkpoint_dir).ok_or_else(|| {
+        CriuCliError::ProcessError(format!("{} is not inside an instance checkpoints directory", checkpoint_dir.display()))
+    })?;
+
+    for (name, hash) in &manifest.files {
+        let path = checkpoint_dir.join(name);
+        if path.exists() {
+            continue;
+        }
+        let blob = blobs_dir.join(hash);
+        if !blob.exists() {
+            return Err(CriuCliError::CheckpointNotFound(format!(
+                "{} (blob {} for {} is missing)", checkpoint_dir.display(), hash, name
+            )));
+        }
+        link_or_copy(&blob, &path)?;
+        debug!("Reassembled {} from blob {}", name, hash);
+    }
+
+    Ok(())
+}
+
+/// Remove blobs that no checkpoint manifest references any more
+pub fn prune_blobs(instance_dir: &Path) -> Result<usize> {
+    let blobs_dir = instance_dir.join(BLOBS_DIR);
+    if !blobs_dir.exists() {
+        return Ok(0);
+    }
+
+    let mut referenced = HashSet::new();
+    if let Ok(entries) = fs::read_dir(instance_dir.join("checkpoints")) {
+        for entry in entries.flatten() {
+            if let Ok(Some(manifest)) = read_manifest(&entry.path()) {
+                referenced.extend(manifest.files.into_values());
+            }
+        }
+    }
+
+    let mut removed = 0;
+    for entry in fs::read_dir(&blobs_dir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !referenced.contains(&name) {
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+fn link_or_copy(src: &Path, dst: &Path) -> Result<()> {
+    if fs::hard_link(src, dst).is_err() {
+        fs::copy(src, dst)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,266 @@
+use crate::types::{CriuCliError, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Versioned descriptor written into each checkpoint directory
+pub const DESCRIPTOR_FILE: &str = "checkpoint.json";
+
+/// Descriptor schema written by this build; bump when the checkpoint layout changes
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Oldest descriptor schema this build can still restore
+pub const MIN_SCHEMA_VERSION: u32 = 1;
+
+/// One file captured in a checkpoint directory
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DescriptorFile {
+    pub name: String,
+    pub size: u64,
+}
+
+/// What a checkpoint contains and which build produced it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CheckpointDescriptor {
+    pub schema_version: u32,
+    pub instance_id: Uuid,
+    pub program: String,
+    pub args: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub criu_version: Option<String>,
+    pub compression: Option<String>, // None: images are stored uncompressed
+    pub files: Vec<DescriptorFile>,
+    /// Cipher of `checkpoint.enc`; None: images are stored in plaintext
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<String>,
+    /// Hex nonce the images were encrypted with, unique to this checkpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption_nonce: Option<String>,
+}
+
+impl CheckpointDescriptor {
+    /// Describe the files currently in `checkpoint_dir`. `cmdline` is the process's
+    /// NUL-separated command line as read from /proc.
+    pub fn new(checkpoint_dir: &Path, instance_id: Uuid, cmdline: Option<&str>, criu_version: Option<String>) -> Result<Self> {
+        let mut parts = cmdline
+            .unwrap_or_default()
+            .split('\0')
+            .filter(|part| !part.is_empty())
+            .map(|part| part.to_string());
+        let program = parts.next().unwrap_or_default();
+        let args = parts.collect();
+
+        Ok(Self {
+            schema_version: SCHEMA_VERSION,
+            instance_id,
+            program,
+            args,
+            created_at: Utc::now(),
+            criu_version,
+            compression: None,
+            files: list_files(checkpoint_dir)?,
+            encryption: None,
+            encryption_nonce: None,
+        })
+    }
+
+    /// Re-list the files of `checkpoint_dir`, e.g. after its images were sealed
+    pub fn refresh_files(&mut self, checkpoint_dir: &Path) -> Result<()> {
+        self.files = list_files(checkpoint_dir)?;
+        Ok(())
+    }
+
+    /// Write the descriptor into `checkpoint_dir`
+    pub fn save(&self, checkpoint_dir: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| CriuCliError::ParseError(format!("Failed to serialize checkpoint descriptor: {}", e)))?;
+        fs::write(checkpoint_dir.join(DESCRIPTOR_FILE), json)?;
+        Ok(())
+    }
+
+    /// Read the descriptor of `checkpoint_dir`; `None` for checkpoints taken before descriptors existed
+    pub fn load(checkpoint_dir: &Path) -> Result<Option<Self>> {
+        let path = checkpoint_dir.join(DESCRIPTOR_FILE);
+        let json = match fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Self::from_json(&json).map(Some)
+    }
+
+    /// Parse a descriptor, rejecting schema versions this build does not understand
+    pub fn from_json(json: &str) -> Result<Self> {
+        // Read the version alone first so a newer layout fails with a clear message, not a field error
+        #[derive(Deserialize)]
+        struct Versioned {
+            schema_version: u32,
+        }
+        let versioned: Versioned = serde_json::from_str(json)
+            .map_err(|e| CriuCliError::IncompatibleCheckpoint(format!("unreadable {}: {}", DESCRIPTOR_FILE, e)))?;
+        check_schema_version(versioned.schema_version)?;
+
+        serde_json::from_str(json)
+            .map_err(|e| CriuCliError::IncompatibleCheckpoint(format!("malformed {}: {}", DESCRIPTOR_FILE, e)))
+    }
+
+    /// Check that every recorded file is still present with its recorded size
+    pub fn verify_files(&self, checkpoint_dir: &Path) -> Result<()> {
+        for file in &self.files {
+            match fs::metadata(checkpoint_dir.join(&file.name)) {
+                Ok(metadata) if metadata.len() == file.size => {}
+                Ok(metadata) => {
+                    return Err(CriuCliError::IncompatibleCheckpoint(format!(
+                        "{} is {} bytes, expected {}",
+                        file.name, metadata.len(), file.size
+                    )));
+                }
+                Err(_) => {
+                    return Err(CriuCliError::IncompatibleCheckpoint(format!("{} is missing", file.name)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_schema_version(version: u32) -> Result<()> {
+    if (MIN_SCHEMA_VERSION..=SCHEMA_VERSION).contains(&version) {
+        Ok(())
+    } else {
+        Err(CriuCliError::IncompatibleCheckpoint(format!(
+            "schema version {} is not supported (this build reads {} to {})",
+            version, MIN_SCHEMA_VERSION, SCHEMA_VERSION
+        )))
+    }
+}
+
+/// Regular files directly in `checkpoint_dir`, sorted by name; symlinks such as an
+/// incremental `parent` link are not part of the checkpoint itself
+fn list_files(checkpoint_dir: &Path) -> Result<Vec<DescriptorFile>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(checkpoint_dir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name == DESCRIPTOR_FILE {
+            continue;
+        }
+        if let Ok(metadata) = fs::symlink_metadata(entry.path()) {
+            if metadata.is_file() {
+                files.push(DescriptorFile { name, size: metadata.len() });
+            }
+        }
+    }
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(files)
+}
+
+/// Write the descriptor of a freshly dumped checkpoint; failures are logged, not fatal
+pub fn write_descriptor(checkpoint_dir: &Path, instance_id: Uuid, cmdline: Option<&str>, criu_version: Option<String>) {
+    let result = CheckpointDescriptor::new(checkpoint_dir, instance_id, cmdline, criu_version)
+        .and_then(|descriptor| descriptor.save(checkpoint_dir));
+    if let Err(e) = result {
+        warn!("Failed to write checkpoint descriptor in {:?}: {}", checkpoint_dir, e);
+    }
+}
+
+/// CRIU images every restore needs; `core-*.img` is checked separately since it is per-task
+const REQUIRED_IMAGES: &[&str] = &["inventory.img", "pstree.img"];
+
+/// Required images absent or empty in `checkpoint_dir`, e.g. after a dump or sync was interrupted
+pub fn missing_images(checkpoint_dir: &Path) -> Vec<String> {
+    let present = |name: &str| {
+        fs::metadata(checkpoint_dir.join(name))
+            .map(|metadata| metadata.is_file() && metadata.len() > 0)
+            .unwrap_or(false)
+    };
+    let mut missing: Vec<String> = REQUIRED_IMAGES
+        .iter()
+        .filter(|name| !present(name))
+        .map(|name| name.to_string())
+        .collect();
+
+    let has_core = fs::read_dir(checkpoint_dir)
+        .map(|entries| {
+            entries.flatten().any(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                name.starts_with("core-") && name.ends_with(".img") && present(&name)
+            })
+        })
+        .unwrap_or(false);
+    if !has_core {
+        missing.push("core-*.img".to_string());
+    }
+    missing
+}
+
+/// Refuse to restore a checkpoint whose descriptor is from an unsupported schema or whose
+/// files no longer match it. Checkpoints without a descriptor predate it and are accepted.
+pub fn validate_for_restore(checkpoint_dir: &Path) -> Result<()> {
+    match CheckpointDescriptor::load(checkpoint_dir)? {
+        Some(descriptor) => {
+            descriptor.verify_files(checkpoint_dir)?;
+            debug!(
+                "Checkpoint descriptor v{} for instance {} ({} files, CRIU {}, encryption {})",
+                descriptor.schema_version,
+                descriptor.instance_id,
+                descriptor.files.len(),
+                descriptor.criu_version.as_deref().unwrap_or("unknown"),
+                descriptor.encryption.as_deref().unwrap_or("none")
+            );
+            Ok(())
+        }
+        None => {
+            debug!("Checkpoint {:?} has no {}, skipping compatibility check", checkpoint_dir, DESCRIPTOR_FILE);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_descriptor_round_trips_and_an_unknown_schema_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("inventory.img"), "inventory").unwrap();
+        fs::write(dir.path().join("pages-1.img"), vec![0u8; 4096]).unwrap();
+        let instance_id = Uuid::new_v4();
+        let cmdline = ["sleep", "30", ""].join("\0");
+
+        let descriptor = CheckpointDescriptor::new(dir.path(), instance_id, Some(&cmdline), Some("3.19".to_string())).unwrap();
+        assert_eq!((descriptor.program.as_str(), descriptor.args.as_slice()), ("sleep", ["30".to_string()].as_slice()));
+        assert_eq!(descriptor.files.iter().map(|file| file.name.as_str()).collect::<Vec<_>>(), ["inventory.img", "pages-1.img"]);
+        descriptor.save(dir.path()).unwrap();
+        assert_eq!(CheckpointDescriptor::load(dir.path()).unwrap(), Some(descriptor.clone()));
+        validate_for_restore(dir.path()).unwrap();
+
+        let mut future = serde_json::to_value(&descriptor).unwrap();
+        future["schema_version"] = serde_json::json!(SCHEMA_VERSION + 1);
+        // A newer layout may rename fields; the version alone decides
+        future.as_object_mut().unwrap().remove("files");
+        fs::write(dir.path().join(DESCRIPTOR_FILE), future.to_string()).unwrap();
+        match validate_for_restore(dir.path()) {
+            Err(CriuCliError::IncompatibleCheckpoint(reason)) => {
+                assert!(reason.contains(&format!("schema version {}", SCHEMA_VERSION + 1)), "{}", reason)
+            }
+            other => panic!("expected an incompatible checkpoint, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_changed_image_fails_validation() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("pages-1.img"), vec![0u8; 4096]).unwrap();
+        write_descriptor(dir.path(), Uuid::new_v4(), None, None);
+        validate_for_restore(dir.path()).unwrap();
+
+        fs::write(dir.path().join("pages-1.img"), vec![0u8; 100]).unwrap();
+        assert!(matches!(validate_for_restore(dir.path()), Err(CriuCliError::IncompatibleCheckpoint(_))));
+        fs::remove_file(dir.path().join("pages-1.img")).unwrap();
+        assert!(matches!(validate_for_restore(dir.path()), Err(CriuCliError::IncompatibleCheckpoint(_))));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::types::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How one file differs between two checkpoints
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileChange {
+    OnlyInA { size: u64 },
+    OnlyInB { size: u64 },
+    Identical { size: u64 },
+    Changed { size_a: u64, size_b: u64 },
+}
+
+/// One file of a checkpoint comparison
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileDiff {
+    pub name: String,
+    pub change: FileChange,
+}
+
+impl FileDiff {
+    pub fn is_identical(&self) -> bool {
+        matches!(self.change, FileChange::Identical { .. })
+    }
+}
+
+/// Regular files below `dir` by name relative to it; the base images of an incremental
+/// checkpoint show up as "parent/<file>"
+fn list_files(dir: &Path, prefix: &str, files: &mut BTreeMap<String, (PathBuf, u64)>) -> Result<()> {
+    for entry in fs::read_dir(dir)?.flatten() {
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let Ok(metadata) = fs::symlink_metadata(entry.path()) else {
+            continue;
+        };
+        if metadata.is_file() {
+            files.insert(name, (entry.path(), metadata.len()));
+        } else if metadata.is_dir() {
+            list_files(&entry.path(), &format!("{}/", name), files)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compare two checkpoint directories file by file, sorted by name. Files present in both are
+/// hashed, so equal sizes with different contents still count as changed.
+pub fn diff_checkpoints(a: &Path, b: &Path) -> Result<Vec<FileDiff>> {
+    let mut files_a = BTreeMap::new();
+    let mut files_b = BTreeMap::new();
+    list_files(a, "", &mut files_a)?;
+    list_files(b, "", &mut files_b)?;
+
+    let mut diffs = Vec::new();
+    for (name, (path_a, size_a)) in &files_a {
+        let change = match files_b.get(name) {
+            None => FileChange::OnlyInA { size: *size_a },
+            Some((path_b, size_b)) => {
+                if size_a == size_b && crate::checkpoint_dedup::hash_file(path_a)? == crate::checkpoint_dedup::hash_file(path_b)? {
+                    FileChange::Identical { size: *size_a }
+                } else {
+                    FileChange::Changed { size_a: *size_a, size_b: *size_b }
+                }
+            }
+        };
+        diffs.push(FileDiff { name: name.clone(), change });
+    }
+    for (name, (_, size)) in &files_b {
+        if !files_a.contains_key(name) {
+            diffs.push(FileDiff { name: name.clone(), change: FileChange::OnlyInB { size: *size } });
+        }
+    }
+    diffs.sort_by(|x, y| x.name.cmp(&y.name));
+    Ok(diffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_file_that_differs_is_reported_as_changed() {
+        let root = tempfile::tempdir().unwrap();
+        let (a, b) = (root.path().join("manual-1"), root.path().join("manual-2"));
+        for dir in [&a, &b] {
+            fs::create_dir_all(dir.join("parent")).unwrap();
+            fs::write(dir.join("inventory.img"), "inventory").unwrap();
+            fs::write(dir.join("parent/pages-1.img"), "base pages").unwrap();
+        }
+        // Same size, different contents
+        fs::write(a.join("pages-1.img"), "pages-aaaa").unwrap();
+        fs::write(b.join("pages-1.img"), "pages-bbbb").unwrap();
+
+        let diffs = diff_checkpoints(&a, &b).unwrap();
+        let changed: Vec<&FileDiff> = diffs.iter().filter(|diff| !diff.is_identical()).collect();
+        assert_eq!(changed, [&FileDiff { name: "pages-1.img".to_string(), change: FileChange::Changed { size_a: 10, size_b: 10 } }]);
+        assert_eq!(diffs.iter().map(|diff| diff.name.as_str()).collect::<Vec<_>>(), ["inventory.img", "pages-1.img", "parent/pages-1.img"]);
+    }
+
+    #[test]
+    fn files_in_only_one_checkpoint_are_listed_with_their_size() {
+        let root = tempfile::tempdir().unwrap();
+        let (a, b) = (root.path().join("a"), root.path().join("b"));
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        fs::write(a.join("core-1.img"), "core").unwrap();
+        fs::write(b.join("core-2.img"), "core-2").unwrap();
+
+        assert_eq!(
+            diff_checkpoints(&a, &b).unwrap(),
+            vec![
+                FileDiff { name: "core-1.img".to_string(), change: FileChange::OnlyInA { size: 4 } },
+                FileDiff { name: "core-2.img".to_string(), change: FileChange::OnlyInB { size: 6 } },
+            ]
+        );
+    }
+}
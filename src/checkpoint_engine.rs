@@ -0,0 +1,619 @@
+use crate::criu_compat::TCP_ESTABLISHED_MARKER;
+use crate::sudo_utils::{Privilege, PrivilegeCommand};
+use crate::types::{CriuCliError, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{debug, info, warn};
+
+/// File in a checkpoint directory recording the extra arguments it was dumped with
+pub const CRIU_ARGS_FILE: &str = "criu_args.json";
+
+/// Options NHI passes on every dump itself; overriding them would dump the wrong tree or directory
+const MANAGED_ARGS: &[&str] = &["-D", "--images-dir", "-t", "--tree"];
+
+/// Accept an extra CRIU argument only if it is an option NHI does not set itself
+pub fn parse_extra_arg(arg: &str) -> std::result::Result<String, String> {
+    if !arg.starts_with('-') {
+        return Err(format!("CRIU argument '{}' must start with '-'", arg));
+    }
+    let name = arg.split('=').next().unwrap_or(arg);
+    if MANAGED_ARGS.contains(&name) {
+        return Err(format!("CRIU argument '{}' is set by nhi and cannot be overridden", name));
+    }
+    Ok(arg.to_string())
+}
+
+/// Extra arguments configured when a checkpoint was dumped. Only the restore list is
+/// replayed; dump-only options such as --leave-running or --auto-dedup would fail a restore.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RecordedArgs {
+    pub dump: Vec<String>,
+    pub restore: Vec<String>,
+}
+
+impl RecordedArgs {
+    /// The arguments recorded in `images_dir`, empty if none were
+    pub fn load(images_dir: &Path) -> Self {
+        std::fs::read_to_string(images_dir.join(CRIU_ARGS_FILE))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, images_dir: &Path) -> std::io::Result<()> {
+        std::fs::write(images_dir.join(CRIU_ARGS_FILE), serde_json::to_string(self)?)
+    }
+}
+
+/// Append `extra` to `args`, skipping options already present
+fn merge_args(args: &mut Vec<String>, extra: Vec<String>) {
+    for arg in extra {
+        if !args.contains(&arg) {
+            args.push(arg);
+        }
+    }
+}
+
+/// I/O scheduling class and level for `ionice -c <class> -n <level>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoPriority {
+    pub class: u8,         // 2 = best-effort, 3 = idle
+    pub level: Option<u8>, // 0 (highest) to 7, best-effort only
+}
+
+/// Lowered scheduling priority for CRIU dumps (--checkpoint-nice / --checkpoint-ionice)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DumpPriority {
+    pub nice: Option<i32>,
+    pub ionice: Option<IoPriority>,
+    pub manual: bool, // Also lower manual checkpoints, not only background auto-sync dumps
+}
+
+/// Parse a nice increment; only values that lower priority (0-19) are accepted
+pub fn parse_nice(value: &str) -> std::result::Result<i32, String> {
+    match value.parse::<i32>() {
+        Ok(nice) if (0..=19).contains(&nice) => Ok(nice),
+        _ => Err(format!("nice value '{}' must be between 0 and 19", value)),
+    }
+}
+
+/// Parse an I/O priority: `idle`, `best-effort` or `best-effort:<0-7>` (classes may be given as 3 and 2).
+/// Realtime is refused since the point is to get out of the workload's way.
+pub fn parse_ionice(value: &str) -> std::result::Result<IoPriority, String> {
+    let (class, level) = match value.split_once(':') {
+        Some((class, level)) => (class, Some(level)),
+        None => (value, None),
+    };
+    let class = match class {
+        "idle" | "3" => 3,
+        "best-effort" | "2" => 2,
+        _ => return Err(format!("I/O class '{}' must be idle or best-effort", class)),
+    };
+    let level = match level {
+        None => None,
+        Some(_) if class == 3 => return Err("the idle I/O class takes no level".to_string()),
+        Some(level) => match level.parse::<u8>() {
+            Ok(level) if level <= 7 => Some(level),
+            _ => return Err(format!("I/O level '{}' must be between 0 and 7", level)),
+        },
+    };
+    Ok(IoPriority { class, level })
+}
+
+impl DumpPriority {
+    /// Whether a dump runs with lowered priority: background dumps always, manual ones only when asked
+    pub fn applies_to(&self, background: bool) -> bool {
+        (self.nice.is_some() || self.ionice.is_some()) && (background || self.manual)
+    }
+
+    /// `nice`/`ionice` invocation to put in front of the CRIU command line
+    pub fn wrapper_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(nice) = self.nice {
+            args.extend(["nice".to_string(), "-n".to_string(), nice.to_string()]);
+        }
+        if let Some(io) = self.ionice {
+            args.extend(["ionice".to_string(), "-c".to_string(), io.class.to_string()]);
+            if let Some(level) = io.level {
+                args.extend(["-n".to_string(), level.to_string()]);
+            }
+        }
+        args
+    }
+}
+
+/// Parameters for a dump (or pre-dump) of a process tree
+#[derive(Debug, Clone, Default)]
+pub struct DumpRequest {
+    pub pid: u32,
+    pub images_dir: PathBuf,
+    pub leave_running: bool,
+    pub shell_job: bool,
+    pub verbose: bool,
+    pub extra_args: Vec<String>,
+    pub background: bool, // Auto-sync dump; runs with the configured DumpPriority
+    pub tcp_established: bool, // Process holds TCP sockets; the checkpoint is marked so restores repeat the flag
+}
+
+/// Parameters for restoring a process tree from images
+#[derive(Debug, Clone, Default)]
+pub struct RestoreRequest {
+    pub images_dir: PathBuf,
+    pub detached: bool,
+    pub shell_job: bool,
+    pub verbose: bool,
+    pub pidfile: Option<PathBuf>,
+    pub log_file: Option<PathBuf>,
+    pub log_pid: bool,
+    pub work_dir: Option<PathBuf>,
+    pub extra_args: Vec<String>,
+    pub stdin: Option<PathBuf>, // Opened as CRIU's stdin, e.g. a TTY target for --inherit-fd fd[0]
+}
+
+/// Result of running an engine command
+#[derive(Debug, Clone)]
+pub struct EngineOutput {
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl From<std::process::Output> for EngineOutput {
+    fn from(output: std::process::Output) -> Self {
+        Self {
+            success: output.status.success(),
+            exit_code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        }
+    }
+}
+
+/// Checkpoint/restore engine; `Err` means the engine could not be run at all,
+/// a non-zero exit is reported through `EngineOutput::success`
+pub trait CheckpointEngine: Send + Sync {
+    /// Engine name for logging
+    fn name(&self) -> &str;
+
+    /// Verify the engine is usable on this host
+    fn check(&self) -> Result<EngineOutput>;
+
+    /// Dump a process tree
+    fn dump(&self, request: &DumpRequest) -> Result<EngineOutput>;
+
+    /// Pre-dump memory pages of a process tree (iterative migration)
+    fn pre_dump(&self, request: &DumpRequest) -> Result<EngineOutput>;
+
+    /// Restore a process tree
+    fn restore(&self, request: &RestoreRequest) -> Result<EngineOutput>;
+
+    /// Engine version recorded in checkpoint descriptors, if it can be determined
+    fn version(&self) -> Option<String> {
+        None
+    }
+}
+
+/// CRIU command-line engine
+pub struct CriuEngine {
+    criu_path: PathBuf,
+    privilege: Privilege,
+    extra_dump_args: Vec<String>,    // --criu-dump-arg
+    extra_restore_args: Vec<String>, // --criu-restore-arg
+    dump_priority: DumpPriority,
+}
+
+impl CriuEngine {
+    /// Run the CRIU binary directly
+    pub fn new<P: AsRef<Path>>(criu_path: P) -> Self {
+        Self {
+            criu_path: Self::absolute_path(criu_path.as_ref()),
+            privilege: Privilege::new(PrivilegeCommand::None),
+            extra_dump_args: Vec::new(),
+            extra_restore_args: Vec::new(),
+            dump_priority: DumpPriority::default(),
+        }
+    }
+
+    /// Run the CRIU binary through sudo
+    pub fn privileged<P: AsRef<Path>>(criu_path: P) -> Self {
+        Self::new(criu_path).with_privilege_command(PrivilegeCommand::Sudo)
+    }
+
+    /// Run the CRIU binary through `command` (none runs it directly)
+    pub fn with_privilege_command(mut self, command: PrivilegeCommand) -> Self {
+        self.privilege = Privilege::new(command);
+        self
+    }
+
+    /// Append these arguments to every dump and restore; each must be an option
+    pub fn with_extra_args(mut self, dump: Vec<String>, restore: Vec<String>) -> Result<Self> {
+        for arg in dump.iter().chain(&restore) {
+            parse_extra_arg(arg).map_err(CriuCliError::ParseError)?;
+        }
+        self.extra_dump_args = dump;
+        self.extra_restore_args = restore;
+        Ok(self)
+    }
+
+    /// Run dumps with this scheduling priority
+    pub fn with_dump_priority(mut self, priority: DumpPriority) -> Self {
+        self.dump_priority = priority;
+        self
+    }
+
+    pub fn criu_path(&self) -> &Path {
+        &self.criu_path
+    }
+
+    /// Wrapper privileged CRIU calls run through
+    pub fn privilege_command(&self) -> PrivilegeCommand {
+        self.privilege.command()
+    }
+
+    /// Convert relative path to absolute path to avoid working directory issues
+    fn absolute_path(criu_path: &Path) -> PathBuf {
+        if criu_path.is_relative() {
+            match std::env::current_dir() {
+                Ok(current_dir) => current_dir.join(criu_path),
+                Err(e) => {
+                    warn!("Failed to get current directory: {}, using relative path", e);
+                    criu_path.to_path_buf()
+                }
+            }
+        } else {
+            criu_path.to_path_buf()
+        }
+    }
+
+    fn command(&self) -> Result<Command> {
+        self.privilege.check_available()?;
+        Ok(crate::sudo_utils::privileged_std_command(self.privilege.command(), &self.criu_path))
+    }
+
+    fn run(&self, mut cmd: Command, action: &str) -> Result<EngineOutput> {
+        debug!("Executing CRIU {}: {:?}", action, cmd);
+        let output = cmd.output().map_err(|e| {
+            CriuCliError::CriuError(format!("Failed to execute CRIU {}: {}", action, e))
+        })?;
+        Ok(output.into())
+    }
+
+    /// CRIU command, prefixed with nice/ionice when the dump should yield to the workload.
+    /// The wrapper goes in front of the privilege command so CRIU inherits the lowered priority.
+    fn prioritized_command(&self, background: bool) -> Result<Command> {
+        if !self.dump_priority.applies_to(background) {
+            return self.command();
+        }
+
+        let wrapper = self.dump_priority.wrapper_args();
+        let mut cmd = Command::new(&wrapper[0]);
+        cmd.args(&wrapper[1..]);
+        self.privilege.check_available()?;
+        cmd.args(self.privilege.command().prefix());
+        cmd.arg(&self.criu_path);
+        Ok(cmd)
+    }
+
+    fn dump_command(&self, action: &str, request: &DumpRequest) -> Result<Command> {
+        let mut cmd = self.prioritized_command(request.background)?;
+        cmd.arg(action)
+            .arg("--tree")
+            .arg(request.pid.to_string())
+            .arg("-D")
+            .arg(&request.images_dir);
+
+        if request.verbose {
+            cmd.arg("-v4");
+        }
+        if request.leave_running {
+            cmd.arg("--leave-running");
+        }
+        if request.shell_job {
+            cmd.arg("--shell-job");
+        }
+        if request.tcp_established {
+            cmd.arg("--tcp-established");
+        }
+        cmd.args(self.dump_args(request));
+
+        Ok(cmd)
+    }
+
+    /// The request's own extra arguments plus the configured dump arguments
+    fn dump_args(&self, request: &DumpRequest) -> Vec<String> {
+        let mut args = request.extra_args.clone();
+        merge_args(&mut args, self.extra_dump_args.clone());
+        args
+    }
+
+    /// The request's own extra arguments plus the restore arguments recorded at dump time and those configured now
+    fn restore_args(&self, request: &RestoreRequest) -> Vec<String> {
+        let mut args = request.extra_args.clone();
+        if request.images_dir.join(TCP_ESTABLISHED_MARKER).exists() {
+            merge_args(&mut args, vec!["--tcp-established".to_string()]);
+        }
+        merge_args(&mut args, RecordedArgs::load(&request.images_dir).restore);
+        merge_args(&mut args, self.extra_restore_args.clone());
+        args
+    }
+}
+
+impl CheckpointEngine for CriuEngine {
+    fn name(&self) -> &str {
+        "criu"
+    }
+
+    fn check(&self) -> Result<EngineOutput> {
+        let mut cmd = self.command()?;
+        cmd.arg("check");
+        self.run(cmd, "check")
+    }
+
+    fn dump(&self, request: &DumpRequest) -> Result<EngineOutput> {
+        let cmd = self.dump_command("dump", request)?;
+        info!("CRIU dump of PID {} into {:?}", request.pid, request.images_dir);
+        let output = self.run(cmd, "dump")?;
+
+        if output.success && request.tcp_established {
+            if let Err(e) = std::fs::write(request.images_dir.join(TCP_ESTABLISHED_MARKER), "") {
+                warn!("Failed to record socket state in {:?}: {}", request.images_dir, e);
+            }
+        }
+        let configured = RecordedArgs { dump: self.extra_dump_args.clone(), restore: self.extra_restore_args.clone() };
+        if output.success && configured != RecordedArgs::default() {
+            if let Err(e) = configured.save(&request.images_dir) {
+                warn!("Failed to record CRIU arguments in {:?}: {}", request.images_dir, e);
+            }
+        }
+        Ok(output)
+    }
+
+    fn pre_dump(&self, request: &DumpRequest) -> Result<EngineOutput> {
+        let cmd = self.dump_command("pre-dump", request)?;
+        info!("CRIU pre-dump of PID {} into {:?}", request.pid, request.images_dir);
+        self.run(cmd, "pre-dump")
+    }
+
+    fn version(&self) -> Option<String> {
+        // `criu --version` prints "Version: 3.17.1" (plus a GitID line for git builds) and needs no privileges
+        let output = Command::new(&self.criu_path).arg("--version").output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .find_map(|line| line.strip_prefix("Version:"))
+            .map(|version| version.trim().to_string())
+    }
+
+    fn restore(&self, request: &RestoreRequest) -> Result<EngineOutput> {
+        let mut cmd = self.command()?;
+        cmd.arg("restore").arg("-D").arg(&request.images_dir);
+
+        if request.verbose {
+            cmd.arg("-v4");
+        }
+        if request.detached {
+            cmd.arg("--restore-detached");
+        }
+        if request.shell_job {
+            cmd.arg("--shell-job");
+        }
+        if let Some(ref pidfile) = request.pidfile {
+            cmd.arg("--pidfile").arg(pidfile);
+        }
+        if let Some(ref log_file) = request.log_file {
+            cmd.arg("--log-file").arg(log_file);
+        }
+        if request.log_pid {
+            cmd.arg("--log-pid");
+        }
+        if let Some(ref work_dir) = request.work_dir {
+            cmd.current_dir(work_dir);
+        }
+        if let Some(ref stdin) = request.stdin {
+            let file = std::fs::OpenOptions::new().read(true).write(true).open(stdin).map_err(|e| {
+                CriuCliError::CriuError(format!("Failed to open {:?} for CRIU restore: {}", stdin, e))
+            })?;
+            cmd.stdin(file);
+        }
+        cmd.args(self.restore_args(request));
+
+        info!("CRIU restore from {:?}", request.images_dir);
+        self.run(cmd, "restore")
+    }
+}
+
+/// Engine that records calls and returns canned results, for exercising orchestration without CRIU
+#[cfg(any(test, feature = "mock-engine"))]
+pub struct MockEngine {
+    calls: std::sync::Mutex<Vec<String>>,
+    dumps: std::sync::Mutex<Vec<DumpRequest>>,
+    result: std::sync::Mutex<EngineOutput>,
+}
+
+#[cfg(any(test, feature = "mock-engine"))]
+impl MockEngine {
+    pub fn new() -> Self {
+        Self {
+            calls: std::sync::Mutex::new(Vec::new()),
+            dumps: std::sync::Mutex::new(Vec::new()),
+            result: std::sync::Mutex::new(EngineOutput {
+                success: true,
+                exit_code: Some(0),
+                stdout: String::new(),
+                stderr: String::new(),
+            }),
+        }
+    }
+
+    /// Set the output returned by every subsequent call
+    pub fn set_result(&self, output: EngineOutput) {
+        *self.result.lock().unwrap() = output;
+    }
+
+    /// Calls recorded so far, e.g. `dump 1234`
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Dump requests recorded so far, with the arguments they were made with
+    pub fn dumps(&self) -> Vec<DumpRequest> {
+        self.dumps.lock().unwrap().clone()
+    }
+
+    fn record(&self, call: String) -> Result<EngineOutput> {
+        self.calls.lock().unwrap().push(call);
+        Ok(self.result.lock().unwrap().clone())
+    }
+}
+
+#[cfg(any(test, feature = "mock-engine"))]
+impl Default for MockEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(any(test, feature = "mock-engine"))]
+impl CheckpointEngine for MockEngine {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn check(&self) -> Result<EngineOutput> {
+        self.record("check".to_string())
+    }
+
+    fn dump(&self, request: &DumpRequest) -> Result<EngineOutput> {
+        self.dumps.lock().unwrap().push(request.clone());
+        self.record(format!("dump {}", request.pid))
+    }
+
+    fn pre_dump(&self, request: &DumpRequest) -> Result<EngineOutput> {
+        self.record(format!("pre-dump {}", request.pid))
+    }
+
+    fn restore(&self, request: &RestoreRequest) -> Result<EngineOutput> {
+        self.record(format!("restore {}", request.images_dir.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args_of(cmd: &Command) -> Vec<String> {
+        cmd.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn dump_with_tcp_sockets_passes_tcp_established() {
+        let engine = CriuEngine::new("/usr/sbin/criu");
+        let mut request = DumpRequest { pid: 42, images_dir: PathBuf::from("/tmp/images"), ..Default::default() };
+        assert!(!args_of(&engine.dump_command("dump", &request).unwrap()).contains(&"--tcp-established".to_string()));
+
+        request.tcp_established = true;
+        assert!(args_of(&engine.dump_command("dump", &request).unwrap()).contains(&"--tcp-established".to_string()));
+    }
+
+    #[test]
+    fn restore_of_a_marked_checkpoint_passes_tcp_established() {
+        let images_dir = tempfile::tempdir().unwrap();
+        let engine = CriuEngine::new("/usr/sbin/criu");
+        let request = RestoreRequest { images_dir: images_dir.path().to_path_buf(), ..Default::default() };
+        assert!(!engine.restore_args(&request).contains(&"--tcp-established".to_string()));
+
+        std::fs::write(images_dir.path().join(TCP_ESTABLISHED_MARKER), "").unwrap();
+        assert_eq!(
+            engine.restore_args(&request).iter().filter(|arg| *arg == "--tcp-established").count(),
+            1
+        );
+    }
+
+    #[test]
+    fn extra_args_reach_the_dump_and_invalid_ones_are_rejected() {
+        let engine = CriuEngine::new("/usr/sbin/criu")
+            .with_extra_args(vec!["--ext-unix-sk".to_string()], Vec::new())
+            .unwrap();
+        let request = DumpRequest { pid: 42, images_dir: PathBuf::from("/tmp/images"), ..Default::default() };
+        assert!(args_of(&engine.dump_command("dump", &request).unwrap()).contains(&"--ext-unix-sk".to_string()));
+
+        assert!(CriuEngine::new("/usr/sbin/criu").with_extra_args(vec!["ext-unix-sk".to_string()], Vec::new()).is_err());
+        assert!(CriuEngine::new("/usr/sbin/criu").with_extra_args(Vec::new(), vec!["--images-dir=/tmp".to_string()]).is_err());
+    }
+
+    #[test]
+    fn restore_replays_recorded_restore_args_but_not_dump_args() {
+        let images_dir = tempfile::tempdir().unwrap();
+        let recorded = RecordedArgs {
+            dump: vec!["--auto-dedup".to_string(), "--ext-unix-sk".to_string()],
+            restore: vec!["--ext-unix-sk".to_string()],
+        };
+        recorded.save(images_dir.path()).unwrap();
+        assert_eq!(RecordedArgs::load(images_dir.path()), recorded);
+
+        let request = RestoreRequest {
+            images_dir: images_dir.path().to_path_buf(),
+            extra_args: vec!["--restore-detached".to_string()],
+            ..Default::default()
+        };
+        let args = CriuEngine::new("/usr/sbin/criu").restore_args(&request);
+        assert!(args.contains(&"--restore-detached".to_string()));
+        assert!(args.contains(&"--ext-unix-sk".to_string()));
+        assert!(!args.contains(&"--auto-dedup".to_string()));
+    }
+
+    #[test]
+    fn dump_passes_the_request_extra_args() {
+        let engine = CriuEngine::new("/usr/sbin/criu");
+        let request = DumpRequest {
+            pid: 42,
+            images_dir: PathBuf::from("/tmp/images"),
+            extra_args: vec!["--file-locks".to_string()],
+            ..Default::default()
+        };
+        assert!(args_of(&engine.dump_command("dump", &request).unwrap()).contains(&"--file-locks".to_string()));
+    }
+
+    #[test]
+    fn background_dumps_run_under_the_configured_nice_and_ionice() {
+        let priority = DumpPriority { nice: Some(10), ionice: Some(parse_ionice("best-effort:7").unwrap()), manual: false };
+        let engine = CriuEngine::new("/usr/sbin/criu").with_dump_priority(priority);
+        let mut request = DumpRequest { pid: 42, images_dir: PathBuf::from("/tmp/images"), background: true, ..Default::default() };
+
+        let cmd = engine.dump_command("dump", &request).unwrap();
+        assert_eq!(cmd.get_program(), "nice");
+        assert_eq!(args_of(&cmd)[..9], ["-n", "10", "ionice", "-c", "2", "-n", "7", "/usr/sbin/criu", "dump"]);
+
+        // Manual checkpoints keep their priority unless asked otherwise
+        request.background = false;
+        assert_eq!(engine.dump_command("dump", &request).unwrap().get_program(), "/usr/sbin/criu");
+        let engine = CriuEngine::new("/usr/sbin/criu").with_dump_priority(DumpPriority { manual: true, ..priority });
+        assert_eq!(engine.dump_command("dump", &request).unwrap().get_program(), "nice");
+
+        assert_eq!(parse_ionice("idle").unwrap(), IoPriority { class: 3, level: None });
+        for invalid in ["realtime", "1", "idle:3", "best-effort:8"] {
+            assert!(parse_ionice(invalid).is_err(), "accepted {:?}", invalid);
+        }
+        assert!(parse_nice("-5").is_err());
+    }
+
+    #[test]
+    fn dumps_run_through_the_configured_privilege_command() {
+        let engine = CriuEngine::new("/usr/sbin/criu").with_privilege_command(PrivilegeCommand::Doas);
+        // As if `doas -n true` had succeeded
+        engine.privilege.record_check(Command::new("true").output().unwrap()).unwrap();
+        let request = DumpRequest { pid: 42, images_dir: PathBuf::from("/tmp/images"), ..Default::default() };
+        let cmd = engine.dump_command("dump", &request).unwrap();
+        assert_eq!(cmd.get_program(), "doas");
+        assert_eq!(args_of(&cmd)[..3], ["-n", "/usr/sbin/criu", "dump"]);
+
+        // The lowered priority wraps the privilege command, so CRIU inherits it
+        let engine = engine.with_dump_priority(DumpPriority { nice: Some(5), ionice: None, manual: true });
+        let cmd = engine.dump_command("dump", &request).unwrap();
+        assert_eq!(cmd.get_program(), "nice");
+        assert_eq!(args_of(&cmd)[..5], ["-n", "5", "doas", "-n", "/usr/sbin/criu"]);
+    }
+}
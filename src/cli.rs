@@ -1,4 +1,4 @@
-use crate::types::{CriuCliError, Result};
+use crate::types::{CriuCliError, InstanceEdit, InstanceFilter, InstanceSort, InstanceStatus, LogStream, LogWindow, OutputTimestamps, RestoreOptions, Result, StartOptions};
 
 #[derive(Debug, Clone)]
 pub enum CliCommand {
@@ -7,13 +7,19 @@ pub enum CliCommand {
     Start {
         program: String,
         args: Vec<String>,
+        options: StartOptions,
     },
     StartDetached {
         program: String,
         args: Vec<String>,
+        options: StartOptions,
     },
     Stop {
         instance_id: String,
+        if_running: bool, // an already stopped instance is a no-op, not an error
+    },
+    StopLabeled {
+        labels: Vec<(String, String)>,
     },
     Pause {
         instance_id: String,
@@ -21,7 +27,37 @@ pub enum CliCommand {
     Resume {
         instance_id: String,
     },
-    List,
+    Retry {
+        instance_id: String,
+    },
+    Edit {
+        instance_id: String,
+        edit: InstanceEdit,
+    },
+    Restart {
+        instance_id: String,
+    },
+    Info {
+        instance_id: String,
+    },
+    Purge {
+        status: Option<InstanceStatus>, // Stopped or Failed; None purges both plus unreadable leftovers
+        older_than: Option<chrono::Duration>,
+        dry_run: bool,
+    },
+    Signal {
+        instance_id: String,
+        signal: nix::sys::signal::Signal,
+    },
+    SyncEnable {
+        instance_id: String,
+        enabled: bool,
+    },
+    List {
+        filter: InstanceFilter,
+        sort: InstanceSort,
+        json: bool,
+    },
     Attach {
         instance_id: String,
     },
@@ -29,14 +65,38 @@ pub enum CliCommand {
     Logs {
         instance_id: Option<String>,
         lines: Option<usize>,
+        stream: Option<LogStream>,
+        all: bool,
+        window: LogWindow,
+    },
+    Grep {
+        instance_id: String,
+        pattern: String,
+        ignore_case: bool,
+        context: usize,
+        files: bool, // Search the on-disk output logs instead of the in-memory history
     },
     Checkpoint {
         instance_id: String,
         name: String,
+        set_base: bool,
+        stop: bool, // Let CRIU terminate the process after the dump
+    },
+    CheckpointLabeled {
+        labels: Vec<(String, String)>,
+        name: String,
+        set_base: bool,
+        stop: bool,
     },
     Restore {
         instance_id: String,
         checkpoint_name: String,
+        options: RestoreOptions,
+    },
+    CheckpointDiff {
+        instance_id: String,
+        a: String,
+        b: String,
     },
     Cd {
         directory: String,
@@ -51,25 +111,73 @@ pub enum CliCommand {
     },
     ClusterConnect {
         address: String,
+        persist: bool,
+        wait_ready: Option<u64>, // Seconds to wait for the peer to hold shadows of our instances
     },
     ClusterDisconnect {
         node_id: String,
     },
-    ClusterStatus,
+    ClusterForget {
+        target: String,
+    },
+    ClusterStatus {
+        watch: bool,
+        interval_secs: u64,
+    },
+    ClusterCapabilities,
+    Stats {
+        json: bool,
+    },
+    ClusterTopology,
+    Locate {
+        instance_id: String,
+    },
+    ClusterPing {
+        node_id: String,
+    },
     // Migration commands
     Migrate {
         instance_id: String,
         target_node_id: String,
+        wait: bool,
+        timeout_secs: Option<u64>,
     },
+    MigrateMany {
+        instance_ids: Vec<String>, // empty with `all`: every running instance on this node
+        all: bool,
+        target_node_id: Option<String>, // None: each instance goes to the least-loaded eligible node
+        parallel: usize,
+        wait: bool,
+        timeout_secs: Option<u64>,
+    },
+    MigrationStatus {
+        migration_id: Option<String>,
+        json: bool,
+    },
+    WatchMigrations,
     // Shadow instance commands
     ShadowView {
         instance_id: String,
     },
 }
 
+/// Commands whose values may contain spaces (program arguments, hook commands, environment
+/// values, search patterns); only these are split with shell quoting
+const QUOTING_COMMANDS: [&str; 5] = ["start", "start-detached", "startd", "edit", "grep"];
+
 impl CliCommand {
     pub fn parse_from_str(input: &str) -> Result<Self> {
-        let parts: Vec<&str> = input.trim().split_whitespace().collect();
+        // Quote like a shell, so `--pre-checkpoint-cmd "redis-cli save"` stays one value
+        let words: Vec<String> = match input.split_whitespace().next() {
+            Some(command) if QUOTING_COMMANDS.contains(&command) => shell_words::split(input).map_err(|_| {
+                CriuCliError::ParseError(format!(
+                    "Unbalanced quotes in {} command: close every \" and ' (or escape it with \\)",
+                    command
+                ))
+            })?,
+            _ => input.split_whitespace().map(str::to_string).collect(),
+        };
+        let parts: Vec<&str> = words.iter().map(String::as_str).collect();
 
         if parts.is_empty() {
             return Err(CriuCliError::ParseError("Empty command".to_string()));
@@ -79,33 +187,58 @@ impl CliCommand {
             "help" | "h" => Ok(CliCommand::Help),
             "exit" | "quit" | "q" => Ok(CliCommand::Exit),
             "start" => {
-                if parts.len() < 2 {
+                let (options, rest) = Self::parse_start_flags(&parts[1..])?;
+                if options.foreground && options.start_paused {
+                    return Err(CriuCliError::ParseError(
+                        "--foreground cannot be combined with --start-paused".to_string(),
+                    ));
+                }
+                if rest.is_empty() {
                     return Err(CriuCliError::ParseError(
                         "start command requires a program name".to_string(),
                     ));
                 }
-                let program = parts[1].to_string();
-                let args = parts[2..].iter().map(|s| s.to_string()).collect();
-                Ok(CliCommand::Start { program, args })
+                let program = rest[0].to_string();
+                let args = rest[1..].iter().map(|s| s.to_string()).collect();
+                Ok(CliCommand::Start { program, args, options })
             }
             "start-detached" | "startd" => {
-                if parts.len() < 2 {
+                let (options, rest) = Self::parse_start_flags(&parts[1..])?;
+                if options.foreground {
+                    return Err(CriuCliError::ParseError(
+                        "--foreground is not supported for detached instances".to_string(),
+                    ));
+                }
+                if rest.is_empty() {
                     return Err(CriuCliError::ParseError(
                         "start-detached command requires a program name".to_string(),
                     ));
                 }
-                let program = parts[1].to_string();
-                let args = parts[2..].iter().map(|s| s.to_string()).collect();
-                Ok(CliCommand::StartDetached { program, args })
+                let program = rest[0].to_string();
+                let args = rest[1..].iter().map(|s| s.to_string()).collect();
+                Ok(CliCommand::StartDetached { program, args, options })
             }
             "stop" => {
-                if parts.len() != 2 {
+                let (labels, rest) = Self::take_labels(&parts[1..])?;
+                let if_running = rest.contains(&"--if-running");
+                let positional: Vec<&str> = rest.iter().copied().filter(|part| *part != "--if-running").collect();
+                if !labels.is_empty() {
+                    // Label selection only ever picks running instances, so --if-running is implied
+                    if !positional.is_empty() {
+                        return Err(CriuCliError::ParseError(
+                            "stop takes either an instance ID or --label selectors".to_string(),
+                        ));
+                    }
+                    return Ok(CliCommand::StopLabeled { labels });
+                }
+                if positional.len() != 1 {
                     return Err(CriuCliError::ParseError(
                         "stop command requires an instance ID".to_string(),
                     ));
                 }
                 Ok(CliCommand::Stop {
-                    instance_id: parts[1].to_string(),
+                    instance_id: positional[0].to_string(),
+                    if_running,
                 })
             }
             "pause" => {
@@ -128,7 +261,182 @@ impl CliCommand {
                     instance_id: parts[1].to_string(),
                 })
             }
-            "list" | "ls" => Ok(CliCommand::List),
+            "retry" => {
+                if parts.len() != 2 {
+                    return Err(CriuCliError::ParseError(
+                        "retry command requires an instance ID".to_string(),
+                    ));
+                }
+                Ok(CliCommand::Retry {
+                    instance_id: parts[1].to_string(),
+                })
+            }
+            "edit" => {
+                if parts.len() < 2 || parts[1].starts_with("--") {
+                    return Err(CriuCliError::ParseError(
+                        "edit command requires an instance ID".to_string(),
+                    ));
+                }
+                let edit = Self::parse_edit_flags(&parts[2..])?;
+                if edit.is_empty() {
+                    return Err(CriuCliError::ParseError(
+                        "edit command requires --args, --env or --cwd".to_string(),
+                    ));
+                }
+                Ok(CliCommand::Edit {
+                    instance_id: parts[1].to_string(),
+                    edit,
+                })
+            }
+            "restart" => {
+                if parts.len() != 2 {
+                    return Err(CriuCliError::ParseError(
+                        "restart command requires an instance ID".to_string(),
+                    ));
+                }
+                Ok(CliCommand::Restart {
+                    instance_id: parts[1].to_string(),
+                })
+            }
+            "purge" => {
+                let mut status = None;
+                let mut older_than = None;
+                let mut dry_run = false;
+                let mut idx = 1;
+                while idx < parts.len() {
+                    match parts[idx] {
+                        "--dry-run" => dry_run = true,
+                        flag @ ("--status" | "--older-than") => {
+                            let value = parts.get(idx + 1).ok_or_else(|| {
+                                CriuCliError::ParseError(format!("{} requires a value", flag))
+                            })?;
+                            if flag == "--older-than" {
+                                older_than = Some(crate::types::parse_duration(value)?);
+                            } else {
+                                status = match *value {
+                                    "stopped" => Some(InstanceStatus::Stopped),
+                                    "failed" => Some(InstanceStatus::Failed),
+                                    "all" => None,
+                                    other => {
+                                        return Err(CriuCliError::ParseError(format!(
+                                            "purge --status must be stopped, failed or all, not {}",
+                                            other
+                                        )))
+                                    }
+                                };
+                            }
+                            idx += 1;
+                        }
+                        other => {
+                            return Err(CriuCliError::ParseError(format!(
+                                "Unknown purge option: {}. Usage: purge [--status stopped|failed|all] [--older-than <dur>] [--dry-run]",
+                                other
+                            )))
+                        }
+                    }
+                    idx += 1;
+                }
+                Ok(CliCommand::Purge { status, older_than, dry_run })
+            }
+            "info" => {
+                if parts.len() != 2 {
+                    return Err(CriuCliError::ParseError(
+                        "info command requires an instance ID".to_string(),
+                    ));
+                }
+                Ok(CliCommand::Info {
+                    instance_id: parts[1].to_string(),
+                })
+            }
+            "locate" => {
+                if parts.len() != 2 {
+                    return Err(CriuCliError::ParseError(
+                        "locate command requires an instance ID".to_string(),
+                    ));
+                }
+                Ok(CliCommand::Locate {
+                    instance_id: parts[1].to_string(),
+                })
+            }
+            "kill" | "signal" => {
+                if parts.len() != 3 {
+                    return Err(CriuCliError::ParseError(
+                        "kill command requires an instance ID and a signal".to_string(),
+                    ));
+                }
+                Ok(CliCommand::Signal {
+                    instance_id: parts[1].to_string(),
+                    signal: Self::parse_signal(parts[2])?,
+                })
+            }
+            "sync" => {
+                if parts.len() != 3 {
+                    return Err(CriuCliError::ParseError(
+                        "sync command requires an instance ID and on/off".to_string(),
+                    ));
+                }
+                let enabled = match parts[2] {
+                    "on" | "enable" | "true" => true,
+                    "off" | "disable" | "false" => false,
+                    other => {
+                        return Err(CriuCliError::ParseError(format!(
+                            "Invalid sync value: {}. Use on or off",
+                            other
+                        )))
+                    }
+                };
+                Ok(CliCommand::SyncEnable {
+                    instance_id: parts[1].to_string(),
+                    enabled,
+                })
+            }
+            "list" | "ls" => {
+                let mut filter = InstanceFilter::default();
+                let mut sort = InstanceSort::default();
+                let mut json = false;
+                let mut idx = 1;
+                while idx < parts.len() {
+                    let flag = parts[idx];
+                    if flag == "--json" {
+                        json = true;
+                        idx += 1;
+                        continue;
+                    }
+                    let value = match flag {
+                        "--output" => {
+                            json = match parts.get(idx + 1) {
+                                Some(&"json") => true,
+                                Some(&"text") => false,
+                                _ => {
+                                    return Err(CriuCliError::ParseError(
+                                        "--output requires 'json' or 'text'".to_string(),
+                                    ))
+                                }
+                            };
+                            idx += 2;
+                            continue;
+                        }
+                        "--status" | "--node" | "--program" | "--sort" | "--label" => parts.get(idx + 1).ok_or_else(|| {
+                            CriuCliError::ParseError(format!("{} requires a value", flag))
+                        })?,
+                        other => {
+                            return Err(CriuCliError::ParseError(format!(
+                                "Unknown list option: {}",
+                                other
+                            )))
+                        }
+                    };
+                    match flag {
+                        "--status" => filter.status = Some(value.parse()?),
+                        "--node" => filter.node = Some(value.to_string()),
+                        "--sort" => sort = value.parse()?,
+                        "--label" => filter.labels.push(crate::types::parse_label(value)?),
+                        _ => filter.program = Some(value.to_string()),
+                    }
+                    idx += 2;
+                }
+                Ok(CliCommand::List { filter, sort, json })
+            }
             "attach" => {
                 if parts.len() != 2 {
                     return Err(CriuCliError::ParseError(
@@ -141,38 +449,188 @@ impl CliCommand {
             }
             "detach" => Ok(CliCommand::Detach),
             "logs" => {
-                let instance_id = if parts.len() > 1 {
-                    Some(parts[1].to_string())
-                } else {
-                    None
-                };
-                let lines = if parts.len() > 2 {
-                    parts[2].parse().ok()
+                // `--stream <stdout|stderr>`, `--since/--until <time>` and `--all` may appear anywhere,
+                // the rest is positional
+                let mut stream = None;
+                let mut all = false;
+                let mut window = LogWindow::default();
+                let mut positional = Vec::new();
+                let mut idx = 1;
+                while idx < parts.len() {
+                    if parts[idx] == "--all" {
+                        all = true;
+                        idx += 1;
+                    } else if parts[idx] == "--stream" {
+                        let value = parts.get(idx + 1).ok_or_else(|| {
+                            CriuCliError::ParseError("--stream requires stdout or stderr".to_string())
+                        })?;
+                        stream = Some(value.parse()?);
+                        idx += 2;
+                    } else if parts[idx] == "--since" || parts[idx] == "--until" {
+                        let value = parts.get(idx + 1).ok_or_else(|| {
+                            CriuCliError::ParseError(format!("{} requires a duration (e.g. 5m) or an RFC 3339 time", parts[idx]))
+                        })?;
+                        if parts[idx] == "--since" {
+                            window.since = Some(value.parse()?);
+                        } else {
+                            window.until = Some(value.parse()?);
+                        }
+                        idx += 2;
+                    } else {
+                        positional.push(parts[idx]);
+                        idx += 1;
+                    }
+                }
+                let instance_id = positional.first().map(|s| s.to_string());
+                let lines = if positional.len() > 1 {
+                    positional[1].parse().ok()
+                } else if !window.is_empty() {
+                    None // Everything in the window
                 } else {
                     Some(20) // Default to 20 lines
                 };
-                Ok(CliCommand::Logs { instance_id, lines })
+                if all && stream.is_some() {
+                    return Err(CriuCliError::ParseError(
+                        "--all covers the combined log and cannot be used with --stream".to_string(),
+                    ));
+                }
+                Ok(CliCommand::Logs { instance_id, lines, stream, all, window })
+            }
+            "grep" => {
+                let mut ignore_case = false;
+                let mut context = 0;
+                let mut files = false;
+                let mut positional = Vec::new();
+                let mut idx = 1;
+                while idx < parts.len() {
+                    match parts[idx] {
+                        "-i" | "--ignore-case" => ignore_case = true,
+                        "--files" => files = true,
+                        "-C" | "--context" => {
+                            context = parts.get(idx + 1).and_then(|v| v.parse().ok()).ok_or_else(|| {
+                                CriuCliError::ParseError("--context requires a number of lines".to_string())
+                            })?;
+                            idx += 1;
+                        }
+                        other => positional.push(other),
+                    }
+                    idx += 1;
+                }
+                if positional.len() != 2 {
+                    return Err(CriuCliError::ParseError(
+                        "usage: grep [-i|--ignore-case] [--context <n>] [--files] <instance_id> <pattern> (quote a pattern with spaces)".to_string(),
+                    ));
+                }
+                Ok(CliCommand::Grep {
+                    instance_id: positional[0].to_string(),
+                    // A pattern with spaces is quoted, so it arrives as a single word
+                    pattern: positional[1].to_string(),
+                    ignore_case,
+                    context,
+                    files,
+                })
             }
             "checkpoint" | "cp" => {
-                if parts.len() != 3 {
+                let set_base = parts[1..].contains(&"--set-base");
+                let stop = parts[1..].contains(&"--stop");
+                let (labels, rest) = Self::take_labels(&parts[1..])?;
+                let positional: Vec<&str> = rest
+                    .into_iter()
+                    .filter(|p| *p != "--set-base" && *p != "--stop")
+                    .collect();
+                if !labels.is_empty() {
+                    if positional.len() != 1 {
+                        return Err(CriuCliError::ParseError(
+                            "checkpoint --label requires a checkpoint name".to_string(),
+                        ));
+                    }
+                    return Ok(CliCommand::CheckpointLabeled {
+                        labels,
+                        name: positional[0].to_string(),
+                        set_base,
+                        stop,
+                    });
+                }
+                if positional.len() != 2 {
                     return Err(CriuCliError::ParseError(
                         "checkpoint command requires instance ID and checkpoint name".to_string(),
                     ));
                 }
                 Ok(CliCommand::Checkpoint {
-                    instance_id: parts[1].to_string(),
-                    name: parts[2].to_string(),
+                    instance_id: positional[0].to_string(),
+                    name: positional[1].to_string(),
+                    set_base,
+                    stop,
                 })
             }
-            "restore" => {
-                if parts.len() != 3 {
+            "checkpoint-diff" => {
+                if parts.len() != 4 {
                     return Err(CriuCliError::ParseError(
-                        "restore command requires instance ID and checkpoint name".to_string(),
+                        "usage: checkpoint-diff <instance_id> <checkpoint_a> <checkpoint_b>".to_string(),
                     ));
                 }
-                Ok(CliCommand::Restore {
+                Ok(CliCommand::CheckpointDiff {
                     instance_id: parts[1].to_string(),
-                    checkpoint_name: parts[2].to_string(),
+                    a: parts[2].to_string(),
+                    b: parts[3].to_string(),
+                })
+            }
+            "restore" => {
+                let mut options = RestoreOptions::default();
+                let mut snapshot = None;
+                let mut positional = Vec::new();
+                let mut idx = 1;
+                while idx < parts.len() {
+                    match parts[idx] {
+                        flag @ ("--uid-map" | "--gid-map") => {
+                            let value = parts.get(idx + 1).ok_or_else(|| {
+                                CriuCliError::ParseError(format!("{} requires <from>:<to>", flag))
+                            })?;
+                            if flag == "--uid-map" {
+                                options.uid_map = Some(value.parse()?);
+                            } else {
+                                options.gid_map = Some(value.parse()?);
+                            }
+                            idx += 1;
+                        }
+                        "--replace" => options.replace = true,
+                        "--tty" => {
+                            let value = parts.get(idx + 1).ok_or_else(|| {
+                                CriuCliError::ParseError("--tty requires inherit, null or pty".to_string())
+                            })?;
+                            options.tty = value.parse()?;
+                            idx += 1;
+                        }
+                        "--snapshot" => {
+                            let value = parts.get(idx + 1).ok_or_else(|| {
+                                CriuCliError::ParseError("--snapshot requires a snapshot time".to_string())
+                            })?;
+                            snapshot = Some(crate::instance::snapshot_checkpoint_name(value));
+                            idx += 1;
+                        }
+                        other => positional.push(other),
+                    }
+                    idx += 1;
+                }
+                // `--snapshot <time>` stands in for the checkpoint name
+                let checkpoint_name = match (snapshot, positional.len()) {
+                    (Some(snapshot), 1) => snapshot,
+                    (None, 2) => positional[1].to_string(),
+                    (Some(_), _) => {
+                        return Err(CriuCliError::ParseError(
+                            "restore --snapshot requires an instance ID and no checkpoint name".to_string(),
+                        ));
+                    }
+                    (None, _) => {
+                        return Err(CriuCliError::ParseError(
+                            "restore command requires instance ID and checkpoint name".to_string(),
+                        ));
+                    }
+                };
+                Ok(CliCommand::Restore {
+                    instance_id: positional[0].to_string(),
+                    checkpoint_name,
+                    options,
                 })
             }
             "cd" => {
@@ -212,13 +670,44 @@ impl CliCommand {
                         Ok(CliCommand::ClusterNodeInfo { node_id })
                     }
                     "connect" => {
-                        if parts.len() != 3 {
+                        let mut persist = false;
+                        let mut wait_ready = false;
+                        let mut timeout_secs = 30;
+                        let mut rest = Vec::new();
+                        let mut idx = 2;
+                        while idx < parts.len() {
+                            match parts[idx] {
+                                "--persist" => persist = true,
+                                "--wait-ready" => wait_ready = true,
+                                "--timeout" => {
+                                    timeout_secs = parts.get(idx + 1).and_then(|v| v.parse().ok()).ok_or_else(|| {
+                                        CriuCliError::ParseError("--timeout requires a number of seconds".to_string())
+                                    })?;
+                                    idx += 1;
+                                }
+                                other => rest.push(other),
+                            }
+                            idx += 1;
+                        }
+                        if rest.len() != 1 {
                             return Err(CriuCliError::ParseError(
                                 "cluster connect requires an address".to_string(),
                             ));
                         }
                         Ok(CliCommand::ClusterConnect {
-                            address: parts[2].to_string(),
+                            address: rest[0].to_string(),
+                            persist,
+                            wait_ready: wait_ready.then_some(timeout_secs),
+                        })
+                    }
+                    "forget" => {
+                        if parts.len() != 3 {
+                            return Err(CriuCliError::ParseError(
+                                "cluster forget requires a node ID or address".to_string(),
+                            ));
+                        }
+                        Ok(CliCommand::ClusterForget {
+                            target: parts[2].to_string(),
                         })
                     }
                     "disconnect" => {
@@ -231,24 +720,194 @@ impl CliCommand {
                             node_id: parts[2].to_string(),
                         })
                     }
-                    "status" => Ok(CliCommand::ClusterStatus),
+                    "status" => {
+                        let mut watch = false;
+                        let mut interval_secs = 2;
+                        let mut idx = 2;
+                        while idx < parts.len() {
+                            match parts[idx] {
+                                "--watch" => watch = true,
+                                "--interval" => {
+                                    let value = parts.get(idx + 1).ok_or_else(|| {
+                                        CriuCliError::ParseError("--interval requires a number of seconds".to_string())
+                                    })?;
+                                    interval_secs = value.parse().ok().filter(|secs| *secs > 0).ok_or_else(|| {
+                                        CriuCliError::ParseError(format!("Invalid interval: {}", value))
+                                    })?;
+                                    idx += 1;
+                                }
+                                other => {
+                                    return Err(CriuCliError::ParseError(format!(
+                                        "Unknown option for cluster status: {}. Usage: cluster status [--watch] [--interval <secs>]",
+                                        other
+                                    )));
+                                }
+                            }
+                            idx += 1;
+                        }
+                        Ok(CliCommand::ClusterStatus { watch, interval_secs })
+                    }
+                    "capabilities" | "caps" => Ok(CliCommand::ClusterCapabilities),
+                    "topology" => Ok(CliCommand::ClusterTopology),
+                    "ping" => {
+                        if parts.len() != 3 {
+                            return Err(CriuCliError::ParseError(
+                                "cluster ping requires a node ID".to_string(),
+                            ));
+                        }
+                        Ok(CliCommand::ClusterPing {
+                            node_id: parts[2].to_string(),
+                        })
+                    }
                     _ => Err(CriuCliError::ParseError(format!(
-                        "Unknown cluster subcommand: {}. Available: list-nodes, node-info, connect, disconnect, status",
+                        "Unknown cluster subcommand: {}. Available: list-nodes, node-info, connect, disconnect, forget, status, capabilities, topology, ping",
                         parts[1]
                     ))),
                 }
             }
             "migrate" => {
-                if parts.len() != 3 {
+                let mut wait = false;
+                let mut timeout_secs = None;
+                let mut target_node_id = None;
+                let mut all = false;
+                let mut evacuate = false;
+                let mut parallel = None;
+                let mut positional = Vec::new();
+                let mut idx = 1;
+                while idx < parts.len() {
+                    match parts[idx] {
+                        "--wait" => wait = true,
+                        "--all" => all = true,
+                        "--evacuate" => evacuate = true,
+                        "--timeout" => {
+                            let value = parts.get(idx + 1).ok_or_else(|| {
+                                CriuCliError::ParseError("--timeout requires a number of seconds".to_string())
+                            })?;
+                            timeout_secs = Some(value.parse().map_err(|_| {
+                                CriuCliError::ParseError(format!("Invalid timeout: {}", value))
+                            })?);
+                            idx += 1;
+                        }
+                        "--target" => {
+                            let value = parts.get(idx + 1).ok_or_else(|| {
+                                CriuCliError::ParseError("--target requires a node ID".to_string())
+                            })?;
+                            target_node_id = Some(value.to_string());
+                            idx += 1;
+                        }
+                        "--parallel" => {
+                            let value = parts.get(idx + 1).ok_or_else(|| {
+                                CriuCliError::ParseError("--parallel requires a number of migrations".to_string())
+                            })?;
+                            parallel = match value.parse::<usize>() {
+                                Ok(n) if n > 0 => Some(n),
+                                _ => return Err(CriuCliError::ParseError(format!("Invalid parallel count: {}", value))),
+                            };
+                            idx += 1;
+                        }
+                        other => positional.push(other),
+                    }
+                    idx += 1;
+                }
+                if timeout_secs.is_some() && !wait {
+                    return Err(CriuCliError::ParseError(
+                        "--timeout is only valid with --wait".to_string(),
+                    ));
+                }
+                if evacuate || all || target_node_id.is_some() {
+                    if evacuate && (all || target_node_id.is_some()) {
+                        return Err(CriuCliError::ParseError(
+                            "--evacuate picks its own targets and cannot be combined with --all or --target".to_string(),
+                        ));
+                    }
+                    if all && target_node_id.is_none() {
+                        return Err(CriuCliError::ParseError(
+                            "--all requires --target <node_id> (or use --evacuate)".to_string(),
+                        ));
+                    }
+                    if (evacuate || all) && !positional.is_empty() {
+                        return Err(CriuCliError::ParseError(
+                            "migrate takes either instance IDs or --all/--evacuate".to_string(),
+                        ));
+                    }
+                    if !evacuate && !all && positional.is_empty() {
+                        return Err(CriuCliError::ParseError(
+                            "migrate --target requires at least one instance ID".to_string(),
+                        ));
+                    }
+                    return Ok(CliCommand::MigrateMany {
+                        instance_ids: positional.iter().map(|id| id.to_string()).collect(),
+                        all: all || evacuate,
+                        target_node_id,
+                        parallel: parallel.unwrap_or(crate::migration_manager::DEFAULT_MAX_CONCURRENT_MIGRATIONS),
+                        wait,
+                        timeout_secs,
+                    });
+                }
+                if parallel.is_some() {
+                    return Err(CriuCliError::ParseError(
+                        "--parallel is only valid with --target, --all or --evacuate".to_string(),
+                    ));
+                }
+                if positional.len() != 2 {
                     return Err(CriuCliError::ParseError(
                         "migrate command requires instance ID and target node ID".to_string(),
                     ));
                 }
                 Ok(CliCommand::Migrate {
-                    instance_id: parts[1].to_string(),
-                    target_node_id: parts[2].to_string(),
+                    instance_id: positional[0].to_string(),
+                    target_node_id: positional[1].to_string(),
+                    wait,
+                    timeout_secs,
                 })
             }
+            "stats" => {
+                let json = match &parts[1..] {
+                    [] => false,
+                    ["--json"] | ["--output", "json"] => true,
+                    ["--output", "text"] => false,
+                    _ => {
+                        return Err(CriuCliError::ParseError(
+                            "usage: stats [--json | --output json|text]".to_string(),
+                        ))
+                    }
+                };
+                Ok(CliCommand::Stats { json })
+            }
+            "migration-status" => {
+                let mut migration_id = None;
+                let mut json = false;
+                let mut idx = 1;
+                while idx < parts.len() {
+                    match parts[idx] {
+                        "--json" => json = true,
+                        "--output" => {
+                            match parts.get(idx + 1) {
+                                Some(&"json") => json = true,
+                                Some(&"text") => json = false,
+                                _ => {
+                                    return Err(CriuCliError::ParseError(
+                                        "--output requires 'json' or 'text'".to_string(),
+                                    ))
+                                }
+                            }
+                            idx += 1;
+                        }
+                        other if migration_id.is_none() => migration_id = Some(other.to_string()),
+                        _ => {
+                            return Err(CriuCliError::ParseError(
+                                "migration-status takes at most one migration ID".to_string(),
+                            ))
+                        }
+                    }
+                    idx += 1;
+                }
+                Ok(CliCommand::MigrationStatus { migration_id, json })
+            }
+            "watch" => match parts.get(1) {
+                Some(&"migrations") if parts.len() == 2 => Ok(CliCommand::WatchMigrations),
+                _ => Err(CriuCliError::ParseError("usage: watch migrations".to_string())),
+            },
             "shadow-view" | "shadow" => {
                 if parts.len() != 2 {
                     return Err(CriuCliError::ParseError(
@@ -265,12 +924,190 @@ impl CliCommand {
             ))),
         }
     }
+
+    /// Parse a signal given as a number (`10`), a name (`USR1`, `SIGUSR1`) or in kill(1) style (`-USR1`)
+    fn parse_signal(value: &str) -> Result<nix::sys::signal::Signal> {
+        use std::str::FromStr;
+        let value = value.trim_start_matches('-');
+        let parsed = if let Ok(number) = value.parse::<i32>() {
+            nix::sys::signal::Signal::try_from(number).ok()
+        } else {
+            let name = value.to_uppercase();
+            let name = if name.starts_with("SIG") { name } else { format!("SIG{}", name) };
+            nix::sys::signal::Signal::from_str(&name).ok()
+        };
+        parsed.ok_or_else(|| CriuCliError::ParseError(format!("Invalid signal: {}", value)))
+    }
+
+    /// Parse `edit` options; `--args` takes every remaining part, so it comes last
+    fn parse_edit_flags(parts: &[&str]) -> Result<InstanceEdit> {
+        let mut edit = InstanceEdit::default();
+        let mut idx = 0;
+        while idx < parts.len() {
+            match parts[idx] {
+                "--args" => {
+                    edit.args = Some(parts[idx + 1..].iter().map(|s| s.to_string()).collect());
+                    break;
+                }
+                "--env" => {
+                    let value = parts.get(idx + 1).ok_or_else(|| {
+                        CriuCliError::ParseError("--env requires KEY=VALUE".to_string())
+                    })?;
+                    let (key, val) = value
+                        .split_once('=')
+                        .filter(|(key, _)| !key.is_empty())
+                        .ok_or_else(|| CriuCliError::ParseError(format!("Invalid --env value (expected KEY=VALUE): {}", value)))?;
+                    edit.env.push((key.to_string(), val.to_string()));
+                    idx += 1;
+                }
+                "--cwd" => {
+                    let value = parts.get(idx + 1).ok_or_else(|| {
+                        CriuCliError::ParseError("--cwd requires a directory".to_string())
+                    })?;
+                    edit.cwd = Some(std::path::PathBuf::from(value));
+                    idx += 1;
+                }
+                other => {
+                    return Err(CriuCliError::ParseError(format!(
+                        "Unknown edit option: {}",
+                        other
+                    )))
+                }
+            }
+            idx += 1;
+        }
+        Ok(edit)
+    }
+
+    /// Split `--label key=value` selectors off `parts`, returning the labels and the other parts
+    fn take_labels<'a>(parts: &[&'a str]) -> Result<(crate::types::LabelSelector, Vec<&'a str>)> {
+        let mut labels = Vec::new();
+        let mut rest = Vec::new();
+        let mut idx = 0;
+        while idx < parts.len() {
+            if parts[idx] == "--label" {
+                let value = parts.get(idx + 1).ok_or_else(|| {
+                    CriuCliError::ParseError("--label requires key=value".to_string())
+                })?;
+                labels.push(crate::types::parse_label(value)?);
+                idx += 2;
+            } else {
+                rest.push(parts[idx]);
+                idx += 1;
+            }
+        }
+        Ok((labels, rest))
+    }
+
+    /// Split leading `--flag` options off a start command, returning the options and the remaining parts
+    fn parse_start_flags<'a>(parts: &'a [&'a str]) -> Result<(StartOptions, &'a [&'a str])> {
+        let mut options = StartOptions::default();
+        let mut snapshot_interval = None;
+        let mut snapshot_retain = None;
+        let mut idx = 0;
+        while idx < parts.len() && parts[idx].starts_with("--") {
+            match parts[idx] {
+                "--sync" => options.sync = true,
+                "--foreground" => options.foreground = true,
+                "--start-paused" => options.start_paused = true,
+                "--append-only" => options.append_only = true,
+                "--label" => {
+                    let value = parts.get(idx + 1).ok_or_else(|| {
+                        CriuCliError::ParseError("--label requires key=value".to_string())
+                    })?;
+                    let (key, value) = crate::types::parse_label(value)?;
+                    options.labels.insert(key, value);
+                    idx += 1;
+                }
+                "--timestamp" => options.output_timestamps = Some(OutputTimestamps::Rfc3339),
+                "--relative-timestamp" => options.output_timestamps = Some(OutputTimestamps::Relative),
+                "--no-timestamp" => options.output_timestamps = Some(OutputTimestamps::Off),
+                "--id" => {
+                    let value = parts.get(idx + 1).ok_or_else(|| {
+                        CriuCliError::ParseError("--id requires an instance UUID".to_string())
+                    })?;
+                    let id = uuid::Uuid::parse_str(value).map_err(|_| {
+                        CriuCliError::ParseError(format!("Invalid instance ID for --id: {}", value))
+                    })?;
+                    options.instance_id = Some(id);
+                    idx += 1;
+                }
+                flag @ ("--require-capability" | "--node-affinity") => {
+                    let value = parts.get(idx + 1).ok_or_else(|| {
+                        CriuCliError::ParseError(format!("{} requires a value", flag))
+                    })?;
+                    if flag == "--require-capability" {
+                        options.affinity.required_capabilities.push(value.to_string());
+                    } else {
+                        let node_id = uuid::Uuid::parse_str(value).map_err(|_| {
+                            CriuCliError::ParseError(format!("Invalid node ID for --node-affinity: {}", value))
+                        })?;
+                        options.affinity.nodes.push(node_id);
+                    }
+                    idx += 1;
+                }
+                flag @ ("--pre-checkpoint-cmd" | "--post-checkpoint-cmd" | "--output-file") => {
+                    let value = parts.get(idx + 1).ok_or_else(|| {
+                        CriuCliError::ParseError(format!("{} requires a value", flag))
+                    })?;
+                    match flag {
+                        "--pre-checkpoint-cmd" => options.checkpoint_hooks.pre_checkpoint_cmd = Some(value.to_string()),
+                        "--post-checkpoint-cmd" => options.checkpoint_hooks.post_checkpoint_cmd = Some(value.to_string()),
+                        _ => options.output_file = Some(std::path::PathBuf::from(value)),
+                    }
+                    idx += 1;
+                }
+                "--snapshot-interval" => {
+                    let value = parts.get(idx + 1).ok_or_else(|| {
+                        CriuCliError::ParseError("--snapshot-interval requires a duration (e.g. 1h)".to_string())
+                    })?;
+                    let interval = crate::types::parse_duration(value)?;
+                    if interval.num_seconds() <= 0 {
+                        return Err(CriuCliError::ParseError("--snapshot-interval must be positive".to_string()));
+                    }
+                    snapshot_interval = Some(interval.num_seconds() as u64);
+                    idx += 1;
+                }
+                "--snapshot-retain" => {
+                    let retain = parts.get(idx + 1).and_then(|v| v.parse::<usize>().ok()).filter(|n| *n > 0).ok_or_else(|| {
+                        CriuCliError::ParseError("--snapshot-retain requires a positive number".to_string())
+                    })?;
+                    snapshot_retain = Some(retain);
+                    idx += 1;
+                }
+                other => {
+                    return Err(CriuCliError::ParseError(format!(
+                        "Unknown start option: {}",
+                        other
+                    )))
+                }
+            }
+            idx += 1;
+        }
+        match (snapshot_interval, snapshot_retain) {
+            (Some(interval_secs), retain) => {
+                options.snapshot_policy = Some(crate::types::SnapshotPolicy {
+                    interval_secs,
+                    retain: retain.unwrap_or(crate::instance::DEFAULT_SNAPSHOT_RETAIN),
+                });
+            }
+            (None, Some(_)) => {
+                return Err(CriuCliError::ParseError("--snapshot-retain requires --snapshot-interval".to_string()));
+            }
+            (None, None) => {}
+        }
+        Ok((options, &parts[idx..]))
+    }
 }
 
 #[derive(Debug)]
 pub struct CliState {
     pub attached_instance: Option<String>,
     pub output_task: Option<tokio::task::JoinHandle<()>>,
+    /// SIGINT/SIGTERM, for commands that block until the cluster catches up
+    pub signals: crate::signals::Signals,
+    /// Whether a terminal is there for full-screen sessions such as `start --foreground`
+    pub interactive: bool,
 }
 
 impl CliState {
@@ -278,6 +1115,129 @@ impl CliState {
         Self {
             attached_instance: None,
             output_task: None,
+            signals: crate::signals::Signals::new(),
+            interactive: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_hook_commands_stay_one_value() {
+        let command = CliCommand::parse_from_str(
+            r#"start --pre-checkpoint-cmd "redis-cli save" --post-checkpoint-cmd 'echo done' redis-server --port 6380"#,
+        )
+        .unwrap();
+        match command {
+            CliCommand::Start { program, args, options } => {
+                assert_eq!(options.checkpoint_hooks.pre_checkpoint_cmd.as_deref(), Some("redis-cli save"));
+                assert_eq!(options.checkpoint_hooks.post_checkpoint_cmd.as_deref(), Some("echo done"));
+                assert_eq!(program, "redis-server");
+                assert_eq!(args, vec!["--port", "6380"]);
+            }
+            other => panic!("expected Start, got {:?}", other),
+        }
+        assert!(CliCommand::parse_from_str(r#"start --pre-checkpoint-cmd "redis-cli save sleep 1"#).is_err());
+    }
+
+    #[test]
+    fn list_filters_by_every_label() {
+        let filter = match CliCommand::parse_from_str("list --label env=prod --label tier=web").unwrap() {
+            CliCommand::List { filter, .. } => filter,
+            other => panic!("expected List, got {:?}", other),
+        };
+        assert_eq!(filter.labels, vec![("env".to_string(), "prod".to_string()), ("tier".to_string(), "web".to_string())]);
+
+        let instance = |labels: &[(&str, &str)]| {
+            let mut instance = nhi::Instance::new("sleep".to_string(), Vec::new(), std::path::PathBuf::from("/"));
+            instance.labels = labels.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+            instance
+        };
+        assert!(filter.matches(&instance(&[("env", "prod"), ("tier", "web"), ("team", "a")]), "Running"));
+        assert!(!filter.matches(&instance(&[("env", "prod")]), "Running"));
+        assert!(!filter.matches(&instance(&[("env", "dev"), ("tier", "web")]), "Running"));
+        assert!(CliCommand::parse_from_str("list --label env").is_err());
+    }
+
+    #[test]
+    fn list_takes_json_output_with_a_sort() {
+        assert!(matches!(
+            CliCommand::parse_from_str("list --sort program --json").unwrap(),
+            CliCommand::List { sort: InstanceSort::Program, json: true, .. }
+        ));
+        assert!(matches!(CliCommand::parse_from_str("ls --output text").unwrap(), CliCommand::List { json: false, .. }));
+        assert!(CliCommand::parse_from_str("list --output yaml").is_err());
+    }
+
+    #[test]
+    fn list_takes_status_node_and_program_filters() {
+        match CliCommand::parse_from_str("list --status running --node 1a2b --program sleep").unwrap() {
+            CliCommand::List { filter, .. } => {
+                assert_eq!(filter.status, Some(InstanceStatus::Running));
+                assert_eq!(filter.node.as_deref(), Some("1a2b"));
+                assert_eq!(filter.program.as_deref(), Some("sleep"));
+            }
+            other => panic!("expected List, got {:?}", other),
+        }
+        assert!(CliCommand::parse_from_str("list --status sleeping").is_err());
+    }
+
+    #[test]
+    fn kill_takes_signal_numbers_and_names() {
+        use nix::sys::signal::Signal;
+        for (arg, expected) in [("10", Signal::SIGUSR1), ("USR1", Signal::SIGUSR1), ("-usr2", Signal::SIGUSR2), ("SIGHUP", Signal::SIGHUP)] {
+            match CliCommand::parse_from_str(&format!("kill abc123 {}", arg)).unwrap() {
+                CliCommand::Signal { instance_id, signal } => assert_eq!((instance_id.as_str(), signal), ("abc123", expected)),
+                other => panic!("expected Signal, got {:?}", other),
+            }
+        }
+        for bad in ["kill abc123 NOPE", "kill abc123 999", "kill abc123"] {
+            assert!(CliCommand::parse_from_str(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn sync_toggles_auto_sync_per_instance() {
+        assert!(matches!(
+            CliCommand::parse_from_str("sync abc123 on").unwrap(),
+            CliCommand::SyncEnable { ref instance_id, enabled: true } if instance_id == "abc123"
+        ));
+        assert!(matches!(CliCommand::parse_from_str("sync abc123 off").unwrap(), CliCommand::SyncEnable { enabled: false, .. }));
+        assert!(CliCommand::parse_from_str("sync abc123 maybe").is_err());
+        assert!(CliCommand::parse_from_str("sync abc123").is_err());
+        match CliCommand::parse_from_str("start --sync sleep 60").unwrap() {
+            CliCommand::Start { program, options, .. } => {
+                assert!(options.sync);
+                assert_eq!(program, "sleep");
+            }
+            other => panic!("expected Start, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn only_commands_taking_spaced_values_honour_quotes() {
+        match CliCommand::parse_from_str(r#"grep -i abc123 "connection reset by peer""#).unwrap() {
+            CliCommand::Grep { instance_id, pattern, ignore_case, .. } => {
+                assert_eq!(instance_id, "abc123");
+                assert_eq!(pattern, "connection reset by peer");
+                assert!(ignore_case);
+            }
+            other => panic!("expected Grep, got {:?}", other),
+        }
+        // An unquoted pattern with spaces is ambiguous rather than silently joined
+        assert!(CliCommand::parse_from_str("grep abc123 connection reset").is_err());
+
+        // Commands without free-form values keep splitting on whitespace, so a stray quote is just a character
+        assert!(matches!(
+            CliCommand::parse_from_str("checkpoint abc123 don't-lose-this").unwrap(),
+            CliCommand::Checkpoint { .. }
+        ));
+        match CliCommand::parse_from_str(r#"grep abc123 "unterminated"#) {
+            Err(CriuCliError::ParseError(message)) => assert!(message.contains("Unbalanced quotes in grep command"), "{}", message),
+            other => panic!("expected a quoting error, got {:?}", other),
         }
     }
 }
@@ -1,6 +1,9 @@
 use crate::message_protocol::*;
 use anyhow::Result;
 
+use std::collections::{HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::time::Duration;
 use std::sync::Arc;
 use tokio::sync::{mpsc, RwLock};
 use tracing::{debug, info, warn};
@@ -132,6 +135,33 @@ impl ClusterStateManager {
         state.get_online_nodes().into_iter().cloned().collect()
     }
 
+    /// Record the direct peers a node reported
+    pub async fn update_peer_links(&self, node_id: NodeId, peers: Vec<NodeId>) {
+        let mut state = self.cluster_state.write().await;
+        state.set_peer_links(node_id, PeerLinks { peers, updated_at: Utc::now() });
+    }
+
+    /// Nodes reachable from the local node over known links, excluding itself
+    pub async fn reachable_nodes(&self) -> HashSet<NodeId> {
+        let state = self.cluster_state.read().await;
+        let mut visited = HashSet::from([self.local_node_id]);
+        let mut queue = VecDeque::from([self.local_node_id]);
+
+        while let Some(node_id) = queue.pop_front() {
+            let neighbours = state.peer_links.iter()
+                .filter(|(from, links)| **from == node_id || links.peers.contains(&node_id))
+                .flat_map(|(from, links)| std::iter::once(*from).chain(links.peers.iter().copied()));
+            for neighbour in neighbours.collect::<Vec<_>>() {
+                if visited.insert(neighbour) {
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+
+        visited.remove(&self.local_node_id);
+        visited
+    }
+
     /// Get cluster statistics
     pub async fn get_cluster_stats(&self) -> ClusterStats {
         let state = self.cluster_state.read().await;
@@ -185,6 +215,13 @@ impl ClusterStateManager {
                 }
             }
 
+            // Merge gossiped peer links; our own links come from our connections only
+            for (node_id, links) in &remote_state.peer_links {
+                if *node_id != self.local_node_id && local_state.set_peer_links(*node_id, links.clone()) {
+                    debug!("Updated peer links for node {} from remote state", node_id);
+                }
+            }
+
             // Check for nodes that exist locally but not remotely
             let local_only_nodes: Vec<NodeId> = local_state.nodes.keys()
                 .filter(|id| !remote_state.nodes.contains_key(id) && **id != self.local_node_id)
@@ -270,6 +307,60 @@ impl ClusterStateManager {
         output
     }
 
+    /// Format the cluster topology (who is connected to whom) for display
+    pub async fn format_topology(&self) -> String {
+        let reachable = self.reachable_nodes().await;
+        let state = self.cluster_state.read().await;
+
+        if state.nodes.is_empty() {
+            return "No nodes in cluster".to_string();
+        }
+
+        let label = |node_id: &NodeId| match state.nodes.get(node_id) {
+            Some(node) => format!("{} ({})", node.name, node_id.to_string()[..8].to_uppercase()),
+            None => node_id.to_string()[..8].to_uppercase(),
+        };
+
+        let mut node_ids: Vec<NodeId> = state.nodes.keys()
+            .chain(state.peer_links.keys())
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        node_ids.sort_by_key(|id| label(id));
+
+        let direct: HashSet<NodeId> = state.peer_links.get(&self.local_node_id)
+            .map(|links| links.peers.iter().copied().collect())
+            .unwrap_or_default();
+
+        let mut output = String::new();
+        output.push_str("Cluster Topology:\n");
+        for node_id in &node_ids {
+            let reach = if *node_id == self.local_node_id {
+                "local"
+            } else if direct.contains(node_id) {
+                "direct"
+            } else if reachable.contains(node_id) {
+                "indirect"
+            } else {
+                "unreachable"
+            };
+            output.push_str(&format!("  {} [{}]\n", label(node_id), reach));
+
+            match state.peer_links.get(node_id) {
+                Some(links) if !links.peers.is_empty() => {
+                    let mut peers: Vec<String> = links.peers.iter().map(label).collect();
+                    peers.sort();
+                    output.push_str(&format!("    -> {}\n", peers.join(", ")));
+                }
+                Some(_) => output.push_str("    -> (no connections)\n"),
+                None => output.push_str("    -> (unknown)\n"),
+            }
+        }
+
+        output
+    }
+
     /// Format node list for display
     pub async fn format_node_list(&self) -> String {
         let state = self.cluster_state.read().await;
@@ -319,3 +410,226 @@ pub struct ClusterStats {
     pub created_at: DateTime<Utc>,
     pub last_updated: DateTime<Utc>,
 }
+
+/// Changes kept on screen by `cluster status --watch`
+const CLUSTER_WATCH_EVENTS: usize = 20;
+/// RTT moves smaller than this are noise, whatever the relative change
+const RTT_CHANGE_MIN: Duration = Duration::from_millis(2);
+/// Relative RTT change (in percent) worth reporting
+const RTT_CHANGE_PERCENT: u128 = 25;
+
+/// One peer as seen at a refresh of `cluster status --watch`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerSample {
+    pub node_id: NodeId,
+    pub name: String,
+    pub address: Option<SocketAddr>, // Some while we hold a connection to it
+    pub status: NodeStatus,
+    pub rtt: Option<Duration>, // None when not connected or the ping went unanswered
+}
+
+/// What changed about a peer between two refreshes
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClusterChange {
+    Joined,
+    Left,
+    StatusChanged { from: NodeStatus, to: NodeStatus },
+    RttChanged { from: Option<Duration>, to: Option<Duration> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClusterWatchEvent {
+    pub at: DateTime<Utc>,
+    pub node_id: NodeId,
+    pub name: String,
+    pub change: ClusterChange,
+}
+
+impl ClusterWatchEvent {
+    pub fn describe(&self) -> String {
+        let node = format!("{} ({})", &self.node_id.to_string()[..8], self.name);
+        match &self.change {
+            ClusterChange::Joined => format!("{} joined", node),
+            ClusterChange::Left => format!("{} left", node),
+            ClusterChange::StatusChanged { from, to } => format!("{} {:?} -> {:?}", node, from, to),
+            ClusterChange::RttChanged { from, to } => format!("{} RTT {} -> {}", node, format_rtt(*from), format_rtt(*to)),
+        }
+    }
+}
+
+/// RTT in milliseconds, or "-" when unknown
+pub fn format_rtt(rtt: Option<Duration>) -> String {
+    match rtt {
+        Some(rtt) => format!("{:.2} ms", rtt.as_secs_f64() * 1000.0),
+        None => "-".to_string(),
+    }
+}
+
+fn rtt_changed(from: Option<Duration>, to: Option<Duration>) -> bool {
+    match (from, to) {
+        (Some(from), Some(to)) => {
+            let delta = from.abs_diff(to);
+            delta >= RTT_CHANGE_MIN && delta.as_micros() * 100 >= from.as_micros() * RTT_CHANGE_PERCENT
+        }
+        (None, None) => false,
+        _ => true,
+    }
+}
+
+/// Peer view of `cluster status --watch`: the latest samples plus the changes seen between refreshes
+#[derive(Debug, Default)]
+pub struct ClusterWatch {
+    peers: Vec<PeerSample>,
+    events: VecDeque<ClusterWatchEvent>,
+    refreshed_at: Option<DateTime<Utc>>,
+}
+
+impl ClusterWatch {
+    /// Peers of the latest refresh, ordered by name
+    pub fn peers(&self) -> &[PeerSample] {
+        &self.peers
+    }
+
+    /// When the latest snapshot was taken
+    pub fn refreshed_at(&self) -> Option<DateTime<Utc>> {
+        self.refreshed_at
+    }
+
+    /// Recent changes, newest last
+    pub fn events(&self) -> &VecDeque<ClusterWatchEvent> {
+        &self.events
+    }
+
+    /// Replace the peer view with a new snapshot and return what changed since the last one.
+    /// The first snapshot is the baseline and reports nothing.
+    pub fn update(&mut self, mut samples: Vec<PeerSample>, at: DateTime<Utc>) -> Vec<ClusterWatchEvent> {
+        samples.sort_by(|a, b| a.name.cmp(&b.name).then(a.node_id.cmp(&b.node_id)));
+        let mut changes = Vec::new();
+        if self.refreshed_at.is_some() {
+            let event = |sample: &PeerSample, change| ClusterWatchEvent { at, node_id: sample.node_id, name: sample.name.clone(), change };
+            for sample in &samples {
+                match self.peers.iter().find(|peer| peer.node_id == sample.node_id) {
+                    None => changes.push(event(sample, ClusterChange::Joined)),
+                    Some(previous) => {
+                        if previous.status != sample.status {
+                            changes.push(event(sample, ClusterChange::StatusChanged { from: previous.status.clone(), to: sample.status.clone() }));
+                        }
+                        if rtt_changed(previous.rtt, sample.rtt) {
+                            changes.push(event(sample, ClusterChange::RttChanged { from: previous.rtt, to: sample.rtt }));
+                        }
+                    }
+                }
+            }
+            for previous in &self.peers {
+                if !samples.iter().any(|sample| sample.node_id == previous.node_id) {
+                    changes.push(event(previous, ClusterChange::Left));
+                }
+            }
+        }
+
+        self.refreshed_at = Some(at);
+        self.peers = samples;
+        self.events.extend(changes.iter().cloned());
+        while self.events.len() > CLUSTER_WATCH_EVENTS {
+            self.events.pop_front();
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(node_id: NodeId, name: &str, rtt_ms: Option<u64>) -> PeerSample {
+        PeerSample {
+            node_id,
+            name: name.to_string(),
+            address: Some("127.0.0.1:7000".parse().unwrap()),
+            status: NodeStatus::Online,
+            rtt: rtt_ms.map(Duration::from_millis),
+        }
+    }
+
+    #[test]
+    fn watch_reports_joins_leaves_and_rtt_changes_after_the_baseline() {
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let start = Utc::now();
+        let at = |secs| start + chrono::Duration::seconds(secs);
+        let changes = |events: Vec<ClusterWatchEvent>| events.into_iter().map(|e| (e.node_id, e.change)).collect::<Vec<_>>();
+        let mut watch = ClusterWatch::default();
+
+        assert!(watch.update(vec![sample(a, "alpha", Some(10)), sample(b, "beta", Some(10))], at(0)).is_empty());
+        assert_eq!(watch.refreshed_at(), Some(at(0)));
+        // Jitter below the threshold is not a change
+        assert!(watch.update(vec![sample(b, "beta", Some(10)), sample(a, "alpha", Some(11))], at(1)).is_empty());
+        assert_eq!(watch.peers().iter().map(|p| p.node_id).collect::<Vec<_>>(), vec![a, b]);
+
+        let events = watch.update(vec![sample(a, "alpha", Some(80)), sample(c, "gamma", None)], at(2));
+        assert_eq!(
+            changes(events),
+            vec![
+                (a, ClusterChange::RttChanged { from: Some(Duration::from_millis(11)), to: Some(Duration::from_millis(80)) }),
+                (c, ClusterChange::Joined),
+                (b, ClusterChange::Left),
+            ]
+        );
+        assert_eq!(watch.events().len(), 3);
+        assert!(watch.events().iter().all(|event| event.at == at(2)));
+    }
+
+    /// The reachability label `format_topology` gives the node called `name`
+    async fn reach_of(manager: &ClusterStateManager, name: &str) -> Option<String> {
+        manager.format_topology().await.lines()
+            .find(|line| line.trim_start().starts_with(&format!("{} (", name)))
+            .and_then(|line| line.rsplit_once('[').map(|(_, reach)| reach.trim_end_matches(']').to_string()))
+    }
+
+    #[tokio::test]
+    async fn three_nodes_converge_on_one_topology_through_gossip() {
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let info = |node_id, name: &str| NodeInfo::new(node_id, name.to_string(), "127.0.0.1:7000".parse().unwrap());
+        let (node_a, node_b, node_c) = (ClusterStateManager::new(a), ClusterStateManager::new(b), ClusterStateManager::new(c));
+        for (manager, node_id, name) in [(&node_a, a, "alpha"), (&node_b, b, "beta"), (&node_c, c, "gamma")] {
+            manager.add_local_node(info(node_id, name)).await.unwrap();
+        }
+
+        // A–B and B–C are linked; each node knows its own links and the peers it heard from
+        node_b.update_peer_links(b, vec![a]).await;
+        let stale_b = node_b.get_cluster_state().await;
+        node_a.update_peer_links(a, vec![b]).await;
+        node_b.update_peer_links(b, vec![a, c]).await;
+        node_c.update_peer_links(c, vec![b]).await;
+        for (manager, peer, name) in [(&node_a, b, "beta"), (&node_b, a, "alpha"), (&node_b, c, "gamma"), (&node_c, b, "beta")] {
+            manager.add_node(info(peer, name)).await.unwrap();
+        }
+        assert_eq!(node_a.reachable_nodes().await, HashSet::from([b]));
+        assert_eq!(reach_of(&node_a, "gamma").await, None);
+
+        // Merging B's state tells A about C, which it reaches only through B
+        node_a.synchronize_state(node_b.get_cluster_state().await).await.unwrap();
+        assert_eq!(node_a.reachable_nodes().await, HashSet::from([b, c]));
+        assert_eq!(reach_of(&node_a, "alpha").await.as_deref(), Some("local"));
+        assert_eq!(reach_of(&node_a, "beta").await.as_deref(), Some("direct"));
+        assert_eq!(reach_of(&node_a, "gamma").await.as_deref(), Some("indirect"));
+
+        // An older report of B's links does not replace the newer one
+        node_a.synchronize_state(stale_b).await.unwrap();
+        assert_eq!(node_a.reachable_nodes().await, HashSet::from([b, c]));
+
+        // A node nobody links to is unreachable
+        node_a.add_node(info(Uuid::new_v4(), "delta")).await.unwrap();
+        assert_eq!(reach_of(&node_a, "delta").await.as_deref(), Some("unreachable"));
+
+        // Gossip through B brings all three to the same view of the links
+        node_b.synchronize_state(node_c.get_cluster_state().await).await.unwrap();
+        node_b.synchronize_state(node_a.get_cluster_state().await).await.unwrap();
+        node_c.synchronize_state(node_b.get_cluster_state().await).await.unwrap();
+        node_a.synchronize_state(node_b.get_cluster_state().await).await.unwrap();
+        let links = node_a.get_cluster_state().await.peer_links;
+        assert_eq!(links.len(), 3);
+        assert_eq!(node_b.get_cluster_state().await.peer_links, links);
+        assert_eq!(node_c.get_cluster_state().await.peer_links, links);
+        assert_eq!(reach_of(&node_c, "alpha").await.as_deref(), Some("indirect"));
+    }
+}
@@ -1,4 +1,62 @@
 use colored::*;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// When terminal output is colored (--color)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color when stdout is a terminal and NO_COLOR is unset or empty
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorChoice::Auto => write!(f, "auto"),
+            ColorChoice::Always => write!(f, "always"),
+            ColorChoice::Never => write!(f, "never"),
+        }
+    }
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("Unknown color mode '{}' (expected auto, always or never)", s)),
+        }
+    }
+}
+
+static COLOR_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Turn colored output on or off for the whole process. Every `ColorScheme` method (and
+/// anything else styled through `colored`) emits plain text while it is off.
+pub fn set_color_choice(choice: ColorChoice) {
+    let enabled = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            // https://no-color.org: any non-empty value disables color
+            let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+            !no_color && std::io::stdout().is_terminal()
+        }
+    };
+    COLOR_ENABLED.store(enabled, Ordering::Relaxed);
+    colored::control::set_override(enabled);
+}
+
+/// Whether terminal output is currently colored
+pub fn color_enabled() -> bool {
+    COLOR_ENABLED.load(Ordering::Relaxed)
+}
 
 /// Color scheme for NHI terminal output
 pub struct ColorScheme;
@@ -128,16 +186,16 @@ impl ColorScheme {
 
     /// Format output with prefix colors
     pub fn format_output_line(line: &str) -> String {
-        if line.starts_with("[STDOUT]") {
-            format!("{} {}", "[STDOUT]".green(), &line[8..])
-        } else if line.starts_with("[STDERR]") {
-            format!("{} {}", "[STDERR]".red(), &line[8..])
-        } else if line.starts_with("[INFO]") {
-            format!("{} {}", "[INFO]".blue(), &line[6..])
-        } else if line.starts_with("[ERROR]") {
-            format!("{} {}", "[ERROR]".red().bold(), &line[7..])
-        } else if line.starts_with("[WARN]") {
-            format!("{} {}", "[WARN]".yellow(), &line[6..])
+        if let Some(rest) = line.strip_prefix("[STDOUT]") {
+            format!("{} {}", "[STDOUT]".green(), rest)
+        } else if let Some(rest) = line.strip_prefix("[STDERR]") {
+            format!("{} {}", "[STDERR]".red(), rest)
+        } else if let Some(rest) = line.strip_prefix("[INFO]") {
+            format!("{} {}", "[INFO]".blue(), rest)
+        } else if let Some(rest) = line.strip_prefix("[ERROR]") {
+            format!("{} {}", "[ERROR]".red().bold(), rest)
+        } else if let Some(rest) = line.strip_prefix("[WARN]") {
+            format!("{} {}", "[WARN]".yellow(), rest)
         } else {
             line.to_string()
         }
@@ -204,3 +262,35 @@ macro_rules! print_progress {
         println!("{}", $crate::colors::ColorScheme::progress(&format!($($arg)*)));
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> String {
+        [
+            ColorScheme::success("done"),
+            ColorScheme::instance_id("1a2b3c4d"),
+            ColorScheme::format_status("Running"),
+            ColorScheme::format_output_line("[STDERR] oops"),
+            ColorScheme::error_indicator("Error:"),
+        ]
+        .join(" ")
+    }
+
+    #[test]
+    fn color_choice_switches_escape_sequences_off_and_on() {
+        set_color_choice(ColorChoice::Never);
+        assert!(!color_enabled());
+        let plain = sample();
+        assert!(!plain.contains('\x1b'), "{:?}", plain);
+        assert!(plain.contains("done 1a2b3c4d"));
+
+        set_color_choice(ColorChoice::Always);
+        assert!(color_enabled());
+        assert!(sample().contains("\x1b["), "{:?}", sample());
+
+        assert_eq!("never".parse::<ColorChoice>(), Ok(ColorChoice::Never));
+        assert!("sometimes".parse::<ColorChoice>().is_err());
+    }
+}
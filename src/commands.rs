@@ -0,0 +1,1811 @@
+//! Interactive command dispatch shared by the REPL and the HTTP API
+
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+use nhi::{criu_manager, instance, message_protocol, process_manager, types};
+use nhi::{migration_manager, node_manager, shadow_instance_manager};
+
+use crate::cli::{CliCommand, CliState};
+use instance::InstanceManager;
+use process_manager::ProcessManager;
+use criu_manager::CriuManager;
+
+use uuid::Uuid;
+use nhi::colors::ColorScheme;
+use node_manager::NodeManager;
+use shadow_instance_manager::ShadowInstanceManager;
+use migration_manager::MigrationManager;
+
+use crate::attach::{enter_attach_mode, enter_shadow_attach_mode, wait_in_foreground};
+use nhi::output::Output;
+
+/// How often `watch migrations` refreshes transfer progress between status updates
+const MIGRATION_WATCH_REFRESH: std::time::Duration = std::time::Duration::from_millis(500);
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn execute_command(
+    input: &str,
+    cli_state: &Arc<Mutex<CliState>>,
+    instance_manager: &Arc<Mutex<InstanceManager>>,
+    process_manager: &Arc<ProcessManager>,
+    criu_manager: &Arc<CriuManager>,
+    node_manager: &Option<Arc<NodeManager>>,
+    shadow_manager: &Option<Arc<tokio::sync::RwLock<ShadowInstanceManager>>>,
+    migration_manager: &Option<Arc<MigrationManager>>,
+) -> Result<bool> {
+    let command = CliCommand::parse_from_str(input)?;
+
+    match command {
+        CliCommand::Help => {
+            print_help();
+            Ok(false)
+        }
+        CliCommand::Exit => {
+            // Gracefully shutdown networking if enabled
+            if let Some(ref node_mgr) = node_manager {
+                if let Err(e) = node_mgr.stop().await {
+                    warn!("Error during node manager shutdown: {}", e);
+                }
+            }
+            Output::success("Goodbye!");
+            Ok(true)
+        }
+        CliCommand::Start { program, args, options } => {
+            let (instance_id, instance) = {
+                let mut manager = instance_manager.lock().await;
+                let instance_id = manager.start_instance_with_options(
+                    program,
+                    args,
+                    types::StartMode::Normal,
+                    &options,
+                    process_manager.clone(),
+                ).await?;
+
+                // Get the instance for shadow creation
+                let instance = manager.get_instance_by_id(&instance_id)
+                    .ok_or_else(|| anyhow::anyhow!("Failed to get started instance"))?
+                    .clone();
+
+                (instance_id, instance)
+            };
+
+            Output::instance(&format!("Started instance: {}", instance_id));
+            if options.start_paused {
+                Output::info(&format!("Instance {} is paused before its first instruction; use 'resume {}' to run it", instance_id, instance_id));
+            }
+
+            // Broadcast instance creation to other nodes if networking is enabled
+            if let Some(ref shadow_mgr) = shadow_manager {
+                let shadow_mgr_read = shadow_mgr.read().await;
+                if let Err(e) = shadow_mgr_read.broadcast_instance_creation(&instance).await {
+                    warn!("Failed to broadcast instance creation: {}", e);
+                    Output::warning("Failed to notify other nodes about instance creation");
+                } else {
+                    Output::network("Instance creation broadcasted to cluster");
+                }
+            }
+
+            if options.foreground {
+                let interactive = cli_state.lock().await.interactive;
+                let exit_status = if interactive {
+                    match enter_attach_mode(
+                        &instance_id,
+                        instance.id,
+                        cli_state,
+                        instance_manager,
+                        process_manager,
+                        true,
+                    ).await {
+                        Ok(status) => status,
+                        Err(e) => {
+                            error!("Failed to run instance in foreground: {}", e);
+                            println!("Error running instance in foreground: {}", e);
+                            return Ok(false);
+                        }
+                    }
+                } else {
+                    wait_in_foreground(instance.id, process_manager).await
+                };
+
+                if let Some(status) = exit_status {
+                    process_manager.remove_process(&instance.id).await;
+                    {
+                        let mut manager = instance_manager.lock().await;
+                        manager.mark_exited(&instance_id)?;
+                    }
+
+                    if let Some(ref shadow_mgr) = shadow_manager {
+                        let shadow_mgr_read = shadow_mgr.read().await;
+                        if let Err(e) = shadow_mgr_read.broadcast_instance_stop(instance.id).await {
+                            warn!("Failed to broadcast instance stop: {}", e);
+                        }
+                    }
+
+                    // A program killed by a signal exits like a shell reports it: 128 plus the signal
+                    let code = status.code()
+                        .unwrap_or_else(|| 128 + std::os::unix::process::ExitStatusExt::signal(&status).unwrap_or(0));
+                    if code != 0 {
+                        return Err(types::CriuCliError::ProgramExited(instance_id, code).into());
+                    }
+                    Output::success(&format!("Instance {} exited with code 0", instance_id));
+                }
+            }
+
+            Ok(false)
+        }
+        CliCommand::StartDetached { program, args, options } => {
+            let (instance_id, instance) = {
+                let mut manager = instance_manager.lock().await;
+                let instance_id = manager.start_instance_with_options(
+                    program,
+                    args,
+                    types::StartMode::Detached,
+                    &options,
+                    process_manager.clone(),
+                ).await?;
+
+                // Get the instance for shadow creation
+                let instance = manager.get_instance_by_id(&instance_id)
+                    .ok_or_else(|| anyhow::anyhow!("Failed to get started instance"))?
+                    .clone();
+
+                (instance_id, instance)
+            };
+
+            println!("{} {} {}",
+                ColorScheme::success_indicator("Started detached instance:"),
+                ColorScheme::instance_id(&instance_id),
+                ColorScheme::info("(optimized for CRIU)")
+            );
+            println!("{} {}",
+                ColorScheme::info_indicator("Note:"),
+                ColorScheme::info("Detached instances have limited input capabilities but are CRIU-friendly")
+            );
+            if options.start_paused {
+                Output::info(&format!("Instance {} is paused before its first instruction; use 'resume {}' to run it", instance_id, instance_id));
+            }
+
+            // Broadcast instance creation to other nodes if networking is enabled
+            if let Some(ref shadow_mgr) = shadow_manager {
+                let shadow_mgr_read = shadow_mgr.read().await;
+                if let Err(e) = shadow_mgr_read.broadcast_instance_creation(&instance).await {
+                    warn!("Failed to broadcast instance creation: {}", e);
+                    println!("{} {}",
+                        ColorScheme::warning_indicator("Warning:"),
+                        ColorScheme::warning("Failed to notify other nodes about instance creation")
+                    );
+                } else {
+                    println!("{} {}",
+                        ColorScheme::info_indicator("Broadcast:"),
+                        ColorScheme::info("Instance creation broadcasted to cluster")
+                    );
+                }
+            }
+
+            Ok(false)
+        }
+        CliCommand::Stop { instance_id, if_running } => {
+            // Get the instance UUID before stopping
+            let instance_uuid = {
+                let manager = instance_manager.lock().await;
+                manager.resolve_instance_id(&instance_id)?
+            };
+
+            // Stop the instance
+            {
+                let mut manager = instance_manager.lock().await;
+                if if_running {
+                    if !manager.stop_instance_if_running(&instance_id, process_manager.clone()).await? {
+                        println!("{} {}",
+                            ColorScheme::info_indicator("Instance is not running:"),
+                            ColorScheme::instance_id(&instance_id)
+                        );
+                        return Ok(false);
+                    }
+                } else {
+                    manager.stop_instance(&instance_id, process_manager.clone()).await?;
+                }
+            }
+
+            // Broadcast instance stop to shadow instances if shadow manager is available
+            if let Some(ref shadow_mgr) = shadow_manager {
+                let shadow_mgr_read = shadow_mgr.read().await;
+                if let Err(e) = shadow_mgr_read.broadcast_instance_stop(instance_uuid).await {
+                    warn!("Failed to broadcast instance stop: {}", e);
+                } else {
+                    info!("Broadcasted instance stop for {}", instance_uuid);
+                }
+            }
+
+            println!("{} {}",
+                ColorScheme::success_indicator("Stopped instance:"),
+                ColorScheme::instance_id(&instance_id)
+            );
+            Ok(false)
+        }
+        CliCommand::StopLabeled { labels } => {
+            let selected = instance_manager.lock().await.select_by_labels(
+                &labels,
+                &[crate::types::InstanceStatus::Running, crate::types::InstanceStatus::Paused],
+            );
+            if selected.is_empty() {
+                println!("{}", ColorScheme::info("No running instances match the labels."));
+                return Ok(false);
+            }
+
+            let mut stopped = 0;
+            for instance_uuid in &selected {
+                let short_id = instance_uuid.to_string()[..8].to_string();
+                let result = {
+                    let mut manager = instance_manager.lock().await;
+                    manager.stop_instance(&instance_uuid.to_string(), process_manager.clone()).await
+                };
+                match result {
+                    Ok(()) => {
+                        stopped += 1;
+                        if let Some(ref shadow_mgr) = shadow_manager {
+                            if let Err(e) = shadow_mgr.read().await.broadcast_instance_stop(*instance_uuid).await {
+                                warn!("Failed to broadcast instance stop: {}", e);
+                            }
+                        }
+                        println!("{} {}", ColorScheme::success_indicator("Stopped instance:"), ColorScheme::instance_id(&short_id));
+                    }
+                    Err(e) => {
+                        println!("{} {}: {}", ColorScheme::error_indicator("Failed to stop"), ColorScheme::instance_id(&short_id), e);
+                    }
+                }
+            }
+            println!("Stopped {} of {} matching instance(s)", stopped, selected.len());
+            Ok(false)
+        }
+        CliCommand::Purge { status, older_than, dry_run } => {
+            let mut manager = instance_manager.lock().await;
+            let candidates = manager.purge_candidates(status.as_ref(), older_than);
+            if candidates.is_empty() {
+                println!("{} {}", ColorScheme::info_indicator("Info:"), ColorScheme::info("Nothing to purge"));
+                return Ok(false);
+            }
+
+            let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+            let describe = |candidate: &instance::PurgeCandidate| {
+                let status = candidate.status.as_ref().map_or("unknown".to_string(), |status| status.to_string());
+                let origin = if candidate.managed.is_some() { "" } else { ", left from an earlier session" };
+                format!("{} ({}{}, {:.1} MiB)", ColorScheme::instance_id(&candidate.short_id), status, origin, mib(candidate.bytes))
+            };
+
+            if dry_run {
+                let total: u64 = candidates.iter().map(|candidate| candidate.bytes).sum();
+                for candidate in &candidates {
+                    println!("  would purge {}", describe(candidate));
+                }
+                println!("{} {}",
+                    ColorScheme::info_indicator("Dry run:"),
+                    ColorScheme::info(&format!("{} instances, {:.1} MiB would be reclaimed", candidates.len(), mib(total)))
+                );
+                return Ok(false);
+            }
+
+            let mut purged = 0;
+            let mut reclaimed = 0;
+            for (candidate, result) in manager.purge(candidates) {
+                match result {
+                    Ok(()) => {
+                        println!("  purged {}", describe(&candidate));
+                        purged += 1;
+                        reclaimed += candidate.bytes;
+                    }
+                    Err(e) => println!("  {} {}: {}", ColorScheme::error_indicator("skipped"), describe(&candidate), e),
+                }
+            }
+            println!("{} {}",
+                ColorScheme::success_indicator("Purged:"),
+                ColorScheme::success(&format!("{} instances, {:.1} MiB reclaimed", purged, mib(reclaimed)))
+            );
+            Ok(false)
+        }
+        CliCommand::Info { instance_id } => {
+            instance_manager.lock().await.print_instance_info(&instance_id)?;
+            Ok(false)
+        }
+        CliCommand::Pause { instance_id } => {
+            let mut manager = instance_manager.lock().await;
+            manager.pause_instance(&instance_id, process_manager.clone()).await?;
+            println!("Paused instance: {}", instance_id);
+            Ok(false)
+        }
+        CliCommand::Resume { instance_id } => {
+            let mut manager = instance_manager.lock().await;
+            manager.resume_instance(&instance_id, process_manager.clone()).await?;
+            println!("Resumed instance: {}", instance_id);
+            Ok(false)
+        }
+        CliCommand::Retry { instance_id } => {
+            let mut manager = instance_manager.lock().await;
+            manager.retry_instance(&instance_id, criu_manager.clone(), process_manager.clone()).await?;
+            println!("{} {}",
+                ColorScheme::success_indicator("Retried failed operation for instance"),
+                ColorScheme::instance_id(&instance_id)
+            );
+            Ok(false)
+        }
+        CliCommand::Edit { instance_id, edit } => {
+            let mut manager = instance_manager.lock().await;
+            manager.edit_instance(&instance_id, &edit)?;
+            println!("{} {} {}",
+                ColorScheme::success_indicator("Updated instance"),
+                ColorScheme::instance_id(&instance_id),
+                ColorScheme::info("(takes effect on the next restart)")
+            );
+            Ok(false)
+        }
+        CliCommand::Restart { instance_id } => {
+            let mut manager = instance_manager.lock().await;
+            let pid = manager.restart_instance(&instance_id, process_manager.clone()).await?;
+            println!("{} {} {}",
+                ColorScheme::success_indicator("Restarted instance"),
+                ColorScheme::instance_id(&instance_id),
+                ColorScheme::info(&format!("(PID {})", pid))
+            );
+            Ok(false)
+        }
+        CliCommand::Signal { instance_id, signal } => {
+            let manager = instance_manager.lock().await;
+            let pid = manager.signal_instance(&instance_id, signal, process_manager.clone()).await?;
+            println!("{} {} {}",
+                ColorScheme::success_indicator(&format!("Sent {} to instance", signal)),
+                ColorScheme::instance_id(&instance_id),
+                ColorScheme::info(&format!("(PID {})", pid))
+            );
+            Ok(false)
+        }
+        CliCommand::SyncEnable { instance_id, enabled } => {
+            let mut manager = instance_manager.lock().await;
+            manager.set_sync_enabled(&instance_id, enabled)?;
+            println!("{} {} {}",
+                ColorScheme::success_indicator(if enabled { "Enabled auto-sync for" } else { "Disabled auto-sync for" }),
+                ColorScheme::instance_id(&instance_id),
+                ColorScheme::info("instance")
+            );
+            Ok(false)
+        }
+        CliCommand::List { filter, sort, json } => {
+            let manager = instance_manager.lock().await;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&manager.list_instances_json(&filter, sort))?);
+            } else {
+                manager.list_instances(&filter, sort);
+            }
+            Ok(false)
+        }
+        CliCommand::Attach { instance_id } => {
+            let manager = instance_manager.lock().await;
+            let uuid = manager.resolve_instance_id_with_status(
+                &instance_id,
+                &[crate::types::InstanceStatus::Running, crate::types::InstanceStatus::Paused, crate::types::InstanceStatus::Shadow],
+            )?;
+
+            if let Some(instance) = manager.get_instance_by_id(&uuid.to_string()) {
+                if instance.status == crate::types::InstanceStatus::Shadow {
+                    // Handle shadow instance attach
+                    drop(manager); // Release the lock before entering attach mode
+                    match enter_shadow_attach_mode(
+                        &instance_id,
+                        uuid,
+                        cli_state,
+                        instance_manager,
+                        shadow_manager,
+                    ).await {
+                        Ok(_) => {},
+                        Err(e) => {
+                            error!("Failed to enter shadow attach mode: {}", e);
+                            println!("Error entering shadow attach mode: {}", e);
+                        }
+                    }
+                } else {
+                    // Handle regular instance attach
+                    // Check if the process is actually running
+                    if let Some(pid) = process_manager.get_process_pid(&uuid).await {
+                        let proc_path = format!("/proc/{}", pid);
+                        if !std::path::Path::new(&proc_path).exists() {
+                            println!("Error: Instance {} (PID {}) is no longer running", instance_id, pid);
+                            println!("Use 'list' to see current instance status");
+                            return Ok(false);
+                        }
+                    } else {
+                        println!("Error: Instance {} has no associated process", instance_id);
+                        return Ok(false);
+                    }
+
+                    drop(manager); // Release the lock before entering attach mode
+                    // Enter attach UI mode
+                    match enter_attach_mode(
+                        &instance_id,
+                        uuid,
+                        cli_state,
+                        instance_manager,
+                        process_manager,
+                        false,
+                    ).await {
+                        Ok(_) => {},
+                        Err(e) => {
+                            error!("Failed to enter attach mode: {}", e);
+                            println!("Error entering attach mode: {}", e);
+                        }
+                    }
+                }
+            } else {
+                println!("Instance not found: {}", instance_id);
+            }
+            Ok(false)
+        }
+        CliCommand::Detach => {
+            let mut state = cli_state.lock().await;
+            if let Some(instance_id) = &state.attached_instance {
+                println!("Detached from instance: {}", instance_id);
+
+                // Stop the output monitoring task
+                if let Some(task) = state.output_task.take() {
+                    task.abort();
+                }
+
+                state.attached_instance = None;
+            } else {
+                println!("Not attached to any instance");
+            }
+            Ok(false)
+        }
+        CliCommand::Logs { instance_id, lines, stream, all, window } => {
+            let target_instance = if let Some(id) = instance_id {
+                id
+            } else {
+                let state = cli_state.lock().await;
+                if let Some(attached_id) = &state.attached_instance {
+                    attached_id.clone()
+                } else {
+                    println!("No instance specified and not attached to any instance");
+                    return Ok(false);
+                }
+            };
+
+            let manager = instance_manager.lock().await;
+            if let Ok(uuid) = manager.resolve_instance_id(&target_instance) {
+                let started_at = manager
+                    .get_instance_by_id(&uuid.to_string())
+                    .map(|instance| instance.created_at)
+                    .unwrap_or_else(chrono::Utc::now);
+                if all {
+                    // Output from before `restore --replace` only survives in the rotated log files
+                    process_manager.show_process_output(&uuid, None, None, true, &window, started_at).await?;
+                } else if let Some(mut history) = process_manager.get_output_history(&uuid).await {
+                    if let Some(stream) = stream {
+                        history.retain(|line| line.starts_with(stream.prefix()));
+                    }
+                    if !window.is_empty() {
+                        // Only timestamped lines (start --timestamp) can be placed in the window
+                        let now = chrono::Utc::now();
+                        history.retain(|line| window.matches_line(line, started_at, now));
+                    }
+                    let lines_to_show = lines.unwrap_or(if window.is_empty() { 20 } else { usize::MAX });
+                    let start_idx = if history.len() > lines_to_show {
+                        history.len() - lines_to_show
+                    } else {
+                        0
+                    };
+
+                    println!("=== Last {} lines of output for instance {} ===",
+                             std::cmp::min(lines_to_show, history.len()), target_instance);
+                    for line in &history[start_idx..] {
+                        println!("{}", line);
+                    }
+                    println!("=== End of logs ===");
+                } else {
+                    // Not tracked in this session, fall back to the instance's log files
+                    process_manager.show_process_output(&uuid, lines, stream, false, &window, started_at).await?;
+                }
+            } else {
+                println!("Instance not found: {}", target_instance);
+            }
+            Ok(false)
+        }
+        CliCommand::Grep { instance_id, pattern, ignore_case, context, files } => {
+            let regex = crate::output_search::build_pattern(&pattern, ignore_case)?;
+            let uuid = instance_manager.lock().await.resolve_instance_id(&instance_id)?;
+
+            // Instances not tracked in this session only have their log files
+            let history = if files { None } else { process_manager.get_output_history(&uuid).await };
+            let (lines, source) = match history {
+                Some(history) => (history, "output history"),
+                None => (ProcessManager::stored_output_lines(&uuid)?, "output logs"),
+            };
+
+            let groups = crate::output_search::search_lines(&lines, &regex, context);
+            for (i, group) in groups.iter().enumerate() {
+                if i > 0 && context > 0 {
+                    println!("{}", ColorScheme::separator(2));
+                }
+                for line in &group.lines {
+                    if line.is_match {
+                        println!("{}:{}", ColorScheme::timestamp(&line.number.to_string()), ColorScheme::format_output_line(&line.text));
+                    } else {
+                        println!("{}-{}", ColorScheme::timestamp(&line.number.to_string()), line.text);
+                    }
+                }
+            }
+            println!("{}", ColorScheme::info(&format!(
+                "{} matching line(s) in {} lines of {} for instance {}",
+                crate::output_search::match_count(&groups), lines.len(), source, instance_id
+            )));
+            Ok(false)
+        }
+        CliCommand::Checkpoint { instance_id, name, set_base, stop } => {
+            let instance_uuid = {
+                let mut manager = instance_manager.lock().await;
+                manager.checkpoint_instance(
+                    &instance_id,
+                    &name,
+                    set_base,
+                    stop,
+                    criu_manager.clone(),
+                    process_manager.clone(),
+                ).await?;
+                manager.resolve_instance_id(&instance_id)?
+            };
+            // The dump killed the process; shadows must drop it just as after `stop`
+            if stop {
+                if let Some(ref shadow_mgr) = shadow_manager {
+                    if let Err(e) = shadow_mgr.read().await.broadcast_instance_stop(instance_uuid).await {
+                        warn!("Failed to broadcast instance stop: {}", e);
+                    }
+                }
+            }
+            println!("{} {} {} {}",
+                ColorScheme::success_indicator("Created checkpoint"),
+                ColorScheme::checkpoint(&name),
+                ColorScheme::info("for instance:"),
+                ColorScheme::instance_id(&instance_id)
+            );
+            if stop {
+                Output::info(&format!("Instance {} was stopped; use 'restore {} {}' to bring it back", instance_id, instance_id, name));
+            }
+            Ok(false)
+        }
+        CliCommand::CheckpointLabeled { labels, name, set_base, stop } => {
+            let selected = instance_manager.lock().await.select_by_labels(&labels, &[crate::types::InstanceStatus::Running]);
+            if selected.is_empty() {
+                println!("{}", ColorScheme::info("No running instances match the labels."));
+                return Ok(false);
+            }
+
+            let mut created = 0;
+            for instance_uuid in &selected {
+                let short_id = instance_uuid.to_string()[..8].to_string();
+                let result = {
+                    let mut manager = instance_manager.lock().await;
+                    manager.checkpoint_instance(
+                        &instance_uuid.to_string(),
+                        &name,
+                        set_base,
+                        stop,
+                        criu_manager.clone(),
+                        process_manager.clone(),
+                    ).await
+                };
+                match result {
+                    Ok(()) => {
+                        created += 1;
+                        if stop {
+                            if let Some(ref shadow_mgr) = shadow_manager {
+                                if let Err(e) = shadow_mgr.read().await.broadcast_instance_stop(*instance_uuid).await {
+                                    warn!("Failed to broadcast instance stop: {}", e);
+                                }
+                            }
+                        }
+                        println!("{} {} {} {}",
+                            ColorScheme::success_indicator("Created checkpoint"),
+                            ColorScheme::checkpoint(&name),
+                            ColorScheme::info("for instance:"),
+                            ColorScheme::instance_id(&short_id)
+                        );
+                    }
+                    Err(e) => {
+                        println!("{} {}: {}", ColorScheme::error_indicator("Failed to checkpoint"), ColorScheme::instance_id(&short_id), e);
+                    }
+                }
+            }
+            println!("Checkpointed {} of {} matching instance(s)", created, selected.len());
+            Ok(false)
+        }
+        CliCommand::Restore { instance_id, checkpoint_name, options } => {
+            let mut manager = instance_manager.lock().await;
+            manager.restore_instance_to_existing(
+                &instance_id,
+                &checkpoint_name,
+                &options,
+                criu_manager.clone(),
+                process_manager.clone(),
+            ).await?;
+            println!("{} {} {} {}",
+                ColorScheme::success_indicator("Restored instance"),
+                ColorScheme::instance_id(&instance_id),
+                ColorScheme::info("from checkpoint:"),
+                ColorScheme::checkpoint(&checkpoint_name)
+            );
+            Ok(false)
+        }
+        CliCommand::CheckpointDiff { instance_id, a, b } => {
+            use nhi::FileChange;
+
+            let full_id = instance_manager.lock().await.resolve_instance_id(&instance_id)?;
+            let dir_a = criu_manager.instance_checkpoint_dir(&full_id, &a)?;
+            let dir_b = criu_manager.instance_checkpoint_dir(&full_id, &b)?;
+            let diffs = tokio::task::spawn_blocking(move || nhi::diff_checkpoints(&dir_a, &dir_b))
+                .await
+                .map_err(|e| anyhow::anyhow!("checkpoint diff task failed: {}", e))??;
+
+            println!("{} {} {} {} {}",
+                ColorScheme::header("Checkpoint diff for"),
+                ColorScheme::instance_id(&instance_id),
+                ColorScheme::checkpoint(&a),
+                ColorScheme::info("vs"),
+                ColorScheme::checkpoint(&b)
+            );
+            let (mut identical, mut changed, mut only_a, mut only_b) = (0, 0, 0, 0);
+            for diff in &diffs {
+                match diff.change {
+                    FileChange::Identical { size } => {
+                        identical += 1;
+                        println!("  = {} ({} bytes) {}", diff.name, size, ColorScheme::success("identical"));
+                    }
+                    FileChange::Changed { size_a, size_b } => {
+                        changed += 1;
+                        println!("  ~ {} ({} -> {} bytes, {:+}) {}", diff.name, size_a, size_b, size_b as i64 - size_a as i64, ColorScheme::warning("changed"));
+                    }
+                    FileChange::OnlyInA { size } => {
+                        only_a += 1;
+                        println!("  - {} ({} bytes) {}", diff.name, size, ColorScheme::error(&format!("only in {}", a)));
+                    }
+                    FileChange::OnlyInB { size } => {
+                        only_b += 1;
+                        println!("  + {} ({} bytes) {}", diff.name, size, ColorScheme::error(&format!("only in {}", b)));
+                    }
+                }
+            }
+            println!("{} identical, {} changed, {} only in {}, {} only in {}", identical, changed, only_a, a, only_b, b);
+            if diffs.iter().any(|diff| diff.name == nhi::SEALED_ARCHIVE) {
+                println!("{}", ColorScheme::info("Encrypted checkpoints are compared as a whole archive, not per image"));
+            }
+            Ok(false)
+        }
+        CliCommand::Cd { directory } => {
+            std::env::set_current_dir(&directory)?;
+            println!("Changed directory to: {}", directory);
+            Ok(false)
+        }
+        CliCommand::AnalyzeTty { instance_id } => {
+            let manager = instance_manager.lock().await;
+            if let Ok(uuid) = manager.resolve_instance_id(&instance_id) {
+                if let Some(pid) = process_manager.get_process_pid(&uuid).await {
+                    use nhi::check_process_tty_compatibility;
+
+                    println!("Analyzing TTY environment for instance {} (PID: {})...", instance_id, pid);
+
+                    match check_process_tty_compatibility(pid) {
+                        Ok(is_compatible) => {
+                            if is_compatible {
+                                println!("✅ Process has good CRIU compatibility");
+                            } else {
+                                println!("⚠️  Process may have CRIU compatibility issues");
+                                println!("💡 Consider using 'start-detached' for better CRIU compatibility");
+                            }
+                        }
+                        Err(e) => {
+                            println!("❌ Failed to analyze TTY environment: {}", e);
+                        }
+                    }
+
+                    match nhi::detect_tcp_sockets(pid) {
+                        Ok(sockets) if sockets.is_empty() => {
+                            println!("✅ No TCP sockets held");
+                        }
+                        Ok(sockets) => {
+                            println!("⚠️  Process holds {} TCP socket(s):", sockets.len());
+                            for socket in &sockets {
+                                println!("  fd {}: {} -> {} ({})", socket.fd, socket.local_address, socket.remote_address, socket.state);
+                            }
+                            println!("💡 Checkpoints of this process will use --tcp-established");
+                        }
+                        Err(e) => {
+                            println!("❌ Failed to analyze sockets: {}", e);
+                        }
+                    }
+                } else {
+                    println!("Instance {} is not running", instance_id);
+                }
+            } else {
+                println!("Instance not found: {}", instance_id);
+            }
+            Ok(false)
+        }
+        // Cluster management commands (Stage 2)
+        CliCommand::ClusterListNodes => {
+            if let Some(ref node_mgr) = node_manager {
+                let node_list = node_mgr.get_node_list().await;
+                println!("{}", node_list);
+            } else {
+                println!("{} {}",
+                    ColorScheme::warning_indicator("Warning:"),
+                    ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
+                );
+            }
+            Ok(false)
+        }
+        CliCommand::ClusterNodeInfo { node_id } => {
+            if let Some(ref node_mgr) = node_manager {
+                if let Some(node_id_str) = node_id {
+                    // Parse node ID and show specific node info
+                    match uuid::Uuid::parse_str(&node_id_str) {
+                        Ok(uuid) => {
+                            if let Some(node_info) = node_mgr.cluster_state().get_node_info(&uuid).await {
+                                println!("Node Information:");
+                                println!("  ID: {}", node_info.node_id);
+                                println!("  Name: {}", node_info.name);
+                                println!("  Address: {}", node_info.listen_addr);
+                                println!("  Status: {:?}", node_info.status);
+                                println!("  Version: {}", node_info.version);
+                                println!("  Joined: {}", node_info.joined_at.format("%Y-%m-%d %H:%M:%S UTC"));
+                                println!("  Last Seen: {}", node_info.last_seen.format("%Y-%m-%d %H:%M:%S UTC"));
+                                println!("  Capabilities: {}", node_info.capabilities.join(", "));
+                                if let Some(bytes) = node_info.disk_available {
+                                    println!("  Disk Available: {:.1} MiB", bytes as f64 / (1024.0 * 1024.0));
+                                }
+                            } else {
+                                println!("Node not found: {}", node_id_str);
+                            }
+                        }
+                        Err(_) => {
+                            println!("Invalid node ID format: {}", node_id_str);
+                        }
+                    }
+                } else {
+                    // Show local node info
+                    let local_info = node_mgr.local_node_info();
+                    println!("Local Node Information:");
+                    println!("  ID: {}", local_info.node_id);
+                    println!("  Name: {}", local_info.name);
+                    println!("  Address: {}", local_info.listen_addr);
+                    println!("  Status: {:?}", local_info.status);
+                    println!("  Version: {}", local_info.version);
+                    println!("  Capabilities: {}", local_info.capabilities.join(", "));
+                    if let Some(bytes) = nhi::disk_available(std::path::Path::new(instance::INSTANCES_DIR)) {
+                        println!("  Disk Available: {:.1} MiB", bytes as f64 / (1024.0 * 1024.0));
+                    }
+                }
+            } else {
+                println!("{} {}",
+                    ColorScheme::warning_indicator("Warning:"),
+                    ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
+                );
+            }
+            Ok(false)
+        }
+        CliCommand::ClusterConnect { address, persist, wait_ready } => {
+            if let Some(ref node_mgr) = node_manager {
+                println!("Connecting to {}...", address);
+                match node_mgr.connect_to_address(&address).await {
+                    Ok((addr, peer_node_id)) => {
+                        println!("{} {}",
+                            ColorScheme::success_indicator("Success:"),
+                            ColorScheme::success(&format!("Connected to {} ({}) as node {}", address, addr, peer_node_id))
+                        );
+                        if persist {
+                            match node_mgr.persist_peer(&address, addr, peer_node_id).await {
+                                Ok(()) => println!("{} {}",
+                                    ColorScheme::info_indicator("Note:"),
+                                    ColorScheme::info(&format!("{} will be reconnected on restart", address))
+                                ),
+                                Err(e) => println!("{} {}",
+                                    ColorScheme::warning_indicator("Warning:"),
+                                    ColorScheme::warning(&format!("Connected, but failed to persist {}: {}", address, e))
+                                ),
+                            }
+                        }
+                        if let Some(timeout_secs) = wait_ready {
+                            match shadow_manager {
+                                Some(shadow_mgr) => {
+                                    let signals = cli_state.lock().await.signals.clone();
+                                    let wait = wait_for_peer_shadows(shadow_mgr, peer_node_id, std::time::Duration::from_secs(timeout_secs));
+                                    if let Err(signal) = signals.interruptible(wait).await {
+                                        Output::warning(&format!("Stopped waiting for shadows on {}", signal.name()));
+                                    }
+                                }
+                                None => println!("{} {}",
+                                    ColorScheme::warning_indicator("Warning:"),
+                                    ColorScheme::warning("Shadow instances are disabled; nothing to wait for")
+                                ),
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        println!("{} {}",
+                            ColorScheme::error_indicator("Error:"),
+                            ColorScheme::error(&format!("Failed to connect to {}: {}", address, e))
+                        );
+                    }
+                }
+            } else {
+                println!("{} {}",
+                    ColorScheme::warning_indicator("Warning:"),
+                    ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
+                );
+            }
+            Ok(false)
+        }
+        CliCommand::ClusterDisconnect { node_id } => {
+            if let Some(ref node_mgr) = node_manager {
+                match uuid::Uuid::parse_str(&node_id) {
+                    Ok(uuid) => {
+                        match node_mgr.disconnect_peer(&uuid).await {
+                            Ok(_) => {
+                                println!("{} {}",
+                                    ColorScheme::success_indicator("Success:"),
+                                    ColorScheme::success(&format!("Disconnected from node {}", node_id))
+                                );
+                            }
+                            Err(e) => {
+                                println!("{} {}",
+                                    ColorScheme::error_indicator("Error:"),
+                                    ColorScheme::error(&format!("Failed to disconnect from {}: {}", node_id, e))
+                                );
+                            }
+                        }
+                    }
+                    Err(_) => {
+                        println!("{} {}",
+                            ColorScheme::error_indicator("Error:"),
+                            ColorScheme::error("Invalid node ID format")
+                        );
+                    }
+                }
+            } else {
+                println!("{} {}",
+                    ColorScheme::warning_indicator("Warning:"),
+                    ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
+                );
+            }
+            Ok(false)
+        }
+        CliCommand::ClusterForget { target } => {
+            if let Some(ref node_mgr) = node_manager {
+                match node_mgr.forget_peer(&target).await {
+                    Ok(removed) => {
+                        for peer in removed {
+                            println!("{} {}",
+                                ColorScheme::success_indicator("Success:"),
+                                ColorScheme::success(&format!("Forgot persisted peer {}", peer.address))
+                            );
+                        }
+                    }
+                    Err(e) => {
+                        println!("{} {}",
+                            ColorScheme::error_indicator("Error:"),
+                            ColorScheme::error(&e.to_string())
+                        );
+                    }
+                }
+            } else {
+                println!("{} {}",
+                    ColorScheme::warning_indicator("Warning:"),
+                    ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
+                );
+            }
+            Ok(false)
+        }
+        CliCommand::ClusterPing { node_id } => {
+            if let Some(ref node_mgr) = node_manager {
+                match uuid::Uuid::parse_str(&node_id) {
+                    Ok(uuid) => {
+                        match node_mgr.ping_node(&uuid, std::time::Duration::from_secs(5)).await {
+                            Ok(rtt) => {
+                                println!("{} {}",
+                                    ColorScheme::success_indicator("Pong:"),
+                                    ColorScheme::success(&format!("reply from {} in {:.2} ms", node_id, rtt.as_secs_f64() * 1000.0))
+                                );
+                            }
+                            Err(e) => {
+                                println!("{} {}",
+                                    ColorScheme::error_indicator("Error:"),
+                                    ColorScheme::error(&format!("Ping to {} failed: {}", node_id, e))
+                                );
+                            }
+                        }
+                    }
+                    Err(_) => {
+                        println!("{} {}",
+                            ColorScheme::error_indicator("Error:"),
+                            ColorScheme::error("Invalid node ID format")
+                        );
+                    }
+                }
+            } else {
+                println!("{} {}",
+                    ColorScheme::warning_indicator("Warning:"),
+                    ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
+                );
+            }
+            Ok(false)
+        }
+        CliCommand::ClusterCapabilities => {
+            if let Some(ref node_mgr) = node_manager {
+                let cluster = node_mgr.cluster_state().get_cluster_state().await;
+                let mut nodes: Vec<_> = cluster.nodes.values().collect();
+                nodes.sort_by(|a, b| a.name.cmp(&b.name));
+
+                println!("Node Capabilities:");
+                for node in nodes {
+                    println!("  {} ({}) [{:?}]",
+                        node.name,
+                        node.node_id.to_string()[..8].to_uppercase(),
+                        node.status
+                    );
+                    println!("    {}", if node.capabilities.is_empty() {
+                        "(none)".to_string()
+                    } else {
+                        node.capabilities.join(", ")
+                    });
+                }
+            } else {
+                println!("{} {}",
+                    ColorScheme::warning_indicator("Warning:"),
+                    ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
+                );
+            }
+            Ok(false)
+        }
+        CliCommand::Stats { json } => {
+            let instances = instance_manager.lock().await.get_all_instances();
+            let (local_node_id, nodes): (_, Vec<_>) = match node_manager {
+                Some(ref node_mgr) => {
+                    let state = node_mgr.cluster_state().get_cluster_state().await;
+                    (Some(node_mgr.node_id()), state.nodes.into_values().collect())
+                }
+                None => (None, Vec::new()),
+            };
+            let active_migrations = match migration_manager {
+                Some(ref migration_mgr) => migration_mgr
+                    .list_active_migrations()
+                    .await
+                    .iter()
+                    .filter(|m| !m.status.is_terminal())
+                    .count(),
+                None => 0,
+            };
+
+            let mut summary = nhi::summarize(&instances, local_node_id, &nodes, active_migrations);
+            summary.checkpoint_bytes = nhi::checkpoint_disk_usage(std::path::Path::new("instances"));
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+                return Ok(false);
+            }
+
+            println!("{}", ColorScheme::table_header("Instances"));
+            println!("  Total: {}  Running: {}  Paused: {}  Shadow: {}  Stopped: {}  Failed: {}  Starting: {}",
+                summary.total_instances, summary.running, summary.paused, summary.shadow,
+                summary.stopped, summary.failed, summary.starting);
+            if !summary.nodes.is_empty() {
+                println!("{}", ColorScheme::table_header("Nodes"));
+                for (node_id, counts) in &summary.nodes {
+                    let local = if Some(*node_id) == local_node_id { " (local)" } else { "" };
+                    println!("  {} {}{}: {} running, {} shadowed here{}",
+                        ColorScheme::info(&node_id.to_string()[..8]),
+                        counts.name,
+                        local,
+                        counts.running,
+                        counts.shadows,
+                        if counts.online { "" } else { " [offline]" }
+                    );
+                }
+            }
+            println!("{}", ColorScheme::table_header("Migrations"));
+            println!("  Active: {}", summary.active_migrations);
+            println!("{}", ColorScheme::table_header("Checkpoints"));
+            println!("  Count: {}  Disk usage: {:.1} MiB",
+                summary.checkpoint_count, summary.checkpoint_bytes as f64 / (1024.0 * 1024.0));
+            Ok(false)
+        }
+        CliCommand::ClusterTopology => {
+            if let Some(ref node_mgr) = node_manager {
+                print!("{}", node_mgr.get_topology().await);
+            } else {
+                println!("{} {}",
+                    ColorScheme::warning_indicator("Warning:"),
+                    ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
+                );
+            }
+            Ok(false)
+        }
+        CliCommand::Locate { instance_id } => {
+            let node_mgr = match node_manager {
+                Some(ref node_mgr) => node_mgr,
+                None => {
+                    println!("{} {}",
+                        ColorScheme::warning_indicator("Warning:"),
+                        ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
+                    );
+                    return Ok(false);
+                }
+            };
+            let local_node_id = node_mgr.cluster_state().local_node_id();
+
+            // What this node knows about its own copy is authoritative; the registry covers the rest
+            let local = {
+                let manager = instance_manager.lock().await;
+                manager.resolve_instance_id(&instance_id).ok()
+                    .and_then(|uuid| manager.get_instance_by_id(&uuid.to_string()))
+                    .map(|instance| (instance.id, instance.status.clone(), instance.source_node_id))
+            };
+            let lookup = match local {
+                Some((uuid, _, _)) => uuid.to_string(),
+                None => instance_id.clone(),
+            };
+            let registry_entry = node_mgr.instance_registry().locate(&lookup).await;
+            let (uuid, mut location) = match (registry_entry, &local) {
+                (Some(entry), _) => entry,
+                (None, Some((uuid, _, _))) => (*uuid, Default::default()),
+                (None, None) => {
+                    Output::warning(&format!("Instance {} is not known to any node this node has heard from", instance_id));
+                    return Ok(false);
+                }
+            };
+            if let Some((_, status, source_node_id)) = local {
+                match status {
+                    types::InstanceStatus::Running | types::InstanceStatus::Paused => {
+                        location.running_node = Some(local_node_id);
+                        location.shadow_nodes.remove(&local_node_id);
+                    }
+                    types::InstanceStatus::Shadow => {
+                        location.shadow_nodes.insert(local_node_id);
+                        if location.running_node == Some(local_node_id) {
+                            location.running_node = None;
+                        }
+                        if location.running_node.is_none() {
+                            location.running_node = source_node_id;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            println!("{} {}", ColorScheme::info("Instance:"), ColorScheme::instance_id(&uuid.to_string()));
+            match location.running_node {
+                Some(node_id) => println!("  {} {}", ColorScheme::info("Running on:"), describe_node(node_mgr, node_id).await),
+                None => println!("  {} {}", ColorScheme::info("Running on:"), ColorScheme::warning("unknown")),
+            }
+            if location.shadow_nodes.is_empty() {
+                println!("  {} none", ColorScheme::info("Shadows:"));
+            } else {
+                println!("  {}", ColorScheme::info("Shadows:"));
+                for node_id in &location.shadow_nodes {
+                    println!("    {}", describe_node(node_mgr, *node_id).await);
+                }
+            }
+            Ok(false)
+        }
+        CliCommand::ClusterStatus { watch: true, interval_secs } => {
+            let Some(ref node_mgr) = node_manager else {
+                println!("{} {}",
+                    ColorScheme::warning_indicator("Warning:"),
+                    ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
+                );
+                return Ok(false);
+            };
+
+            let interval = std::time::Duration::from_secs(interval_secs);
+            // Pings must come back well within one refresh to keep the interval
+            let ping_timeout = (interval / 2).min(std::time::Duration::from_secs(2));
+            let mut watch = nhi::cluster_state::ClusterWatch::default();
+            let mut view = crate::ui::ClusterWatchUI::new()?;
+            view.enter()?;
+
+            let mut refresh = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = refresh.tick() => {
+                        watch.update(node_mgr.sample_peers(ping_timeout).await, chrono::Utc::now());
+                    }
+                    quit = view.next_quit() => {
+                        if quit? {
+                            break;
+                        }
+                    }
+                }
+                view.draw(&watch, interval_secs)?;
+            }
+
+            view.exit()?;
+            Ok(false)
+        }
+        CliCommand::ClusterStatus { watch: false, .. } => {
+            if let Some(ref node_mgr) = node_manager {
+                let cluster_info = node_mgr.get_cluster_info().await;
+                println!("{}", cluster_info);
+
+                // Also show connected peers and their outbound queues
+                let peers = node_mgr.get_connected_peers().await;
+                let (broadcast_depth, peer_depths) = node_mgr.network_manager().outbound_queue_depths().await;
+                if !peers.is_empty() {
+                    println!("\nActive Connections:");
+                    for (peer_id, addr) in peers {
+                        let queue = peer_depths.iter()
+                            .find(|depth| depth.node_id == peer_id)
+                            .map(|depth| format!(" (queue {}/{}, {} dropped)", depth.queued, depth.capacity, depth.dropped))
+                            .unwrap_or_default();
+                        println!("  {} - {}{}",
+                            peer_id.to_string()[..8].to_uppercase(),
+                            addr,
+                            queue
+                        );
+                    }
+                } else {
+                    println!("\nNo active connections");
+                }
+                println!("Broadcast queue: {}/{} ({} dropped)",
+                    broadcast_depth.queued, broadcast_depth.capacity, broadcast_depth.dropped);
+            } else {
+                println!("{} {}",
+                    ColorScheme::warning_indicator("Warning:"),
+                    ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
+                );
+            }
+            Ok(false)
+        }
+        CliCommand::Migrate { instance_id, target_node_id, wait, timeout_secs } => {
+            if let Some(ref node_mgr) = node_manager {
+                match uuid::Uuid::parse_str(&target_node_id) {
+                    Ok(target_uuid) => {
+                        // Only a running instance can be migrated; resolve to its full ID so a
+                        // shadow sharing the short ID is not picked up later
+                        let resolved = instance_manager.lock().await
+                            .resolve_instance_id_with_status(&instance_id, &[crate::types::InstanceStatus::Running]);
+                        let full_instance_id = match resolved {
+                            Ok(uuid) => uuid.to_string(),
+                            Err(e) => {
+                                println!("{} {}",
+                                    ColorScheme::error_indicator("Error:"),
+                                    ColorScheme::error(&e.to_string())
+                                );
+                                return Ok(false);
+                            }
+                        };
+
+                        // Check if target node exists in cluster
+                        let cluster_state = node_mgr.cluster_state();
+                        let nodes = cluster_state.get_online_nodes().await;
+
+                        if !nodes.iter().any(|node| node.node_id == target_uuid) {
+                            println!("{} {}",
+                                ColorScheme::error_indicator("Error:"),
+                                ColorScheme::error(&format!("Target node '{}' not found in cluster", target_node_id))
+                            );
+                            return Ok(false);
+                        }
+
+                        println!("{} {} {} {}",
+                            ColorScheme::info_indicator("Migration:"),
+                            ColorScheme::info("Starting migration of instance"),
+                            ColorScheme::instance_id(&instance_id),
+                            ColorScheme::info(&format!("to node {}", target_node_id))
+                        );
+
+                        // Use migration manager to initiate migration
+                        if let Some(ref migration_mgr) = migration_manager {
+                            // Use default migration options
+                            let options = crate::migration_manager::MigrationOptions::default();
+                            let timeout = std::time::Duration::from_secs(timeout_secs.unwrap_or(options.timeout_secs));
+
+                            // Initiate migration
+                            match migration_mgr.migrate_instance(&full_instance_id, target_uuid, options).await {
+                                Ok(migration_id) => {
+                                    println!("{} {} {}",
+                                        ColorScheme::success_indicator("Success:"),
+                                        ColorScheme::success("Migration request initiated with ID:"),
+                                        ColorScheme::info(&migration_id.to_string()[..8])
+                                    );
+                                    if wait {
+                                        let signals = cli_state.lock().await.signals.clone();
+                                        let waited = signals.interruptible(migration_mgr.wait_for_migration(migration_id, timeout, |status| {
+                                            Output::migration(&format!("Migration {}: {:?}", &migration_id.to_string()[..8], status));
+                                        })).await;
+                                        match waited {
+                                            Ok(result) => {
+                                                result?;
+                                                Output::success(&format!("Migration {} completed", &migration_id.to_string()[..8]));
+                                            }
+                                            Err(signal) => Output::warning(&format!(
+                                                "Stopped waiting on {}; migration {} continues in the background",
+                                                signal.name(), &migration_id.to_string()[..8]
+                                            )),
+                                        }
+                                        return Ok(false);
+                                    }
+                                    println!("{} {}",
+                                        ColorScheme::info_indicator("Note:"),
+                                        ColorScheme::info("Migration is running in the background. Use 'migration-status' to check progress.")
+                                    );
+                                }
+                                Err(e) if wait => {
+                                    return Err(anyhow::anyhow!("Failed to initiate migration: {}", e));
+                                }
+                                Err(e) => {
+                                    println!("{} {}",
+                                        ColorScheme::error_indicator("Error:"),
+                                        ColorScheme::error(&format!("Failed to initiate migration: {}", e))
+                                    );
+                                }
+                            }
+                        } else {
+                            println!("{} {}",
+                                ColorScheme::warning_indicator("Warning:"),
+                                ColorScheme::warning("Migration manager is not available.")
+                            );
+                        }
+                    }
+                    Err(_) => {
+                        println!("{} {}",
+                            ColorScheme::error_indicator("Error:"),
+                            ColorScheme::error("Invalid target node ID format")
+                        );
+                    }
+                }
+            } else {
+                println!("{} {}",
+                    ColorScheme::warning_indicator("Warning:"),
+                    ColorScheme::warning("Networking is disabled. Migration requires networking.")
+                );
+            }
+            Ok(false)
+        }
+        CliCommand::MigrateMany { instance_ids, all, target_node_id, parallel, wait, timeout_secs } => {
+            let (node_mgr, migration_mgr) = match (node_manager, migration_manager) {
+                (Some(node_mgr), Some(migration_mgr)) => (node_mgr, migration_mgr),
+                _ => {
+                    println!("{} {}",
+                        ColorScheme::warning_indicator("Warning:"),
+                        ColorScheme::warning("Networking is disabled. Migration requires networking.")
+                    );
+                    return Ok(false);
+                }
+            };
+            let target_uuid = match target_node_id.as_deref().map(uuid::Uuid::parse_str).transpose() {
+                Ok(target_uuid) => target_uuid,
+                Err(_) => {
+                    println!("{} {}",
+                        ColorScheme::error_indicator("Error:"),
+                        ColorScheme::error("Invalid target node ID format")
+                    );
+                    return Ok(false);
+                }
+            };
+
+            let instances: Vec<crate::types::Instance> = {
+                let manager = instance_manager.lock().await;
+                if all {
+                    manager.get_all_instances().into_iter()
+                        .filter(|instance| instance.status == crate::types::InstanceStatus::Running)
+                        .collect()
+                } else {
+                    let mut selected = Vec::new();
+                    for instance_id in &instance_ids {
+                        match manager.resolve_instance_id_with_status(instance_id, &[crate::types::InstanceStatus::Running]) {
+                            Ok(uuid) => selected.extend(manager.get_instance_by_id(&uuid.to_string()).cloned()),
+                            Err(e) => println!("{} {}: {}", ColorScheme::error_indicator("Skipping"), ColorScheme::instance_id(instance_id), e),
+                        }
+                    }
+                    selected
+                }
+            };
+            if instances.is_empty() {
+                println!("{}", ColorScheme::info("No running instances to migrate."));
+                return Ok(false);
+            }
+
+            let cluster_state = node_mgr.cluster_state();
+            let local_node_id = cluster_state.local_node_id();
+            let nodes = cluster_state.get_online_nodes().await;
+            let node_name = |node_id: &uuid::Uuid| {
+                nodes.iter().find(|node| node.node_id == *node_id)
+                    .map(|node| node.name.clone())
+                    .unwrap_or_else(|| node_id.to_string()[..8].to_string())
+            };
+            if let Some(target_uuid) = target_uuid {
+                if !nodes.iter().any(|node| node.node_id == target_uuid && node.node_id != local_node_id) {
+                    println!("{} {}",
+                        ColorScheme::error_indicator("Error:"),
+                        ColorScheme::error(&format!("Target node '{}' not found in cluster", target_uuid))
+                    );
+                    return Ok(false);
+                }
+            }
+
+            // Instances each peer already runs, as seen through its shadows; grows as targets are assigned
+            let mut load: std::collections::HashMap<uuid::Uuid, usize> = std::collections::HashMap::new();
+            if let Some(ref shadow_mgr) = shadow_manager {
+                for shadow in shadow_mgr.read().await.get_shadow_instances().await {
+                    *load.entry(shadow.source_node_id).or_default() += 1;
+                }
+            }
+
+            let options = crate::migration_manager::MigrationOptions::default();
+            let timeout = std::time::Duration::from_secs(timeout_secs.unwrap_or(options.timeout_secs));
+            let mut outcomes: Vec<(String, Option<uuid::Uuid>, std::result::Result<uuid::Uuid, String>)> = Vec::new();
+            let signals = cli_state.lock().await.signals.clone();
+            let mut interrupted = None;
+            for instance in &instances {
+                let short_id = instance.short_id();
+                // migrate_instance checks an explicit target's capabilities and the instance's affinity
+                let target = target_uuid
+                    .or_else(|| crate::migration_manager::pick_evacuation_target(&nodes, local_node_id, &instance.affinity, &load));
+                let target = match target {
+                    Some(target) => target,
+                    None => {
+                        outcomes.push((short_id, None, Err("no eligible target node".to_string())));
+                        continue;
+                    }
+                };
+
+                match signals.interruptible(migration_mgr.wait_for_migration_slot(parallel, timeout)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        outcomes.push((short_id, Some(target), Err(e.to_string())));
+                        continue;
+                    }
+                    Err(signal) => {
+                        interrupted = Some(signal);
+                        break;
+                    }
+                }
+                let result = migration_mgr.migrate_instance(&instance.id.to_string(), target, options.clone()).await;
+                if result.is_ok() {
+                    *load.entry(target).or_default() += 1;
+                }
+                outcomes.push((short_id, Some(target), result.map_err(|e| e.to_string())));
+            }
+
+            if wait && interrupted.is_none() {
+                for (_, _, result) in outcomes.iter_mut() {
+                    if let Ok(migration_id) = result {
+                        let migration_id = *migration_id;
+                        match signals.interruptible(migration_mgr.wait_for_migration(migration_id, timeout, |_| {})).await {
+                            Ok(Ok(_)) => {}
+                            Ok(Err(e)) => *result = Err(format!("migration {} failed: {}", &migration_id.to_string()[..8], e)),
+                            Err(signal) => {
+                                interrupted = Some(signal);
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(signal) = interrupted {
+                Output::warning(&format!("Stopped on {}; migrations already started continue in the background", signal.name()));
+            }
+
+            let succeeded = outcomes.iter().filter(|(_, _, result)| result.is_ok()).count();
+            for (short_id, target, result) in &outcomes {
+                let target = match target {
+                    Some(target) => node_name(target),
+                    None => "-".to_string(),
+                };
+                match result {
+                    Ok(migration_id) => println!("  {} {} -> {}: migration {} {}",
+                        ColorScheme::success_indicator("✓"),
+                        ColorScheme::instance_id(short_id),
+                        target,
+                        &migration_id.to_string()[..8],
+                        if wait { "completed" } else { "initiated" }
+                    ),
+                    Err(e) => println!("  {} {} -> {}: {}",
+                        ColorScheme::error_indicator("✗"),
+                        ColorScheme::instance_id(short_id),
+                        target,
+                        e
+                    ),
+                }
+            }
+            println!("{} of {} migration(s) {}", succeeded, outcomes.len(), if wait { "completed" } else { "initiated" });
+            Ok(false)
+        }
+        CliCommand::MigrationStatus { migration_id, json } => {
+            let migration_mgr = match migration_manager {
+                Some(ref migration_mgr) => migration_mgr,
+                None => {
+                    println!("{} {}",
+                        ColorScheme::warning_indicator("Warning:"),
+                        ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
+                    );
+                    return Ok(false);
+                }
+            };
+
+            let mut migrations: Vec<_> = migration_mgr
+                .list_active_migrations()
+                .await
+                .into_iter()
+                .filter(|m| migration_id.as_ref().is_none_or(|id| m.migration_id.to_string().starts_with(id.as_str())))
+                .collect();
+            migrations.sort_by_key(|m| m.started_at);
+
+            if json {
+                let entries: Vec<_> = migrations.iter().map(|m| m.status_json()).collect();
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+            } else if migrations.is_empty() {
+                println!("{}", ColorScheme::info("No migrations found."));
+            } else {
+                for m in &migrations {
+                    let progress = m.progress_percent().map_or("-".to_string(), |p| format!("{}%", p));
+                    let transfer = if m.bytes_total > 0 {
+                        format!(" ({}/{} bytes)", m.bytes_sent, m.bytes_total)
+                    } else {
+                        String::new()
+                    };
+                    println!("{} {} {} {:?} {}{}",
+                        ColorScheme::info(&m.migration_id.to_string()[..8]),
+                        ColorScheme::instance_id(&m.instance_id.to_string()[..8]),
+                        ColorScheme::info(&format!("-> {}", &m.target_node_id.to_string()[..8])),
+                        m.status, progress, transfer
+                    );
+                }
+            }
+            Ok(false)
+        }
+        CliCommand::WatchMigrations => {
+            let migration_mgr = match migration_manager {
+                Some(ref migration_mgr) => migration_mgr,
+                None => {
+                    println!("{} {}",
+                        ColorScheme::warning_indicator("Warning:"),
+                        ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
+                    );
+                    return Ok(false);
+                }
+            };
+
+            // Subscribe before the snapshot so no transition falls in between
+            let mut updates = migration_mgr.subscribe_status();
+            let mut watch = migration_manager::MigrationWatch::new(&migration_mgr.list_active_migrations().await);
+            let mut view = crate::ui::MigrationWatchUI::new()?;
+            view.enter()?;
+
+            // Transfer counters are not broadcast, so refresh them from a snapshot on every tick
+            let mut refresh = tokio::time::interval(MIGRATION_WATCH_REFRESH);
+            let mut updates_open = true;
+            loop {
+                tokio::select! {
+                    update = updates.recv(), if updates_open => match update {
+                        Ok(update) => {
+                            if !watch.apply(&update, chrono::Utc::now()) {
+                                watch.sync(&migration_mgr.list_active_migrations().await, chrono::Utc::now());
+                            }
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
+                            watch.sync(&migration_mgr.list_active_migrations().await, chrono::Utc::now());
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => updates_open = false,
+                    },
+                    _ = refresh.tick() => {
+                        watch.sync(&migration_mgr.list_active_migrations().await, chrono::Utc::now());
+                    }
+                    quit = view.next_quit() => {
+                        if quit? {
+                            break;
+                        }
+                    }
+                }
+                view.draw(&watch)?;
+            }
+
+            view.exit()?;
+            Ok(false)
+        }
+        CliCommand::ShadowView { instance_id } => {
+            println!("{} {}",
+                ColorScheme::info_indicator("Shadow View:"),
+                ColorScheme::info(&format!("Shadow view of {} will be implemented after core sync is working", instance_id))
+            );
+            Ok(false)
+        }
+    }
+}
+
+/// Node ID with its name, marking this node and nodes the cluster state doesn't list as online
+async fn describe_node(node_mgr: &NodeManager, node_id: Uuid) -> String {
+    let cluster_state = node_mgr.cluster_state();
+    if node_id == cluster_state.local_node_id() {
+        return format!("{} (this node)", node_id);
+    }
+    match cluster_state.get_node_info(&node_id).await {
+        Some(node) if node.status == message_protocol::NodeStatus::Online => format!("{} ({})", node_id, node.name),
+        Some(node) => format!("{} ({}, {:?})", node_id, node.name, node.status),
+        None => format!("{} (not in cluster)", node_id),
+    }
+}
+
+/// How often `cluster connect --wait-ready` checks for the peer's acknowledgement
+const WAIT_READY_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+/// How often the instance list is re-sent while waiting, in case the peer was not listening yet
+const WAIT_READY_RESEND_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Advertise local running instances and block until `peer_node_id` acknowledges holding
+/// shadows of them, or `timeout` passes. True when the peer shadows every advertised instance.
+async fn wait_for_peer_shadows(
+    shadow_mgr: &Arc<tokio::sync::RwLock<ShadowInstanceManager>>,
+    peer_node_id: Uuid,
+    timeout: std::time::Duration,
+) -> bool {
+    let deadline = std::time::Instant::now() + timeout;
+    let mut sync_id = None;
+    let mut advertised = Vec::new();
+    let mut next_send = std::time::Instant::now();
+
+    println!("Waiting for node {} to create shadows...", peer_node_id);
+    let shadowed = loop {
+        if std::time::Instant::now() >= next_send {
+            match shadow_mgr.read().await.advertise_for_ack(sync_id).await {
+                Ok((id, instances)) => {
+                    sync_id = Some(id);
+                    advertised = instances;
+                }
+                Err(e) => warn!("Failed to send instance list to {}: {}", peer_node_id, e),
+            }
+            next_send = std::time::Instant::now() + WAIT_READY_RESEND_INTERVAL;
+        }
+        if let Some(id) = sync_id {
+            if let Some(shadowed) = shadow_mgr.read().await.sync_ack(id, peer_node_id).await {
+                break Some(shadowed);
+            }
+        }
+        if std::time::Instant::now() >= deadline {
+            break None;
+        }
+        tokio::time::sleep(WAIT_READY_POLL_INTERVAL).await;
+    };
+    if let Some(id) = sync_id {
+        shadow_mgr.read().await.forget_sync(id).await;
+    }
+
+    match shadowed {
+        Some(shadowed) => {
+            let missing: Vec<String> = advertised.iter()
+                .filter(|id| !shadowed.contains(id))
+                .map(|id| id.to_string()[..8].to_string())
+                .collect();
+            if missing.is_empty() {
+                println!("{} {}",
+                    ColorScheme::success_indicator("Ready:"),
+                    ColorScheme::success(&format!("Node {} holds shadows of all {} running instance(s)", peer_node_id, advertised.len()))
+                );
+                true
+            } else {
+                println!("{} {}",
+                    ColorScheme::warning_indicator("Warning:"),
+                    ColorScheme::warning(&format!("Node {} did not create shadows of: {}", peer_node_id, missing.join(", ")))
+                );
+                false
+            }
+        }
+        None => {
+            println!("{} {}",
+                ColorScheme::error_indicator("Error:"),
+                ColorScheme::error(&format!("Node {} did not acknowledge the instance list within {}s", peer_node_id, timeout.as_secs()))
+            );
+            false
+        }
+    }
+}
+
+fn print_help() {
+    println!("{}", ColorScheme::header("Available commands:"));
+    println!("  {} {} - Start a new program instance", ColorScheme::command("start"), ColorScheme::info("[--sync] [--foreground] [--start-paused] [--id <uuid>] [--timestamp|--relative-timestamp|--no-timestamp] [--output-file <path>] [--append-only] [--label key=value] [--require-capability <cap>] [--node-affinity <node_id>] [--pre-checkpoint-cmd <cmd>] [--post-checkpoint-cmd <cmd>] [--snapshot-interval <dur> [--snapshot-retain <n>]] <program> [args...]"));
+    println!("  {} {} - Start a detached instance (CRIU-optimized)", ColorScheme::command("start-detached"), ColorScheme::info("[--sync] [--start-paused] [--id <uuid>] [--timestamp|--relative-timestamp|--no-timestamp] [--output-file <path>] [--append-only] [--label key=value] [--require-capability <cap>] [--node-affinity <node_id>] [--pre-checkpoint-cmd <cmd>] [--post-checkpoint-cmd <cmd>] [--snapshot-interval <dur> [--snapshot-retain <n>]] <program> [args...]"));
+    println!("  {} {} - Stop an instance (--if-running: succeed if already stopped), or every running instance with the labels", ColorScheme::command("stop"), ColorScheme::info("[--if-running] <instance_id> | --label key=value"));
+    println!("  {} {} - Delete stopped/failed instances and leftover instance directories, reporting reclaimed space", ColorScheme::command("purge"), ColorScheme::info("[--status stopped|failed|all] [--older-than <dur>] [--dry-run]"));
+    println!("  {} {} - Show an instance's details, labels included", ColorScheme::command("info"), ColorScheme::info("<instance_id>"));
+    println!("  {} {} - Pause an instance", ColorScheme::command("pause"), ColorScheme::info("<instance_id>"));
+    println!("  {} {} - Resume a paused instance", ColorScheme::command("resume"), ColorScheme::info("<instance_id>"));
+    println!("  {} {} - Re-attempt the start, stop or restore that left an instance failed", ColorScheme::command("retry"), ColorScheme::info("<instance_id>"));
+    println!("  {} {} - Change a stopped instance's launch config", ColorScheme::command("edit"), ColorScheme::info("<instance_id> [--env KEY=VALUE] [--cwd <dir>] [--args <args...>]"));
+    println!("  {} {} - Stop (if running) and start again with the stored config", ColorScheme::command("restart"), ColorScheme::info("<instance_id>"));
+    println!("  {} {} - Send a signal (name or number) to an instance", ColorScheme::command("kill"), ColorScheme::info("<instance_id> <signal>"));
+    println!("  {} {} - Enable or disable checkpoint auto-sync", ColorScheme::command("sync"), ColorScheme::info("<instance_id> <on|off>"));
+    println!("  {} {} - List instances, optionally filtered and sorted (default: by creation time)", ColorScheme::command("list"), ColorScheme::info("[--status <status>] [--node <node_id>] [--program <substr>] [--label key=value] [--sort <created|id|status|program>] [--json]"));
+    println!("  {} {} - Enter instance mode (shows historical output; a shadow shows a live view of the source node)", ColorScheme::command("attach"), ColorScheme::info("<instance_id>"));
+    println!("  {} - Exit instance mode", ColorScheme::command("detach"));
+    println!("  {} {} - Show recent output (default: current instance, 20 lines; --all includes output from before restores; --since/--until keep timestamped lines in the window)", ColorScheme::command("logs"), ColorScheme::info("[instance_id] [lines] [--stream stdout|stderr] [--all] [--since <5m|rfc3339>] [--until <5m|rfc3339>]"));
+    println!("  {} {} - Search an instance's output with a regex (--files searches the on-disk logs, including rotated ones)", ColorScheme::command("grep"), ColorScheme::info("[-i|--ignore-case] [--context <n>] [--files] <instance_id> <pattern>"));
+    println!("  {} {} - Create a checkpoint (--set-base: auto-syncs build incrementally on it; --stop: terminate the process after the dump)", ColorScheme::command("checkpoint"), ColorScheme::info("<instance_id>|--label key=value <name> [--set-base] [--stop]"));
+    println!("  {} {} - Compare two checkpoints file by file (missing, resized and changed files)", ColorScheme::command("checkpoint-diff"), ColorScheme::info("<instance_id> <checkpoint_a> <checkpoint_b>"));
+    println!("  {} {} - Restore instance from checkpoint (--replace starts a fresh output log, --tty reparents its terminal)", ColorScheme::command("restore"), ColorScheme::info("[--uid-map <from>:<to>] [--gid-map <from>:<to>] [--replace] [--tty inherit|null|pty] <instance_id> <checkpoint_name> | --snapshot <time> <instance_id>"));
+    println!("  {} {} - Analyze TTY environment for CRIU compatibility", ColorScheme::command("analyze-tty"), ColorScheme::info("<instance_id>"));
+    println!("  {} {} - Change working directory", ColorScheme::command("cd"), ColorScheme::info("<directory>"));
+    println!("  {} - Show this help", ColorScheme::command("help"));
+    println!("  {} - Exit the CLI", ColorScheme::command("exit"));
+    println!();
+    println!("{}", ColorScheme::header("Cluster Commands (Stage 2):"));
+    println!("  {} {} - List all nodes in the cluster", ColorScheme::command("cluster list-nodes"), ColorScheme::info(""));
+    println!("  {} {} - Show node information (local if no ID)", ColorScheme::command("cluster node-info"), ColorScheme::info("[node_id]"));
+    println!("  {} {} - Connect to a peer node (IP or hostname); --persist reconnects on restart, --wait-ready waits until the peer holds shadows of local instances", ColorScheme::command("cluster connect"), ColorScheme::info("<host:port> [--persist] [--wait-ready [--timeout <secs>]]"));
+    println!("  {} {} - Stop reconnecting to a persisted peer", ColorScheme::command("cluster forget"), ColorScheme::info("<node_id|host:port>"));
+    println!("  {} {} - Disconnect from a peer node", ColorScheme::command("cluster disconnect"), ColorScheme::info("<node_id>"));
+    println!("  {} {} - Show cluster status and connections; --watch refreshes peers, RTTs, joins and leaves live (q to return)", ColorScheme::command("cluster status"), ColorScheme::info("[--watch] [--interval <secs>]"));
+    println!("  {} {} - List each node's capabilities", ColorScheme::command("cluster capabilities"), ColorScheme::info(""));
+    println!("  {} {} - Show which nodes are connected to which", ColorScheme::command("cluster topology"), ColorScheme::info(""));
+    println!("  {} {} - Show which node runs an instance and which hold shadows", ColorScheme::command("locate"), ColorScheme::info("<instance_id>"));
+    println!("  {} {} - Measure round-trip time to a node", ColorScheme::command("cluster ping"), ColorScheme::info("<node_id>"));
+    println!();
+    println!("{}", ColorScheme::header("Migration Commands (Stage 3):"));
+    println!("  {} {} - Migrate instance to another node", ColorScheme::command("migrate"), ColorScheme::info("<instance_id> <target_node_id> [--wait [--timeout <secs>]]"));
+    println!("  {} {} - Migrate several instances, or every running one to the least-loaded nodes", ColorScheme::command("migrate"), ColorScheme::info("<instance_id>... --target <node_id> | --all --target <node_id> | --evacuate [--parallel <n>] [--wait [--timeout <secs>]]"));
+    println!("  {} {} - Summarize instances per status and node, migrations and checkpoint disk usage", ColorScheme::command("stats"), ColorScheme::info("[--json]"));
+    println!("  {} {} - Show migration phases and progress", ColorScheme::command("migration-status"), ColorScheme::info("[<migration_id>] [--json]"));
+    println!("  {} - Live view of migration phases and progress (q to return)", ColorScheme::command("watch migrations"));
+    println!("  {} {} - View shadow instance output and status", ColorScheme::command("shadow-view"), ColorScheme::info("<instance_id>"));
+    println!();
+    println!("{}", ColorScheme::header("Aliases:"));
+    println!("  {} = {}", ColorScheme::command("startd"), ColorScheme::command("start-detached"));
+    println!("  {} = {}", ColorScheme::command("tty"), ColorScheme::command("analyze-tty"));
+    println!("  {} = {}", ColorScheme::command("cluster nodes"), ColorScheme::command("cluster list-nodes"));
+    println!("  {} = {}", ColorScheme::command("cluster info"), ColorScheme::command("cluster node-info"));
+    println!();
+    println!("{}", ColorScheme::header("Tips:"));
+    println!("  {} Use 'start-detached' for better CRIU checkpoint/restore compatibility", ColorScheme::info_indicator("•"));
+    println!("  {} Use 'analyze-tty' to check if a process is CRIU-friendly", ColorScheme::info_indicator("•"));
+    println!("  {} Detached instances have limited input capabilities but work better with CRIU", ColorScheme::info_indicator("•"));
+    println!("  {} Use --no-network to disable P2P networking (Stage 1 compatibility mode)", ColorScheme::info_indicator("•"));
+    println!("  {} Nodes auto-discover each other on the local network", ColorScheme::info_indicator("•"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn foreground_start_returns_the_exit_code_of_the_program() {
+        crate::test_support::use_scratch_dir();
+        let cli_state = Arc::new(Mutex::new(CliState::new()));
+        let instance_manager = Arc::new(Mutex::new(InstanceManager::new()));
+        let process_manager = Arc::new(ProcessManager::new());
+        let criu_manager = Arc::new(CriuManager::new());
+        let run = |input: &'static str| execute_command(input, &cli_state, &instance_manager, &process_manager, &criu_manager, &None, &None, &None);
+
+        match run("start --foreground false").await {
+            Err(e) => match e.downcast_ref::<types::CriuCliError>() {
+                Some(types::CriuCliError::ProgramExited(_, code)) => assert_eq!(*code, 1),
+                _ => panic!("expected the exit code, got {}", e),
+            },
+            Ok(_) => panic!("a failing foreground program must fail the command"),
+        }
+        assert!(!run("start --foreground true").await.unwrap());
+
+        // Both instances were waited for and are no longer running
+        let instances = instance_manager.lock().await.get_all_instances();
+        assert_eq!(instances.len(), 2);
+        assert!(instances.iter().all(|instance| instance.status != types::InstanceStatus::Running));
+    }
+
+    #[tokio::test]
+    async fn wait_ready_returns_once_the_peer_holds_the_shadow() {
+        crate::test_support::use_scratch_dir();
+        let engine: Arc<dyn nhi::checkpoint_engine::CheckpointEngine> = Arc::new(nhi::checkpoint_engine::CriuEngine::new("./criu/bin/criu"));
+        let node = |node_id: Uuid, instances: Arc<Mutex<InstanceManager>>| {
+            let queue = nhi::network_manager::OutboundQueue::new(16);
+            let mut manager = ShadowInstanceManager::new_with_engine(node_id, instances, Arc::new(ProcessManager::new()), engine.clone());
+            manager.set_network_sender(queue.clone());
+            (manager, queue)
+        };
+        let (source_instances, target_instances) = (Arc::new(Mutex::new(InstanceManager::new())), Arc::new(Mutex::new(InstanceManager::new())));
+        let (source, source_queue) = node(Uuid::new_v4(), source_instances.clone());
+        let source = Arc::new(tokio::sync::RwLock::new(source));
+        let target_id = Uuid::new_v4();
+        let (target, target_queue) = node(target_id, target_instances.clone());
+
+        let mut instance = types::Instance::new("sleep".to_string(), vec!["30".to_string()], std::path::PathBuf::from("/"));
+        instance.status = types::InstanceStatus::Running;
+        let instance_id = instance.id;
+        source_instances.lock().await.add_instance(instance);
+
+        // A peer that takes a while to receive the instance list, then answers once it created the shadow
+        let relay_source = source.clone();
+        tokio::spawn(async move {
+            while let Some(message) = source_queue.recv().await {
+                if let message_protocol::NetworkMessage::InstanceSync(sync) = message {
+                    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+                    target.handle_instance_sync(sync).await.unwrap();
+                }
+            }
+        });
+        tokio::spawn(async move {
+            while let Some(message) = target_queue.recv().await {
+                if let message_protocol::NetworkMessage::InstanceSyncAck(ack) = message {
+                    relay_source.read().await.handle_instance_sync_ack(ack).await;
+                }
+            }
+        });
+
+        let started = std::time::Instant::now();
+        assert!(wait_for_peer_shadows(&source, target_id, std::time::Duration::from_secs(5)).await);
+        assert!(started.elapsed() >= std::time::Duration::from_millis(300));
+        let shadow = target_instances.lock().await.get_instance_by_id(&instance_id.to_string()).cloned();
+        assert_eq!(shadow.map(|shadow| shadow.status), Some(types::InstanceStatus::Shadow));
+
+        // A peer that never answers
+        assert!(!wait_for_peer_shadows(&source, Uuid::new_v4(), std::time::Duration::from_millis(500)).await);
+    }
+
+    #[tokio::test]
+    async fn evacuate_initiates_a_migration_for_every_running_instance_to_eligible_nodes() {
+        crate::test_support::use_scratch_dir();
+        let config = message_protocol::NetworkConfig {
+            listen_addr: "127.0.0.1:0".parse().unwrap(),
+            discovery_enabled: false,
+            ..message_protocol::NetworkConfig::default()
+        };
+        let node_manager = Arc::new(NodeManager::new(config.clone()).unwrap());
+        let cluster_state = node_manager.cluster_state().clone();
+
+        // Three peers that can restore, connected so migration requests reach them
+        let mut eligible = Vec::new();
+        for name in ["peer-a", "peer-b", "peer-c"] {
+            let (listener, addr) = nhi::network_manager::NetworkManager::pre_bind(config.listen_addr).unwrap();
+            let peer = nhi::network_manager::NetworkManager::new_with_listener(
+                message_protocol::NetworkConfig { listen_addr: addr, ..config.clone() }, Uuid::new_v4(), listener);
+            peer.start_listening().await.unwrap();
+            let peer_id = node_manager.network_manager().connect_to_peer(addr).await.unwrap();
+            cluster_state.add_node(message_protocol::NodeInfo::new(peer_id, name.to_string(), addr)).await.unwrap();
+            eligible.push((peer_id, peer));
+        }
+        // And one that cannot, which evacuation must pass over
+        let mut no_restore = message_protocol::NodeInfo::new(Uuid::new_v4(), "peer-d".to_string(), "127.0.0.1:1".parse().unwrap());
+        no_restore.capabilities.clear();
+        cluster_state.add_node(no_restore).await.unwrap();
+
+        let instance_manager = Arc::new(Mutex::new(InstanceManager::new()));
+        let process_manager = Arc::new(ProcessManager::new());
+        let mut running = Vec::new();
+        for _ in 0..3 {
+            let mut instance = types::Instance::new("/bin/sleep".to_string(), vec!["30".to_string()], std::path::PathBuf::from("/"));
+            instance.status = types::InstanceStatus::Running;
+            running.push(instance.id);
+            instance_manager.lock().await.add_instance(instance);
+        }
+        let mut migration_manager = MigrationManager::new_with_engine(
+            node_manager.node_id(),
+            node_manager.network_manager().clone(),
+            instance_manager.clone(),
+            process_manager.clone(),
+            Arc::new(nhi::checkpoint_engine::CriuEngine::new("./criu/bin/criu")),
+        );
+        migration_manager.set_cluster_state(cluster_state);
+        let migration_manager = Arc::new(migration_manager);
+
+        let cli_state = Arc::new(Mutex::new(CliState::new()));
+        let criu_manager = Arc::new(CriuManager::new());
+        execute_command("migrate --evacuate --parallel 3", &cli_state, &instance_manager, &process_manager, &criu_manager,
+            &Some(node_manager), &None, &Some(migration_manager.clone())).await.unwrap();
+
+        let migrations = migration_manager.list_active_migrations().await;
+        let mut migrated: Vec<Uuid> = migrations.iter().map(|migration| migration.instance_id).collect();
+        migrated.sort();
+        running.sort();
+        assert_eq!(migrated, running);
+        // Every target can restore, and with equal load each peer takes one
+        let mut targets: Vec<Uuid> = migrations.iter().map(|migration| migration.target_node_id).collect();
+        targets.sort();
+        let mut expected: Vec<Uuid> = eligible.iter().map(|(peer_id, _)| *peer_id).collect();
+        expected.sort();
+        assert_eq!(targets, expected);
+        assert!(migrations.iter().all(|migration| !migration.status.is_terminal()), "{:?}", migrations);
+    }
+}
@@ -0,0 +1,505 @@
+use crate::types::{CriuCliError, IdMap, RestoreOptions, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Marker file in a checkpoint directory recording that it was dumped with `--tcp-established`
+pub const TCP_ESTABLISHED_MARKER: &str = "tcp_established";
+
+/// File in a checkpoint directory recording the UID and GID the process ran as
+pub const ORIGINAL_IDS_FILE: &str = "original_ids.json";
+
+/// File in a checkpoint directory recording how many descendants were dumped with the process
+pub const PROCESS_TREE_FILE: &str = "process_tree_size";
+
+/// Real UID and GID of a process, from /proc/<pid>/status
+pub fn read_process_ids(pid: u32) -> Option<(u32, u32)> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let field = |name: &str| -> Option<u32> {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name))?
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()
+    };
+    Some((field("Uid:")?, field("Gid:")?))
+}
+
+/// Record the process's UID and GID in the checkpoint directory
+pub fn save_original_ids(pid: u32, checkpoint_dir: &Path) {
+    let (uid, gid) = match read_process_ids(pid) {
+        Some(ids) => ids,
+        None => {
+            warn!("Failed to read UID/GID of process {}", pid);
+            return;
+        }
+    };
+    if let Err(e) = write_original_ids(checkpoint_dir, uid, gid) {
+        warn!("Failed to record UID/GID for checkpoint: {}", e);
+    }
+}
+
+/// Record a UID and GID in the checkpoint directory, e.g. ones a migration source reported
+pub fn write_original_ids(checkpoint_dir: &Path, uid: u32, gid: u32) -> std::io::Result<()> {
+    let json = serde_json::json!({ "uid": uid, "gid": gid });
+    fs::write(checkpoint_dir.join(ORIGINAL_IDS_FILE), json.to_string())
+}
+
+/// UID and GID recorded at checkpoint time, if the checkpoint has them
+pub fn load_original_ids(checkpoint_dir: &Path) -> Option<(u32, u32)> {
+    let content = fs::read_to_string(checkpoint_dir.join(ORIGINAL_IDS_FILE)).ok()?;
+    let value: serde_json::Value = serde_json::from_str(&content).ok()?;
+    Some((value["uid"].as_u64()? as u32, value["gid"].as_u64()? as u32))
+}
+
+/// Check the checkpoint's recorded IDs against the restoring user and build the CRIU mapping
+/// arguments. Restoring as a different user requires an explicit map.
+pub fn check_restore_ids(checkpoint_dir: &Path, options: &RestoreOptions) -> Result<Vec<String>> {
+    let current = (nix::unistd::getuid().as_raw(), nix::unistd::getgid().as_raw());
+    let recorded = load_original_ids(checkpoint_dir);
+
+    let mut args = Vec::new();
+    for (kind, flag, map, recorded_id, current_id) in [
+        ("UID", "--uid", options.uid_map, recorded.map(|ids| ids.0), current.0),
+        ("GID", "--gid", options.gid_map, recorded.map(|ids| ids.1), current.1),
+    ] {
+        match (map, recorded_id) {
+            (Some(map), recorded_id) => {
+                if let Some(recorded_id) = recorded_id.filter(|id| *id != map.from) {
+                    warn!("{} map {} does not match the checkpoint's {} {}", kind, map, kind, recorded_id);
+                }
+                args.push(flag.to_string());
+                args.push(map.to_string());
+            }
+            (None, Some(recorded_id)) if recorded_id != current_id => {
+                return Err(CriuCliError::CriuError(format!(
+                    "Checkpoint was taken as {} {} but restoring as {} {}; pass {}-map {} to map it",
+                    kind, recorded_id, kind, current_id, flag, IdMap { from: recorded_id, to: current_id }
+                )));
+            }
+            _ => {}
+        }
+    }
+    Ok(args)
+}
+
+/// Parent PID and process group of a process, from /proc/<pid>/stat
+fn read_stat_ids(pid: u32) -> Option<(u32, u32)> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name may contain spaces, so parse from the closing parenthesis
+    let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+    let ppid = fields.nth(1)?.parse().ok()?;
+    let pgrp = fields.next()?.parse().ok()?;
+    Some((ppid, pgrp))
+}
+
+/// Page faults plus CPU clock ticks of one process, from /proc/<pid>/stat
+fn read_stat_activity(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    // minflt, majflt, utime and stime follow the command name at these offsets
+    [7, 9, 11, 12]
+        .iter()
+        .map(|index| fields.get(*index)?.parse::<u64>().ok())
+        .sum()
+}
+
+/// A process tree and its combined activity (page faults plus CPU clock ticks)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeActivity {
+    /// The root process followed by its descendants, sorted
+    pub processes: Vec<u32>,
+    pub activity: u64,
+}
+
+impl TreeActivity {
+    /// Whether the tree did at most `threshold` work since `earlier`; a process starting or
+    /// exiting changes what CRIU would dump, so it always counts as activity
+    pub fn idle_since(&self, earlier: &TreeActivity, threshold: u64) -> bool {
+        self.processes == earlier.processes && self.activity.saturating_sub(earlier.activity) <= threshold
+    }
+}
+
+/// Combined activity of a process and its descendants; it only grows while the tree runs,
+/// so an unchanged value over the same processes means nothing ran or touched memory in between
+pub fn tree_activity(pid: u32) -> Option<TreeActivity> {
+    let own = read_stat_activity(pid)?;
+    let descendants = descendants(pid);
+    let activity = own + descendants.iter().filter_map(|child| read_stat_activity(*child)).sum::<u64>();
+    let mut processes = vec![pid];
+    processes.extend(descendants);
+    Some(TreeActivity { processes, activity })
+}
+
+/// Whether `pid` descends from `ancestor`
+pub fn is_descendant(pid: u32, ancestor: u32) -> bool {
+    let mut current = pid;
+    while let Some((ppid, _)) = read_stat_ids(current) {
+        if ppid == ancestor {
+            return true;
+        }
+        if ppid <= 1 {
+            return false;
+        }
+        current = ppid;
+    }
+    false
+}
+
+/// Every process descending from `pid`, sorted; this is the tree CRIU dumps with `-t <pid>`
+pub fn descendants(pid: u32) -> Vec<u32> {
+    ProcessTable::read().descendants(pid)
+}
+
+/// Parent of every process, read from /proc once so many process trees can be walked cheaply
+pub struct ProcessTable {
+    children: HashMap<u32, Vec<u32>>,
+}
+
+impl ProcessTable {
+    pub fn read() -> Self {
+        let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+        if let Ok(entries) = fs::read_dir("/proc") {
+            for pid in entries.flatten().filter_map(|entry| entry.file_name().to_string_lossy().parse::<u32>().ok()) {
+                if let Some((ppid, _)) = read_stat_ids(pid) {
+                    children.entry(ppid).or_default().push(pid);
+                }
+            }
+        }
+        Self { children }
+    }
+
+    /// Every process descending from `pid` when the table was read, sorted
+    pub fn descendants(&self, pid: u32) -> Vec<u32> {
+        let mut found = Vec::new();
+        let mut pending = vec![pid];
+        while let Some(parent) = pending.pop() {
+            for child in self.children.get(&parent).into_iter().flatten() {
+                found.push(*child);
+                pending.push(*child);
+            }
+        }
+        found.sort_unstable();
+        found
+    }
+}
+
+/// CRIU processes restoring from `images_dir`, found by their command line
+pub fn restore_processes(images_dir: &Path) -> Vec<u32> {
+    let images_dir = images_dir.to_string_lossy();
+    let mut found: Vec<u32> = fs::read_dir("/proc")
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|entry| entry.file_name().to_string_lossy().parse::<u32>().ok())
+                .filter(|pid| {
+                    let cmdline = fs::read(format!("/proc/{}/cmdline", pid)).unwrap_or_default();
+                    let args: Vec<String> = cmdline
+                        .split(|byte| *byte == 0)
+                        .map(|arg| String::from_utf8_lossy(arg).to_string())
+                        .collect();
+                    args.iter().any(|arg| arg == "restore") && args.iter().any(|arg| *arg == images_dir)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    found.sort_unstable();
+    found
+}
+
+/// The other members of the process group `pid` leads, sorted. Empty when `pid` is not a group
+/// leader, since it then shares its group with whatever started it.
+pub fn process_group_members(pid: u32) -> Vec<u32> {
+    match read_stat_ids(pid) {
+        Some((_, pgrp)) if pgrp == pid => {}
+        _ => return Vec::new(),
+    }
+
+    let mut members: Vec<u32> = fs::read_dir("/proc")
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|entry| entry.file_name().to_string_lossy().parse::<u32>().ok())
+                .filter(|other| *other != pid && read_stat_ids(*other).map(|(_, pgrp)| pgrp) == Some(pid))
+                .collect()
+        })
+        .unwrap_or_default();
+    members.sort_unstable();
+    members
+}
+
+/// Warn about group members CRIU would leave behind: `-t <pid>` dumps only the process tree,
+/// so members that were reparented (e.g. double-forked daemons) are not part of the checkpoint
+pub fn check_process_group(pid: u32) -> Vec<u32> {
+    let members = process_group_members(pid);
+    let outside: Vec<u32> = members.iter().copied().filter(|member| !is_descendant(*member, pid)).collect();
+    if !outside.is_empty() {
+        crate::output::Output::warning(&format!(
+            "Process group {} has member(s) {:?} outside its process tree; they will not be checkpointed",
+            pid, outside
+        ));
+    } else if !members.is_empty() {
+        info!("Process {} leads a group of {} other process(es): {:?}", pid, members.len(), members);
+    }
+    members
+}
+
+/// Record the size of the process tree being dumped
+pub fn save_process_tree(pid: u32, checkpoint_dir: &Path) {
+    check_process_group(pid);
+    let children = descendants(pid);
+    if children.is_empty() {
+        return;
+    }
+    info!("Checkpointing process {} with {} descendant(s): {:?}", pid, children.len(), children);
+    if let Err(e) = fs::write(checkpoint_dir.join(PROCESS_TREE_FILE), children.len().to_string()) {
+        warn!("Failed to record process tree for checkpoint: {}", e);
+    }
+}
+
+/// Compare a restored process tree with the one recorded at checkpoint time
+pub fn verify_restored_tree(pid: u32, checkpoint_dir: &Path) {
+    let expected: usize = match fs::read_to_string(checkpoint_dir.join(PROCESS_TREE_FILE)) {
+        Ok(content) => content.trim().parse().unwrap_or(0),
+        Err(_) => return,
+    };
+    let restored = descendants(pid).len();
+    if restored < expected {
+        warn!("Restored process {} has {} of {} descendant(s) from the checkpoint; some may have exited",
+              pid, restored, expected);
+    } else {
+        info!("Restored process {} with {} descendant(s)", pid, restored);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TcpSocketInfo {
+    pub fd: i32,
+    pub inode: u64,
+    pub local_address: String,
+    pub remote_address: String,
+    pub state: String,
+}
+
+impl TcpSocketInfo {
+    pub fn is_listening(&self) -> bool {
+        self.state == "LISTEN"
+    }
+}
+
+/// Find the TCP sockets held by a process by matching its socket fds against /proc/<pid>/net/tcp{,6}
+pub fn detect_tcp_sockets(pid: u32) -> Result<Vec<TcpSocketInfo>> {
+    let fd_dir = format!("/proc/{}/fd", pid);
+    let fd_path = Path::new(&fd_dir);
+
+    if !fd_path.exists() {
+        return Err(CriuCliError::ProcessError(format!(
+            "Process {} not found or no access to /proc/{}/fd", pid, pid
+        )));
+    }
+
+    let entries = fs::read_dir(fd_path).map_err(|e| {
+        CriuCliError::ProcessError(format!("Failed to read /proc/{}/fd: {}", pid, e))
+    })?;
+
+    // Collect socket inodes, links look like "socket:[12345]"
+    let mut socket_fds = Vec::new();
+    for entry in entries.flatten() {
+        let fd_num = match entry.file_name().to_string_lossy().parse::<i32>() {
+            Ok(fd) => fd,
+            Err(_) => continue,
+        };
+        if let Ok(link_target) = fs::read_link(entry.path()) {
+            let target_str = link_target.to_string_lossy();
+            if let Some(inode) = target_str
+                .strip_prefix("socket:[")
+                .and_then(|rest| rest.strip_suffix(']'))
+                .and_then(|inode| inode.parse::<u64>().ok())
+            {
+                socket_fds.push((fd_num, inode));
+            }
+        }
+    }
+
+    if socket_fds.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let inodes: HashSet<u64> = socket_fds.iter().map(|(_, inode)| *inode).collect();
+    let mut sockets = Vec::new();
+
+    // Read the tables from the process's own network namespace
+    for table in ["tcp", "tcp6"] {
+        let content = match fs::read_to_string(format!("/proc/{}/net/{}", pid, table)) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+
+        for line in content.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 {
+                continue;
+            }
+            let inode = match fields[9].parse::<u64>() {
+                Ok(inode) if inodes.contains(&inode) => inode,
+                _ => continue,
+            };
+
+            for (fd, _) in socket_fds.iter().filter(|(_, i)| *i == inode) {
+                sockets.push(TcpSocketInfo {
+                    fd: *fd,
+                    inode,
+                    local_address: fields[1].to_string(),
+                    remote_address: fields[2].to_string(),
+                    state: tcp_state_name(fields[3]).to_string(),
+                });
+            }
+        }
+    }
+
+    sockets.sort_by_key(|s| s.fd);
+    Ok(sockets)
+}
+
+fn tcp_state_name(hex: &str) -> &'static str {
+    match hex {
+        "01" => "ESTABLISHED",
+        "02" => "SYN_SENT",
+        "03" => "SYN_RECV",
+        "04" => "FIN_WAIT1",
+        "05" => "FIN_WAIT2",
+        "06" => "TIME_WAIT",
+        "07" => "CLOSE",
+        "08" => "CLOSE_WAIT",
+        "09" => "LAST_ACK",
+        "0A" => "LISTEN",
+        "0B" => "CLOSING",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Whether a process holds TCP sockets and so must be dumped with `--tcp-established`; for
+/// background dumps, which should not warn on every pass
+pub fn holds_tcp_sockets(pid: u32) -> bool {
+    detect_tcp_sockets(pid).is_ok_and(|sockets| !sockets.is_empty())
+}
+
+/// Warn about TCP sockets before a checkpoint; returns whether the dump needs `--tcp-established`
+pub fn check_process_socket_compatibility(pid: u32) -> bool {
+    match detect_tcp_sockets(pid) {
+        Ok(sockets) if sockets.is_empty() => {
+            info!("Process {} holds no TCP sockets", pid);
+            false
+        }
+        Ok(sockets) => {
+            warn!("Process {} holds {} TCP socket(s)", pid, sockets.len());
+            for socket in &sockets {
+                warn!("  fd {}: {} -> {} ({})", socket.fd, socket.local_address, socket.remote_address, socket.state);
+            }
+            crate::output::Output::warning(&tcp_socket_warning(pid, &sockets));
+            true
+        }
+        Err(e) => {
+            warn!("Failed to analyze sockets for process {}: {}", pid, e);
+            false
+        }
+    }
+}
+
+/// What the user is told before a process holding `sockets` is checkpointed
+fn tcp_socket_warning(pid: u32, sockets: &[TcpSocketInfo]) -> String {
+    let established = sockets.iter().filter(|s| !s.is_listening()).count();
+    format!(
+        "Process {} holds {} TCP socket(s) ({} connected); checkpointing with --tcp-established. \
+         Peers may see the connections reset if the restore happens elsewhere.",
+        pid, sockets.len(), established
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(processes: &[u32], activity: u64) -> TreeActivity {
+        TreeActivity { processes: processes.to_vec(), activity }
+    }
+
+    #[test]
+    fn process_table_walks_the_whole_subtree() {
+        let children = HashMap::from([(1, vec![10, 20]), (10, vec![12, 11]), (11, vec![13]), (20, vec![21])]);
+        let table = ProcessTable { children };
+        assert_eq!(table.descendants(10), vec![11, 12, 13]);
+        assert_eq!(table.descendants(13), Vec::<u32>::new());
+        assert_eq!(table.descendants(99), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn unchanged_tree_within_threshold_is_idle() {
+        assert!(tree(&[10, 11], 105).idle_since(&tree(&[10, 11], 100), 5));
+        assert!(!tree(&[10, 11], 106).idle_since(&tree(&[10, 11], 100), 5));
+    }
+
+    #[test]
+    fn exited_descendant_counts_as_activity() {
+        // The child's ticks leave the sum, so the total drops
+        assert!(!tree(&[10], 40).idle_since(&tree(&[10, 11], 100), 5));
+    }
+
+    #[test]
+    fn new_descendant_counts_as_activity() {
+        assert!(!tree(&[10, 12], 100).idle_since(&tree(&[10], 100), 5));
+    }
+
+    #[test]
+    fn own_tree_activity_is_readable() {
+        let own = tree_activity(std::process::id()).unwrap();
+        assert_eq!(own.processes[0], std::process::id());
+    }
+
+    #[test]
+    fn tcp_sockets_held_by_a_process_are_detected_and_warned_about() {
+        use std::os::fd::AsRawFd;
+        use std::os::unix::process::CommandExt;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        let (listener_fd, accepted_fd) = (listener.as_raw_fd(), accepted.as_raw_fd());
+        // The child gets the listening socket as fd 3 and the connected one as fd 4
+        let mut holder = unsafe {
+            std::process::Command::new("sleep")
+                .arg("30")
+                .pre_exec(move || {
+                    // Move both out of the way first, either may already sit on 3 or 4
+                    let moved = |fd| nix::fcntl::fcntl(fd, nix::fcntl::FcntlArg::F_DUPFD_CLOEXEC(100));
+                    let (listener_fd, accepted_fd) = (moved(listener_fd)?, moved(accepted_fd)?);
+                    nix::unistd::dup2(listener_fd, 3)?;
+                    nix::unistd::dup2(accepted_fd, 4)?;
+                    Ok(())
+                })
+                .spawn()
+                .unwrap()
+        };
+        let mut plain = std::process::Command::new("sleep").arg("30").spawn().unwrap();
+
+        let sockets = detect_tcp_sockets(holder.id()).unwrap();
+        let states: Vec<(i32, &str)> = sockets.iter().map(|s| (s.fd, s.state.as_str())).collect();
+        assert_eq!(states, [(3, "LISTEN"), (4, "ESTABLISHED")]);
+        assert!(holds_tcp_sockets(holder.id()));
+        assert!(check_process_socket_compatibility(holder.id()));
+        let warning = tcp_socket_warning(holder.id(), &sockets);
+        assert!(warning.contains("holds 2 TCP socket(s) (1 connected)"), "{}", warning);
+        assert!(warning.contains("--tcp-established"), "{}", warning);
+
+        assert!(!holds_tcp_sockets(plain.id()));
+        assert!(!check_process_socket_compatibility(plain.id()));
+
+        for child in [&mut holder, &mut plain] {
+            child.kill().unwrap();
+            child.wait().unwrap();
+        }
+    }
+}
@@ -1,13 +1,32 @@
-use crate::types::{CriuCliError, Result};
-use crate::tty_utils::{detect_tty_environment, generate_criu_tty_args, print_tty_analysis};
+use crate::checkpoint_engine::{CheckpointEngine, CriuEngine, DumpRequest, RestoreRequest};
+use crate::criu_compat::{
+    check_process_socket_compatibility, check_restore_ids, save_original_ids, save_process_tree, verify_restored_tree,
+    TCP_ESTABLISHED_MARKER,
+};
+use crate::types::{CheckpointHooks, CheckpointStorage, CriuCliError, RestoreOptions, RestoreTty, Result};
+use crate::tty_utils::{
+    detect_tty_environment, forward_pty_output, generate_criu_restore_tty_args, generate_criu_tty_args,
+    load_tty_environment, open_restore_pty, print_tty_analysis, save_tty_environment,
+};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::Arc;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+/// How long a pre/post checkpoint hook may run before it is killed
+const CHECKPOINT_HOOK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
 pub struct CriuManager {
-    criu_path: PathBuf,
+    engine: Arc<dyn CheckpointEngine>,
     checkpoints_dir: PathBuf,
+    storage: CheckpointStorage,
+}
+
+impl Default for CriuManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CriuManager {
@@ -16,7 +35,13 @@ impl CriuManager {
     }
 
     pub fn new_with_path<P: AsRef<Path>>(criu_path: P) -> Self {
-        let criu_path = PathBuf::from(criu_path.as_ref());
+        let engine = CriuEngine::new(criu_path);
+        info!("Using CRIU binary at: {:?}", engine.criu_path());
+        Self::new_with_engine(Arc::new(engine))
+    }
+
+    /// Create a manager driving the given checkpoint engine
+    pub fn new_with_engine(engine: Arc<dyn CheckpointEngine>) -> Self {
         let checkpoints_dir = PathBuf::from("instances"); // Use instances directory
 
         // Create instances directory if it doesn't exist
@@ -26,42 +51,42 @@ impl CriuManager {
             }
         }
 
-        // Convert relative path to absolute path to avoid working directory issues
-        let criu_path = if criu_path.is_relative() {
-            match std::env::current_dir() {
-                Ok(current_dir) => current_dir.join(criu_path),
-                Err(e) => {
-                    warn!("Failed to get current directory: {}, using relative path", e);
-                    criu_path
-                }
-            }
-        } else {
-            criu_path
-        };
-
-        info!("Using CRIU binary at: {:?}", criu_path);
-
         Self {
-            criu_path,
+            engine,
             checkpoints_dir,
+            storage: CheckpointStorage::default(),
         }
     }
 
+    /// Deduplicate and/or encrypt new checkpoints; the key also opens sealed ones on restore
+    pub fn set_storage(&mut self, storage: CheckpointStorage) {
+        self.storage = storage;
+    }
+
+    /// Engine dumps and restores run through
+    pub fn engine(&self) -> &Arc<dyn CheckpointEngine> {
+        &self.engine
+    }
+
     pub async fn create_checkpoint(
         &self,
         pid: u32,
         checkpoint_name: &str,
         instance_id: &Uuid,
         output_history: Option<Vec<String>>,
+        hooks: &CheckpointHooks,
     ) -> Result<PathBuf> {
         // Create instance-specific directory structure using short ID
         let short_id = instance_id.to_string()[..8].to_string();
         let instance_dir = self.checkpoints_dir.join(format!("instance_{}", short_id));
         let checkpoint_dir = instance_dir.join("checkpoints").join(checkpoint_name);
 
-        self.create_checkpoint_in_dir(pid, checkpoint_name, &checkpoint_dir, instance_id, output_history).await
+        self.create_checkpoint_in_dir(pid, checkpoint_name, &checkpoint_dir, instance_id, output_history, hooks, false, true).await
     }
 
+    /// Dump `pid` into `checkpoint_dir`. With `leave_running` false CRIU kills the process
+    /// tree once the dump succeeds (it is still resumed if the dump fails).
+    #[allow(clippy::too_many_arguments)]
     pub async fn create_checkpoint_in_dir(
         &self,
         pid: u32,
@@ -69,19 +94,77 @@ impl CriuManager {
         checkpoint_dir: &PathBuf,
         instance_id: &Uuid,
         output_history: Option<Vec<String>>,
+        hooks: &CheckpointHooks,
+        track_mem: bool,
+        leave_running: bool,
     ) -> Result<PathBuf> {
         // Create checkpoint directory
-        std::fs::create_dir_all(&checkpoint_dir).map_err(|e| {
-            error!("Failed to create checkpoint directory: {}", e);
-            CriuCliError::IoError(e)
+        let created_dir = !checkpoint_dir.exists();
+        std::fs::create_dir_all(checkpoint_dir).map_err(|e| {
+            error!("Failed to create checkpoint directory {:?}: {}", checkpoint_dir, e);
+            CriuCliError::IoError(std::io::Error::new(e.kind(), format!("{}: {}", checkpoint_dir.display(), e)))
         })?;
 
         info!("Creating checkpoint for PID {} in {:?}", pid, checkpoint_dir);
 
+        // Let the application quiesce first; a failing pre-hook aborts the checkpoint
+        if let Some(ref cmd) = hooks.pre_checkpoint_cmd {
+            if let Err(e) = self.run_checkpoint_hook("pre-checkpoint", cmd, pid, instance_id, checkpoint_dir).await {
+                // Nothing was dumped; don't leave a checkpoint directory that lists as a checkpoint
+                if created_dir {
+                    if let Err(remove_err) = std::fs::remove_dir_all(checkpoint_dir) {
+                        warn!("Failed to remove checkpoint directory {:?}: {}", checkpoint_dir, remove_err);
+                    }
+                }
+                return Err(e);
+            }
+        }
+
         // Step 1: Pause the process before checkpoint
         info!("Pausing process {} before checkpoint", pid);
         self.pause_process(pid)?;
 
+        // From here on every outcome resumes the process (unless CRIU ended it) and runs the post-hook
+        let result = match self.dump_paused(pid, checkpoint_dir, output_history, track_mem, leave_running) {
+            Ok(cmdline) => {
+                // Step 2: Resume the original process after successful checkpoint
+                if leave_running {
+                    info!("Resuming original process {} after checkpoint", pid);
+                    if let Err(e) = self.resume_process(pid) {
+                        warn!("Failed to resume process {} after checkpoint: {}", pid, e);
+                        // Don't fail the checkpoint operation, just warn
+                    }
+                } else {
+                    info!("Process {} was terminated by CRIU after the dump", pid);
+                }
+                self.finish_checkpoint(checkpoint_dir, instance_id, cmdline.as_deref())
+            }
+            Err(e) => {
+                // Resume the process even if checkpoint failed
+                if let Err(resume_err) = self.resume_process(pid) {
+                    error!("Failed to resume process {} after checkpoint failure: {}", pid, resume_err);
+                }
+                Err(e)
+            }
+        };
+
+        self.run_post_checkpoint_hook(hooks, pid, instance_id, checkpoint_dir).await;
+
+        result?;
+        info!("Checkpoint created successfully: {}", checkpoint_name);
+        Ok(checkpoint_dir.clone())
+    }
+
+    /// Everything done while the process is paused, up to and including the CRIU dump;
+    /// returns the command line recorded with the checkpoint
+    fn dump_paused(
+        &self,
+        pid: u32,
+        checkpoint_dir: &Path,
+        output_history: Option<Vec<String>>,
+        track_mem: bool,
+        leave_running: bool,
+    ) -> Result<Option<String>> {
         // Analyze TTY environment before creating checkpoint
         let tty_env = match detect_tty_environment(pid) {
             Ok(env) => {
@@ -118,72 +201,160 @@ impl CriuManager {
         }
 
         // Backup output files that might change after checkpoint
-        self.backup_output_files(pid, &checkpoint_dir)?;
+        self.backup_output_files(pid, checkpoint_dir)?;
 
-        // Build CRIU dump command with TTY arguments
-        let mut cmd = Command::new(&self.criu_path);
-        cmd.arg("dump")
-            .arg("--tree")
-            .arg(pid.to_string())
-            .arg("-D")
-            .arg(&checkpoint_dir)
-            .arg("-v4")
-            .arg("--leave-running")
-            .arg("--shell-job");
+        // Record the command line so a restore can tell a stale copy of this process from an unrelated PID holder
+        let cmdline = Self::read_cmdline(pid);
+        if let Some(ref cmdline) = cmdline {
+            if let Err(e) = std::fs::write(checkpoint_dir.join("original_cmdline"), cmdline) {
+                warn!("Failed to save command line for PID {}: {}", pid, e);
+            }
+        }
+        save_original_ids(pid, checkpoint_dir);
+        // CRIU dumps every descendant of the process along with it
+        save_process_tree(pid, checkpoint_dir);
+
+        // Build CRIU dump request with TTY arguments
+        let mut request = DumpRequest {
+            pid,
+            images_dir: checkpoint_dir.to_path_buf(),
+            leave_running,
+            shell_job: true,
+            verbose: true,
+            extra_args: Vec::new(),
+            background: false,
+            tcp_established: false,
+        };
+
+        // Reset dirty-memory tracking so later dumps can be taken incrementally on top of this one
+        if track_mem {
+            request.extra_args.push("--track-mem".to_string());
+        }
 
         // Add TTY-specific arguments if needed
         if let Some(ref env) = tty_env {
             let tty_args = generate_criu_tty_args(env);
             if !tty_args.is_empty() {
                 info!("Adding TTY arguments to CRIU dump: {:?}", tty_args);
-                for arg in tty_args {
-                    cmd.arg(arg);
-                }
+                request.extra_args.extend(tty_args);
             }
+            save_tty_environment(env, checkpoint_dir);
         }
 
-        // Execute CRIU dump command
-        let output = cmd.output().map_err(|e| {
+        // Open TCP connections need --tcp-established on both dump and restore
+        request.tcp_established = check_process_socket_compatibility(pid);
+
+        // Execute CRIU dump
+        let output = self.engine.dump(&request).map_err(|e| {
             error!("Failed to execute CRIU dump: {}", e);
-            CriuCliError::CriuError(format!("Failed to execute CRIU: {}", e))
+            e
         })?;
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.success {
+            let stderr = &output.stderr;
             error!("CRIU dump failed: {}", stderr);
 
-            // Resume the process even if checkpoint failed
-            if let Err(resume_err) = self.resume_process(pid) {
-                error!("Failed to resume process {} after checkpoint failure: {}", pid, resume_err);
-            }
-
             return Err(CriuCliError::CriuError(format!(
                 "CRIU dump failed: {}",
                 stderr
             )));
         }
 
-        // Step 2: Resume the original process after successful checkpoint
-        info!("Resuming original process {} after checkpoint", pid);
-        if let Err(e) = self.resume_process(pid) {
-            warn!("Failed to resume process {} after checkpoint: {}", pid, e);
-            // Don't fail the checkpoint operation, just warn
+        Ok(cmdline)
+    }
+
+    /// Record statistics and the descriptor of a finished dump, then seal and deduplicate it
+    fn finish_checkpoint(&self, checkpoint_dir: &Path, instance_id: &Uuid, cmdline: Option<&str>) -> Result<()> {
+        // Read CRIU's statistics before sealing would fold stats-dump into the archive
+        crate::criu_stats::record_dump(*instance_id, checkpoint_dir);
+
+        crate::checkpoint_descriptor::write_descriptor(checkpoint_dir, *instance_id, cmdline, self.engine.version());
+        crate::checkpoint_crypto::seal_checkpoint(checkpoint_dir, *instance_id, self.storage.key).map_err(|e| {
+            CriuCliError::CriuError(format!("Checkpoint was dumped but could not be encrypted: {}", e))
+        })?;
+
+        crate::checkpoint_dedup::dedup_if_enabled(checkpoint_dir, self.storage.dedup);
+        Ok(())
+    }
+
+    /// Run a checkpoint hook through `sh -c`, failing on non-zero exit or timeout
+    async fn run_checkpoint_hook(
+        &self,
+        stage: &str,
+        cmd: &str,
+        pid: u32,
+        instance_id: &Uuid,
+        checkpoint_dir: &Path,
+    ) -> Result<()> {
+        info!("Running {} hook for PID {}: {}", stage, pid, cmd);
+
+        let child = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .env("NHI_INSTANCE_ID", instance_id.to_string())
+            .env("NHI_PID", pid.to_string())
+            .env("NHI_CHECKPOINT_DIR", checkpoint_dir)
+            .stdin(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .output();
+
+        let output = match tokio::time::timeout(CHECKPOINT_HOOK_TIMEOUT, child).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(CriuCliError::ProcessError(format!(
+                    "Failed to run {} hook: no POSIX shell (sh) found in PATH", stage
+                )));
+            }
+            Ok(Err(e)) => {
+                return Err(CriuCliError::ProcessError(format!(
+                    "Failed to run {} hook: {}", stage, e
+                )));
+            }
+            Err(_) => {
+                return Err(CriuCliError::ProcessError(format!(
+                    "{} hook timed out after {}s", stage, CHECKPOINT_HOOK_TIMEOUT.as_secs()
+                )));
+            }
+        };
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(CriuCliError::ProcessError(format!(
+                "{} hook failed ({}): {}", stage, output.status, stderr.trim()
+            )));
         }
 
-        info!("Checkpoint created successfully: {}", checkpoint_name);
-        Ok(checkpoint_dir.clone())
+        debug!("{} hook output: {}", stage, String::from_utf8_lossy(&output.stdout));
+        Ok(())
+    }
+
+    /// Run the post-checkpoint hook if configured; failures only warn since the checkpoint is already taken
+    async fn run_post_checkpoint_hook(&self, hooks: &CheckpointHooks, pid: u32, instance_id: &Uuid, checkpoint_dir: &Path) {
+        if let Some(ref cmd) = hooks.post_checkpoint_cmd {
+            if let Err(e) = self.run_checkpoint_hook("post-checkpoint", cmd, pid, instance_id, checkpoint_dir).await {
+                warn!("{}", e);
+            }
+        }
     }
 
     pub async fn restore_checkpoint(
         &self,
         checkpoint_name: &str,
         instance_id: Option<&Uuid>,
+    ) -> Result<(u32, Option<Vec<String>>)> {
+        self.restore_checkpoint_with_options(checkpoint_name, instance_id, &RestoreOptions::default()).await
+    }
+
+    pub async fn restore_checkpoint_with_options(
+        &self,
+        checkpoint_name: &str,
+        instance_id: Option<&Uuid>,
+        options: &RestoreOptions,
     ) -> Result<(u32, Option<Vec<String>>)> {
         // Try to find checkpoint in instance-specific directory first, then search globally
         let checkpoint_dir = if let Some(id) = instance_id {
             let short_id = id.to_string()[..8].to_string();
-            let instance_dir = self.checkpoints_dir.join(format!("instance_{}", short_id));
-            let expected_checkpoint_path = instance_dir.join("checkpoints").join(checkpoint_name);
+            let expected_checkpoint_path = self.instance_checkpoints_dir(id).join(checkpoint_name);
 
             if expected_checkpoint_path.exists() {
                 info!("Found checkpoint '{}' in expected instance directory: instance_{}", checkpoint_name, short_id);
@@ -206,6 +377,9 @@ impl CriuManager {
                         }
                         found_path
                     }
+                    Err(CriuCliError::CheckpointNotFound(_)) => {
+                        return Err(self.checkpoint_not_found(checkpoint_name, id));
+                    }
                     Err(e) => return Err(e)
                 }
             }
@@ -218,6 +392,9 @@ impl CriuManager {
             return Err(CriuCliError::CheckpointNotFound(checkpoint_name.to_string()));
         }
 
+        // Deduplicated checkpoints may need files relinked from the blob store
+        crate::checkpoint_dedup::reassemble_checkpoint(&checkpoint_dir)?;
+
         // Convert to absolute path for CRIU
         let checkpoint_dir = checkpoint_dir.canonicalize().map_err(|e| {
             error!("Failed to get absolute path for checkpoint directory: {}", e);
@@ -226,9 +403,36 @@ impl CriuManager {
 
         info!("Restoring checkpoint from {:?}", checkpoint_dir);
 
+        // Reject checkpoints written with a layout this build cannot read before CRIU sees them
+        crate::checkpoint_descriptor::validate_for_restore(&checkpoint_dir)?;
+
+        // Encrypted images are decrypted next to the archive and removed again once the restore returns
+        let _unsealed = crate::checkpoint_crypto::unseal_checkpoint(&checkpoint_dir, self.storage.key)?;
+
+        // Hand CRIU only a complete image set; a partial one fails deep inside restore
+        let missing = crate::checkpoint_descriptor::missing_images(&checkpoint_dir);
+        if !missing.is_empty() {
+            return Err(self.corrupt_checkpoint(checkpoint_name, &checkpoint_dir, &missing));
+        }
+
+        // Refuse to restore another user's checkpoint without an explicit ID map
+        let id_map_args = check_restore_ids(&checkpoint_dir, options)?;
+
         // Check for PID conflicts before restoring
         if let Some(original_pid) = self.get_original_pid_from_checkpoint(&checkpoint_dir)? {
             if self.is_pid_in_use(original_pid) {
+                if !self.pid_matches_checkpoint(original_pid, &checkpoint_dir) {
+                    // CRIU restores the original PID; never kill an unrelated process to get it back
+                    return Err(CriuCliError::CriuError(format!(
+                        "Cannot restore: PID {} is in use by an unrelated process ({}). \
+                         The process was not terminated; wait for the PID to be released or stop it manually.",
+                        original_pid,
+                        Self::read_cmdline(original_pid)
+                            .map(|c| c.replace('\0', " ").trim().to_string())
+                            .unwrap_or_else(|| "unknown command".to_string())
+                    )));
+                }
+
                 warn!("PID {} is already in use. The original process is still running.", original_pid);
                 warn!("Will terminate the original process to restore the checkpoint.");
 
@@ -292,42 +496,87 @@ impl CriuManager {
             }
         };
 
-        // Execute CRIU restore command
-        let mut cmd = Command::new(&self.criu_path);
-        cmd.arg("restore")
-            .arg("-D")
-            .arg(&checkpoint_dir)
-            .arg("-v4")
-            .arg("--restore-detached")
-            .arg("--shell-job")
-            .arg("--pidfile")
-            .arg(checkpoint_dir.join("restored.pid"))
-            .arg("--log-file")
-            .arg(checkpoint_dir.join("restore.log"));
-
+        // Execute CRIU restore
+        let mut request = RestoreRequest {
+            images_dir: checkpoint_dir.clone(),
+            detached: true,
+            shell_job: true,
+            verbose: true,
+            pidfile: Some(checkpoint_dir.join("restored.pid")),
+            log_file: Some(checkpoint_dir.join("restore.log")),
+            ..Default::default()
+        };
+        if checkpoint_dir.join(TCP_ESTABLISHED_MARKER).exists() {
+            info!("Checkpoint holds TCP connections, restoring with --tcp-established");
+        }
+        if !id_map_args.is_empty() {
+            info!("Adding ID mapping arguments to CRIU restore: {:?}", id_map_args);
+            request.extra_args.extend(id_map_args);
+        }
 
+        // Reparent the dumped terminal fds; the pty master must stay open for the restored process's lifetime
+        let mut pty_master = None;
+        if options.tty != RestoreTty::Inherit {
+            match load_tty_environment(&checkpoint_dir) {
+                Some(tty_env) => {
+                    request.stdin = Some(match options.tty {
+                        RestoreTty::Pty => {
+                            let (master, slave_path) = open_restore_pty()?;
+                            pty_master = Some(master);
+                            slave_path
+                        }
+                        _ => PathBuf::from("/dev/null"),
+                    });
+                    let tty_args = generate_criu_restore_tty_args(&tty_env, options.tty);
+                    info!("Restoring with TTY target {} ({:?}): {:?}", options.tty, request.stdin, tty_args);
+                    request.extra_args.extend(tty_args);
+                }
+                None => warn!("Checkpoint has no recorded TTY fds, ignoring --tty {}", options.tty),
+            }
+        }
 
-        let output = cmd.output().map_err(|e| {
+        let output = self.engine.restore(&request).map_err(|e| {
             error!("Failed to execute CRIU restore: {}", e);
-            CriuCliError::CriuError(format!("Failed to execute CRIU: {}", e))
+            e
         })?;
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            error!("CRIU restore failed: {}", stderr);
+        if !output.success {
+            error!("CRIU restore failed: {}", output.stderr);
             return Err(CriuCliError::CriuError(format!(
                 "CRIU restore failed: {}",
-                stderr
+                output.stderr
             )));
         }
 
-        // Parse the output to get the restored PID
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        debug!("CRIU restore output: {}", stdout);
+        debug!("CRIU restore output: {}", output.stdout);
+
+        // Without an explicit instance, attribute the restore to the one that took the checkpoint
+        let stats_instance = instance_id.copied().or_else(|| {
+            crate::checkpoint_descriptor::CheckpointDescriptor::load(&checkpoint_dir)
+                .ok()
+                .flatten()
+                .map(|descriptor| descriptor.instance_id)
+        });
+        if let Some(stats_instance) = stats_instance {
+            crate::criu_stats::record_restore(stats_instance, &checkpoint_dir);
+        }
 
         // Get the restored PID
         let restored_pid = self.get_restored_pid(&checkpoint_dir).await?;
 
+        if let Some(master) = pty_master {
+            let log_path = match instance_id {
+                Some(id) => self
+                    .checkpoints_dir
+                    .join(format!("instance_{}", &id.to_string()[..8]))
+                    .join("output")
+                    .join("process_output.log"),
+                None => checkpoint_dir.join("pty_output.log"),
+            };
+            info!("Forwarding pseudo-terminal output of restored process {} to {}", restored_pid, log_path.display());
+            forward_pty_output(master, log_path);
+        }
+
         // Resume the restored process (CRIU restores processes in stopped state)
         info!("Resuming restored process {} after CRIU restore", restored_pid);
         if let Err(e) = self.resume_process(restored_pid) {
@@ -337,6 +586,8 @@ impl CriuManager {
             info!("Successfully resumed restored process {}", restored_pid);
         }
 
+        verify_restored_tree(restored_pid, &checkpoint_dir);
+
         info!("Checkpoint restored successfully with PID: {}", restored_pid);
         Ok((restored_pid, output_history))
     }
@@ -431,6 +682,93 @@ impl CriuManager {
         Ok(checkpoints)
     }
 
+    /// Directory holding an instance's checkpoints
+    fn instance_checkpoints_dir(&self, instance_id: &Uuid) -> PathBuf {
+        self.checkpoints_dir
+            .join(format!("instance_{}", &instance_id.to_string()[..8]))
+            .join("checkpoints")
+    }
+
+    /// Whether the instance itself has a checkpoint of that name
+    pub fn checkpoint_exists_for_instance(&self, checkpoint_name: &str, instance_id: &Uuid) -> bool {
+        self.instance_checkpoints_dir(instance_id).join(checkpoint_name).is_dir()
+    }
+
+    /// Names of the checkpoints stored for an instance, sorted
+    pub fn list_checkpoints_for_instance(&self, instance_id: &Uuid) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(self.instance_checkpoints_dir(instance_id))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|entry| entry.file_type().map(|t| t.is_dir()).unwrap_or(false))
+                    .map(|entry| entry.file_name().to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    /// Directory of one of an instance's checkpoints
+    pub fn instance_checkpoint_dir(&self, instance_id: &Uuid, checkpoint_name: &str) -> Result<PathBuf> {
+        let checkpoint_dir = self
+            .checkpoints_dir
+            .join(format!("instance_{}", &instance_id.to_string()[..8]))
+            .join("checkpoints")
+            .join(checkpoint_name);
+        if checkpoint_dir.is_dir() {
+            Ok(checkpoint_dir)
+        } else {
+            Err(self.checkpoint_not_found(checkpoint_name, instance_id))
+        }
+    }
+
+    /// `CheckpointNotFound` naming the checkpoints the instance does have
+    pub fn checkpoint_not_found(&self, checkpoint_name: &str, instance_id: &Uuid) -> CriuCliError {
+        let available = self.list_checkpoints_for_instance(instance_id);
+        let hint = if available.is_empty() {
+            "instance has no checkpoints".to_string()
+        } else {
+            format!("available: {}", available.join(", "))
+        };
+        CriuCliError::CheckpointNotFound(format!("{} ({})", checkpoint_name, hint))
+    }
+
+    /// Error for an incomplete checkpoint, naming the newest complete sibling to try instead
+    pub fn corrupt_checkpoint(&self, checkpoint_name: &str, checkpoint_dir: &Path, missing: &[String]) -> CriuCliError {
+        let mut siblings: Vec<(std::time::SystemTime, String)> = checkpoint_dir
+            .parent()
+            .and_then(|parent| std::fs::read_dir(parent).ok())
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|entry| entry.file_type().map(|t| t.is_dir()).unwrap_or(false))
+                    .filter(|entry| entry.file_name().to_string_lossy() != checkpoint_name)
+                    .filter(|entry| {
+                        let path = entry.path();
+                        crate::checkpoint_crypto::is_sealed(&path)
+                            || crate::checkpoint_descriptor::missing_images(&path).is_empty()
+                    })
+                    .filter_map(|entry| {
+                        let modified = entry.metadata().and_then(|m| m.modified()).ok()?;
+                        Some((modified, entry.file_name().to_string_lossy().to_string()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        siblings.sort();
+        let hint = match siblings.last() {
+            Some((_, name)) => format!("try checkpoint '{}' instead", name),
+            None => "no complete checkpoint is available for this instance".to_string(),
+        };
+        CriuCliError::CorruptCheckpoint(format!(
+            "{} is incomplete, missing {} ({})",
+            checkpoint_name,
+            missing.join(", "),
+            hint
+        ))
+    }
+
     fn find_checkpoint_in_any_instance(&self, checkpoint_name: &str) -> Result<PathBuf> {
         if !self.checkpoints_dir.exists() {
             return Err(CriuCliError::CheckpointNotFound(checkpoint_name.to_string()));
@@ -516,6 +854,39 @@ impl CriuManager {
         std::path::Path::new(&proc_path).exists()
     }
 
+    /// Raw NUL-separated command line of a running process
+    fn read_cmdline(pid: u32) -> Option<String> {
+        std::fs::read(format!("/proc/{}/cmdline", pid))
+            .ok()
+            .filter(|bytes| !bytes.is_empty())
+            .map(|bytes| String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    /// Whether the process holding `pid` is the checkpointed program (same command line as at dump time)
+    fn pid_matches_checkpoint(&self, pid: u32, checkpoint_dir: &Path) -> bool {
+        let expected = match std::fs::read_to_string(checkpoint_dir.join("original_cmdline")) {
+            Ok(cmdline) => cmdline,
+            Err(_) => {
+                warn!("Checkpoint has no recorded command line, refusing to kill PID {}", pid);
+                return false;
+            }
+        };
+
+        match Self::read_cmdline(pid) {
+            Some(actual) if actual == expected => true,
+            Some(actual) => {
+                warn!(
+                    "PID {} runs '{}', checkpoint was taken from '{}'",
+                    pid,
+                    actual.replace('\0', " ").trim(),
+                    expected.replace('\0', " ").trim()
+                );
+                false
+            }
+            None => false,
+        }
+    }
+
     fn kill_conflicting_process(&self, pid: u32) -> Result<()> {
         info!("Attempting to kill conflicting process with PID {}", pid);
 
@@ -698,31 +1069,276 @@ impl CriuManager {
         info!("Process {} resumed successfully", pid);
         Ok(())
     }
+}
 
-    async fn find_newest_process_by_name(&self, process_name: &str) -> Option<u32> {
-        info!("Looking for process with name: {}", process_name);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint_engine::{EngineOutput, MockEngine};
+    use crate::types::IdMap;
 
-        let output = Command::new("ps")
-            .arg("aux")
-            .output()
-            .ok()?;
-
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut candidates = Vec::new();
-
-        for line in stdout.lines() {
-            if line.contains(process_name) && !line.contains("ps aux") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    if let Ok(pid) = parts[1].parse::<u32>() {
-                        info!("Found candidate process: PID {} - {}", pid, line);
-                        candidates.push(pid);
-                    }
-                }
+    /// Dumps succeed, noting whether the pre-checkpoint hook's marker was already there
+    struct MarkerEngine {
+        marker_seen: std::sync::Mutex<Option<bool>>,
+    }
+
+    impl CheckpointEngine for MarkerEngine {
+        fn name(&self) -> &str {
+            "marker"
+        }
+
+        fn check(&self) -> Result<EngineOutput> {
+            MockEngine::new().check()
+        }
+
+        fn dump(&self, request: &DumpRequest) -> Result<EngineOutput> {
+            *self.marker_seen.lock().unwrap() = Some(request.images_dir.join("pre-hook-ran").exists());
+            MockEngine::new().dump(request)
+        }
+
+        fn pre_dump(&self, request: &DumpRequest) -> Result<EngineOutput> {
+            MockEngine::new().pre_dump(request)
+        }
+
+        fn restore(&self, request: &RestoreRequest) -> Result<EngineOutput> {
+            MockEngine::new().restore(request)
+        }
+    }
+
+    /// Records the arguments each restore was asked for and reports this test process as restored
+    #[derive(Default)]
+    struct RestoreArgsEngine {
+        restore_args: std::sync::Mutex<Vec<Vec<String>>>,
+    }
+
+    impl CheckpointEngine for RestoreArgsEngine {
+        fn name(&self) -> &str {
+            "restore-args"
+        }
+
+        fn check(&self) -> Result<EngineOutput> {
+            MockEngine::new().check()
+        }
+
+        fn dump(&self, request: &DumpRequest) -> Result<EngineOutput> {
+            MockEngine::new().dump(request)
+        }
+
+        fn pre_dump(&self, request: &DumpRequest) -> Result<EngineOutput> {
+            MockEngine::new().pre_dump(request)
+        }
+
+        fn restore(&self, request: &RestoreRequest) -> Result<EngineOutput> {
+            self.restore_args.lock().unwrap().push(request.extra_args.clone());
+            if let Some(ref pidfile) = request.pidfile {
+                std::fs::write(pidfile, std::process::id().to_string())?;
             }
+            MockEngine::new().restore(request)
+        }
+    }
+
+    /// A complete image set whose process no longer exists, recorded as taken by `uid`/`gid`
+    fn foreign_checkpoint(manager: &CriuManager, instance_id: &Uuid, name: &str, uid: u32, gid: u32) {
+        let checkpoint_dir = manager.instance_checkpoints_dir(instance_id).join(name);
+        std::fs::create_dir_all(&checkpoint_dir).unwrap();
+        // Above the kernel's pid_max, so the original PID is never in use
+        for image in ["inventory.img", "pstree.img", "core-4194305.img"] {
+            std::fs::write(checkpoint_dir.join(image), "image").unwrap();
         }
+        crate::criu_compat::write_original_ids(&checkpoint_dir, uid, gid).unwrap();
+    }
+
+    fn sleeper() -> std::process::Child {
+        std::process::Command::new("sleep").arg("30").spawn().unwrap()
+    }
+
+    #[tokio::test]
+    async fn pre_checkpoint_hook_runs_before_the_dump() {
+        crate::test_support::use_scratch_dir();
+        let engine = Arc::new(MarkerEngine { marker_seen: std::sync::Mutex::new(None) });
+        let manager = CriuManager::new_with_engine(engine.clone());
+        let mut child = sleeper();
+        let checkpoint_dir = std::env::current_dir().unwrap().join("hook-ran-before-dump");
+        let hooks = CheckpointHooks {
+            pre_checkpoint_cmd: Some("touch \"$NHI_CHECKPOINT_DIR/pre-hook-ran\"".to_string()),
+            post_checkpoint_cmd: None,
+        };
+
+        let result = manager
+            .create_checkpoint_in_dir(child.id(), "hooked", &checkpoint_dir, &Uuid::new_v4(), None, &hooks, false, true)
+            .await;
+        let _ = child.kill();
+        let _ = child.wait();
+
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(*engine.marker_seen.lock().unwrap(), Some(true));
+    }
+
+    #[tokio::test]
+    async fn failing_pre_checkpoint_hook_leaves_no_checkpoint_dir() {
+        crate::test_support::use_scratch_dir();
+        let engine = Arc::new(MockEngine::new());
+        let manager = CriuManager::new_with_engine(engine.clone());
+        let mut child = sleeper();
+        let checkpoint_dir = std::env::current_dir().unwrap().join("hook-failed");
+        let hooks = CheckpointHooks {
+            pre_checkpoint_cmd: Some("exit 3".to_string()),
+            post_checkpoint_cmd: None,
+        };
+
+        let result = manager
+            .create_checkpoint_in_dir(child.id(), "hooked", &checkpoint_dir, &Uuid::new_v4(), None, &hooks, false, true)
+            .await;
+        let _ = child.kill();
+        let _ = child.wait();
+
+        assert!(result.is_err());
+        assert!(!checkpoint_dir.exists());
+        assert!(engine.calls().is_empty(), "dumped despite the failed hook: {:?}", engine.calls());
+    }
+
+    #[tokio::test]
+    async fn failure_after_the_pause_resumes_and_runs_the_post_hook() {
+        crate::test_support::use_scratch_dir();
+        let engine = Arc::new(MockEngine::new());
+        let manager = CriuManager::new_with_engine(engine.clone());
+        let mut child = sleeper();
+        let checkpoint_dir = std::env::current_dir().unwrap().join("history-unwritable");
+        // Saving the output history fails once the process is paused
+        std::fs::create_dir_all(checkpoint_dir.join("output_history.json")).unwrap();
+        let hooks = CheckpointHooks {
+            pre_checkpoint_cmd: None,
+            post_checkpoint_cmd: Some("touch \"$NHI_CHECKPOINT_DIR/post-hook-ran\"".to_string()),
+        };
+
+        let result = manager
+            .create_checkpoint_in_dir(child.id(), "history", &checkpoint_dir, &Uuid::new_v4(), Some(vec!["line".to_string()]), &hooks, false, true)
+            .await;
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", child.id())).unwrap();
+        let _ = child.kill();
+        let _ = child.wait();
+
+        assert!(result.is_err());
+        let state = stat.rsplit(')').next().unwrap().trim_start().chars().next();
+        assert_ne!(state, Some('T'), "process left stopped: {}", stat);
+        assert!(checkpoint_dir.join("post-hook-ran").exists());
+        assert!(engine.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn named_checkpoints_are_deduplicated() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = CriuManager::new_with_engine(Arc::new(MockEngine::new()));
+        manager.set_storage(CheckpointStorage { dedup: true, key: None });
+        let instance_id = Uuid::new_v4();
+        let instance_dir = std::env::current_dir().unwrap().join(format!("instance_{}", &instance_id.to_string()[..8]));
+        let mut child = sleeper();
+
+        for name in ["first", "second"] {
+            let checkpoint_dir = instance_dir.join("checkpoints").join(name);
+            let result = manager
+                .create_checkpoint_in_dir(child.id(), name, &checkpoint_dir, &instance_id, None, &Default::default(), false, true)
+                .await;
+            assert!(result.is_ok(), "{:?}", result);
+            assert!(checkpoint_dir.join(crate::checkpoint_dedup::MANIFEST_FILE).exists());
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+
+        let files = std::fs::read_dir(instance_dir.join("checkpoints/first")).unwrap().count() - 1;
+        let blobs = std::fs::read_dir(instance_dir.join(crate::checkpoint_dedup::BLOBS_DIR)).unwrap().count();
+        assert!(blobs < 2 * files, "{} blobs for two checkpoints of {} files", blobs, files);
+    }
+
+    #[tokio::test]
+    async fn id_maps_reach_the_restore_and_a_foreign_checkpoint_needs_one() {
+        crate::test_support::use_scratch_dir();
+        let engine = Arc::new(RestoreArgsEngine::default());
+        let manager = CriuManager::new_with_engine(engine.clone());
+        let instance_id = Uuid::new_v4();
+        let (uid, gid) = (nix::unistd::getuid().as_raw(), nix::unistd::getgid().as_raw());
+        let (foreign_uid, foreign_gid) = (uid + 1000, gid + 1000);
+        foreign_checkpoint(&manager, &instance_id, "foreign", foreign_uid, foreign_gid);
+
+        let refused = manager.restore_checkpoint("foreign", Some(&instance_id)).await;
+        assert!(matches!(refused, Err(CriuCliError::CriuError(ref message)) if message.contains("--uid-map")), "{:?}", refused);
+        assert!(engine.restore_args.lock().unwrap().is_empty());
+
+        let options = RestoreOptions {
+            uid_map: Some(IdMap { from: foreign_uid, to: uid }),
+            gid_map: Some(IdMap { from: foreign_gid, to: gid }),
+            ..Default::default()
+        };
+        let (pid, _) = manager.restore_checkpoint_with_options("foreign", Some(&instance_id), &options).await.unwrap();
+        assert_eq!(pid, std::process::id());
+
+        let restores = engine.restore_args.lock().unwrap();
+        let args = restores.last().unwrap();
+        for (flag, map) in [("--uid", format!("{}:{}", foreign_uid, uid)), ("--gid", format!("{}:{}", foreign_gid, gid))] {
+            let position = args.iter().position(|arg| arg == flag).unwrap_or_else(|| panic!("{} missing from {:?}", flag, args));
+            assert_eq!(args.get(position + 1), Some(&map));
+        }
+    }
+
+    #[tokio::test]
+    async fn an_incomplete_checkpoint_is_rejected_before_restore_naming_what_is_missing() {
+        crate::test_support::use_scratch_dir();
+        let engine = Arc::new(RestoreArgsEngine::default());
+        let manager = CriuManager::new_with_engine(engine.clone());
+        let instance_id = Uuid::new_v4();
+        let (uid, gid) = (nix::unistd::getuid().as_raw(), nix::unistd::getgid().as_raw());
+        foreign_checkpoint(&manager, &instance_id, "complete", uid, gid);
+
+        // A dump interrupted after the inventory, with the process tree image never filled in
+        let interrupted = manager.instance_checkpoints_dir(&instance_id).join("interrupted");
+        std::fs::create_dir_all(&interrupted).unwrap();
+        std::fs::write(interrupted.join("inventory.img"), "image").unwrap();
+        std::fs::write(interrupted.join("pstree.img"), "").unwrap();
+        assert_eq!(crate::checkpoint_descriptor::missing_images(&interrupted), ["pstree.img", "core-*.img"]);
+
+        match manager.restore_checkpoint("interrupted", Some(&instance_id)).await {
+            Err(CriuCliError::CorruptCheckpoint(message)) => {
+                assert!(message.contains("missing pstree.img, core-*.img"), "{}", message);
+                assert!(message.contains("try checkpoint 'complete' instead"), "{}", message);
+            }
+            other => panic!("expected a corrupt checkpoint error, got {:?}", other),
+        }
+        assert!(engine.restore_args.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn an_unrelated_process_on_the_original_pid_is_not_killed() {
+        crate::test_support::use_scratch_dir();
+        let engine = Arc::new(RestoreArgsEngine::default());
+        let manager = CriuManager::new_with_engine(engine.clone());
+        let instance_id = Uuid::new_v4();
+        let mut unrelated = sleeper();
+        let pid = unrelated.id();
+        while std::fs::read_to_string(format!("/proc/{}/comm", pid)).unwrap_or_default().trim() != "sleep" {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        // A checkpoint of some other program that happened to run under the same PID
+        let checkpoint_dir = manager.instance_checkpoints_dir(&instance_id).join("reused-pid");
+        std::fs::create_dir_all(&checkpoint_dir).unwrap();
+        for image in ["inventory.img".to_string(), "pstree.img".to_string(), format!("core-{}.img", pid)] {
+            std::fs::write(checkpoint_dir.join(image), "image").unwrap();
+        }
+        std::fs::write(checkpoint_dir.join("original_cmdline"), "/usr/bin/my-server\0--port\08080\0").unwrap();
+
+        let result = manager.restore_checkpoint("reused-pid", Some(&instance_id)).await;
+        match result {
+            Err(CriuCliError::CriuError(message)) => assert!(message.contains("unrelated process (sleep 30)"), "{}", message),
+            other => panic!("expected the restore to be refused, got {:?}", other),
+        }
+        assert!(engine.restore_args.lock().unwrap().is_empty());
+        assert_eq!(unrelated.try_wait().unwrap(), None, "the unrelated process was killed");
+
+        // The same process is only a stale copy when its command line matches the dump
+        std::fs::write(checkpoint_dir.join("original_cmdline"), ["sleep", "30", ""].join("\0")).unwrap();
+        assert!(manager.pid_matches_checkpoint(pid, &checkpoint_dir));
 
-        // Return the highest PID (most recently created)
-        candidates.into_iter().max()
+        unrelated.kill().unwrap();
+        unrelated.wait().unwrap();
     }
 }
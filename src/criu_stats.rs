@@ -0,0 +1,304 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Statistics CRIU writes into the images directory after a dump
+pub const STATS_DUMP_FILE: &str = "stats-dump";
+
+/// Statistics CRIU writes into the images directory after a restore
+pub const STATS_RESTORE_FILE: &str = "stats-restore";
+
+/// Latest statistics of an instance, kept next to its metadata across restarts
+const STATS_RECORD_FILE: &str = "criu_stats.json";
+
+const IMG_COMMON_MAGIC: u32 = 0x54564319;
+const IMG_SERVICE_MAGIC: u32 = 0x55105940;
+const STATS_MAGIC: u32 = 0x57093306;
+
+/// CRIU counts memory in pages; 4 KiB on the platforms NHI runs on
+const PAGE_SIZE: u64 = 4096;
+
+/// Timings (microseconds) and page counts of one CRIU dump
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct DumpStats {
+    pub freezing_time_us: u64,
+    pub frozen_time_us: u64,
+    pub memdump_time_us: u64,
+    pub memwrite_time_us: u64,
+    pub pages_scanned: u64,
+    pub pages_skipped_parent: u64,
+    pub pages_written: u64,
+}
+
+impl DumpStats {
+    /// Bytes of memory CRIU wrote into the images
+    pub fn memory_written(&self) -> u64 {
+        self.pages_written * PAGE_SIZE
+    }
+}
+
+/// Timings (microseconds) and page counts of one CRIU restore
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct RestoreStats {
+    pub forking_time_us: u64,
+    pub restore_time_us: u64,
+    pub pages_restored: u64,
+}
+
+/// Latest dump and restore statistics of an instance
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CriuStats {
+    pub dump: Option<DumpStats>,
+    pub restore: Option<RestoreStats>,
+}
+
+static STATS: Mutex<Option<HashMap<Uuid, CriuStats>>> = Mutex::new(None);
+
+/// Parse the `stats-dump` CRIU left in `images_dir`; None if it is missing or unreadable
+pub fn read_dump_stats(images_dir: &Path) -> Option<DumpStats> {
+    let entry = read_stats_entry(&images_dir.join(STATS_DUMP_FILE))?;
+    // StatsEntry.dump (field 1) is a DumpStatsEntry
+    let dump = find_message(&entry, 1)?;
+    let mut stats = DumpStats::default();
+    for (field, value) in varint_fields(dump) {
+        match field {
+            1 => stats.freezing_time_us = value,
+            2 => stats.frozen_time_us = value,
+            3 => stats.memdump_time_us = value,
+            4 => stats.memwrite_time_us = value,
+            5 => stats.pages_scanned = value,
+            6 => stats.pages_skipped_parent = value,
+            7 => stats.pages_written = value,
+            _ => {}
+        }
+    }
+    Some(stats)
+}
+
+/// Parse the `stats-restore` CRIU left in `images_dir`; None if it is missing or unreadable
+pub fn read_restore_stats(images_dir: &Path) -> Option<RestoreStats> {
+    let entry = read_stats_entry(&images_dir.join(STATS_RESTORE_FILE))?;
+    // StatsEntry.restore (field 2) is a RestoreStatsEntry
+    let restore = find_message(&entry, 2)?;
+    let mut stats = RestoreStats::default();
+    for (field, value) in varint_fields(restore) {
+        match field {
+            3 => stats.forking_time_us = value,
+            4 => stats.restore_time_us = value,
+            5 => stats.pages_restored = value,
+            _ => {}
+        }
+    }
+    Some(stats)
+}
+
+/// Record the statistics of a dump of `instance_id` taken into `images_dir`
+pub fn record_dump(instance_id: Uuid, images_dir: &Path) -> Option<DumpStats> {
+    let stats = read_dump_stats(images_dir)?;
+    info!(
+        "CRIU dump of {}: frozen {:.1} ms, {} pages written ({:.1} MiB)",
+        &instance_id.to_string()[..8],
+        stats.frozen_time_us as f64 / 1000.0,
+        stats.pages_written,
+        stats.memory_written() as f64 / (1024.0 * 1024.0)
+    );
+    update(instance_id, |record| record.dump = Some(stats));
+    Some(stats)
+}
+
+/// Record the statistics of a restore of `instance_id` from `images_dir`
+pub fn record_restore(instance_id: Uuid, images_dir: &Path) -> Option<RestoreStats> {
+    let stats = read_restore_stats(images_dir)?;
+    info!(
+        "CRIU restore of {}: {:.1} ms, {} pages restored",
+        &instance_id.to_string()[..8],
+        stats.restore_time_us as f64 / 1000.0,
+        stats.pages_restored
+    );
+    update(instance_id, |record| record.restore = Some(stats));
+    Some(stats)
+}
+
+/// Latest statistics recorded for `instance_id`
+pub fn get(instance_id: &Uuid) -> Option<CriuStats> {
+    let mut guard = STATS.lock().unwrap();
+    let stats = guard.get_or_insert_with(HashMap::new);
+    if let Some(record) = stats.get(instance_id) {
+        return Some(record.clone());
+    }
+    let record: CriuStats = serde_json::from_str(&fs::read_to_string(record_path(instance_id)).ok()?).ok()?;
+    stats.insert(*instance_id, record.clone());
+    Some(record)
+}
+
+fn update(instance_id: Uuid, apply: impl FnOnce(&mut CriuStats)) {
+    let mut record = get(&instance_id).unwrap_or_default();
+    apply(&mut record);
+    match serde_json::to_string_pretty(&record) {
+        Ok(json) => {
+            if let Err(e) = fs::write(record_path(&instance_id), json) {
+                debug!("Failed to save CRIU statistics of {}: {}", instance_id, e);
+            }
+        }
+        Err(e) => warn!("Failed to serialize CRIU statistics of {}: {}", instance_id, e),
+    }
+    STATS.lock().unwrap().get_or_insert_with(HashMap::new).insert(instance_id, record);
+}
+
+fn record_path(instance_id: &Uuid) -> PathBuf {
+    PathBuf::from(crate::instance::INSTANCES_DIR)
+        .join(format!("instance_{}", &instance_id.to_string()[..8]))
+        .join(STATS_RECORD_FILE)
+}
+
+/// The StatsEntry protobuf of a CRIU stats image: magics, a u32 length, then the message
+fn read_stats_entry(path: &Path) -> Option<Vec<u8>> {
+    let data = fs::read(path).ok()?;
+    let u32_at = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    let mut offset = 0;
+    if matches!(u32_at(offset), Some(IMG_COMMON_MAGIC) | Some(IMG_SERVICE_MAGIC)) {
+        offset += 4;
+    }
+    if u32_at(offset) != Some(STATS_MAGIC) {
+        warn!("{} is not a CRIU stats image", path.display());
+        return None;
+    }
+    offset += 4;
+    let len = u32_at(offset)? as usize;
+    offset += 4;
+    data.get(offset..offset + len).map(|entry| entry.to_vec())
+}
+
+/// Read a protobuf varint at `*pos`, advancing past it
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Value of one protobuf field
+enum FieldValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// Walk the fields of a protobuf message, calling `visit` with each field number and value;
+/// stops at the first malformed field
+fn walk_fields<'a>(data: &'a [u8], mut visit: impl FnMut(u64, FieldValue<'a>)) {
+    let mut pos = 0;
+    while pos < data.len() {
+        let Some(key) = read_varint(data, &mut pos) else { return };
+        let field = key >> 3;
+        match key & 0x7 {
+            0 => match read_varint(data, &mut pos) {
+                Some(value) => visit(field, FieldValue::Varint(value)),
+                None => return,
+            },
+            1 => pos += 8,
+            2 => {
+                let Some(len) = read_varint(data, &mut pos) else { return };
+                let Some(end) = pos.checked_add(len as usize) else { return };
+                let Some(bytes) = data.get(pos..end) else { return };
+                pos = end;
+                visit(field, FieldValue::Bytes(bytes));
+            }
+            5 => pos += 4,
+            _ => return,
+        }
+    }
+}
+
+fn find_message(data: &[u8], wanted: u64) -> Option<&[u8]> {
+    let mut found = None;
+    walk_fields(data, |field, value| {
+        if let FieldValue::Bytes(bytes) = value {
+            if field == wanted {
+                found = Some(bytes);
+            }
+        }
+    });
+    found
+}
+
+fn varint_fields(data: &[u8]) -> Vec<(u64, u64)> {
+    let mut fields = Vec::new();
+    walk_fields(data, |field, value| {
+        if let FieldValue::Varint(value) = value {
+            fields.push((field, value));
+        }
+    });
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(mut value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while value >= 0x80 {
+            bytes.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+        bytes
+    }
+
+    /// A stats image laid out as CRIU writes it, wrapping `fields` in StatsEntry field `entry_field`
+    fn stats_image(entry_field: u64, fields: &[(u64, u64)]) -> Vec<u8> {
+        let mut message = Vec::new();
+        for &(field, value) in fields {
+            message.extend(varint(field << 3));
+            message.extend(varint(value));
+        }
+        let mut entry = varint(entry_field << 3 | 2);
+        entry.extend(varint(message.len() as u64));
+        entry.extend(message);
+
+        let mut image = Vec::new();
+        for word in [IMG_SERVICE_MAGIC, STATS_MAGIC, entry.len() as u32] {
+            image.extend(word.to_le_bytes());
+        }
+        image.extend(entry);
+        image
+    }
+
+    #[test]
+    fn dump_and_restore_stats_are_parsed_from_the_images() {
+        let dir = std::env::temp_dir().join(format!("nhi-criu-stats-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        // freezing, frozen, memdump, memwrite, scanned, skipped_parent, written
+        let dump = [(1, 1_520), (2, 123_456), (3, 40_000), (4, 9_000), (5, 70_000), (6, 512), (7, 3_000)];
+        fs::write(dir.join(STATS_DUMP_FILE), stats_image(1, &dump)).unwrap();
+        // forking, restore, pages_restored
+        fs::write(dir.join(STATS_RESTORE_FILE), stats_image(2, &[(3, 800), (4, 65_000), (5, 3_000)])).unwrap();
+
+        let stats = read_dump_stats(&dir).unwrap();
+        assert_eq!(stats.frozen_time_us, 123_456);
+        assert_eq!(stats.pages_scanned, 70_000);
+        assert_eq!(stats.pages_written, 3_000);
+        assert_eq!(stats.memory_written(), 3_000 * PAGE_SIZE);
+        let restore = read_restore_stats(&dir).unwrap();
+        assert_eq!(restore, RestoreStats { forking_time_us: 800, restore_time_us: 65_000, pages_restored: 3_000 });
+
+        // Anything that is not a stats image yields no statistics
+        fs::write(dir.join(STATS_DUMP_FILE), b"not a stats image").unwrap();
+        assert_eq!(read_dump_stats(&dir), None);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(read_restore_stats(&dir), None);
+    }
+}
@@ -1,246 +1,197 @@
 use crate::message_protocol::*;
-use crate::types::{Instance, InstanceStatus};
-use anyhow::{Result, Context};
+use crate::types::InstanceStatus;
 use chrono::{DateTime, Utc};
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::{RwLock, mpsc};
-use tracing::{debug, error, info, warn};
+use std::collections::{BTreeSet, HashMap};
+use tokio::sync::RwLock;
+use tracing::debug;
 use uuid::Uuid;
 
-/// Events emitted by the distributed registry
-#[derive(Debug, Clone)]
-pub enum RegistryEvent {
-    /// New instance created on a node
-    InstanceCreated(Uuid, NodeId),
-    /// Instance status changed
-    InstanceStatusChanged(Uuid, InstanceStatus),
-    /// Shadow instance created
-    ShadowInstanceCreated(Uuid, NodeId),
-    /// Instance migrated between nodes
-    InstanceMigrated(Uuid, NodeId, NodeId), // instance_id, from_node, to_node
-    /// Instance removed
-    InstanceRemoved(Uuid),
+/// Where an instance runs and which nodes hold shadows of it, as last heard from the cluster
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InstanceLocation {
+    pub running_node: Option<NodeId>,
+    pub shadow_nodes: BTreeSet<NodeId>,
+    /// Shadow holders that announced a checkpoint they can restore from
+    pub checkpoint_nodes: BTreeSet<NodeId>,
+    /// Time of the message that set `running_node`; older reports don't override it
+    pub running_since: Option<DateTime<Utc>>,
 }
 
-/// Distributed instance registry that maintains consistency across all nodes
+/// Cluster-wide view of instance placement, built from the instance sync, shadow stream and
+/// stop messages every node sees. It is eventually consistent: a node's answer reflects the
+/// messages it has received so far. Reports are ordered by when this node received them, since
+/// the senders' clocks may disagree.
 pub struct DistributedInstanceRegistry {
-    local_node_id: NodeId,
-    instances: Arc<RwLock<HashMap<Uuid, InstanceInfo>>>,
-    event_sender: mpsc::UnboundedSender<RegistryEvent>,
-    event_receiver: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<RegistryEvent>>>,
+    locations: RwLock<HashMap<Uuid, InstanceLocation>>,
 }
 
-impl DistributedInstanceRegistry {
-    pub fn new(local_node_id: NodeId) -> Self {
-        let (event_sender, event_receiver) = mpsc::unbounded_channel();
-
-        Self {
-            local_node_id,
-            instances: Arc::new(RwLock::new(HashMap::new())),
-            event_sender,
-            event_receiver: Arc::new(tokio::sync::Mutex::new(event_receiver)),
-        }
+impl Default for DistributedInstanceRegistry {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    /// Register a new instance in the distributed registry
-    pub async fn register_instance(&self, instance: &Instance, node_id: NodeId) -> Result<()> {
-        let instance_info = InstanceInfo {
-            id: instance.id,
-            program: instance.program.clone(),
-            args: instance.args.clone(),
-            status: instance.status.clone(),
-            node_id,
-            created_at: instance.created_at,
-            source_node_id: instance.source_node_id,
-        };
-
-        {
-            let mut instances = self.instances.write().await;
-            instances.insert(instance.id, instance_info);
-        }
-
-        info!("Registered instance {} on node {}", instance.short_id(), node_id);
-        let _ = self.event_sender.send(RegistryEvent::InstanceCreated(instance.id, node_id));
-
-        Ok(())
+impl DistributedInstanceRegistry {
+    pub fn new() -> Self {
+        Self { locations: RwLock::new(HashMap::new()) }
     }
 
-    /// Create shadow instances on all other nodes when a new instance is started
-    pub async fn create_shadow_instances(&self, instance: &Instance, source_node_id: NodeId) -> Result<Vec<InstanceInfo>> {
-        let mut shadow_instances = Vec::new();
-
-        // Get all nodes except the source node
-        let instances = self.instances.read().await;
-        let all_nodes: std::collections::HashSet<NodeId> = instances
-            .values()
-            .map(|info| info.node_id)
-            .collect();
-
-        for node_id in all_nodes {
-            if node_id != source_node_id {
-                let shadow_info = InstanceInfo {
-                    id: instance.id,
-                    program: instance.program.clone(),
-                    args: instance.args.clone(),
-                    status: InstanceStatus::Shadow,
-                    node_id,
-                    created_at: instance.created_at,
-                    source_node_id: Some(source_node_id),
-                };
-
-                shadow_instances.push(shadow_info.clone());
-                info!("Created shadow instance {} on node {}", instance.short_id(), node_id);
-                let _ = self.event_sender.send(RegistryEvent::ShadowInstanceCreated(instance.id, node_id));
-            }
+    /// Record that `node_id` runs the instance as of `at`. The previous running node, if any,
+    /// is demoted to a shadow holder, as nodes do when an instance migrates away.
+    pub async fn record_running(&self, instance_id: Uuid, node_id: NodeId, at: DateTime<Utc>) {
+        let mut locations = self.locations.write().await;
+        let location = locations.entry(instance_id).or_default();
+        if location.running_node == Some(node_id) {
+            location.running_since = Some(at.max(location.running_since.unwrap_or(at)));
+            return;
         }
-
-        Ok(shadow_instances)
-    }
-
-    /// Update instance status in the registry
-    pub async fn update_instance_status(&self, instance_id: Uuid, new_status: InstanceStatus) -> Result<()> {
-        let updated = {
-            let mut instances = self.instances.write().await;
-            if let Some(instance_info) = instances.get_mut(&instance_id) {
-                let old_status = instance_info.status.clone();
-                instance_info.status = new_status.clone();
-                old_status != new_status
-            } else {
-                false
-            }
-        };
-
-        if updated {
-            debug!("Instance {} status changed to {:?}", instance_id, new_status);
-            let _ = self.event_sender.send(RegistryEvent::InstanceStatusChanged(instance_id, new_status));
+        if location.running_since.is_some_and(|since| at < since) {
+            return; // A stale report from before the instance moved
         }
-
-        Ok(())
+        if let Some(previous) = location.running_node.replace(node_id) {
+            debug!("Instance {} moved from node {} to node {}", instance_id, previous, node_id);
+            location.shadow_nodes.insert(previous);
+        }
+        location.shadow_nodes.remove(&node_id);
+        location.checkpoint_nodes.remove(&node_id);
+        location.running_since = Some(at);
     }
 
-    /// Migrate instance from one node to another
-    pub async fn migrate_instance(&self, instance_id: Uuid, from_node: NodeId, to_node: NodeId) -> Result<()> {
+    /// Record that `node_id` holds a shadow of the instance, with or without a checkpoint
+    pub async fn record_shadow(
+        &self,
+        instance_id: Uuid,
+        node_id: NodeId,
+        source_node_id: Option<NodeId>,
+        has_checkpoint: bool,
+        at: DateTime<Utc>,
+    ) {
         {
-            let mut instances = self.instances.write().await;
-
-            // Update the running instance to be on the target node
-            if let Some(instance_info) = instances.get_mut(&instance_id) {
-                if instance_info.node_id == from_node && instance_info.status == InstanceStatus::Running {
-                    instance_info.node_id = to_node;
-                    instance_info.status = InstanceStatus::Running;
-                    instance_info.source_node_id = None;
-                } else {
-                    anyhow::bail!("Instance {} is not running on node {}", instance_id, from_node);
+            let mut locations = self.locations.write().await;
+            let location = locations.entry(instance_id).or_default();
+            if location.running_node == Some(node_id) {
+                if location.running_since.is_some_and(|since| at < since) {
+                    return;
                 }
-            } else {
-                anyhow::bail!("Instance {} not found in registry", instance_id);
+                location.running_node = None;
+                location.running_since = None;
             }
-
-            // Update all other instances to be shadows pointing to the new running node
-            for (_, instance_info) in instances.iter_mut() {
-                if instance_info.id == instance_id && instance_info.node_id != to_node {
-                    instance_info.status = InstanceStatus::Shadow;
-                    instance_info.source_node_id = Some(to_node);
-                }
+            location.shadow_nodes.insert(node_id);
+            if has_checkpoint {
+                location.checkpoint_nodes.insert(node_id);
+            } else {
+                location.checkpoint_nodes.remove(&node_id);
             }
         }
-
-        info!("Migrated instance {} from node {} to node {}", instance_id, from_node, to_node);
-        let _ = self.event_sender.send(RegistryEvent::InstanceMigrated(instance_id, from_node, to_node));
-
-        Ok(())
+        if let Some(source_node_id) = source_node_id {
+            self.record_running(instance_id, source_node_id, at).await;
+        }
     }
 
-    /// Get all instances in the registry
-    pub async fn get_all_instances(&self) -> HashMap<Uuid, InstanceInfo> {
-        let instances = self.instances.read().await;
-        instances.clone()
+    /// Apply an instance sync message received at `received_at`: running entries name the
+    /// sender as the running node, shadow entries name it as a shadow holder
+    pub async fn apply_instance_sync(&self, sync: &InstanceSyncMessage, received_at: DateTime<Utc>) {
+        for info in &sync.instances {
+            match info.status {
+                InstanceStatus::Shadow => self.record_shadow(info.id, sync.sender_id, info.source_node_id, info.has_checkpoint, received_at).await,
+                InstanceStatus::Running | InstanceStatus::Paused => self.record_running(info.id, sync.sender_id, received_at).await,
+                _ => {}
+            }
+        }
     }
 
-    /// Get instances for a specific node
-    pub async fn get_instances_for_node(&self, node_id: NodeId) -> Vec<InstanceInfo> {
-        let instances = self.instances.read().await;
-        instances
-            .values()
-            .filter(|info| info.node_id == node_id)
-            .cloned()
-            .collect()
+    /// Forget an instance that was stopped
+    pub async fn remove_instance(&self, instance_id: Uuid) {
+        self.locations.write().await.remove(&instance_id);
     }
 
-    /// Get running instances (not shadows)
-    pub async fn get_running_instances(&self) -> Vec<InstanceInfo> {
-        let instances = self.instances.read().await;
-        instances
-            .values()
-            .filter(|info| info.status == InstanceStatus::Running)
-            .cloned()
-            .collect()
+    /// Whether `node_id` announced a shadow of the instance
+    pub async fn holds_shadow(&self, instance_id: Uuid, node_id: NodeId) -> bool {
+        self.locations.read().await
+            .get(&instance_id)
+            .is_some_and(|location| location.shadow_nodes.contains(&node_id))
     }
 
-    /// Get shadow instances for a specific source node
-    pub async fn get_shadow_instances(&self, source_node_id: NodeId) -> Vec<InstanceInfo> {
-        let instances = self.instances.read().await;
-        instances
-            .values()
-            .filter(|info| info.status == InstanceStatus::Shadow && info.source_node_id == Some(source_node_id))
-            .cloned()
-            .collect()
+    /// Nodes that announced a checkpoint of the instance
+    pub async fn checkpoint_holders(&self, instance_id: Uuid) -> BTreeSet<NodeId> {
+        self.locations.read().await
+            .get(&instance_id)
+            .map(|location| location.checkpoint_nodes.clone())
+            .unwrap_or_default()
     }
 
-    /// Synchronize registry with remote registry data
-    pub async fn synchronize_registry(&self, remote_instances: Vec<InstanceInfo>) -> Result<()> {
-        let mut conflicts = Vec::new();
-
-        {
-            let mut local_instances = self.instances.write().await;
-
-            for remote_instance in remote_instances {
-                match local_instances.get(&remote_instance.id) {
-                    Some(local_instance) => {
-                        // Check for conflicts and resolve them
-                        if local_instance.status != remote_instance.status ||
-                           local_instance.node_id != remote_instance.node_id {
-                            // Use the most recent timestamp to resolve conflicts
-                            if remote_instance.created_at > local_instance.created_at {
-                                local_instances.insert(remote_instance.id, remote_instance.clone());
-                                debug!("Updated instance {} from remote registry", remote_instance.id);
-                            } else {
-                                conflicts.push(remote_instance.id);
-                            }
-                        }
-                    }
-                    None => {
-                        // New instance from remote node
-                        local_instances.insert(remote_instance.id, remote_instance.clone());
-                        debug!("Added new instance {} from remote registry", remote_instance.id);
-                    }
-                }
-            }
+    /// Look up an instance by full ID or by a unique ID prefix (e.g. the short ID)
+    pub async fn locate(&self, instance_id_str: &str) -> Option<(Uuid, InstanceLocation)> {
+        let locations = self.locations.read().await;
+        if let Ok(instance_id) = Uuid::parse_str(instance_id_str) {
+            return locations.get(&instance_id).map(|location| (instance_id, location.clone()));
         }
-
-        if !conflicts.is_empty() {
-            warn!("Registry synchronization conflicts detected for {} instances", conflicts.len());
+        let mut matches = locations.iter().filter(|(id, _)| id.to_string().starts_with(instance_id_str));
+        match (matches.next(), matches.next()) {
+            (Some((id, location)), None) => Some((*id, location.clone())),
+            _ => None,
         }
-
-        Ok(())
     }
+}
 
-    /// Remove instance from registry
-    pub async fn remove_instance(&self, instance_id: Uuid) -> Result<()> {
-        {
-            let mut instances = self.instances.write().await;
-            instances.remove(&instance_id);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn info(id: Uuid, status: InstanceStatus, source_node_id: Option<NodeId>) -> InstanceInfo {
+        InstanceInfo {
+            id,
+            program: "sleep".to_string(),
+            args: Vec::new(),
+            status,
+            node_id: Uuid::nil(),
+            created_at: Utc::now(),
+            source_node_id,
+            affinity: Default::default(),
+            output_timestamps: Default::default(),
+            labels: Default::default(),
+            has_checkpoint: false,
+            env: Vec::new(),
         }
+    }
 
-        info!("Removed instance {} from registry", instance_id);
-        let _ = self.event_sender.send(RegistryEvent::InstanceRemoved(instance_id));
+    fn sync(sender_id: NodeId, instances: Vec<InstanceInfo>, sent_at: DateTime<Utc>) -> InstanceSyncMessage {
+        InstanceSyncMessage { sender_id, instances, timestamp: sent_at, sync_id: None, complete: true }
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn locate_follows_a_migration() {
+        let registry = DistributedInstanceRegistry::new();
+        let (source, target) = (Uuid::new_v4(), Uuid::new_v4());
+        let instance_id = Uuid::new_v4();
+        let start = Utc::now();
+
+        registry.apply_instance_sync(&sync(source, vec![info(instance_id, InstanceStatus::Running, None)], start), start).await;
+        registry.apply_instance_sync(&sync(target, vec![info(instance_id, InstanceStatus::Shadow, Some(source))], start), start).await;
+        let (_, location) = registry.locate(&instance_id.to_string()[..8]).await.unwrap();
+        assert_eq!(location.running_node, Some(source));
+        assert_eq!(location.shadow_nodes, BTreeSet::from([target]));
+
+        // The target's clock runs an hour behind; its report still arrives after the move
+        let moved = start + Duration::seconds(1);
+        let behind = moved - Duration::hours(1);
+        registry.apply_instance_sync(&sync(target, vec![info(instance_id, InstanceStatus::Running, None)], behind), moved).await;
+        registry.apply_instance_sync(&sync(source, vec![info(instance_id, InstanceStatus::Shadow, Some(target))], moved), moved).await;
+        let (_, location) = registry.locate(&instance_id.to_string()).await.unwrap();
+        assert_eq!(location.running_node, Some(target));
+        assert_eq!(location.shadow_nodes, BTreeSet::from([source]));
     }
 
-    /// Get event receiver for listening to registry events
-    pub fn get_event_receiver(&self) -> Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<RegistryEvent>>> {
-        self.event_receiver.clone()
+    #[tokio::test]
+    async fn reports_received_before_the_move_do_not_override_it() {
+        let registry = DistributedInstanceRegistry::new();
+        let (source, target) = (Uuid::new_v4(), Uuid::new_v4());
+        let instance_id = Uuid::new_v4();
+        let moved = Utc::now();
+
+        registry.record_running(instance_id, target, moved).await;
+        registry.record_running(instance_id, source, moved - Duration::seconds(1)).await;
+        assert_eq!(registry.locate(&instance_id.to_string()).await.unwrap().1.running_node, Some(target));
     }
 }
@@ -9,7 +9,7 @@ use crate::cli::CliState;
 use crate::output::Output;
 use axum::{
     extract::{State, Query},
-    http::{StatusCode, HeaderMap, HeaderValue, Method},
+    http::{StatusCode, HeaderValue, Method},
     response::Json,
     routing::{post, get},
     Router,
@@ -20,8 +20,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::{Mutex, RwLock};
-use tracing::{info, error, warn};
-use chrono;
+use tracing::{info, error};
 
 #[derive(Clone)]
 pub struct ApiState {
@@ -80,6 +79,12 @@ pub struct SystemStatusResponse {
     pub api_version: String,
 }
 
+#[derive(Serialize)]
+pub struct CriuStatsResponse {
+    pub success: bool,
+    pub instances: HashMap<String, nhi::CriuStats>,
+}
+
 #[derive(Deserialize)]
 pub struct LogsQuery {
     pub lines: Option<usize>,
@@ -99,6 +104,7 @@ pub fn create_router(state: ApiState) -> Router {
         .route("/api/cpu", get(get_cpu_usage_handler))
         .route("/api/memory", get(get_memory_usage_handler))
         .route("/api/status", get(get_system_status_handler))
+        .route("/api/criu-stats", get(get_criu_stats_handler))
         .layer(cors)
         .with_state(state)
 }
@@ -112,15 +118,16 @@ pub async fn start_http_server(
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
 
     info!("🌐 HTTP API server listening on port {}", port);
-    Output::network(&format!("HTTP API available at:"));
-    Output::network(&format!("  Commands:"));
+    Output::network("HTTP API available at:");
+    Output::network("  Commands:");
     Output::network(&format!("    JSON: http://0.0.0.0:{}/api/command", port));
     Output::network(&format!("    TEXT: http://0.0.0.0:{}/command", port));
-    Output::network(&format!("  System Info:"));
+    Output::network("  System Info:");
     Output::network(&format!("    Logs: http://0.0.0.0:{}/api/logs", port));
     Output::network(&format!("    CPU:  http://0.0.0.0:{}/api/cpu", port));
     Output::network(&format!("    Memory: http://0.0.0.0:{}/api/memory", port));
     Output::network(&format!("    Status: http://0.0.0.0:{}/api/status", port));
+    Output::network(&format!("    CRIU stats: http://0.0.0.0:{}/api/criu-stats", port));
 
     axum::serve(listener, app).await?;
 
@@ -134,27 +141,24 @@ async fn execute_command_handler(
     let command_text = payload.command.trim();
 
     // Log the received command prominently
-    Output::header(&format!("📡 HTTP API Command Received"));
+    Output::header("📡 HTTP API Command Received");
     Output::info(&format!("Command: {}", command_text));
     info!("HTTP API received command: {}", command_text);
 
     // Parse the command
-    let command = match CliCommand::parse_from_str(command_text) {
-        Ok(cmd) => cmd,
-        Err(e) => {
-            let error_msg = format!("Failed to parse command: {}", e);
-            error!("{}", error_msg);
-            Output::error(&error_msg);
-            return Ok(Json(CommandResponse {
-                success: false,
-                message: error_msg,
-                output: None,
-            }));
-        }
-    };
+    if let Err(e) = CliCommand::parse_from_str(command_text) {
+        let error_msg = format!("Failed to parse command: {}", e);
+        error!("{}", error_msg);
+        Output::error(&error_msg);
+        return Ok(Json(CommandResponse {
+            success: false,
+            message: error_msg,
+            output: None,
+        }));
+    }
 
     // Execute the command using main.rs execute_command function
-    match crate::execute_command(
+    match crate::commands::execute_command(
         command_text,
         &state.cli_state,
         &state.instance_manager,
@@ -225,7 +229,7 @@ async fn execute_text_command_handler(
     info!("HTTP TEXT API received command: {}", command_text);
 
     // Use the same execute_command function from main.rs
-    match crate::execute_command(
+    match crate::commands::execute_command(
         &command_text,
         &state.cli_state,
         &state.instance_manager,
@@ -396,6 +400,21 @@ async fn get_system_status_handler(
     }))
 }
 
+// GET /api/criu-stats - latest CRIU dump/restore statistics of each instance
+async fn get_criu_stats_handler(
+    State(state): State<ApiState>,
+) -> Result<Json<CriuStatsResponse>, StatusCode> {
+    info!("HTTP API: Fetching CRIU statistics");
+
+    let instances = state.instance_manager.lock().await
+        .criu_stats()
+        .into_iter()
+        .map(|(id, stats)| (id.to_string(), stats))
+        .collect();
+
+    Ok(Json(CriuStatsResponse { success: true, instances }))
+}
+
 // 辅助函数：读取日志文件
 async fn read_log_files(lines: usize) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
     use tokio::fs;
@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::{Child, Command};
+
+/// Marks a migration connection carrying a criu-image-streamer stream instead of a tar.gz archive
+pub const STREAM_MAGIC: &[u8; 4] = b"NHIS";
+
+/// Wrapper around the criu-image-streamer binary
+#[derive(Debug, Clone)]
+pub struct ImageStreamer {
+    path: PathBuf,
+}
+
+impl Default for ImageStreamer {
+    fn default() -> Self {
+        Self::new(crate::capabilities::DEFAULT_IMAGE_STREAMER_PATH)
+    }
+}
+
+impl ImageStreamer {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the streamer binary is installed
+    pub fn is_available(&self) -> bool {
+        self.path.is_file()
+    }
+
+    /// Arguments for capturing a `criu dump --stream` from `images_dir` to stdout
+    pub fn capture_args(images_dir: &Path) -> Vec<String> {
+        vec![
+            "--images-dir".to_string(),
+            images_dir.display().to_string(),
+            "capture".to_string(),
+        ]
+    }
+
+    /// Arguments for extracting a stream read from stdin into image files in `images_dir`
+    pub fn extract_args(images_dir: &Path) -> Vec<String> {
+        vec![
+            "--images-dir".to_string(),
+            images_dir.display().to_string(),
+            "extract".to_string(),
+        ]
+    }
+
+    /// Start a capture; the image stream is available on the child's stdout
+    pub fn spawn_capture(&self, images_dir: &Path) -> std::io::Result<Child> {
+        Command::new(&self.path)
+            .args(Self::capture_args(images_dir))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+    }
+
+    /// Start an extraction; the image stream must be written to the child's stdin
+    pub fn spawn_extract(&self, images_dir: &Path) -> std::io::Result<Child> {
+        Command::new(&self.path)
+            .args(Self::extract_args(images_dir))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn command_lines_name_the_images_dir_and_mode() {
+        let dir = Path::new("/var/lib/nhi/images dir");
+        assert_eq!(ImageStreamer::capture_args(dir), ["--images-dir", "/var/lib/nhi/images dir", "capture"]);
+        assert_eq!(ImageStreamer::extract_args(dir), ["--images-dir", "/var/lib/nhi/images dir", "extract"]);
+    }
+
+    #[tokio::test]
+    async fn capture_runs_the_configured_binary_with_its_arguments() {
+        let bin_dir = tempfile::tempdir().unwrap();
+        let binary = bin_dir.path().join("criu-image-streamer");
+        std::fs::write(&binary, "#!/bin/sh\nprintf '%s\\n' \"$@\"\n").unwrap();
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let streamer = ImageStreamer::new(&binary);
+        assert!(streamer.is_available());
+        assert!(!ImageStreamer::new(bin_dir.path().join("missing")).is_available());
+
+        // A fork in another test thread may briefly hold the script open for writing
+        let capture = loop {
+            match streamer.spawn_capture(Path::new("/tmp/images")) {
+                Err(e) if e.kind() == std::io::ErrorKind::ExecutableFileBusy => tokio::task::yield_now().await,
+                spawned => break spawned.unwrap(),
+            }
+        };
+        let output = capture.wait_with_output().await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "--images-dir\n/tmp/images\ncapture\n");
+    }
+}
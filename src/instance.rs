@@ -1,16 +1,95 @@
+use crate::audit::AuditLog;
 use crate::criu_manager::CriuManager;
-use crate::process_manager::ProcessManager;
-use crate::types::{CriuCliError, Instance, InstanceStatus, Result, StartMode};
+use crate::process_manager::{LineStamp, ProcessManager};
+use crate::types::{format_labels, matches_labels, CheckpointHooks, CriuCliError, FailedOperation, Instance, InstanceEdit, InstanceFilter, InstanceSort, InstanceStatus, RestoreOptions, Result, StartMode, StartOptions};
 use crate::colors::ColorScheme;
 use std::collections::HashMap;
 use std::env;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
+/// Directory holding all instance state, relative to the working directory
+pub const INSTANCES_DIR: &str = "instances";
+
+static DATA_DIR: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+
+/// `INSTANCES_DIR` resolved against the working directory at first use, so records written
+/// after a `cd` still land next to the instances they belong to
+pub fn data_dir() -> &'static Path {
+    DATA_DIR.get_or_init(|| {
+        env::current_dir()
+            .map(|cwd| cwd.join(INSTANCES_DIR))
+            .unwrap_or_else(|_| PathBuf::from(INSTANCES_DIR))
+    })
+}
+
+/// Make sure `dir` exists and files can be created in it
+pub fn check_data_dir_writable(dir: &std::path::Path) -> Result<()> {
+    let not_writable = |e| CriuCliError::DataDirNotWritable(dir.display().to_string(), e);
+    std::fs::create_dir_all(dir).map_err(not_writable)?;
+    let probe = dir.join(format!(".write-test-{}", std::process::id()));
+    std::fs::write(&probe, b"").map_err(not_writable)?;
+    let _ = std::fs::remove_file(&probe);
+    Ok(())
+}
+
+/// Statuses `purge` may remove; running, paused and shadow instances are never touched
+const PURGEABLE_STATUSES: [InstanceStatus; 2] = [InstanceStatus::Stopped, InstanceStatus::Failed];
+
+/// Leftover directories without metadata may be a migration or shadow still being set up;
+/// `purge` only takes them once nothing has been written to them for this long
+const UNKNOWN_LEFTOVER_MIN_IDLE: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// An instance, or an instance directory left behind by an earlier session, selected by `purge`
+#[derive(Debug, Clone)]
+pub struct PurgeCandidate {
+    pub short_id: String,
+    pub managed: Option<Uuid>,          // Known to this manager; None for a leftover directory
+    pub status: Option<InstanceStatus>, // None when a leftover directory has no readable metadata
+    pub dir: PathBuf,
+    pub bytes: u64,
+}
+
+/// How long ago an instance directory was last written to (its metadata, else the directory itself)
+fn idle_for(dir: &Path) -> Option<std::time::Duration> {
+    let modified = std::fs::metadata(dir.join("metadata.json"))
+        .or_else(|_| std::fs::metadata(dir))
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+    Some(modified.elapsed().unwrap_or_default())
+}
+
+/// Name prefix of scheduled snapshots, kept apart from transient `auto-sync-`/`sync-` checkpoints
+pub const SNAPSHOT_PREFIX: &str = "snapshot-";
+
+/// Snapshots kept when `--snapshot-interval` is given without `--snapshot-retain`
+pub const DEFAULT_SNAPSHOT_RETAIN: usize = 24;
+
+/// How often the snapshot scheduler checks for due snapshots
+const SNAPSHOT_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// Checkpoint name of the snapshot taken at `time` (`restore --snapshot <time>`);
+/// accepts the time alone or the full `snapshot-<time>` name
+pub fn snapshot_checkpoint_name(time: &str) -> String {
+    if time.starts_with(SNAPSHOT_PREFIX) {
+        time.to_string()
+    } else {
+        format!("{}{}", SNAPSHOT_PREFIX, time)
+    }
+}
+
 pub struct InstanceManager {
     instances: HashMap<Uuid, Instance>,
     instance_by_short_id: HashMap<String, Uuid>,
+    audit: AuditLog,
+}
+
+impl Default for InstanceManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl InstanceManager {
@@ -18,49 +97,23 @@ impl InstanceManager {
         Self {
             instances: HashMap::new(),
             instance_by_short_id: HashMap::new(),
+            audit: AuditLog::default(),
         }
     }
 
+    /// Record starts, stops, checkpoints and restores in this audit log
+    pub fn set_audit_log(&mut self, audit: AuditLog) {
+        self.audit = audit;
+    }
+
     pub async fn start_instance(
         &mut self,
         program: String,
         args: Vec<String>,
         process_manager: Arc<ProcessManager>,
     ) -> Result<String> {
-        let working_dir = env::current_dir().map_err(CriuCliError::IoError)?;
-        let mut instance = Instance::new(program.clone(), args.clone(), working_dir);
-
-        info!("Starting instance: {} {}", program, args.join(" "));
-
-        // Start the process
-        match process_manager
-            .start_process(instance.id, &program, &args, &instance.working_dir)
+        self.start_instance_with_options(program, args, StartMode::Normal, &StartOptions::default(), process_manager)
             .await
-        {
-            Ok(pid) => {
-                instance.pid = Some(pid);
-                instance.status = InstanceStatus::Running;
-                info!("Instance {} started with PID: {}", instance.short_id(), pid);
-            }
-            Err(e) => {
-                instance.status = InstanceStatus::Failed;
-                error!("Failed to start instance {}: {}", instance.short_id(), e);
-                return Err(e);
-            }
-        }
-
-        let short_id = instance.short_id();
-        let instance_id = instance.id;
-
-        // Save instance metadata
-        if let Err(e) = instance.save_metadata() {
-            warn!("Failed to save instance metadata: {}", e);
-        }
-
-        self.instances.insert(instance_id, instance);
-        self.instance_by_short_id.insert(short_id.clone(), instance_id);
-
-        Ok(short_id)
     }
 
     pub async fn start_instance_detached(
@@ -69,24 +122,73 @@ impl InstanceManager {
         args: Vec<String>,
         process_manager: Arc<ProcessManager>,
     ) -> Result<String> {
-        let working_dir = env::current_dir().map_err(CriuCliError::IoError)?;
-        let mut instance = Instance::new_with_mode(program.clone(), args.clone(), working_dir, StartMode::Detached);
+        self.start_instance_with_options(program, args, StartMode::Detached, &StartOptions::default(), process_manager)
+            .await
+    }
 
-        info!("Starting detached instance: {} {}", program, args.join(" "));
+    pub async fn start_instance_with_options(
+        &mut self,
+        program: String,
+        args: Vec<String>,
+        start_mode: StartMode,
+        options: &StartOptions,
+        process_manager: Arc<ProcessManager>,
+    ) -> Result<String> {
+        let working_dir = env::current_dir().map_err(CriuCliError::IoError)?;
+        let mut instance = match options.instance_id {
+            Some(id) => {
+                self.check_instance_id_free(&id)?;
+                Instance::new_with_id(id, program.clone(), args.clone(), working_dir, start_mode.clone())
+            }
+            None => Instance::new_with_mode(program.clone(), args.clone(), working_dir, start_mode.clone()),
+        };
+        instance.sync_enabled = options.sync;
+        instance.checkpoint_hooks = options.checkpoint_hooks.clone();
+        instance.output_file = options.output_file.clone();
+        instance.affinity = options.affinity.clone();
+        instance.output_timestamps = options.output_timestamps.unwrap_or_else(|| process_manager.default_output_timestamps());
+        instance.append_only = options.append_only;
+        instance.labels = options.labels.clone();
+        instance.snapshot_policy = options.snapshot_policy;
+
+        // Persist before spawning, so an unwritable instance directory fails the start
+        // instead of leaving a running process nothing records
+        instance.save_metadata()?;
+
+        let mode_label = if start_mode == StartMode::Detached { "detached instance" } else { "instance" };
+        info!("Starting {}: {} {}", mode_label, program, args.join(" "));
 
-        // Start the process in detached mode
+        // Start the process
         match process_manager
-            .start_process_with_mode(instance.id, &program, &args, &instance.working_dir, StartMode::Detached)
+            .start_process_with_mode(
+                instance.id,
+                &program,
+                &args,
+                &instance.env,
+                &instance.working_dir,
+                start_mode,
+                options.output_file.as_deref(),
+                options.start_paused,
+                LineStamp::new(instance.output_timestamps, instance.created_at),
+                instance.append_only,
+            )
             .await
         {
             Ok(pid) => {
                 instance.pid = Some(pid);
-                instance.status = InstanceStatus::Running;
-                info!("Detached instance {} started with PID: {}", instance.short_id(), pid);
+                instance.set_status(if options.start_paused { InstanceStatus::Paused } else { InstanceStatus::Running })?;
+                info!("Started {} {} with PID: {}", mode_label, instance.short_id(), pid);
+                self.audit.record("start", Some(instance.id), None, None);
             }
             Err(e) => {
-                instance.status = InstanceStatus::Failed;
-                error!("Failed to start detached instance {}: {}", instance.short_id(), e);
+                instance.mark_failed(FailedOperation::Start, &e);
+                error!("Failed to start {} {}: {}", mode_label, instance.short_id(), e);
+                self.audit.record("start", Some(instance.id), None, Some(&e.to_string()));
+                // Keep the failed instance so it can be retried
+                let saved = instance.save_metadata();
+                self.instance_by_short_id.insert(instance.short_id(), instance.id);
+                self.instances.insert(instance.id, instance);
+                saved?;
                 return Err(e);
             }
         }
@@ -94,31 +196,41 @@ impl InstanceManager {
         let short_id = instance.short_id();
         let instance_id = instance.id;
 
-        // Save instance metadata
-        if let Err(e) = instance.save_metadata() {
-            warn!("Failed to save instance metadata: {}", e);
-        }
-
+        // Track the running process even if its metadata can't be saved, so it can be stopped
+        let saved = instance.save_metadata();
         self.instances.insert(instance_id, instance);
         self.instance_by_short_id.insert(short_id.clone(), instance_id);
+        saved?;
 
         Ok(short_id)
     }
 
+    /// Stop an instance unless it has already stopped or failed, returning whether it was stopped now.
+    /// Unknown IDs are still an error.
+    pub async fn stop_instance_if_running(
+        &mut self,
+        instance_id_str: &str,
+        process_manager: Arc<ProcessManager>,
+    ) -> Result<bool> {
+        match self.stop_instance(instance_id_str, process_manager).await {
+            Ok(()) => Ok(true),
+            Err(CriuCliError::UnexpectedStatus(_, InstanceStatus::Stopped | InstanceStatus::Failed, _)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
     pub async fn stop_instance(
         &mut self,
         instance_id_str: &str,
         process_manager: Arc<ProcessManager>,
     ) -> Result<()> {
-        let instance_id = self.resolve_instance_id(instance_id_str)?;
+        let instance_id =
+            self.resolve_instance_id_with_status(instance_id_str, &[InstanceStatus::Running, InstanceStatus::Paused])?;
 
         // First, get the instance info we need without holding a mutable reference
-        let (is_detached, stored_pid, program_name, short_id) = {
+        let (is_detached, stored_pid, instance, short_id) = {
             if let Some(instance) = self.instances.get(&instance_id) {
-                if instance.status != InstanceStatus::Running && instance.status != InstanceStatus::Paused {
-                    return Err(CriuCliError::InstanceNotRunning(instance_id_str.to_string()));
-                }
-                (instance.start_mode == StartMode::Detached, instance.pid, instance.program.clone(), instance.short_id())
+                (instance.start_mode == StartMode::Detached, instance.pid, instance.clone(), instance.short_id())
             } else {
                 return Err(CriuCliError::InstanceNotFound(instance_id_str.to_string()));
             }
@@ -129,14 +241,13 @@ impl InstanceManager {
         // Now determine the actual PID to stop
         let actual_pid = if is_detached {
             if let Some(stored_pid) = stored_pid {
-                // Try to find the actual running process by name
-                if let Some(real_pid) = self.find_actual_process_pid(&program_name).await {
-                    if real_pid != stored_pid {
-                        warn!("PID mismatch for instance {}: stored {} vs actual {}", short_id, stored_pid, real_pid);
-                        // Update the stored PID in the instance
-                        if let Some(instance) = self.instances.get_mut(&instance_id) {
-                            instance.pid = Some(real_pid);
-                        }
+                if Self::process_matches_instance(stored_pid, &instance) {
+                    stored_pid
+                } else if let Some(real_pid) = Self::find_actual_process_pid(&instance) {
+                    // The stored PID is stale, e.g. after an external restart
+                    warn!("PID mismatch for instance {}: stored {} vs actual {}", short_id, stored_pid, real_pid);
+                    if let Some(instance) = self.instances.get_mut(&instance_id) {
+                        instance.pid = Some(real_pid);
                     }
                     real_pid
                 } else {
@@ -157,16 +268,18 @@ impl InstanceManager {
         };
 
         // Finally, update the instance state
+        self.audit.record_result("stop", Some(instance_id), None, &result);
         if let Some(instance) = self.instances.get_mut(&instance_id) {
             match result {
                 Ok(()) => {
-                    instance.status = InstanceStatus::Stopped;
+                    instance.set_status(InstanceStatus::Stopped)?;
                     instance.pid = None;
+                    instance.clear_failure();
                     info!("Instance {} stopped successfully", instance.short_id());
                     Ok(())
                 }
                 Err(e) => {
-                    instance.status = InstanceStatus::Failed;
+                    instance.mark_failed(FailedOperation::Stop, &e);
                     error!("Failed to stop instance {}: {}", instance.short_id(), e);
                     Err(e)
                 }
@@ -192,7 +305,7 @@ impl InstanceManager {
 
             match process_manager.pause_process(&instance_id).await {
                 Ok(()) => {
-                    instance.status = InstanceStatus::Paused;
+                    instance.set_status(InstanceStatus::Paused)?;
                     info!("Instance {} paused successfully", instance.short_id());
                     Ok(())
                 }
@@ -222,7 +335,7 @@ impl InstanceManager {
 
             match process_manager.resume_process(&instance_id).await {
                 Ok(()) => {
-                    instance.status = InstanceStatus::Running;
+                    instance.set_status(InstanceStatus::Running)?;
                     info!("Instance {} resumed successfully", instance.short_id());
                     Ok(())
                 }
@@ -236,10 +349,40 @@ impl InstanceManager {
         }
     }
 
+    /// Send a signal to a running or paused instance, returning the PID it was delivered to
+    pub async fn signal_instance(
+        &self,
+        instance_id_str: &str,
+        signal: nix::sys::signal::Signal,
+        process_manager: Arc<ProcessManager>,
+    ) -> Result<u32> {
+        let instance_id = self.resolve_instance_id(instance_id_str)?;
+        let instance = self
+            .instances
+            .get(&instance_id)
+            .ok_or_else(|| CriuCliError::InstanceNotFound(instance_id_str.to_string()))?;
+
+        if instance.status != InstanceStatus::Running && instance.status != InstanceStatus::Paused {
+            return Err(CriuCliError::InstanceNotRunning(instance_id_str.to_string()));
+        }
+
+        // Managed processes are tracked by the process manager, detached ones only by their stored PID
+        let pid = match process_manager.get_process_pid(&instance_id).await.or(instance.pid) {
+            Some(pid) => pid,
+            None => return Err(CriuCliError::ProcessError("Instance has no PID".to_string())),
+        };
+
+        let result = process_manager.send_signal(pid, signal);
+        self.audit.record_result(&format!("signal {}", signal), Some(instance.id), None, &result);
+        result.map(|_| pid)
+    }
+
     pub async fn checkpoint_instance(
         &mut self,
         instance_id_str: &str,
         checkpoint_name: &str,
+        set_base: bool,
+        stop: bool,
         criu_manager: Arc<CriuManager>,
         process_manager: Arc<ProcessManager>,
     ) -> Result<()> {
@@ -263,22 +406,35 @@ impl InstanceManager {
             let checkpoint_dir = instance.checkpoints_dir().join(checkpoint_name);
 
             match criu_manager
-                .create_checkpoint_in_dir(pid, checkpoint_name, &checkpoint_dir, &instance_id, output_history)
+                .create_checkpoint_in_dir(pid, checkpoint_name, &checkpoint_dir, &instance_id, output_history, &instance.checkpoint_hooks, set_base, !stop)
                 .await
             {
                 Ok(checkpoint_dir) => {
                     instance.add_checkpoint(checkpoint_name.to_string(), checkpoint_dir);
-
-                    // Save updated instance metadata
-                    if let Err(e) = instance.save_metadata() {
-                        warn!("Failed to save instance metadata after checkpoint: {}", e);
+                    instance.refresh_child_pids();
+                    if set_base {
+                        info!("Checkpoint '{}' is now the auto-sync base for instance {}", checkpoint_name, instance.short_id());
+                        instance.sync_base = Some(checkpoint_name.to_string());
+                    }
+                    if stop {
+                        // CRIU killed the process after dumping it; the checkpoint is how it comes back
+                        process_manager.remove_process(&instance_id).await;
+                        instance.set_status(InstanceStatus::Stopped)?;
+                        instance.pid = None;
+                        instance.child_pids.clear();
+                        info!("Instance {} stopped after checkpoint '{}'", instance.short_id(), checkpoint_name);
                     }
 
+                    // Save updated instance metadata; the checkpoint is not usable from a restart without it
+                    instance.save_metadata()?;
+
                     info!("Checkpoint '{}' created for instance {}", checkpoint_name, instance.short_id());
+                    self.audit.record("checkpoint", Some(instance.id), None, None);
                     Ok(())
                 }
                 Err(e) => {
                     error!("Failed to create checkpoint for instance {}: {}", instance.short_id(), e);
+                    self.audit.record("checkpoint", Some(instance.id), None, Some(&e.to_string()));
                     Err(e)
                 }
             }
@@ -291,6 +447,7 @@ impl InstanceManager {
         &mut self,
         instance_id_str: &str,
         checkpoint_name: &str,
+        options: &RestoreOptions,
         criu_manager: Arc<CriuManager>,
         process_manager: Arc<ProcessManager>,
     ) -> Result<()> {
@@ -301,6 +458,12 @@ impl InstanceManager {
             return Err(CriuCliError::InstanceNotFound(instance_id_str.to_string()));
         }
 
+        // Check before stopping anything, so a mistyped name (or another instance's checkpoint)
+        // leaves the instance running
+        if !criu_manager.checkpoint_exists_for_instance(checkpoint_name, &instance_id) {
+            return Err(criu_manager.checkpoint_not_found(checkpoint_name, &instance_id));
+        }
+
         info!("Restoring instance {} from checkpoint: {}", instance_id_str, checkpoint_name);
 
         // Step 1: Stop the current process if it's running
@@ -315,31 +478,51 @@ impl InstanceManager {
         }
 
         // Step 2: Restore from checkpoint using the specific instance
-        match criu_manager.restore_checkpoint(checkpoint_name, Some(&instance_id)).await {
-            Ok((pid, _output_history)) => {
+        match criu_manager.restore_checkpoint_with_options(checkpoint_name, Some(&instance_id), options).await {
+            Ok((pid, output_history)) => {
                 // Step 3: Update the instance with new PID and status
                 if let Some(instance) = self.instances.get_mut(&instance_id) {
                     instance.pid = Some(pid);
-                    instance.status = InstanceStatus::Running;
+                    instance.set_status(InstanceStatus::Running)?;
+                    instance.clear_failure();
+                    instance.refresh_child_pids();
                     info!("Updated instance {} with restored PID {}", instance.short_id(), pid);
                 } else {
                     return Err(CriuCliError::InstanceNotFound(instance_id_str.to_string()));
                 }
 
+                // The restored process gets a fresh live log; earlier output stays readable with
+                // `logs --all`. Only after the restore succeeded, so a failed one keeps the log as it was.
+                if options.replace {
+                    match ProcessManager::rotate_combined_log(&instance_id, checkpoint_name) {
+                        Ok(Some(rotated)) => info!("Rotated output log of instance {} to {}", instance_id_str, rotated.display()),
+                        Ok(None) => {}
+                        Err(e) => warn!("Failed to rotate output log of instance {}: {}", instance_id_str, e),
+                    }
+                }
+
                 // Step 4: Register the restored process with the process manager
-                if let Err(e) = process_manager.register_restored_process(instance_id, pid, None).await {
+                let stamp = self.line_stamp(&instance_id);
+                let append_only = self.instances.get(&instance_id).is_some_and(|instance| instance.append_only);
+                if let Err(e) = process_manager.register_restored_process(instance_id, pid, output_history, stamp, append_only).await {
                     error!("Failed to register restored process: {}", e);
                     return Err(e);
                 }
 
                 info!("Instance {} restored from checkpoint '{}'", instance_id_str, checkpoint_name);
+                self.audit.record("restore", Some(instance_id), None, None);
                 Ok(())
             }
             Err(e) => {
                 error!("Failed to restore checkpoint '{}': {}", checkpoint_name, e);
+                self.audit.record("restore", Some(instance_id), None, Some(&e.to_string()));
                 // Mark instance as failed
                 if let Some(instance) = self.instances.get_mut(&instance_id) {
-                    instance.status = InstanceStatus::Failed;
+                    let operation = FailedOperation::Restore {
+                        checkpoint_name: checkpoint_name.to_string(),
+                        options: options.clone(),
+                    };
+                    instance.mark_failed(operation, &e);
                     instance.pid = None;
                 }
                 Err(e)
@@ -347,6 +530,157 @@ impl InstanceManager {
         }
     }
 
+    /// Re-attempt the operation that left an instance `Failed`
+    pub async fn retry_instance(
+        &mut self,
+        instance_id_str: &str,
+        criu_manager: Arc<CriuManager>,
+        process_manager: Arc<ProcessManager>,
+    ) -> Result<()> {
+        let instance_id = self.resolve_instance_id(instance_id_str)?;
+        let instance = self
+            .instances
+            .get_mut(&instance_id)
+            .ok_or_else(|| CriuCliError::InstanceNotFound(instance_id_str.to_string()))?;
+
+        if instance.status != InstanceStatus::Failed {
+            return Err(CriuCliError::ProcessError(format!(
+                "Instance {} is {:?}, only failed instances can be retried",
+                instance_id_str, instance.status
+            )));
+        }
+        let operation = instance.failed_operation.clone().ok_or_else(|| {
+            CriuCliError::ProcessError(format!("Instance {} has no failed operation to retry", instance_id_str))
+        })?;
+
+        info!("Retrying {:?} for instance {}", operation, instance.short_id());
+
+        let result = match operation {
+            FailedOperation::Start => Self::launch_stored(instance, &process_manager, &self.audit).await.map(|_| ()),
+            FailedOperation::Stop => {
+                // The process may still be alive; let stop_instance find it again
+                instance.set_status(InstanceStatus::Running)?;
+                self.stop_instance(instance_id_str, process_manager).await
+            }
+            FailedOperation::Restore { checkpoint_name, options } => {
+                self.restore_instance_to_existing(instance_id_str, &checkpoint_name, &options, criu_manager, process_manager)
+                    .await
+            }
+        };
+
+        if let Some(instance) = self.instances.get(&instance_id) {
+            instance.save_metadata()?;
+        }
+        result
+    }
+
+    /// Start an instance's program again from its stored program, args, env and working directory
+    async fn launch_stored(instance: &mut Instance, process_manager: &ProcessManager, audit: &AuditLog) -> Result<u32> {
+        let started = process_manager
+            .start_process_with_mode(
+                instance.id,
+                &instance.program,
+                &instance.args,
+                &instance.env,
+                &instance.working_dir,
+                instance.start_mode.clone(),
+                instance.output_file.as_deref(),
+                false,
+                LineStamp::new(instance.output_timestamps, instance.created_at),
+                instance.append_only,
+            )
+            .await;
+        audit.record_result("start", Some(instance.id), None, &started);
+        match started {
+            Ok(pid) => {
+                instance.pid = Some(pid);
+                instance.child_pids.clear();
+                instance.set_status(InstanceStatus::Running)?;
+                instance.clear_failure();
+                info!("Started instance {} with PID: {}", instance.short_id(), pid);
+                Ok(pid)
+            }
+            Err(e) => {
+                instance.mark_failed(FailedOperation::Start, &e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Change a stopped instance's stored args, environment or working directory
+    pub fn edit_instance(&mut self, instance_id_str: &str, edit: &InstanceEdit) -> Result<()> {
+        let instance_id = self.resolve_instance_id(instance_id_str)?;
+        let instance = self
+            .instances
+            .get_mut(&instance_id)
+            .ok_or_else(|| CriuCliError::InstanceNotFound(instance_id_str.to_string()))?;
+
+        if !matches!(instance.status, InstanceStatus::Stopped | InstanceStatus::Failed) {
+            return Err(CriuCliError::ProcessError(format!(
+                "Instance {} is {}, stop it before editing",
+                instance_id_str, instance.status
+            )));
+        }
+
+        if let Some(cwd) = &edit.cwd {
+            let cwd = if cwd.is_absolute() { cwd.clone() } else { env::current_dir().map_err(CriuCliError::IoError)?.join(cwd) };
+            if !cwd.is_dir() {
+                return Err(CriuCliError::ProcessError(format!("Working directory {} does not exist", cwd.display())));
+            }
+            instance.working_dir = cwd;
+        }
+        if let Some(args) = &edit.args {
+            instance.args = args.clone();
+        }
+        for (key, value) in &edit.env {
+            match instance.env.iter_mut().find(|(existing, _)| existing == key) {
+                Some(entry) => entry.1 = value.clone(),
+                None => instance.env.push((key.clone(), value.clone())),
+            }
+        }
+
+        instance.save_metadata()?;
+        info!("Edited instance {}: {} {}", instance.short_id(), instance.program, instance.args.join(" "));
+        self.audit.record("edit", Some(instance.id), None, None);
+        Ok(())
+    }
+
+    /// Stop the instance if it is running, then start it again under the same ID with its stored config
+    pub async fn restart_instance(
+        &mut self,
+        instance_id_str: &str,
+        process_manager: Arc<ProcessManager>,
+    ) -> Result<u32> {
+        let instance_id = self.resolve_instance_id(instance_id_str)?;
+        let status = self
+            .instances
+            .get(&instance_id)
+            .map(|instance| instance.status.clone())
+            .ok_or_else(|| CriuCliError::InstanceNotFound(instance_id_str.to_string()))?;
+
+        match status {
+            InstanceStatus::Running | InstanceStatus::Paused => {
+                self.stop_instance(&instance_id.to_string(), process_manager.clone()).await?;
+            }
+            InstanceStatus::Stopped | InstanceStatus::Failed => {}
+            other => {
+                return Err(CriuCliError::ProcessError(format!(
+                    "Instance {} is {}, it cannot be restarted",
+                    instance_id_str, other
+                )));
+            }
+        }
+
+        let instance = self
+            .instances
+            .get_mut(&instance_id)
+            .ok_or_else(|| CriuCliError::InstanceNotFound(instance_id_str.to_string()))?;
+        info!("Restarting instance {}: {} {}", instance.short_id(), instance.program, instance.args.join(" "));
+        let result = Self::launch_stored(instance, &process_manager, &self.audit).await;
+        instance.save_metadata()?;
+        result
+    }
+
     pub async fn restore_instance(
         &mut self,
         checkpoint_name: &str,
@@ -371,7 +705,7 @@ impl InstanceManager {
                     // Update the original instance
                     if let Some(instance) = self.instances.get_mut(&original_id) {
                         instance.pid = Some(pid);
-                        instance.status = InstanceStatus::Running;
+                        instance.set_status(InstanceStatus::Running)?;
                         let short_id = instance.short_id();
                         info!("Updated original instance {} with restored PID {}", short_id, pid);
                         (original_id, short_id)
@@ -388,56 +722,363 @@ impl InstanceManager {
                 };
 
                 // Register the restored process with the process manager
-                if let Err(e) = process_manager.register_restored_process(instance_id, pid, output_history).await {
+                let stamp = self.line_stamp(&instance_id);
+                let append_only = self.instances.get(&instance_id).is_some_and(|instance| instance.append_only);
+                if let Err(e) = process_manager.register_restored_process(instance_id, pid, output_history, stamp, append_only).await {
                     error!("Failed to register restored process: {}", e);
                     return Err(e);
                 }
 
                 info!("Instance {} restored from checkpoint '{}'", short_id, checkpoint_name);
+                self.audit.record("restore", Some(instance_id), None, None);
                 Ok(short_id)
             }
             Err(e) => {
                 error!("Failed to restore checkpoint '{}': {}", checkpoint_name, e);
+                let original_id = self.find_instance_with_checkpoint(checkpoint_name).map(|(id, _)| id);
+                self.audit.record("restore", original_id, None, Some(&e.to_string()));
                 Err(e)
             }
         }
     }
 
-    pub fn list_instances(&self) {
-        if self.instances.is_empty() {
-            println!("{}", ColorScheme::info("No instances running."));
-            return;
+    /// IDs of the instances carrying every one of `labels` whose status is in `statuses`, oldest first
+    pub fn select_by_labels(&self, labels: &[(String, String)], statuses: &[InstanceStatus]) -> Vec<Uuid> {
+        let mut selected: Vec<&Instance> = self
+            .instances
+            .values()
+            .filter(|instance| statuses.contains(&instance.status) && matches_labels(instance, labels))
+            .collect();
+        selected.sort_by_key(|instance| instance.created_at);
+        selected.into_iter().map(|instance| instance.id).collect()
+    }
+
+    /// Stopped/failed instances and leftover instance directories matching `status` (None: both,
+    /// plus leftovers whose metadata cannot be read) that have been idle for at least `older_than`
+    pub fn purge_candidates(&self, status: Option<&InstanceStatus>, older_than: Option<chrono::Duration>) -> Vec<PurgeCandidate> {
+        let wanted = |candidate: Option<&InstanceStatus>| match (candidate, status) {
+            (Some(candidate), Some(status)) => candidate == status,
+            (Some(candidate), None) => PURGEABLE_STATUSES.contains(candidate),
+            (None, Some(_)) => false,
+            (None, None) => true,
+        };
+        let old_enough = |dir: &Path| match older_than {
+            None => true,
+            Some(min_age) => idle_for(dir)
+                .and_then(|idle| chrono::Duration::from_std(idle).ok())
+                .is_some_and(|idle| idle >= min_age),
+        };
+
+        let mut candidates = Vec::new();
+        for instance in self.instances.values() {
+            if PURGEABLE_STATUSES.contains(&instance.status) && wanted(Some(&instance.status)) && old_enough(&instance.instance_dir) {
+                candidates.push(PurgeCandidate {
+                    short_id: instance.short_id(),
+                    managed: Some(instance.id),
+                    status: Some(instance.status.clone()),
+                    dir: instance.instance_dir.clone(),
+                    bytes: crate::stats::dir_disk_usage(&instance.instance_dir),
+                });
+            }
         }
 
-        // Print colorized header
-        println!("{:<10} {:<12} {:<20} {:<8} {:<10} {:<30}",
-            ColorScheme::table_header("ID"),
-            ColorScheme::table_header("STATUS"),
-            ColorScheme::table_header("PROGRAM"),
-            ColorScheme::table_header("PID"),
-            ColorScheme::table_header("MODE"),
-            ColorScheme::table_header("CREATED")
-        );
-        println!("{}", ColorScheme::separator(90));
+        // Directories of instances from earlier sessions, judged by the status they last recorded
+        let entries = std::fs::read_dir(INSTANCES_DIR).into_iter().flatten().flatten();
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(short_id) = name.strip_prefix("instance_") else {
+                continue;
+            };
+            if self.instance_by_short_id.contains_key(short_id) || !entry.file_type().is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            let dir = entry.path();
+            let recorded = Instance::load_metadata(&dir.join("metadata.json")).ok().map(|instance| instance.status);
+            let purgeable = match recorded {
+                Some(ref recorded) => PURGEABLE_STATUSES.contains(recorded),
+                None => idle_for(&dir).is_some_and(|idle| idle >= UNKNOWN_LEFTOVER_MIN_IDLE),
+            };
+            if purgeable && wanted(recorded.as_ref()) && old_enough(&dir) {
+                candidates.push(PurgeCandidate {
+                    short_id: short_id.to_string(),
+                    managed: None,
+                    status: recorded,
+                    bytes: crate::stats::dir_disk_usage(&dir),
+                    dir,
+                });
+            }
+        }
+
+        candidates.sort_by(|a, b| a.short_id.cmp(&b.short_id));
+        candidates
+    }
+
+    /// Take a snapshot of every running instance whose snapshot interval has elapsed at `now`,
+    /// then prune its snapshots to the retain count. Returns the snapshot names taken or the
+    /// errors hit. The manager is locked only to pick the instances and to record the results,
+    /// not while CRIU dumps, so commands keep working during a long snapshot.
+    pub async fn take_due_snapshots(
+        instance_manager: &Arc<tokio::sync::Mutex<InstanceManager>>,
+        criu_manager: Arc<CriuManager>,
+        process_manager: Arc<ProcessManager>,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Vec<(String, Result<String>)> {
+        let name = format!("{}{}", SNAPSHOT_PREFIX, now.format("%Y%m%dT%H%M%SZ"));
+        let mut results = Vec::new();
+        let due: Vec<(Uuid, u32, PathBuf, CheckpointHooks)> = {
+            let mut manager = instance_manager.lock().await;
+            manager.instances.values_mut()
+                .filter(|instance| instance.status == InstanceStatus::Running)
+                .filter(|instance| match (instance.snapshot_policy, instance.last_snapshot_at) {
+                    (Some(policy), Some(last)) => (now - last).num_seconds() >= policy.interval_secs as i64,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                })
+                .filter_map(|instance| {
+                    // A failed snapshot is retried at the next interval, not every check
+                    instance.last_snapshot_at = Some(now);
+                    if let Err(e) = instance.save_metadata() {
+                        results.push((instance.short_id(), Err(e)));
+                        return None;
+                    }
+                    let pid = instance.pid?;
+                    Some((instance.id, pid, instance.checkpoints_dir().join(&name), instance.checkpoint_hooks.clone()))
+                })
+                .collect()
+        };
+
+        for (instance_id, pid, checkpoint_dir, hooks) in due {
+            let short_id = instance_id.to_string()[..8].to_string();
+            let output_history = process_manager.get_output_history(&instance_id).await;
+
+            // Leave the process running
+            let dumped = criu_manager
+                .create_checkpoint_in_dir(pid, &name, &checkpoint_dir, &instance_id, output_history, &hooks, false, true)
+                .await;
+
+            let mut manager = instance_manager.lock().await;
+            let result = match dumped {
+                Ok(checkpoint_dir) => manager.record_snapshot(&instance_id, &name, checkpoint_dir).map(|_| name.clone()),
+                Err(e) => Err(e),
+            };
+            if let Some(instance) = manager.instances.get(&instance_id) {
+                manager.audit.record("checkpoint", Some(instance.id), None, result.as_ref().err().map(|e| e.to_string()).as_deref());
+            }
+            let result = result.and_then(|name| {
+                for pruned in manager.prune_snapshots(&instance_id)? {
+                    info!("Pruned snapshot '{}' of instance {}", pruned, short_id);
+                }
+                Ok(name)
+            });
+            results.push((short_id, result));
+        }
+        results
+    }
+
+    /// Add a snapshot dumped without the manager locked to its instance
+    fn record_snapshot(&mut self, instance_id: &Uuid, name: &str, checkpoint_dir: PathBuf) -> Result<()> {
+        let instance = self.instances.get_mut(instance_id)
+            .ok_or_else(|| CriuCliError::InstanceNotFound(instance_id.to_string()))?;
+        instance.add_checkpoint(name.to_string(), checkpoint_dir);
+        instance.refresh_child_pids();
+        // The snapshot is not usable from a restart without it
+        instance.save_metadata()
+    }
+
+    /// Delete an instance's oldest snapshots beyond its retain count, returning their names
+    fn prune_snapshots(&mut self, instance_id: &Uuid) -> Result<Vec<String>> {
+        let instance = match self.instances.get_mut(instance_id) {
+            Some(instance) => instance,
+            None => return Ok(Vec::new()),
+        };
+        let retain = match instance.snapshot_policy {
+            Some(policy) => policy.retain,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut snapshots: Vec<(chrono::DateTime<chrono::Utc>, String, PathBuf)> = instance.checkpoints.values()
+            .filter(|checkpoint| checkpoint.name.starts_with(SNAPSHOT_PREFIX))
+            .map(|checkpoint| (checkpoint.created_at, checkpoint.name.clone(), checkpoint.checkpoint_dir.clone()))
+            .collect();
+        if snapshots.len() <= retain {
+            return Ok(Vec::new());
+        }
+        snapshots.sort();
+
+        // Keep snapshots that later dumps build on: the sync base and any checkpoint's `parent`
+        let mut pinned = crate::checkpoint_archive::referenced_bases(&instance.checkpoints_dir());
+        pinned.extend(instance.sync_base.clone());
+
+        let excess = snapshots.len() - retain;
+        let mut pruned = Vec::new();
+        for (_, name, dir) in snapshots.into_iter().filter(|(_, name, _)| !pinned.contains(name)).take(excess) {
+            if let Err(e) = std::fs::remove_dir_all(&dir) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Failed to delete snapshot '{}' of instance {}: {}", name, instance.short_id(), e);
+                    continue;
+                }
+            }
+            instance.checkpoints.remove(&name);
+            pruned.push(name);
+        }
+
+        if !pruned.is_empty() {
+            // The pruned snapshots are gone; metadata still listing them would offer them for restore
+            instance.save_metadata()?;
+            // Deduplicated snapshots leave blobs nothing links to any more
+            if let Err(e) = crate::checkpoint_dedup::prune_blobs(&instance.instance_dir) {
+                warn!("Failed to prune checkpoint blobs of instance {}: {}", instance.short_id(), e);
+            }
+        }
+        Ok(pruned)
+    }
+
+    /// Start the scheduler taking `--snapshot-interval` snapshots
+    pub fn start_snapshot_task(
+        instance_manager: Arc<tokio::sync::Mutex<InstanceManager>>,
+        criu_manager: Arc<CriuManager>,
+        process_manager: Arc<ProcessManager>,
+    ) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SNAPSHOT_CHECK_INTERVAL);
+
+            loop {
+                interval.tick().await;
+
+                let results = InstanceManager::take_due_snapshots(
+                    &instance_manager,
+                    criu_manager.clone(),
+                    process_manager.clone(),
+                    chrono::Utc::now(),
+                ).await;
+                for (short_id, result) in results {
+                    match result {
+                        Ok(name) => info!("Took snapshot '{}' of instance {}", name, short_id),
+                        Err(e) => warn!("Scheduled snapshot of instance {} failed: {}", short_id, e),
+                    }
+                }
+            }
+        });
+    }
+
+    /// Remove purge candidates from the manager and delete their directories. An instance that
+    /// is no longer stopped or failed (e.g. restarted since it was selected) is skipped.
+    pub fn purge(&mut self, candidates: Vec<PurgeCandidate>) -> Vec<(PurgeCandidate, std::result::Result<(), String>)> {
+        let mut results = Vec::new();
+        for candidate in candidates {
+            if let Some(instance_id) = candidate.managed {
+                match self.instances.get(&instance_id) {
+                    Some(instance) if PURGEABLE_STATUSES.contains(&instance.status) => {}
+                    Some(instance) => {
+                        let reason = format!("instance is {} now", instance.status);
+                        results.push((candidate, Err(reason)));
+                        continue;
+                    }
+                    None => {}
+                }
+                self.remove_instance(&instance_id.to_string());
+            }
+            let removed = match std::fs::remove_dir_all(&candidate.dir) {
+                Ok(()) => Ok(()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(format!("{}: {}", candidate.dir.display(), e)),
+            };
+            if removed.is_ok() {
+                info!("Purged instance {} ({} bytes)", candidate.short_id, candidate.bytes);
+            }
+            results.push((candidate, removed));
+        }
+        results
+    }
 
-        // Track PIDs to detect conflicts
-        let mut pid_usage: std::collections::HashMap<u32, Vec<String>> = std::collections::HashMap::new();
+    /// Print everything recorded about one instance
+    pub fn print_instance_info(&self, instance_id_str: &str) -> Result<()> {
+        let instance_id = self.resolve_instance_id(instance_id_str)?;
+        let instance = self
+            .instances
+            .get(&instance_id)
+            .ok_or_else(|| CriuCliError::InstanceNotFound(instance_id_str.to_string()))?;
+
+        let field = |name: &str, value: String| println!("  {:<14} {}", ColorScheme::table_header(name), value);
+        println!("{} {}", ColorScheme::header("Instance"), ColorScheme::instance_id(&instance.id.to_string()));
+        field("Status", ColorScheme::format_status(&instance.status.to_string()));
+        field("Program", format!("{} {}", ColorScheme::program(&instance.program), instance.args.join(" ")));
+        field("PID", instance.pid.map(|pid| ColorScheme::pid(&pid.to_string())).unwrap_or_else(|| "N/A".to_string()));
+        field("Mode", ColorScheme::format_mode(&format!("{:?}", instance.start_mode)));
+        field("Working dir", ColorScheme::path(&instance.working_dir.display().to_string()));
+        field("Created", ColorScheme::timestamp(&instance.created_at.format("%Y-%m-%d %H:%M:%S").to_string()));
+        field("Labels", if instance.labels.is_empty() { "none".to_string() } else { format_labels(&instance.labels) });
+        if !instance.env.is_empty() {
+            field("Env", instance.env.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>().join(","));
+        }
+        field("Auto-sync", if instance.sync_enabled { "enabled".to_string() } else { "disabled".to_string() });
+        if let Some(policy) = instance.snapshot_policy {
+            field("Snapshots", policy.to_string());
+        }
+        if let Some(ref output_file) = instance.output_file {
+            field("Output file", ColorScheme::path(&output_file.display().to_string()));
+        }
+        if instance.append_only {
+            field("Output log", "append-only".to_string());
+        }
+        if !instance.affinity.is_empty() {
+            field("Affinity", instance.affinity.to_string());
+        }
+        if let Some(stats) = crate::criu_stats::get(&instance.id) {
+            if let Some(dump) = stats.dump {
+                field("Last dump", format!(
+                    "frozen {:.1} ms, {} pages written ({:.1} MiB), {} scanned",
+                    dump.frozen_time_us as f64 / 1000.0,
+                    dump.pages_written,
+                    dump.memory_written() as f64 / (1024.0 * 1024.0),
+                    dump.pages_scanned
+                ));
+            }
+            if let Some(restore) = stats.restore {
+                field("Last restore", format!(
+                    "{:.1} ms, {} pages restored",
+                    restore.restore_time_us as f64 / 1000.0,
+                    restore.pages_restored
+                ));
+            }
+        }
+        let mut checkpoints: Vec<&String> = instance.checkpoints.keys().collect();
+        checkpoints.sort();
+        if !checkpoints.is_empty() {
+            field("Checkpoints", checkpoints.iter().map(|name| ColorScheme::checkpoint(name)).collect::<Vec<_>>().join(", "));
+        }
+        if let Some(ref last_error) = instance.last_error {
+            field("Last error", ColorScheme::error(last_error));
+        }
+        if !instance.status_history.is_empty() {
+            println!("  {}", ColorScheme::table_header("Status history"));
+            for transition in &instance.status_history {
+                println!("    {} {} -> {}",
+                    ColorScheme::timestamp(&transition.at.format("%Y-%m-%d %H:%M:%S").to_string()),
+                    ColorScheme::format_status(&transition.from.to_string()),
+                    ColorScheme::format_status(&transition.to.to_string())
+                );
+            }
+        }
+        Ok(())
+    }
 
-        // First pass: collect all PIDs and their instances
+    /// Short IDs of the instances claiming each PID, to detect conflicts
+    fn pid_usage(&self) -> HashMap<u32, Vec<String>> {
+        let mut pid_usage: HashMap<u32, Vec<String>> = HashMap::new();
         for instance in self.instances.values() {
             if let Some(pid) = instance.pid {
-                pid_usage.entry(pid).or_insert_with(Vec::new).push(instance.short_id());
+                pid_usage.entry(pid).or_default().push(instance.short_id());
             }
         }
+        pid_usage
+    }
 
+    /// Instances passing `filter` with their effective status, in `sort` order
+    fn list_rows(&self, filter: &InstanceFilter, sort: InstanceSort) -> Vec<(&Instance, String)> {
+        let pid_usage = self.pid_usage();
+        let mut rows = Vec::new();
         for instance in self.instances.values() {
-            let pid_str = instance.pid.map_or("N/A".to_string(), |p| p.to_string());
-            let created_str = instance.created_at.format("%Y-%m-%d %H:%M:%S").to_string();
-            let mode_str = match instance.start_mode {
-                StartMode::Normal => "Normal",
-                StartMode::Detached => "Detached",
-            };
-
             // Check if process is actually running and handle PID conflicts
             let actual_status = if let Some(pid) = instance.pid {
                 // Check for PID conflicts
@@ -462,19 +1103,102 @@ impl InstanceManager {
                 instance.status.to_string()
             };
 
+            if filter.matches(instance, &actual_status) {
+                rows.push((instance, actual_status));
+            }
+        }
+
+        // HashMap order changes between runs, so sort before rendering
+        rows.sort_by(|a, b| sort.compare((a.0, &a.1), (b.0, &b.1)));
+        rows
+    }
+
+    /// `list --json`: the rows of the table as an array, in the same order
+    pub fn list_instances_json(&self, filter: &InstanceFilter, sort: InstanceSort) -> serde_json::Value {
+        let rows = self.list_rows(filter, sort);
+        rows.iter()
+            .map(|(instance, actual_status)| serde_json::json!({
+                "id": instance.id,
+                "short_id": instance.short_id(),
+                "status": actual_status,
+                "program": instance.program,
+                "args": instance.args,
+                "pid": instance.pid,
+                "mode": instance.start_mode,
+                "created_at": instance.created_at,
+                "labels": instance.labels,
+            }))
+            .collect()
+    }
+
+    pub fn list_instances(&self, filter: &InstanceFilter, sort: InstanceSort) {
+        if self.instances.is_empty() {
+            println!("{}", ColorScheme::info("No instances running."));
+            return;
+        }
+
+        // Print colorized header
+        println!("{:<10} {:<12} {:<20} {:<8} {:<10} {:<30}",
+            ColorScheme::table_header("ID"),
+            ColorScheme::table_header("STATUS"),
+            ColorScheme::table_header("PROGRAM"),
+            ColorScheme::table_header("PID"),
+            ColorScheme::table_header("MODE"),
+            ColorScheme::table_header("CREATED")
+        );
+        println!("{}", ColorScheme::separator(90));
+
+        let rows = self.list_rows(filter, sort);
+        let pid_usage = self.pid_usage();
+
+        // One /proc scan for every instance's descendants
+        let processes = crate::criu_compat::ProcessTable::read();
+        for (instance, actual_status) in &rows {
+            // Running instances show how many descendants share their checkpoints, e.g. "1234+2"
+            let pid_str = match instance.pid {
+                Some(pid) if actual_status == "Running" || actual_status == "Paused" => {
+                    match processes.descendants(pid).len() {
+                        0 => pid.to_string(),
+                        children => format!("{}+{}", pid, children),
+                    }
+                }
+                Some(pid) => pid.to_string(),
+                None => "N/A".to_string(),
+            };
+            let created_str = instance.created_at.format("%Y-%m-%d %H:%M:%S").to_string();
+            let mode_str = match instance.start_mode {
+                StartMode::Normal => "Normal",
+                StartMode::Detached => "Detached",
+            };
+
             println!(
                 "{:<10} {:<12} {:<20} {:<8} {:<10} {:<30}",
                 ColorScheme::instance_id(&instance.short_id()),
-                ColorScheme::format_status(&actual_status),
+                ColorScheme::format_status(actual_status),
                 ColorScheme::program(&instance.program),
                 if pid_str == "N/A" { pid_str } else { ColorScheme::pid(&pid_str) },
                 ColorScheme::format_mode(mode_str),
                 ColorScheme::timestamp(&created_str)
             );
+            if let (InstanceStatus::Failed, Some(last_error)) = (&instance.status, &instance.last_error) {
+                println!("{:<10} {}", "", ColorScheme::error(&format!("last error: {}", last_error)));
+            }
+            if !instance.affinity.is_empty() {
+                println!("{:<10} {}", "", ColorScheme::info(&format!("affinity: {}", instance.affinity)));
+            }
+            if !instance.labels.is_empty() {
+                println!("{:<10} {}", "", ColorScheme::info(&format!("labels: {}", format_labels(&instance.labels))));
+            }
+        }
+
+        if rows.is_empty() && !filter.is_empty() {
+            println!("{}", ColorScheme::info("No instances match the filter."));
         }
 
         // Show warnings for PID conflicts
-        for (pid, instances) in pid_usage.iter() {
+        let mut conflicts: Vec<_> = pid_usage.iter().collect();
+        conflicts.sort_by_key(|(pid, _)| **pid);
+        for (pid, instances) in conflicts {
             if instances.len() > 1 {
                 println!("\n⚠️  Warning: PID {} is claimed by multiple instances: {}",
                          pid, instances.join(", "));
@@ -545,7 +1269,7 @@ impl InstanceManager {
         );
 
         instance.pid = Some(pid);
-        instance.status = InstanceStatus::Running;
+        instance.set_status(InstanceStatus::Running)?;
 
         let short_id = instance.short_id();
         let instance_id = instance.id;
@@ -561,9 +1285,51 @@ impl InstanceManager {
         self.resolve_instance_id(instance_id_str).is_ok()
     }
 
+    /// Write the metadata of every instance, returning how many could not be saved
+    pub fn save_all_metadata(&self) -> usize {
+        let mut failed = 0;
+        for instance in self.instances.values() {
+            if let Err(e) = instance.save_metadata() {
+                warn!("Failed to save metadata of instance {}: {}", instance.short_id(), e);
+                failed += 1;
+            }
+        }
+        failed
+    }
+
     /// Get all instances as a vector
     pub fn get_all_instances(&self) -> Vec<Instance> {
-        self.instances.values().cloned().collect()
+        let mut instances: Vec<Instance> = self.instances.values().cloned().collect();
+        instances.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.short_id().cmp(&b.short_id())));
+        instances
+    }
+
+    /// Statistics of the last CRIU dump and restore of each instance that has any
+    pub fn criu_stats(&self) -> HashMap<Uuid, crate::criu_stats::CriuStats> {
+        self.instances.keys().filter_map(|id| crate::criu_stats::get(id).map(|stats| (*id, stats))).collect()
+    }
+
+    /// Reject a chosen instance ID that is taken, including by its short ID, which names
+    /// the instance directory
+    fn check_instance_id_free(&self, id: &Uuid) -> Result<()> {
+        let short_id = id.to_string()[..8].to_string();
+        if self.instances.contains_key(id) {
+            return Err(CriuCliError::ProcessError(format!("Instance ID {} is already in use", id)));
+        }
+        if let Some(existing) = self.instance_by_short_id.get(&short_id) {
+            return Err(CriuCliError::ProcessError(format!(
+                "Instance ID {} shares its short ID {} with instance {}", id, short_id, existing
+            )));
+        }
+        Ok(())
+    }
+
+    /// How the process of `instance_id` timestamps its captured output
+    fn line_stamp(&self, instance_id: &Uuid) -> LineStamp {
+        self.instances
+            .get(instance_id)
+            .map(|instance| LineStamp::new(instance.output_timestamps, instance.created_at))
+            .unwrap_or_else(LineStamp::off)
     }
 
     /// Get instance by ID (read-only)
@@ -607,6 +1373,57 @@ impl InstanceManager {
         }
     }
 
+    /// Record that an instance's process exited on its own
+    pub fn mark_exited(&mut self, instance_id_str: &str) -> Result<()> {
+        let instance = self
+            .get_instance_by_id_mut(instance_id_str)
+            .ok_or_else(|| CriuCliError::InstanceNotFound(instance_id_str.to_string()))?;
+
+        instance.set_status(InstanceStatus::Stopped)?;
+        instance.pid = None;
+        instance.save_metadata()?;
+
+        info!("Instance {} exited", instance.short_id());
+        Ok(())
+    }
+
+    /// Enable or disable periodic checkpoint auto-sync for an instance
+    pub fn set_sync_enabled(&mut self, instance_id_str: &str, enabled: bool) -> Result<()> {
+        let instance = self
+            .get_instance_by_id_mut(instance_id_str)
+            .ok_or_else(|| CriuCliError::InstanceNotFound(instance_id_str.to_string()))?;
+
+        instance.sync_enabled = enabled;
+        instance.save_metadata()?;
+
+        info!("Auto-sync {} for instance {}", if enabled { "enabled" } else { "disabled" }, instance.short_id());
+        Ok(())
+    }
+
+    /// Record the checkpoint the next auto-sync of an instance builds on
+    pub fn set_sync_base(&mut self, instance_id_str: &str, checkpoint_name: Option<String>) -> Result<()> {
+        let instance = self
+            .get_instance_by_id_mut(instance_id_str)
+            .ok_or_else(|| CriuCliError::InstanceNotFound(instance_id_str.to_string()))?;
+
+        instance.sync_base = checkpoint_name;
+        instance.save_metadata()?;
+        Ok(())
+    }
+
+    /// Set the commands run before and after checkpoints of an instance
+    pub fn set_checkpoint_hooks(&mut self, instance_id_str: &str, hooks: CheckpointHooks) -> Result<()> {
+        let instance = self
+            .get_instance_by_id_mut(instance_id_str)
+            .ok_or_else(|| CriuCliError::InstanceNotFound(instance_id_str.to_string()))?;
+
+        instance.checkpoint_hooks = hooks;
+        instance.save_metadata()?;
+
+        info!("Updated checkpoint hooks for instance {}", instance.short_id());
+        Ok(())
+    }
+
     pub fn resolve_instance_id(&self, instance_id_str: &str) -> Result<Uuid> {
         // Try to parse as full UUID first
         if let Ok(uuid) = Uuid::parse_str(instance_id_str) {
@@ -623,42 +1440,1050 @@ impl InstanceManager {
         Err(CriuCliError::InstanceNotFound(instance_id_str.to_string()))
     }
 
-    async fn find_actual_process_pid(&self, program_path: &str) -> Option<u32> {
-        // Extract just the program name without path
-        let program_basename = std::path::Path::new(program_path)
-            .file_name()
-            .and_then(|name| name.to_str())
-            .unwrap_or(program_path);
+    /// Resolve an instance ID, requiring the instance to be in one of `allowed`.
+    /// A short ID shared by several instances (e.g. a shadow and a remembered running copy)
+    /// resolves to the one whose status fits; otherwise the error names the status it has.
+    pub fn resolve_instance_id_with_status(&self, instance_id_str: &str, allowed: &[InstanceStatus]) -> Result<Uuid> {
+        let candidates: Vec<&Instance> = match Uuid::parse_str(instance_id_str) {
+            Ok(uuid) => self.instances.get(&uuid).into_iter().collect(),
+            Err(_) => self.instances.values().filter(|instance| instance.short_id() == instance_id_str).collect(),
+        };
+        if candidates.is_empty() {
+            // Fall back to the short ID index, which also covers non-UUID-prefix aliases
+            let uuid = self.resolve_instance_id(instance_id_str)?;
+            return match self.instances.get(&uuid) {
+                Some(instance) if !allowed.contains(&instance.status) => Err(Self::unexpected_status(instance_id_str, instance, allowed)),
+                Some(_) => Ok(uuid),
+                None => Err(CriuCliError::InstanceNotFound(instance_id_str.to_string())),
+            };
+        }
+
+        let matching: Vec<&&Instance> = candidates.iter().filter(|instance| allowed.contains(&instance.status)).collect();
+        match matching.as_slice() {
+            [instance] => Ok(instance.id),
+            [] => Err(Self::unexpected_status(instance_id_str, candidates[0], allowed)),
+            _ => Err(CriuCliError::ProcessError(format!(
+                "Instance ID {} is ambiguous ({} matches), use the full ID",
+                instance_id_str,
+                matching.len()
+            ))),
+        }
+    }
+
+    fn unexpected_status(instance_id_str: &str, instance: &Instance, allowed: &[InstanceStatus]) -> CriuCliError {
+        let expected: Vec<String> = allowed.iter().map(|status| status.to_string()).collect();
+        CriuCliError::UnexpectedStatus(instance_id_str.to_string(), instance.status.clone(), expected.join(" or "))
+    }
 
-        info!("Looking for actual PID of process: {}", program_basename);
+    /// Find the single running process that matches this instance, or None if there is no
+    /// match or the match is ambiguous (guessing could signal an unrelated process)
+    fn find_actual_process_pid(instance: &Instance) -> Option<u32> {
+        info!("Looking for actual PID of instance {} ({})", instance.short_id(), instance.program);
 
-        // Read /proc to find processes
+        let mut candidates = Vec::new();
         if let Ok(entries) = std::fs::read_dir("/proc") {
             for entry in entries.flatten() {
-                if let Ok(pid_str) = entry.file_name().into_string() {
-                    if let Ok(pid) = pid_str.parse::<u32>() {
-                        // Read the command line
-                        let cmdline_path = format!("/proc/{}/cmdline", pid);
-                        if let Ok(cmdline) = std::fs::read_to_string(&cmdline_path) {
-                            // cmdline is null-separated, take the first part
-                            let cmd = cmdline.split('\0').next().unwrap_or("");
-                            let cmd_basename = std::path::Path::new(cmd)
-                                .file_name()
-                                .and_then(|name| name.to_str())
-                                .unwrap_or(cmd);
-
-                            // Exact match for the program name
-                            if cmd_basename == program_basename && !cmd.contains("sh") && !cmd.contains("bash") {
-                                info!("Found actual process: PID {} with cmdline: {}", pid, cmdline.replace('\0', " "));
-                                return Some(pid);
-                            }
-                        }
+                if let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) {
+                    if Self::process_matches_instance(pid, instance) {
+                        candidates.push(pid);
                     }
                 }
             }
         }
 
-        warn!("No actual process found with name: {}", program_basename);
-        None
+        match candidates.as_slice() {
+            [pid] => {
+                info!("Found actual process for instance {}: PID {}", instance.short_id(), pid);
+                Some(*pid)
+            }
+            [] => {
+                warn!("No running process matches instance {}", instance.short_id());
+                None
+            }
+            _ => {
+                warn!("Ambiguous process match for instance {}: {:?}", instance.short_id(), candidates);
+                None
+            }
+        }
+    }
+
+    /// Whether `pid` runs this instance's program with its arguments and, when the instance has
+    /// per-stream logs, writes to its stdout.log
+    fn process_matches_instance(pid: u32, instance: &Instance) -> bool {
+        let cmdline = match std::fs::read(format!("/proc/{}/cmdline", pid)) {
+            Ok(raw) if !raw.is_empty() => raw,
+            _ => return false,
+        };
+        let mut argv: Vec<String> = cmdline
+            .split(|&b| b == 0)
+            .map(|part| String::from_utf8_lossy(part).into_owned())
+            .collect();
+        // cmdline ends with a NUL, leaving one empty trailing element
+        if argv.last().map(|a| a.is_empty()).unwrap_or(false) {
+            argv.pop();
+        }
+        if argv.is_empty() {
+            return false;
+        }
+
+        // Same resolution as the launcher: relative programs live in the working directory
+        let expected_path = if std::path::Path::new(&instance.program).is_absolute() {
+            std::path::PathBuf::from(&instance.program)
+        } else {
+            instance.working_dir.join(&instance.program)
+        };
+        let expected_path = std::fs::canonicalize(&expected_path).unwrap_or(expected_path);
+        let resolves_to_program = |arg: &str| {
+            let path = std::path::Path::new(arg);
+            let path = if path.is_absolute() { path.to_path_buf() } else { instance.working_dir.join(path) };
+            std::fs::canonicalize(&path).map(|p| p == expected_path).unwrap_or(false)
+        };
+
+        // The program is argv[0] when executed directly, or argv[1] under an interpreter (scripts)
+        let exe_matches = std::fs::read_link(format!("/proc/{}/exe", pid))
+            .map(|exe| exe == expected_path)
+            .unwrap_or(false);
+        let program_index = if exe_matches || argv.first().map(|a| resolves_to_program(a)).unwrap_or(false) {
+            0
+        } else if argv.get(1).map(|a| resolves_to_program(a)).unwrap_or(false) {
+            1
+        } else {
+            return false;
+        };
+
+        if argv[program_index + 1..] != instance.args[..] {
+            return false;
+        }
+
+        // Correlate via the output file the launcher opened for this instance
+        let stdout_log = instance.instance_dir.join("output").join("stdout.log");
+        match std::fs::canonicalize(&stdout_log) {
+            Ok(stdout_log) => std::fs::read_dir(format!("/proc/{}/fd", pid))
+                .map(|fds| {
+                    fds.flatten()
+                        .any(|fd| std::fs::read_link(fd.path()).map(|t| t == stdout_log).unwrap_or(false))
+                })
+                .unwrap_or(false),
+            Err(_) => true, // No per-stream log to correlate with (older instances)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint_engine::{CheckpointEngine, DumpRequest, EngineOutput, MockEngine, RestoreRequest};
+
+    /// Like CRIU, kills the process unless the dump leaves it running, and restores a new `sleep`
+    /// detached from us, as `criu restore -d` does
+    struct StoppingEngine;
+
+    impl CheckpointEngine for StoppingEngine {
+        fn name(&self) -> &str {
+            "stopping"
+        }
+
+        fn check(&self) -> Result<EngineOutput> {
+            MockEngine::new().check()
+        }
+
+        fn dump(&self, request: &DumpRequest) -> Result<EngineOutput> {
+            for image in ["inventory.img", "pstree.img", &format!("core-{}.img", request.pid)] {
+                std::fs::write(request.images_dir.join(image), request.pid.to_string())?;
+            }
+            if !request.leave_running {
+                let _ = nix::sys::signal::kill(nix::unistd::Pid::from_raw(request.pid as i32), nix::sys::signal::Signal::SIGKILL);
+            }
+            MockEngine::new().dump(request)
+        }
+
+        fn pre_dump(&self, request: &DumpRequest) -> Result<EngineOutput> {
+            MockEngine::new().pre_dump(request)
+        }
+
+        fn restore(&self, request: &RestoreRequest) -> Result<EngineOutput> {
+            let pidfile = request.pidfile.clone().unwrap_or_else(|| request.images_dir.join("restored.pid"));
+            // A detached subshell parents and reaps the sleep, standing in for an init that reaps orphans
+            std::process::Command::new("sh")
+                .arg("-c")
+                .arg("(sleep 30 & echo $! > \"$PIDFILE.tmp\" && mv \"$PIDFILE.tmp\" \"$PIDFILE\"; wait) >/dev/null 2>&1 &")
+                .env("PIDFILE", &pidfile)
+                .status()?;
+            while !pidfile.exists() {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            MockEngine::new().restore(request)
+        }
+    }
+
+    /// Children of this test process that exited but were never reaped
+    fn zombie_children() -> Vec<u32> {
+        let own_pid = std::process::id().to_string();
+        std::fs::read_dir("/proc")
+            .unwrap()
+            .flatten()
+            .filter_map(|entry| {
+                let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+                let stat = std::fs::read_to_string(entry.path().join("stat")).ok()?;
+                let fields: Vec<&str> = stat.rsplit(')').next()?.split_whitespace().collect();
+                (fields.first() == Some(&"Z") && fields.get(1) == Some(&own_pid.as_str())).then_some(pid)
+            })
+            .collect()
+    }
+
+    fn process_gone(pid: u32) -> bool {
+        // A killed child lingers as a zombie until it is reaped
+        match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Ok(stat) => stat.rsplit(')').next().map(|rest| rest.trim_start().starts_with('Z')).unwrap_or(false),
+            Err(_) => true,
+        }
+    }
+
+    #[tokio::test]
+    async fn restore_from_a_missing_checkpoint_lists_the_instance_checkpoints() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let instance = Instance::new("sleep".to_string(), vec!["30".to_string()], PathBuf::from("/"));
+        let other = Instance::new("sleep".to_string(), vec!["30".to_string()], PathBuf::from("/"));
+        for name in ["manual-1", "auto-sync-20260101-000000"] {
+            std::fs::create_dir_all(instance.checkpoints_dir().join(name)).unwrap();
+        }
+        std::fs::create_dir_all(other.checkpoints_dir().join("elsewhere")).unwrap();
+        let instance_id = instance.id.to_string();
+        manager.add_instance(instance);
+        manager.add_instance(other);
+
+        let engine = Arc::new(MockEngine::new());
+        let criu_manager = Arc::new(CriuManager::new_with_engine(engine.clone()));
+        // Another instance's checkpoint is not one of this instance's
+        for name in ["typo", "elsewhere"] {
+            let result = manager
+                .restore_instance_to_existing(&instance_id, name, &RestoreOptions::default(), criu_manager.clone(), Arc::new(ProcessManager::new()))
+                .await;
+            match result {
+                Err(CriuCliError::CheckpointNotFound(message)) => {
+                    assert!(message.contains("available: auto-sync-20260101-000000, manual-1"), "{}", message)
+                }
+                other => panic!("expected CheckpointNotFound, got {:?}", other),
+            }
+        }
+        assert!(engine.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn restart_runs_the_edited_config() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let mut instance = Instance::new("sh".to_string(), vec!["-c".to_string(), "echo before".to_string()], env::current_dir().unwrap());
+        instance.status = InstanceStatus::Stopped;
+        let instance_id = instance.id;
+        manager.add_instance(instance);
+
+        let edit = InstanceEdit {
+            args: Some(vec!["-c".to_string(), "echo \"$GREETING after\"".to_string()]),
+            env: vec![("GREETING".to_string(), "hello".to_string())],
+            cwd: None,
+        };
+        manager.edit_instance(&instance_id.to_string(), &edit).unwrap();
+        let stored = Instance::load_metadata(&manager.instances[&instance_id].metadata_file).unwrap();
+        assert_eq!(stored.args, edit.args.clone().unwrap());
+
+        let process_manager = Arc::new(ProcessManager::new());
+        manager.restart_instance(&instance_id.to_string(), process_manager.clone()).await.unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            let output = process_manager.get_output_history(&instance_id).await.unwrap_or_default();
+            if output.iter().any(|line| line.ends_with("hello after")) {
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline, "restarted output: {:?}", output);
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_replace_restore_keeps_the_output_log() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let mut instance = Instance::new("sleep".to_string(), vec!["30".to_string()], PathBuf::from("/"));
+        instance.status = InstanceStatus::Stopped;
+        let instance_id = instance.id;
+        let checkpoint_name = format!("replace-{}", instance.short_id());
+        let checkpoint_dir = instance.checkpoints_dir().join(&checkpoint_name);
+        std::fs::create_dir_all(&checkpoint_dir).unwrap();
+        for image in ["inventory.img", "pstree.img", "core-1.img"] {
+            std::fs::write(checkpoint_dir.join(image), "image").unwrap();
+        }
+        let output_dir = instance.instance_dir.join("output");
+        std::fs::create_dir_all(&output_dir).unwrap();
+        std::fs::write(output_dir.join("process_output.log"), "before the restore\n").unwrap();
+        manager.add_instance(instance);
+
+        let engine = Arc::new(MockEngine::new());
+        engine.set_result(EngineOutput { success: false, exit_code: Some(1), stdout: String::new(), stderr: "no".to_string() });
+        let options = RestoreOptions { replace: true, ..Default::default() };
+        let result = manager
+            .restore_instance_to_existing(&instance_id.to_string(), &checkpoint_name, &options, Arc::new(CriuManager::new_with_engine(engine)), Arc::new(ProcessManager::new()))
+            .await;
+
+        assert!(matches!(result, Err(CriuCliError::CriuError(_))), "{:?}", result);
+        assert_eq!(std::fs::read_to_string(output_dir.join("process_output.log")).unwrap(), "before the restore\n");
+        assert_eq!(std::fs::read_dir(&output_dir).unwrap().count(), 1, "log was rotated");
+    }
+
+    #[tokio::test]
+    async fn replace_restore_starts_a_fresh_live_log_and_keeps_the_history() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let process_manager = Arc::new(ProcessManager::new());
+        let mut instance = Instance::new("sleep".to_string(), vec!["30".to_string()], PathBuf::from("/"));
+        instance.status = InstanceStatus::Stopped;
+        let instance_id = instance.id;
+        let checkpoint_name = format!("replace-{}", instance.short_id());
+        let checkpoint_dir = instance.checkpoints_dir().join(&checkpoint_name);
+        std::fs::create_dir_all(&checkpoint_dir).unwrap();
+        // The checkpointed PID has to be free again for the restore
+        let mut exited = std::process::Command::new("true").spawn().unwrap();
+        exited.wait().unwrap();
+        for image in ["inventory.img", "pstree.img", &format!("core-{}.img", exited.id())] {
+            std::fs::write(checkpoint_dir.join(image), "image").unwrap();
+        }
+        let output_dir = instance.instance_dir.join("output");
+        std::fs::create_dir_all(&output_dir).unwrap();
+        std::fs::write(output_dir.join("process_output.log"), "before the restore\n").unwrap();
+        manager.add_instance(instance);
+
+        let options = RestoreOptions { replace: true, ..Default::default() };
+        manager
+            .restore_instance_to_existing(&instance_id.to_string(), &checkpoint_name, &options, Arc::new(CriuManager::new_with_engine(Arc::new(StoppingEngine))), process_manager.clone())
+            .await
+            .unwrap();
+        let restored_pid = manager.get_instance_by_id(&instance_id.to_string()).unwrap().pid.unwrap();
+
+        // The restored process writes to the live log it inherited
+        let mut live_log = std::fs::OpenOptions::new().append(true).open(output_dir.join("process_output.log")).unwrap();
+        std::io::Write::write_all(&mut live_log, b"after the restore\n").unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while process_manager.get_output_history(&instance_id).await.unwrap_or_default().is_empty() {
+            assert!(std::time::Instant::now() < deadline, "post-restore output never reached the tailer");
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        assert_eq!(process_manager.get_output_history(&instance_id).await.unwrap(), vec!["[OUTPUT] after the restore".to_string()]);
+        assert_eq!(std::fs::read_to_string(output_dir.join("process_output.log")).unwrap(), "after the restore\n");
+        // `logs --all` reads the rotated log ahead of the live one
+        assert_eq!(
+            ProcessManager::stored_output_lines(&instance_id).unwrap(),
+            vec!["before the restore".to_string(), "after the restore".to_string()],
+        );
+        let _ = nix::sys::signal::kill(nix::unistd::Pid::from_raw(restored_pid as i32), nix::sys::signal::Signal::SIGKILL);
+    }
+
+    #[tokio::test]
+    async fn checkpoint_with_stop_ends_the_process_and_restores() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let process_manager = Arc::new(ProcessManager::new());
+        let criu_manager = Arc::new(CriuManager::new_with_engine(Arc::new(StoppingEngine)));
+        let instance_id = manager.start_instance("sleep".to_string(), vec!["30".to_string()], process_manager.clone()).await.unwrap();
+        let pid = manager.get_instance_by_id(&instance_id).unwrap().pid.unwrap();
+
+        manager
+            .checkpoint_instance(&instance_id, "before-stop", false, true, criu_manager.clone(), process_manager.clone())
+            .await
+            .unwrap();
+        assert_eq!(manager.get_instance_by_id(&instance_id).unwrap().status, InstanceStatus::Stopped);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !process_gone(pid) {
+            assert!(std::time::Instant::now() < deadline, "process {} survived checkpoint --stop", pid);
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        manager
+            .restore_instance_to_existing(&instance_id, "before-stop", &RestoreOptions::default(), criu_manager, process_manager)
+            .await
+            .unwrap();
+        let restored = manager.get_instance_by_id(&instance_id).unwrap();
+        assert_eq!(restored.status, InstanceStatus::Running);
+        let restored_pid = restored.pid.unwrap();
+        assert_ne!(restored_pid, pid);
+        assert!(!process_gone(restored_pid));
+        let _ = nix::sys::signal::kill(nix::unistd::Pid::from_raw(restored_pid as i32), nix::sys::signal::Signal::SIGKILL);
+    }
+
+    #[tokio::test]
+    async fn a_restored_instance_keeps_the_output_from_before_the_checkpoint() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let process_manager = Arc::new(ProcessManager::new());
+        let criu_manager = Arc::new(CriuManager::new_with_engine(Arc::new(StoppingEngine)));
+        let args = vec!["-c".to_string(), "echo before-checkpoint-1; echo before-checkpoint-2; sleep 30".to_string()];
+        let instance_id = manager.start_instance("sh".to_string(), args, process_manager.clone()).await.unwrap();
+        let uuid = manager.get_instance_by_id(&instance_id).unwrap().id;
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while process_manager.get_output_history(&uuid).await.unwrap_or_default().len() < 2 {
+            assert!(std::time::Instant::now() < deadline, "output never captured");
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        manager
+            .checkpoint_instance(&instance_id, "with-output", false, true, criu_manager.clone(), process_manager.clone())
+            .await
+            .unwrap();
+        // Without its log files, only the checkpoint can bring the history back
+        let _ = std::fs::remove_dir_all(manager.get_instance_by_id(&instance_id).unwrap().output_dir());
+        manager
+            .restore_instance_to_existing(&instance_id, "with-output", &RestoreOptions::default(), criu_manager, process_manager.clone())
+            .await
+            .unwrap();
+
+        let history = process_manager.get_output_history(&uuid).await.unwrap_or_default();
+        for line in ["before-checkpoint-1", "before-checkpoint-2"] {
+            assert!(history.iter().any(|logged| logged.ends_with(line)), "{} missing from {:?}", line, history);
+        }
+        let restored_pid = manager.get_instance_by_id(&instance_id).unwrap().pid.unwrap();
+        let _ = nix::sys::signal::kill(nix::unistd::Pid::from_raw(restored_pid as i32), nix::sys::signal::Signal::SIGKILL);
+    }
+
+    fn labeled(labels: &[(&str, &str)]) -> StartOptions {
+        StartOptions {
+            labels: labels.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn selector(labels: &[(&str, &str)]) -> Vec<(String, String)> {
+        labels.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[tokio::test]
+    async fn labels_are_stored_and_select_the_instance() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let process_manager = Arc::new(ProcessManager::new());
+        let options = labeled(&[("env", "prod"), ("tier", "web")]);
+        let instance_id = manager
+            .start_instance_with_options("sleep".to_string(), vec!["30".to_string()], StartMode::Normal, &options, process_manager.clone())
+            .await
+            .unwrap();
+        let instance = manager.get_instance_by_id(&instance_id).unwrap().clone();
+
+        assert_eq!(instance.labels, options.labels);
+        assert_eq!(Instance::load_metadata(&instance.metadata_file).unwrap().labels, options.labels);
+        let running = [InstanceStatus::Running];
+        assert!(manager.select_by_labels(&selector(&[("env", "prod")]), &running).contains(&instance.id));
+        assert!(manager.select_by_labels(&selector(&[("env", "prod"), ("tier", "web")]), &running).contains(&instance.id));
+        assert!(!manager.select_by_labels(&selector(&[("env", "prod"), ("tier", "db")]), &running).contains(&instance.id));
+        assert!(!manager.select_by_labels(&selector(&[("env", "prod")]), &[InstanceStatus::Stopped]).contains(&instance.id));
+
+        manager.stop_instance(&instance_id, process_manager).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn bulk_stop_by_label_leaves_other_instances_running() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let process_manager = Arc::new(ProcessManager::new());
+        // A label no other test uses, since the scratch directory is shared
+        let mut ids = Vec::new();
+        for batch in ["drain", "drain", "keep"] {
+            let options = labeled(&[("batch", batch), ("suite", "bulk-stop")]);
+            ids.push(
+                manager
+                    .start_instance_with_options("sleep".to_string(), vec!["30".to_string()], StartMode::Normal, &options, process_manager.clone())
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        // What `stop --label batch=drain` does
+        let selected = manager.select_by_labels(&selector(&[("batch", "drain"), ("suite", "bulk-stop")]), &[InstanceStatus::Running, InstanceStatus::Paused]);
+        assert_eq!(selected.len(), 2);
+        for instance_uuid in &selected {
+            manager.stop_instance(&instance_uuid.to_string(), process_manager.clone()).await.unwrap();
+        }
+
+        for id in &ids[..2] {
+            assert_eq!(manager.get_instance_by_id(id).unwrap().status, InstanceStatus::Stopped);
+        }
+        let kept = manager.get_instance_by_id(&ids[2]).unwrap();
+        assert_eq!(kept.status, InstanceStatus::Running);
+        assert!(!process_gone(kept.pid.unwrap()));
+        manager.stop_instance(&ids[2], process_manager).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn repeated_restores_leave_no_zombies() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let process_manager = Arc::new(ProcessManager::new());
+        let criu_manager = Arc::new(CriuManager::new_with_engine(Arc::new(StoppingEngine)));
+        let instance_id = manager.start_instance("sleep".to_string(), vec!["30".to_string()], process_manager.clone()).await.unwrap();
+
+        for round in 0..5 {
+            let name = format!("round-{}", round);
+            manager
+                .checkpoint_instance(&instance_id, &name, false, true, criu_manager.clone(), process_manager.clone())
+                .await
+                .unwrap();
+            manager
+                .restore_instance_to_existing(&instance_id, &name, &RestoreOptions::default(), criu_manager.clone(), process_manager.clone())
+                .await
+                .unwrap();
+        }
+        manager.stop_instance(&instance_id, process_manager).await.unwrap();
+
+        // Other tests' children may be between exit and reaping; leaked ones never get reaped
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !zombie_children().is_empty() {
+            assert!(std::time::Instant::now() < deadline, "zombie children: {:?}", zombie_children());
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+    }
+
+    /// Dumps like the mock, noting whether the instance manager was locked during the dump
+    struct LockCheckingEngine {
+        instance_manager: Arc<tokio::sync::Mutex<InstanceManager>>,
+        locked_during_dump: std::sync::atomic::AtomicBool,
+    }
+
+    impl CheckpointEngine for LockCheckingEngine {
+        fn name(&self) -> &str {
+            "lock-checking"
+        }
+
+        fn check(&self) -> Result<EngineOutput> {
+            MockEngine::new().check()
+        }
+
+        fn dump(&self, request: &DumpRequest) -> Result<EngineOutput> {
+            if self.instance_manager.try_lock().is_err() {
+                self.locked_during_dump.store(true, std::sync::atomic::Ordering::SeqCst);
+            }
+            for image in ["inventory.img", "pstree.img", &format!("core-{}.img", request.pid)] {
+                std::fs::write(request.images_dir.join(image), request.pid.to_string())?;
+            }
+            MockEngine::new().dump(request)
+        }
+
+        fn pre_dump(&self, request: &DumpRequest) -> Result<EngineOutput> {
+            MockEngine::new().pre_dump(request)
+        }
+
+        fn restore(&self, request: &RestoreRequest) -> Result<EngineOutput> {
+            MockEngine::new().restore(request)
+        }
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_taken_on_schedule_and_pruned_to_the_retain_count() {
+        crate::test_support::use_scratch_dir();
+        let instance_manager = Arc::new(tokio::sync::Mutex::new(InstanceManager::new()));
+        let engine = Arc::new(LockCheckingEngine {
+            instance_manager: instance_manager.clone(),
+            locked_during_dump: std::sync::atomic::AtomicBool::new(false),
+        });
+        let criu_manager = Arc::new(CriuManager::new_with_engine(engine.clone()));
+        let process_manager = Arc::new(ProcessManager::new());
+        let options = StartOptions {
+            snapshot_policy: Some(crate::types::SnapshotPolicy { interval_secs: 60, retain: 2 }),
+            ..Default::default()
+        };
+        let instance_id = instance_manager.lock().await
+            .start_instance_with_options("sleep".to_string(), vec!["30".to_string()], StartMode::Normal, &options, process_manager.clone())
+            .await
+            .unwrap();
+
+        // The first check snapshots right away; one before the interval is up takes none
+        let start = chrono::Utc::now();
+        for (offset_secs, taken) in [(0, 1), (30, 0), (60, 1), (120, 1), (180, 1)] {
+            let at = start + chrono::Duration::seconds(offset_secs);
+            let results = InstanceManager::take_due_snapshots(&instance_manager, criu_manager.clone(), process_manager.clone(), at).await;
+            assert_eq!(results.len(), taken, "at +{}s", offset_secs);
+            for (_, result) in results {
+                assert_eq!(result.unwrap(), format!("{}{}", SNAPSHOT_PREFIX, at.format("%Y%m%dT%H%M%SZ")));
+            }
+        }
+
+        let manager = instance_manager.lock().await;
+        let instance = manager.get_instance_by_id(&instance_id).unwrap();
+        let mut kept: Vec<&String> = instance.checkpoints.keys().collect();
+        kept.sort();
+        let expected: Vec<String> = [120, 180]
+            .map(|offset_secs| format!("{}{}", SNAPSHOT_PREFIX, (start + chrono::Duration::seconds(offset_secs)).format("%Y%m%dT%H%M%SZ")))
+            .into();
+        assert_eq!(kept, expected.iter().collect::<Vec<_>>());
+        let on_disk = std::fs::read_dir(instance.checkpoints_dir()).unwrap().count();
+        assert_eq!(on_disk, 2, "pruned snapshots were left on disk");
+        assert!(!engine.locked_during_dump.load(std::sync::atomic::Ordering::SeqCst), "the manager was locked during a dump");
+        drop(manager);
+
+        instance_manager.lock().await.stop_instance(&instance_id, process_manager).await.unwrap();
+    }
+
+    #[test]
+    fn a_read_only_data_dir_is_reported_at_startup() {
+        crate::test_support::use_scratch_dir();
+        // Root ignores permission bits, but sysfs refuses new directories even to root
+        let dir = if nix::unistd::geteuid().is_root() {
+            PathBuf::from("/sys/nhi-instances")
+        } else {
+            let parent = std::env::temp_dir().join(format!("nhi-read-only-{}", std::process::id()));
+            std::fs::create_dir_all(&parent).unwrap();
+            std::fs::set_permissions(&parent, std::os::unix::fs::PermissionsExt::from_mode(0o555)).unwrap();
+            parent.join("instances")
+        };
+
+        let error = check_data_dir_writable(&dir).unwrap_err();
+        assert!(error.to_string().starts_with(&format!("Data directory {} is not writable", dir.display())), "{}", error);
+        match error {
+            CriuCliError::DataDirNotWritable(_, cause) => assert_eq!(cause.kind(), std::io::ErrorKind::PermissionDenied),
+            other => panic!("expected DataDirNotWritable, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn restart_fails_when_the_metadata_cannot_be_saved() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let process_manager = Arc::new(ProcessManager::new());
+        let instance_id = manager.start_instance("sleep".to_string(), vec!["30".to_string()], process_manager.clone()).await.unwrap();
+        manager.stop_instance(&instance_id, process_manager.clone()).await.unwrap();
+
+        // A directory in place of the metadata file can't be written, even by root
+        let metadata_file = manager.get_instance_by_id(&instance_id).unwrap().metadata_file.clone();
+        std::fs::remove_file(&metadata_file).unwrap();
+        std::fs::create_dir(&metadata_file).unwrap();
+
+        let result = manager.restart_instance(&instance_id, process_manager.clone()).await;
+        assert!(matches!(result, Err(CriuCliError::IoError(_))), "{:?}", result);
+
+        std::fs::remove_dir(&metadata_file).unwrap();
+        manager.stop_instance(&instance_id, process_manager).await.unwrap();
+    }
+
+    /// Launch `program args` with its stdout going to the instance's stdout.log, as the detached launcher does
+    fn launch_with_stdout_log(instance: &Instance) -> std::process::Child {
+        let output_dir = instance.instance_dir.join("output");
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let stdout_log = std::fs::File::create(output_dir.join("stdout.log")).unwrap();
+        let child = loop {
+            let spawned = std::process::Command::new(&instance.program)
+                .args(&instance.args)
+                .current_dir(&instance.working_dir)
+                .stdin(std::process::Stdio::piped())
+                .stdout(stdout_log.try_clone().unwrap())
+                .spawn();
+            match spawned {
+                // A just-written script stays busy while a concurrently forked test child holds it open
+                Err(e) if e.raw_os_error() == Some(nix::libc::ETXTBSY) => std::thread::sleep(std::time::Duration::from_millis(10)),
+                spawned => break spawned.unwrap(),
+            }
+        };
+
+        // /proc shows the new executable before the new arguments, so wait for the arguments
+        let mut expected: Vec<u8> = instance.args.iter().flat_map(|arg| [arg.as_bytes(), b"\0"].concat()).collect();
+        expected.insert(0, 0);
+        while !std::fs::read(format!("/proc/{}/cmdline", child.id())).unwrap_or_default().ends_with(&expected) {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        child
+    }
+
+    #[test]
+    fn a_script_whose_path_contains_bash_is_matched() {
+        crate::test_support::use_scratch_dir();
+        let tools = env::current_dir().unwrap().join("bash-tools");
+        std::fs::create_dir_all(&tools).unwrap();
+        let script = tools.join("serve.sh");
+        // Only a builtin, so the shell never forks a copy of itself holding the same log
+        std::fs::write(&script, "#!/bin/sh\nread line\n").unwrap();
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+
+        let instance = Instance::new(script.to_string_lossy().into_owned(), vec!["--port".to_string(), "8080".to_string()], tools.clone());
+        let mut child = launch_with_stdout_log(&instance);
+
+        assert!(InstanceManager::process_matches_instance(child.id(), &instance));
+        assert_eq!(InstanceManager::find_actual_process_pid(&instance), Some(child.id()));
+        let mut other_args = instance.clone();
+        other_args.args = vec!["--port".to_string(), "9090".to_string()];
+        assert!(!InstanceManager::process_matches_instance(child.id(), &other_args));
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn concurrent_launches_of_one_program_each_find_their_own_pid() {
+        crate::test_support::use_scratch_dir();
+        let first = Instance::new("/bin/sleep".to_string(), vec!["31".to_string()], PathBuf::from("/"));
+        let second = Instance::new("/bin/sleep".to_string(), vec!["31".to_string()], PathBuf::from("/"));
+        let mut first_child = launch_with_stdout_log(&first);
+        let mut second_child = launch_with_stdout_log(&second);
+
+        // Same program and arguments; only the output file tells them apart
+        assert_eq!(InstanceManager::find_actual_process_pid(&first), Some(first_child.id()));
+        assert_eq!(InstanceManager::find_actual_process_pid(&second), Some(second_child.id()));
+        assert!(!InstanceManager::process_matches_instance(second_child.id(), &first));
+
+        for child in [&mut first_child, &mut second_child] {
+            child.kill().unwrap();
+            child.wait().unwrap();
+        }
+    }
+
+    #[test]
+    fn list_order_is_stable_across_calls_and_insertion_orders() {
+        crate::test_support::use_scratch_dir();
+        let created = chrono::Utc::now();
+        let instances: Vec<Instance> = ["b-prog", "a-prog", "c-prog", "a-prog"]
+            .iter()
+            .enumerate()
+            .map(|(idx, program)| {
+                let mut instance = Instance::new(program.to_string(), Vec::new(), PathBuf::from("/"));
+                instance.status = InstanceStatus::Stopped;
+                // Two share a creation time, so the short ID breaks the tie
+                instance.created_at = created + chrono::Duration::seconds((idx / 2) as i64);
+                instance
+            })
+            .collect();
+
+        let mut forward = InstanceManager::new();
+        let mut backward = InstanceManager::new();
+        for instance in &instances {
+            forward.add_instance(instance.clone());
+        }
+        for instance in instances.iter().rev() {
+            backward.add_instance(instance.clone());
+        }
+
+        let ids = |manager: &InstanceManager, sort: InstanceSort| -> Vec<String> {
+            let listed = manager.list_instances_json(&InstanceFilter::default(), sort);
+            listed.as_array().unwrap().iter().map(|row| row["short_id"].as_str().unwrap().to_string()).collect()
+        };
+        for sort in [InstanceSort::Created, InstanceSort::Id, InstanceSort::Program] {
+            let first = ids(&forward, sort);
+            assert_eq!(first.len(), instances.len());
+            for _ in 0..5 {
+                assert_eq!(ids(&forward, sort), first);
+                assert_eq!(ids(&backward, sort), first);
+            }
+        }
+
+        let by_created = ids(&forward, InstanceSort::Created);
+        let mut tied: Vec<String> = instances[..2].iter().map(Instance::short_id).collect();
+        tied.sort();
+        assert_eq!(by_created[..2], tied[..]);
+        let listed = forward.list_instances_json(&InstanceFilter::default(), InstanceSort::Program);
+        let programs: Vec<&str> = listed.as_array().unwrap().iter().map(|row| row["program"].as_str().unwrap()).collect();
+        assert_eq!(programs, ["a-prog", "a-prog", "b-prog", "c-prog"]);
+    }
+
+    #[tokio::test]
+    async fn output_file_receives_the_lines_of_normal_and_detached_programs() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let process_manager = Arc::new(ProcessManager::new());
+        let args = vec!["-c".to_string(), "echo to-stdout; echo to-stderr >&2; sleep 30".to_string()];
+
+        for (mode, dir) in [(StartMode::Normal, "normal"), (StartMode::Detached, "detached")] {
+            // Parent directories are created as needed
+            let output_file = env::current_dir().unwrap().join("pipeline").join(dir).join("program.log");
+            let options = StartOptions { output_file: Some(output_file.clone()), ..Default::default() };
+            let instance_id = manager
+                .start_instance_with_options("/bin/sh".to_string(), args.clone(), mode, &options, process_manager.clone())
+                .await
+                .unwrap();
+
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+            loop {
+                let content = std::fs::read_to_string(&output_file).unwrap_or_default();
+                if content.contains("to-stdout") && content.contains("to-stderr") {
+                    break;
+                }
+                assert!(std::time::Instant::now() < deadline, "{} output file holds {:?}", dir, content);
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+            // The internal history still gets the output too
+            let history = process_manager.get_output_history(&manager.get_instance_by_id(&instance_id).unwrap().id).await.unwrap_or_default();
+            assert!(history.iter().any(|line| line.ends_with("to-stdout")), "{:?}", history);
+
+            manager.stop_instance(&instance_id, process_manager.clone()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn usr1_sent_with_signal_instance_is_delivered() {
+        crate::test_support::use_scratch_dir();
+        let dir = env::current_dir().unwrap().join(format!("usr1-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (ready, marker) = (dir.join("ready"), dir.join("usr1-count"));
+        let script = format!(
+            "n=0; trap 'n=$((n+1)); echo $n > \"{}\"' USR1; touch \"{}\"; while :; do sleep 0.05; done",
+            marker.display(),
+            ready.display()
+        );
+
+        let mut manager = InstanceManager::new();
+        let process_manager = Arc::new(ProcessManager::new());
+        let instance_id = manager.start_instance("sh".to_string(), vec!["-c".to_string(), script], process_manager.clone()).await.unwrap();
+        // USR1 would end the shell before its trap is set
+        while !ready.exists() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        for expected in ["1", "2"] {
+            manager.signal_instance(&instance_id, nix::sys::signal::Signal::SIGUSR1, process_manager.clone()).await.unwrap();
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+            while std::fs::read_to_string(&marker).unwrap_or_default().trim() != expected {
+                assert!(std::time::Instant::now() < deadline, "USR1 #{} never counted", expected);
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        }
+        assert!(!process_manager.process_exited(&manager.get_instance_by_id(&instance_id).unwrap().id).await);
+
+        manager.stop_instance(&instance_id, process_manager.clone()).await.unwrap();
+        let result = manager.signal_instance(&instance_id, nix::sys::signal::Signal::SIGUSR1, process_manager).await;
+        assert!(matches!(result, Err(CriuCliError::InstanceNotRunning(_))), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn a_start_paused_instance_is_stopped_and_silent_until_resumed() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let process_manager = Arc::new(ProcessManager::new());
+        let options = StartOptions { start_paused: true, ..Default::default() };
+        let instance_id = manager
+            .start_instance_with_options("/bin/echo".to_string(), vec!["after-resume".to_string()], StartMode::Normal, &options, process_manager.clone())
+            .await
+            .unwrap();
+        let (uuid, pid) = {
+            let instance = manager.get_instance_by_id(&instance_id).unwrap();
+            assert_eq!(instance.status, InstanceStatus::Paused);
+            (instance.id, instance.pid.unwrap())
+        };
+
+        // The SIGSTOP left by the detach lands before the program's first instruction
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+            if stat.rsplit(')').next().unwrap().trim_start().starts_with('T') {
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline, "never stopped: {}", stat);
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        let history = process_manager.get_output_history(&uuid).await.unwrap_or_default();
+        assert!(history.is_empty(), "output before resume: {:?}", history);
+
+        manager.resume_instance(&instance_id, process_manager.clone()).await.unwrap();
+        assert_eq!(manager.get_instance_by_id(&instance_id).unwrap().status, InstanceStatus::Running);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            let history = process_manager.get_output_history(&uuid).await.unwrap_or_default();
+            if history.iter().any(|line| line.ends_with("after-resume")) {
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline, "no output after resume: {:?}", history);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn list_filtered_by_running_status_returns_only_running_instances() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let process_manager = Arc::new(ProcessManager::new());
+        let running = manager.start_instance("sleep".to_string(), vec!["30".to_string()], process_manager.clone()).await.unwrap();
+        let paused_options = StartOptions { start_paused: true, ..Default::default() };
+        let paused = manager
+            .start_instance_with_options("/bin/echo".to_string(), Vec::new(), StartMode::Normal, &paused_options, process_manager.clone())
+            .await
+            .unwrap();
+        let mut stopped = Instance::new("sleep".to_string(), vec!["30".to_string()], PathBuf::from("/"));
+        stopped.status = InstanceStatus::Stopped;
+        manager.add_instance(stopped);
+
+        let listed_ids = |filter: &InstanceFilter| -> Vec<String> {
+            let listed = manager.list_instances_json(filter, InstanceSort::Created);
+            listed.as_array().unwrap().iter().map(|row| row["short_id"].as_str().unwrap().to_string()).collect()
+        };
+        let running_only = InstanceFilter { status: Some(InstanceStatus::Running), ..Default::default() };
+        assert_eq!(listed_ids(&running_only), vec![running.clone()]);
+        let paused_only = InstanceFilter { status: Some(InstanceStatus::Paused), ..Default::default() };
+        assert_eq!(listed_ids(&paused_only), vec![paused.clone()]);
+        let echo_only = InstanceFilter { program: Some("echo".to_string()), ..Default::default() };
+        assert_eq!(listed_ids(&echo_only), vec![paused.clone()]);
+        assert_eq!(listed_ids(&InstanceFilter::default()).len(), 3);
+
+        manager.stop_instance(&running, process_manager.clone()).await.unwrap();
+        manager.stop_instance(&paused, process_manager).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn retry_starts_a_failed_instance_once_the_program_exists() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let process_manager = Arc::new(ProcessManager::new());
+        let criu_manager = Arc::new(CriuManager::new_with_engine(Arc::new(MockEngine::new())));
+        let program = env::current_dir().unwrap().join(format!("not-yet-{}.sh", Uuid::new_v4()));
+
+        let result = manager.start_instance(program.display().to_string(), Vec::new(), process_manager.clone()).await;
+        assert!(result.is_err());
+        let failed = manager.get_all_instances().into_iter().find(|instance| instance.program == program.display().to_string()).unwrap();
+        assert_eq!(failed.status, InstanceStatus::Failed);
+        assert!(failed.last_error.is_some());
+        let instance_id = failed.short_id();
+
+        std::fs::write(&program, "#!/bin/sh\nexec sleep 30\n").unwrap();
+        std::fs::set_permissions(&program, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        // A just-written script can still be busy for a moment
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while let Err(e) = manager.retry_instance(&instance_id, criu_manager.clone(), process_manager.clone()).await {
+            assert!(std::time::Instant::now() < deadline, "retry kept failing: {}", e);
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        let retried = manager.get_instance_by_id(&instance_id).unwrap();
+        assert_eq!(retried.status, InstanceStatus::Running);
+        assert!(retried.last_error.is_none());
+        assert!(!process_gone(retried.pid.unwrap()));
+        // Only failed instances can be retried
+        assert!(manager.retry_instance(&instance_id, criu_manager, process_manager.clone()).await.is_err());
+        manager.stop_instance(&instance_id, process_manager).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn an_instance_started_with_a_fixed_id_resolves_by_that_id() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let process_manager = Arc::new(ProcessManager::new());
+        let fixed = Uuid::new_v4();
+        let options = StartOptions { instance_id: Some(fixed), ..Default::default() };
+
+        let short_id = manager
+            .start_instance_with_options("sleep".to_string(), vec!["30".to_string()], StartMode::Normal, &options, process_manager.clone())
+            .await
+            .unwrap();
+        assert_eq!(short_id, fixed.to_string()[..8]);
+        assert_eq!(manager.resolve_instance_id(&fixed.to_string()).unwrap(), fixed);
+        assert_eq!(manager.resolve_instance_id(&short_id).unwrap(), fixed);
+
+        // The ID is taken now, also for an ID that only shares its short form
+        let mut same_short = fixed.to_string();
+        same_short.replace_range(30.., "000000");
+        for taken in [fixed, Uuid::parse_str(&same_short).unwrap()] {
+            let options = StartOptions { instance_id: Some(taken), ..Default::default() };
+            let result = manager
+                .start_instance_with_options("sleep".to_string(), vec!["30".to_string()], StartMode::Normal, &options, process_manager.clone())
+                .await;
+            assert!(result.is_err(), "{} was accepted twice", taken);
+        }
+        assert_eq!(manager.get_all_instances().len(), 1);
+
+        manager.stop_instance(&short_id, process_manager).await.unwrap();
+    }
+
+    #[test]
+    fn status_aware_resolution_names_the_status_the_instance_has() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let mut shadow = Instance::new("sleep".to_string(), vec!["30".to_string()], PathBuf::from("/"));
+        shadow.status = InstanceStatus::Shadow;
+        let shadow_id = shadow.id.to_string();
+        manager.add_instance(shadow);
+
+        let running = [InstanceStatus::Running, InstanceStatus::Paused];
+        let err = manager.resolve_instance_id_with_status(&shadow_id, &running).unwrap_err();
+        assert_eq!(err.to_string(), format!("Instance {} is Shadow, not Running or Paused", shadow_id));
+        let short_id = shadow_id[..8].to_string();
+        let err = manager.resolve_instance_id_with_status(&short_id, &[InstanceStatus::Running]).unwrap_err();
+        assert_eq!(err.to_string(), format!("Instance {} is Shadow, not Running", short_id));
+        assert!(matches!(
+            manager.resolve_instance_id_with_status("00000000", &running),
+            Err(CriuCliError::InstanceNotFound(_))
+        ));
+
+        // A running copy sharing the short ID wins over the shadow
+        let mut copy = Instance::new("sleep".to_string(), vec!["30".to_string()], PathBuf::from("/"));
+        copy.id = Uuid::parse_str(&format!("{}{}", short_id, &copy.id.to_string()[8..])).unwrap();
+        copy.status = InstanceStatus::Running;
+        let copy_id = copy.id;
+        manager.add_instance(copy);
+        assert_eq!(manager.resolve_instance_id_with_status(&short_id, &running).unwrap(), copy_id);
+        assert_eq!(
+            manager.resolve_instance_id_with_status(&short_id, &[InstanceStatus::Shadow]).unwrap().to_string(),
+            shadow_id
+        );
+    }
+
+    #[test]
+    fn purge_takes_only_stopped_and_failed_instances() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let with_status = |status: InstanceStatus| {
+            let mut instance = Instance::new("sleep".to_string(), vec!["30".to_string()], PathBuf::from("/"));
+            instance.status = status;
+            instance
+        };
+        let mut ids = HashMap::new();
+        for status in [InstanceStatus::Running, InstanceStatus::Paused, InstanceStatus::Shadow, InstanceStatus::Stopped, InstanceStatus::Failed] {
+            let instance = with_status(status.clone());
+            ids.insert(status.to_string(), instance.short_id());
+            manager.add_instance(instance);
+        }
+        // Left behind by an earlier session, known only by their metadata
+        for status in [InstanceStatus::Running, InstanceStatus::Stopped] {
+            let leftover = with_status(status.clone());
+            leftover.save_metadata().unwrap();
+            ids.insert(format!("leftover {}", status), leftover.short_id());
+        }
+        // Other tests share the instances directory, so only look at ours
+        let ours = |candidates: Vec<PurgeCandidate>| -> Vec<PurgeCandidate> {
+            candidates.into_iter().filter(|candidate| ids.values().any(|id| *id == candidate.short_id)).collect()
+        };
+        let statuses = |candidates: &[PurgeCandidate]| -> Vec<String> {
+            let mut names: Vec<String> = candidates
+                .iter()
+                .map(|candidate| ids.iter().find(|(_, id)| **id == candidate.short_id).unwrap().0.clone())
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(statuses(&ours(manager.purge_candidates(Some(&InstanceStatus::Failed), None))), ["Failed"]);
+        assert!(ours(manager.purge_candidates(None, Some(chrono::Duration::hours(1)))).is_empty());
+        let candidates = ours(manager.purge_candidates(None, None));
+        assert_eq!(statuses(&candidates), ["Failed", "Stopped", "leftover Stopped"]);
+
+        let dirs: Vec<PathBuf> = candidates.iter().map(|candidate| candidate.dir.clone()).collect();
+        assert!(manager.purge(candidates).iter().all(|(_, result)| result.is_ok()));
+        assert!(dirs.iter().all(|dir| !dir.exists()));
+        let mut remaining: Vec<String> = manager.get_all_instances().iter().map(|instance| instance.status.to_string()).collect();
+        remaining.sort();
+        assert_eq!(remaining, ["Paused", "Running", "Shadow"]);
+        assert!(manager.get_all_instances().iter().all(|instance| instance.instance_dir.exists()));
+        assert!(PathBuf::from(INSTANCES_DIR).join(format!("instance_{}", ids["leftover Running"])).exists());
+    }
+
+    #[tokio::test]
+    async fn stop_if_running_succeeds_on_a_stopped_instance_but_not_an_unknown_one() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = InstanceManager::new();
+        let process_manager = Arc::new(ProcessManager::new());
+        let short_id = manager.start_instance("sleep".to_string(), vec!["30".to_string()], process_manager.clone()).await.unwrap();
+
+        assert!(manager.stop_instance_if_running(&short_id, process_manager.clone()).await.unwrap());
+        assert!(!manager.stop_instance_if_running(&short_id, process_manager.clone()).await.unwrap());
+        // Strict by default
+        assert!(matches!(
+            manager.stop_instance(&short_id, process_manager.clone()).await,
+            Err(CriuCliError::UnexpectedStatus(_, InstanceStatus::Stopped, _))
+        ));
+        assert!(matches!(
+            manager.stop_instance_if_running("00000000", process_manager).await,
+            Err(CriuCliError::InstanceNotFound(_))
+        ));
     }
 }
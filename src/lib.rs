@@ -0,0 +1,64 @@
+//! NHI: CRIU-based process management with checkpoint, restore and live migration.
+//!
+//! The `nhi` binary is a thin interactive CLI over this library. Embedders
+//! usually start from [`NhiBuilder`] to get the core managers wired together.
+
+pub(crate) mod audit;
+mod builder;
+pub(crate) mod capabilities;
+pub(crate) mod checkpoint_archive;
+pub(crate) mod checkpoint_crypto;
+pub(crate) mod checkpoint_dedup;
+pub(crate) mod checkpoint_descriptor;
+pub(crate) mod checkpoint_diff;
+pub mod checkpoint_engine;
+pub mod colors;
+pub(crate) mod criu_compat;
+pub mod criu_manager;
+pub(crate) mod criu_stats;
+pub(crate) mod image_streamer;
+pub mod instance;
+pub mod output;
+pub mod process_manager;
+pub(crate) mod stats;
+pub(crate) mod sudo_utils;
+pub(crate) mod tty_utils;
+pub mod types;
+// Stage 2: Networking modules
+pub mod cluster_state;
+pub mod message_protocol;
+pub mod network_manager;
+pub mod node_manager;
+pub(crate) mod node_discovery;
+// Stage 3: Shadow state and migration modules
+pub mod migration_manager;
+pub mod shadow_instance_manager;
+pub(crate) mod distributed_registry;
+// Superseded by MigrationManager and ShadowInstanceManager; kept for reference
+#[allow(dead_code)]
+pub(crate) mod migration_executor;
+#[allow(dead_code)]
+pub(crate) mod shadow_manager;
+pub(crate) mod streaming_manager;
+#[cfg(test)]
+pub(crate) mod test_support;
+
+pub use builder::{ClusterOptions, Nhi, NhiBuilder};
+pub use checkpoint_engine::{CheckpointEngine, CriuEngine};
+pub use criu_manager::CriuManager;
+pub use instance::InstanceManager;
+pub use migration_manager::MigrationManager;
+pub use node_manager::NodeManager;
+pub use process_manager::ProcessManager;
+pub use shadow_instance_manager::ShadowInstanceManager;
+pub use types::{CriuCliError, Instance, InstanceStatus, MigrationError, ShadowError, StartMode};
+// Helpers the nhi CLI builds on
+pub use audit::{default_audit_dir, AuditLog};
+pub use capabilities::DEFAULT_IMAGE_STREAMER_PATH;
+pub use checkpoint_crypto::{load_key_file, parse_key, SEALED_ARCHIVE};
+pub use checkpoint_diff::{diff_checkpoints, FileChange, FileDiff};
+pub use criu_compat::detect_tcp_sockets;
+pub use criu_stats::CriuStats;
+pub use stats::{checkpoint_disk_usage, disk_available, summarize, ClusterSummary};
+pub use sudo_utils::PrivilegeCommand;
+pub use tty_utils::check_process_tty_compatibility;
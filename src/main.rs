@@ -2,51 +2,41 @@ use anyhow::Result;
 use clap::Parser;
 use rustyline::error::ReadlineError;
 use rustyline::DefaultEditor;
+use std::io::IsTerminal;
+use signals::ShutdownSignal;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tracing::{error, info, warn};
 
+mod attach;
 mod cli;
-mod instance;
-mod process_manager;
-mod criu_manager;
-mod types;
+mod commands;
+mod http_api;
+mod logger;
+mod output_search;
+mod signals;
 mod ui;
-mod tty_utils;
-mod colors;
+#[cfg(test)]
+#[path = "test_support.rs"]
+mod test_support;
+
+use nhi::{colors, criu_manager, instance, output, process_manager, types};
 // Stage 2: Networking modules
-mod message_protocol;
-mod network_manager;
-mod node_discovery;
-mod cluster_state;
-mod node_manager;
+use nhi::{message_protocol, node_manager};
 // Stage 3: Shadow state and migration modules
-mod distributed_registry;
-mod shadow_manager;
-mod migration_manager;
-mod streaming_manager;
-mod migration_executor;
-mod shadow_instance_manager;
-mod logger;
-mod output;
-mod http_api;
+use nhi::{migration_manager, shadow_instance_manager};
+use nhi::{ClusterOptions, Nhi, NhiBuilder};
 
-use cli::{CliCommand, CliState};
+use cli::CliState;
+use commands::execute_command;
 use instance::InstanceManager;
-use process_manager::ProcessManager;
-use criu_manager::CriuManager;
 
-use ui::AttachUI;
-use uuid::Uuid;
 use colors::ColorScheme;
 // Stage 2: Networking imports
 use message_protocol::NetworkConfig;
-use network_manager::NetworkManager;
 use node_manager::NodeManager;
 // Stage 3: Shadow state imports
-use shadow_instance_manager::ShadowInstanceManager;
-// Stage 4: Migration imports
-use migration_manager::MigrationManager;
+use shadow_instance_manager::FailoverPolicy;
 
 use crate::output::Output;
 
@@ -57,7 +47,7 @@ struct Args {
     #[arg(short, long, default_value = "info")]
     log_level: String,
 
-    /// Network listen address for P2P connections
+    /// Network listen address for P2P connections (use port 0 for an ephemeral port)
     #[arg(long, default_value = "0.0.0.0:8080")]
     listen_addr: String,
 
@@ -73,18 +63,144 @@ struct Args {
     #[arg(long)]
     no_network: bool,
 
+    /// Disable UDP node discovery but keep listening for and making explicit connections
+    #[arg(long)]
+    no_discovery: bool,
+
+    /// Seconds between UDP discovery announcements
+    #[arg(long, default_value = "10")]
+    discovery_interval: u64,
+
+    /// Seconds a discovered node is kept as a candidate peer after its last announcement
+    #[arg(long, default_value = "30")]
+    discovery_ttl: u64,
+
+    /// Discovery destination: a broadcast address or an IPv4 multicast group (e.g. 239.255.42.1)
+    #[arg(long, default_value = "255.255.255.255")]
+    discovery_group: std::net::Ipv4Addr,
+
     /// Path to CRIU binary (default: ./criu/bin/criu)
     #[arg(long, default_value = "./criu/bin/criu")]
     criu_path: String,
 
+    /// Path to the criu-image-streamer binary; migrations fall back to tar transfer when it is missing
+    #[arg(long, default_value = nhi::DEFAULT_IMAGE_STREAMER_PATH)]
+    criu_streamer_path: String,
+
     /// HTTP API port (default: 3000, 0 to disable)
     #[arg(long, default_value = "3000")]
     http_port: u16,
+
+    /// Run CRIU directly, same as --privilege-cmd none (CRIU binary must already be privileged)
+    #[arg(long)]
+    no_sudo: bool,
+
+    /// Command privileged CRIU calls are wrapped in: sudo, doas, run0 or none (run directly)
+    #[arg(long, default_value = "sudo")]
+    privilege_cmd: nhi::PrivilegeCommand,
+
+    /// Share unchanged checkpoint files between checkpoints via instances/<id>/blobs/
+    #[arg(long)]
+    dedup_checkpoints: bool,
+
+    /// Extra argument for every CRIU dump, e.g. --criu-dump-arg=--ext-unix-sk (repeatable)
+    #[arg(long = "criu-dump-arg", value_parser = nhi::checkpoint_engine::parse_extra_arg, allow_hyphen_values = true)]
+    criu_dump_args: Vec<String>,
+
+    /// Extra argument for every CRIU restore (repeatable; recorded with each checkpoint and reused when it is restored)
+    #[arg(long = "criu-restore-arg", value_parser = nhi::checkpoint_engine::parse_extra_arg, allow_hyphen_values = true)]
+    criu_restore_args: Vec<String>,
+
+    /// Run auto-sync CRIU dumps under `nice -n <N>` (0-19) so they disturb the workload less
+    #[arg(long, value_parser = nhi::checkpoint_engine::parse_nice)]
+    checkpoint_nice: Option<i32>,
+
+    /// Run auto-sync CRIU dumps under ionice: idle, best-effort or best-effort:<0-7>
+    #[arg(long, value_parser = nhi::checkpoint_engine::parse_ionice)]
+    checkpoint_ionice: Option<nhi::checkpoint_engine::IoPriority>,
+
+    /// Apply --checkpoint-nice/--checkpoint-ionice to manual checkpoints too
+    #[arg(long)]
+    checkpoint_nice_manual: bool,
+
+    /// Default timestamp on captured output lines (off, rfc3339 or relative); start --timestamp overrides it
+    #[arg(long, default_value = "off")]
+    output_timestamps: types::OutputTimestamps,
+
+    /// Encrypt checkpoint images at rest with this AES-256-GCM key (64 hex digits); migrations then
+    /// send sealed archives instead of streaming through criu-image-streamer
+    #[arg(long, value_parser = nhi::parse_key, conflicts_with = "checkpoint_key_file")]
+    checkpoint_key: Option<[u8; 32]>,
+
+    /// Read the checkpoint key from a file (64 hex digits or 32 raw bytes) instead of the command line
+    #[arg(long)]
+    checkpoint_key_file: Option<std::path::PathBuf>,
+
+    /// Captured output lines longer than this are split into pieces marked "[line split]"
+    #[arg(long, default_value = "1048576")]
+    max_output_line_bytes: usize,
+
+    /// Output lines buffered per process for attached viewers; a viewer further behind misses lines
+    #[arg(long, default_value_t = process_manager::DEFAULT_OUTPUT_CHANNEL_CAPACITY)]
+    output_channel_capacity: usize,
+
+    /// Write an append-only JSON-lines audit log (instances/audit.log)
+    #[arg(long)]
+    audit_log: bool,
+
+    /// Seconds a shadow's source node may stay offline before the shadow is garbage collected
+    #[arg(long, default_value = "600")]
+    shadow_gc_grace_secs: u64,
+
+    /// Seconds between re-advertisements of running instances, so nodes that missed a creation broadcast get the shadow (0 disables)
+    #[arg(long, default_value = "60")]
+    instance_reconcile_secs: u64,
+
+    /// Recent output bytes kept in memory per shadow instance (full output stays on disk)
+    #[arg(long, default_value = "1048576")]
+    shadow_buffer_bytes: usize,
+
+    /// Milliseconds of program output collected into one (compressed) shadow sync message; 0 sends each chunk at once
+    #[arg(long, default_value = "100")]
+    shadow_output_batch_ms: u64,
+
+    /// Seconds a migration restore may run before it is killed and reported as failed
+    #[arg(long, default_value = "10")]
+    restore_timeout_secs: u64,
+
+    /// Restore and promote shadows automatically when their source node goes offline
+    #[arg(long)]
+    auto_failover: bool,
+
+    /// How the node taking over an orphaned instance is chosen (lowest-node-id or rendezvous)
+    #[arg(long, default_value = "lowest-node-id")]
+    failover_policy: FailoverPolicy,
+
+    /// Seconds a source node must stay offline before its instances are failed over
+    #[arg(long, default_value = "30")]
+    failover_grace_secs: u64,
+
+    /// Max age in seconds of a sync checkpoint used as the base of an incremental migration dump (0 always dumps in full)
+    #[arg(long, default_value = "60")]
+    incremental_migration_max_age_secs: u64,
+
+    /// Page faults plus CPU clock ticks since an instance's last auto-sync at or below which the next sync is skipped
+    #[arg(long, default_value = "0")]
+    sync_idle_threshold: u64,
+
+    /// Messages queued per peer before best-effort streams drop their oldest and control messages wait
+    #[arg(long, default_value = "1024")]
+    outbound_queue_capacity: usize,
+
+    /// Color terminal output: auto (only on a terminal, honoring NO_COLOR), always or never
+    #[arg(long, default_value = "auto")]
+    color: colors::ColorChoice,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    colors::set_color_choice(args.color);
 
     // Initialize logging system
     let log_dir = logger::default_log_dir();
@@ -96,28 +212,63 @@ async fn main() -> Result<()> {
     info!("Starting NHI");
     Output::header("NHI v0.1.0 - Starting Up");
 
+    // Every command records state under instances/; refuse to start rather than fail later
+    if let Err(e) = instance::check_data_dir_writable(std::path::Path::new(instance::INSTANCES_DIR)) {
+        error!("{}", e);
+        eprintln!("{} {}", ColorScheme::error_indicator("Error:"), ColorScheme::error(&e.to_string()));
+        std::process::exit(1);
+    }
+
+    let checkpoint_key = match args.checkpoint_key_file {
+        Some(ref path) => match nhi::load_key_file(path) {
+            Ok(key) => Some(key),
+            Err(e) => {
+                error!("Failed to read checkpoint key: {}", e);
+                eprintln!("{} {}", ColorScheme::error_indicator("Error:"), ColorScheme::error(&format!("Failed to read checkpoint key: {}", e)));
+                std::process::exit(1);
+            }
+        },
+        None => args.checkpoint_key,
+    };
+
     // Initialize managers
-    let process_manager = Arc::new(ProcessManager::new());
-    let criu_manager = Arc::new(CriuManager::new_with_path(&args.criu_path));
-    let instance_manager = Arc::new(Mutex::new(InstanceManager::new()));
+    let mut builder = NhiBuilder::new()
+        .criu_path(&args.criu_path)
+        .privilege_command(if args.no_sudo { nhi::PrivilegeCommand::None } else { args.privilege_cmd })
+        .dedup_checkpoints(args.dedup_checkpoints)
+        .output_timestamps(args.output_timestamps)
+        .max_output_line_bytes(args.max_output_line_bytes)
+        .output_channel_capacity(args.output_channel_capacity)
+        .checkpoint_key(checkpoint_key)
+        .criu_dump_args(args.criu_dump_args.clone())
+        .criu_restore_args(args.criu_restore_args.clone())
+        .dump_priority(nhi::checkpoint_engine::DumpPriority {
+            nice: args.checkpoint_nice,
+            ionice: args.checkpoint_ionice,
+            manual: args.checkpoint_nice_manual,
+        });
+    if args.audit_log {
+        builder = builder.audit_log_dir(nhi::default_audit_dir());
+    }
+    let mut nhi = match builder.build() {
+        Ok(nhi) => nhi,
+        Err(e) => {
+            error!("Failed to initialize: {}", e);
+            eprintln!("{} {}", ColorScheme::error_indicator("Error:"), ColorScheme::error(&e.to_string()));
+            std::process::exit(1);
+        }
+    };
+
+    // Scheduled rollback snapshots (start --snapshot-interval), independent of networking
+    instance::InstanceManager::start_snapshot_task(nhi.instance_manager.clone(), nhi.criu_manager.clone(), nhi.process_manager.clone());
 
     // Initialize CLI state
-    let cli_state = Arc::new(Mutex::new(CliState::new()));
-
-    // Initialize shadow instance manager (Stage 3)
-    let shadow_manager = if !args.no_network {
-        Some(Arc::new(tokio::sync::RwLock::new(ShadowInstanceManager::new_with_criu_path(
-            uuid::Uuid::new_v4(), // Will be updated with actual node ID
-            instance_manager.clone(),
-            process_manager.clone(),
-            &args.criu_path
-        ))))
-    } else {
-        None
-    };
+    let mut cli_state = CliState::new();
+    cli_state.interactive = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+    let cli_state = Arc::new(Mutex::new(cli_state));
 
-    // Initialize networking (Stage 2)
-    let node_manager = if !args.no_network {
+    // Initialize networking, shadow state and migration (Stages 2-4)
+    if !args.no_network {
         let network_config = NetworkConfig {
             listen_addr: args.listen_addr.parse()
                 .map_err(|e| anyhow::anyhow!("Invalid listen address: {}", e))?,
@@ -128,110 +279,52 @@ async fn main() -> Result<()> {
             heartbeat_interval_secs: 5,   // 更频繁的心跳，5秒间隔
             connection_timeout_secs: 10,
             max_connections: 100,
+            discovery_enabled: !args.no_discovery,
+            outbound_queue_capacity: args.outbound_queue_capacity,
+            discovery_interval_secs: args.discovery_interval,
+            discovery_ttl_secs: args.discovery_ttl,
+            discovery_group: args.discovery_group,
+        };
+        let cluster_options = ClusterOptions {
+            image_streamer_path: args.criu_streamer_path.clone().into(),
+            shadow_buffer_bytes: args.shadow_buffer_bytes,
+            shadow_output_batch: std::time::Duration::from_millis(args.shadow_output_batch_ms),
+            restore_timeout: std::time::Duration::from_secs(args.restore_timeout_secs),
+            shadow_gc_grace: std::time::Duration::from_secs(args.shadow_gc_grace_secs),
+            instance_reconcile_interval: match args.instance_reconcile_secs {
+                0 => None,
+                secs => Some(std::time::Duration::from_secs(secs)),
+            },
+            auto_failover: args.auto_failover,
+            failover_policy: args.failover_policy,
+            failover_grace: std::time::Duration::from_secs(args.failover_grace_secs),
+            incremental_max_age: match args.incremental_migration_max_age_secs {
+                0 => None,
+                secs => Some(std::time::Duration::from_secs(secs)),
+            },
+            sync_idle_threshold: args.sync_idle_threshold,
         };
 
-        let node_manager = Arc::new(NodeManager::new(network_config)?);
-
-        // Start networking
-        if let Err(e) = node_manager.start().await {
-            error!("Failed to start networking: {}", e);
-            Output::warning("Failed to start networking, running in standalone mode");
-            None
-        } else {
-            info!("Networking started successfully");
-
-            // Update shadow manager with actual node ID and set up network sender
-            if let Some(ref shadow_mgr) = shadow_manager {
-                let node_id = node_manager.node_id();
-                let mut shadow_mgr_write = shadow_mgr.write().await;
-                // Create a new shadow manager with the correct node ID
-                let mut new_shadow_mgr = ShadowInstanceManager::new_with_criu_path(node_id, instance_manager.clone(), process_manager.clone(), &args.criu_path);
-
-                // Set up network sender for shadow manager
-                let network_sender = node_manager.network_manager().get_sender();
-                new_shadow_mgr.set_network_sender(network_sender);
-
-                *shadow_mgr_write = new_shadow_mgr;
-            }
-
-            Output::network(&format!("Node {} listening on {}",
-                node_manager.node_id().to_string()[..8].to_uppercase(),
-                node_manager.local_node_info().listen_addr
-            ));
-            Some(node_manager)
-        }
-    } else {
-        Output::info("Running in standalone mode (networking disabled)");
-        None
-    };
-
-    // Initialize migration manager (Stage 4)
-    let migration_manager = if !args.no_network {
-        if let Some(ref node_mgr) = node_manager {
-            let mut mgr = MigrationManager::new_with_criu_path(
-                node_mgr.node_id(),
-                node_mgr.network_manager().clone(),
-                instance_manager.clone(),
-                process_manager.clone(),
-                &args.criu_path,
-            );
-
-            // Set shadow manager if available
-            if let Some(ref shadow_mgr) = shadow_manager {
-                mgr.set_shadow_manager(shadow_mgr.clone());
-            }
-
-            // Start the migration manager
-            if let Err(e) = mgr.start().await {
-                warn!("Failed to start migration manager: {}", e);
-                None
-            } else {
-                Some(Arc::new(mgr))
+        match nhi.start_cluster(network_config, cluster_options).await {
+            Ok(()) => {
+                info!("Networking started successfully");
+                if let Some(ref node_mgr) = nhi.node_manager {
+                    Output::network(&format!("Node {} listening on {}",
+                        node_mgr.node_id().to_string()[..8].to_uppercase(),
+                        node_mgr.local_node_info().listen_addr
+                    ));
+                }
             }
-        } else {
-            // Even in standalone mode, create a basic migration manager for checkpoint functionality
-            warn!("Creating standalone migration manager for checkpoint functionality");
-            let dummy_node_id = uuid::Uuid::new_v4();
-            let dummy_config = NetworkConfig {
-                listen_addr: "127.0.0.1:0".parse().unwrap(),
-                node_name: "standalone".to_string(),
-                discovery_port: 0,
-                heartbeat_interval_secs: 30,
-                connection_timeout_secs: 10,
-                max_connections: 1,
-            };
-            let dummy_network_manager = Arc::new(NetworkManager::new(dummy_config, dummy_node_id));
-
-            let mgr = MigrationManager::new_with_criu_path(
-                dummy_node_id,
-                dummy_network_manager,
-                instance_manager.clone(),
-                process_manager.clone(),
-                &args.criu_path,
-            );
-
-            // Start the migration manager (mainly for checkpoint functionality)
-            if let Err(e) = mgr.start().await {
-                warn!("Failed to start standalone migration manager: {}", e);
-                None
-            } else {
-                Some(Arc::new(mgr))
+            Err(e) => {
+                error!("Failed to start networking: {}", e);
+                Output::warning("Failed to start networking, running in standalone mode");
             }
         }
     } else {
-        None
-    };
-
-    // Set up shadow manager in node manager and process manager if available
-    if let (Some(ref node_mgr), Some(ref shadow_mgr)) = (&node_manager, &shadow_manager) {
-        node_mgr.set_shadow_manager(shadow_mgr.clone()).await;
-        process_manager.set_shadow_manager(shadow_mgr.clone()).await;
+        Output::info("Running in standalone mode (networking disabled)");
     }
 
-    // Set up migration manager in node manager if available
-    if let (Some(ref node_mgr), Some(ref migration_mgr)) = (&node_manager, &migration_manager) {
-        node_mgr.set_migration_manager(migration_mgr.clone()).await;
-    }
+    let Nhi { instance_manager, process_manager, criu_manager, node_manager, shadow_manager, migration_manager, .. } = nhi;
 
     // Start HTTP API server if enabled
     if args.http_port > 0 {
@@ -255,13 +348,24 @@ async fn main() -> Result<()> {
         Output::info("HTTP API disabled (port set to 0)");
     }
 
-    // Create readline editor
-    let mut rl = DefaultEditor::new()?;
+    // SIGTERM (service managers) shuts down like `exit`; SIGINT does too while idle, and
+    // otherwise only stops a command that is waiting on the cluster
+    let signals = cli_state.lock().await.signals.clone();
+    let mut shutdown_signals = signals.subscribe();
+    signals.listen();
+    let terminal = signals::SavedTerminal::save();
+    let mut lines = LineReader::spawn(DefaultEditor::new()?);
+    let mut exit_signal = None;
 
     Output::header("NHI v0.1.0 - Ready");
     Output::info("Type 'help' for available commands or 'exit' to quit.");
 
     loop {
+        if let Some(signal) = signals::pending_shutdown(&mut shutdown_signals) {
+            exit_signal = Some(signal);
+            break;
+        }
+
         let prompt = {
             let state = cli_state.lock().await;
             if let Some(instance_id) = &state.attached_instance {
@@ -271,7 +375,13 @@ async fn main() -> Result<()> {
             }
         };
 
-        let readline = rl.readline(&prompt);
+        let readline = tokio::select! {
+            line = lines.read(prompt) => line,
+            signal = signals::next_signal(&mut shutdown_signals) => {
+                exit_signal = Some(signal);
+                break;
+            }
+        };
         match readline {
             Ok(line) => {
                 let line = line.trim();
@@ -279,8 +389,6 @@ async fn main() -> Result<()> {
                     continue;
                 }
 
-                rl.add_history_entry(line)?;
-
                 // Check if we're in attach mode
                 let attached_instance = {
                     let state = cli_state.lock().await;
@@ -316,9 +424,13 @@ async fn main() -> Result<()> {
                                     // Handle shadow instance input forwarding
                                     if let Some(ref shadow_mgr) = shadow_manager {
                                         let shadow_mgr_read = shadow_mgr.read().await;
-                                        if let Err(e) = shadow_mgr_read.forward_input_to_source(uuid, line.to_string()).await {
-                                            error!("Failed to forward input to source instance: {}", e);
-                                            println!("Error forwarding input: {}", e);
+                                        match shadow_mgr_read.forward_input_to_source(uuid, line.to_string()).await {
+                                            // Nothing here waits for the acknowledgement
+                                            Ok(input_id) => shadow_mgr_read.forget_input(input_id).await,
+                                            Err(e) => {
+                                                error!("Failed to forward input to source instance: {}", e);
+                                                println!("Error forwarding input: {}", e);
+                                            }
                                         }
                                     } else {
                                         println!("Shadow management not available");
@@ -376,813 +488,113 @@ async fn main() -> Result<()> {
         }
     }
 
-    info!("Shutting down NHI");
-
-    // Gracefully shutdown networking if enabled
-    if let Some(ref node_mgr) = node_manager {
-        if let Err(e) = node_mgr.stop().await {
-            warn!("Error during node manager shutdown: {}", e);
-        }
+    // Line editing may still hold the terminal when a signal ends the loop
+    ui::restore_terminal();
+    terminal.restore();
+    let code = finish(exit_signal, &node_manager, &instance_manager).await;
+    if code != 0 {
+        std::process::exit(code);
     }
 
     Ok(())
 }
 
-pub async fn execute_command(
-    input: &str,
-    cli_state: &Arc<Mutex<CliState>>,
-    instance_manager: &Arc<Mutex<InstanceManager>>,
-    process_manager: &Arc<ProcessManager>,
-    criu_manager: &Arc<CriuManager>,
+/// Run the shutdown and return the exit status: 0 after `exit` or end of input, 128 plus the
+/// signal number when a signal ended the session
+async fn finish(
+    signal: Option<ShutdownSignal>,
     node_manager: &Option<Arc<NodeManager>>,
-    shadow_manager: &Option<Arc<tokio::sync::RwLock<ShadowInstanceManager>>>,
-    migration_manager: &Option<Arc<MigrationManager>>,
-) -> Result<bool> {
-    let command = CliCommand::parse_from_str(input)?;
-
-    match command {
-        CliCommand::Help => {
-            print_help();
-            Ok(false)
-        }
-        CliCommand::Exit => {
-            // Gracefully shutdown networking if enabled
-            if let Some(ref node_mgr) = node_manager {
-                if let Err(e) = node_mgr.stop().await {
-                    warn!("Error during node manager shutdown: {}", e);
-                }
-            }
-            Output::success("Goodbye!");
-            Ok(true)
-        }
-        CliCommand::Start { program, args } => {
-            let (instance_id, instance) = {
-                let mut manager = instance_manager.lock().await;
-                let instance_id = manager.start_instance(
-                    program,
-                    args,
-                    process_manager.clone(),
-                ).await?;
-
-                // Get the instance for shadow creation
-                let instance = manager.get_instance_by_id(&instance_id)
-                    .ok_or_else(|| anyhow::anyhow!("Failed to get started instance"))?
-                    .clone();
-
-                (instance_id, instance)
-            };
-
-            Output::instance(&format!("Started instance: {}", instance_id));
-
-            // Broadcast instance creation to other nodes if networking is enabled
-            if let Some(ref shadow_mgr) = shadow_manager {
-                let shadow_mgr_read = shadow_mgr.read().await;
-                if let Err(e) = shadow_mgr_read.broadcast_instance_creation(&instance).await {
-                    warn!("Failed to broadcast instance creation: {}", e);
-                    Output::warning("Failed to notify other nodes about instance creation");
-                } else {
-                    Output::network("Instance creation broadcasted to cluster");
-                }
-            }
-
-            Ok(false)
-        }
-        CliCommand::StartDetached { program, args } => {
-            let (instance_id, instance) = {
-                let mut manager = instance_manager.lock().await;
-                let instance_id = manager.start_instance_detached(
-                    program,
-                    args,
-                    process_manager.clone(),
-                ).await?;
-
-                // Get the instance for shadow creation
-                let instance = manager.get_instance_by_id(&instance_id)
-                    .ok_or_else(|| anyhow::anyhow!("Failed to get started instance"))?
-                    .clone();
-
-                (instance_id, instance)
-            };
-
-            println!("{} {} {}",
-                ColorScheme::success_indicator("Started detached instance:"),
-                ColorScheme::instance_id(&instance_id),
-                ColorScheme::info("(optimized for CRIU)")
-            );
-            println!("{} {}",
-                ColorScheme::info_indicator("Note:"),
-                ColorScheme::info("Detached instances have limited input capabilities but are CRIU-friendly")
-            );
-
-            // Broadcast instance creation to other nodes if networking is enabled
-            if let Some(ref shadow_mgr) = shadow_manager {
-                let shadow_mgr_read = shadow_mgr.read().await;
-                if let Err(e) = shadow_mgr_read.broadcast_instance_creation(&instance).await {
-                    warn!("Failed to broadcast instance creation: {}", e);
-                    println!("{} {}",
-                        ColorScheme::warning_indicator("Warning:"),
-                        ColorScheme::warning("Failed to notify other nodes about instance creation")
-                    );
-                } else {
-                    println!("{} {}",
-                        ColorScheme::info_indicator("Broadcast:"),
-                        ColorScheme::info("Instance creation broadcasted to cluster")
-                    );
-                }
-            }
-
-            Ok(false)
-        }
-        CliCommand::Stop { instance_id } => {
-            // Get the instance UUID before stopping
-            let instance_uuid = {
-                let manager = instance_manager.lock().await;
-                manager.resolve_instance_id(&instance_id)?
-            };
-
-            // Stop the instance
-            {
-                let mut manager = instance_manager.lock().await;
-                manager.stop_instance(&instance_id, process_manager.clone()).await?;
-            }
-
-            // Broadcast instance stop to shadow instances if shadow manager is available
-            if let Some(ref shadow_mgr) = shadow_manager {
-                let shadow_mgr_read = shadow_mgr.read().await;
-                if let Err(e) = shadow_mgr_read.broadcast_instance_stop(instance_uuid).await {
-                    warn!("Failed to broadcast instance stop: {}", e);
-                } else {
-                    info!("Broadcasted instance stop for {}", instance_uuid);
-                }
-            }
-
-            println!("{} {}",
-                ColorScheme::success_indicator("Stopped instance:"),
-                ColorScheme::instance_id(&instance_id)
-            );
-            Ok(false)
-        }
-        CliCommand::Pause { instance_id } => {
-            let mut manager = instance_manager.lock().await;
-            manager.pause_instance(&instance_id, process_manager.clone()).await?;
-            println!("Paused instance: {}", instance_id);
-            Ok(false)
-        }
-        CliCommand::Resume { instance_id } => {
-            let mut manager = instance_manager.lock().await;
-            manager.resume_instance(&instance_id, process_manager.clone()).await?;
-            println!("Resumed instance: {}", instance_id);
-            Ok(false)
-        }
-        CliCommand::List => {
-            let manager = instance_manager.lock().await;
-            manager.list_instances();
-            Ok(false)
-        }
-        CliCommand::Attach { instance_id } => {
-            let manager = instance_manager.lock().await;
-            if manager.has_instance(&instance_id) {
-                let uuid = manager.resolve_instance_id(&instance_id)?;
-
-                if let Some(instance) = manager.get_instance_by_id(&uuid.to_string()) {
-                    if instance.status == crate::types::InstanceStatus::Shadow {
-                        // Handle shadow instance attach
-                        drop(manager); // Release the lock before entering attach mode
-                        match enter_shadow_attach_mode(
-                            &instance_id,
-                            uuid,
-                            &cli_state,
-                            &instance_manager,
-                            &shadow_manager,
-                        ).await {
-                            Ok(_) => {},
-                            Err(e) => {
-                                error!("Failed to enter shadow attach mode: {}", e);
-                                println!("Error entering shadow attach mode: {}", e);
-                            }
-                        }
-                    } else {
-                        // Handle regular instance attach
-                        // Check if the process is actually running
-                        if let Some(pid) = process_manager.get_process_pid(&uuid).await {
-                            let proc_path = format!("/proc/{}", pid);
-                            if !std::path::Path::new(&proc_path).exists() {
-                                println!("Error: Instance {} (PID {}) is no longer running", instance_id, pid);
-                                println!("Use 'list' to see current instance status");
-                                return Ok(false);
-                            }
-                        } else {
-                            println!("Error: Instance {} has no associated process", instance_id);
-                            return Ok(false);
-                        }
-
-                        drop(manager); // Release the lock before entering attach mode
-                        // Enter attach UI mode
-                        match enter_attach_mode(
-                            &instance_id,
-                            uuid,
-                            &cli_state,
-                            &instance_manager,
-                            &process_manager,
-                        ).await {
-                            Ok(_) => {},
-                            Err(e) => {
-                                error!("Failed to enter attach mode: {}", e);
-                                println!("Error entering attach mode: {}", e);
-                            }
-                        }
-                    }
-                } else {
-                    println!("Instance not found: {}", instance_id);
-                }
-            } else {
-                println!("Instance not found: {}", instance_id);
-            }
-            Ok(false)
-        }
-        CliCommand::Detach => {
-            let mut state = cli_state.lock().await;
-            if let Some(instance_id) = &state.attached_instance {
-                println!("Detached from instance: {}", instance_id);
-
-                // Stop the output monitoring task
-                if let Some(task) = state.output_task.take() {
-                    task.abort();
-                }
-
-                state.attached_instance = None;
-            } else {
-                println!("Not attached to any instance");
-            }
-            Ok(false)
-        }
-        CliCommand::Logs { instance_id, lines } => {
-            let target_instance = if let Some(id) = instance_id {
-                id
-            } else {
-                let state = cli_state.lock().await;
-                if let Some(attached_id) = &state.attached_instance {
-                    attached_id.clone()
-                } else {
-                    println!("No instance specified and not attached to any instance");
-                    return Ok(false);
-                }
-            };
-
-            let manager = instance_manager.lock().await;
-            if let Ok(uuid) = manager.resolve_instance_id(&target_instance) {
-                if let Some(history) = process_manager.get_output_history(&uuid).await {
-                    let lines_to_show = lines.unwrap_or(20);
-                    let start_idx = if history.len() > lines_to_show {
-                        history.len() - lines_to_show
-                    } else {
-                        0
-                    };
-
-                    println!("=== Last {} lines of output for instance {} ===",
-                             std::cmp::min(lines_to_show, history.len()), target_instance);
-                    for line in &history[start_idx..] {
-                        println!("{}", line);
-                    }
-                    println!("=== End of logs ===");
-                } else {
-                    println!("No output history available for instance: {}", target_instance);
-                }
-            } else {
-                println!("Instance not found: {}", target_instance);
-            }
-            Ok(false)
-        }
-        CliCommand::Checkpoint { instance_id, name } => {
-            let mut manager = instance_manager.lock().await;
-            manager.checkpoint_instance(
-                &instance_id,
-                &name,
-                criu_manager.clone(),
-                process_manager.clone(),
-            ).await?;
-            println!("{} {} {} {}",
-                ColorScheme::success_indicator("Created checkpoint"),
-                ColorScheme::checkpoint(&name),
-                ColorScheme::info("for instance:"),
-                ColorScheme::instance_id(&instance_id)
-            );
-            Ok(false)
-        }
-        CliCommand::Restore { instance_id, checkpoint_name } => {
-            let mut manager = instance_manager.lock().await;
-            manager.restore_instance_to_existing(
-                &instance_id,
-                &checkpoint_name,
-                criu_manager.clone(),
-                process_manager.clone(),
-            ).await?;
-            println!("{} {} {} {}",
-                ColorScheme::success_indicator("Restored instance"),
-                ColorScheme::instance_id(&instance_id),
-                ColorScheme::info("from checkpoint:"),
-                ColorScheme::checkpoint(&checkpoint_name)
-            );
-            Ok(false)
-        }
-        CliCommand::Cd { directory } => {
-            std::env::set_current_dir(&directory)?;
-            println!("Changed directory to: {}", directory);
-            Ok(false)
-        }
-        CliCommand::AnalyzeTty { instance_id } => {
-            let manager = instance_manager.lock().await;
-            if let Ok(uuid) = manager.resolve_instance_id(&instance_id) {
-                if let Some(pid) = process_manager.get_process_pid(&uuid).await {
-                    use crate::tty_utils::check_process_tty_compatibility;
-
-                    println!("Analyzing TTY environment for instance {} (PID: {})...", instance_id, pid);
-
-                    match check_process_tty_compatibility(pid) {
-                        Ok(is_compatible) => {
-                            if is_compatible {
-                                println!("✅ Process has good CRIU compatibility");
-                            } else {
-                                println!("⚠️  Process may have CRIU compatibility issues");
-                                println!("💡 Consider using 'start-detached' for better CRIU compatibility");
-                            }
-                        }
-                        Err(e) => {
-                            println!("❌ Failed to analyze TTY environment: {}", e);
-                        }
-                    }
-                } else {
-                    println!("Instance {} is not running", instance_id);
-                }
-            } else {
-                println!("Instance not found: {}", instance_id);
-            }
-            Ok(false)
-        }
-        // Cluster management commands (Stage 2)
-        CliCommand::ClusterListNodes => {
-            if let Some(ref node_mgr) = node_manager {
-                let node_list = node_mgr.get_node_list().await;
-                println!("{}", node_list);
-            } else {
-                println!("{} {}",
-                    ColorScheme::warning_indicator("Warning:"),
-                    ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
-                );
-            }
-            Ok(false)
-        }
-        CliCommand::ClusterNodeInfo { node_id } => {
-            if let Some(ref node_mgr) = node_manager {
-                if let Some(node_id_str) = node_id {
-                    // Parse node ID and show specific node info
-                    match uuid::Uuid::parse_str(&node_id_str) {
-                        Ok(uuid) => {
-                            if let Some(node_info) = node_mgr.cluster_state().get_node_info(&uuid).await {
-                                println!("Node Information:");
-                                println!("  ID: {}", node_info.node_id);
-                                println!("  Name: {}", node_info.name);
-                                println!("  Address: {}", node_info.listen_addr);
-                                println!("  Status: {:?}", node_info.status);
-                                println!("  Version: {}", node_info.version);
-                                println!("  Joined: {}", node_info.joined_at.format("%Y-%m-%d %H:%M:%S UTC"));
-                                println!("  Last Seen: {}", node_info.last_seen.format("%Y-%m-%d %H:%M:%S UTC"));
-                                println!("  Capabilities: {}", node_info.capabilities.join(", "));
-                            } else {
-                                println!("Node not found: {}", node_id_str);
-                            }
-                        }
-                        Err(_) => {
-                            println!("Invalid node ID format: {}", node_id_str);
-                        }
-                    }
-                } else {
-                    // Show local node info
-                    let local_info = node_mgr.local_node_info();
-                    println!("Local Node Information:");
-                    println!("  ID: {}", local_info.node_id);
-                    println!("  Name: {}", local_info.name);
-                    println!("  Address: {}", local_info.listen_addr);
-                    println!("  Status: {:?}", local_info.status);
-                    println!("  Version: {}", local_info.version);
-                    println!("  Capabilities: {}", local_info.capabilities.join(", "));
-                }
-            } else {
-                println!("{} {}",
-                    ColorScheme::warning_indicator("Warning:"),
-                    ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
-                );
-            }
-            Ok(false)
-        }
-        CliCommand::ClusterConnect { address } => {
-            if let Some(ref node_mgr) = node_manager {
-                match address.parse::<std::net::SocketAddr>() {
-                    Ok(addr) => {
-                        println!("Connecting to {}...", addr);
-                        match node_mgr.connect_to_peer(addr).await {
-                            Ok(_) => {
-                                println!("{} {}",
-                                    ColorScheme::success_indicator("Success:"),
-                                    ColorScheme::success(&format!("Connection initiated to {}", addr))
-                                );
-                            }
-                            Err(e) => {
-                                println!("{} {}",
-                                    ColorScheme::error_indicator("Error:"),
-                                    ColorScheme::error(&format!("Failed to connect to {}: {}", addr, e))
-                                );
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        println!("{} {}",
-                            ColorScheme::error_indicator("Error:"),
-                            ColorScheme::error(&format!("Invalid address format: {}", e))
-                        );
-                    }
-                }
-            } else {
-                println!("{} {}",
-                    ColorScheme::warning_indicator("Warning:"),
-                    ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
-                );
-            }
-            Ok(false)
-        }
-        CliCommand::ClusterDisconnect { node_id } => {
-            if let Some(ref node_mgr) = node_manager {
-                match uuid::Uuid::parse_str(&node_id) {
-                    Ok(uuid) => {
-                        match node_mgr.disconnect_peer(&uuid).await {
-                            Ok(_) => {
-                                println!("{} {}",
-                                    ColorScheme::success_indicator("Success:"),
-                                    ColorScheme::success(&format!("Disconnected from node {}", node_id))
-                                );
-                            }
-                            Err(e) => {
-                                println!("{} {}",
-                                    ColorScheme::error_indicator("Error:"),
-                                    ColorScheme::error(&format!("Failed to disconnect from {}: {}", node_id, e))
-                                );
-                            }
-                        }
-                    }
-                    Err(_) => {
-                        println!("{} {}",
-                            ColorScheme::error_indicator("Error:"),
-                            ColorScheme::error("Invalid node ID format")
-                        );
-                    }
-                }
-            } else {
-                println!("{} {}",
-                    ColorScheme::warning_indicator("Warning:"),
-                    ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
-                );
-            }
-            Ok(false)
-        }
-        CliCommand::ClusterStatus => {
-            if let Some(ref node_mgr) = node_manager {
-                let cluster_info = node_mgr.get_cluster_info().await;
-                println!("{}", cluster_info);
-
-                // Also show connected peers
-                let peers = node_mgr.get_connected_peers().await;
-                if !peers.is_empty() {
-                    println!("\nActive Connections:");
-                    for (peer_id, addr) in peers {
-                        println!("  {} - {}",
-                            peer_id.to_string()[..8].to_uppercase(),
-                            addr
-                        );
-                    }
-                } else {
-                    println!("\nNo active connections");
-                }
-            } else {
-                println!("{} {}",
-                    ColorScheme::warning_indicator("Warning:"),
-                    ColorScheme::warning("Networking is disabled. Use --help to see networking options.")
-                );
-            }
-            Ok(false)
-        }
-        CliCommand::Migrate { instance_id, target_node_id } => {
-            if let Some(ref node_mgr) = node_manager {
-                match uuid::Uuid::parse_str(&target_node_id) {
-                    Ok(target_uuid) => {
-                        // Check if instance exists
-                        if !instance_manager.lock().await.has_instance(&instance_id) {
-                            println!("{} {}",
-                                ColorScheme::error_indicator("Error:"),
-                                ColorScheme::error(&format!("Instance '{}' not found", instance_id))
-                            );
-                            return Ok(false);
-                        }
-
-                        // Check if target node exists in cluster
-                        let cluster_state = node_mgr.cluster_state();
-                        let nodes = cluster_state.get_online_nodes().await;
-
-                        if !nodes.iter().any(|node| node.node_id == target_uuid) {
-                            println!("{} {}",
-                                ColorScheme::error_indicator("Error:"),
-                                ColorScheme::error(&format!("Target node '{}' not found in cluster", target_node_id))
-                            );
-                            return Ok(false);
-                        }
-
-                        println!("{} {} {} {}",
-                            ColorScheme::info_indicator("Migration:"),
-                            ColorScheme::info("Starting migration of instance"),
-                            ColorScheme::instance_id(&instance_id),
-                            ColorScheme::info(&format!("to node {}", target_node_id))
-                        );
-
-                        // Use migration manager to initiate migration
-                        if let Some(ref migration_mgr) = migration_manager {
-                            // Use default migration options
-                            let options = crate::migration_manager::MigrationOptions::default();
-
-                            // Initiate migration
-                            match migration_mgr.migrate_instance(&instance_id, target_uuid, options).await {
-                                Ok(migration_id) => {
-                                    println!("{} {} {}",
-                                        ColorScheme::success_indicator("Success:"),
-                                        ColorScheme::success("Migration request initiated with ID:"),
-                                        ColorScheme::info(&migration_id.to_string()[..8])
-                                    );
-                                    println!("{} {}",
-                                        ColorScheme::info_indicator("Note:"),
-                                        ColorScheme::info("Migration is running in the background. Use 'list' to check status.")
-                                    );
-                                }
-                                Err(e) => {
-                                    println!("{} {}",
-                                        ColorScheme::error_indicator("Error:"),
-                                        ColorScheme::error(&format!("Failed to initiate migration: {}", e))
-                                    );
-                                }
-                            }
-                        } else {
-                            println!("{} {}",
-                                ColorScheme::warning_indicator("Warning:"),
-                                ColorScheme::warning("Migration manager is not available.")
-                            );
-                        }
-                    }
-                    Err(_) => {
-                        println!("{} {}",
-                            ColorScheme::error_indicator("Error:"),
-                            ColorScheme::error("Invalid target node ID format")
-                        );
-                    }
-                }
-            } else {
-                println!("{} {}",
-                    ColorScheme::warning_indicator("Warning:"),
-                    ColorScheme::warning("Networking is disabled. Migration requires networking.")
-                );
-            }
-            Ok(false)
-        }
-        CliCommand::ShadowView { instance_id: _ } => {
-            println!("{} {}",
-                ColorScheme::info_indicator("Shadow View:"),
-                ColorScheme::info("Shadow view functionality will be implemented after core sync is working")
-            );
-            Ok(false)
-        }
+    instance_manager: &Arc<Mutex<InstanceManager>>,
+) -> i32 {
+    if let Some(signal) = signal {
+        warn!("Received {}, shutting down", signal.name());
     }
+    shutdown(node_manager, instance_manager).await;
+    signal.map_or(0, ShutdownSignal::exit_code)
 }
 
-async fn enter_attach_mode(
-    instance_id: &str,
-    uuid: Uuid,
-    cli_state: &Arc<Mutex<CliState>>,
-    _instance_manager: &Arc<Mutex<InstanceManager>>,
-    process_manager: &Arc<ProcessManager>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut ui = AttachUI::new()?;
-    ui.enter_attach_mode(instance_id)?;
-
-    // Get historical output and display it
-    if let Some(history) = process_manager.get_output_history(&uuid).await {
-        for line in &history {
-            ui.add_output_line(line.clone())?;
-        }
-    }
-
-    // Subscribe to real-time output
-    let mut output_receiver = process_manager.subscribe_to_output(&uuid).await;
-
-    // Set attached state
-    {
-        let mut state = cli_state.lock().await;
-        state.attached_instance = Some(instance_id.to_string());
-    }
+/// Line editing blocks, so it runs on its own thread and the command loop can wait on it and
+/// on signals at the same time
+struct LineReader {
+    prompts: std::sync::mpsc::Sender<String>,
+    lines: tokio::sync::mpsc::UnboundedReceiver<rustyline::Result<String>>,
+}
 
-    // Main attach loop
-    loop {
-        // Handle real-time output
-        if let Some(ref mut receiver) = output_receiver {
-            match receiver.try_recv() {
-                Ok(output) => {
-                    ui.add_output_line(output)?;
-                }
-                Err(tokio::sync::broadcast::error::TryRecvError::Empty) => {
-                    // No new output, continue
-                }
-                Err(tokio::sync::broadcast::error::TryRecvError::Lagged(_)) => {
-                    // We're lagging behind, continue
-                }
-                Err(tokio::sync::broadcast::error::TryRecvError::Closed) => {
-                    // Output stream closed, process might have ended
-                    ui.add_output_line("[Process output stream closed]".to_string())?;
+impl LineReader {
+    fn spawn(mut editor: DefaultEditor) -> Self {
+        let (prompts, prompt_receiver) = std::sync::mpsc::channel::<String>();
+        let (line_sender, lines) = tokio::sync::mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            while let Ok(prompt) = prompt_receiver.recv() {
+                let line = editor.readline(&prompt);
+                if let Ok(line) = &line {
+                    if !line.trim().is_empty() {
+                        let _ = editor.add_history_entry(line.trim());
+                    }
                 }
-            }
-        }
-
-        // Handle user input
-        if let Some(input) = ui.handle_input()? {
-            if input == "detach" {
-                break;
-            } else {
-                // Forward input to process
-                if let Err(e) = process_manager.send_input(&uuid, input).await {
-                    ui.add_output_line(format!("[Error sending input: {}]", e))?;
+                if line_sender.send(line).is_err() {
+                    break;
                 }
             }
-        }
-
-        // Small delay to prevent busy waiting
-        tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        });
+        Self { prompts, lines }
     }
 
-    // Clean up
-    ui.exit_attach_mode()?;
-
-    // Clear attached state
-    {
-        let mut state = cli_state.lock().await;
-        state.attached_instance = None;
-        if let Some(task) = state.output_task.take() {
-            task.abort();
+    async fn read(&mut self, prompt: String) -> rustyline::Result<String> {
+        if self.prompts.send(prompt).is_err() {
+            return Err(ReadlineError::Eof);
         }
+        self.lines.recv().await.unwrap_or(Err(ReadlineError::Eof))
     }
-
-    println!("Detached from instance: {}", instance_id);
-    Ok(())
 }
 
-async fn enter_shadow_attach_mode(
-    instance_id: &str,
-    uuid: Uuid,
-    cli_state: &Arc<Mutex<CliState>>,
-    _instance_manager: &Arc<Mutex<InstanceManager>>,
-    shadow_manager: &Option<Arc<tokio::sync::RwLock<ShadowInstanceManager>>>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let shadow_mgr = match shadow_manager {
-        Some(mgr) => mgr,
-        None => {
-            println!("Shadow management is not available (networking disabled)");
-            return Ok(());
-        }
-    };
-
-    let mut ui = AttachUI::new()?;
-    ui.enter_attach_mode(&format!("{} (Shadow)", instance_id))?;
-
-    // Get shadow instance info and display historical output
-    {
-        let shadow_mgr_read = shadow_mgr.read().await;
-        if let Some(shadow_info) = shadow_mgr_read.get_shadow_instance(uuid).await {
-            ui.add_output_line(format!("[Shadow Instance - Source Node: {}]",
-                shadow_info.source_node_id.to_string()[..8].to_uppercase()))?;
-            ui.add_output_line(format!("[Last Sync: {}]",
-                shadow_info.last_sync_time.format("%H:%M:%S")))?;
-
-            // Display historical output from buffer
-            if !shadow_info.output_buffer.is_empty() {
-                if let Ok(output_str) = String::from_utf8(shadow_info.output_buffer.clone()) {
-                    for line in output_str.lines() {
-                        if !line.is_empty() {
-                            ui.add_output_line(line.to_string())?;
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    // Set attached state
-    {
-        let mut state = cli_state.lock().await;
-        state.attached_instance = Some(instance_id.to_string());
-    }
-
-    // Track what we've already displayed to show only new content
-    let mut last_displayed_size = 0;
-
-    // Main loop for shadow attach mode
-    loop {
-        // Check for new shadow sync data and display new output
-        {
-            let shadow_mgr_read = shadow_mgr.read().await;
-            if let Some(shadow_info) = shadow_mgr_read.get_shadow_instance(uuid).await {
-                // Check if there's new output data
-                if shadow_info.output_buffer.len() > last_displayed_size {
-                    // Get the new content
-                    let new_content = &shadow_info.output_buffer[last_displayed_size..];
-                    if let Ok(new_output) = String::from_utf8(new_content.to_vec()) {
-                        for line in new_output.lines() {
-                            if !line.is_empty() {
-                                ui.add_output_line(line.to_string())?;
-                            }
-                        }
-                    }
-                    last_displayed_size = shadow_info.output_buffer.len();
-                }
-            }
-        }
+/// Stop networking and flush instance state; shared by `exit`, end of input and termination signals
+async fn shutdown(node_manager: &Option<Arc<NodeManager>>, instance_manager: &Arc<Mutex<InstanceManager>>) {
+    info!("Shutting down NHI");
 
-        // Handle user input
-        if let Some(input) = ui.handle_input()? {
-            if input == "detach" {
-                break;
-            } else {
-                // Forward input to the source node through shadow manager
-                let shadow_mgr_read = shadow_mgr.read().await;
-                if let Err(e) = shadow_mgr_read.forward_input_to_source(uuid, input.clone()).await {
-                    ui.add_output_line(format!("[Shadow] Failed to forward input: {}", e))?;
-                } else {
-                    ui.add_output_line(format!("[Shadow] Forwarded input: {}", input))?;
-                }
-            }
+    // Gracefully shutdown networking if enabled
+    if let Some(ref node_mgr) = node_manager {
+        if let Err(e) = node_mgr.stop().await {
+            warn!("Error during node manager shutdown: {}", e);
         }
-
-        // Small delay to prevent busy waiting
-        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
     }
 
-    // Clean up
-    ui.exit_attach_mode()?;
-
-    // Clear attached state
-    {
-        let mut state = cli_state.lock().await;
-        state.attached_instance = None;
-        if let Some(task) = state.output_task.take() {
-            task.abort();
-        }
+    let failed = instance_manager.lock().await.save_all_metadata();
+    if failed > 0 {
+        warn!("{} instance(s) could not be saved during shutdown", failed);
     }
-
-    println!("Detached from shadow instance: {}", instance_id);
-    Ok(())
 }
 
-fn print_help() {
-    println!("{}", ColorScheme::header("Available commands:"));
-    println!("  {} {} - {}", ColorScheme::command("start"), ColorScheme::info("<program> [args...]"), "Start a new program instance");
-    println!("  {} {} - {}", ColorScheme::command("start-detached"), ColorScheme::info("<program> [args...]"), "Start a detached instance (CRIU-optimized)");
-    println!("  {} {} - {}", ColorScheme::command("stop"), ColorScheme::info("<instance_id>"), "Stop an instance");
-    println!("  {} {} - {}", ColorScheme::command("pause"), ColorScheme::info("<instance_id>"), "Pause an instance");
-    println!("  {} {} - {}", ColorScheme::command("resume"), ColorScheme::info("<instance_id>"), "Resume a paused instance");
-    println!("  {} - {}", ColorScheme::command("list"), "List all instances");
-    println!("  {} {} - {}", ColorScheme::command("attach"), ColorScheme::info("<instance_id>"), "Enter instance mode (shows historical output)");
-    println!("  {} - {}", ColorScheme::command("detach"), "Exit instance mode");
-    println!("  {} {} - {}", ColorScheme::command("logs"), ColorScheme::info("[instance_id] [lines]"), "Show recent output (default: current instance, 20 lines)");
-    println!("  {} {} - {}", ColorScheme::command("checkpoint"), ColorScheme::info("<instance_id> <name>"), "Create a checkpoint");
-    println!("  {} {} - {}", ColorScheme::command("restore"), ColorScheme::info("<instance_id> <checkpoint_name>"), "Restore instance from checkpoint");
-    println!("  {} {} - {}", ColorScheme::command("analyze-tty"), ColorScheme::info("<instance_id>"), "Analyze TTY environment for CRIU compatibility");
-    println!("  {} {} - {}", ColorScheme::command("cd"), ColorScheme::info("<directory>"), "Change working directory");
-    println!("  {} - {}", ColorScheme::command("help"), "Show this help");
-    println!("  {} - {}", ColorScheme::command("exit"), "Exit the CLI");
-    println!();
-    println!("{}", ColorScheme::header("Cluster Commands (Stage 2):"));
-    println!("  {} {} - {}", ColorScheme::command("cluster list-nodes"), ColorScheme::info(""), "List all nodes in the cluster");
-    println!("  {} {} - {}", ColorScheme::command("cluster node-info"), ColorScheme::info("[node_id]"), "Show node information (local if no ID)");
-    println!("  {} {} - {}", ColorScheme::command("cluster connect"), ColorScheme::info("<address>"), "Connect to a peer node");
-    println!("  {} {} - {}", ColorScheme::command("cluster disconnect"), ColorScheme::info("<node_id>"), "Disconnect from a peer node");
-    println!("  {} {} - {}", ColorScheme::command("cluster status"), ColorScheme::info(""), "Show cluster status and connections");
-    println!();
-    println!("{}", ColorScheme::header("Migration Commands (Stage 3):"));
-    println!("  {} {} - {}", ColorScheme::command("migrate"), ColorScheme::info("<instance_id> <target_node_id>"), "Migrate instance to another node");
-    println!("  {} {} - {}", ColorScheme::command("shadow-view"), ColorScheme::info("<instance_id>"), "View shadow instance output and status");
-    println!();
-    println!("{}", ColorScheme::header("Aliases:"));
-    println!("  {} = {}", ColorScheme::command("startd"), ColorScheme::command("start-detached"));
-    println!("  {} = {}", ColorScheme::command("tty"), ColorScheme::command("analyze-tty"));
-    println!("  {} = {}", ColorScheme::command("cluster nodes"), ColorScheme::command("cluster list-nodes"));
-    println!("  {} = {}", ColorScheme::command("cluster info"), ColorScheme::command("cluster node-info"));
-    println!();
-    println!("{}", ColorScheme::header("Tips:"));
-    println!("  {} {}", ColorScheme::info_indicator("•"), "Use 'start-detached' for better CRIU checkpoint/restore compatibility");
-    println!("  {} {}", ColorScheme::info_indicator("•"), "Use 'analyze-tty' to check if a process is CRIU-friendly");
-    println!("  {} {}", ColorScheme::info_indicator("•"), "Detached instances have limited input capabilities but work better with CRIU");
-    println!("  {} {}", ColorScheme::info_indicator("•"), "Use --no-network to disable P2P networking (Stage 1 compatibility mode)");
-    println!("  {} {}", ColorScheme::info_indicator("•"), "Nodes auto-discover each other on the local network");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use signals::Signals;
+
+    #[tokio::test]
+    async fn a_signal_on_the_channel_runs_the_shutdown_and_sets_the_exit_code() {
+        crate::test_support::use_scratch_dir();
+        let instance = types::Instance::new("sleep".to_string(), vec!["30".to_string()], std::path::PathBuf::from("/"));
+        let metadata_file = instance.metadata_file.clone();
+        let mut manager = InstanceManager::new();
+        manager.add_instance(instance);
+        let instance_manager = Arc::new(Mutex::new(manager));
+
+        let signals = Signals::new();
+        let mut command_loop = signals.subscribe();
+
+        // SIGINT while idle ends the session
+        signals.raise(ShutdownSignal::Interrupt);
+        let signal = signals::next_signal(&mut command_loop).await;
+        assert_eq!(finish(Some(signal), &None, &instance_manager).await, 130);
+        assert!(metadata_file.exists());
+
+        // SIGTERM that arrives while a command runs ends it once the command returns
+        std::fs::remove_file(&metadata_file).unwrap();
+        signals.raise(ShutdownSignal::Terminate);
+        let signal = signals::pending_shutdown(&mut command_loop);
+        assert_eq!(finish(signal, &None, &instance_manager).await, 143);
+        assert!(metadata_file.exists());
+
+        assert_eq!(finish(None, &None, &instance_manager).await, 0);
+    }
 }
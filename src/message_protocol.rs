@@ -1,12 +1,50 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::net::{Ipv4Addr, SocketAddr};
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
 
 /// Unique identifier for a node in the cluster
 pub type NodeId = Uuid;
 
+/// Version of the wire format. Messages are bincode-encoded, which has no notion of optional
+/// or defaulted fields: a message whose layout changed cannot be read by nodes built before
+/// the change. Bump this with every such change. Peers exchange it before the handshake and
+/// refuse a mismatch, so every node of a cluster has to be upgraded together.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Start of the protocol preamble, telling unversioned (older) peers apart from a version mismatch
+pub const PROTOCOL_MAGIC: [u8; 4] = *b"NHI\0";
+
+/// Length of the preamble each side sends before the handshake: magic, then the version
+pub const PROTOCOL_PREAMBLE_LEN: usize = 8;
+
+/// This node's protocol preamble
+pub fn protocol_preamble() -> [u8; PROTOCOL_PREAMBLE_LEN] {
+    let mut preamble = [0u8; PROTOCOL_PREAMBLE_LEN];
+    preamble[..4].copy_from_slice(&PROTOCOL_MAGIC);
+    preamble[4..].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    preamble
+}
+
+/// Check a peer's preamble, explaining why its messages could not be read
+pub fn check_protocol_preamble(preamble: &[u8; PROTOCOL_PREAMBLE_LEN]) -> std::result::Result<(), String> {
+    if preamble[..4] != PROTOCOL_MAGIC {
+        return Err(format!(
+            "peer sent no protocol version; it runs an older nhi build (this node speaks protocol {}), upgrade every node together",
+            PROTOCOL_VERSION
+        ));
+    }
+    let version = u32::from_be_bytes([preamble[4], preamble[5], preamble[6], preamble[7]]);
+    if version != PROTOCOL_VERSION {
+        return Err(format!(
+            "peer speaks protocol {}, this node speaks protocol {}; upgrade every node together",
+            version, PROTOCOL_VERSION
+        ));
+    }
+    Ok(())
+}
+
 /// Network message types for P2P communication
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NetworkMessage {
@@ -23,16 +61,47 @@ pub enum NetworkMessage {
     Goodbye(GoodbyeMessage),
     /// Instance registry synchronization
     InstanceSync(InstanceSyncMessage),
+    /// Ask peers to re-advertise their running instances, e.g. after joining the cluster
+    RequestInstanceSync(RequestInstanceSyncMessage),
+    /// Answer to an instance sync that asked for acknowledgement
+    InstanceSyncAck(InstanceSyncAckMessage),
     /// Instance stop notification
     InstanceStop(InstanceStopMessage),
     /// Shadow state data synchronization
     ShadowSync(ShadowSyncMessage),
     /// Shadow instance input forwarding
     ShadowInput(ShadowInputMessage),
+    /// Source node's answer to forwarded shadow input
+    ShadowInputAck(ShadowInputAckMessage),
+    /// Shadow attach session asking the source node to stream output without batching
+    LiveOutputRequest(LiveOutputRequestMessage),
     /// Migration command and coordination
     Migration(MigrationMessage),
     /// Real-time data streaming
     DataStream(DataStreamMessage),
+    /// Sequenced shadow output/checkpoint stream, reassembled in order by the receiver
+    StreamChunk(StreamChunkMessage),
+    /// Auto-failover asking every peer to let the sender take over an orphaned instance
+    FailoverClaim(FailoverClaimMessage),
+    /// A peer's answer to a failover claim
+    FailoverClaimAck(FailoverClaimAckMessage),
+    /// Withdraws a failover claim that did not collect every grant
+    FailoverRelease(FailoverClaimMessage),
+}
+
+impl NetworkMessage {
+    /// Messages that may be dropped under backpressure because a later one supersedes them
+    pub fn is_best_effort(&self) -> bool {
+        match self {
+            NetworkMessage::Heartbeat(_) | NetworkMessage::ClusterSync(_) => true,
+            // Output is superseded by the output file; checkpoint, memory and stdin data are not
+            NetworkMessage::DataStream(stream) => matches!(stream.stream_type, StreamType::Stdout | StreamType::Stderr),
+            NetworkMessage::ShadowSync(sync) => sync.checkpoint_data.is_none() && !sync.is_migration,
+            // A dropped output chunk leaves a gap the receiver skips; checkpoints must arrive
+            NetworkMessage::StreamChunk(message) => message.chunk.kind == StreamKind::Output,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -68,6 +137,8 @@ pub struct HeartbeatMessage {
     pub sender_id: NodeId,
     pub timestamp: DateTime<Utc>,
     pub load_info: NodeLoadInfo,
+    /// Peers the sender is directly connected to, gossiped to build the cluster topology
+    pub known_peers: Vec<NodeId>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -86,7 +157,7 @@ pub enum RequestType {
     /// Request to connect to this node
     ConnectRequest { listen_addr: SocketAddr },
     /// Ping request for connectivity test
-    Ping,
+    Ping { nonce: u64, sent_at: DateTime<Utc> },
 }
 
 /// Response types for requests
@@ -98,8 +169,8 @@ pub enum ResponseType {
     ClusterStatus(ClusterState),
     /// Connection acceptance/rejection
     ConnectResponse { accepted: bool, reason: Option<String> },
-    /// Pong response
-    Pong,
+    /// Pong response, echoing the ping's nonce and send time
+    Pong { nonce: u64, sent_at: DateTime<Utc> },
     /// Error response
     Error(String),
 }
@@ -115,6 +186,8 @@ pub struct NodeInfo {
     pub joined_at: DateTime<Utc>,
     pub last_seen: DateTime<Utc>,
     pub status: NodeStatus,
+    /// Free data dir space as of the node's last heartbeat; None if the node could not measure it
+    pub disk_available: Option<u64>,
 }
 
 /// Status of a node
@@ -133,6 +206,8 @@ pub struct NodeLoadInfo {
     pub memory_usage: f32,
     pub active_instances: u32,
     pub network_connections: u32,
+    /// Free bytes on the filesystem holding the data dir; None if the sender could not measure it
+    pub disk_available: Option<u64>,
 }
 
 /// Complete cluster state
@@ -142,6 +217,15 @@ pub struct ClusterState {
     pub cluster_id: Uuid,
     pub created_at: DateTime<Utc>,
     pub last_updated: DateTime<Utc>,
+    /// Direct connections reported by each node, merged from heartbeats and cluster syncs
+    pub peer_links: HashMap<NodeId, PeerLinks>,
+}
+
+/// A node's directly connected peers as of `updated_at`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PeerLinks {
+    pub peers: Vec<NodeId>,
+    pub updated_at: DateTime<Utc>,
 }
 
 impl NodeInfo {
@@ -160,6 +244,7 @@ impl NodeInfo {
             joined_at: now,
             last_seen: now,
             status: NodeStatus::Online,
+            disk_available: crate::stats::disk_available(std::path::Path::new(crate::instance::INSTANCES_DIR)),
         }
     }
 
@@ -167,6 +252,21 @@ impl NodeInfo {
         self.last_seen = Utc::now();
     }
 
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Whether the node satisfies an instance's affinity constraints
+    pub fn check_affinity(&self, affinity: &crate::types::Affinity) -> std::result::Result<(), String> {
+        affinity.check(self.node_id, &self.capabilities)
+    }
+
+    /// Whether the node can restore checkpoints, i.e. be a migration target
+    pub fn supports_checkpoint_restore(&self) -> bool {
+        self.has_capability(crate::capabilities::CAP_CRIU_CHECKPOINT)
+            && self.has_capability(crate::capabilities::CAP_CRIU_RESTORE)
+    }
+
     pub fn set_status(&mut self, status: NodeStatus) {
         self.status = status;
         self.update_last_seen();
@@ -181,6 +281,7 @@ impl ClusterState {
             cluster_id: Uuid::new_v4(),
             created_at: now,
             last_updated: now,
+            peer_links: HashMap::new(),
         }
     }
 
@@ -191,9 +292,22 @@ impl ClusterState {
 
     pub fn remove_node(&mut self, node_id: &NodeId) -> Option<NodeInfo> {
         self.last_updated = Utc::now();
+        self.peer_links.remove(node_id);
         self.nodes.remove(node_id)
     }
 
+    /// Record a node's direct peers, keeping whichever report is newer
+    pub fn set_peer_links(&mut self, node_id: NodeId, links: PeerLinks) -> bool {
+        match self.peer_links.get(&node_id) {
+            Some(existing) if existing.updated_at >= links.updated_at => false,
+            _ => {
+                self.peer_links.insert(node_id, links);
+                self.last_updated = Utc::now();
+                true
+            }
+        }
+    }
+
     pub fn update_node(&mut self, node_info: NodeInfo) {
         if let Some(existing) = self.nodes.get_mut(&node_info.node_id) {
             *existing = node_info;
@@ -227,6 +341,15 @@ impl Default for ClusterState {
     }
 }
 
+/// Default number of outbound messages queued per peer
+pub const DEFAULT_OUTBOUND_QUEUE_CAPACITY: usize = 1024;
+
+/// Default seconds between UDP discovery announcements
+pub const DEFAULT_DISCOVERY_INTERVAL_SECS: u64 = 10;
+
+/// Default seconds a discovered node stays a candidate peer without being heard from
+pub const DEFAULT_DISCOVERY_TTL_SECS: u64 = 30;
+
 /// Network configuration for the node
 #[derive(Debug, Clone)]
 pub struct NetworkConfig {
@@ -236,6 +359,16 @@ pub struct NetworkConfig {
     pub heartbeat_interval_secs: u64,
     pub connection_timeout_secs: u64,
     pub max_connections: usize,
+    /// Announce and listen for peers over UDP; explicit connections work either way
+    pub discovery_enabled: bool,
+    /// Messages queued per peer (and for broadcast) before backpressure applies
+    pub outbound_queue_capacity: usize,
+    /// Seconds between discovery announcements
+    pub discovery_interval_secs: u64,
+    /// Seconds after which a node that stopped announcing is dropped from the discovered set
+    pub discovery_ttl_secs: u64,
+    /// Where announcements and probes go: a broadcast address, or a multicast group the listener joins
+    pub discovery_group: Ipv4Addr,
 }
 
 impl Default for NetworkConfig {
@@ -247,6 +380,11 @@ impl Default for NetworkConfig {
             heartbeat_interval_secs: 30,
             connection_timeout_secs: 10,
             max_connections: 100,
+            discovery_enabled: true,
+            outbound_queue_capacity: DEFAULT_OUTBOUND_QUEUE_CAPACITY,
+            discovery_interval_secs: DEFAULT_DISCOVERY_INTERVAL_SECS,
+            discovery_ttl_secs: DEFAULT_DISCOVERY_TTL_SECS,
+            discovery_group: Ipv4Addr::BROADCAST,
         }
     }
 }
@@ -257,6 +395,27 @@ pub struct InstanceSyncMessage {
     pub sender_id: NodeId,
     pub instances: Vec<InstanceInfo>,
     pub timestamp: DateTime<Utc>,
+    /// Set when the sender wants an `InstanceSyncAck` once the shadows exist
+    pub sync_id: Option<Uuid>,
+    /// The list holds every instance running on the sender, so shadows of any other are orphaned
+    pub complete: bool,
+}
+
+/// Which of an acknowledged sync's instances the sender now holds shadows of
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceSyncAckMessage {
+    pub sender_id: NodeId,
+    pub target_node_id: NodeId, // Node that sent the sync
+    pub sync_id: Uuid,
+    pub shadowed: Vec<Uuid>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Request for a full `InstanceSync` of every instance the receiver runs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestInstanceSyncMessage {
+    pub sender_id: NodeId,
+    pub timestamp: DateTime<Utc>,
 }
 
 /// Instance stop notification message
@@ -276,6 +435,89 @@ pub struct ShadowSyncMessage {
     pub checkpoint_data: Option<Vec<u8>>,
     pub output_data: Option<Vec<u8>>,
     pub timestamp: DateTime<Utc>,
+    /// Migration checkpoint: processed regardless of `data_version` and does not advance it
+    pub is_migration: bool,
+    /// `output_data` is gzip-compressed
+    pub output_compressed: bool,
+}
+
+/// Output batches smaller than this are sent uncompressed
+pub const OUTPUT_COMPRESSION_MIN_BYTES: usize = 512;
+
+/// Largest batch of shadow output a peer's compressed data may expand to
+pub const MAX_DECOMPRESSED_OUTPUT_BYTES: usize = 64 * 1024 * 1024;
+
+/// Gzip a batch of shadow output, returning whether it was compressed. Small batches and
+/// batches that don't shrink are left as they are.
+pub fn compress_output(data: Vec<u8>) -> (Vec<u8>, bool) {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    if data.len() < OUTPUT_COMPRESSION_MIN_BYTES {
+        return (data, false);
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    match encoder.write_all(&data).and_then(|_| encoder.finish()) {
+        Ok(compressed) if compressed.len() < data.len() => (compressed, true),
+        _ => (data, false),
+    }
+}
+
+impl ShadowSyncMessage {
+    /// Undo output compression so `output_data` holds the raw bytes. Data that would expand
+    /// past `MAX_DECOMPRESSED_OUTPUT_BYTES` is refused.
+    pub fn decompress_output(&mut self) -> std::io::Result<()> {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        if !self.output_compressed {
+            return Ok(());
+        }
+        if let Some(compressed) = self.output_data.take() {
+            let mut output = Vec::new();
+            GzDecoder::new(compressed.as_slice())
+                .take(MAX_DECOMPRESSED_OUTPUT_BYTES as u64 + 1)
+                .read_to_end(&mut output)?;
+            if output.len() > MAX_DECOMPRESSED_OUTPUT_BYTES {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("compressed output expands past {} bytes", MAX_DECOMPRESSED_OUTPUT_BYTES),
+                ));
+            }
+            self.output_data = Some(output);
+        }
+        self.output_compressed = false;
+        Ok(())
+    }
+}
+
+/// What a shadow stream chunk carries
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum StreamKind {
+    /// Program output, gzip-compressed when `compressed` is set
+    Output,
+    /// Checkpoint archive for the shadow to save
+    Checkpoint,
+}
+
+/// One piece of an instance's shadow stream. `seq` counts up from 1 per instance and source
+/// node, so receivers can put chunks back in order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamChunk {
+    pub instance_id: Uuid,
+    pub seq: u64,
+    pub kind: StreamKind,
+    pub data: Vec<u8>,
+    pub compressed: bool,
+}
+
+/// Stream chunk as sent between nodes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamChunkMessage {
+    pub sender_id: NodeId,
+    pub chunk: StreamChunk,
+    pub timestamp: DateTime<Utc>,
 }
 
 /// Shadow instance input forwarding message
@@ -286,6 +528,52 @@ pub struct ShadowInputMessage {
     pub instance_id: Uuid,
     pub input_data: String,
     pub timestamp: DateTime<Utc>,
+    pub input_id: Uuid, // Echoed in the ack
+}
+
+/// Whether the source node handed forwarded input to the instance's process
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShadowInputAckMessage {
+    pub sender_id: NodeId,
+    pub target_node_id: NodeId, // Node that forwarded the input
+    pub instance_id: Uuid,
+    pub input_id: Uuid,
+    pub accepted: bool,
+    pub error: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Start or stop live output for a shadow attach session; an enable request holds for
+/// `LIVE_OUTPUT_LEASE` and is renewed while the session lasts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveOutputRequestMessage {
+    pub sender_id: NodeId,
+    pub target_node_id: NodeId, // Source node running the instance
+    pub instance_id: Uuid,
+    pub enabled: bool,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Claim to take over an instance whose source node went offline; the sender restores it only
+/// once every peer it asked has granted the claim
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailoverClaimMessage {
+    pub sender_id: NodeId,
+    pub instance_id: Uuid,
+    pub source_node_id: NodeId, // Node the instance ran on
+    pub claim_id: Uuid,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Whether a peer lets the claimant take over the instance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailoverClaimAckMessage {
+    pub sender_id: NodeId,
+    pub target_node_id: NodeId, // Node that sent the claim
+    pub instance_id: Uuid,
+    pub claim_id: Uuid,
+    pub granted: bool,
+    pub timestamp: DateTime<Utc>,
 }
 
 /// Migration coordination message
@@ -298,16 +586,24 @@ pub enum MigrationMessage {
         source_node_id: NodeId,
         target_node_id: NodeId,
         options: crate::migration_manager::MigrationOptions,
+        /// Source's estimate of the checkpoint size in bytes; None if the instance has no PID to measure
+        estimated_size: Option<u64>,
     },
     /// Accept migration request
     MigrationAccept {
         migration_id: Uuid,
         target_port: u16,
+        /// Target can extract a criu-image-streamer stream on `target_port`
+        image_streamer: bool,
+        /// Data version of the last checkpoint the target's shadow applied, compared against the
+        /// last one the source sent before migrating
+        shadow_checkpoint_version: u64,
     },
     /// Reject migration request
     MigrationReject {
         migration_id: Uuid,
         reason: String,
+        kind: MigrationRejectKind,
     },
     /// Transfer checkpoint data for migration
     CheckpointTransfer {
@@ -325,6 +621,14 @@ pub enum MigrationMessage {
     },
 }
 
+/// Why a target turned a migration down, for sources that react to specific causes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MigrationRejectKind {
+    Other,
+    /// The target's data dir cannot hold the checkpoint
+    InsufficientSpace { available: u64, required: u64 },
+}
+
 /// Real-time data streaming message
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DataStreamMessage {
@@ -363,4 +667,60 @@ pub struct InstanceInfo {
     pub node_id: NodeId, // Node where this instance is located
     pub created_at: DateTime<Utc>,
     pub source_node_id: Option<NodeId>, // For shadow instances
+    pub affinity: crate::types::Affinity, // Nodes allowed to host the instance
+    pub output_timestamps: crate::types::OutputTimestamps,
+    pub labels: std::collections::BTreeMap<String, String>, // Kept across migration so `stop --label` still matches
+    pub has_checkpoint: bool, // Shadow entries: the node holds a checkpoint it can restore from
+    pub env: Vec<(String, String)>, // Kept so a takeover restarts the program with the same environment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output_sync(output: Vec<u8>, compressed: bool) -> ShadowSyncMessage {
+        ShadowSyncMessage {
+            sender_id: Uuid::new_v4(),
+            instance_id: Uuid::new_v4(),
+            data_version: 1,
+            checkpoint_data: None,
+            output_data: Some(output),
+            timestamp: Utc::now(),
+            is_migration: false,
+            output_compressed: compressed,
+        }
+    }
+
+    #[test]
+    fn compressed_output_reconstructs_byte_for_byte() {
+        let burst: Vec<u8> = (0..200).flat_map(|line| format!("tick {} é\n", line).into_bytes()).collect();
+        let (data, compressed) = compress_output(burst.clone());
+        assert!(compressed);
+        assert!(data.len() < burst.len());
+        let mut sync = output_sync(data, true);
+        sync.decompress_output().unwrap();
+        assert_eq!(sync.output_data, Some(burst));
+        assert!(!sync.output_compressed);
+
+        // Small batches and batches that don't shrink go out as they are
+        let small = vec![b'x'; OUTPUT_COMPRESSION_MIN_BYTES - 1];
+        assert_eq!(compress_output(small.clone()), (small, false));
+        let mut state = 0x2545_f491_u32;
+        let noise: Vec<u8> = (0..4096).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        }).collect();
+        assert_eq!(compress_output(noise.clone()), (noise, false));
+    }
+
+    #[test]
+    fn output_expanding_past_the_cap_is_refused() {
+        let (bomb, compressed) = compress_output(vec![0; MAX_DECOMPRESSED_OUTPUT_BYTES + 1]);
+        assert!(compressed);
+        let mut sync = output_sync(bomb, true);
+        let error = sync.decompress_output().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
 }
@@ -1,18 +1,16 @@
 use crate::message_protocol::*;
-use crate::types::{Instance, InstanceStatus};
+use crate::network_manager::OutboundQueue;
+use crate::types::InstanceStatus;
 use crate::criu_manager::CriuManager;
 use crate::process_manager::ProcessManager;
 use crate::instance::InstanceManager;
 use anyhow::{Result, Context};
 use chrono::{DateTime, Utc};
 use std::sync::Arc;
-use tokio::sync::{RwLock, mpsc};
+use tokio::sync::RwLock;
 use tokio::process::Command;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::fs::File;
-use tracing::{debug, error, info, warn};
+use tracing::{error, info, warn};
 use uuid::Uuid;
-use std::path::PathBuf;
 
 /// Executes actual instance migration between nodes
 pub struct MigrationExecutor {
@@ -20,7 +18,7 @@ pub struct MigrationExecutor {
     criu_manager: Arc<CriuManager>,
     process_manager: Arc<ProcessManager>,
     instance_manager: Arc<RwLock<InstanceManager>>,
-    network_sender: Option<mpsc::UnboundedSender<NetworkMessage>>,
+    network_sender: Option<OutboundQueue>,
 }
 
 /// Migration execution state
@@ -62,7 +60,7 @@ impl MigrationExecutor {
         }
     }
 
-    pub fn set_network_sender(&mut self, sender: mpsc::UnboundedSender<NetworkMessage>) {
+    pub fn set_network_sender(&mut self, sender: OutboundQueue) {
         self.network_sender = Some(sender);
     }
 
@@ -106,7 +104,7 @@ impl MigrationExecutor {
         info!("Creating checkpoint for PID {} with name {}", instance_pid, checkpoint_name);
 
         let checkpoint_result = self.criu_manager
-            .create_checkpoint(instance_pid, &checkpoint_name, &instance_id, None)
+            .create_checkpoint(instance_pid, &checkpoint_name, &instance_id, None, &Default::default())
             .await;
 
         match checkpoint_result {
@@ -254,7 +252,7 @@ impl MigrationExecutor {
 
     async fn send_checkpoint_to_target(
         &self,
-        target_node_id: NodeId,
+        _target_node_id: NodeId,
         migration_id: Uuid,
         instance_id: Uuid,
         checkpoint_data: Vec<u8>,
@@ -269,7 +267,7 @@ impl MigrationExecutor {
 
             // Send the migration message
             let network_message = NetworkMessage::Migration(migration_message);
-            network_sender.send(network_message)
+            network_sender.send(network_message).await
                 .context("Failed to send migration message")?;
 
             // Send checkpoint data as data stream
@@ -283,7 +281,7 @@ impl MigrationExecutor {
             };
 
             let data_message = NetworkMessage::DataStream(stream_message);
-            network_sender.send(data_message)
+            network_sender.send(data_message).await
                 .context("Failed to send checkpoint data")?;
         }
 
@@ -292,9 +290,9 @@ impl MigrationExecutor {
 
     async fn send_migration_complete(
         &self,
-        source_node_id: NodeId,
+        _source_node_id: NodeId,
         migration_id: Uuid,
-        instance_id: Uuid,
+        _instance_id: Uuid,
     ) -> Result<()> {
         if let Some(network_sender) = &self.network_sender {
             let migration_message = MigrationMessage::MigrationComplete {
@@ -304,7 +302,7 @@ impl MigrationExecutor {
             };
 
             let network_message = NetworkMessage::Migration(migration_message);
-            network_sender.send(network_message)
+            network_sender.send(network_message).await
                 .context("Failed to send migration complete message")?;
         }
 
@@ -325,9 +323,7 @@ impl MigrationExecutor {
 
         // Update instance status and PID
         if let Some(instance) = instance_manager.get_instance_by_id_mut(&instance_id.to_string()) {
-            instance.pid = Some(new_pid);
-            instance.status = InstanceStatus::Running;
-            instance.source_node_id = None; // No longer a shadow
+            instance.promote_to_running(new_pid)?;
 
             // Save updated metadata
             if let Err(e) = instance.save_metadata() {
@@ -1,22 +1,138 @@
+use crate::audit::AuditLog;
+use crate::checkpoint_archive::PARENT_IMAGES_DIR;
+use crate::checkpoint_engine::{CheckpointEngine, DumpRequest};
+use crate::cluster_state::ClusterStateManager;
+use crate::image_streamer::{ImageStreamer, STREAM_MAGIC};
 use crate::instance::InstanceManager;
-use crate::message_protocol::{MigrationMessage, NetworkMessage, ShadowSyncMessage, NodeId};
+use crate::message_protocol::{MigrationMessage, MigrationRejectKind, NetworkMessage, NodeId, NodeInfo, ShadowSyncMessage};
 use crate::network_manager::NetworkManager;
 use crate::process_manager::ProcessManager;
 use crate::shadow_instance_manager::ShadowInstanceManager;
+use crate::types::{CheckpointStorage, MigrationError, MigrationResult};
 use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{broadcast, Mutex, RwLock};
 use tokio::time::interval;
-use tokio::process::Command;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+/// Checkpoint name prefixes that can serve as the base of an incremental migration dump
+const INCREMENTAL_BASE_PREFIXES: [&str; 2] = ["auto-sync-", "sync-"];
+
+
+/// Longest chain of incremental auto-syncs before the next one is a full dump again
+const MAX_INCREMENTAL_CHAIN: usize = 8;
+
+/// Staging name for the base images; CRIU itself creates a `parent` symlink to `--prev-images-dir`
+const BASE_IMAGES_STAGING_DIR: &str = "base-images";
+
+/// Largest metadata block accepted at the start of a migration image stream
+const MAX_STREAM_METADATA_BYTES: usize = 64 * 1024;
+
+/// What opens a migration image stream
+#[derive(Debug, PartialEq)]
+struct StreamHeader {
+    instance_id: Uuid,
+    migration_id: Uuid,
+    tcp_established: bool, // Dumped with --tcp-established, so it is restored with it too
+    original_ids: Option<(u32, u32)>, // UID and GID the source process ran as
+}
+
+/// Instance and migration IDs from migration metadata. Both end up in paths on the target,
+/// so anything but a UUID is rejected.
+fn parse_migration_ids(metadata: &serde_json::Value) -> Result<(Uuid, Uuid)> {
+    let parse = |key: &str| {
+        let value = metadata[key].as_str().ok_or_else(|| anyhow!("{} not found in migration metadata", key))?;
+        Uuid::parse_str(value).map_err(|_| anyhow!("invalid {} in migration metadata: {:?}", key, value))
+    };
+    Ok((parse("instance_id")?, parse("migration_id")?))
+}
+
+/// Free space a migration target keeps beyond the estimated checkpoint size (at least 10% of it)
+const MIGRATION_DISK_MARGIN: u64 = 256 * 1024 * 1024;
+
+/// Space a target needs to accept a checkpoint of `estimated_size` bytes
+fn required_disk_space(estimated_size: u64) -> u64 {
+    estimated_size + (estimated_size / 10).max(MIGRATION_DISK_MARGIN)
+}
+
+/// Why a target with `available` free bytes must turn down a checkpoint of `estimated_size`
+/// bytes, if it must. Unknown sizes pass, since there is nothing to compare.
+fn insufficient_space(estimated_size: Option<u64>, available: Option<u64>) -> Option<MigrationRejectKind> {
+    let required = required_disk_space(estimated_size?);
+    let available = available?;
+    (available < required).then_some(MigrationRejectKind::InsufficientSpace { available, required })
+}
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+/// Outbound migrations a batch `migrate` keeps in flight at once unless told otherwise
+pub const DEFAULT_MAX_CONCURRENT_MIGRATIONS: usize = 2;
+
+/// Least-loaded online node other than `local_node_id` that can take an instance with `affinity`.
+/// `load` counts instances per node (running there or already assigned by the caller); ties go to the node name.
+pub fn pick_evacuation_target(
+    nodes: &[NodeInfo],
+    local_node_id: NodeId,
+    affinity: &crate::types::Affinity,
+    load: &HashMap<NodeId, usize>,
+) -> Option<NodeId> {
+    nodes
+        .iter()
+        .filter(|node| node.node_id != local_node_id)
+        .filter(|node| node.supports_checkpoint_restore() && node.check_affinity(affinity).is_ok())
+        .min_by(|a, b| {
+            let load_a = load.get(&a.node_id).copied().unwrap_or(0);
+            let load_b = load.get(&b.node_id).copied().unwrap_or(0);
+            load_a.cmp(&load_b).then_with(|| a.name.cmp(&b.name))
+        })
+        .map(|node| node.node_id)
+}
+
+/// How long records of completed or failed migrations are kept for `migration-status`
+const MIGRATION_RECORD_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Least time between repeated info-level "nothing to sync" messages
+const IDLE_SYNC_LOG_INTERVAL: Duration = Duration::from_secs(600);
+
+/// What the sync loop last reported at info level, so steady state stays quiet
+#[derive(Debug, Default)]
+struct SyncLogState {
+    last_summary: Option<(usize, usize)>,          // (synced, total) of the last info summary
+    last_idle_log: Option<std::time::Instant>,     // When "nothing to sync" was last logged at info
+}
+
+impl SyncLogState {
+    /// Log the outcome of one sync pass: an info line when instances were synced or the
+    /// counts changed, otherwise debug, with idle passes at info at most once per interval
+    fn report(&mut self, synced: usize, total: usize) {
+        let changed = self.last_summary != Some((synced, total));
+        self.last_summary = Some((synced, total));
+
+        if synced > 0 {
+            self.last_idle_log = None;
+            info!("Synced {} of {} instances", synced, total);
+            return;
+        }
+
+        let idle_due = self.last_idle_log.is_none_or(|at| at.elapsed() >= IDLE_SYNC_LOG_INTERVAL);
+        if changed || idle_due {
+            self.last_idle_log = Some(std::time::Instant::now());
+            info!("No running instances to sync ({} known)", total);
+        } else {
+            debug!("No running instances to sync ({} known)", total);
+        }
+    }
+}
+
 /// Migration options for controlling migration behavior
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct MigrationOptions {
@@ -36,7 +152,7 @@ impl Default for MigrationOptions {
 }
 
 /// Migration status tracking
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum MigrationStatus {
     Preparing,
     CreatingCheckpoint,
@@ -45,10 +161,105 @@ pub enum MigrationStatus {
     Verifying,
     Completed,
     Failed(String),
+    /// The target node refused the migration
+    Rejected(String),
 }
 
-/// Active migration tracking
+impl MigrationStatus {
+    /// Whether the migration has reached a final state
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, MigrationStatus::Completed | MigrationStatus::Failed(_) | MigrationStatus::Rejected(_))
+    }
+}
+
+/// A status transition of a tracked migration
 #[derive(Debug, Clone)]
+pub struct MigrationStatusUpdate {
+    pub migration_id: Uuid,
+    pub instance_id: Uuid,
+    pub status: MigrationStatus,
+}
+
+/// One migration in the `watch migrations` view, with the time spent in each phase
+#[derive(Debug, Clone)]
+pub struct WatchedMigration {
+    pub migration: ActiveMigration,
+    pub phase_started_at: DateTime<Utc>,
+    pub phases: Vec<(MigrationStatus, chrono::Duration)>, // Phases already left, in order
+}
+
+impl WatchedMigration {
+    /// Time spent in the current phase, frozen once the migration is finished
+    pub fn phase_elapsed(&self, now: DateTime<Utc>) -> chrono::Duration {
+        if self.migration.status.is_terminal() {
+            chrono::Duration::zero()
+        } else {
+            now - self.phase_started_at
+        }
+    }
+
+    fn transition(&mut self, status: MigrationStatus, at: DateTime<Utc>) {
+        if status == self.migration.status {
+            return;
+        }
+        let previous = std::mem::replace(&mut self.migration.status, status);
+        self.phases.push((previous, at - self.phase_started_at));
+        self.phase_started_at = at;
+    }
+}
+
+/// View model of the live `watch migrations` view, fed by status broadcasts and periodic snapshots
+#[derive(Debug, Default)]
+pub struct MigrationWatch {
+    migrations: Vec<WatchedMigration>,
+}
+
+impl MigrationWatch {
+    pub fn new(active: &[ActiveMigration]) -> Self {
+        let mut watch = Self::default();
+        watch.sync(active, Utc::now());
+        watch
+    }
+
+    /// Migrations oldest first
+    pub fn migrations(&self) -> &[WatchedMigration] {
+        &self.migrations
+    }
+
+    /// Record a status transition; false if the migration is not known yet and needs a `sync`
+    pub fn apply(&mut self, update: &MigrationStatusUpdate, at: DateTime<Utc>) -> bool {
+        match self.migrations.iter_mut().find(|m| m.migration.migration_id == update.migration_id) {
+            Some(watched) => {
+                watched.transition(update.status.clone(), at);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Merge a snapshot of the tracked migrations: new ones are added, transfer counters refreshed,
+    /// and status changes missed by the broadcast (e.g. after lagging) recorded at `at`
+    pub fn sync(&mut self, active: &[ActiveMigration], at: DateTime<Utc>) {
+        for migration in active {
+            match self.migrations.iter_mut().find(|m| m.migration.migration_id == migration.migration_id) {
+                Some(watched) => {
+                    watched.transition(migration.status.clone(), at);
+                    watched.migration.bytes_sent = migration.bytes_sent;
+                    watched.migration.bytes_total = migration.bytes_total;
+                }
+                None => self.migrations.push(WatchedMigration {
+                    migration: migration.clone(),
+                    phase_started_at: migration.started_at.min(at),
+                    phases: Vec::new(),
+                }),
+            }
+        }
+        self.migrations.sort_by_key(|m| m.migration.started_at);
+    }
+}
+
+/// Active migration tracking
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ActiveMigration {
     pub migration_id: Uuid,
     pub instance_id: Uuid,
@@ -57,6 +268,119 @@ pub struct ActiveMigration {
     pub status: MigrationStatus,
     pub started_at: DateTime<Utc>,
     pub options: MigrationOptions,
+    #[serde(default)]
+    pub bytes_sent: u64, // Checkpoint bytes handed to the target so far
+    #[serde(default)]
+    pub bytes_total: u64, // Size of the checkpoint transfer, 0 while unknown
+    #[serde(default)]
+    pub finished_at: Option<DateTime<Utc>>, // When the migration completed or failed
+    #[serde(default)]
+    pub source_pid: Option<u32>, // Process stopped on the source once the target has restored it
+}
+
+impl ActiveMigration {
+    /// Estimated progress in percent from the phase and transfer counters; None once failed
+    pub fn progress_percent(&self) -> Option<u8> {
+        let percent = match self.status {
+            MigrationStatus::Preparing => 10,
+            MigrationStatus::CreatingCheckpoint => 30,
+            MigrationStatus::TransferringData if self.bytes_total > 0 => {
+                30 + (50 * self.bytes_sent.min(self.bytes_total) / self.bytes_total) as u8
+            }
+            MigrationStatus::TransferringData => 30,
+            MigrationStatus::RestoringProcess => 90,
+            MigrationStatus::Verifying => 95,
+            MigrationStatus::Completed => 100,
+            MigrationStatus::Failed(_) | MigrationStatus::Rejected(_) => return None,
+        };
+        Some(percent)
+    }
+
+    /// `migration-status --json` entry for this migration
+    pub fn status_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "migration_id": self.migration_id,
+            "instance_id": self.instance_id,
+            "source_node_id": self.source_node_id,
+            "target_node_id": self.target_node_id,
+            "status": self.status,
+            "progress": self.progress_percent(),
+            "bytes_sent": self.bytes_sent,
+            "bytes_total": self.bytes_total,
+            "started_at": self.started_at,
+        })
+    }
+
+    /// Whether a finished migration is older than `retention` and its record can go
+    pub fn expired(&self, now: DateTime<Utc>, retention: Duration) -> bool {
+        if !self.status.is_terminal() {
+            return false;
+        }
+        let finished_at = self.finished_at.unwrap_or(self.started_at);
+        chrono::Duration::from_std(retention).is_ok_and(|retention| now - finished_at > retention)
+    }
+
+    /// Path of the persisted record: <data dir>/instance_<id>/migrations/<migration_id>.json
+    pub fn record_path(&self) -> PathBuf {
+        let short_id = self.instance_id.to_string()[..8].to_string();
+        crate::instance::data_dir()
+            .join(format!("instance_{}", short_id))
+            .join("migrations")
+            .join(format!("{}.json", self.migration_id))
+    }
+
+    /// Write the migration record to disk
+    pub fn persist(&self) -> Result<()> {
+        let path = self.record_path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(&path, json)?;
+        Ok(())
+    }
+
+    /// Delete the persisted record
+    pub fn remove_record(&self) {
+        if let Err(e) = std::fs::remove_file(self.record_path()) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove migration record {}: {}", self.migration_id, e);
+            }
+        }
+    }
+
+    /// Load all persisted migration records found under the instances directory
+    pub fn load_all() -> Vec<ActiveMigration> {
+        let mut records = Vec::new();
+        let entries = match std::fs::read_dir(crate::instance::data_dir()) {
+            Ok(entries) => entries,
+            Err(_) => return records,
+        };
+
+        for entry in entries.flatten() {
+            let migrations_dir = entry.path().join("migrations");
+            let files = match std::fs::read_dir(&migrations_dir) {
+                Ok(files) => files,
+                Err(_) => continue,
+            };
+
+            for file in files.flatten() {
+                let path = file.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                    continue;
+                }
+                match std::fs::read_to_string(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|json| serde_json::from_str::<ActiveMigration>(&json).map_err(anyhow::Error::from))
+                {
+                    Ok(record) => records.push(record),
+                    Err(e) => warn!("Failed to load migration record {}: {}", path.display(), e),
+                }
+            }
+        }
+
+        records
+    }
 }
 
 /// Image synchronization manager for periodic checkpoint creation
@@ -68,39 +392,28 @@ pub struct ImageSyncManager {
     shadow_manager: Option<Arc<RwLock<ShadowInstanceManager>>>,
     sync_interval: Duration,
     is_running: Arc<Mutex<bool>>,
-    criu_path: PathBuf,
+    engine: Arc<dyn CheckpointEngine>,
+    storage: CheckpointStorage,
+    image_streamer: ImageStreamer,
+    idle_threshold: u64,
 }
 
-impl ImageSyncManager {
-    pub fn new(
-        instance_manager: Arc<Mutex<InstanceManager>>,
-        process_manager: Arc<ProcessManager>,
-        sync_interval_secs: u64,
-    ) -> Self {
-        Self::new_with_criu_path(instance_manager, process_manager, sync_interval_secs, "./criu/bin/criu")
-    }
+/// What the auto-sync loop carries from one pass to the next
+#[derive(Debug, Default)]
+struct SyncLoopState {
+    log: SyncLogState,
+    /// Activity of each instance's process tree at its last auto-sync
+    last_activity: HashMap<Uuid, crate::criu_compat::TreeActivity>,
+}
 
-    pub fn new_with_criu_path<P: AsRef<std::path::Path>>(
+impl ImageSyncManager {
+    /// Create a sync manager driving the given checkpoint engine
+    pub fn new_with_engine(
         instance_manager: Arc<Mutex<InstanceManager>>,
         process_manager: Arc<ProcessManager>,
         sync_interval_secs: u64,
-        criu_path: P,
+        engine: Arc<dyn CheckpointEngine>,
     ) -> Self {
-        let criu_path = PathBuf::from(criu_path.as_ref());
-
-        // Convert relative path to absolute path to avoid working directory issues
-        let criu_path = if criu_path.is_relative() {
-            match std::env::current_dir() {
-                Ok(current_dir) => current_dir.join(criu_path),
-                Err(e) => {
-                    warn!("Failed to get current directory: {}, using relative path", e);
-                    criu_path
-                }
-            }
-        } else {
-            criu_path
-        };
-
         Self {
             instance_manager,
             process_manager,
@@ -108,10 +421,29 @@ impl ImageSyncManager {
             shadow_manager: None,
             sync_interval: Duration::from_secs(sync_interval_secs),
             is_running: Arc::new(Mutex::new(false)),
-            criu_path,
+            engine,
+            storage: CheckpointStorage::default(),
+            image_streamer: ImageStreamer::default(),
+            idle_threshold: 0,
         }
     }
 
+    /// Deduplicate and/or encrypt sync checkpoints
+    pub fn set_storage(&mut self, storage: CheckpointStorage) {
+        self.storage = storage;
+    }
+
+    /// Skip the auto-sync of instances whose activity since their last sync is at most
+    /// `threshold` (page faults plus CPU clock ticks)
+    pub fn set_idle_threshold(&mut self, threshold: u64) {
+        self.idle_threshold = threshold;
+    }
+
+    /// Use this criu-image-streamer to receive streamed checkpoints
+    pub fn set_image_streamer(&mut self, image_streamer: ImageStreamer) {
+        self.image_streamer = image_streamer;
+    }
+
     /// Set network and shadow managers for distributed sync
     pub fn set_managers(
         &mut self,
@@ -137,10 +469,13 @@ impl ImageSyncManager {
         let shadow_manager = self.shadow_manager.clone();
         let sync_interval = self.sync_interval;
         let is_running = self.is_running.clone();
-        let criu_path = self.criu_path.clone();
+        let engine = self.engine.clone();
+        let storage = self.storage;
+        let idle_threshold = self.idle_threshold;
 
         tokio::spawn(async move {
             let mut interval = interval(sync_interval);
+            let mut state = SyncLoopState::default();
 
             while *is_running.lock().await {
                 interval.tick().await;
@@ -150,7 +485,10 @@ impl ImageSyncManager {
                     &process_manager,
                     network_manager.as_ref(),
                     shadow_manager.as_ref(),
-                    &criu_path,
+                    &engine,
+                    storage,
+                    &mut state,
+                    idle_threshold,
                 ).await {
                     error!("Failed to sync instances: {}", e);
                 }
@@ -169,23 +507,38 @@ impl ImageSyncManager {
     }
 
     /// Sync all running instances
+    #[allow(clippy::too_many_arguments)]
     async fn sync_all_instances(
         instance_manager: &Arc<Mutex<InstanceManager>>,
         process_manager: &Arc<ProcessManager>,
         network_manager: Option<&Arc<NetworkManager>>,
         shadow_manager: Option<&Arc<RwLock<ShadowInstanceManager>>>,
-        criu_path: &std::path::Path,
+        engine: &Arc<dyn CheckpointEngine>,
+        storage: CheckpointStorage,
+        state: &mut SyncLoopState,
+        idle_threshold: u64,
     ) -> Result<()> {
         let instances = {
             let manager = instance_manager.lock().await;
             manager.get_all_instances()
         };
 
-        info!("Checking {} instances for sync", instances.len());
+        // Per-instance messages are debug-level; one summary per pass goes to info
+        debug!("Checking {} instances for sync", instances.len());
+        let total = instances.len();
+
+        // Forget the activity of instances that no longer exist
+        let known: HashSet<Uuid> = instances.iter().map(|instance| instance.id).collect();
+        state.last_activity.retain(|id, _| known.contains(id));
 
         let mut sync_count = 0;
         for instance in instances {
-            info!("Instance {}: status={:?}, pid={:?}", instance.short_id(), instance.status, instance.pid);
+            debug!("Instance {}: status={:?}, pid={:?}", instance.short_id(), instance.status, instance.pid);
+
+            if !instance.sync_enabled {
+                debug!("Skipping instance {} (auto-sync not enabled)", instance.short_id());
+                continue;
+            }
 
             // Check if instance is actually running by verifying PID
             let is_actually_running = if let Some(pid) = instance.pid {
@@ -195,24 +548,37 @@ impl ImageSyncManager {
             };
 
             if is_actually_running {
-                info!("Syncing running instance {}", instance.short_id());
-                if let Err(e) = Self::sync_instance(&instance, process_manager, network_manager, shadow_manager, criu_path).await {
-                    warn!("Failed to sync instance {}: {}", instance.id, e);
-                } else {
-                    sync_count += 1;
-                    info!("Successfully synced instance {}", instance.short_id());
+                // An idle process has nothing new to dump since its last sync
+                let pid = instance.pid.unwrap_or_default();
+                let activity = crate::criu_compat::tree_activity(pid);
+                if let (Some(now), Some(last)) = (&activity, state.last_activity.get(&instance.id)) {
+                    if now.idle_since(last, idle_threshold) {
+                        debug!("Skipping idle instance {} (activity {} since last sync)",
+                               instance.short_id(), now.activity.saturating_sub(last.activity));
+                        continue;
+                    }
+                }
+
+                debug!("Syncing running instance {}", instance.short_id());
+                match Self::sync_instance(&instance, process_manager, network_manager, shadow_manager, engine, storage).await {
+                    Err(e) => warn!("Failed to sync instance {}: {}", instance.id, e),
+                    Ok(checkpoint_name) => {
+                        if let Some(checkpoint_name) = checkpoint_name {
+                            Self::advance_sync_base(instance_manager, &instance, checkpoint_name).await;
+                        }
+                        if let Some(activity) = activity {
+                            state.last_activity.insert(instance.id, activity);
+                        }
+                        sync_count += 1;
+                        debug!("Successfully synced instance {}", instance.short_id());
+                    }
                 }
             } else {
-                info!("Skipping instance {} with status {:?} (not actually running)", instance.short_id(), instance.status);
+                debug!("Skipping instance {} with status {:?} (not actually running)", instance.short_id(), instance.status);
             }
         }
 
-        if sync_count > 0 {
-            info!("Synced {} running instances", sync_count);
-        } else {
-            info!("No running instances found to sync");
-        }
-
+        state.log.report(sync_count, total);
         Ok(())
     }
 
@@ -222,217 +588,128 @@ impl ImageSyncManager {
         std::path::Path::new(&proc_path).exists()
     }
 
-    /// Start migration receiver server on specified port
-    async fn start_migration_receiver(&self, port: u16) -> Result<()> {
-        use tokio::net::TcpListener;
-        use tokio::io::AsyncReadExt;
-
-        let addr = format!("0.0.0.0:{}", port);
-        let listener = TcpListener::bind(&addr).await?;
-        info!("Migration receiver listening on port {}", port);
-
-        // Accept one connection for this migration
-        if let Ok((mut socket, peer_addr)) = listener.accept().await {
+    /// Accept connections on `port` until the image stream of `migration_id` arrives or `timeout`
+    /// passes, restore it, and tell the source node how the restore went. Connections carrying
+    /// anything else are dropped.
+    async fn start_migration_receiver(
+        &self,
+        port: u16,
+        migration_id: Uuid,
+        source_node_id: NodeId,
+        timeout: Duration,
+    ) -> Result<()> {
+        let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+        info!("Migration receiver for {} listening on port {}", migration_id, port);
+
+        // A source without the streamer sends the checkpoint over the peer connection instead
+        let deadline = tokio::time::Instant::now() + timeout;
+        let (socket, header) = loop {
+            let (mut socket, peer_addr) = match tokio::time::timeout_at(deadline, listener.accept()).await {
+                Ok(accepted) => accepted?,
+                Err(_) => {
+                    debug!("No image stream for migration {} within {}s, receiver closed", migration_id, timeout.as_secs());
+                    return Ok(());
+                }
+            };
             info!("Migration receiver accepted connection from {}", peer_addr);
 
-            // Read checkpoint data
-            let mut buffer = Vec::new();
-            if let Err(e) = socket.read_to_end(&mut buffer).await {
-                error!("Failed to read checkpoint data: {}", e);
-                return Err(e.into());
-            }
-
-            info!("Received {} bytes of checkpoint data", buffer.len());
-
-            // Process the received checkpoint data
-            if let Err(e) = self.process_received_migration_data(buffer).await {
-                error!("Failed to process migration data: {}", e);
-                return Err(e);
+            match Self::read_stream_header(&mut socket).await {
+                Ok(header) if header.migration_id == migration_id => break (socket, header),
+                Ok(header) => {
+                    warn!("Dropping image stream from {} for migration {}, waiting for {}", peer_addr, header.migration_id, migration_id);
+                }
+                Err(e) => warn!("Dropping connection from {}: {}", peer_addr, e),
             }
+        };
 
-            info!("Migration receiver completed successfully");
+        let result = self.receive_image_stream(socket, &header).await;
+        if let Err(ref e) = result {
+            error!("Migration {} failed on the target: {}", migration_id, e);
         }
-
-        Ok(())
+        // The source keeps its instance running until it hears the restore succeeded
+        let complete_message = MigrationMessage::MigrationComplete {
+            migration_id,
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        if let Some(network_manager) = &self.network_manager {
+            network_manager.send_to_peer(&source_node_id, NetworkMessage::Migration(complete_message)).await?;
+        }
+        result
     }
 
-    /// Process received migration data and restore the instance
-    async fn process_received_migration_data(&self, data: Vec<u8>) -> Result<()> {
-        use std::io::Cursor;
-        use tar::Archive;
-
-        info!("Processing migration data: {} bytes", data.len());
-
-        // Extract the tar.gz data
-        let cursor = Cursor::new(data.clone());
-        let tar = flate2::read::GzDecoder::new(cursor);
-        let mut archive = Archive::new(tar);
-
-        // Create a temporary directory to extract to first
-        let temp_dir = std::env::temp_dir().join(format!("migration-{}", uuid::Uuid::new_v4()));
-        std::fs::create_dir_all(&temp_dir)?;
-        info!("Created temp directory: {:?}", temp_dir);
-
-        // Try to list entries first for debugging
-        info!("Attempting to iterate over archive entries...");
-        let cursor2 = Cursor::new(data);
-        let tar2 = flate2::read::GzDecoder::new(cursor2);
-        let mut archive2 = Archive::new(tar2);
-
-        let mut entry_count = 0;
-        for entry_result in archive2.entries()? {
-            match entry_result {
-                Ok(entry) => {
-                    let path = entry.path()?;
-                    info!("Found archive entry: {:?}", path);
-                    entry_count += 1;
-                }
-                Err(e) => {
-                    error!("Error reading archive entry: {}", e);
-                    return Err(e.into());
-                }
-            }
+    /// Read the magic tag and length-prefixed JSON metadata that open an image stream
+    async fn read_stream_header(socket: &mut TcpStream) -> Result<StreamHeader> {
+        let mut head = [0u8; 4];
+        socket.read_exact(&mut head).await?;
+        if &head != STREAM_MAGIC {
+            return Err(anyhow!("not a criu-image-streamer stream"));
         }
-        info!("Archive contains {} entries", entry_count);
-
-        // Extract the archive
-        info!("Extracting archive to temp directory...");
-        archive.unpack(&temp_dir)?;
 
-        // Find the migration metadata file to get instance information
-        let metadata_file = temp_dir.join("migration_metadata.json");
-        if !metadata_file.exists() {
-            return Err(anyhow::anyhow!("Migration metadata not found"));
-        }
-
-        let metadata_content = std::fs::read_to_string(&metadata_file)?;
-        let metadata: serde_json::Value = serde_json::from_str(&metadata_content)?;
-
-        let instance_id = metadata["instance_id"].as_str()
-            .ok_or_else(|| anyhow::anyhow!("Instance ID not found in metadata"))?;
-        let migration_id = metadata["migration_id"].as_str()
-            .ok_or_else(|| anyhow::anyhow!("Migration ID not found in metadata"))?;
-
-        // Create the proper checkpoint directory
-        let checkpoint_dir = format!("instances/instance_{}/checkpoints/migration-{}", instance_id, migration_id);
-        std::fs::create_dir_all(&checkpoint_dir)?;
-
-        // Move all checkpoint files to the proper location
-        for entry in std::fs::read_dir(&temp_dir)? {
-            let entry = entry?;
-            let file_name = entry.file_name();
-            if file_name != "migration_metadata.json" {
-                let src = entry.path();
-                let dst = std::path::Path::new(&checkpoint_dir).join(&file_name);
-                std::fs::rename(src, dst)?;
-                info!("Moved checkpoint file: {:?}", file_name);
-            }
+        let metadata_len = socket.read_u32().await? as usize;
+        if metadata_len > MAX_STREAM_METADATA_BYTES {
+            return Err(anyhow!("stream metadata of {} bytes is too large", metadata_len));
         }
-
-        // Clean up temp directory
-        std::fs::remove_dir_all(&temp_dir)?;
-
-        info!("Migration checkpoint extracted to: {}", checkpoint_dir);
-
-        // Now restore the instance
-        self.restore_migrated_instance(instance_id, migration_id).await?;
-
-        Ok(())
+        let mut metadata_buf = vec![0u8; metadata_len];
+        socket.read_exact(&mut metadata_buf).await?;
+        let metadata: serde_json::Value = serde_json::from_slice(&metadata_buf)?;
+        let (instance_id, migration_id) = parse_migration_ids(&metadata)?;
+        Ok(StreamHeader {
+            instance_id,
+            migration_id,
+            tcp_established: metadata["tcp_established"].as_bool().unwrap_or(false),
+            original_ids: metadata["uid"].as_u64().zip(metadata["gid"].as_u64())
+                .and_then(|(uid, gid)| Some((u32::try_from(uid).ok()?, u32::try_from(gid).ok()?))),
+        })
     }
 
-    /// Restore a migrated instance from checkpoint
-    async fn restore_migrated_instance(&self, instance_id: &str, migration_id: &str) -> Result<()> {
-        let checkpoint_name = format!("migration-{}", migration_id);
-        let checkpoint_dir = format!("instances/instance_{}/checkpoints/{}", instance_id, checkpoint_name);
-
-        info!("Restoring migrated instance {} from checkpoint {}", instance_id, checkpoint_name);
-
-        // Use CRIU to restore the process
-        let restore_cmd = std::process::Command::new("sudo")
-            .arg(&self.criu_path)
-            .arg("restore")
-            .arg("-D")
-            .arg(&checkpoint_dir)
-            .arg("--shell-job")
-            .output();
-
-        match restore_cmd {
-            Ok(output) => {
-                if output.status.success() {
-                    info!("Successfully restored migrated instance {}", instance_id);
-
-                    // Find the restored PID
-                    if let Ok(new_pid) = self.find_restored_pid(&checkpoint_dir).await {
-                        info!("Restored instance {} with PID: {}", instance_id, new_pid);
-
-                        // Update instance status to Running
-                        self.update_instance_after_migration(instance_id, new_pid).await?;
-                    } else {
-                        warn!("Could not find PID for restored instance {}", instance_id);
-                    }
-                } else {
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    error!("CRIU restore failed for instance {}: {}", instance_id, stderr);
-                    return Err(anyhow::anyhow!("CRIU restore failed: {}", stderr));
-                }
-            }
-            Err(e) => {
-                error!("Failed to execute CRIU restore for instance {}: {}", instance_id, e);
-                return Err(e.into());
-            }
+    /// Receive a criu-image-streamer stream into the instance's migration checkpoint and restore it
+    async fn receive_image_stream(&self, mut socket: TcpStream, header: &StreamHeader) -> Result<()> {
+        let StreamHeader { instance_id, migration_id, tcp_established, original_ids } = *header;
+        let instance_dir = PathBuf::from(crate::instance::INSTANCES_DIR).join(format!("instance_{}", &instance_id.to_string()[..8]));
+        let checkpoint_dir = instance_dir.join("checkpoints").join(format!("migration-{}", migration_id));
+        tokio::fs::create_dir_all(&checkpoint_dir).await?;
+
+        info!("Receiving image stream for instance {} into {:?}", instance_id, checkpoint_dir);
+
+        let mut extract = self.image_streamer.spawn_extract(&checkpoint_dir)?;
+        let mut stdin = extract.stdin.take()
+            .ok_or_else(|| anyhow!("Failed to open criu-image-streamer stdin"))?;
+        let received = tokio::io::copy(&mut socket, &mut stdin).await?;
+        drop(stdin);
+
+        let output = extract.wait_with_output().await?;
+        if !output.status.success() {
+            return Err(anyhow!("criu-image-streamer extract failed: {}",
+                               String::from_utf8_lossy(&output.stderr)));
         }
 
-        Ok(())
-    }
-
-    /// Find the PID of a restored process
-    async fn find_restored_pid(&self, checkpoint_dir: &str) -> Result<u32> {
-        // Read the pstree.img file to get the PID
-        let pstree_file = format!("{}/pstree.img", checkpoint_dir);
-
-        // For now, use a simple approach - look for running processes
-        // In a real implementation, you'd parse the CRIU image files
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-
-        // Look for simple_counter processes
-        let output = std::process::Command::new("pgrep")
-            .arg("simple_counter")
-            .output()?;
-
-        if output.status.success() {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            if let Some(pid_str) = stdout.lines().last() {
-                if let Ok(pid) = pid_str.parse::<u32>() {
-                    return Ok(pid);
-                }
-            }
+        info!("Extracted {} streamed bytes for migration {}", received, migration_id);
+        if tcp_established {
+            tokio::fs::write(checkpoint_dir.join(crate::criu_compat::TCP_ESTABLISHED_MARKER), "").await?;
+        }
+        if let Some((uid, gid)) = original_ids {
+            crate::criu_compat::write_original_ids(&checkpoint_dir, uid, gid)?;
         }
 
-        Err(anyhow::anyhow!("Could not find restored PID"))
-    }
-
-    /// Update instance status after successful migration
-    async fn update_instance_after_migration(&self, instance_id: &str, new_pid: u32) -> Result<()> {
-        // This would update the instance manager with the new PID and status
-        // For now, just log the update
-        info!("Updated instance {} with new PID {} after migration", instance_id, new_pid);
-
-        // TODO: Actually update the instance manager
-        // self.instance_manager.update_instance_after_migration(instance_id, new_pid).await?;
-
-        Ok(())
+        // Restoring through the shadow promotes it to the running instance
+        let shadow_manager = self.shadow_manager.as_ref()
+            .ok_or_else(|| anyhow!("Shadow manager not available"))?;
+        shadow_manager.read().await.restore_migration_checkpoint(instance_id, &checkpoint_dir, &instance_dir).await
     }
 
-    /// Create a checkpoint for a specific instance and sync to other nodes
+    /// Create a checkpoint for a specific instance and sync to other nodes.
+    /// Returns the checkpoint name if the dump succeeded.
     async fn sync_instance(
         instance: &crate::types::Instance,
         _process_manager: &Arc<ProcessManager>,
         network_manager: Option<&Arc<NetworkManager>>,
         shadow_manager: Option<&Arc<RwLock<ShadowInstanceManager>>>,
-        criu_path: &std::path::Path,
-    ) -> Result<()> {
+        engine: &Arc<dyn CheckpointEngine>,
+        storage: CheckpointStorage,
+    ) -> Result<Option<String>> {
         let checkpoint_name = format!("auto-sync-{}", Utc::now().timestamp());
-        info!("Starting sync checkpoint for instance {}: {}", instance.short_id(), checkpoint_name);
+        debug!("Starting sync checkpoint for instance {}: {}", instance.short_id(), checkpoint_name);
 
         // Get the PID for the instance
         if let Some(pid) = instance.pid {
@@ -440,26 +717,60 @@ impl ImageSyncManager {
             let instance_dir = PathBuf::from("instances").join(format!("instance_{}", instance.short_id()));
             let checkpoint_dir = instance_dir.join("checkpoints").join(&checkpoint_name);
 
-            info!("Creating checkpoint directory: {:?}", checkpoint_dir);
+            debug!("Creating checkpoint directory: {:?}", checkpoint_dir);
             if let Err(e) = tokio::fs::create_dir_all(&checkpoint_dir).await {
                 warn!("Failed to create checkpoint directory: {}", e);
-                return Ok(());
+                return Ok(None);
             }
 
-            // Use CRIU to create checkpoint
-            let mut cmd = Command::new("sudo");
-            cmd.arg(criu_path)
-               .arg("dump")
-               .arg("--tree").arg(pid.to_string())
-               .arg("-D").arg(&checkpoint_dir)
-               .arg("--shell-job")
-               .arg("--leave-running");
-
-            info!("Executing CRIU command for PID {}: {:?}", pid, cmd);
-            match cmd.output().await {
+            // Use CRIU to create checkpoint, tracking dirty memory so later dumps can be incremental
+            let mut extra_args = vec!["--track-mem".to_string()];
+            // A sealed base only has image files while it is unsealed, which has to last through the dump
+            let mut unsealed_base = None;
+            if let Some(ref base) = instance.sync_base {
+                let base_dir = instance_dir.join("checkpoints").join(base);
+                match crate::checkpoint_crypto::unseal_checkpoint(&base_dir, storage.key) {
+                    Ok(unsealed) => unsealed_base = unsealed,
+                    Err(e) => warn!("Failed to decrypt sync base {} of instance {}: {}", base, instance.short_id(), e),
+                }
+                let chain = Self::parent_chain_len(&base_dir);
+                if !base_dir.join("inventory.img").exists() {
+                    warn!("Sync base {} of instance {} is missing, taking a full dump", base, instance.short_id());
+                } else if chain >= MAX_INCREMENTAL_CHAIN {
+                    info!("Incremental chain of instance {} reached {} dumps, taking a full dump", instance.short_id(), chain);
+                } else {
+                    info!("Auto-sync of instance {} builds on {}", instance.short_id(), base);
+                    extra_args.push("--prev-images-dir".to_string());
+                    extra_args.push(format!("../{}", base));
+                }
+            }
+
+            crate::criu_compat::save_original_ids(pid, &checkpoint_dir);
+            let request = DumpRequest {
+                pid,
+                images_dir: checkpoint_dir.clone(),
+                leave_running: true,
+                shell_job: true,
+                extra_args,
+                background: true,
+                tcp_established: crate::criu_compat::holds_tcp_sockets(pid),
+                ..Default::default()
+            };
+
+            debug!("Executing {} dump for PID {}: {:?}", engine.name(), pid, request);
+            let engine = engine.clone();
+            match tokio::task::spawn_blocking(move || engine.dump(&request)).await? {
                 Ok(output) => {
-                    if output.status.success() {
-                        info!("Created sync checkpoint for instance {}: {}", instance.short_id(), checkpoint_name);
+                    if output.success {
+                        debug!("Created sync checkpoint for instance {}: {}", instance.short_id(), checkpoint_name);
+                        // Plaintext base images only had to last through the dump
+                        drop(unsealed_base);
+                        if let Err(e) = crate::checkpoint_crypto::seal_checkpoint(&checkpoint_dir, instance.id, storage.key) {
+                            warn!("Failed to encrypt sync checkpoint of instance {}, discarding it: {}", instance.short_id(), e);
+                            let _ = tokio::fs::remove_dir_all(&checkpoint_dir).await;
+                            return Ok(None);
+                        }
+                        crate::checkpoint_dedup::dedup_if_enabled(&checkpoint_dir, storage.dedup);
 
                         // If we have network connectivity, stream checkpoint to other nodes
                         if let (Some(network_mgr), Some(shadow_mgr)) = (network_manager, shadow_manager) {
@@ -469,9 +780,10 @@ impl ImageSyncManager {
                                 warn!("Failed to stream checkpoint to shadows: {}", e);
                             }
                         }
+                        return Ok(Some(checkpoint_name));
                     } else {
                         warn!("CRIU checkpoint failed for instance {}: {}",
-                              instance.short_id(), String::from_utf8_lossy(&output.stderr));
+                              instance.short_id(), output.stderr);
                     }
                 }
                 Err(e) => {
@@ -482,7 +794,29 @@ impl ImageSyncManager {
             warn!("Instance {} has no PID, skipping checkpoint", instance.short_id());
         }
 
-        Ok(())
+        Ok(None)
+    }
+
+    /// Number of `parent` links below a checkpoint directory
+    fn parent_chain_len(checkpoint_dir: &std::path::Path) -> usize {
+        let mut len = 0;
+        let mut dir = checkpoint_dir.join(PARENT_IMAGES_DIR);
+        while dir.is_dir() {
+            len += 1;
+            dir = dir.join(PARENT_IMAGES_DIR);
+        }
+        len
+    }
+
+    /// Advance an instance's sync base to its latest auto-sync, if it has one
+    async fn advance_sync_base(instance_manager: &Arc<Mutex<InstanceManager>>, instance: &crate::types::Instance, checkpoint_name: String) {
+        if instance.sync_base.is_none() {
+            return;
+        }
+        let mut manager = instance_manager.lock().await;
+        if let Err(e) = manager.set_sync_base(&instance.id.to_string(), Some(checkpoint_name)) {
+            warn!("Failed to update sync base of instance {}: {}", instance.short_id(), e);
+        }
     }
 
     /// Stream checkpoint data to shadow instances on other nodes
@@ -490,83 +824,58 @@ impl ImageSyncManager {
         instance: &crate::types::Instance,
         checkpoint_name: &str,
         checkpoint_dir: &PathBuf,
-        network_manager: &Arc<NetworkManager>,
+        _network_manager: &Arc<NetworkManager>,
         shadow_manager: &Arc<RwLock<ShadowInstanceManager>>,
     ) -> Result<()> {
-        info!("Streaming checkpoint {} for instance {} to shadow nodes", checkpoint_name, instance.short_id());
+        debug!("Streaming checkpoint {} for instance {} to shadow nodes", checkpoint_name, instance.short_id());
 
-        // Read checkpoint data from files
-        let checkpoint_data = Self::read_checkpoint_data(checkpoint_dir).await?;
+        // Shadows hold the earlier syncs, so an incremental dump only carries its own images
+        let checkpoint_data = {
+            let checkpoint_dir = checkpoint_dir.clone();
+            tokio::task::spawn_blocking(move || crate::checkpoint_archive::compress_delta(&checkpoint_dir)).await??
+        };
 
         if checkpoint_data.is_empty() {
             warn!("No checkpoint data found in {:?}", checkpoint_dir);
             return Ok(());
         }
 
-        info!("Read {} bytes of checkpoint data", checkpoint_data.len());
+        debug!("Read {} bytes of checkpoint data", checkpoint_data.len());
 
         // Stream to shadow instances using the shadow manager
         let shadow_mgr = shadow_manager.read().await;
         shadow_mgr.stream_checkpoint_to_shadows(instance.id, checkpoint_data).await?;
 
-        info!("Successfully streamed checkpoint for instance {} to shadows", instance.short_id());
+        debug!("Successfully streamed checkpoint for instance {} to shadows", instance.short_id());
         Ok(())
     }
 
     /// Read checkpoint data from directory and compress it
-    async fn read_checkpoint_data(checkpoint_dir: &PathBuf) -> Result<Vec<u8>> {
-        use std::io::Write;
-        use flate2::Compression;
-        use flate2::write::GzEncoder;
-
-        let mut compressed_data = Vec::new();
-        {
-            let mut encoder = GzEncoder::new(&mut compressed_data, Compression::default());
-
-            // Read all files in the checkpoint directory
-            let mut entries = tokio::fs::read_dir(checkpoint_dir).await?;
-            while let Some(entry) = entries.next_entry().await? {
-                let path = entry.path();
-                if path.is_file() {
-                    let file_name = path.file_name()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or("unknown");
-
-                    let file_data = tokio::fs::read(&path).await?;
-
-                    // Write file header: name length + name + data length + data
-                    let name_bytes = file_name.as_bytes();
-                    encoder.write_all(&(name_bytes.len() as u32).to_le_bytes())?;
-                    encoder.write_all(name_bytes)?;
-                    encoder.write_all(&(file_data.len() as u32).to_le_bytes())?;
-                    encoder.write_all(&file_data)?;
-                }
-            }
-
-            encoder.finish()?;
-        }
-
-        Ok(compressed_data)
+    async fn read_checkpoint_data(checkpoint_dir: &Path) -> Result<Vec<u8>> {
+        let checkpoint_dir = checkpoint_dir.to_path_buf();
+        Ok(tokio::task::spawn_blocking(move || crate::checkpoint_archive::compress_dir(&checkpoint_dir)).await??)
     }
 
     /// Force sync a specific instance (used before migration)
-    pub async fn force_sync_instance(&self, instance_id: &str) -> Result<String> {
+    pub async fn force_sync_instance(&self, instance_id: &str) -> MigrationResult<String> {
         let instance = {
             let manager = self.instance_manager.lock().await;
             manager.get_instance_by_id(instance_id)
-                .ok_or_else(|| anyhow!("Instance {} not found", instance_id))?
+                .ok_or_else(|| MigrationError::InstanceNotFound(instance_id.to_string()))?
                 .clone()
         };
 
-        let checkpoint_name = format!("migration-sync-{}", Utc::now().timestamp());
-
-        Self::sync_instance(
+        let checkpoint_name = Self::sync_instance(
             &instance,
             &self.process_manager,
             self.network_manager.as_ref(),
             self.shadow_manager.as_ref(),
-            &self.criu_path,
-        ).await?;
+            &self.engine,
+            self.storage,
+        ).await
+            .map_err(|e| MigrationError::CheckpointFailed(e.to_string()))?
+            .ok_or_else(|| MigrationError::CheckpointFailed(format!("Sync dump of instance {} failed", instance_id)))?;
+        Self::advance_sync_base(&self.instance_manager, &instance, checkpoint_name.clone()).await;
 
         info!("Force synced instance {} for migration: {}", instance_id, checkpoint_name);
         Ok(checkpoint_name)
@@ -583,50 +892,31 @@ pub struct MigrationManager {
     shadow_manager: Option<Arc<RwLock<ShadowInstanceManager>>>,
     image_sync_manager: ImageSyncManager,
     active_migrations: Arc<RwLock<HashMap<Uuid, ActiveMigration>>>,
-    criu_image_streamer_path: PathBuf,
-    criu_path: PathBuf,
+    status_sender: broadcast::Sender<MigrationStatusUpdate>,
+    image_streamer: ImageStreamer,
+    engine: Arc<dyn CheckpointEngine>,
+    storage: CheckpointStorage,
+    audit: AuditLog,
+    incremental_max_age: Option<Duration>,
+    cluster_state: Option<Arc<ClusterStateManager>>,
 }
 
 impl MigrationManager {
-    pub fn new(
+    /// Create a migration manager driving the given checkpoint engine
+    pub fn new_with_engine(
         local_node_id: NodeId,
         network_manager: Arc<NetworkManager>,
         instance_manager: Arc<Mutex<InstanceManager>>,
         process_manager: Arc<ProcessManager>,
+        engine: Arc<dyn CheckpointEngine>,
     ) -> Self {
-        // Use a default path that will be converted to absolute
-        Self::new_with_criu_path(local_node_id, network_manager, instance_manager, process_manager, "/usr/local/bin/criu")
-    }
-
-    pub fn new_with_criu_path<P: AsRef<std::path::Path>>(
-        local_node_id: NodeId,
-        network_manager: Arc<NetworkManager>,
-        instance_manager: Arc<Mutex<InstanceManager>>,
-        process_manager: Arc<ProcessManager>,
-        criu_path: P,
-    ) -> Self {
-        let image_sync_manager = ImageSyncManager::new_with_criu_path(
+        let image_sync_manager = ImageSyncManager::new_with_engine(
             instance_manager.clone(),
             process_manager.clone(),
             30, // 30 seconds sync interval
-            &criu_path,
+            engine.clone(),
         );
 
-        let criu_path = PathBuf::from(criu_path.as_ref());
-
-        // Convert relative path to absolute path to avoid working directory issues
-        let criu_path = if criu_path.is_relative() {
-            match std::env::current_dir() {
-                Ok(current_dir) => current_dir.join(criu_path),
-                Err(e) => {
-                    warn!("Failed to get current directory: {}, using relative path", e);
-                    criu_path
-                }
-            }
-        } else {
-            criu_path
-        };
-
         Self {
             local_node_id,
             network_manager,
@@ -635,9 +925,63 @@ impl MigrationManager {
             shadow_manager: None,
             image_sync_manager,
             active_migrations: Arc::new(RwLock::new(HashMap::new())),
-            criu_image_streamer_path: PathBuf::from("./criu-image-streamer/target/release/criu-image-streamer"),
-            criu_path,
+            status_sender: broadcast::channel(64).0,
+            image_streamer: ImageStreamer::default(),
+            engine,
+            storage: CheckpointStorage::default(),
+            audit: AuditLog::default(),
+            incremental_max_age: Some(Duration::from_secs(60)),
+            cluster_state: None,
+        }
+    }
+
+    /// Deduplicate and/or encrypt migration and sync checkpoints
+    pub fn set_storage(&mut self, storage: CheckpointStorage) {
+        self.storage = storage;
+        self.image_sync_manager.set_storage(storage);
+    }
+
+    /// Record migrations in this audit log
+    pub fn set_audit_log(&mut self, audit: AuditLog) {
+        self.audit = audit;
+    }
+
+    /// Set how recent a sync checkpoint must be to serve as the base of an incremental
+    /// migration dump; `None` always takes a full dump
+    pub fn set_incremental_max_age(&mut self, max_age: Option<Duration>) {
+        self.incremental_max_age = max_age;
+    }
+
+    /// Set the activity at or below which periodic auto-sync skips an instance
+    pub fn set_sync_idle_threshold(&mut self, threshold: u64) {
+        self.image_sync_manager.set_idle_threshold(threshold);
+    }
+
+    /// Location of the criu-image-streamer binary; without it migrations use tar transfer
+    pub fn set_image_streamer_path<P: AsRef<std::path::Path>>(&mut self, path: P) {
+        let image_streamer = ImageStreamer::new(path);
+        if !image_streamer.is_available() {
+            info!("criu-image-streamer not found at {:?}, using tar transfer for migrations", image_streamer.path());
         }
+        self.image_sync_manager.set_image_streamer(image_streamer.clone());
+        self.image_streamer = image_streamer;
+    }
+
+    /// Whether this node can send or receive migrations as a criu-image-streamer stream. Streamed
+    /// images bypass checkpoint sealing, so with a checkpoint key set only the tar transfer is used.
+    fn streams_images(&self) -> bool {
+        self.image_streamer.is_available() && self.storage.key.is_none()
+    }
+
+    /// Whether a migration to a target that does (or does not) accept image streams is streamed;
+    /// otherwise the checkpoint is dumped to disk and sent as an archive
+    fn transfer_by_streamer(&self, target_streams: bool) -> bool {
+        target_streams && self.streams_images()
+    }
+
+    /// Set the cluster view used to check that a migration target may host the instance
+    pub fn set_cluster_state(&mut self, cluster_state: Arc<ClusterStateManager>) {
+        self.cluster_state = Some(cluster_state);
     }
 
     /// Set shadow manager for migration coordination
@@ -651,17 +995,160 @@ impl MigrationManager {
         );
     }
 
-    /// Start the migration manager
-    pub async fn start(&self) -> Result<()> {
-        self.image_sync_manager.start().await?;
-        info!("Migration manager started");
-        Ok(())
+    /// Start the migration manager
+    pub async fn start(&self) -> Result<()> {
+        self.load_persisted_migrations().await;
+        self.image_sync_manager.start().await?;
+        info!("Migration manager started");
+        Ok(())
+    }
+
+    /// Stop the migration manager
+    pub async fn stop(&self) {
+        self.image_sync_manager.stop().await;
+        info!("Migration manager stopped");
+    }
+
+    /// Reload migration records from disk, marking unfinished ones as interrupted
+    async fn load_persisted_migrations(&self) {
+        let records = ActiveMigration::load_all();
+        if records.is_empty() {
+            return;
+        }
+
+        {
+            let mut migrations = self.active_migrations.write().await;
+            for mut record in records {
+                if !record.status.is_terminal() {
+                    warn!("🔄 [MIGRATION] Migration {} for instance {} was interrupted (last status: {:?})",
+                          record.migration_id, record.instance_id, record.status);
+                    record.status = MigrationStatus::Failed("interrupted".to_string());
+                    record.finished_at = Some(Utc::now());
+                    if let Err(e) = record.persist() {
+                        warn!("Failed to persist migration record {}: {}", record.migration_id, e);
+                    }
+                }
+                migrations.insert(record.migration_id, record);
+            }
+            info!("Loaded {} persisted migration record(s)", migrations.len());
+        }
+
+        self.prune_finished_migrations().await;
+    }
+
+    /// Forget finished migrations older than the retention period, in memory and on disk
+    async fn prune_finished_migrations(&self) {
+        let now = Utc::now();
+        let mut migrations = self.active_migrations.write().await;
+        migrations.retain(|_, migration| {
+            if migration.expired(now, MIGRATION_RECORD_RETENTION) {
+                debug!("Pruning record of finished migration {}", migration.migration_id);
+                migration.remove_record();
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Update the status of a tracked migration and persist it
+    async fn set_migration_status(&self, migration_id: Uuid, status: MigrationStatus) -> Option<ActiveMigration> {
+        let mut migrations = self.active_migrations.write().await;
+        let migration = migrations.get_mut(&migration_id)?;
+        if migration.status.is_terminal() {
+            debug!("Migration {} already ended as {:?}, ignoring {:?}", migration_id, migration.status, status);
+            return None;
+        }
+        migration.status = status;
+        if migration.status.is_terminal() {
+            migration.finished_at = Some(Utc::now());
+        }
+        if let Err(e) = migration.persist() {
+            warn!("Failed to persist migration record {}: {}", migration_id, e);
+        }
+
+        match &migration.status {
+            MigrationStatus::Completed => self.audit.record(
+                "migrate_complete",
+                Some(migration.instance_id),
+                Some(&migration.target_node_id.to_string()),
+                None,
+            ),
+            MigrationStatus::Failed(reason) | MigrationStatus::Rejected(reason) => self.audit.record(
+                "migrate_complete",
+                Some(migration.instance_id),
+                Some(&migration.target_node_id.to_string()),
+                Some(reason),
+            ),
+            _ => {}
+        }
+
+        let _ = self.status_sender.send(MigrationStatusUpdate {
+            migration_id,
+            instance_id: migration.instance_id,
+            status: migration.status.clone(),
+        });
+
+        Some(migration.clone())
+    }
+
+    /// Record how much of a migration's checkpoint has been sent to the target
+    async fn set_transfer_progress(&self, migration_id: Uuid, bytes_sent: u64, bytes_total: u64) {
+        if let Some(migration) = self.active_migrations.write().await.get_mut(&migration_id) {
+            migration.bytes_sent = bytes_sent;
+            migration.bytes_total = bytes_total;
+        }
+    }
+
+    /// Subscribe to status transitions of all tracked migrations
+    pub fn subscribe_status(&self) -> broadcast::Receiver<MigrationStatusUpdate> {
+        self.status_sender.subscribe()
     }
 
-    /// Stop the migration manager
-    pub async fn stop(&self) {
-        self.image_sync_manager.stop().await;
-        info!("Migration manager stopped");
+    /// Wait until a migration reaches `Completed` or `Failed`, calling `on_progress` for each
+    /// status it passes through
+    pub async fn wait_for_migration<F: FnMut(&MigrationStatus)>(
+        &self,
+        migration_id: Uuid,
+        timeout: Duration,
+        mut on_progress: F,
+    ) -> MigrationResult<()> {
+        // Subscribe before reading the current status so no transition is missed
+        let mut updates = self.subscribe_status();
+        let mut last = self.get_migration_status(migration_id).await
+            .ok_or(MigrationError::MigrationNotFound(migration_id))?;
+        on_progress(&last);
+
+        let wait = async {
+            while !last.is_terminal() {
+                match updates.recv().await {
+                    Ok(update) if update.migration_id == migration_id => {
+                        last = update.status;
+                        on_progress(&last);
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        if let Some(status) = self.get_migration_status(migration_id).await {
+                            if status != last {
+                                last = status;
+                                on_progress(&last);
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return Err(MigrationError::Other(anyhow!("Migration status updates closed")));
+                    }
+                }
+            }
+            match &last {
+                MigrationStatus::Failed(reason) => Err(MigrationError::Failed(reason.clone())),
+                MigrationStatus::Rejected(reason) => Err(MigrationError::Rejected(reason.clone())),
+                _ => Ok(()),
+            }
+        };
+
+        tokio::time::timeout(timeout, wait).await
+            .map_err(|_| MigrationError::Timeout(format!("waited {}s for migration {}", timeout.as_secs(), migration_id)))?
     }
 
     /// Initiate migration of an instance to another node
@@ -670,20 +1157,21 @@ impl MigrationManager {
         instance_id: &str,
         target_node_id: NodeId,
         options: MigrationOptions,
-    ) -> Result<Uuid> {
+    ) -> MigrationResult<Uuid> {
         info!("Starting migration of instance {} to node {}", instance_id, target_node_id);
 
         // Validate instance exists and is running
         let instance = {
             let manager = self.instance_manager.lock().await;
             manager.get_instance_by_id(instance_id)
-                .ok_or_else(|| anyhow!("Instance {} not found", instance_id))?
+                .ok_or_else(|| MigrationError::InstanceNotFound(instance_id.to_string()))?
                 .clone()
         };
 
         if instance.status != crate::types::InstanceStatus::Running {
-            return Err(anyhow!("Instance {} is not running", instance_id));
+            return Err(MigrationError::InstanceNotRunning(instance_id.to_string()));
         }
+        self.check_target(&instance, target_node_id).await?;
 
         // Generate migration ID
         let migration_id = Uuid::new_v4();
@@ -698,14 +1186,29 @@ impl MigrationManager {
             status: MigrationStatus::Preparing,
             started_at: Utc::now(),
             options: options.clone(),
+            bytes_sent: 0,
+            bytes_total: 0,
+            finished_at: None,
+            source_pid: instance.pid,
         };
 
         // Store active migration
+        self.prune_finished_migrations().await;
+        if let Err(e) = migration.persist() {
+            warn!("Failed to persist migration record {}: {}", migration_id, e);
+        }
         {
             let mut migrations = self.active_migrations.write().await;
             migrations.insert(migration_id, migration);
         }
 
+        // The dump is about as large as the process tree's resident memory
+        let estimated_size = instance.pid.map(|pid| {
+            let mut pids = vec![pid];
+            pids.extend(crate::criu_compat::descendants(pid));
+            crate::stats::resident_bytes(&pids)
+        });
+
         // Send migration request to target node
         let migration_request = MigrationMessage::MigrationRequest {
             migration_id,
@@ -713,15 +1216,43 @@ impl MigrationManager {
             source_node_id: self.local_node_id,
             target_node_id,
             options,
+            estimated_size,
         };
 
         let network_message = NetworkMessage::Migration(migration_request);
-        self.network_manager.send_to_peer(&target_node_id, network_message).await?;
+        let send_result = self.network_manager.send_to_peer(&target_node_id, network_message).await;
+        self.audit.record_result("migrate", Some(instance.id), Some(&target_node_id.to_string()), &send_result);
+        if let Err(e) = send_result {
+            warn!("Failed to send migration request to {}: {}", target_node_id, e);
+            self.set_migration_status(migration_id, MigrationStatus::Failed(e.to_string())).await;
+            return Err(MigrationError::TargetOffline(target_node_id));
+        }
 
         info!("Migration request sent for instance {}: {}", instance_id, migration_id);
         Ok(migration_id)
     }
 
+    /// Check that the target can restore checkpoints and satisfies the instance's affinity.
+    /// Without a cluster view the target's capabilities are unknown, so only the node list can pass.
+    async fn check_target(&self, instance: &crate::types::Instance, target_node_id: NodeId) -> MigrationResult<()> {
+        let capabilities = match self.cluster_state {
+            Some(ref cluster_state) => {
+                let node = cluster_state.get_node_info(&target_node_id).await
+                    .ok_or(MigrationError::TargetOffline(target_node_id))?;
+                if !node.supports_checkpoint_restore() {
+                    return Err(MigrationError::TargetIneligible(format!(
+                        "node {} does not advertise checkpoint/restore capability (has: {})",
+                        node.name,
+                        node.capabilities.join(", ")
+                    )));
+                }
+                node.capabilities
+            }
+            None => Vec::new(),
+        };
+        instance.affinity.check(target_node_id, &capabilities).map_err(MigrationError::TargetIneligible)
+    }
+
     /// Get migration status
     pub async fn get_migration_status(&self, migration_id: Uuid) -> Option<MigrationStatus> {
         let migrations = self.active_migrations.read().await;
@@ -734,6 +1265,28 @@ impl MigrationManager {
         migrations.values().cloned().collect()
     }
 
+    /// Migrations started from this node that have not yet completed or failed
+    pub async fn outbound_in_flight(&self) -> usize {
+        self.active_migrations.read().await.values()
+            .filter(|m| m.source_node_id == self.local_node_id && !m.status.is_terminal())
+            .count()
+    }
+
+    /// Wait until fewer than `limit` outbound migrations are in flight
+    pub async fn wait_for_migration_slot(&self, limit: usize, timeout: Duration) -> MigrationResult<()> {
+        let mut updates = self.subscribe_status();
+        let wait = async {
+            while self.outbound_in_flight().await >= limit.max(1) {
+                if let Err(broadcast::error::RecvError::Closed) = updates.recv().await {
+                    return Err(MigrationError::Other(anyhow!("Migration status updates closed")));
+                }
+            }
+            Ok(())
+        };
+        tokio::time::timeout(timeout, wait).await
+            .map_err(|_| MigrationError::Timeout(format!("waited {}s for a free migration slot", timeout.as_secs())))?
+    }
+
     /// Handle incoming migration message
     pub async fn handle_migration_message(&self, migration_message: MigrationMessage) -> Result<()> {
         match migration_message {
@@ -741,22 +1294,23 @@ impl MigrationManager {
                 migration_id,
                 instance_id,
                 source_node_id,
-                target_node_id,
-                options
+                target_node_id: _,
+                options,
+                estimated_size,
             } => {
-                self.handle_migration_request(migration_id, instance_id, source_node_id, options).await
+                self.handle_migration_request(migration_id, instance_id, source_node_id, options, estimated_size).await
             }
-            MigrationMessage::MigrationAccept { migration_id, target_port } => {
-                self.handle_migration_accept(migration_id, target_port).await
+            MigrationMessage::MigrationAccept { migration_id, target_port, image_streamer, shadow_checkpoint_version } => {
+                self.handle_migration_accept(migration_id, target_port, image_streamer, shadow_checkpoint_version).await
             }
-            MigrationMessage::MigrationReject { migration_id, reason } => {
-                self.handle_migration_reject(migration_id, reason).await
+            MigrationMessage::MigrationReject { migration_id, reason, kind } => {
+                self.handle_migration_reject(migration_id, reason, kind).await
             }
             MigrationMessage::CheckpointTransfer {
                 migration_id,
                 instance_id,
                 source_node_id,
-                target_node_id,
+                target_node_id: _,
                 checkpoint_data
             } => {
                 self.handle_checkpoint_transfer(migration_id, instance_id, source_node_id, checkpoint_data).await
@@ -774,26 +1328,54 @@ impl MigrationManager {
         instance_id: Uuid,
         source_node_id: NodeId,
         options: MigrationOptions,
+        estimated_size: Option<u64>,
     ) -> Result<()> {
         info!("Received migration request for instance {} from node {}", instance_id, source_node_id);
 
+        // Turn the migration down now rather than fail halfway through the transfer
+        let available = crate::stats::disk_available(std::path::Path::new(crate::instance::INSTANCES_DIR));
+        if estimated_size.is_some() && available.is_none() {
+            warn!("Could not determine free space for {}, accepting migration without a space check",
+                  crate::instance::INSTANCES_DIR);
+        }
+        if let Some(kind @ MigrationRejectKind::InsufficientSpace { available, required }) = insufficient_space(estimated_size, available) {
+            let reject_message = MigrationMessage::MigrationReject {
+                migration_id,
+                reason: format!(
+                    "Insufficient disk space on target: {:.1} MiB free, {:.1} MiB needed",
+                    mib(available), mib(required)
+                ),
+                kind,
+            };
+            let network_message = NetworkMessage::Migration(reject_message);
+            self.network_manager.send_to_peer(&source_node_id, network_message).await?;
+
+            warn!("Rejected migration request for instance {} - {:.1} MiB free, {:.1} MiB needed",
+                  instance_id, mib(available), mib(required));
+            return Ok(());
+        }
+
         // Check if we have a shadow instance for this instance
         if let Some(shadow_mgr) = &self.shadow_manager {
             let shadow_mgr_read = shadow_mgr.read().await;
-            if shadow_mgr_read.get_shadow_instance(instance_id).await.is_some() {
+            if let Some(shadow) = shadow_mgr_read.get_shadow_instance(instance_id).await {
                 // Accept the migration and start receiver
                 let target_port = 9999; // TODO: Use dynamic port allocation
 
+                // The source checks our shadow is current before dumping the instance
                 let accept_message = MigrationMessage::MigrationAccept {
                     migration_id,
                     target_port,
+                    image_streamer: self.streams_images(),
+                    shadow_checkpoint_version: shadow.checkpoint_version,
                 };
 
                 // Start migration receiver server
                 let image_sync_manager = self.image_sync_manager.clone();
+                let timeout = Duration::from_secs(options.timeout_secs);
                 tokio::spawn(async move {
-                    if let Err(e) = image_sync_manager.start_migration_receiver(target_port).await {
-                        error!("Failed to start migration receiver on port {}: {}", target_port, e);
+                    if let Err(e) = image_sync_manager.start_migration_receiver(target_port, migration_id, source_node_id, timeout).await {
+                        error!("Migration receiver on port {} failed: {}", target_port, e);
                     }
                 });
 
@@ -806,6 +1388,7 @@ impl MigrationManager {
                 let reject_message = MigrationMessage::MigrationReject {
                     migration_id,
                     reason: "No shadow instance found".to_string(),
+                    kind: MigrationRejectKind::Other,
                 };
 
                 let network_message = NetworkMessage::Migration(reject_message);
@@ -818,6 +1401,7 @@ impl MigrationManager {
             let reject_message = MigrationMessage::MigrationReject {
                 migration_id,
                 reason: "Shadow manager not available".to_string(),
+                kind: MigrationRejectKind::Other,
             };
 
             let network_message = NetworkMessage::Migration(reject_message);
@@ -828,34 +1412,83 @@ impl MigrationManager {
     }
 
     /// Handle migration acceptance
-    async fn handle_migration_accept(&self, migration_id: Uuid, target_port: u16) -> Result<()> {
-        info!("Migration {} accepted, target port: {}", migration_id, target_port);
+    async fn handle_migration_accept(
+        &self,
+        migration_id: Uuid,
+        target_port: u16,
+        image_streamer: bool,
+        shadow_checkpoint_version: u64,
+    ) -> Result<()> {
+        info!("Migration {} accepted, target port: {}, image streamer: {}", migration_id, target_port, image_streamer);
 
         // Update migration status
-        {
-            let mut migrations = self.active_migrations.write().await;
-            if let Some(migration) = migrations.get_mut(&migration_id) {
-                migration.status = MigrationStatus::CreatingCheckpoint;
+        self.set_migration_status(migration_id, MigrationStatus::CreatingCheckpoint).await;
+
+        if let Err(e) = self.ensure_target_shadow_current(migration_id, shadow_checkpoint_version).await {
+            error!("Migration {} aborted: {}", migration_id, e);
+            self.set_migration_status(migration_id, MigrationStatus::Failed(e.to_string())).await;
+            if let Some(target_node_id) = self.active_migrations.read().await.get(&migration_id).map(|m| m.target_node_id) {
+                let complete_message = MigrationMessage::MigrationComplete {
+                    migration_id,
+                    success: false,
+                    error: Some(e.to_string()),
+                };
+                self.network_manager.send_to_peer(&target_node_id, NetworkMessage::Migration(complete_message)).await?;
             }
+            return Ok(());
         }
 
         // Start the actual migration process
-        self.execute_migration(migration_id, target_port).await?;
+        self.execute_migration(migration_id, target_port, image_streamer).await?;
 
         Ok(())
     }
 
+    /// Make sure the target's shadow holds the last checkpoint this node sent, so it cannot end
+    /// up holding an older state than the one migrated. Output chunks share the stream's data
+    /// versions but do not count: a target that missed a checkpoint gets a fresh one pushed
+    /// first; if that fails the migration is aborted.
+    async fn ensure_target_shadow_current(&self, migration_id: Uuid, target_checkpoint: u64) -> Result<()> {
+        let Some(shadow_mgr) = &self.shadow_manager else {
+            return Ok(());
+        };
+        let instance_id = self.active_migrations.read().await.get(&migration_id)
+            .map(|m| m.instance_id)
+            .ok_or_else(|| anyhow!("Migration {} not found", migration_id))?;
+
+        let source_checkpoint = shadow_mgr.read().await.checkpoint_stream_position(instance_id).await;
+        if target_checkpoint >= source_checkpoint {
+            debug!("Target shadow of instance {} holds the checkpoint at data version {} (source sent {})",
+                   instance_id, target_checkpoint, source_checkpoint);
+            return Ok(());
+        }
+
+        warn!(
+            "Target shadow of instance {} holds the checkpoint at data version {}, but this node sent one at {}; pushing a fresh checkpoint before migrating",
+            instance_id, target_checkpoint, source_checkpoint
+        );
+        match self.image_sync_manager.force_sync_instance(&instance_id.to_string()).await {
+            Ok(checkpoint_name) => {
+                info!("Refreshed shadows of instance {} with checkpoint {}", instance_id, checkpoint_name);
+                Ok(())
+            }
+            Err(e) => Err(anyhow!(
+                "target shadow of instance {} missed a checkpoint (holds data version {}, source sent {}) and could not be refreshed: {}",
+                instance_id, target_checkpoint, source_checkpoint, e
+            )),
+        }
+    }
+
     /// Handle migration rejection
-    async fn handle_migration_reject(&self, migration_id: Uuid, reason: String) -> Result<()> {
+    async fn handle_migration_reject(&self, migration_id: Uuid, reason: String, kind: MigrationRejectKind) -> Result<()> {
         error!("Migration {} rejected: {}", migration_id, reason);
+        if let MigrationRejectKind::InsufficientSpace { available, required } = kind {
+            warn!("Migration {} needs {:.1} MiB on the target, which has {:.1} MiB free; free space there or pick another node",
+                  migration_id, mib(required), mib(available));
+        }
 
         // Update migration status
-        {
-            let mut migrations = self.active_migrations.write().await;
-            if let Some(migration) = migrations.get_mut(&migration_id) {
-                migration.status = MigrationStatus::Failed(reason);
-            }
-        }
+        self.set_migration_status(migration_id, MigrationStatus::Rejected(reason)).await;
 
         Ok(())
     }
@@ -866,18 +1499,13 @@ impl MigrationManager {
             info!("Migration {} completed successfully", migration_id);
 
             // Update migration status and convert source instance to shadow
-            {
-                let mut migrations = self.active_migrations.write().await;
-                if let Some(migration) = migrations.get_mut(&migration_id) {
-                    migration.status = MigrationStatus::Completed;
-
-                    // Convert the source instance to shadow state
-                    info!("🔄 [MIGRATION] Converting source instance {} to shadow state", migration.instance_id);
-                    if let Err(e) = self.convert_instance_to_shadow(&migration.instance_id.to_string(), &migration.target_node_id).await {
-                        error!("❌ [MIGRATION] Failed to convert instance to shadow: {}", e);
-                    } else {
-                        info!("✅ [MIGRATION] Successfully converted source instance to shadow state");
-                    }
+            if let Some(migration) = self.set_migration_status(migration_id, MigrationStatus::Completed).await {
+                // Convert the source instance to shadow state
+                info!("🔄 [MIGRATION] Converting source instance {} to shadow state", migration.instance_id);
+                if let Err(e) = self.convert_instance_to_shadow(&migration.instance_id.to_string(), &migration.target_node_id, migration.source_pid).await {
+                    error!("❌ [MIGRATION] Failed to convert instance to shadow: {}", e);
+                } else {
+                    info!("✅ [MIGRATION] Successfully converted source instance to shadow state");
                 }
             }
         } else {
@@ -885,26 +1513,22 @@ impl MigrationManager {
             error!("Migration {} failed: {}", migration_id, error_msg);
 
             // Update migration status
-            {
-                let mut migrations = self.active_migrations.write().await;
-                if let Some(migration) = migrations.get_mut(&migration_id) {
-                    migration.status = MigrationStatus::Failed(error_msg);
-                }
-            }
+            self.set_migration_status(migration_id, MigrationStatus::Failed(error_msg)).await;
         }
 
         Ok(())
     }
 
     /// Convert a source instance to shadow state after successful migration
-    async fn convert_instance_to_shadow(&self, instance_id: &str, target_node_id: &NodeId) -> Result<()> {
+    async fn convert_instance_to_shadow(&self, instance_id: &str, target_node_id: &NodeId, source_pid: Option<u32>) -> Result<()> {
         info!("🔄 [SHADOW_CONVERT] Converting instance {} to shadow state", instance_id);
 
         // Step 1: Stop the original process
         {
             let mut manager = self.instance_manager.lock().await;
             if let Some(instance) = manager.get_instance_by_id_mut(instance_id) {
-                if let Some(pid) = instance.pid {
+                // A re-advertisement from the target may have demoted the instance already
+                if let Some(pid) = instance.pid.or(source_pid) {
                     info!("🛑 [SHADOW_CONVERT] Stopping original process with PID {}", pid);
 
                     // Kill the original process
@@ -916,16 +1540,11 @@ impl MigrationManager {
                 }
 
                 // Step 2: Update instance to shadow state
-                instance.status = crate::types::InstanceStatus::Shadow;
-                instance.pid = None;
-                instance.source_node_id = Some(*target_node_id);
+                instance.demote_to_shadow(*target_node_id)?;
 
                 // Step 3: Save updated metadata
-                if let Err(e) = instance.save_metadata() {
-                    warn!("⚠️ [SHADOW_CONVERT] Failed to save instance metadata: {}", e);
-                } else {
-                    info!("✅ [SHADOW_CONVERT] Updated instance metadata to shadow state");
-                }
+                instance.save_metadata()?;
+                info!("✅ [SHADOW_CONVERT] Updated instance metadata to shadow state");
 
                 info!("✅ [SHADOW_CONVERT] Instance {} converted to shadow state (source: {})", instance_id, target_node_id);
             } else {
@@ -961,7 +1580,7 @@ impl MigrationManager {
     }
 
     /// Execute the actual migration process using criu-image-streamer
-    async fn execute_migration(&self, migration_id: Uuid, target_port: u16) -> Result<()> {
+    async fn execute_migration(&self, migration_id: Uuid, target_port: u16, use_streamer: bool) -> Result<()> {
         let migration = {
             let migrations = self.active_migrations.read().await;
             migrations.get(&migration_id).cloned()
@@ -978,25 +1597,50 @@ impl MigrationManager {
                 .clone()
         };
 
-        // Step 2: Create final checkpoint for migration
         let checkpoint_name = format!("migration-{}", migration_id);
-        self.create_migration_checkpoint(&instance, &checkpoint_name).await?;
 
-        // Step 3: Update status to transferring data
-        {
-            let mut migrations = self.active_migrations.write().await;
-            if let Some(m) = migrations.get_mut(&migration_id) {
-                m.status = MigrationStatus::TransferringData;
+        // Get target node IP from the peer connection
+        let target_ip = self.network_manager.get_connected_peers().await
+            .into_iter()
+            .find(|(node_id, _)| *node_id == migration.target_node_id)
+            .map(|(_, addr)| addr.ip())
+            .unwrap_or(std::net::IpAddr::from([127, 0, 0, 1]));
+
+        // Step 2: Prefer streaming the dump straight to the target, skipping the local image copy
+        if use_streamer && self.storage.key.is_some() {
+            info!("Checkpoint key set, sending migration {} as a sealed archive instead of streaming plaintext images", migration_id);
+        }
+        if self.transfer_by_streamer(use_streamer) {
+            self.set_migration_status(migration_id, MigrationStatus::TransferringData).await;
+            info!("🔄 [MIGRATION] Streaming images for migration {} via criu-image-streamer", migration_id);
+
+            let target_addr = std::net::SocketAddr::new(target_ip, target_port);
+            match self.stream_migration_images(&instance, &checkpoint_name, target_addr).await {
+                Ok(bytes) => {
+                    info!("✅ [MIGRATION] Streamed {} bytes to {}, waiting for the target to restore", bytes, target_addr);
+                    self.set_transfer_progress(migration_id, bytes, bytes).await;
+                    // The target answers with MigrationComplete, which stops the instance here
+                    self.set_migration_status(migration_id, MigrationStatus::RestoringProcess).await;
+                }
+                Err(e) => {
+                    // The dump left the instance running, so failing here leaves it as it was
+                    error!("Migration {} failed during streaming: {}", migration_id, e);
+                    self.set_migration_status(migration_id, MigrationStatus::Failed(e.to_string())).await;
+                }
             }
+            return Ok(());
         }
 
-        // Step 4: Use criu-image-streamer to transfer checkpoint data
+        // Step 3: Create final checkpoint for migration
+        self.create_migration_checkpoint(&instance, &checkpoint_name).await?;
+
+        // Step 4: Update status to transferring data
+        self.set_migration_status(migration_id, MigrationStatus::TransferringData).await;
         info!("Transferring checkpoint data for migration {}", migration_id);
 
-        // Get target node IP (for now, use localhost for testing)
-        let target_ip = "127.0.0.1"; // TODO: Get actual target node IP
+        let result = self.stream_checkpoint_to_target(migration_id, &instance, &checkpoint_name).await;
 
-        match self.stream_checkpoint_to_target(&instance, &checkpoint_name, target_ip, target_port).await {
+        match result {
             Ok(_) => {
                 info!("Checkpoint streaming completed for migration {}", migration_id);
 
@@ -1016,12 +1660,7 @@ impl MigrationManager {
                 error!("Migration {} failed during streaming: {}", migration_id, e);
 
                 // Update status to failed
-                {
-                    let mut migrations = self.active_migrations.write().await;
-                    if let Some(m) = migrations.get_mut(&migration_id) {
-                        m.status = MigrationStatus::Failed(e.to_string());
-                    }
-                }
+                self.set_migration_status(migration_id, MigrationStatus::Failed(e.to_string())).await;
 
                 // Notify failure
                 let complete_message = MigrationMessage::MigrationComplete {
@@ -1067,10 +1706,12 @@ impl MigrationManager {
             let sync_message = ShadowSyncMessage {
                 sender_id: source_node_id,
                 instance_id,
-                data_version: 999999, // High version to ensure it's processed
+                data_version: 0,
                 checkpoint_data: Some(checkpoint_data.clone()),
                 output_data: None,
                 timestamp: chrono::Utc::now(),
+                is_migration: true,
+                output_compressed: false,
             };
 
             info!("🚀 [MIGRATION] Sending checkpoint data to shadow sync handler");
@@ -1103,30 +1744,58 @@ impl MigrationManager {
 
             info!("Creating migration checkpoint for PID {} in {:?}", pid, checkpoint_dir);
 
+            // With a fresh sync checkpoint as parent, only memory dirtied since then is dumped
+            let mut extra_args = Vec::new();
+            let base = self.incremental_max_age
+                .and_then(|max_age| Self::find_incremental_base(&instance_dir.join("checkpoints"), instance.sync_base.as_deref(), max_age));
+            if let Some(ref base) = base {
+                let staging_dir = checkpoint_dir.join(BASE_IMAGES_STAGING_DIR);
+                match Self::stage_base_images(base, &staging_dir) {
+                    Ok(()) => {
+                        info!("Incremental migration dump on top of {:?}", base);
+                        extra_args.push("--prev-images-dir".to_string());
+                        extra_args.push(BASE_IMAGES_STAGING_DIR.to_string());
+                        extra_args.push("--track-mem".to_string());
+                    }
+                    Err(e) => {
+                        warn!("Failed to stage base images from {:?}, taking a full dump: {}", base, e);
+                        let _ = std::fs::remove_dir_all(&staging_dir);
+                    }
+                }
+            } else {
+                info!("No recent sync checkpoint for instance {}, taking a full migration dump", instance.short_id());
+            }
+            let incremental = !extra_args.is_empty();
+
             // Use CRIU to create checkpoint (stop the process for migration)
-            let mut cmd = Command::new("sudo");
-            cmd.arg(&self.criu_path)
-               .arg("dump")
-               .arg("--tree").arg(pid.to_string())
-               .arg("-D").arg(&checkpoint_dir)
-               .arg("--shell-job");
-
-            let output = cmd.output().await?;
-
-            if !output.status.success() {
-                return Err(anyhow!("CRIU checkpoint failed: {}",
-                                 String::from_utf8_lossy(&output.stderr)));
+            crate::criu_compat::save_original_ids(pid, &checkpoint_dir);
+            let request = DumpRequest {
+                pid,
+                images_dir: checkpoint_dir.clone(),
+                shell_job: true,
+                extra_args,
+                tcp_established: crate::criu_compat::check_process_socket_compatibility(pid),
+                ..Default::default()
+            };
+
+            let engine = self.engine.clone();
+            let output = tokio::task::spawn_blocking(move || engine.dump(&request)).await??;
+
+            if !output.success {
+                return Err(anyhow!("CRIU checkpoint failed: {}", output.stderr));
+            }
+            // The frozen time of this dump is the migration's downtime on the source
+            crate::criu_stats::record_dump(instance.id, &checkpoint_dir);
+
+            if incremental {
+                Self::finalize_parent_images(&checkpoint_dir)?;
             }
 
+            // Encrypted images travel to the target as-is and are decrypted there on restore
+            crate::checkpoint_crypto::seal_checkpoint(&checkpoint_dir, instance.id, self.storage.key)?;
+
             // Create migration metadata file
-            let metadata = serde_json::json!({
-                "instance_id": instance.short_id(),
-                "migration_id": checkpoint_name.replace("migration-", ""),
-                "source_node_id": self.local_node_id.to_string(),
-                "timestamp": chrono::Utc::now().to_rfc3339(),
-                "program": instance.program,
-                "args": instance.args
-            });
+            let metadata = self.migration_metadata(instance, checkpoint_name);
 
             let metadata_file = checkpoint_dir.join("migration_metadata.json");
             tokio::fs::write(&metadata_file, metadata.to_string()).await?;
@@ -1140,8 +1809,159 @@ impl MigrationManager {
         Ok(())
     }
 
+    /// Newest `sync-*`/`auto-sync-*` checkpoint (or the instance's `--set-base` checkpoint) if it is
+    /// complete and younger than `max_age`. Older checkpoints are never used: the newest dump is the
+    /// last point dirty tracking was reset.
+    fn find_incremental_base(checkpoints_dir: &std::path::Path, sync_base: Option<&str>, max_age: Duration) -> Option<PathBuf> {
+        let newest = std::fs::read_dir(checkpoints_dir).ok()?
+            .flatten()
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                INCREMENTAL_BASE_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+                    || sync_base == Some(name.as_str())
+            })
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .max_by_key(|(modified, _)| *modified);
+
+        let (modified, path) = newest?;
+        let age = modified.elapsed().unwrap_or_default();
+        if age > max_age {
+            debug!("Newest sync checkpoint {:?} is {}s old, too old for an incremental dump", path, age.as_secs());
+            return None;
+        }
+        if !path.join("inventory.img").exists() {
+            debug!("Newest sync checkpoint {:?} is incomplete", path);
+            return None;
+        }
+        Some(path)
+    }
+
+    /// Link the base checkpoint's images, and those of its own parents, into the staging
+    /// directory inside the new checkpoint
+    fn stage_base_images(base: &std::path::Path, staging_dir: &std::path::Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(staging_dir)?;
+        for entry in std::fs::read_dir(base)?.flatten() {
+            let path = entry.path();
+            if path.is_dir() && entry.file_name() == PARENT_IMAGES_DIR {
+                Self::stage_base_images(&path, &staging_dir.join(PARENT_IMAGES_DIR))?;
+                continue;
+            }
+            if !path.is_file() {
+                continue;
+            }
+            let dst = staging_dir.join(entry.file_name());
+            if std::fs::hard_link(&path, &dst).is_err() {
+                std::fs::copy(&path, &dst)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace CRIU's `parent` symlink with the staged directory so the checkpoint can be transferred as-is
+    fn finalize_parent_images(checkpoint_dir: &std::path::Path) -> Result<()> {
+        let parent_link = checkpoint_dir.join(PARENT_IMAGES_DIR);
+        if parent_link.symlink_metadata().is_ok() {
+            std::fs::remove_file(&parent_link)?;
+        }
+        std::fs::rename(checkpoint_dir.join(BASE_IMAGES_STAGING_DIR), &parent_link)?;
+        Ok(())
+    }
+
+    /// Metadata sent alongside migration images
+    fn migration_metadata(&self, instance: &crate::types::Instance, checkpoint_name: &str) -> serde_json::Value {
+        serde_json::json!({
+            "instance_id": instance.id.to_string(),
+            "migration_id": checkpoint_name.replace("migration-", ""),
+            "source_node_id": self.local_node_id.to_string(),
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "program": instance.program,
+            "args": instance.args
+        })
+    }
+
+    /// Dump the instance with `criu dump --stream` and pipe the images to the target's receiver
+    async fn stream_migration_images(
+        &self,
+        instance: &crate::types::Instance,
+        checkpoint_name: &str,
+        target_addr: std::net::SocketAddr,
+    ) -> Result<u64> {
+        let pid = instance.pid.ok_or_else(|| anyhow!("Instance has no PID"))?;
+
+        // criu and the streamer rendezvous over sockets in the images dir; no image files land here
+        let images_dir = PathBuf::from("instances")
+            .join(format!("instance_{}", instance.short_id()))
+            .join("checkpoints")
+            .join(checkpoint_name);
+        tokio::fs::create_dir_all(&images_dir).await?;
+
+        // The images never land here, so the target records the socket state itself
+        let tcp_established = crate::criu_compat::check_process_socket_compatibility(pid);
+        let mut metadata = self.migration_metadata(instance, checkpoint_name);
+        metadata["tcp_established"] = tcp_established.into();
+        if let Some((uid, gid)) = crate::criu_compat::read_process_ids(pid) {
+            metadata["uid"] = uid.into();
+            metadata["gid"] = gid.into();
+        }
+
+        let mut socket = TcpStream::connect(target_addr).await?;
+        let metadata = serde_json::to_vec(&metadata)?;
+        socket.write_all(STREAM_MAGIC).await?;
+        socket.write_u32(metadata.len() as u32).await?;
+        socket.write_all(&metadata).await?;
+
+        let mut capture = self.image_streamer.spawn_capture(&images_dir)?;
+        let mut capture_stdout = capture.stdout.take()
+            .ok_or_else(|| anyhow!("Failed to open criu-image-streamer stdout"))?;
+
+        // criu connects to the streamer's socket, so wait until it is listening
+        let capture_socket = images_dir.join("streamer-capture.sock");
+        let mut waited = 0;
+        while !capture_socket.exists() {
+            if waited >= 50 {
+                return Err(anyhow!("criu-image-streamer did not become ready"));
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            waited += 1;
+        }
+
+        // The source keeps running until the target confirms the restore; see `handle_migration_complete`
+        let request = DumpRequest {
+            pid,
+            images_dir: images_dir.clone(),
+            leave_running: true,
+            shell_job: true,
+            extra_args: vec!["--stream".to_string()],
+            tcp_established,
+            ..Default::default()
+        };
+        let engine = self.engine.clone();
+        let dump = tokio::task::spawn_blocking(move || engine.dump(&request));
+
+        let copy = async {
+            let bytes = tokio::io::copy(&mut capture_stdout, &mut socket).await?;
+            socket.shutdown().await?;
+            Ok::<u64, std::io::Error>(bytes)
+        };
+
+        let (dump_result, copy_result) = tokio::join!(dump, copy);
+        let output = dump_result??;
+        if !output.success {
+            return Err(anyhow!("CRIU streaming dump failed: {}", output.stderr));
+        }
+        let bytes = copy_result?;
+
+        let capture_output = capture.wait_with_output().await?;
+        if !capture_output.status.success() {
+            return Err(anyhow!("criu-image-streamer capture failed: {}",
+                               String::from_utf8_lossy(&capture_output.stderr)));
+        }
+
+        Ok(bytes)
+    }
+
     /// Transfer checkpoint data to target node using dedicated Migration message
-    async fn stream_checkpoint_to_target(&self, instance: &crate::types::Instance, checkpoint_name: &str, target_ip: &str, target_port: u16) -> Result<()> {
+    async fn stream_checkpoint_to_target(&self, tracked_migration_id: Uuid, instance: &crate::types::Instance, checkpoint_name: &str) -> Result<()> {
         let checkpoint_dir = PathBuf::from("instances")
             .join(format!("instance_{}", instance.short_id()))
             .join("checkpoints")
@@ -1177,6 +1997,8 @@ impl MigrationManager {
         }
 
         debug!("Sending checkpoint data: {} bytes", checkpoint_data.len());
+        let transfer_size = checkpoint_data.len() as u64;
+        self.set_transfer_progress(tracked_migration_id, 0, transfer_size).await;
 
         // Send dedicated Migration message with checkpoint data
         let network_manager = &self.network_manager;
@@ -1198,6 +2020,7 @@ impl MigrationManager {
             Ok(_) => {
                 info!("✅ [MIGRATION] Successfully sent migration checkpoint using Migration message");
                 info!("📊 [MIGRATION] Transfer summary: {} bytes sent for instance {}", checkpoint_data.len(), instance.short_id());
+                self.set_transfer_progress(tracked_migration_id, transfer_size, transfer_size).await;
             }
             Err(e) => {
                 error!("❌ [MIGRATION] Failed to send migration checkpoint: {}", e);
@@ -1306,50 +2129,670 @@ impl MigrationManager {
     }
 
     /// Extract compressed checkpoint data to directory
-    async fn extract_checkpoint_data(compressed_data: &[u8], target_dir: &PathBuf) -> Result<()> {
-        use std::io::Read;
-        use flate2::read::GzDecoder;
-
-        let mut decoder = GzDecoder::new(compressed_data);
-        let mut decompressed = Vec::new();
-        decoder.read_to_end(&mut decompressed)?;
-
-        let mut cursor = 0;
-
-        while cursor < decompressed.len() {
-            // Read file name length
-            if cursor + 4 > decompressed.len() { break; }
-            let name_len = u32::from_be_bytes([
-                decompressed[cursor], decompressed[cursor + 1],
-                decompressed[cursor + 2], decompressed[cursor + 3]
-            ]) as usize;
-            cursor += 4;
-
-            // Read file name
-            if cursor + name_len > decompressed.len() { break; }
-            let file_name = String::from_utf8(decompressed[cursor..cursor + name_len].to_vec())?;
-            cursor += name_len;
-
-            // Read file data length
-            if cursor + 4 > decompressed.len() { break; }
-            let data_len = u32::from_be_bytes([
-                decompressed[cursor], decompressed[cursor + 1],
-                decompressed[cursor + 2], decompressed[cursor + 3]
-            ]) as usize;
-            cursor += 4;
-
-            // Read file data
-            if cursor + data_len > decompressed.len() { break; }
-            let file_data = &decompressed[cursor..cursor + data_len];
-            cursor += data_len;
-
-            // Write file to target directory
-            let file_path = target_dir.join(&file_name);
-            tokio::fs::write(&file_path, file_data).await?;
-
-            info!("Extracted file: {} ({} bytes)", file_name, data_len);
+    async fn extract_checkpoint_data(compressed_data: &[u8], target_dir: &Path) -> Result<()> {
+        let compressed_data = compressed_data.to_vec();
+        let target_dir = target_dir.to_path_buf();
+        let files = tokio::task::spawn_blocking(move || crate::checkpoint_archive::decompress_into(&compressed_data, &target_dir)).await??;
+        info!("Extracted {} checkpoint files", files);
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn migration(status: MigrationStatus, finished_hours_ago: Option<i64>) -> ActiveMigration {
+        let now = Utc::now();
+        ActiveMigration {
+            migration_id: Uuid::new_v4(),
+            instance_id: Uuid::new_v4(),
+            source_node_id: Uuid::new_v4(),
+            target_node_id: Uuid::new_v4(),
+            status,
+            started_at: now - chrono::Duration::hours(48),
+            options: MigrationOptions::default(),
+            bytes_sent: 0,
+            bytes_total: 0,
+            finished_at: finished_hours_ago.map(|hours| now - chrono::Duration::hours(hours)),
+            source_pid: None,
         }
+    }
 
-        Ok(())
+    /// Bytes a source writes at the start of an image stream
+    fn stream_header(metadata: serde_json::Value) -> Vec<u8> {
+        let metadata = serde_json::to_vec(&metadata).unwrap();
+        let mut header = STREAM_MAGIC.to_vec();
+        header.extend((metadata.len() as u32).to_be_bytes());
+        header.extend(metadata);
+        header
+    }
+
+    /// Send `bytes` over a loopback connection and read them back as a stream header
+    async fn read_header_of(bytes: Vec<u8>) -> Result<StreamHeader> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let sender = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(&bytes).await.unwrap();
+        });
+        let (mut socket, _) = listener.accept().await.unwrap();
+        sender.await.unwrap();
+        ImageSyncManager::read_stream_header(&mut socket).await
+    }
+
+    fn migration_manager() -> MigrationManager {
+        crate::test_support::use_scratch_dir();
+        let node_id = Uuid::new_v4();
+        MigrationManager::new_with_engine(
+            node_id,
+            Arc::new(NetworkManager::new(crate::message_protocol::NetworkConfig::default(), node_id)),
+            Arc::new(Mutex::new(InstanceManager::new())),
+            Arc::new(ProcessManager::new()),
+            Arc::new(crate::checkpoint_engine::MockEngine::new()),
+        )
+    }
+
+    #[tokio::test]
+    async fn migrate_instance_refuses_targets_outside_the_affinity() {
+        let mut manager = migration_manager();
+        let allowed = Uuid::new_v4();
+        let mut instance = crate::types::Instance::new("sleep".to_string(), vec!["30".to_string()], PathBuf::from("/"));
+        instance.status = crate::types::InstanceStatus::Running;
+        instance.affinity.nodes.push(allowed);
+        let instance_id = instance.id.to_string();
+        manager.instance_manager.lock().await.add_instance(instance);
+
+        let cluster_state = Arc::new(ClusterStateManager::new(manager.local_node_id));
+        let outside = Uuid::new_v4();
+        cluster_state.add_node(NodeInfo::new(outside, "outside".to_string(), "127.0.0.1:1".parse().unwrap())).await.unwrap();
+        let mut no_restore = NodeInfo::new(allowed, "allowed".to_string(), "127.0.0.1:2".parse().unwrap());
+        no_restore.capabilities.retain(|cap| cap != crate::capabilities::CAP_CRIU_RESTORE);
+        cluster_state.add_node(no_restore).await.unwrap();
+        manager.set_cluster_state(cluster_state);
+
+        for target in [outside, allowed] {
+            let result = manager.migrate_instance(&instance_id, target, MigrationOptions::default()).await;
+            assert!(matches!(&result, Err(MigrationError::TargetIneligible(_))), "{:?}", result);
+        }
+        let result = manager.migrate_instance(&instance_id, Uuid::new_v4(), MigrationOptions::default()).await;
+        assert!(matches!(&result, Err(MigrationError::TargetOffline(_))), "{:?}", result);
+        assert!(manager.list_active_migrations().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn migration_dumps_on_top_of_a_fresh_sync_checkpoint_and_fully_otherwise() {
+        let mut manager = migration_manager();
+        let mut sleeper = std::process::Command::new("sleep").arg("30").spawn().unwrap();
+        let mut instance = crate::types::Instance::new("sleep".to_string(), vec!["30".to_string()], PathBuf::from("/"));
+        instance.pid = Some(sleeper.id());
+        let checkpoints_dir = PathBuf::from("instances").join(format!("instance_{}", instance.short_id())).join("checkpoints");
+
+        // No sync checkpoint yet: a full dump without parent images
+        manager.create_migration_checkpoint(&instance, "migration-full").await.unwrap();
+        assert!(!checkpoints_dir.join("migration-full").join(PARENT_IMAGES_DIR).exists());
+
+        let sync_dir = checkpoints_dir.join(format!("sync-{}", Utc::now().timestamp()));
+        std::fs::create_dir_all(&sync_dir).unwrap();
+        std::fs::write(sync_dir.join("inventory.img"), "base").unwrap();
+        std::fs::write(sync_dir.join("pages-1.img"), "base pages").unwrap();
+        manager.create_migration_checkpoint(&instance, "migration-incremental").await.unwrap();
+        let parent = checkpoints_dir.join("migration-incremental").join(PARENT_IMAGES_DIR);
+        assert_eq!(std::fs::read_to_string(parent.join("pages-1.img")).unwrap(), "base pages");
+        assert!(!checkpoints_dir.join("migration-incremental").join(BASE_IMAGES_STAGING_DIR).exists());
+
+        // The same checkpoint is too old once the allowed age is shorter than its age
+        manager.set_incremental_max_age(Some(Duration::ZERO));
+        manager.create_migration_checkpoint(&instance, "migration-stale").await.unwrap();
+        assert!(!checkpoints_dir.join("migration-stale").join(PARENT_IMAGES_DIR).exists());
+
+        sleeper.kill().unwrap();
+        sleeper.wait().unwrap();
+    }
+
+    #[tokio::test]
+    async fn migration_to_a_node_without_criu_is_refused_before_sending() {
+        let mut manager = migration_manager();
+        let mut instance = crate::types::Instance::new("sleep".to_string(), vec!["30".to_string()], PathBuf::from("/"));
+        instance.status = crate::types::InstanceStatus::Running;
+        let instance_id = instance.id.to_string();
+        manager.instance_manager.lock().await.add_instance(instance);
+
+        let cluster_state = Arc::new(ClusterStateManager::new(manager.local_node_id));
+        let target = Uuid::new_v4();
+        let mut no_criu = NodeInfo::new(target, "no-criu".to_string(), "127.0.0.1:1".parse().unwrap());
+        no_criu.capabilities = vec![crate::capabilities::CAP_INSTANCE_MANAGEMENT.to_string()];
+        cluster_state.add_node(no_criu).await.unwrap();
+        manager.set_cluster_state(cluster_state);
+
+        match manager.migrate_instance(&instance_id, target, MigrationOptions::default()).await {
+            Err(MigrationError::TargetIneligible(reason)) => {
+                assert!(reason.contains("no-criu does not advertise checkpoint/restore capability"), "{}", reason)
+            }
+            other => panic!("expected TargetIneligible, got {:?}", other),
+        }
+        // Refused locally: no migration was recorded, so no request went out
+        assert!(manager.list_active_migrations().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reloaded_unfinished_migrations_are_marked_interrupted() {
+        let manager = migration_manager();
+        let interrupted = migration(MigrationStatus::TransferringData, None);
+        let completed = migration(MigrationStatus::Completed, Some(1));
+        interrupted.persist().unwrap();
+        completed.persist().unwrap();
+        let before = Utc::now();
+
+        manager.load_persisted_migrations().await;
+
+        let migrations = manager.active_migrations.read().await;
+        let reloaded = &migrations[&interrupted.migration_id];
+        assert_eq!(reloaded.status, MigrationStatus::Failed("interrupted".to_string()));
+        assert!(reloaded.finished_at.is_some_and(|at| at >= before), "{:?}", reloaded.finished_at);
+        let on_disk: ActiveMigration = serde_json::from_str(&std::fs::read_to_string(interrupted.record_path()).unwrap()).unwrap();
+        assert_eq!(on_disk.status, MigrationStatus::Failed("interrupted".to_string()));
+        assert_eq!(migrations[&completed.migration_id].status, MigrationStatus::Completed);
+        assert_eq!(migrations[&completed.migration_id].finished_at, completed.finished_at);
+    }
+
+    #[tokio::test]
+    async fn low_space_target_rejects_with_the_space_reason() {
+        let estimate = 1024 * 1024 * 1024;
+        let required = required_disk_space(estimate);
+        assert_eq!(
+            insufficient_space(Some(estimate), Some(required - 1)),
+            Some(MigrationRejectKind::InsufficientSpace { available: required - 1, required })
+        );
+        assert_eq!(insufficient_space(Some(estimate), Some(required)), None);
+        assert_eq!(insufficient_space(None, Some(0)), None);
+        assert_eq!(insufficient_space(Some(estimate), None), None);
+
+        // The source reports the space reason as a rejection
+        let manager = migration_manager();
+        let migration = migration(MigrationStatus::Preparing, None);
+        let migration_id = migration.migration_id;
+        manager.active_migrations.write().await.insert(migration_id, migration);
+        let kind = MigrationRejectKind::InsufficientSpace { available: required - 1, required };
+        manager.handle_migration_reject(migration_id, "Insufficient disk space on target".to_string(), kind).await.unwrap();
+        assert!(matches!(
+            manager.get_migration_status(migration_id).await,
+            Some(MigrationStatus::Rejected(reason)) if reason.contains("disk space")
+        ));
+    }
+
+    #[tokio::test]
+    async fn sealed_checkpoints_cross_the_transfer_encrypted() {
+        let key = [7u8; 32];
+        let source = tempfile::tempdir().unwrap();
+        let secret = b"process memory with a secret";
+        std::fs::write(source.path().join("inventory.img"), b"inventory").unwrap();
+        std::fs::write(source.path().join("pages-1.img"), secret).unwrap();
+        crate::checkpoint_crypto::seal_checkpoint(source.path(), Uuid::new_v4(), Some(key)).unwrap();
+
+        // The bytes on the wire and on the target's disk hold no plaintext image
+        let transferred = ImageSyncManager::read_checkpoint_data(source.path()).await.unwrap();
+        let target = tempfile::tempdir().unwrap();
+        // As the target's shadow saves a transferred checkpoint
+        crate::checkpoint_archive::decompress_into(&transferred, target.path()).unwrap();
+        assert!(crate::checkpoint_crypto::is_sealed(target.path()));
+        assert!(!target.path().join("pages-1.img").exists());
+        let mut plain = Vec::new();
+        std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(transferred.as_slice()), &mut plain).unwrap();
+        assert!(!plain.windows(secret.len()).any(|window| window == secret));
+
+        assert!(crate::checkpoint_crypto::unseal_checkpoint(target.path(), Some([8; 32])).is_err());
+        let unsealed = crate::checkpoint_crypto::unseal_checkpoint(target.path(), Some(key)).unwrap();
+        assert!(unsealed.is_some());
+        assert_eq!(std::fs::read(target.path().join("pages-1.img")).unwrap(), secret);
+
+        // Streaming would send the dump unsealed, so a key rules it out on either end
+        let mut manager = migration_manager();
+        let streamer = tempfile::NamedTempFile::new().unwrap();
+        manager.set_image_streamer_path(streamer.path());
+        assert!(manager.streams_images());
+        manager.set_storage(CheckpointStorage { key: Some(key), ..Default::default() });
+        assert!(!manager.streams_images());
+    }
+
+    #[test]
+    fn migrations_fall_back_to_the_archive_transfer_without_a_streamer_on_both_ends() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = migration_manager();
+        manager.set_image_streamer_path("/nonexistent/criu-image-streamer");
+        assert!(!manager.transfer_by_streamer(true));
+
+        let streamer = tempfile::NamedTempFile::new().unwrap();
+        manager.set_image_streamer_path(streamer.path());
+        assert!(manager.transfer_by_streamer(true));
+        // The target said `image_streamer: false` in its MigrationAccept
+        assert!(!manager.transfer_by_streamer(false));
+    }
+
+    #[tokio::test]
+    async fn rejected_migration_reports_rejected() {
+        let manager = migration_manager();
+        let migration = migration(MigrationStatus::Preparing, None);
+        let migration_id = migration.migration_id;
+        manager.active_migrations.write().await.insert(migration_id, migration);
+
+        manager.handle_migration_reject(migration_id, "no shadow".to_string(), MigrationRejectKind::Other).await.unwrap();
+
+        assert_eq!(manager.get_migration_status(migration_id).await, Some(MigrationStatus::Rejected("no shadow".to_string())));
+        let result = manager.wait_for_migration(migration_id, Duration::from_secs(1), |_| {}).await;
+        assert!(matches!(&result, Err(MigrationError::Rejected(reason)) if reason == "no shadow"), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn waiting_on_a_migration_follows_it_to_completion() {
+        let manager = Arc::new(migration_manager());
+        let tracked = migration(MigrationStatus::Preparing, None);
+        let migration_id = tracked.migration_id;
+        manager.active_migrations.write().await.insert(migration_id, tracked);
+
+        let driver = {
+            let manager = manager.clone();
+            tokio::spawn(async move {
+                for status in [MigrationStatus::CreatingCheckpoint, MigrationStatus::TransferringData, MigrationStatus::RestoringProcess] {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    manager.set_migration_status(migration_id, status).await;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                manager.handle_migration_complete(migration_id, true, None).await.unwrap();
+            })
+        };
+        let mut seen = Vec::new();
+        manager.wait_for_migration(migration_id, Duration::from_secs(5), |status| seen.push(status.clone())).await.unwrap();
+        driver.await.unwrap();
+
+        assert_eq!(seen.first(), Some(&MigrationStatus::Preparing));
+        assert_eq!(seen.last(), Some(&MigrationStatus::Completed));
+        assert!(seen.contains(&MigrationStatus::TransferringData), "{:?}", seen);
+
+        // A failed migration makes the wait fail, which `migrate --wait` turns into a nonzero exit
+        let failing = migration(MigrationStatus::TransferringData, None);
+        let failing_id = failing.migration_id;
+        manager.active_migrations.write().await.insert(failing_id, failing);
+        manager.handle_migration_complete(failing_id, false, Some("restore failed".to_string())).await.unwrap();
+        let result = manager.wait_for_migration(failing_id, Duration::from_secs(1), |_| {}).await;
+        assert!(matches!(&result, Err(MigrationError::Failed(reason)) if reason == "restore failed"), "{:?}", result);
+    }
+
+    #[test]
+    fn status_json_progress_increases_across_phase_transitions() {
+        let mut tracked = migration(MigrationStatus::Preparing, None);
+        let mut progress = Vec::new();
+        let mut record = |tracked: &ActiveMigration| {
+            let json = tracked.status_json();
+            assert_eq!(json["migration_id"], tracked.migration_id.to_string());
+            progress.push(json["progress"].as_u64().unwrap());
+        };
+
+        record(&tracked);
+        tracked.status = MigrationStatus::CreatingCheckpoint;
+        record(&tracked);
+        tracked.status = MigrationStatus::TransferringData;
+        tracked.bytes_total = 1000;
+        for sent in [250, 500, 1000] {
+            tracked.bytes_sent = sent;
+            record(&tracked);
+        }
+        tracked.status = MigrationStatus::RestoringProcess;
+        record(&tracked);
+        tracked.status = MigrationStatus::Completed;
+        record(&tracked);
+
+        assert_eq!(progress.first(), Some(&10));
+        assert_eq!(progress.last(), Some(&100));
+        assert!(progress.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", progress);
+        tracked.status = MigrationStatus::Failed("lost".to_string());
+        assert!(tracked.status_json()["progress"].is_null());
+    }
+
+    /// Log output collected by a test subscriber
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedLogs {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).lines().map(|line| line.to_string()).collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn idle_sync_passes_log_a_bounded_number_of_info_lines() {
+        crate::test_support::use_scratch_dir();
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::INFO)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let instance_manager = Arc::new(Mutex::new(InstanceManager::new()));
+        let process_manager = Arc::new(ProcessManager::new());
+        let engine: Arc<dyn CheckpointEngine> = Arc::new(crate::checkpoint_engine::MockEngine::new());
+        let mut state = SyncLoopState::default();
+        for pass in 0..40 {
+            if pass == 20 {
+                assert_eq!(logs.lines().len(), 1, "{:?}", logs.lines());
+                assert!(logs.lines()[0].contains("No running instances to sync (0 known)"), "{:?}", logs.lines());
+                // A change in what is known is worth one more line, then the loop is quiet again
+                let mut stopped = crate::types::Instance::new("sleep".to_string(), vec!["30".to_string()], PathBuf::from("/"));
+                stopped.status = crate::types::InstanceStatus::Stopped;
+                stopped.sync_enabled = true;
+                instance_manager.lock().await.add_instance(stopped);
+            }
+            ImageSyncManager::sync_all_instances(&instance_manager, &process_manager, None, None, &engine, CheckpointStorage::default(), &mut state, 0)
+                .await
+                .unwrap();
+        }
+        assert_eq!(logs.lines().len(), 2, "{:?}", logs.lines());
+        assert!(logs.lines()[1].contains("(1 known)"), "{:?}", logs.lines());
+    }
+
+    #[test]
+    fn watch_records_the_time_spent_in_each_phase() {
+        let start = Utc::now();
+        let at = |secs| start + chrono::Duration::seconds(secs);
+        let mut snapshot = migration(MigrationStatus::Preparing, None);
+        snapshot.started_at = start;
+        let update = |status| MigrationStatusUpdate { migration_id: snapshot.migration_id, instance_id: snapshot.instance_id, status };
+        let mut watch = MigrationWatch::default();
+        watch.sync(std::slice::from_ref(&snapshot), start);
+
+        assert!(watch.apply(&update(MigrationStatus::CreatingCheckpoint), at(2)));
+        assert!(watch.apply(&update(MigrationStatus::TransferringData), at(5)));
+        assert_eq!(watch.migrations()[0].phase_elapsed(at(7)), chrono::Duration::seconds(2));
+        // Snapshots refresh the transfer counters without starting a new phase
+        let mut transferring = snapshot.clone();
+        transferring.status = MigrationStatus::TransferringData;
+        transferring.bytes_sent = 50;
+        transferring.bytes_total = 100;
+        watch.sync(std::slice::from_ref(&transferring), at(6));
+        // A transition the broadcast missed is picked up from the next snapshot
+        transferring.status = MigrationStatus::RestoringProcess;
+        watch.sync(&[transferring], at(9));
+        assert!(watch.apply(&update(MigrationStatus::Completed), at(10)));
+        // Unknown migrations ask for a snapshot
+        let unknown = MigrationStatusUpdate { migration_id: Uuid::new_v4(), instance_id: Uuid::new_v4(), status: MigrationStatus::Preparing };
+        assert!(!watch.apply(&unknown, at(10)));
+
+        let watched = &watch.migrations()[0];
+        assert_eq!(watch.migrations().len(), 1);
+        assert_eq!(watched.migration.status, MigrationStatus::Completed);
+        assert_eq!(watched.migration.progress_percent(), Some(100));
+        assert_eq!(watched.migration.bytes_sent, 50);
+        assert_eq!(
+            watched.phases,
+            vec![
+                (MigrationStatus::Preparing, chrono::Duration::seconds(2)),
+                (MigrationStatus::CreatingCheckpoint, chrono::Duration::seconds(3)),
+                (MigrationStatus::TransferringData, chrono::Duration::seconds(4)),
+                (MigrationStatus::RestoringProcess, chrono::Duration::seconds(1)),
+            ]
+        );
+        assert_eq!(watched.phase_elapsed(at(60)), chrono::Duration::zero());
+    }
+
+    #[test]
+    fn migration_ids_must_be_uuids() {
+        let instance_id = Uuid::new_v4();
+        let migration_id = Uuid::new_v4();
+        let metadata = serde_json::json!({ "instance_id": instance_id.to_string(), "migration_id": migration_id.to_string() });
+        assert_eq!(parse_migration_ids(&metadata).unwrap(), (instance_id, migration_id));
+
+        let traversal = serde_json::json!({ "instance_id": "../../etc", "migration_id": migration_id.to_string() });
+        assert!(parse_migration_ids(&traversal).is_err());
+        let short_id = serde_json::json!({ "instance_id": &instance_id.to_string()[..8], "migration_id": migration_id.to_string() });
+        assert!(parse_migration_ids(&short_id).is_err());
+        assert!(parse_migration_ids(&serde_json::json!({ "instance_id": instance_id.to_string() })).is_err());
+    }
+
+    #[tokio::test]
+    async fn stream_header_yields_the_migration_ids() {
+        let instance_id = Uuid::new_v4();
+        let migration_id = Uuid::new_v4();
+        let header = stream_header(serde_json::json!({
+            "instance_id": instance_id.to_string(),
+            "migration_id": migration_id.to_string(),
+            "tcp_established": true,
+            "uid": 1000,
+            "gid": 100,
+        }));
+        let expected = StreamHeader { instance_id, migration_id, tcp_established: true, original_ids: Some((1000, 100)) };
+        assert_eq!(read_header_of(header).await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn stream_header_rejects_strangers_and_path_components() {
+        assert!(read_header_of(b"GET / HTTP/1.1\r\n\r\n".to_vec()).await.is_err());
+
+        let header = stream_header(serde_json::json!({
+            "instance_id": Uuid::new_v4().to_string(),
+            "migration_id": "../../../tmp/x",
+        }));
+        assert!(read_header_of(header).await.is_err());
+
+        let mut oversized = STREAM_MAGIC.to_vec();
+        oversized.extend(u32::MAX.to_be_bytes());
+        assert!(read_header_of(oversized).await.is_err());
+    }
+
+    #[test]
+    fn finished_migrations_expire_after_the_retention_period() {
+        let now = Utc::now();
+        assert!(migration(MigrationStatus::Completed, Some(25)).expired(now, MIGRATION_RECORD_RETENTION));
+        assert!(!migration(MigrationStatus::Completed, Some(1)).expired(now, MIGRATION_RECORD_RETENTION));
+        assert!(migration(MigrationStatus::Failed("x".to_string()), None).expired(now, MIGRATION_RECORD_RETENTION));
+    }
+
+    #[test]
+    fn unfinished_migrations_never_expire() {
+        let stale = migration(MigrationStatus::TransferringData, None);
+        assert!(!stale.expired(Utc::now(), MIGRATION_RECORD_RETENTION));
+    }
+
+    #[test]
+    fn records_live_under_the_data_dir_regardless_of_cwd() {
+        crate::test_support::use_scratch_dir();
+        let record = migration(MigrationStatus::Completed, Some(25));
+        assert!(record.record_path().starts_with(crate::instance::data_dir()));
+        assert!(record.record_path().is_absolute());
+
+        record.persist().unwrap();
+        assert!(ActiveMigration::load_all().iter().any(|m| m.migration_id == record.migration_id));
+        record.remove_record();
+        assert!(!record.record_path().exists());
+    }
+
+    #[tokio::test]
+    async fn sync_tick_checkpoints_only_opted_in_instances() {
+        crate::test_support::use_scratch_dir();
+        let instance_manager = Arc::new(Mutex::new(InstanceManager::new()));
+        let process_manager = Arc::new(ProcessManager::new());
+        let mock = Arc::new(crate::checkpoint_engine::MockEngine::new());
+        let engine: Arc<dyn CheckpointEngine> = mock.clone();
+        let (synced, skipped) = {
+            let mut manager = instance_manager.lock().await;
+            let synced = manager.start_instance("sleep".to_string(), vec!["30".to_string()], process_manager.clone()).await.unwrap();
+            let skipped = manager.start_instance("sleep".to_string(), vec!["30".to_string()], process_manager.clone()).await.unwrap();
+            manager.set_sync_enabled(&synced, true).unwrap();
+            (synced, skipped)
+        };
+        let pid_of = |id: &str, manager: &InstanceManager| manager.get_instance_by_id(id).unwrap().pid.unwrap();
+        let (synced_pid, skipped_pid) = {
+            let manager = instance_manager.lock().await;
+            (pid_of(&synced, &manager), pid_of(&skipped, &manager))
+        };
+
+        ImageSyncManager::sync_all_instances(
+            &instance_manager, &process_manager, None, None, &engine, CheckpointStorage::default(), &mut SyncLoopState::default(), 0,
+        ).await.unwrap();
+
+        let calls = mock.calls();
+        assert!(calls.contains(&format!("dump {}", synced_pid)), "{:?}", calls);
+        assert!(!calls.contains(&format!("dump {}", skipped_pid)), "{:?}", calls);
+        let mut manager = instance_manager.lock().await;
+        for id in [synced, skipped] {
+            manager.stop_instance(&id, process_manager.clone()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn sync_tick_skips_idle_instances_after_their_first_sync() {
+        crate::test_support::use_scratch_dir();
+        let instance_manager = Arc::new(Mutex::new(InstanceManager::new()));
+        let process_manager = Arc::new(ProcessManager::new());
+        let mock = Arc::new(crate::checkpoint_engine::MockEngine::new());
+        let engine: Arc<dyn CheckpointEngine> = mock.clone();
+        let (idle, busy) = {
+            let mut manager = instance_manager.lock().await;
+            let idle = manager.start_instance("sleep".to_string(), vec!["30".to_string()], process_manager.clone()).await.unwrap();
+            let busy_loop = vec!["-c".to_string(), "while :; do :; done".to_string()];
+            let busy = manager.start_instance("sh".to_string(), busy_loop, process_manager.clone()).await.unwrap();
+            manager.set_sync_enabled(&idle, true).unwrap();
+            manager.set_sync_enabled(&busy, true).unwrap();
+            (idle, busy)
+        };
+        let (idle_dump, busy_dump) = {
+            let manager = instance_manager.lock().await;
+            let dump_of = |id: &str| format!("dump {}", manager.get_instance_by_id(id).unwrap().pid.unwrap());
+            (dump_of(&idle), dump_of(&busy))
+        };
+        let dumps_of = |call: &str| mock.calls().iter().filter(|c| *c == call).count();
+
+        let mut state = SyncLoopState::default();
+        for _ in 0..3 {
+            ImageSyncManager::sync_all_instances(
+                &instance_manager, &process_manager, None, None, &engine, CheckpointStorage::default(), &mut state, 0,
+            ).await.unwrap();
+            // Long enough for the busy loop to use a few clock ticks
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+
+        assert_eq!(dumps_of(&idle_dump), 1, "{:?}", mock.calls());
+        assert_eq!(dumps_of(&busy_dump), 3, "{:?}", mock.calls());
+        let mut manager = instance_manager.lock().await;
+        for id in [idle, busy] {
+            manager.stop_instance(&id, process_manager.clone()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn auto_syncs_build_on_the_set_base_checkpoint_until_the_chain_is_too_long() {
+        crate::test_support::use_scratch_dir();
+        let instance_manager = Arc::new(Mutex::new(InstanceManager::new()));
+        let process_manager = Arc::new(ProcessManager::new());
+        let mock = Arc::new(crate::checkpoint_engine::MockEngine::new());
+        let engine: Arc<dyn CheckpointEngine> = mock.clone();
+        let criu_manager = Arc::new(crate::criu_manager::CriuManager::new_with_engine(engine.clone()));
+        let (id, checkpoints_dir) = {
+            let mut manager = instance_manager.lock().await;
+            let id = manager.start_instance("sleep".to_string(), vec!["30".to_string()], process_manager.clone()).await.unwrap();
+            manager.set_sync_enabled(&id, true).unwrap();
+            manager.checkpoint_instance(&id, "base", true, false, criu_manager, process_manager.clone()).await.unwrap();
+            let checkpoints_dir = manager.get_instance_by_id(&id).unwrap().checkpoints_dir();
+            (id, checkpoints_dir)
+        };
+        // CRIU leaves an inventory in every complete dump, the mock does not
+        std::fs::write(checkpoints_dir.join("base").join("inventory.img"), b"").unwrap();
+        let prev_images_dir = |dump: &DumpRequest| {
+            dump.extra_args.iter().position(|arg| arg == "--prev-images-dir").map(|i| dump.extra_args[i + 1].clone())
+        };
+
+        ImageSyncManager::sync_all_instances(
+            &instance_manager, &process_manager, None, None, &engine, CheckpointStorage::default(), &mut SyncLoopState::default(), 0,
+        ).await.unwrap();
+        let dump = mock.dumps().last().cloned().unwrap();
+        assert_eq!(prev_images_dir(&dump), Some("../base".to_string()), "{:?}", dump);
+
+        // The auto-sync becomes the base of the next one; once it sits on a full chain, the next is a full dump
+        let sync_base = instance_manager.lock().await.get_instance_by_id(&id).unwrap().sync_base.clone().unwrap();
+        assert!(sync_base.starts_with("auto-sync-"), "{}", sync_base);
+        let mut dir = checkpoints_dir.join(&sync_base);
+        std::fs::write(dir.join("inventory.img"), b"").unwrap();
+        for _ in 0..MAX_INCREMENTAL_CHAIN {
+            dir = dir.join(PARENT_IMAGES_DIR);
+            std::fs::create_dir_all(&dir).unwrap();
+        }
+
+        ImageSyncManager::sync_all_instances(
+            &instance_manager, &process_manager, None, None, &engine, CheckpointStorage::default(), &mut SyncLoopState::default(), 0,
+        ).await.unwrap();
+        let dump = mock.dumps().last().cloned().unwrap();
+        assert_eq!(mock.dumps().len(), 3);
+        assert_eq!(prev_images_dir(&dump), None, "{:?}", dump);
+        assert!(dump.extra_args.contains(&"--track-mem".to_string()), "{:?}", dump);
+
+        instance_manager.lock().await.stop_instance(&id, process_manager.clone()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_a_target_that_missed_a_checkpoint_is_refreshed_before_cutover() {
+        crate::test_support::use_scratch_dir();
+        let node_id = Uuid::new_v4();
+        let instance_manager = Arc::new(Mutex::new(InstanceManager::new()));
+        let process_manager = Arc::new(ProcessManager::new());
+        let mock = Arc::new(crate::checkpoint_engine::MockEngine::new());
+        let engine: Arc<dyn CheckpointEngine> = mock.clone();
+        let mut manager = MigrationManager::new_with_engine(
+            node_id,
+            Arc::new(NetworkManager::new(crate::message_protocol::NetworkConfig::default(), node_id)),
+            instance_manager.clone(),
+            process_manager.clone(),
+            engine.clone(),
+        );
+        let queue = crate::network_manager::OutboundQueue::new(64);
+        let mut shadows = ShadowInstanceManager::new_with_engine(node_id, instance_manager.clone(), process_manager.clone(), engine);
+        shadows.set_network_sender(queue.clone());
+        shadows.set_output_batch_window(Duration::ZERO);
+        let shadows = Arc::new(RwLock::new(shadows));
+        manager.set_shadow_manager(shadows.clone());
+
+        let started = instance_manager.lock().await.start_instance("sleep".to_string(), vec!["30".to_string()], process_manager.clone()).await.unwrap();
+        let (instance_id, pid) = {
+            let instances = instance_manager.lock().await;
+            let instance = instances.get_instance_by_id(&started).unwrap();
+            (instance.id, instance.pid.unwrap())
+        };
+        let mut tracked = migration(MigrationStatus::Preparing, None);
+        tracked.instance_id = instance_id;
+        let migration_id = tracked.migration_id;
+        manager.active_migrations.write().await.insert(migration_id, tracked);
+
+        // A checkpoint at data version 1, then a chatty stretch of output
+        shadows.read().await.stream_checkpoint_to_shadows(instance_id, b"checkpoint".to_vec()).await.unwrap();
+        for line in 0..40 {
+            shadows.read().await.stream_output_to_shadows(instance_id, format!("line {}\n", line).into_bytes(), crate::message_protocol::StreamType::Stdout).await.unwrap();
+        }
+        assert_eq!(shadows.read().await.stream_position(instance_id).await, 41);
+
+        // Holding that checkpoint is current, however much output came after it
+        manager.ensure_target_shadow_current(migration_id, 1).await.unwrap();
+        assert!(!mock.calls().contains(&format!("dump {}", pid)), "{:?}", mock.calls());
+
+        // Missing it gets a fresh checkpoint pushed first
+        manager.ensure_target_shadow_current(migration_id, 0).await.unwrap();
+        assert!(mock.calls().contains(&format!("dump {}", pid)), "{:?}", mock.calls());
+
+        // And a refresh that fails aborts the migration
+        mock.set_result(crate::checkpoint_engine::EngineOutput { success: false, exit_code: Some(1), stdout: String::new(), stderr: "dump failed".to_string() });
+        let error = manager.ensure_target_shadow_current(migration_id, 0).await.unwrap_err().to_string();
+        assert!(error.contains("missed a checkpoint"), "{}", error);
+
+        instance_manager.lock().await.stop_instance(&started, process_manager.clone()).await.unwrap();
     }
-}
\ No newline at end of file
+}
@@ -2,11 +2,14 @@ use crate::message_protocol::*;
 use anyhow::{Result, Context};
 use bytes::{Buf, BufMut, BytesMut};
 use futures::{SinkExt, StreamExt};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio::sync::{mpsc, oneshot, Mutex, Notify, RwLock};
 use tokio_util::codec::{Decoder, Encoder, Framed};
 use tracing::{debug, error, info, warn};
 
@@ -54,15 +57,171 @@ impl Encoder<NetworkMessage> for MessageCodec {
     }
 }
 
+/// How long a control message waits for space in a full outbound queue
+const CRITICAL_SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for a peer's protocol preamble
+const PREAMBLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bounded outbound message queue. When full, best-effort messages (see
+/// `NetworkMessage::is_best_effort`) evict the oldest queued best-effort message,
+/// while control messages wait up to `CRITICAL_SEND_TIMEOUT` for space.
+#[derive(Debug, Clone)]
+pub struct OutboundQueue {
+    inner: Arc<OutboundQueueInner>,
+}
+
+#[derive(Debug)]
+struct OutboundQueueInner {
+    messages: std::sync::Mutex<VecDeque<NetworkMessage>>,
+    capacity: usize,
+    dropped: AtomicU64,
+    closed: AtomicBool,
+    readable: Notify,
+    writable: Notify,
+}
+
+impl OutboundQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(OutboundQueueInner {
+                messages: std::sync::Mutex::new(VecDeque::new()),
+                capacity: capacity.max(1),
+                dropped: AtomicU64::new(0),
+                closed: AtomicBool::new(false),
+                readable: Notify::new(),
+                writable: Notify::new(),
+            }),
+        }
+    }
+
+    /// Queue a message, applying backpressure according to its kind
+    pub async fn send(&self, message: NetworkMessage) -> Result<()> {
+        if message.is_best_effort() {
+            self.push_best_effort(message)
+        } else {
+            self.push_critical(message).await
+        }
+    }
+
+    fn push_best_effort(&self, message: NetworkMessage) -> Result<()> {
+        let mut messages = self.inner.messages.lock().unwrap();
+        if self.inner.closed.load(Ordering::SeqCst) {
+            anyhow::bail!("Outbound queue closed");
+        }
+        if messages.len() >= self.inner.capacity {
+            self.inner.dropped.fetch_add(1, Ordering::Relaxed);
+            match messages.iter().position(|m| m.is_best_effort()) {
+                Some(oldest) => {
+                    messages.remove(oldest);
+                    debug!("Outbound queue full, dropped oldest best-effort message");
+                }
+                None => {
+                    debug!("Outbound queue full of control messages, dropped best-effort message");
+                    return Ok(());
+                }
+            }
+        }
+        messages.push_back(message);
+        drop(messages);
+        self.inner.readable.notify_one();
+        Ok(())
+    }
+
+    async fn push_critical(&self, message: NetworkMessage) -> Result<()> {
+        let deadline = tokio::time::Instant::now() + CRITICAL_SEND_TIMEOUT;
+        let mut pending = Some(message);
+        loop {
+            let writable = self.inner.writable.notified();
+            {
+                let mut messages = self.inner.messages.lock().unwrap();
+                if self.inner.closed.load(Ordering::SeqCst) {
+                    anyhow::bail!("Outbound queue closed");
+                }
+                if messages.len() < self.inner.capacity {
+                    if let Some(message) = pending.take() {
+                        messages.push_back(message);
+                    }
+                    drop(messages);
+                    self.inner.readable.notify_one();
+                    return Ok(());
+                }
+            }
+            if tokio::time::timeout_at(deadline, writable).await.is_err() {
+                anyhow::bail!("Outbound queue still full after {:?}", CRITICAL_SEND_TIMEOUT);
+            }
+        }
+    }
+
+    /// Next queued message, or `None` once the queue is closed and drained
+    pub async fn recv(&self) -> Option<NetworkMessage> {
+        loop {
+            let readable = self.inner.readable.notified();
+            let next = self.inner.messages.lock().unwrap().pop_front();
+            if let Some(message) = next {
+                self.inner.writable.notify_one();
+                return Some(message);
+            }
+            if self.inner.closed.load(Ordering::SeqCst) {
+                return None;
+            }
+            readable.await;
+        }
+    }
+
+    /// Reject further messages and wake the receiver so it can finish
+    pub fn close(&self) {
+        self.inner.closed.store(true, Ordering::SeqCst);
+        self.inner.readable.notify_one();
+        self.inner.writable.notify_waiters();
+    }
+
+    /// Messages currently queued
+    pub fn len(&self) -> usize {
+        self.inner.messages.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity
+    }
+
+    /// Best-effort messages dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Outbound queue state for one peer, for display
+#[derive(Debug, Clone)]
+pub struct QueueDepth {
+    pub node_id: NodeId,
+    pub queued: usize,
+    pub capacity: usize,
+    pub dropped: u64,
+}
+
 /// Connection information for a peer
 #[derive(Debug, Clone)]
 pub struct PeerConnection {
     pub node_id: NodeId,
     pub addr: SocketAddr,
-    pub sender: mpsc::UnboundedSender<NetworkMessage>,
+    pub sender: OutboundQueue,
     pub connected_at: chrono::DateTime<chrono::Utc>,
 }
 
+/// What a connection task needs from the network manager
+#[derive(Clone)]
+struct ConnectionContext {
+    local_node_id: NodeId,
+    connections: Arc<RwLock<HashMap<NodeId, PeerConnection>>>,
+    event_sender: mpsc::UnboundedSender<NetworkEvent>,
+    queue_capacity: usize,
+}
+
 /// Events emitted by the network manager
 #[derive(Debug, Clone)]
 pub enum NetworkEvent {
@@ -85,14 +244,14 @@ pub struct NetworkManager {
     connections: Arc<RwLock<HashMap<NodeId, PeerConnection>>>,
     event_sender: mpsc::UnboundedSender<NetworkEvent>,
     event_receiver: Arc<Mutex<mpsc::UnboundedReceiver<NetworkEvent>>>,
-    broadcast_sender: mpsc::UnboundedSender<NetworkMessage>,
-    broadcast_receiver: Arc<Mutex<mpsc::UnboundedReceiver<NetworkMessage>>>,
+    broadcast_queue: OutboundQueue,
+    pre_bound_listener: std::sync::Mutex<Option<std::net::TcpListener>>,
 }
 
 impl NetworkManager {
     pub fn new(config: NetworkConfig, node_id: NodeId) -> Self {
         let (event_sender, event_receiver) = mpsc::unbounded_channel();
-        let (broadcast_sender, broadcast_receiver) = mpsc::unbounded_channel();
+        let broadcast_queue = OutboundQueue::new(config.outbound_queue_capacity);
 
         Self {
             node_id,
@@ -100,11 +259,29 @@ impl NetworkManager {
             connections: Arc::new(RwLock::new(HashMap::new())),
             event_sender,
             event_receiver: Arc::new(Mutex::new(event_receiver)),
-            broadcast_sender,
-            broadcast_receiver: Arc::new(Mutex::new(broadcast_receiver)),
+            broadcast_queue,
+            pre_bound_listener: std::sync::Mutex::new(None),
         }
     }
 
+    /// Create a network manager that will listen on an already bound socket
+    pub fn new_with_listener(config: NetworkConfig, node_id: NodeId, listener: std::net::TcpListener) -> Self {
+        let manager = Self::new(config, node_id);
+        *manager.pre_bound_listener.lock().unwrap() = Some(listener);
+        manager
+    }
+
+    /// Bind a listener ahead of time so an ephemeral port (`:0`) can be resolved before startup
+    pub fn pre_bind(addr: SocketAddr) -> Result<(std::net::TcpListener, SocketAddr)> {
+        let listener = std::net::TcpListener::bind(addr)
+            .context("Failed to bind TCP listener")?;
+        listener.set_nonblocking(true)
+            .context("Failed to set TCP listener non-blocking")?;
+        let bound_addr = listener.local_addr()
+            .context("Failed to read bound TCP address")?;
+        Ok((listener, bound_addr))
+    }
+
     pub fn node_id(&self) -> NodeId {
         self.node_id
     }
@@ -114,40 +291,68 @@ impl NetworkManager {
     }
 
     /// Get a sender for broadcasting messages to all connected peers
-    pub fn get_sender(&self) -> mpsc::UnboundedSender<NetworkMessage> {
-        self.broadcast_sender.clone()
+    pub fn get_sender(&self) -> OutboundQueue {
+        self.broadcast_queue.clone()
     }
 
     /// Start the broadcast handler
     pub async fn start_broadcast_handler(&self) {
-        let broadcast_receiver = self.broadcast_receiver.clone();
+        let broadcast_queue = self.broadcast_queue.clone();
         let connections = self.connections.clone();
 
         tokio::spawn(async move {
-            let mut receiver = broadcast_receiver.lock().await;
-            while let Some(message) = receiver.recv().await {
-                let connections_read = connections.read().await;
-                for connection in connections_read.values() {
-                    if let Err(e) = connection.sender.send(message.clone()) {
-                        warn!("Failed to send broadcast message to {}: {}", connection.node_id, e);
-                    }
-                }
+            while let Some(message) = broadcast_queue.recv().await {
+                Self::send_to_all(&connections, message).await;
             }
         });
     }
 
+    /// Queue a message for every connected peer without holding the connection lock while waiting
+    async fn send_to_all(connections: &Arc<RwLock<HashMap<NodeId, PeerConnection>>>, message: NetworkMessage) {
+        let queues: Vec<(NodeId, OutboundQueue)> = connections.read().await
+            .values()
+            .map(|connection| (connection.node_id, connection.sender.clone()))
+            .collect();
+
+        for (node_id, queue) in queues {
+            if let Err(e) = queue.send(message.clone()).await {
+                warn!("Failed to send broadcast message to {}: {}", node_id, e);
+            }
+        }
+    }
+
+    /// Outbound queue depth of the broadcast queue and of each connected peer
+    pub async fn outbound_queue_depths(&self) -> (QueueDepth, Vec<QueueDepth>) {
+        let depth = |node_id: NodeId, queue: &OutboundQueue| QueueDepth {
+            node_id,
+            queued: queue.len(),
+            capacity: queue.capacity(),
+            dropped: queue.dropped(),
+        };
+
+        let peers = self.connections.read().await
+            .values()
+            .map(|connection| depth(connection.node_id, &connection.sender))
+            .collect();
+        (depth(self.node_id, &self.broadcast_queue), peers)
+    }
+
     /// Start listening for incoming connections
     pub async fn start_listening(&self) -> Result<()> {
-        let listener = TcpListener::bind(&self.config.listen_addr).await
-            .context("Failed to bind TCP listener")?;
+        let pre_bound = self.pre_bound_listener.lock().unwrap().take();
+        let listener = match pre_bound {
+            Some(std_listener) => TcpListener::from_std(std_listener)
+                .context("Failed to register pre-bound TCP listener")?,
+            None => TcpListener::bind(&self.config.listen_addr).await
+                .context("Failed to bind TCP listener")?,
+        };
 
-        info!("Network manager listening on {}", self.config.listen_addr);
+        let local_addr = listener.local_addr().unwrap_or(self.config.listen_addr);
+        info!("Network manager listening on {}", local_addr);
 
-        let _ = self.event_sender.send(NetworkEvent::ListeningStarted(self.config.listen_addr));
+        let _ = self.event_sender.send(NetworkEvent::ListeningStarted(local_addr));
 
-        let connections = self.connections.clone();
-        let event_sender = self.event_sender.clone();
-        let node_id = self.node_id;
+        let context = self.connection_context();
 
         tokio::spawn(async move {
             loop {
@@ -155,13 +360,10 @@ impl NetworkManager {
                     Ok((stream, addr)) => {
                         debug!("Accepted connection from {}", addr);
 
-                        let connections = connections.clone();
-                        let event_sender = event_sender.clone();
+                        let context = context.clone();
 
                         tokio::spawn(async move {
-                            if let Err(e) = Self::handle_incoming_connection(
-                                stream, addr, node_id, connections, event_sender
-                            ).await {
+                            if let Err(e) = Self::handle_incoming_connection(stream, addr, context).await {
                                 error!("Error handling incoming connection from {}: {}", addr, e);
                             }
                         });
@@ -176,38 +378,44 @@ impl NetworkManager {
         Ok(())
     }
 
-    /// Connect to a remote peer
-    pub async fn connect_to_peer(&self, addr: SocketAddr) -> Result<()> {
+    /// Connect to a remote peer and complete the handshake, returning the peer's node ID.
+    /// On success the peer is in the connected set.
+    pub async fn connect_to_peer(&self, addr: SocketAddr) -> Result<NodeId> {
         info!("Connecting to peer at {}", addr);
 
-        let stream = tokio::time::timeout(
-            std::time::Duration::from_secs(self.config.connection_timeout_secs),
-            TcpStream::connect(addr)
-        ).await
+        let timeout = std::time::Duration::from_secs(self.config.connection_timeout_secs);
+        let stream = tokio::time::timeout(timeout, TcpStream::connect(addr)).await
             .context("Connection timeout")?
             .context("Failed to connect to peer")?;
 
-        let connections = self.connections.clone();
-        let event_sender = self.event_sender.clone();
-        let node_id = self.node_id;
+        let context = self.connection_context();
+        let (handshake_sender, handshake_receiver) = oneshot::channel();
 
-        tokio::spawn(async move {
-            if let Err(e) = Self::handle_outgoing_connection(
-                stream, addr, node_id, connections, event_sender
-            ).await {
+        let connection_task = tokio::spawn(async move {
+            if let Err(e) = Self::handle_outgoing_connection(stream, addr, context, handshake_sender).await {
                 error!("Error handling outgoing connection to {}: {}", addr, e);
             }
         });
 
-        Ok(())
+        match tokio::time::timeout(timeout, handshake_receiver).await {
+            Ok(Ok(Ok(peer_node_id))) => Ok(peer_node_id),
+            Ok(Ok(Err(reason))) => anyhow::bail!("Handshake with {} failed: {}", addr, reason),
+            Ok(Err(_)) => anyhow::bail!("Connection to {} closed during handshake", addr),
+            Err(_) => {
+                connection_task.abort();
+                anyhow::bail!("Handshake with {} timed out", addr)
+            }
+        }
     }
 
     /// Send a message to a specific peer
     pub async fn send_to_peer(&self, peer_id: &NodeId, message: NetworkMessage) -> Result<()> {
-        let connections = self.connections.read().await;
+        let queue = self.connections.read().await
+            .get(peer_id)
+            .map(|connection| connection.sender.clone());
 
-        if let Some(connection) = connections.get(peer_id) {
-            connection.sender.send(message)
+        if let Some(queue) = queue {
+            queue.send(message).await
                 .context("Failed to send message to peer")?;
             Ok(())
         } else {
@@ -217,14 +425,7 @@ impl NetworkManager {
 
     /// Broadcast a message to all connected peers
     pub async fn broadcast(&self, message: NetworkMessage) -> Result<()> {
-        let connections = self.connections.read().await;
-
-        for connection in connections.values() {
-            if let Err(e) = connection.sender.send(message.clone()) {
-                warn!("Failed to send broadcast message to {}: {}", connection.node_id, e);
-            }
-        }
-
+        Self::send_to_all(&self.connections, message).await;
         Ok(())
     }
 
@@ -240,8 +441,9 @@ impl NetworkManager {
     pub async fn disconnect_peer(&self, peer_id: &NodeId) -> Result<()> {
         let mut connections = self.connections.write().await;
 
-        if let Some(_connection) = connections.remove(peer_id) {
-            // Sender will be dropped, causing the connection task to terminate
+        if let Some(connection) = connections.remove(peer_id) {
+            // Closing the queue ends the connection's send task
+            connection.sender.close();
             info!("Disconnected from peer {}", peer_id);
             let _ = self.event_sender.send(NetworkEvent::PeerDisconnected(
                 *peer_id,
@@ -258,41 +460,57 @@ impl NetworkManager {
         receiver.recv().await
     }
 
+    fn connection_context(&self) -> ConnectionContext {
+        ConnectionContext {
+            local_node_id: self.node_id,
+            connections: self.connections.clone(),
+            event_sender: self.event_sender.clone(),
+            queue_capacity: self.config.outbound_queue_capacity,
+        }
+    }
+
     /// Handle incoming TCP connection
-    async fn handle_incoming_connection(
-        stream: TcpStream,
-        addr: SocketAddr,
-        local_node_id: NodeId,
-        connections: Arc<RwLock<HashMap<NodeId, PeerConnection>>>,
-        event_sender: mpsc::UnboundedSender<NetworkEvent>,
-    ) -> Result<()> {
-        Self::handle_connection(stream, addr, local_node_id, connections, event_sender, true).await
+    async fn handle_incoming_connection(stream: TcpStream, addr: SocketAddr, context: ConnectionContext) -> Result<()> {
+        Self::handle_connection(stream, addr, context, true, None).await
     }
 
     /// Handle outgoing TCP connection
     async fn handle_outgoing_connection(
         stream: TcpStream,
         addr: SocketAddr,
-        local_node_id: NodeId,
-        connections: Arc<RwLock<HashMap<NodeId, PeerConnection>>>,
-        event_sender: mpsc::UnboundedSender<NetworkEvent>,
+        context: ConnectionContext,
+        handshake_done: oneshot::Sender<std::result::Result<NodeId, String>>,
     ) -> Result<()> {
-        Self::handle_connection(stream, addr, local_node_id, connections, event_sender, false).await
+        Self::handle_connection(stream, addr, context, false, Some(handshake_done)).await
     }
 
-    /// Handle a TCP connection (common logic for incoming/outgoing)
-    async fn handle_connection(
-        stream: TcpStream,
+    /// Swap protocol preambles with the peer and refuse it unless it speaks our protocol version.
+    /// Both sides write first, so neither waits on the other.
+    async fn exchange_preamble(stream: &mut TcpStream, addr: SocketAddr) -> Result<()> {
+        stream.write_all(&protocol_preamble()).await
+            .context("Failed to send protocol preamble")?;
+        let mut preamble = [0u8; PROTOCOL_PREAMBLE_LEN];
+        match tokio::time::timeout(PREAMBLE_TIMEOUT, stream.read_exact(&mut preamble)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => anyhow::bail!("Connection closed before protocol preamble: {}", e),
+            Err(_) => anyhow::bail!(
+                "{} sent no protocol preamble; it may run an older nhi build (this node speaks protocol {})",
+                addr, PROTOCOL_VERSION
+            ),
+        }
+        check_protocol_preamble(&preamble).map_err(|reason| {
+            warn!("Refusing peer {}: {}", addr, reason);
+            anyhow::anyhow!("Incompatible peer {}: {}", addr, reason)
+        })
+    }
+
+    /// Exchange discovery messages to learn the peer's node ID; the connecting side speaks first
+    async fn handshake(
+        framed: &mut Framed<TcpStream, MessageCodec>,
         addr: SocketAddr,
         local_node_id: NodeId,
-        connections: Arc<RwLock<HashMap<NodeId, PeerConnection>>>,
-        event_sender: mpsc::UnboundedSender<NetworkEvent>,
         is_incoming: bool,
-    ) -> Result<()> {
-        let mut framed = Framed::new(stream, MessageCodec);
-        let (message_sender, mut message_receiver) = mpsc::unbounded_channel();
-
-        // Perform handshake to exchange node IDs
+    ) -> Result<NodeId> {
         let peer_node_id = if is_incoming {
             // Wait for discovery message from peer
             match framed.next().await {
@@ -341,11 +559,43 @@ impl NetworkManager {
             }
         };
 
+        Ok(peer_node_id)
+    }
+
+    /// Handle a TCP connection (common logic for incoming/outgoing)
+    async fn handle_connection(
+        stream: TcpStream,
+        addr: SocketAddr,
+        context: ConnectionContext,
+        is_incoming: bool,
+        handshake_done: Option<oneshot::Sender<std::result::Result<NodeId, String>>>,
+    ) -> Result<()> {
+        let ConnectionContext { local_node_id, connections, event_sender, queue_capacity } = context;
+        let mut stream = stream;
+        let message_queue = OutboundQueue::new(queue_capacity);
+
+        // Agree on the protocol version, then perform handshake to exchange node IDs
+        let handshake = async {
+            Self::exchange_preamble(&mut stream, addr).await?;
+            let mut framed = Framed::new(stream, MessageCodec);
+            let peer_node_id = Self::handshake(&mut framed, addr, local_node_id, is_incoming).await?;
+            Ok::<_, anyhow::Error>((framed, peer_node_id))
+        };
+        let (framed, peer_node_id) = match handshake.await {
+            Ok(connected) => connected,
+            Err(e) => {
+                if let Some(handshake_done) = handshake_done {
+                    let _ = handshake_done.send(Err(e.to_string()));
+                }
+                return Err(e);
+            }
+        };
+
         // Register the connection
         let connection = PeerConnection {
             node_id: peer_node_id,
             addr,
-            sender: message_sender,
+            sender: message_queue.clone(),
             connected_at: chrono::Utc::now(),
         };
 
@@ -355,13 +605,17 @@ impl NetworkManager {
         }
 
         let _ = event_sender.send(NetworkEvent::PeerConnected(peer_node_id, addr));
+        if let Some(handshake_done) = handshake_done {
+            let _ = handshake_done.send(Ok(peer_node_id));
+        }
 
         // Split the framed stream for sending and receiving
         let (mut sink, mut stream) = framed.split();
 
         // Handle message sending
+        let send_queue = message_queue.clone();
         let send_task = tokio::spawn(async move {
-            while let Some(message) = message_receiver.recv().await {
+            while let Some(message) = send_queue.recv().await {
                 if let Err(e) = sink.send(message).await {
                     error!("Failed to send message: {}", e);
                     break;
@@ -402,7 +656,120 @@ impl NetworkManager {
             _ = send_task => {},
             _ = receive_task => {},
         }
+        message_queue.close();
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listening_manager() -> (NetworkManager, SocketAddr) {
+        let (listener, addr) = NetworkManager::pre_bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let config = NetworkConfig { listen_addr: addr, ..NetworkConfig::default() };
+        (NetworkManager::new_with_listener(config, uuid::Uuid::new_v4(), listener), addr)
+    }
+
+    #[tokio::test]
+    async fn connect_returns_the_peer_node_id_after_the_handshake() {
+        let (server, server_addr) = listening_manager();
+        server.start_listening().await.unwrap();
+        let (client, _) = listening_manager();
+
+        let peer = client.connect_to_peer(server_addr).await.unwrap();
+        assert_eq!(peer, server.node_id);
+        assert!(client.connections.read().await.contains_key(&peer));
+    }
+
+    #[tokio::test]
+    async fn failed_handshake_is_reported_and_leaves_no_peer() {
+        // A peer that reads our discovery and hangs up without answering
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(&protocol_preamble()).await.unwrap();
+            let mut preamble = [0u8; PROTOCOL_PREAMBLE_LEN];
+            stream.read_exact(&mut preamble).await.unwrap();
+            let mut framed = Framed::new(stream, MessageCodec);
+            assert!(matches!(framed.next().await, Some(Ok(NetworkMessage::Discovery(_)))));
+        });
+        let (client, _) = listening_manager();
+
+        let error = client.connect_to_peer(peer_addr).await.unwrap_err().to_string();
+        assert!(error.contains("Handshake"), "{}", error);
+        assert!(client.get_connected_peers().await.is_empty());
+        // Cluster state follows these events; none may announce the peer
+        let mut events = client.event_receiver.lock().await;
+        while let Ok(event) = events.try_recv() {
+            assert!(!matches!(event, NetworkEvent::PeerConnected(..)), "{:?}", event);
+        }
+    }
+
+    #[tokio::test]
+    async fn peers_on_another_protocol_version_are_refused() {
+        let (server, server_addr) = listening_manager();
+        server.start_listening().await.unwrap();
+
+        // A newer peer announcing a different version
+        let mut newer = TcpStream::connect(server_addr).await.unwrap();
+        let mut preamble = protocol_preamble();
+        preamble[4..].copy_from_slice(&(PROTOCOL_VERSION + 1).to_be_bytes());
+        newer.write_all(&preamble).await.unwrap();
+        // An older peer that opens with a framed discovery message and no preamble
+        let older = TcpStream::connect(server_addr).await.unwrap();
+        let mut framed = Framed::new(older, MessageCodec);
+        let node_id = uuid::Uuid::new_v4();
+        framed.send(NetworkMessage::Discovery(DiscoveryMessage {
+            node_id,
+            node_info: NodeInfo::new(node_id, "old".to_string(), server_addr),
+            cluster_nodes: vec![],
+        })).await.unwrap();
+
+        // The server answers with its own preamble and hangs up on both
+        for mut stream in [newer, framed.into_inner()] {
+            let mut received = [0u8; PROTOCOL_PREAMBLE_LEN];
+            stream.read_exact(&mut received).await.unwrap();
+            assert_eq!(received, protocol_preamble());
+            // Closed, or reset because our unread bytes were discarded
+            assert!(matches!(stream.read(&mut [0u8; 1]).await, Ok(0) | Err(_)));
+        }
+        assert!(server.get_connected_peers().await.is_empty());
+
+        let mut preamble = protocol_preamble();
+        assert!(check_protocol_preamble(&preamble).is_ok());
+        preamble[4..].copy_from_slice(&(PROTOCOL_VERSION + 1).to_be_bytes());
+        let reason = check_protocol_preamble(&preamble).unwrap_err();
+        assert!(reason.contains(&format!("protocol {}", PROTOCOL_VERSION + 1)), "{}", reason);
+        let reason = check_protocol_preamble(&[0, 0, 0, 42, 0, 0, 0, 0]).unwrap_err();
+        assert!(reason.contains("older nhi build"), "{}", reason);
+    }
+
+    #[tokio::test]
+    async fn only_output_data_streams_are_droppable() {
+        let stream = |stream_type| NetworkMessage::DataStream(DataStreamMessage {
+            sender_id: uuid::Uuid::new_v4(),
+            instance_id: uuid::Uuid::new_v4(),
+            stream_type,
+            data: vec![0; 16],
+            sequence_number: 1,
+            timestamp: chrono::Utc::now(),
+        });
+        assert!(stream(StreamType::Stdout).is_best_effort());
+        assert!(stream(StreamType::Stderr).is_best_effort());
+        assert!(!stream(StreamType::Checkpoint).is_best_effort());
+        assert!(!stream(StreamType::Memory).is_best_effort());
+        assert!(!stream(StreamType::Stdin).is_best_effort());
+
+        // A full queue evicts output but keeps the checkpoint data
+        let queue = OutboundQueue::new(2);
+        queue.send(stream(StreamType::Checkpoint)).await.unwrap();
+        queue.send(stream(StreamType::Stdout)).await.unwrap();
+        queue.send(stream(StreamType::Stderr)).await.unwrap();
+        assert_eq!((queue.len(), queue.dropped()), (2, 1));
+        let first = queue.recv().await.unwrap();
+        assert!(matches!(first, NetworkMessage::DataStream(ref d) if matches!(d.stream_type, StreamType::Checkpoint)));
+    }
+}
@@ -1,11 +1,11 @@
 use crate::message_protocol::*;
 use anyhow::{Result, Context};
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 use tokio::net::UdpSocket;
 use tokio::sync::{mpsc, RwLock};
-use tokio::time::{interval, Duration};
+use tokio::time::{interval, Duration, Instant};
 use tracing::{debug, error, info, warn};
 
 
@@ -14,8 +14,8 @@ use tracing::{debug, error, info, warn};
 pub enum DiscoveryEvent {
     /// New node discovered
     NodeDiscovered(NodeInfo),
-    /// Node announcement received
-    NodeAnnouncement(NodeInfo),
+    /// Node at this address was not heard from within the discovery TTL
+    NodeExpired(SocketAddr),
     /// Discovery error
     DiscoveryError(String),
 }
@@ -24,7 +24,7 @@ pub enum DiscoveryEvent {
 pub struct NodeDiscovery {
     config: NetworkConfig,
     local_node_info: NodeInfo,
-    discovered_nodes: Arc<RwLock<HashSet<SocketAddr>>>,
+    discovered_nodes: Arc<RwLock<HashMap<SocketAddr, Instant>>>, // Last time each node was heard from
     event_sender: mpsc::UnboundedSender<DiscoveryEvent>,
     event_receiver: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<DiscoveryEvent>>>,
 }
@@ -54,7 +54,7 @@ impl NodeDiscovery {
         Self {
             config,
             local_node_info,
-            discovered_nodes: Arc::new(RwLock::new(HashSet::new())),
+            discovered_nodes: Arc::new(RwLock::new(HashMap::new())),
             event_sender,
             event_receiver: Arc::new(tokio::sync::Mutex::new(event_receiver)),
         }
@@ -62,7 +62,14 @@ impl NodeDiscovery {
 
     /// Start the discovery service
     pub async fn start(&self) -> Result<()> {
-        info!("Starting node discovery on port {}", self.config.discovery_port);
+        info!(
+            "Starting node discovery on port {} (group {}, every {}s, TTL {}s)",
+            self.config.discovery_port, self.config.discovery_group,
+            self.config.discovery_interval_secs, self.config.discovery_ttl_secs
+        );
+        if self.config.discovery_ttl_secs <= self.config.discovery_interval_secs {
+            warn!("Discovery TTL is not longer than the announcement interval; live nodes may be expired between announcements");
+        }
 
         // Start UDP listener for discovery messages
         self.start_discovery_listener().await?;
@@ -70,6 +77,9 @@ impl NodeDiscovery {
         // Start periodic announcement
         self.start_periodic_announcement().await;
 
+        // Drop nodes that stopped announcing
+        self.start_expiry().await;
+
         // Start initial network probe
         self.start_network_probe().await;
 
@@ -82,12 +92,6 @@ impl NodeDiscovery {
         receiver.recv().await
     }
 
-    /// Get list of discovered nodes
-    pub async fn get_discovered_nodes(&self) -> Vec<SocketAddr> {
-        let nodes = self.discovered_nodes.read().await;
-        nodes.iter().cloned().collect()
-    }
-
     /// Manually probe for nodes on the network
     pub async fn probe_network(&self) -> Result<()> {
         info!("Probing network for NHI nodes");
@@ -101,9 +105,9 @@ impl NodeDiscovery {
         let data = bincode::serialize(&probe_packet)
             .context("Failed to serialize probe packet")?;
 
-        // Send probe to broadcast address
+        // Send probe to the discovery group
         let broadcast_addr = SocketAddr::new(
-            IpAddr::V4(Ipv4Addr::BROADCAST),
+            IpAddr::V4(self.config.discovery_group),
             self.config.discovery_port
         );
 
@@ -157,6 +161,13 @@ impl NodeDiscovery {
         let socket = UdpSocket::bind(bind_addr).await
             .context("Failed to bind discovery UDP socket")?;
 
+        let group = self.config.discovery_group;
+        if group.is_multicast() {
+            socket.join_multicast_v4(group, Ipv4Addr::UNSPECIFIED)
+                .with_context(|| format!("Failed to join discovery multicast group {}", group))?;
+            info!("Joined discovery multicast group {}", group);
+        }
+
         info!("Discovery listener bound to {}", bind_addr);
 
         let event_sender = self.event_sender.clone();
@@ -195,14 +206,16 @@ impl NodeDiscovery {
         let event_sender = self.event_sender.clone();
         let local_node_info = self.local_node_info.clone();
         let discovery_port = self.config.discovery_port;
+        let group = self.config.discovery_group;
+        let period = Duration::from_secs(self.config.discovery_interval_secs.max(1));
 
         tokio::spawn(async move {
-            let mut interval = interval(Duration::from_secs(10)); // Announce every 10 seconds
+            let mut interval = interval(period);
 
             loop {
                 interval.tick().await;
 
-                if let Err(e) = Self::send_announcement(&local_node_info, discovery_port).await {
+                if let Err(e) = Self::send_announcement(&local_node_info, discovery_port, group).await {
                     let _ = event_sender.send(DiscoveryEvent::DiscoveryError(
                         format!("Failed to send announcement: {}", e)
                     ));
@@ -211,6 +224,41 @@ impl NodeDiscovery {
         });
     }
 
+    /// Periodically remove nodes not heard from within the TTL, so a node that comes back
+    /// is reported (and connected to) again
+    async fn start_expiry(&self) {
+        let event_sender = self.event_sender.clone();
+        let discovered_nodes = self.discovered_nodes.clone();
+        let ttl = Duration::from_secs(self.config.discovery_ttl_secs);
+        let period = Duration::from_secs(self.config.discovery_interval_secs.max(1));
+
+        tokio::spawn(async move {
+            let mut interval = interval(period);
+
+            loop {
+                interval.tick().await;
+
+                let mut nodes = discovered_nodes.write().await;
+                let expired: Vec<SocketAddr> = nodes
+                    .iter()
+                    .filter(|(_, last_seen)| last_seen.elapsed() > ttl)
+                    .map(|(addr, _)| *addr)
+                    .collect();
+                for addr in expired {
+                    nodes.remove(&addr);
+                    info!("Discovered node at {} expired after {}s without announcements", addr, ttl.as_secs());
+                    let _ = event_sender.send(DiscoveryEvent::NodeExpired(addr));
+                }
+            }
+        });
+    }
+
+    /// Note that a node was heard from; true if it was not already known
+    async fn record_node(discovered_nodes: &Arc<RwLock<HashMap<SocketAddr, Instant>>>, addr: SocketAddr) -> bool {
+        let mut nodes = discovered_nodes.write().await;
+        nodes.insert(addr, Instant::now()).is_none()
+    }
+
     /// Start initial network probe
     async fn start_network_probe(&self) {
         let discovery = self.clone();
@@ -230,7 +278,7 @@ impl NodeDiscovery {
         data: &[u8],
         sender_addr: SocketAddr,
         local_node_info: &NodeInfo,
-        discovered_nodes: &Arc<RwLock<HashSet<SocketAddr>>>,
+        discovered_nodes: &Arc<RwLock<HashMap<SocketAddr, Instant>>>,
         event_sender: &mpsc::UnboundedSender<DiscoveryEvent>,
         socket: &UdpSocket,
     ) -> Result<()> {
@@ -249,12 +297,9 @@ impl NodeDiscovery {
                 // Node is announcing its presence
                 let tcp_addr = packet.node_info.listen_addr;
 
-                {
-                    let mut nodes = discovered_nodes.write().await;
-                    if nodes.insert(tcp_addr) {
-                        info!("Discovered new node: {} at {}", packet.node_info.node_id, tcp_addr);
-                        let _ = event_sender.send(DiscoveryEvent::NodeDiscovered(packet.node_info));
-                    }
+                if Self::record_node(discovered_nodes, tcp_addr).await {
+                    info!("Discovered new node: {} at {}", packet.node_info.node_id, tcp_addr);
+                    let _ = event_sender.send(DiscoveryEvent::NodeDiscovered(packet.node_info));
                 }
             }
             DiscoveryMessageType::Probe => {
@@ -275,12 +320,9 @@ impl NodeDiscovery {
                 // Response to our probe
                 let tcp_addr = packet.node_info.listen_addr;
 
-                {
-                    let mut nodes = discovered_nodes.write().await;
-                    if nodes.insert(tcp_addr) {
-                        info!("Discovered node via probe response: {} at {}", packet.node_info.node_id, tcp_addr);
-                        let _ = event_sender.send(DiscoveryEvent::NodeDiscovered(packet.node_info));
-                    }
+                if Self::record_node(discovered_nodes, tcp_addr).await {
+                    info!("Discovered node via probe response: {} at {}", packet.node_info.node_id, tcp_addr);
+                    let _ = event_sender.send(DiscoveryEvent::NodeDiscovered(packet.node_info));
                 }
             }
         }
@@ -289,7 +331,7 @@ impl NodeDiscovery {
     }
 
     /// Send announcement packet
-    async fn send_announcement(node_info: &NodeInfo, discovery_port: u16) -> Result<()> {
+    async fn send_announcement(node_info: &NodeInfo, discovery_port: u16, group: Ipv4Addr) -> Result<()> {
         let announcement_packet = DiscoveryPacket {
             message_type: DiscoveryMessageType::Announce,
             node_info: node_info.clone(),
@@ -305,9 +347,9 @@ impl NodeDiscovery {
         socket.set_broadcast(true)
             .context("Failed to enable broadcast on UDP socket")?;
 
-        // Send to broadcast address
+        // Send to the discovery group
         let broadcast_addr = SocketAddr::new(
-            IpAddr::V4(Ipv4Addr::BROADCAST),
+            IpAddr::V4(group),
             discovery_port
         );
 
@@ -326,7 +368,7 @@ impl NodeDiscovery {
             }
         }
 
-        debug!("Sent node announcement to broadcast and localhost ports");
+        debug!("Sent node announcement to {} and localhost ports", group);
         Ok(())
     }
 }
@@ -343,3 +385,67 @@ impl Clone for NodeDiscovery {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn discovery(discovery_port: u16, interval_secs: u64, ttl_secs: u64) -> NodeDiscovery {
+        let config = NetworkConfig {
+            discovery_port,
+            discovery_group: Ipv4Addr::LOCALHOST,
+            discovery_interval_secs: interval_secs,
+            discovery_ttl_secs: ttl_secs,
+            ..NetworkConfig::default()
+        };
+        let node_info = NodeInfo::new(uuid::Uuid::new_v4(), "discovery-test".to_string(), "127.0.0.1:1".parse().unwrap());
+        NodeDiscovery::new(config, node_info)
+    }
+
+    #[tokio::test]
+    async fn announcements_go_out_at_the_configured_interval() {
+        // Stands in for the discovery group
+        let group = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let discovery = discovery(group.local_addr().unwrap().port(), 1, 3);
+        discovery.start_periodic_announcement().await;
+
+        let mut arrivals = Vec::new();
+        let mut buffer = [0u8; 4096];
+        while arrivals.len() < 3 {
+            let (len, _) = tokio::time::timeout(Duration::from_secs(3), group.recv_from(&mut buffer)).await.unwrap().unwrap();
+            let packet: DiscoveryPacket = bincode::deserialize(&buffer[..len]).unwrap();
+            assert!(matches!(packet.message_type, DiscoveryMessageType::Announce));
+            assert_eq!(packet.node_info.node_id, discovery.local_node_info.node_id);
+            arrivals.push(Instant::now());
+        }
+        for pair in arrivals.windows(2) {
+            let gap = pair[1] - pair[0];
+            assert!(gap >= Duration::from_millis(800) && gap <= Duration::from_millis(1500), "announced {:?} apart", gap);
+        }
+    }
+
+    #[tokio::test]
+    async fn nodes_not_heard_from_within_the_ttl_expire() {
+        let discovery = discovery(0, 1, 5);
+        let (stale, fresh): (SocketAddr, SocketAddr) = ("127.0.0.1:7001".parse().unwrap(), "127.0.0.1:7002".parse().unwrap());
+        {
+            let mut nodes = discovery.discovered_nodes.write().await;
+            nodes.insert(stale, Instant::now() - Duration::from_secs(10));
+            nodes.insert(fresh, Instant::now());
+        }
+        discovery.start_expiry().await;
+
+        match tokio::time::timeout(Duration::from_secs(3), discovery.next_event()).await.unwrap() {
+            Some(DiscoveryEvent::NodeExpired(addr)) => assert_eq!(addr, stale),
+            other => panic!("expected the stale node to expire, got {:?}", other),
+        }
+        let nodes = discovery.discovered_nodes.read().await;
+        assert!(!nodes.contains_key(&stale));
+        assert!(nodes.contains_key(&fresh));
+        drop(nodes);
+
+        // Heard from again, the node is reported as new
+        assert!(NodeDiscovery::record_node(&discovery.discovered_nodes, stale).await);
+        assert!(!NodeDiscovery::record_node(&discovery.discovered_nodes, fresh).await);
+    }
+}
@@ -1,41 +1,110 @@
-use crate::cluster_state::{ClusterEvent, ClusterStateManager};
+use crate::audit::AuditLog;
+use crate::cluster_state::{ClusterEvent, ClusterStateManager, PeerSample};
+use crate::distributed_registry::DistributedInstanceRegistry;
 use crate::message_protocol::*;
 use crate::migration_manager::MigrationManager;
 use crate::network_manager::{NetworkEvent, NetworkManager};
 use crate::node_discovery::{DiscoveryEvent, NodeDiscovery};
 use crate::shadow_instance_manager::ShadowInstanceManager;
 use anyhow::{Result, Context};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{oneshot, Mutex, RwLock};
 use tokio::time::{interval, Duration};
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+/// Peers added with `cluster connect --persist`, redialed at startup
+pub const PEERS_FILE: &str = "instances/peers.json";
+
+/// How often persisted peers that are not connected are redialed
+const PERSISTED_PEER_RETRY: Duration = Duration::from_secs(30);
+
+/// A peer address kept across restarts
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PersistedPeer {
+    pub address: String,              // As given to `cluster connect`, resolved again on each dial
+    #[serde(default)]
+    pub resolved: Option<SocketAddr>, // Address the last successful dial used
+    #[serde(default)]
+    pub node_id: Option<NodeId>,      // Node last seen at that address; changes when the peer restarts
+}
+
+/// Read the persisted peer list; a missing or unreadable file means no peers
+pub fn load_persisted_peers() -> Vec<PersistedPeer> {
+    let json = match std::fs::read_to_string(PEERS_FILE) {
+        Ok(json) => json,
+        Err(_) => return Vec::new(),
+    };
+    serde_json::from_str(&json).unwrap_or_else(|e| {
+        warn!("Ignoring unreadable {}: {}", PEERS_FILE, e);
+        Vec::new()
+    })
+}
+
+fn save_persisted_peers(peers: &[PersistedPeer]) -> Result<()> {
+    let path = std::path::Path::new(PEERS_FILE);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(peers)?)
+        .with_context(|| format!("Failed to write {}", PEERS_FILE))
+}
+
 /// High-level node manager that coordinates networking, discovery, and cluster state
 pub struct NodeManager {
     network_manager: Arc<NetworkManager>,
     discovery_service: Arc<NodeDiscovery>,
     cluster_state: Arc<ClusterStateManager>,
+    instance_registry: Arc<DistributedInstanceRegistry>,
     local_node_info: NodeInfo,
+    discovery_enabled: bool,
     is_running: Arc<Mutex<bool>>,
     shadow_manager: Arc<Mutex<Option<Arc<RwLock<ShadowInstanceManager>>>>>,
     migration_manager: Arc<Mutex<Option<Arc<MigrationManager>>>>,
+    pending_requests: PendingRequests,
+    audit: AuditLog,
 }
 
+/// Requests awaiting a response, keyed by request ID
+type PendingRequests = Arc<Mutex<HashMap<Uuid, oneshot::Sender<ResponseMessage>>>>;
+
 impl NodeManager {
     pub fn new(config: NetworkConfig) -> Result<Self> {
+        Self::new_with_capabilities(config, None)
+    }
+
+    /// Create a node manager advertising the given capabilities (defaults when None)
+    pub fn new_with_capabilities(mut config: NetworkConfig, capabilities: Option<Vec<String>>) -> Result<Self> {
         let node_id = Uuid::new_v4();
 
+        // For an ephemeral port, bind now so the real port is known before it is announced
+        let pre_bound_listener = if config.listen_addr.port() == 0 {
+            let (listener, bound_addr) = NetworkManager::pre_bind(config.listen_addr)?;
+            info!("Requested ephemeral port, bound to {}", bound_addr);
+            config.listen_addr.set_port(bound_addr.port());
+            Some(listener)
+        } else {
+            None
+        };
+
         // Create local node info
-        let local_node_info = NodeInfo::new(
+        let mut local_node_info = NodeInfo::new(
             node_id,
             config.node_name.clone(),
             config.listen_addr,
         );
+        if let Some(capabilities) = capabilities {
+            local_node_info.capabilities = capabilities;
+        }
 
         // Initialize components
-        let network_manager = Arc::new(NetworkManager::new(config.clone(), local_node_info.node_id));
+        let network_manager = Arc::new(match pre_bound_listener {
+            Some(listener) => NetworkManager::new_with_listener(config.clone(), local_node_info.node_id, listener),
+            None => NetworkManager::new(config.clone(), local_node_info.node_id),
+        });
         let discovery_service = Arc::new(NodeDiscovery::new(config.clone(), local_node_info.clone()));
         let cluster_state = Arc::new(ClusterStateManager::new(node_id));
 
@@ -43,13 +112,22 @@ impl NodeManager {
             network_manager,
             discovery_service,
             cluster_state,
+            instance_registry: Arc::new(DistributedInstanceRegistry::new()),
             local_node_info,
+            discovery_enabled: config.discovery_enabled,
             is_running: Arc::new(Mutex::new(false)),
             shadow_manager: Arc::new(Mutex::new(None)),
             migration_manager: Arc::new(Mutex::new(None)),
+            pending_requests: Arc::new(Mutex::new(HashMap::new())),
+            audit: AuditLog::default(),
         })
     }
 
+    /// Record peer connections and disconnections in this audit log
+    pub fn set_audit_log(&mut self, audit: AuditLog) {
+        self.audit = audit;
+    }
+
     /// Start the node manager and all its services
     pub async fn start(&self) -> Result<()> {
         {
@@ -73,8 +151,12 @@ impl NodeManager {
         self.network_manager.start_broadcast_handler().await;
 
         // Start discovery service
-        self.discovery_service.start().await
-            .context("Failed to start discovery service")?;
+        if self.discovery_enabled {
+            self.discovery_service.start().await
+                .context("Failed to start discovery service")?;
+        } else {
+            info!("UDP discovery disabled, peers must be connected explicitly");
+        }
 
         // Start event processing loops
         self.start_event_loops().await;
@@ -120,6 +202,11 @@ impl NodeManager {
         &self.cluster_state
     }
 
+    /// Get the cluster-wide record of where instances run and which nodes shadow them
+    pub fn instance_registry(&self) -> &Arc<DistributedInstanceRegistry> {
+        &self.instance_registry
+    }
+
     /// Get network manager
     pub fn network_manager(&self) -> &Arc<NetworkManager> {
         &self.network_manager
@@ -137,12 +224,100 @@ impl NodeManager {
         *mgr = Some(migration_manager);
     }
 
-    /// Connect to a specific peer
-    pub async fn connect_to_peer(&self, addr: SocketAddr) -> Result<()> {
+    /// Connect to a specific peer, returning its node ID once the handshake completed
+    pub async fn connect_to_peer(&self, addr: SocketAddr) -> Result<NodeId> {
         info!("Attempting to connect to peer at {}", addr);
         self.network_manager.connect_to_peer(addr).await
     }
 
+    /// Connect to a peer given as `ip:port` or `hostname:port`, returning the address that
+    /// succeeded and the node ID learned in the handshake
+    pub async fn connect_to_address(&self, address: &str) -> Result<(SocketAddr, NodeId)> {
+        Self::dial(&self.network_manager, address).await
+    }
+
+    /// Remember a connected address so it is redialed after a restart
+    pub async fn persist_peer(&self, address: &str, resolved: SocketAddr, node_id: NodeId) -> Result<()> {
+        let mut peers = load_persisted_peers();
+        match peers.iter_mut().find(|peer| peer.address == address) {
+            Some(peer) => {
+                peer.resolved = Some(resolved);
+                peer.node_id = Some(node_id);
+            }
+            None => peers.push(PersistedPeer {
+                address: address.to_string(),
+                resolved: Some(resolved),
+                node_id: Some(node_id),
+            }),
+        }
+        save_persisted_peers(&peers)
+    }
+
+    /// Drop persisted peers matching a node ID or an address as given to `cluster connect`,
+    /// returning the removed entries
+    pub async fn forget_peer(&self, target: &str) -> Result<Vec<PersistedPeer>> {
+        let node_id = Uuid::parse_str(target).ok();
+        // The node may be known only by the address it is connected from
+        let connected_addr = match node_id {
+            Some(node_id) => self.network_manager.get_connected_peers().await
+                .into_iter()
+                .find(|(id, _)| *id == node_id)
+                .map(|(_, addr)| addr),
+            None => None,
+        };
+
+        let (removed, kept): (Vec<PersistedPeer>, Vec<PersistedPeer>) = load_persisted_peers()
+            .into_iter()
+            .partition(|peer| {
+                peer.address == target
+                    || (node_id.is_some() && peer.node_id == node_id)
+                    || (connected_addr.is_some() && peer.resolved == connected_addr)
+            });
+
+        if removed.is_empty() {
+            anyhow::bail!("No persisted peer matches {}", target);
+        }
+        save_persisted_peers(&kept)?;
+        Ok(removed)
+    }
+
+    /// Resolve `address` and connect to the first address that accepts
+    async fn dial(network_manager: &Arc<NetworkManager>, address: &str) -> Result<(SocketAddr, NodeId)> {
+        // Fast path for literal socket addresses
+        if let Ok(addr) = address.parse::<SocketAddr>() {
+            info!("Attempting to connect to peer at {}", addr);
+            let node_id = network_manager.connect_to_peer(addr).await?;
+            return Ok((addr, node_id));
+        }
+
+        let resolved: Vec<SocketAddr> = tokio::net::lookup_host(address).await
+            .with_context(|| format!("Failed to resolve {}", address))?
+            .collect();
+
+        if resolved.is_empty() {
+            anyhow::bail!("{} did not resolve to any address", address);
+        }
+
+        info!("Resolved {} to {:?}", address, resolved);
+
+        let mut last_error = None;
+        for addr in resolved {
+            info!("Attempting to connect to peer at {}", addr);
+            match network_manager.connect_to_peer(addr).await {
+                Ok(node_id) => {
+                    info!("Connected to {} via resolved address {}", address, addr);
+                    return Ok((addr, node_id));
+                }
+                Err(e) => {
+                    warn!("Failed to connect to {} ({}): {}", address, addr, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("Failed to connect to {}", address)))
+    }
+
     /// Disconnect from a peer
     pub async fn disconnect_peer(&self, node_id: &NodeId) -> Result<()> {
         info!("Disconnecting from peer {}", node_id);
@@ -159,6 +334,85 @@ impl NodeManager {
         self.network_manager.get_connected_peers().await
     }
 
+    /// Send an application-level ping to a peer and return the round-trip time
+    pub async fn ping_node(&self, node_id: &NodeId, timeout: Duration) -> Result<Duration> {
+        let request_id = Uuid::new_v4();
+        let nonce = Uuid::new_v4().as_u64_pair().0;
+        let (sender, receiver) = oneshot::channel();
+        self.pending_requests.lock().await.insert(request_id, sender);
+
+        let started = std::time::Instant::now();
+        let request = NetworkMessage::Request(RequestMessage {
+            request_id,
+            sender_id: self.node_id(),
+            request_type: RequestType::Ping { nonce, sent_at: chrono::Utc::now() },
+        });
+
+        if let Err(e) = self.network_manager.send_to_peer(node_id, request).await {
+            self.pending_requests.lock().await.remove(&request_id);
+            return Err(e);
+        }
+
+        let response = match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => anyhow::bail!("Ping to {} was cancelled", node_id),
+            Err(_) => {
+                self.pending_requests.lock().await.remove(&request_id);
+                anyhow::bail!("No pong from {} within {:?}", node_id, timeout);
+            }
+        };
+
+        match response.response_type {
+            ResponseType::Pong { nonce: echoed, .. } if echoed == nonce => Ok(started.elapsed()),
+            ResponseType::Pong { .. } => anyhow::bail!("Pong from {} carried the wrong nonce", node_id),
+            other => anyhow::bail!("Unexpected response to ping from {}: {:?}", node_id, other),
+        }
+    }
+
+    /// Every known peer with its connection and, for connected ones, a fresh RTT.
+    /// Peers are pinged concurrently, so this takes at most `ping_timeout`.
+    pub async fn sample_peers(&self, ping_timeout: Duration) -> Vec<PeerSample> {
+        let local_node_id = self.node_id();
+        let nodes = self.cluster_state.get_cluster_state().await.nodes;
+        let connected: HashMap<NodeId, SocketAddr> = self.get_connected_peers().await.into_iter().collect();
+
+        let mut samples: Vec<PeerSample> = nodes
+            .into_values()
+            .filter(|node| node.node_id != local_node_id)
+            .map(|node| PeerSample {
+                address: connected.get(&node.node_id).copied(),
+                node_id: node.node_id,
+                name: node.name,
+                status: node.status,
+                rtt: None,
+            })
+            .collect();
+        // A connection can come up before the peer's node info arrives
+        for (node_id, address) in &connected {
+            if !samples.iter().any(|sample| sample.node_id == *node_id) {
+                samples.push(PeerSample {
+                    node_id: *node_id,
+                    name: "unknown".to_string(),
+                    address: Some(*address),
+                    status: NodeStatus::Connecting,
+                    rtt: None,
+                });
+            }
+        }
+
+        let pings = samples.iter().map(|sample| async move {
+            match sample.address {
+                Some(_) => self.ping_node(&sample.node_id, ping_timeout).await.ok(),
+                None => None,
+            }
+        });
+        let rtts = futures::future::join_all(pings).await;
+        for (sample, rtt) in samples.iter_mut().zip(rtts) {
+            sample.rtt = rtt;
+        }
+        samples
+    }
+
     /// Get cluster information
     pub async fn get_cluster_info(&self) -> String {
         self.cluster_state.format_cluster_info().await
@@ -169,19 +423,27 @@ impl NodeManager {
         self.cluster_state.format_node_list().await
     }
 
+    /// Get the gossiped cluster topology
+    pub async fn get_topology(&self) -> String {
+        self.cluster_state.format_topology().await
+    }
+
     /// Start event processing loops
     async fn start_event_loops(&self) {
         // Network events loop
         let network_manager = self.network_manager.clone();
         let cluster_state = self.cluster_state.clone();
+        let instance_registry = self.instance_registry.clone();
         let shadow_manager = self.shadow_manager.clone();
         let migration_manager = self.migration_manager.clone();
+        let pending_requests = self.pending_requests.clone();
+        let audit = self.audit.clone();
         let is_running = self.is_running.clone();
 
         tokio::spawn(async move {
             while *is_running.lock().await {
                 if let Some(event) = network_manager.next_event().await {
-                    if let Err(e) = Self::handle_network_event(event, &cluster_state, &network_manager, &shadow_manager, &migration_manager).await {
+                    if let Err(e) = Self::handle_network_event(event, &cluster_state, &instance_registry, &network_manager, &shadow_manager, &migration_manager, &pending_requests, &audit).await {
                         error!("Error handling network event: {}", e);
                     }
                 }
@@ -207,11 +469,12 @@ impl NodeManager {
         // Cluster events loop
         let cluster_state = self.cluster_state.clone();
         let is_running = self.is_running.clone();
+        let shadow_manager = self.shadow_manager.clone();
 
         tokio::spawn(async move {
             while *is_running.lock().await {
                 if let Some(event) = cluster_state.next_event().await {
-                    Self::handle_cluster_event(event).await;
+                    Self::handle_cluster_event(event, &shadow_manager).await;
                 }
             }
         });
@@ -221,6 +484,7 @@ impl NodeManager {
     async fn start_periodic_tasks(&self) {
         // Heartbeat task
         let network_manager = self.network_manager.clone();
+        let cluster_state = self.cluster_state.clone();
         let local_node_id = self.node_id();
         let is_running = self.is_running.clone();
 
@@ -230,6 +494,7 @@ impl NodeManager {
             while *is_running.lock().await {
                 interval.tick().await;
 
+                let known_peers = Self::refresh_local_peer_links(&network_manager, &cluster_state).await;
                 let heartbeat = NetworkMessage::Heartbeat(HeartbeatMessage {
                     sender_id: local_node_id,
                     timestamp: chrono::Utc::now(),
@@ -238,7 +503,9 @@ impl NodeManager {
                         memory_usage: 0.0,
                         active_instances: 0,
                         network_connections: 0,
+                        disk_available: crate::stats::disk_available(std::path::Path::new(crate::instance::INSTANCES_DIR)),
                     },
+                    known_peers,
                 });
 
                 if let Err(e) = network_manager.broadcast(heartbeat).await {
@@ -285,6 +552,19 @@ impl NodeManager {
             }
         });
 
+        // Persisted peer task; the first tick fires at once, so saved peers are dialed at startup
+        let network_manager = self.network_manager.clone();
+        let is_running = self.is_running.clone();
+
+        tokio::spawn(async move {
+            let mut interval = interval(PERSISTED_PEER_RETRY);
+
+            while *is_running.lock().await {
+                interval.tick().await;
+                Self::redial_persisted_peers(&network_manager).await;
+            }
+        });
+
         // Cluster state sync task
         let network_manager = self.network_manager.clone();
         let cluster_state = self.cluster_state.clone();
@@ -306,22 +586,80 @@ impl NodeManager {
         });
     }
 
+    /// Dial every persisted peer that is not currently connected
+    async fn redial_persisted_peers(network_manager: &Arc<NetworkManager>) {
+        let peers = load_persisted_peers();
+        if peers.is_empty() {
+            return;
+        }
+
+        let connected = network_manager.get_connected_peers().await;
+        for peer in peers {
+            let is_connected = connected.iter()
+                .any(|(node_id, addr)| peer.node_id == Some(*node_id) || peer.resolved == Some(*addr));
+            if is_connected {
+                continue;
+            }
+            match Self::dial(network_manager, &peer.address).await {
+                Ok((addr, _)) => info!("Redialed persisted peer {} at {}", peer.address, addr),
+                Err(e) => debug!("Persisted peer {} unreachable: {}", peer.address, e),
+            }
+        }
+    }
+
+    /// Note which node answered at a persisted peer's address, so `cluster forget` can name it
+    fn remember_peer_node_id(addr: SocketAddr, node_id: NodeId) {
+        let mut peers = load_persisted_peers();
+        let mut changed = false;
+        for peer in peers.iter_mut().filter(|peer| peer.resolved == Some(addr)) {
+            if peer.node_id != Some(node_id) {
+                peer.node_id = Some(node_id);
+                changed = true;
+            }
+        }
+        if changed {
+            if let Err(e) = save_persisted_peers(&peers) {
+                warn!("Failed to update persisted peer {}: {}", addr, e);
+            }
+        }
+    }
+
+    /// Record the local node's current connections in the topology and return them for gossip
+    async fn refresh_local_peer_links(
+        network_manager: &Arc<NetworkManager>,
+        cluster_state: &Arc<ClusterStateManager>,
+    ) -> Vec<NodeId> {
+        let peers: Vec<NodeId> = network_manager.get_connected_peers().await
+            .into_iter()
+            .map(|(node_id, _)| node_id)
+            .collect();
+        cluster_state.update_peer_links(cluster_state.local_node_id(), peers.clone()).await;
+        peers
+    }
+
     /// Handle network events
+    #[allow(clippy::too_many_arguments)]
     async fn handle_network_event(
         event: NetworkEvent,
         cluster_state: &Arc<ClusterStateManager>,
+        instance_registry: &DistributedInstanceRegistry,
         network_manager: &Arc<NetworkManager>,
         shadow_manager: &Arc<Mutex<Option<Arc<RwLock<ShadowInstanceManager>>>>>,
         migration_manager: &Arc<Mutex<Option<Arc<MigrationManager>>>>,
+        pending_requests: &PendingRequests,
+        audit: &AuditLog,
     ) -> Result<()> {
         match event {
             NetworkEvent::PeerConnected(node_id, addr) => {
                 info!("Peer connected: {} at {}", node_id, addr);
+                audit.record("peer_connect", None, Some(&node_id.to_string()), None);
+                Self::remember_peer_node_id(addr, node_id);
 
                 // Update cluster state
                 cluster_state.update_node_status(&node_id, NodeStatus::Online).await?;
 
                 // Send immediate heartbeat to establish connection faster
+                let known_peers = Self::refresh_local_peer_links(network_manager, cluster_state).await;
                 let heartbeat = HeartbeatMessage {
                     sender_id: cluster_state.local_node_id(),
                     timestamp: chrono::Utc::now(),
@@ -330,7 +668,9 @@ impl NodeManager {
                         memory_usage: 0.0,
                         active_instances: 0,
                         network_connections: 1,
+                        disk_available: crate::stats::disk_available(std::path::Path::new(crate::instance::INSTANCES_DIR)),
                     },
+                    known_peers,
                 };
 
                 let heartbeat_msg = NetworkMessage::Heartbeat(heartbeat);
@@ -340,13 +680,15 @@ impl NodeManager {
             }
             NetworkEvent::PeerDisconnected(node_id, reason) => {
                 info!("Peer disconnected: {} ({})", node_id, reason);
+                audit.record("peer_disconnect", None, Some(&node_id.to_string()), None);
 
                 // Update cluster state to offline but don't remove immediately
                 // Let the timeout mechanism handle removal after grace period
                 cluster_state.update_node_status(&node_id, NodeStatus::Offline).await?;
+                Self::refresh_local_peer_links(network_manager, cluster_state).await;
             }
             NetworkEvent::MessageReceived(sender_id, message) => {
-                Self::handle_network_message(sender_id, message, cluster_state, network_manager, shadow_manager, migration_manager).await?;
+                Self::handle_network_message(sender_id, message, cluster_state, instance_registry, network_manager, shadow_manager, migration_manager, pending_requests).await?;
             }
             NetworkEvent::ConnectionError(addr, error) => {
                 warn!("Connection error to {}: {}", addr, error);
@@ -377,9 +719,8 @@ impl NodeManager {
                     warn!("Failed to connect to discovered node {}: {}", node_info.node_id, e);
                 }
             }
-            DiscoveryEvent::NodeAnnouncement(node_info) => {
-                debug!("Node announcement from: {}", node_info.node_id);
-                cluster_state.add_node(node_info).await?;
+            DiscoveryEvent::NodeExpired(addr) => {
+                debug!("Discovered node at {} expired", addr);
             }
             DiscoveryEvent::DiscoveryError(error) => {
                 warn!("Discovery error: {}", error);
@@ -390,10 +731,20 @@ impl NodeManager {
     }
 
     /// Handle cluster events
-    async fn handle_cluster_event(event: ClusterEvent) {
+    async fn handle_cluster_event(
+        event: ClusterEvent,
+        shadow_manager: &Arc<Mutex<Option<Arc<RwLock<ShadowInstanceManager>>>>>,
+    ) {
         match event {
             ClusterEvent::NodeJoined(node_info) => {
                 info!("Node joined cluster: {} ({})", node_info.node_id, node_info.name);
+                // Either side may have missed the other's creation broadcasts; exchange full snapshots
+                if let Some(shadow_mgr) = shadow_manager.lock().await.as_ref() {
+                    let shadow_mgr_read = shadow_mgr.read().await;
+                    if let Err(e) = shadow_mgr_read.request_instance_sync().await {
+                        warn!("Failed to request instance sync after {} joined: {}", node_info.node_id, e);
+                    }
+                }
             }
             ClusterEvent::NodeLeft(node_id, reason) => {
                 info!("Node left cluster: {} ({})", node_id, reason);
@@ -411,13 +762,16 @@ impl NodeManager {
     }
 
     /// Handle incoming network messages
+    #[allow(clippy::too_many_arguments)]
     async fn handle_network_message(
         sender_id: NodeId,
         message: NetworkMessage,
         cluster_state: &Arc<ClusterStateManager>,
+        instance_registry: &DistributedInstanceRegistry,
         network_manager: &Arc<NetworkManager>,
         shadow_manager: &Arc<Mutex<Option<Arc<RwLock<ShadowInstanceManager>>>>>,
         migration_manager: &Arc<Mutex<Option<Arc<MigrationManager>>>>,
+        pending_requests: &PendingRequests,
     ) -> Result<()> {
         match message {
             NetworkMessage::Discovery(discovery) => {
@@ -438,13 +792,18 @@ impl NodeManager {
             }
             NetworkMessage::Response(response) => {
                 debug!("Received response from {}: {:?}", sender_id, response.response_type);
+                if let Some(waiter) = pending_requests.lock().await.remove(&response.request_id) {
+                    let _ = waiter.send(response);
+                }
             }
             NetworkMessage::Heartbeat(heartbeat) => {
                 debug!("Received heartbeat from {}", sender_id);
+                cluster_state.update_peer_links(sender_id, heartbeat.known_peers).await;
                 // Update node's last seen time and status
                 if let Some(mut node_info) = cluster_state.get_node_info(&sender_id).await {
                     node_info.update_last_seen();
                     node_info.set_status(NodeStatus::Online);
+                    node_info.disk_available = heartbeat.load_info.disk_available;
                     cluster_state.add_node(node_info).await?;
                     debug!("Updated heartbeat for node {}", sender_id);
                 } else {
@@ -472,6 +831,7 @@ impl NodeManager {
             }
             NetworkMessage::InstanceSync(instance_sync) => {
                 debug!("Received instance sync from {}", sender_id);
+                instance_registry.apply_instance_sync(&instance_sync, chrono::Utc::now()).await;
                 // Forward to shadow manager if available
                 if let Some(shadow_mgr) = shadow_manager.lock().await.as_ref() {
                     let shadow_mgr_read = shadow_mgr.read().await;
@@ -480,8 +840,25 @@ impl NodeManager {
                     }
                 }
             }
+            NetworkMessage::RequestInstanceSync(request) => {
+                debug!("Received instance sync request from {}", request.sender_id);
+                if let Some(shadow_mgr) = shadow_manager.lock().await.as_ref() {
+                    let shadow_mgr_read = shadow_mgr.read().await;
+                    if let Err(e) = shadow_mgr_read.advertise_running_instances().await {
+                        error!("Failed to answer instance sync request: {}", e);
+                    }
+                }
+            }
+            NetworkMessage::InstanceSyncAck(ack) => {
+                debug!("Received instance sync ack from {} for sync {}", sender_id, ack.sync_id);
+                if let Some(shadow_mgr) = shadow_manager.lock().await.as_ref() {
+                    let shadow_mgr_read = shadow_mgr.read().await;
+                    shadow_mgr_read.handle_instance_sync_ack(ack).await;
+                }
+            }
             NetworkMessage::InstanceStop(instance_stop) => {
                 debug!("Received instance stop from {} for instance {}", sender_id, instance_stop.instance_id);
+                instance_registry.remove_instance(instance_stop.instance_id).await;
                 // Forward to shadow manager if available
                 if let Some(shadow_mgr) = shadow_manager.lock().await.as_ref() {
                     let shadow_mgr_read = shadow_mgr.read().await;
@@ -492,6 +869,9 @@ impl NodeManager {
             }
             NetworkMessage::ShadowSync(shadow_sync) => {
                 debug!("Received shadow sync from {}", sender_id);
+                if !shadow_sync.is_migration {
+                    instance_registry.record_running(shadow_sync.instance_id, sender_id, chrono::Utc::now()).await;
+                }
                 // Forward to shadow manager if available
                 if let Some(shadow_mgr) = shadow_manager.lock().await.as_ref() {
                     let shadow_mgr_read = shadow_mgr.read().await;
@@ -504,10 +884,48 @@ impl NodeManager {
                 debug!("Received shadow input from {} for instance {}", sender_id, shadow_input.instance_id);
                 // Forward input to the local process if this is the target node
                 if shadow_input.target_node_id == cluster_state.local_node_id() {
-                    // TODO: Forward input to the actual running process
-                    // This would require access to the process manager
-                    debug!("Would forward input '{}' to instance {}",
-                           shadow_input.input_data, shadow_input.instance_id);
+                    if let Some(shadow_mgr) = shadow_manager.lock().await.as_ref() {
+                        let shadow_mgr_read = shadow_mgr.read().await;
+                        if let Err(e) = shadow_mgr_read.handle_shadow_input(shadow_input, sender_id, instance_registry).await {
+                            error!("Failed to handle shadow input: {}", e);
+                        }
+                    }
+                }
+            }
+            NetworkMessage::ShadowInputAck(ack) => {
+                debug!("Received shadow input ack from {} for input {}", sender_id, ack.input_id);
+                if let Some(shadow_mgr) = shadow_manager.lock().await.as_ref() {
+                    shadow_mgr.read().await.handle_shadow_input_ack(ack).await;
+                }
+            }
+            NetworkMessage::LiveOutputRequest(request) => {
+                debug!("Received live output request from {} for instance {}", sender_id, request.instance_id);
+                if request.target_node_id == cluster_state.local_node_id() {
+                    if let Some(shadow_mgr) = shadow_manager.lock().await.as_ref() {
+                        shadow_mgr.read().await.handle_live_output_request(request).await;
+                    }
+                }
+            }
+            NetworkMessage::FailoverClaim(claim) => {
+                debug!("Received failover claim from {} for instance {}", sender_id, claim.instance_id);
+                if let Some(shadow_mgr) = shadow_manager.lock().await.as_ref() {
+                    let source_online = cluster_state.get_node_info(&claim.source_node_id).await
+                        .is_some_and(|node| node.status == NodeStatus::Online);
+                    if let Err(e) = shadow_mgr.read().await.handle_failover_claim(claim, source_online).await {
+                        error!("Failed to answer failover claim: {}", e);
+                    }
+                }
+            }
+            NetworkMessage::FailoverClaimAck(ack) => {
+                debug!("Received failover claim ack from {} for instance {}", sender_id, ack.instance_id);
+                if let Some(shadow_mgr) = shadow_manager.lock().await.as_ref() {
+                    shadow_mgr.read().await.handle_failover_claim_ack(ack).await;
+                }
+            }
+            NetworkMessage::FailoverRelease(claim) => {
+                debug!("Received failover release from {} for instance {}", sender_id, claim.instance_id);
+                if let Some(shadow_mgr) = shadow_manager.lock().await.as_ref() {
+                    shadow_mgr.read().await.handle_failover_release(claim).await;
                 }
             }
             NetworkMessage::Migration(migration) => {
@@ -525,6 +943,16 @@ impl NodeManager {
                 // TODO: Handle real-time data streaming
                 debug!("Received data stream from {}", sender_id);
             }
+            NetworkMessage::StreamChunk(stream_chunk) => {
+                // Only the node running an instance streams it
+                instance_registry.record_running(stream_chunk.chunk.instance_id, sender_id, chrono::Utc::now()).await;
+                if let Some(shadow_mgr) = shadow_manager.lock().await.as_ref() {
+                    let shadow_mgr_read = shadow_mgr.read().await;
+                    if let Err(e) = shadow_mgr_read.handle_stream_chunk(stream_chunk).await {
+                        error!("Failed to handle stream chunk: {}", e);
+                    }
+                }
+            }
         }
 
         Ok(())
@@ -554,8 +982,8 @@ impl NodeManager {
                     reason: None
                 }
             }
-            RequestType::Ping => {
-                ResponseType::Pong
+            RequestType::Ping { nonce, sent_at } => {
+                ResponseType::Pong { nonce, sent_at }
             }
         };
 
@@ -569,3 +997,141 @@ impl NodeManager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listening_manager() -> (Arc<NetworkManager>, SocketAddr) {
+        let (listener, addr) = NetworkManager::pre_bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let config = NetworkConfig { listen_addr: addr, ..NetworkConfig::default() };
+        (Arc::new(NetworkManager::new_with_listener(config, Uuid::new_v4(), listener)), addr)
+    }
+
+    #[tokio::test]
+    async fn hostnames_are_dialed_at_their_resolved_address() {
+        let (server, server_addr) = listening_manager();
+        server.start_listening().await.unwrap();
+        let (client, _) = listening_manager();
+
+        let (addr, node_id) = NodeManager::dial(&client, &format!("localhost:{}", server_addr.port())).await.unwrap();
+        assert_eq!(addr, server_addr);
+        assert_eq!(node_id, server.node_id());
+
+        assert!(NodeManager::dial(&client, "localhost").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn nodes_on_ephemeral_ports_discover_each_other_at_their_bound_ports() {
+        let node = |discovery_port: u16| {
+            let config = NetworkConfig {
+                listen_addr: "127.0.0.1:0".parse().unwrap(),
+                discovery_port,
+                discovery_group: std::net::Ipv4Addr::LOCALHOST,
+                discovery_interval_secs: 1,
+                ..NetworkConfig::default()
+            };
+            NodeManager::new(config).unwrap()
+        };
+        // Announcements also go to the other localhost discovery ports, so each node hears the other
+        let (a, b) = (node(8084), node(8085));
+        for node in [&a, &b] {
+            assert_ne!(node.local_node_info().listen_addr.port(), 0);
+        }
+        assert_ne!(a.local_node_info().listen_addr, b.local_node_info().listen_addr);
+        a.start().await.unwrap();
+        b.start().await.unwrap();
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        for (node, peer) in [(&a, &b), (&b, &a)] {
+            loop {
+                let nodes = node.cluster_state().get_cluster_state().await.nodes;
+                let seen = nodes.get(&peer.node_id()).map(|info| info.listen_addr);
+                let connected = node.get_connected_peers().await.iter().any(|(id, _)| *id == peer.node_id());
+                if seen == Some(peer.local_node_info().listen_addr) && connected {
+                    break;
+                }
+                assert!(std::time::Instant::now() < deadline, "{} never discovered {} (saw {:?})", node.node_id(), peer.node_id(), seen);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn a_ping_to_a_connected_node_gets_a_pong() {
+        let node = || {
+            let config = NetworkConfig { listen_addr: "127.0.0.1:0".parse().unwrap(), discovery_enabled: false, ..NetworkConfig::default() };
+            NodeManager::new(config).unwrap()
+        };
+        let (a, b) = (node(), node());
+        a.start().await.unwrap();
+        b.start().await.unwrap();
+        assert_eq!(a.connect_to_peer(b.local_node_info().listen_addr).await.unwrap(), b.node_id());
+
+        for (from, to) in [(&a, &b), (&b, &a)] {
+            let rtt = from.ping_node(&to.node_id(), Duration::from_secs(5)).await.unwrap();
+            assert!(rtt > Duration::ZERO && rtt < Duration::from_secs(1), "{:?}", rtt);
+        }
+        // A node that is not connected can't answer
+        assert!(a.ping_node(&Uuid::new_v4(), Duration::from_millis(200)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn without_discovery_no_packets_are_sent_but_connect_works() {
+        // Stands in for the discovery group: every announcement and probe would arrive here
+        let group = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = NetworkConfig {
+            listen_addr: "127.0.0.1:0".parse().unwrap(),
+            discovery_enabled: false,
+            discovery_port: group.local_addr().unwrap().port(),
+            discovery_group: std::net::Ipv4Addr::LOCALHOST,
+            discovery_interval_secs: 1,
+            ..NetworkConfig::default()
+        };
+        let quiet = NodeManager::new(config).unwrap();
+        quiet.start().await.unwrap();
+
+        let peer_config = NetworkConfig { listen_addr: "127.0.0.1:0".parse().unwrap(), discovery_enabled: false, ..NetworkConfig::default() };
+        let peer = NodeManager::new(peer_config).unwrap();
+        peer.start().await.unwrap();
+        assert_eq!(quiet.connect_to_peer(peer.local_node_info().listen_addr).await.unwrap(), peer.node_id());
+        assert_eq!(peer.connect_to_peer(quiet.local_node_info().listen_addr).await.unwrap(), quiet.node_id());
+
+        // Past the first probe and two announcement intervals
+        let mut buffer = [0u8; 4096];
+        let received = tokio::time::timeout(Duration::from_millis(2500), group.recv_from(&mut buffer)).await;
+        assert!(received.is_err(), "discovery packet sent: {:?}", received);
+    }
+
+    #[tokio::test]
+    async fn persisted_peers_are_redialed_after_a_restart() {
+        crate::test_support::use_scratch_dir();
+        let node = || {
+            let config = NetworkConfig { listen_addr: "127.0.0.1:0".parse().unwrap(), discovery_enabled: false, ..NetworkConfig::default() };
+            NodeManager::new(config).unwrap()
+        };
+        let peer = node();
+        peer.start().await.unwrap();
+        let address = format!("localhost:{}", peer.local_node_info().listen_addr.port());
+
+        // The previous run connected with --persist
+        let before_restart = node();
+        let (resolved, node_id) = before_restart.connect_to_address(&address).await.unwrap();
+        before_restart.persist_peer(&address, resolved, node_id).await.unwrap();
+        assert!(load_persisted_peers().iter().any(|saved| saved.address == address));
+        before_restart.stop().await.unwrap();
+
+        let restarted = node();
+        restarted.start().await.unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !restarted.get_connected_peers().await.iter().any(|(id, _)| *id == peer.node_id()) {
+            assert!(std::time::Instant::now() < deadline, "persisted peer {} was not redialed", address);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let forgotten = restarted.forget_peer(&peer.node_id().to_string()).await.unwrap();
+        assert_eq!(forgotten.iter().map(|saved| saved.address.as_str()).collect::<Vec<_>>(), [address.as_str()]);
+        assert!(!load_persisted_peers().iter().any(|saved| saved.address == address));
+        assert!(restarted.forget_peer(&address).await.is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! Regex search over captured instance output (`grep`).
+
+use nhi::types::{CriuCliError, Result};
+use regex::{Regex, RegexBuilder};
+
+/// A run of output lines around one or more matches, in line order
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchGroup {
+    pub lines: Vec<MatchLine>,
+}
+
+/// One line of a match group; `number` is 1-based within the searched output
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchLine {
+    pub number: usize,
+    pub text: String,
+    pub is_match: bool, // False for context lines
+}
+
+/// Compile a search pattern, reporting a bad one as a parse error
+pub fn build_pattern(pattern: &str, ignore_case: bool) -> Result<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| CriuCliError::ParseError(format!("Invalid search pattern '{}': {}", pattern, e)))
+}
+
+/// Lines matching `regex` with up to `context` lines on either side. Groups whose context
+/// would touch or overlap are merged, as grep does.
+pub fn search_lines<S: AsRef<str>>(lines: &[S], regex: &Regex, context: usize) -> Vec<MatchGroup> {
+    let mut groups: Vec<MatchGroup> = Vec::new();
+    let mut group_end = 0; // One past the last line already placed in the current group
+
+    for (idx, line) in lines.iter().enumerate() {
+        if !regex.is_match(line.as_ref()) {
+            continue;
+        }
+
+        let start = idx.saturating_sub(context);
+        let end = (idx + context + 1).min(lines.len());
+        let extend = !groups.is_empty() && start <= group_end;
+        if !extend {
+            groups.push(MatchGroup { lines: Vec::new() });
+        }
+        let group = groups.last_mut().unwrap();
+        // Lines up to group_end are already in the group, possibly as context of an earlier match
+        let from = if extend { group_end } else { start };
+        for (offset, text) in lines[from..end].iter().enumerate() {
+            group.lines.push(MatchLine {
+                number: from + offset + 1,
+                text: text.as_ref().to_string(),
+                is_match: regex.is_match(text.as_ref()),
+            });
+        }
+        group_end = group_end.max(end);
+    }
+
+    groups
+}
+
+/// Number of matching lines across all groups
+pub fn match_count(groups: &[MatchGroup]) -> usize {
+    groups.iter().flat_map(|group| &group.lines).filter(|line| line.is_match).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: [&str; 10] = [
+        "[STDOUT] starting",
+        "[STDOUT] loading config",
+        "[STDERR] ERROR: config missing",
+        "[STDOUT] using defaults",
+        "[STDOUT] serving",
+        "[STDOUT] request 1",
+        "[STDOUT] request 2",
+        "[STDERR] error: request 3 failed",
+        "[STDERR] error: request 4 failed",
+        "[STDOUT] shutting down",
+    ];
+
+    /// Each group as "number:text" for matches and "number-text" for context, like grep prints them
+    fn rendered(groups: &[MatchGroup]) -> Vec<Vec<String>> {
+        groups
+            .iter()
+            .map(|group| {
+                group
+                    .lines
+                    .iter()
+                    .map(|line| format!("{}{}{}", line.number, if line.is_match { ':' } else { '-' }, line.text))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn grep_returns_exactly_the_matching_lines() {
+        let groups = search_lines(&OUTPUT, &build_pattern("error", false).unwrap(), 0);
+        assert_eq!(
+            rendered(&groups),
+            [vec!["8:[STDERR] error: request 3 failed", "9:[STDERR] error: request 4 failed"]]
+        );
+        let groups = search_lines(&OUTPUT, &build_pattern("error", true).unwrap(), 0);
+        assert_eq!(match_count(&groups), 3);
+        assert!(search_lines(&OUTPUT, &build_pattern("timeout", true).unwrap(), 2).is_empty());
+        assert!(build_pattern("request (", false).is_err());
+    }
+
+    #[test]
+    fn context_lines_surround_matches_and_overlapping_groups_merge() {
+        let groups = search_lines(&OUTPUT, &build_pattern("^\\[STDERR\\]", false).unwrap(), 1);
+        assert_eq!(
+            rendered(&groups),
+            [
+                vec!["2-[STDOUT] loading config", "3:[STDERR] ERROR: config missing", "4-[STDOUT] using defaults"],
+                vec![
+                    "7-[STDOUT] request 2",
+                    "8:[STDERR] error: request 3 failed",
+                    "9:[STDERR] error: request 4 failed",
+                    "10-[STDOUT] shutting down",
+                ],
+            ]
+        );
+        // Context stops at the ends of the output
+        let groups = search_lines(&OUTPUT, &build_pattern("starting", false).unwrap(), 3);
+        assert_eq!(groups[0].lines.first().unwrap().number, 1);
+        assert_eq!(groups[0].lines.len(), 4);
+    }
+}
@@ -1,10 +1,11 @@
-use crate::types::{CriuCliError, ProcessInfo, Result, StartMode};
+use crate::types::{CriuCliError, LogStream, LogWindow, OutputTimestamps, ProcessInfo, Result, StartMode};
+use chrono::{DateTime, Utc};
 use nix::sys::signal::{self, Signal};
 use nix::unistd::Pid;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::Command;
 use tokio::sync::Mutex;
 use tracing::{error, info, warn};
@@ -12,9 +13,194 @@ use uuid::Uuid;
 use std::fs::File;
 use std::io::Write;
 
+/// Shared append handle for a user-specified output file (`start --output-file`)
+type OutputSink = Option<Arc<Mutex<tokio::fs::File>>>;
+
+/// How long a stopped process gets to exit after SIGTERM before it is killed
+const STOP_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// How often a stopping process that is not our child is checked for exit
+const STOP_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Lines appended to an `--append-only` log between fsyncs
+const APPEND_ONLY_SYNC_LINES: usize = 256;
+
+/// Longest a line appended to an `--append-only` log stays unsynced
+const APPEND_ONLY_SYNC_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// The combined log of an `--append-only` instance. Every captured line is appended and
+/// fsynced in batches, whatever the in-memory history keeps, so `logs --all` has it all.
+struct DurableLog {
+    file: File,
+    unsynced: usize,
+    last_sync: std::time::Instant,
+}
+
+type DurableSink = Option<Arc<std::sync::Mutex<DurableLog>>>;
+
+impl DurableLog {
+    /// Append complete lines (each ending in a newline), syncing once a batch is full or old enough
+    fn append(&mut self, lines: &str) -> std::io::Result<()> {
+        self.file.write_all(lines.as_bytes())?;
+        self.unsynced += lines.matches('\n').count();
+        if self.unsynced >= APPEND_ONLY_SYNC_LINES || self.last_sync.elapsed() >= APPEND_ONLY_SYNC_INTERVAL {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        if self.unsynced > 0 {
+            self.file.sync_data()?;
+            self.unsynced = 0;
+        }
+        self.last_sync = std::time::Instant::now();
+        Ok(())
+    }
+}
+
+/// Combined stdout+stderr view in an instance's `output/` directory
+pub const COMBINED_LOG: &str = "process_output.log";
+/// Start and exit records for an instance's process
+pub const STATUS_LOG: &str = "status.log";
+
+/// Named pipe a detached instance reads its stdin from, in its `output/` directory
+pub const STDIN_FIFO: &str = "stdin.fifo";
+
+/// Input lines queued for a process before `send_input` reports the buffer as full
+pub const STDIN_BUFFER_CAPACITY: usize = 256;
+
+/// Default longest output line kept in one piece
+pub const DEFAULT_MAX_OUTPUT_LINE_BYTES: usize = 1024 * 1024;
+/// Appended to every piece of a split line but the last
+pub const LINE_SPLIT_MARKER: &str = " [line split]";
+
+/// Default number of output lines an attached viewer may fall behind before it misses some
+pub const DEFAULT_OUTPUT_CHANNEL_CAPACITY: usize = 4096;
+
+/// Read the next line without its terminator, buffering at most `max` bytes of it. A longer
+/// line comes back in pieces split on character boundaries; `true` marks a piece that the rest
+/// of the line follows. `buf` carries a character cut by the split over to the next call.
+async fn read_bounded_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    max: usize,
+) -> std::io::Result<Option<(String, bool)>> {
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            // EOF: a final unterminated line is still a line
+            return Ok(if buf.is_empty() { None } else { Some((take_line(buf, buf.len()), false)) });
+        }
+
+        // A terminator right at the limit still ends the line in one piece
+        let room = max.saturating_sub(buf.len());
+        let window = &available[..available.len().min(room + 2)];
+        if let Some(pos) = window.iter().position(|&b| b == b'\n').filter(|&pos| pos <= room || window[room] == b'\r') {
+            buf.extend_from_slice(&window[..pos]);
+            reader.consume(pos + 1);
+            if buf.last() == Some(&b'\r') {
+                buf.pop();
+            }
+            return Ok(Some((take_line(buf, buf.len()), false)));
+        }
+
+        if room == 0 {
+            let cut = char_boundary(buf);
+            return Ok(Some((take_line(buf, cut), true)));
+        }
+        let taken = available.len().min(room);
+        buf.extend_from_slice(&available[..taken]);
+        reader.consume(taken);
+    }
+}
+
+/// The first `len` bytes of `buf` as text, leaving the rest in `buf`
+fn take_line(buf: &mut Vec<u8>, len: usize) -> String {
+    let rest = buf.split_off(len);
+    let line = String::from_utf8_lossy(buf).into_owned();
+    *buf = rest;
+    line
+}
+
+/// Length of `bytes` without a UTF-8 sequence cut off at its end
+fn char_boundary(bytes: &[u8]) -> usize {
+    for (back, &byte) in bytes.iter().rev().take(4).enumerate() {
+        let sequence_len = match byte {
+            0x80..=0xBF => continue, // Continuation byte
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => return bytes.len(),
+        };
+        let start = bytes.len() - back - 1;
+        // Splitting a lone partial sequence would make no progress
+        return if start > 0 && start + sequence_len > bytes.len() { start } else { bytes.len() };
+    }
+    bytes.len()
+}
+
+/// How an instance's captured lines are timestamped; relative stamps count from `started_at`
+#[derive(Debug, Clone, Copy)]
+pub struct LineStamp {
+    pub format: OutputTimestamps,
+    pub started_at: DateTime<Utc>,
+}
+
+impl LineStamp {
+    pub fn new(format: OutputTimestamps, started_at: DateTime<Utc>) -> Self {
+        Self { format, started_at }
+    }
+
+    /// No timestamps
+    pub fn off() -> Self {
+        Self::new(OutputTimestamps::Off, Utc::now())
+    }
+
+    /// Timestamp for a line captured now, if stamping is on
+    fn now(&self) -> Option<String> {
+        self.format.stamp(self.started_at)
+    }
+
+    /// History line for `line`; the timestamp goes after the stream prefix so prefix filters still match
+    fn apply(&self, prefix: &str, line: &str) -> String {
+        format!("{} {}", prefix, Self::join(self.now().as_deref(), line))
+    }
+
+    fn join(stamp: Option<&str>, line: &str) -> String {
+        match stamp {
+            Some(stamp) => format!("{} {}", stamp, line),
+            None => line.to_string(),
+        }
+    }
+}
+
+/// What the log tailer does with output already on disk when it starts
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExistingOutput {
+    /// Treat it as new output (freshly created log files)
+    Stream,
+    /// Load it into the history only, e.g. after a restore
+    Replay,
+    /// Skip it because the history already holds it
+    Skip,
+}
+
 pub struct ProcessManager {
     processes: Arc<Mutex<HashMap<Uuid, ProcessInfo>>>,
     shadow_manager: Arc<Mutex<Option<Arc<tokio::sync::RwLock<crate::shadow_instance_manager::ShadowInstanceManager>>>>>,
+    /// Lines longer than this are split so a program writing without newlines cannot exhaust memory
+    max_output_line_bytes: usize,
+    /// Capacity of each process's output broadcast channel (--output-channel-capacity)
+    output_channel_capacity: usize,
+    /// Timestamp format for instances started without `--timestamp`/`--no-timestamp`
+    default_output_timestamps: OutputTimestamps,
+}
+
+impl Default for ProcessManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ProcessManager {
@@ -22,9 +208,28 @@ impl ProcessManager {
         Self {
             processes: Arc::new(Mutex::new(HashMap::new())),
             shadow_manager: Arc::new(Mutex::new(None)),
+            max_output_line_bytes: DEFAULT_MAX_OUTPUT_LINE_BYTES,
+            output_channel_capacity: DEFAULT_OUTPUT_CHANNEL_CAPACITY,
+            default_output_timestamps: OutputTimestamps::Off,
         }
     }
 
+    pub fn set_max_output_line_bytes(&mut self, max: usize) {
+        self.max_output_line_bytes = max.max(1);
+    }
+
+    pub fn set_output_channel_capacity(&mut self, capacity: usize) {
+        self.output_channel_capacity = capacity.max(1);
+    }
+
+    pub fn set_default_output_timestamps(&mut self, timestamps: OutputTimestamps) {
+        self.default_output_timestamps = timestamps;
+    }
+
+    pub fn default_output_timestamps(&self) -> OutputTimestamps {
+        self.default_output_timestamps
+    }
+
     pub async fn set_shadow_manager(&self, shadow_manager: Arc<tokio::sync::RwLock<crate::shadow_instance_manager::ShadowInstanceManager>>) {
         let mut mgr = self.shadow_manager.lock().await;
         *mgr = Some(shadow_manager);
@@ -37,39 +242,182 @@ impl ProcessManager {
         args: &[String],
         working_dir: &PathBuf,
     ) -> Result<u32> {
-        self.start_process_with_mode(instance_id, program, args, working_dir, StartMode::Normal).await
+        self.start_process_with_mode(instance_id, program, args, &[], working_dir, StartMode::Normal, None, false, LineStamp::off(), false).await
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn start_process_with_mode(
         &self,
         instance_id: Uuid,
         program: &str,
         args: &[String],
+        env: &[(String, String)],
         working_dir: &PathBuf,
         start_mode: StartMode,
+        output_file: Option<&Path>,
+        start_paused: bool,
+        stamp: LineStamp,
+        append_only: bool,
     ) -> Result<u32> {
+        let output_sink = Self::open_output_sink(output_file).await?;
+        let durable_log = Self::open_durable_log(&instance_id, append_only)?;
         match start_mode {
-            StartMode::Normal => self.start_process_normal(instance_id, program, args, working_dir).await,
-            StartMode::Detached => self.start_process_detached(instance_id, program, args, working_dir).await,
+            StartMode::Normal => self.start_process_normal(instance_id, program, args, env, working_dir, output_sink, durable_log, start_paused, stamp).await,
+            StartMode::Detached => self.start_process_detached(instance_id, program, args, env, working_dir, output_sink, durable_log, start_paused, stamp).await,
+        }
+    }
+
+    /// Wait for a child started under `PTRACE_TRACEME` to stop at its exec, then detach it
+    /// into an ordinary SIGSTOP so it sits before its first instruction until resumed.
+    /// Must run right after spawn, before anything else waits on the child.
+    fn hold_at_exec(pid: u32) -> Result<()> {
+        use nix::sys::ptrace;
+        use nix::sys::wait::{waitpid, WaitStatus};
+
+        let pid = Pid::from_raw(pid as i32);
+        match waitpid(pid, None) {
+            Ok(WaitStatus::Stopped(_, Signal::SIGTRAP)) => {}
+            Ok(status) => {
+                return Err(CriuCliError::ProcessError(format!(
+                    "Process {} did not stop at exec: {:?}", pid, status
+                )));
+            }
+            Err(e) => {
+                return Err(CriuCliError::ProcessError(format!(
+                    "Failed to wait for process {} to exec: {}", pid, e
+                )));
+            }
+        }
+
+        ptrace::detach(pid, Signal::SIGSTOP).map_err(|e| {
+            CriuCliError::ProcessError(format!("Failed to detach from process {}: {}", pid, e))
+        })?;
+        info!("Process {} is paused before its first instruction", pid);
+        Ok(())
+    }
+
+    /// Open the user-requested output file for appending, creating parent directories
+    async fn open_output_sink(output_file: Option<&Path>) -> Result<OutputSink> {
+        let path = match output_file {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                tokio::fs::create_dir_all(parent).await.map_err(CriuCliError::IoError)?;
+            }
+        }
+
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|e| {
+                CriuCliError::ProcessError(format!("Failed to open output file {}: {}", path.display(), e))
+            })?;
+
+        info!("Writing process output to {}", path.display());
+        Ok(Some(Arc::new(Mutex::new(file))))
+    }
+
+    /// Append a line to the output sink, if any; `split` pieces of a long line get no newline
+    /// so the file keeps the raw output
+    async fn write_output_sink(sink: &OutputSink, line: &str, split: bool) {
+        if let Some(file) = sink {
+            let mut file = file.lock().await;
+            let result = async {
+                file.write_all(line.as_bytes()).await?;
+                if !split {
+                    file.write_all(b"\n").await?;
+                }
+                file.flush().await
+            }
+            .await;
+            if let Err(e) = result {
+                warn!("Failed to write to output file: {}", e);
+            }
         }
     }
 
+    /// Open the combined log of an `--append-only` instance for durable appends. A background
+    /// task syncs lines left over from the last batch until the log is dropped.
+    fn open_durable_log(instance_id: &Uuid, append_only: bool) -> Result<DurableSink> {
+        if !append_only {
+            return Ok(None);
+        }
+
+        let output_dir = Self::instance_output_dir(instance_id);
+        std::fs::create_dir_all(&output_dir)?;
+        let path = output_dir.join(COMBINED_LOG);
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| CriuCliError::ProcessError(format!("Failed to open output log {}: {}", path.display(), e)))?;
+        let log = Arc::new(std::sync::Mutex::new(DurableLog { file, unsynced: 0, last_sync: std::time::Instant::now() }));
+
+        let weak = Arc::downgrade(&log);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(APPEND_ONLY_SYNC_INTERVAL).await;
+                let Some(log) = weak.upgrade() else { break };
+                Self::sync_durable_log(&Some(log)).await;
+            }
+        });
+
+        info!("Writing every output line of instance {} durably to {}", instance_id, path.display());
+        Ok(Some(log))
+    }
+
+    /// Append lines to the durable log, if any. The write and any fsync it triggers block,
+    /// so they run off the async workers.
+    async fn write_durable_log(log: &DurableSink, lines: String) {
+        if let Some(log) = log.clone() {
+            if let Ok(Err(e)) = tokio::task::spawn_blocking(move || log.lock().unwrap().append(&lines)).await {
+                warn!("Failed to append to output log: {}", e);
+            }
+        }
+    }
+
+    /// Sync whatever the durable log has not synced yet, e.g. when the output ends
+    async fn sync_durable_log(log: &DurableSink) {
+        if let Some(log) = log.clone() {
+            if let Ok(Err(e)) = tokio::task::spawn_blocking(move || log.lock().unwrap().sync()).await {
+                warn!("Failed to sync output log: {}", e);
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     async fn start_process_normal(
         &self,
         instance_id: Uuid,
         program: &str,
         args: &[String],
+        env: &[(String, String)],
         working_dir: &PathBuf,
+        output_sink: OutputSink,
+        durable_log: DurableSink,
+        start_paused: bool,
+        stamp: LineStamp,
     ) -> Result<u32> {
         info!("Starting process: {} with args: {:?}", program, args);
 
         let mut cmd = Command::new(program);
         cmd.args(args)
+            .envs(env.iter().cloned())
             .current_dir(working_dir)
             .stdin(std::process::Stdio::piped())
             .stdout(std::process::Stdio::piped())
             .stderr(std::process::Stdio::piped())
             .kill_on_drop(true);
+        if start_paused {
+            unsafe {
+                cmd.pre_exec(|| nix::sys::ptrace::traceme().map_err(std::io::Error::from));
+            }
+        }
 
         let mut child = cmd.spawn().map_err(|e| {
             error!("Failed to start process {}: {}", program, e);
@@ -79,15 +427,18 @@ impl ProcessManager {
         let pid = child
             .id()
             .ok_or_else(|| CriuCliError::ProcessError("Failed to get process ID".to_string()))?;
+        if start_paused {
+            Self::hold_at_exec(pid)?;
+        }
 
         info!("Started process {} with PID: {}", program, pid);
 
         // Create shared output history and broadcast channel
         let output_history = Arc::new(Mutex::new(Vec::new()));
-        let (output_sender, _) = tokio::sync::broadcast::channel(1000);
+        let (output_sender, _) = tokio::sync::broadcast::channel(self.output_channel_capacity);
 
         // Create stdin channel for input forwarding
-        let (stdin_sender, mut stdin_receiver) = tokio::sync::mpsc::unbounded_channel::<String>();
+        let (stdin_sender, mut stdin_receiver) = tokio::sync::mpsc::channel::<String>(STDIN_BUFFER_CAPACITY);
 
         // Take stdin, stdout and stderr from child
         let mut stdin = child.stdin.take();
@@ -114,13 +465,22 @@ impl ProcessManager {
         let stdout_handle = if let Some(stdout) = stdout {
             let history = output_history.clone();
             let sender = output_sender.clone();
+            let max = self.max_output_line_bytes;
             let shadow_mgr = self.shadow_manager.clone();
             let instance_id_copy = instance_id;
+            let sink = output_sink.clone();
+            let durable = durable_log.clone();
             Some(tokio::spawn(async move {
-                let reader = BufReader::new(stdout);
-                let mut lines = reader.lines();
-                while let Ok(Some(line)) = lines.next_line().await {
-                    let output_line = format!("[STDOUT] {}", line);
+                let mut reader = BufReader::new(stdout);
+                let mut buf = Vec::new();
+                while let Ok(Some((line, split))) = read_bounded_line(&mut reader, &mut buf, max).await {
+                    Self::write_output_sink(&sink, &line, split).await;
+                    let mut output_line = stamp.apply("[STDOUT]", &line);
+                    if split {
+                        output_line.push_str(LINE_SPLIT_MARKER);
+                    }
+
+                    Self::write_durable_log(&durable, format!("{}\n", output_line)).await;
 
                     // Store in history
                     {
@@ -134,7 +494,7 @@ impl ProcessManager {
                     // Stream to shadow instances if shadow manager is available
                     if let Some(shadow_mgr_ref) = shadow_mgr.lock().await.as_ref() {
                         let shadow_mgr_read = shadow_mgr_ref.read().await;
-                        let output_bytes = format!("{}\n", line).into_bytes();
+                        let output_bytes = if split { line.into_bytes() } else { format!("{}\n", line).into_bytes() };
                         if let Err(e) = shadow_mgr_read.stream_output_to_shadows(
                             instance_id_copy,
                             output_bytes,
@@ -144,6 +504,7 @@ impl ProcessManager {
                         }
                     }
                 }
+                Self::sync_durable_log(&durable).await;
             }))
         } else {
             None
@@ -152,13 +513,22 @@ impl ProcessManager {
         let stderr_handle = if let Some(stderr) = stderr {
             let history = output_history.clone();
             let sender = output_sender.clone();
+            let max = self.max_output_line_bytes;
             let shadow_mgr = self.shadow_manager.clone();
             let instance_id_copy = instance_id;
+            let sink = output_sink.clone();
+            let durable = durable_log.clone();
             Some(tokio::spawn(async move {
-                let reader = BufReader::new(stderr);
-                let mut lines = reader.lines();
-                while let Ok(Some(line)) = lines.next_line().await {
-                    let output_line = format!("[STDERR] {}", line);
+                let mut reader = BufReader::new(stderr);
+                let mut buf = Vec::new();
+                while let Ok(Some((line, split))) = read_bounded_line(&mut reader, &mut buf, max).await {
+                    Self::write_output_sink(&sink, &line, split).await;
+                    let mut output_line = stamp.apply("[STDERR]", &line);
+                    if split {
+                        output_line.push_str(LINE_SPLIT_MARKER);
+                    }
+
+                    Self::write_durable_log(&durable, format!("{}\n", output_line)).await;
 
                     // Store in history
                     {
@@ -172,7 +542,7 @@ impl ProcessManager {
                     // Stream to shadow instances if shadow manager is available
                     if let Some(shadow_mgr_ref) = shadow_mgr.lock().await.as_ref() {
                         let shadow_mgr_read = shadow_mgr_ref.read().await;
-                        let output_bytes = format!("{}\n", line).into_bytes();
+                        let output_bytes = if split { line.into_bytes() } else { format!("{}\n", line).into_bytes() };
                         if let Err(e) = shadow_mgr_read.stream_output_to_shadows(
                             instance_id_copy,
                             output_bytes,
@@ -182,6 +552,7 @@ impl ProcessManager {
                         }
                     }
                 }
+                Self::sync_durable_log(&durable).await;
             }))
         } else {
             None
@@ -189,7 +560,7 @@ impl ProcessManager {
 
         let process_info = ProcessInfo {
             pid,
-            child,
+            child: Some(child),
             output_history,
             stdout_handle,
             stderr_handle,
@@ -204,25 +575,42 @@ impl ProcessManager {
     }
 
     pub async fn stop_process(&self, instance_id: &Uuid) -> Result<()> {
-        let mut processes = self.processes.lock().await;
+        let process_info = self.processes.lock().await.remove(instance_id);
 
-        if let Some(mut process_info) = processes.remove(instance_id) {
+        if let Some(mut process_info) = process_info {
             info!("Stopping managed process with PID: {}", process_info.pid);
+            // The tree as it is now; children forked since the last checkpoint must go too
+            let children = crate::criu_compat::descendants(process_info.pid);
+
+            if let Some(ref mut child) = process_info.child {
+                // Ask the process to exit, and kill it if it hasn't within the grace period
+                let _ = signal::kill(Pid::from_raw(process_info.pid as i32), Signal::SIGTERM);
+                let status = match tokio::time::timeout(STOP_GRACE_PERIOD, child.wait()).await {
+                    Ok(status) => status,
+                    Err(_) => {
+                        warn!("Process {} ignored SIGTERM for {}s, killing it", process_info.pid, STOP_GRACE_PERIOD.as_secs());
+                        if let Err(e) = child.kill().await {
+                            warn!("Failed to kill process {}: {}", process_info.pid, e);
+                        }
+                        child.wait().await
+                    }
+                };
 
-            // Try graceful shutdown first
-            if let Err(e) = process_info.child.kill().await {
-                warn!("Failed to kill process {}: {}", process_info.pid, e);
-            }
-
-            // Wait for the process to exit
-            match process_info.child.wait().await {
-                Ok(status) => {
-                    info!("Process {} exited with status: {}", process_info.pid, status);
+                match status {
+                    Ok(status) => {
+                        info!("Process {} exited with status: {}", process_info.pid, status);
+                    }
+                    Err(e) => {
+                        error!("Error waiting for process {}: {}", process_info.pid, e);
+                    }
                 }
-                Err(e) => {
-                    error!("Error waiting for process {}: {}", process_info.pid, e);
+            } else {
+                // Restored or migrated process: not our child, signal it by PID
+                if let Err(e) = Self::terminate(process_info.pid).await {
+                    warn!("Failed to stop process {}: {}", process_info.pid, e);
                 }
             }
+            Self::terminate_orphans(&children);
 
             Ok(())
         } else {
@@ -236,32 +624,48 @@ impl ProcessManager {
 
     pub async fn stop_detached_process(&self, pid: u32) -> Result<()> {
         info!("Stopping detached process with PID: {}", pid);
+        let children = crate::criu_compat::descendants(pid);
 
-        // Use system signal to kill the detached process
-        let pid_nix = Pid::from_raw(pid as i32);
+        Self::terminate(pid).await?;
+        Self::terminate_orphans(&children);
 
-        // Try SIGTERM first (graceful)
-        if let Err(e) = signal::kill(pid_nix, Signal::SIGTERM) {
-            warn!("Failed to send SIGTERM to process {}: {}", pid, e);
+        info!("Detached process {} is no longer running", pid);
+        Ok(())
+    }
 
-            // Try SIGKILL (forceful)
-            if let Err(e) = signal::kill(pid_nix, Signal::SIGKILL) {
-                error!("Failed to send SIGKILL to process {}: {}", pid, e);
-                return Err(CriuCliError::ProcessError(format!("Failed to kill process {}: {}", pid, e)));
-            }
+    /// Ask the descendants of a stopped process to exit; they would be reparented and keep running on their own
+    fn terminate_orphans(children: &[u32]) {
+        for child in children {
+            let _ = signal::kill(Pid::from_raw(*child as i32), Signal::SIGTERM);
         }
+    }
 
-        // Wait a moment and check if process is gone
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    /// Send SIGTERM to a process that is not our child, then SIGKILL if it is still
+    /// there after `STOP_GRACE_PERIOD`
+    async fn terminate(pid: u32) -> Result<()> {
+        let pid_nix = Pid::from_raw(pid as i32);
+        match signal::kill(pid_nix, Signal::SIGTERM) {
+            Ok(()) => {}
+            Err(nix::errno::Errno::ESRCH) => return Ok(()),
+            Err(e) => warn!("Failed to send SIGTERM to process {}: {}", pid, e),
+        }
 
-        if signal::kill(pid_nix, None).is_ok() {
-            warn!("Process {} is still running after kill attempts", pid);
-            info!("Detached process {} is no longer running", pid);
-        } else {
-            info!("Detached process {} is no longer running", pid);
+        let deadline = tokio::time::Instant::now() + STOP_GRACE_PERIOD;
+        while tokio::time::Instant::now() < deadline {
+            if signal::kill(pid_nix, None) == Err(nix::errno::Errno::ESRCH) {
+                return Ok(());
+            }
+            tokio::time::sleep(STOP_POLL_INTERVAL).await;
         }
 
-        Ok(())
+        warn!("Process {} ignored SIGTERM for {}s, killing it", pid, STOP_GRACE_PERIOD.as_secs());
+        match signal::kill(pid_nix, Signal::SIGKILL) {
+            Ok(()) | Err(nix::errno::Errno::ESRCH) => Ok(()),
+            Err(e) => {
+                error!("Failed to send SIGKILL to process {}: {}", pid, e);
+                Err(CriuCliError::ProcessError(format!("Failed to kill process {}: {}", pid, e)))
+            }
+        }
     }
 
     pub async fn pause_process(&self, instance_id: &Uuid) -> Result<()> {
@@ -286,6 +690,15 @@ impl ProcessManager {
         }
     }
 
+    /// Send an arbitrary signal to a process by PID
+    pub fn send_signal(&self, pid: u32, sig: Signal) -> Result<()> {
+        info!("Sending {} to process {}", sig, pid);
+        signal::kill(Pid::from_raw(pid as i32), sig).map_err(|e| {
+            error!("Failed to send {} to process {}: {}", sig, pid, e);
+            CriuCliError::ProcessError(format!("Failed to send {}: {}", sig, e))
+        })
+    }
+
     pub async fn resume_process(&self, instance_id: &Uuid) -> Result<()> {
         let processes = self.processes.lock().await;
 
@@ -324,6 +737,32 @@ impl ProcessManager {
         }
     }
 
+    /// Whether an instance's process is gone: our child has exited, or a process we did not spawn
+    /// no longer exists. Also true once the process was removed from the manager.
+    pub async fn process_exited(&self, instance_id: &Uuid) -> bool {
+        let mut processes = self.processes.lock().await;
+        let Some(process_info) = processes.get_mut(instance_id) else {
+            return true;
+        };
+        match process_info.child.as_mut() {
+            Some(child) => matches!(child.try_wait(), Ok(Some(_))),
+            None => signal::kill(Pid::from_raw(process_info.pid as i32), None).is_err(),
+        }
+    }
+
+    /// Exit status of a managed child if it has exited (reaps it), `None` while it is still running
+    pub async fn try_exit_status(&self, instance_id: &Uuid) -> Option<std::process::ExitStatus> {
+        let mut processes = self.processes.lock().await;
+        let child = processes.get_mut(instance_id)?.child.as_mut()?;
+        match child.try_wait() {
+            Ok(status) => status,
+            Err(e) => {
+                warn!("Failed to poll process for instance {}: {}", instance_id, e);
+                None
+            }
+        }
+    }
+
     pub async fn remove_process(&self, instance_id: &Uuid) {
         let mut processes = self.processes.lock().await;
         processes.remove(instance_id);
@@ -342,9 +781,10 @@ impl ProcessManager {
         &self,
         instance_id: Uuid,
         pid: u32,
-        program: &str,
-        args: &[String],
-        working_dir: &PathBuf,
+        _program: &str,
+        _args: &[String],
+        _working_dir: &PathBuf,
+        stamp: LineStamp,
     ) -> Result<()> {
         info!("🔄 [MIGRATE_REG] Registering migrated process: PID {} for instance {}", pid, instance_id);
 
@@ -352,15 +792,10 @@ impl ProcessManager {
         // Since we can't create a real Child from an existing PID, we'll create a minimal ProcessInfo
         let output_history = Arc::new(Mutex::new(Vec::new()));
 
-        // Try to find and monitor the output file for this migrated process
-        let output_file_path = if let Some(output_file) = self.find_output_file_for_pid(pid).await {
-            Some(output_file)
-        } else {
-            // Create output file path based on instance structure
-            let instance_short_id = instance_id.to_string()[..8].to_string();
-            let instance_dir = PathBuf::from("instances").join(format!("instance_{}", instance_short_id));
-            let output_dir = instance_dir.join("output");
-            let output_file = output_dir.join("process_output.log");
+        // Programs started before the stream split only have the combined log
+        let output_file_path = {
+            let output_dir = Self::instance_output_dir(&instance_id);
+            let output_file = output_dir.join(COMBINED_LOG);
 
             // Ensure output directory exists for migrated process
             if let Err(e) = std::fs::create_dir_all(&output_dir) {
@@ -382,9 +817,25 @@ impl ProcessManager {
             }
         };
 
-        // Start output monitoring for the migrated process if we found an output file
-        let (output_sender, _) = tokio::sync::broadcast::channel::<String>(1000);
-        let output_monitor = if let Some(output_file) = output_file_path {
+        // Start output monitoring for the migrated process, preferring per-stream logs
+        let (output_sender, _) = tokio::sync::broadcast::channel::<String>(self.output_channel_capacity);
+        let output_dir = Self::instance_output_dir(&instance_id);
+        let output_monitor = if output_dir.join(LogStream::Stdout.file_name()).exists() {
+            info!("📄 [MIGRATE_REG] Starting stream log monitoring for migrated process in {:?}", output_dir);
+            Self::append_status(&output_dir, &format!("migrated in with PID {}", pid));
+            Some(self.spawn_stream_log_tailer(
+                instance_id,
+                pid,
+                output_dir,
+                output_history.clone(),
+                output_sender.clone(),
+                None,
+                None,
+                ExistingOutput::Replay,
+                true,
+                stamp,
+            ))
+        } else if let Some(output_file) = output_file_path {
             let output_history_clone = output_history.clone();
             let output_sender_clone = output_sender.clone();
 
@@ -393,7 +844,7 @@ impl ProcessManager {
             Some(tokio::spawn(async move {
                 // Monitor the output file for changes
                 let mut last_size = 0;
-                let mut file_path = PathBuf::from(&output_file);
+                let file_path = PathBuf::from(&output_file);
 
                 loop {
                     if let Ok(metadata) = tokio::fs::metadata(&file_path).await {
@@ -433,16 +884,10 @@ impl ProcessManager {
             None
         };
 
-        // Create a dummy child that represents the migrated process
-        // We can't control this process directly, but we can monitor it
-        let dummy_child = tokio::process::Command::new("sleep")
-            .arg("0")
-            .spawn()
-            .map_err(|e| CriuCliError::ProcessError(format!("Failed to create dummy child: {}", e)))?;
-
+        // The migrated process is not our child, so there is no Child handle to keep
         let process_info = ProcessInfo {
             pid,
-            child: dummy_child,
+            child: None,
             output_history,
             stdout_handle: output_monitor,
             stderr_handle: None,
@@ -472,33 +917,92 @@ impl ProcessManager {
 
     pub async fn get_output_history_arc(&self, instance_id: &Uuid) -> Option<Arc<Mutex<Vec<String>>>> {
         let processes = self.processes.lock().await;
-        if let Some(process_info) = processes.get(instance_id) {
-            Some(process_info.output_history.clone())
-        } else {
-            None
-        }
+        processes.get(instance_id).map(|process_info| process_info.output_history.clone())
     }
 
     pub async fn subscribe_to_output(&self, instance_id: &Uuid) -> Option<tokio::sync::broadcast::Receiver<String>> {
         let processes = self.processes.lock().await;
         if let Some(process_info) = processes.get(instance_id) {
-            if let Some(sender) = &process_info.output_sender {
-                Some(sender.subscribe())
-            } else {
-                None
-            }
+            process_info.output_sender.as_ref().map(|sender| sender.subscribe())
         } else {
             None
         }
     }
 
+    /// Whether input for a process we did not spawn has somewhere to go
+    fn check_proc_stdin(pid: u32) -> Result<()> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let stdin_path = format!("/proc/{}/fd/0", pid);
+        // Writing to /dev/null would succeed and the input would be gone
+        if std::fs::read_link(&stdin_path).is_ok_and(|target| target == Path::new("/dev/null")) {
+            return Err(CriuCliError::ProcessError(format!("Cannot send input to process {}: its stdin is /dev/null", pid)));
+        }
+        // Without blocking, so a pipe nobody reads any more fails instead of hanging
+        std::fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(nix::fcntl::OFlag::O_NONBLOCK.bits())
+            .open(&stdin_path)
+            .map(|_| ())
+            .map_err(|e| {
+                CriuCliError::ProcessError(format!("Cannot send input to process {}: {} is not writable ({})", pid, stdin_path, e))
+            })
+    }
+
+    /// Forward queued input to a process that is not our child through /proc/<pid>/fd/0. A
+    /// write blocks until the process reads, so a slow reader fills the queue and `send_input`
+    /// reports it; once the stdin can't be written the queue closes and `send_input` says so.
+    fn spawn_proc_stdin_writer(pid: u32, mut stdin_receiver: tokio::sync::mpsc::Receiver<String>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            while let Some(input) = stdin_receiver.recv().await {
+                let written = tokio::task::spawn_blocking(move || {
+                    let mut stdin = std::fs::OpenOptions::new().write(true).open(format!("/proc/{}/fd/0", pid))?;
+                    stdin.write_all(input.as_bytes())
+                })
+                .await;
+                match written {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        warn!("Cannot write to stdin of process {}, closing its input: {}", pid, e);
+                        break;
+                    }
+                    Err(e) => {
+                        warn!("Stdin writer for process {} failed: {}", pid, e);
+                        break;
+                    }
+                }
+            }
+        })
+    }
+
+    /// Create the stdin FIFO in `output_dir` unless it exists. A restore needs it at the path the
+    /// checkpointed process had open.
+    pub fn ensure_stdin_fifo(output_dir: &Path) -> std::io::Result<PathBuf> {
+        let fifo = output_dir.join(STDIN_FIFO);
+        match nix::unistd::mkfifo(&fifo, nix::sys::stat::Mode::S_IRUSR | nix::sys::stat::Mode::S_IWUSR) {
+            Ok(()) | Err(nix::errno::Errno::EEXIST) => Ok(fifo),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     pub async fn send_input(&self, instance_id: &Uuid, input: String) -> Result<()> {
         let processes = self.processes.lock().await;
         if let Some(process_info) = processes.get(instance_id) {
             if let Some(sender) = &process_info.stdin_sender {
+                // Detached and restored processes are written through /proc; refuse input up front
+                // rather than queueing lines the forwarding task would have to drop
+                if process_info.child.is_none() {
+                    Self::check_proc_stdin(process_info.pid)?;
+                }
                 let input_with_newline = format!("{}\n", input);
-                sender.send(input_with_newline).map_err(|_| {
-                    CriuCliError::ProcessError("Failed to send input to process".to_string())
+                sender.try_send(input_with_newline).map_err(|e| match e {
+                    tokio::sync::mpsc::error::TrySendError::Full(_) => CriuCliError::ProcessError(format!(
+                        "Input buffer full: process {} is not reading stdin fast enough ({} lines pending); input was not sent",
+                        process_info.pid, STDIN_BUFFER_CAPACITY
+                    )),
+                    tokio::sync::mpsc::error::TrySendError::Closed(_) => {
+                        CriuCliError::ProcessError(format!("stdin of process {} is closed", process_info.pid))
+                    }
                 })?;
                 Ok(())
             } else {
@@ -517,22 +1021,45 @@ impl ProcessManager {
         instance_id: Uuid,
         pid: u32,
         restored_history: Option<Vec<String>>,
+        stamp: LineStamp,
+        append_only: bool,
     ) -> Result<()> {
         // For restored processes, we need to attach to the existing process
         // We can't capture stdout/stderr from an already running process easily,
         // but we can try to send input to it via /proc/PID/fd/0
 
-        // Start with empty history for restored processes - we'll read from the live output file
-        let output_history = Arc::new(Mutex::new(Vec::new()));
-        let (output_sender, _) = tokio::sync::broadcast::channel(1000);
-        let (stdin_sender, mut stdin_receiver) = tokio::sync::mpsc::unbounded_channel::<String>();
+        // Seed the history with the output saved at checkpoint time; without it, rebuild it from the live output file
+        let restored_history = restored_history.unwrap_or_default();
+        let replay_existing = restored_history.is_empty();
+        if !replay_existing {
+            info!("Restored {} lines of pre-checkpoint output for process {}", restored_history.len(), pid);
+        }
+        let output_history = Arc::new(Mutex::new(restored_history));
+        let (output_sender, _) = tokio::sync::broadcast::channel(self.output_channel_capacity);
+        let (stdin_sender, stdin_receiver) = tokio::sync::mpsc::channel::<String>(STDIN_BUFFER_CAPACITY);
 
         // For restored processes, we know the output file location based on instance ID
         // Find the instance that matches this PID and use its output file
+        let output_dir = Self::instance_output_dir(&instance_id);
         let output_file_path = self.find_output_file_for_restored_process(instance_id, pid).await;
 
-        // Start output monitoring if we found an output file
-        let output_monitor = if let Some(output_file) = output_file_path {
+        // Start output monitoring, preferring the per-stream logs of detached instances
+        let output_monitor = if output_dir.join(LogStream::Stdout.file_name()).exists() {
+            info!("Starting stream log monitoring for restored process {} in {:?}", pid, output_dir);
+            Self::append_status(&output_dir, &format!("restored with PID {}", pid));
+            Some(self.spawn_stream_log_tailer(
+                instance_id,
+                pid,
+                output_dir,
+                output_history.clone(),
+                output_sender.clone(),
+                None,
+                Self::open_durable_log(&instance_id, append_only)?,
+                if replay_existing { ExistingOutput::Replay } else { ExistingOutput::Skip },
+                true,
+                stamp,
+            ))
+        } else if let Some(output_file) = output_file_path {
             let history = output_history.clone();
             let sender = output_sender.clone();
             let output_file_path = output_file.clone();
@@ -540,8 +1067,13 @@ impl ProcessManager {
             info!("Starting output monitoring for restored process {} using file: {}", pid, output_file_path);
 
             Some(tokio::spawn(async move {
-                let mut last_size = 0;
-                let mut first_read = true;
+                // Output already in the file is covered by the restored history
+                let mut last_size = if replay_existing {
+                    0
+                } else {
+                    std::fs::metadata(&output_file_path).map(|m| m.len()).unwrap_or(0)
+                };
+                let mut first_read = replay_existing;
 
                 loop {
                     tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
@@ -552,6 +1084,8 @@ impl ProcessManager {
                         // For restored processes, read the entire file on first read
                         if first_read || current_size > last_size {
                             if let Ok(content) = std::fs::read_to_string(&output_file_path) {
+                                // Lines from before the restore were captured at an unknown time
+                                let line_stamp = if first_read { LineStamp::off() } else { stamp };
                                 let content_to_process = if first_read {
                                     // Read entire file on first read for restored processes
                                     first_read = false;
@@ -563,7 +1097,7 @@ impl ProcessManager {
 
                                 for line in content_to_process.lines() {
                                     if !line.is_empty() {
-                                        let output_line = format!("[OUTPUT] {}", line);
+                                        let output_line = line_stamp.apply("[OUTPUT]", line);
 
                                         // Store in history
                                         {
@@ -593,50 +1127,13 @@ impl ProcessManager {
             None
         };
 
-        // Create a task to handle stdin forwarding to the restored process
-        let stdin_task = {
-            let pid_copy = pid;
-            tokio::spawn(async move {
-                while let Some(input) = stdin_receiver.recv().await {
-                    // Try multiple methods to send input to the restored process
-                    let mut success = false;
-
-                    // Method 1: Try /proc/PID/fd/0
-                    let stdin_path = format!("/proc/{}/fd/0", pid_copy);
-                    if let Ok(mut file) = std::fs::OpenOptions::new().write(true).open(&stdin_path) {
-                        use std::io::Write;
-                        if writeln!(file, "{}", input).is_ok() {
-                            info!("Successfully sent input to process {} via /proc/fd/0", pid_copy);
-                            success = true;
-                        }
-                    }
-
-                    // Method 2: Try using kill to send signals (for simple commands)
-                    if !success {
-                        warn!("Failed to send input to restored process {} via /proc/fd/0", pid_copy);
-                        warn!("Input was: {}", input);
-
-                        // Check if process is still running
-                        let proc_path = format!("/proc/{}", pid_copy);
-                        if !std::path::Path::new(&proc_path).exists() {
-                            error!("Process {} is no longer running", pid_copy);
-                            break;
-                        }
-                    }
-                }
-            })
-        };
-
-        // Create a dummy child process - this won't be used for restored processes
-        let mut dummy_cmd = Command::new("echo");
-        dummy_cmd.arg("dummy");
-        let dummy_child = dummy_cmd.spawn().map_err(|e| {
-            CriuCliError::ProcessError(format!("Failed to create dummy process: {}", e))
-        })?;
+        // Forward input to the restored process through its stdin in /proc
+        let stdin_task = Self::spawn_proc_stdin_writer(pid, stdin_receiver);
 
+        // The restored process is not our child, so there is no Child handle to keep
         let process_info = ProcessInfo {
             pid,
-            child: dummy_child,
+            child: None,
             output_history,
             stdout_handle: output_monitor, // Use output monitor for stdout
             stderr_handle: Some(stdin_task), // Use stdin task for stderr
@@ -651,12 +1148,18 @@ impl ProcessManager {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn start_process_detached(
         &self,
         instance_id: Uuid,
         program: &str,
         args: &[String],
+        env: &[(String, String)],
         working_dir: &PathBuf,
+        output_sink: OutputSink,
+        durable_log: DurableSink,
+        start_paused: bool,
+        stamp: LineStamp,
     ) -> Result<u32> {
         info!("Starting detached process: {} with args: {:?}", program, args);
 
@@ -670,19 +1173,8 @@ impl ProcessManager {
             error!("Failed to create output directory {:?}: {}", output_dir, e);
         }
 
-        let output_file = output_dir.join("process_output.log");
-
-        // Create a shell script that will start the process in a completely detached way
-        let script_path = working_dir.join(format!("start_detached_{}.sh", instance_id.to_string()[..8].to_string()));
-
-        // Use our daemon wrapper for true daemonization
-        let daemon_wrapper_path = std::env::current_dir()
-            .unwrap_or_else(|_| PathBuf::from("."))
-            .join("target")
-            .join("daemon_wrapper");
-
-        // Use pure Rust daemonization instead of C wrapper
-        info!("Using Rust-native daemonization for better integration");
+        let stdout_file = output_dir.join(LogStream::Stdout.file_name());
+        let stderr_file = output_dir.join(LogStream::Stderr.file_name());
 
         // Convert program path to absolute path if it's relative
         let absolute_program_path = if Path::new(program).is_absolute() {
@@ -691,241 +1183,112 @@ impl ProcessManager {
             working_dir.join(program).display().to_string()
         };
 
-        // Create a Rust-based daemon launcher script
-        let script_content = format!(
-            r#"#!/bin/bash
-
-# Detailed logging for debugging
-LOGFILE="instances/instance_{}/logs/daemon_startup.log"
-mkdir -p "$(dirname "$LOGFILE")"
-echo "$(date): Starting daemon process for {}" >> "$LOGFILE"
-
-# Kill any existing processes with the same name
-echo "$(date): Killing existing processes" >> "$LOGFILE"
-pkill -f "{}" || true
-
-# Change to working directory
-echo "$(date): Changing to working directory: {}" >> "$LOGFILE"
-cd "{}"
-
-# Start process with proper daemonization using nohup and setsid
-echo "$(date): Starting daemon process: {}" >> "$LOGFILE"
-echo "$(date): Output file: {}" >> "$LOGFILE"
-echo "$(date): Arguments: {}" >> "$LOGFILE"
-
-# Use nohup and setsid for proper daemonization
-nohup setsid "{}" {} </dev/null >"{}" 2>&1 &
-DAEMON_PID=$!
-
-echo "$(date): Daemon started with PID: $DAEMON_PID" >> "$LOGFILE"
-
-# Wait for daemon creation to complete
-sleep 1
-
-# Verify the process is running
-if ps -p $DAEMON_PID > /dev/null 2>&1; then
-    echo "$(date): Daemon process verified running" >> "$LOGFILE"
-else
-    echo "$(date): ERROR: Daemon process not running!" >> "$LOGFILE"
-fi
-"#,
-            instance_id.to_string()[..8].to_string(),
-            program,
-            program,
-            working_dir.display(),
-            working_dir.display(),
-            absolute_program_path,
-            output_file.display(),
-            args.join(" "),
-            absolute_program_path,
-            args.join(" "),
-            output_file.display()
-        );
-
-        // Create logs directory for this instance
-        let logs_dir = working_dir.join("instances").join(format!("instance_{}", instance_id.to_string()[..8].to_string())).join("logs");
+        // Startup log for debugging launches
+        let logs_dir = instance_dir.join("logs");
         if let Err(e) = std::fs::create_dir_all(&logs_dir) {
             warn!("Failed to create logs directory {:?}: {}", logs_dir, e);
         }
+        let startup_log = logs_dir.join("daemon_startup.log");
+        let log_startup = |message: &str| {
+            let line = format!("{}: {}\n", chrono::Utc::now().to_rfc3339(), message);
+            if let Ok(mut file) = std::fs::OpenOptions::new().create(true).append(true).open(&startup_log) {
+                let _ = file.write_all(line.as_bytes());
+            }
+        };
+        log_startup(&format!("Starting daemon process: {} {:?}", absolute_program_path, args));
 
-        // Write the script
-        info!("Creating detached start script at: {:?}", script_path);
-        let mut script_file = File::create(&script_path).map_err(|e| {
-            error!("Failed to create detached start script at {:?}: {}", script_path, e);
-            CriuCliError::ProcessError(format!("Failed to create detached start script: {}", e))
-        })?;
-
-        script_file.write_all(script_content.as_bytes()).map_err(|e| {
-            error!("Failed to write detached start script: {}", e);
-            CriuCliError::ProcessError(format!("Failed to write detached start script: {}", e))
-        })?;
-
-        info!("Successfully created script: {:?}", script_path);
-
-        // Make script executable
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = std::fs::metadata(&script_path).map_err(|e| {
-            CriuCliError::ProcessError(format!("Failed to get script permissions: {}", e))
-        })?.permissions();
-        perms.set_mode(0o755);
-        std::fs::set_permissions(&script_path, perms).map_err(|e| {
-            CriuCliError::ProcessError(format!("Failed to set script permissions: {}", e))
-        })?;
-
-        // Start the script directly with bash
-        let mut cmd = Command::new("bash");
-        cmd.arg(&script_path)
+        let open_log = |path: &Path| {
+            File::create(path).map_err(|e| {
+                error!("Failed to create output file {:?}: {}", path, e);
+                CriuCliError::ProcessError(format!("Failed to create output file {:?}: {}", path, e))
+            })
+        };
+        let stdout_log = open_log(&stdout_file)?;
+        let stderr_log = open_log(&stderr_file)?;
+        // The program holds its stdin FIFO open for reading and writing, so it never sees
+        // end-of-file between inputs and input can be written by any later nhi run
+        let stdin_fifo = Self::ensure_stdin_fifo(&output_dir)
+            .and_then(|fifo| std::fs::OpenOptions::new().read(true).write(true).open(fifo))
+            .map_err(|e| CriuCliError::ProcessError(format!("Failed to create stdin FIFO in {:?}: {}", output_dir, e)))?;
+
+        // Exec the program directly (no shell) in its own session, so it outlives the terminal and nhi
+        let mut cmd = Command::new(&absolute_program_path);
+        cmd.args(args)
+            .envs(env.iter().cloned())
             .current_dir(working_dir)
-            .stdin(std::process::Stdio::null())
-            .stdout(std::process::Stdio::null())
-            .stderr(std::process::Stdio::null());
+            .stdin(std::process::Stdio::from(stdin_fifo))
+            .stdout(std::process::Stdio::from(stdout_log))
+            .stderr(std::process::Stdio::from(stderr_log));
+        unsafe {
+            cmd.pre_exec(move || {
+                nix::unistd::setsid().map_err(std::io::Error::from)?;
+                if start_paused {
+                    nix::sys::ptrace::traceme().map_err(std::io::Error::from)?;
+                }
+                Ok(())
+            });
+        }
 
         let mut child = cmd.spawn().map_err(|e| {
             error!("Failed to start detached process {}: {}", program, e);
+            log_startup(&format!("ERROR: failed to start: {}", e));
             CriuCliError::ProcessError(format!("Failed to start detached process: {}", e))
         })?;
 
-        // Wait for the setsid command to complete
-        match child.wait().await {
-            Ok(status) => {
-                if !status.success() {
-                    error!("Script execution failed with status: {}", status);
-                    return Err(CriuCliError::ProcessError(format!("Script execution failed: {}", status)));
-                }
-                info!("Script executed successfully");
-            }
-            Err(e) => {
-                error!("Failed to wait for script execution: {}", e);
-                return Err(CriuCliError::ProcessError(format!("Failed to wait for script: {}", e)));
-            }
+        let pid = child
+            .id()
+            .ok_or_else(|| CriuCliError::ProcessError("Failed to get process ID".to_string()))?;
+        log_startup(&format!("Daemon started with PID: {}", pid));
+        if start_paused {
+            Self::hold_at_exec(pid)?;
+            log_startup("Daemon paused before its first instruction");
         }
 
-        // Wait a moment for the script to start the actual program
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-
-        // Find the actual program PID by name with multiple attempts
-        let mut pid = None;
-        for attempt in 1..=5 {
-            if let Some(found_pid) = self.find_process_pid_by_name(program).await {
-                pid = Some(found_pid);
-                break;
-            }
-            info!("Attempt {} to find PID for {}, waiting...", attempt, program);
-            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        // Reap the program when it exits and record its exit status
+        {
+            let status_dir = output_dir.clone();
+            tokio::spawn(async move {
+                match child.wait().await {
+                    Ok(status) => {
+                        info!("Detached process {} exited with {}", pid, status);
+                        Self::append_status(&status_dir, &format!("process {} exited with {}", pid, status));
+                    }
+                    Err(e) => warn!("Failed to wait for detached process {}: {}", pid, e),
+                }
+            });
         }
 
-        let pid = pid.ok_or_else(|| {
-            // Read the daemon startup log for debugging
-            let log_file = working_dir.join("instances").join(format!("instance_{}", instance_id.to_string()[..8].to_string())).join("logs").join("daemon_startup.log");
-            if let Ok(log_content) = std::fs::read_to_string(&log_file) {
-                error!("Daemon startup log content:\n{}", log_content);
-            } else {
-                error!("Could not read daemon startup log at {:?}", log_file);
-            }
-
-            CriuCliError::ProcessError(format!("Failed to find PID for detached process: {} after 5 attempts. Check daemon startup log for details.", program))
-        })?;
-
         info!("Started detached process {} with PID: {}", program, pid);
 
         // Create shared output history and broadcast channel
         let output_history = Arc::new(Mutex::new(Vec::new()));
-        let (output_sender, _) = tokio::sync::broadcast::channel(1000);
+        let (output_sender, _) = tokio::sync::broadcast::channel(self.output_channel_capacity);
 
-        // Create stdin channel for input forwarding (limited for detached processes)
-        let (stdin_sender, mut stdin_receiver) = tokio::sync::mpsc::unbounded_channel::<String>();
-
-        // Create a task to monitor the output file
-        let output_monitor = {
-            let output_file_path = output_file.clone();
-            let history = output_history.clone();
-            let sender = output_sender.clone();
-            let shadow_mgr = self.shadow_manager.clone();
-            let instance_id_copy = instance_id;
-
-            tokio::spawn(async move {
-                let mut last_size = 0;
-                loop {
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        // Create stdin channel for input forwarding
+        let (stdin_sender, stdin_receiver) = tokio::sync::mpsc::channel::<String>(STDIN_BUFFER_CAPACITY);
 
-                    if let Ok(metadata) = std::fs::metadata(&output_file_path) {
-                        let current_size = metadata.len();
-                        if current_size > last_size {
-                            // Read new content
-                            if let Ok(content) = std::fs::read_to_string(&output_file_path) {
-                                let new_content = &content[last_size as usize..];
-                                for line in new_content.lines() {
-                                    if !line.is_empty() {
-                                        let output_line = format!("[OUTPUT] {}", line);
+        Self::append_status(&output_dir, &format!("started {} with PID {}", program, pid));
 
-                                        // Store in history
-                                        {
-                                            let mut history = history.lock().await;
-                                            history.push(output_line.clone());
-                                        }
-
-                                        // Broadcast to any attached listeners
-                                        let _ = sender.send(output_line.clone());
-
-                                        // Stream to shadow instances if shadow manager is available
-                                        if let Some(shadow_mgr_ref) = shadow_mgr.lock().await.as_ref() {
-                                            let shadow_mgr_read = shadow_mgr_ref.read().await;
-                                            let output_bytes = format!("{}\n", line).into_bytes();
-                                            tracing::debug!("Streaming output to shadows: '{}' for instance {}", line, instance_id_copy);
-                                            if let Err(e) = shadow_mgr_read.stream_output_to_shadows(
-                                                instance_id_copy,
-                                                output_bytes,
-                                                crate::message_protocol::StreamType::Stdout
-                                            ).await {
-                                                tracing::error!("Failed to stream output to shadows: {}", e);
-                                            } else {
-                                                tracing::debug!("Successfully streamed output to shadows");
-                                            }
-                                        } else {
-                                            tracing::debug!("No shadow manager available for output streaming");
-                                        }
-                                    }
-                                }
-                            }
-                            last_size = current_size;
-                        }
-                    }
+        // Tail the per-stream log files
+        let output_monitor = self.spawn_stream_log_tailer(
+            instance_id,
+            pid,
+            output_dir.clone(),
+            output_history.clone(),
+            output_sender.clone(),
+            output_sink,
+            durable_log,
+            ExistingOutput::Stream,
+            false,
+            stamp,
+        );
 
-                    // Check if process is still running
-                    let proc_path = format!("/proc/{}", pid);
-                    if !std::path::Path::new(&proc_path).exists() {
-                        info!("Detached process {} is no longer running", pid);
-                        break;
-                    }
-                }
-            })
-        };
-
-        // Create a task to handle stdin forwarding (limited functionality for detached processes)
-        let stdin_task = {
-            let pid_copy = pid;
-            tokio::spawn(async move {
-                while let Some(input) = stdin_receiver.recv().await {
-                    warn!("Input to detached process {} (limited functionality): {}", pid_copy, input);
-                    // For detached processes, input capability is very limited
-                    // We can try to write to /proc/PID/fd/0 but it may not work
-                    let stdin_path = format!("/proc/{}/fd/0", pid_copy);
-                    if let Ok(mut file) = std::fs::OpenOptions::new().write(true).open(&stdin_path) {
-                        if writeln!(file, "{}", input).is_ok() {
-                            info!("Successfully sent input to detached process {}", pid_copy);
-                        } else {
-                            warn!("Failed to send input to detached process {}", pid_copy);
-                        }
-                    } else {
-                        warn!("Cannot send input to detached process {} - no stdin access", pid_copy);
-                    }
-                }
-            })
-        };
+        // Forward input through the stdin FIFO the process holds open
+        let stdin_task = Self::spawn_proc_stdin_writer(pid, stdin_receiver);
 
         let process_info = ProcessInfo {
             pid,
-            child,
+            child: None, // Owned by the reaper task
             output_history,
             stdout_handle: Some(output_monitor),
             stderr_handle: Some(stdin_task),
@@ -936,151 +1299,291 @@ fi
         let mut processes = self.processes.lock().await;
         processes.insert(instance_id, process_info);
 
-        // Clean up the script file after a delay
-        let script_cleanup = script_path.clone();
-        tokio::spawn(async move {
-            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-            let _ = std::fs::remove_file(script_cleanup);
-        });
-
         Ok(pid)
     }
 
-    async fn find_process_pid_by_name(&self, program_name: &str) -> Option<u32> {
-        // Extract just the program name without path
-        let program_basename = std::path::Path::new(program_name)
-            .file_name()
-            .and_then(|name| name.to_str())
-            .unwrap_or(program_name);
-
-        info!("Looking for process with name: {}", program_basename);
-
-        // Get list of all processes and find the newest one matching our criteria
-        let mut matching_processes: Vec<u32> = Vec::new();
-
-        // Read /proc to find processes
-        if let Ok(entries) = std::fs::read_dir("/proc") {
-            for entry in entries.flatten() {
-                if let Ok(pid_str) = entry.file_name().into_string() {
-                    if let Ok(pid) = pid_str.parse::<u32>() {
-                        // Read the command line
-                        let cmdline_path = format!("/proc/{}/cmdline", pid);
-                        if let Ok(cmdline) = std::fs::read_to_string(&cmdline_path) {
-                            // cmdline is null-separated, take the first part
-                            let cmd = cmdline.split('\0').next().unwrap_or("");
-                            let cmd_basename = std::path::Path::new(cmd)
-                                .file_name()
-                                .and_then(|name| name.to_str())
-                                .unwrap_or(cmd);
-
-                            // More precise matching: exact basename match and not a shell script
-                            if cmd_basename == program_basename && !cmd.contains("sh") && !cmd.contains("bash") {
-                                // Additional check: make sure it's not a script wrapper
-                                let stat_path = format!("/proc/{}/stat", pid);
-                                if let Ok(stat_content) = std::fs::read_to_string(&stat_path) {
-                                    // Check if this process was recently started (within last 10 seconds)
-                                    let parts: Vec<&str> = stat_content.split_whitespace().collect();
-                                    if parts.len() > 21 {
-                                        if let Ok(starttime) = parts[21].parse::<u64>() {
-                                            // This is a more reliable way to find the actual process
-                                            matching_processes.push(pid);
-                                            info!("Found candidate process: PID {} with cmdline: {}", pid, cmdline.replace('\0', " "));
-                                        }
-                                    }
-                                }
+    async fn find_output_file_for_restored_process(&self, instance_id: Uuid, pid: u32) -> Option<String> {
+        // For restored processes, we can directly construct the output file path
+        let short_id = instance_id.to_string()[..8].to_string();
+        let output_file = Self::instance_output_dir(&instance_id).join(COMBINED_LOG);
+
+        if output_file.exists() {
+            info!("Found output file for restored process {} (instance {}): {}", pid, short_id, output_file.display());
+            Some(output_file.to_string_lossy().to_string())
+        } else {
+            warn!("Output file not found for restored process {} (instance {}): {}", pid, short_id, output_file.display());
+            None
+        }
+    }
+
+    fn instance_output_dir(instance_id: &Uuid) -> PathBuf {
+        PathBuf::from("instances")
+            .join(format!("instance_{}", &instance_id.to_string()[..8]))
+            .join("output")
+    }
+
+    /// Append a timestamped record to the instance's status log
+    fn append_status(output_dir: &Path, message: &str) {
+        let line = format!("{}: {}\n", chrono::Utc::now().to_rfc3339(), message);
+        let result = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(output_dir.join(STATUS_LOG))
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+        if let Err(e) = result {
+            warn!("Failed to write status log in {:?}: {}", output_dir, e);
+        }
+    }
+
+    /// Read complete lines appended to `path` since `offset`, advancing it
+    fn read_new_lines(path: &Path, offset: &mut u64) -> Vec<String> {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => return Vec::new(),
+        };
+        let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+        if len < *offset {
+            // Truncated, start over
+            *offset = 0;
+        }
+        if len == *offset || file.seek(SeekFrom::Start(*offset)).is_err() {
+            return Vec::new();
+        }
+
+        let mut buffer = Vec::new();
+        if file.read_to_end(&mut buffer).is_err() {
+            return Vec::new();
+        }
+
+        // Leave a trailing partial line for the next read
+        let complete = match buffer.iter().rposition(|&b| b == b'\n') {
+            Some(pos) => pos + 1,
+            None => return Vec::new(),
+        };
+        *offset += complete as u64;
+
+        String::from_utf8_lossy(&buffer[..complete])
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    /// Tail an instance's stdout.log and stderr.log into its history, attached listeners, shadows
+    /// and the combined log until the process is gone.
+    /// `existing` decides what happens to lines already on disk when tailing starts.
+    /// With `record_exit`, the exit is noted in status.log (for processes nobody can wait on).
+    #[allow(clippy::too_many_arguments)]
+    fn spawn_stream_log_tailer(
+        &self,
+        instance_id: Uuid,
+        pid: u32,
+        output_dir: PathBuf,
+        history: Arc<Mutex<Vec<String>>>,
+        sender: tokio::sync::broadcast::Sender<String>,
+        sink: OutputSink,
+        durable: DurableSink,
+        existing: ExistingOutput,
+        record_exit: bool,
+        stamp: LineStamp,
+    ) -> tokio::task::JoinHandle<()> {
+        let shadow_mgr = self.shadow_manager.clone();
+
+        tokio::spawn(async move {
+            let streams = [
+                (LogStream::Stdout, crate::message_protocol::StreamType::Stdout),
+                (LogStream::Stderr, crate::message_protocol::StreamType::Stderr),
+            ];
+            let combined_path = output_dir.join(COMBINED_LOG);
+            let mut offsets = [0u64; 2];
+            if existing == ExistingOutput::Skip {
+                for (idx, (stream, _)) in streams.iter().enumerate() {
+                    offsets[idx] = std::fs::metadata(output_dir.join(stream.file_name())).map(|m| m.len()).unwrap_or(0);
+                }
+            }
+            let mut replaying = existing == ExistingOutput::Replay;
+
+            loop {
+                // Check before reading so output written just before exit is still drained
+                let exited = !Path::new(&format!("/proc/{}", pid)).exists();
+
+                for (idx, (stream, stream_type)) in streams.iter().enumerate() {
+                    let lines = Self::read_new_lines(&output_dir.join(stream.file_name()), &mut offsets[idx]);
+                    if lines.is_empty() {
+                        continue;
+                    }
+
+                    // Replayed lines were captured earlier, so their time is unknown
+                    let stamps: Vec<Option<String>> = lines.iter().map(|_| if replaying { None } else { stamp.now() }).collect();
+
+                    if !replaying {
+                        let mut combined = lines
+                            .iter()
+                            .zip(&stamps)
+                            .map(|(line, line_stamp)| LineStamp::join(line_stamp.as_deref(), line))
+                            .collect::<Vec<_>>()
+                            .join("\n");
+                        combined.push('\n');
+                        if durable.is_some() {
+                            Self::write_durable_log(&durable, combined).await;
+                        } else {
+                            let result = std::fs::OpenOptions::new()
+                                .create(true)
+                                .append(true)
+                                .open(&combined_path)
+                                .and_then(|mut file| file.write_all(combined.as_bytes()));
+                            if let Err(e) = result {
+                                warn!("Failed to append to combined log {:?}: {}", combined_path, e);
                             }
                         }
                     }
-                }
-            }
-        }
 
-        // Return the most recently started process if we found any
-        if !matching_processes.is_empty() {
-            // Sort by PID (higher PID usually means more recent)
-            matching_processes.sort();
-            let selected_pid = *matching_processes.last().unwrap();
-            info!("Selected PID {} from {} candidates", selected_pid, matching_processes.len());
-            return Some(selected_pid);
-        }
+                    for (line, line_stamp) in lines.into_iter().zip(stamps) {
+                        let output_line = format!("{} {}", stream.prefix(), LineStamp::join(line_stamp.as_deref(), &line));
+                        history.lock().await.push(output_line.clone());
+                        let _ = sender.send(output_line);
 
-        warn!("No process found with name: {}", program_basename);
-        None
-    }
-
-    async fn find_output_file_for_pid(&self, pid: u32) -> Option<String> {
-        // Try to find the output file for a given PID
-        // Look in the new instances directory structure
-
-        let instances_dir = PathBuf::from("instances");
-
-        // Look for output files in all instance directories
-        if let Ok(entries) = std::fs::read_dir(&instances_dir) {
-            for entry in entries.flatten() {
-                if entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false) {
-                    let instance_dir = entry.path();
-                    let output_file = instance_dir.join("output").join("process_output.log");
-
-                    if output_file.exists() {
-                        // Check if this file is being written to by our PID
-                        // We can check the file descriptors of the process
-                        let fd_dir = format!("/proc/{}/fd", pid);
-                        if let Ok(fd_entries) = std::fs::read_dir(&fd_dir) {
-                            for fd_entry in fd_entries.flatten() {
-                                if let Ok(link_target) = std::fs::read_link(fd_entry.path()) {
-                                    if link_target == output_file {
-                                        info!("Found output file for PID {}: {}", pid, output_file.display());
-                                        return Some(output_file.to_string_lossy().to_string());
-                                    }
-                                }
+                        if replaying {
+                            continue;
+                        }
+                        Self::write_output_sink(&sink, &line, false).await;
+
+                        // Stream to shadow instances if shadow manager is available
+                        if let Some(shadow_mgr_ref) = shadow_mgr.lock().await.as_ref() {
+                            let shadow_mgr_read = shadow_mgr_ref.read().await;
+                            let output_bytes = format!("{}\n", line).into_bytes();
+                            if let Err(e) = shadow_mgr_read.stream_output_to_shadows(
+                                instance_id,
+                                output_bytes,
+                                stream_type.clone(),
+                            ).await {
+                                tracing::debug!("Failed to stream output to shadows: {}", e);
                             }
                         }
                     }
                 }
+                replaying = false;
+
+                if exited {
+                    Self::sync_durable_log(&durable).await;
+                    info!("Detached process {} is no longer running", pid);
+                    if record_exit {
+                        // Not our child, so the exit code cannot be collected
+                        Self::append_status(&output_dir, &format!("process {} exited (exit code unavailable)", pid));
+                    }
+                    break;
+                }
+
+                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
             }
+        })
+    }
+
+    /// Move an instance's combined log aside as `process_output.<checkpoint>.log` so a restored
+    /// process starts from an empty live log. Returns where the old log went, if there was one.
+    pub fn rotate_combined_log(instance_id: &Uuid, checkpoint_name: &str) -> Result<Option<PathBuf>> {
+        let output_dir = Self::instance_output_dir(instance_id);
+        let live = output_dir.join(COMBINED_LOG);
+        if !live.exists() {
+            return Ok(None);
         }
 
-        warn!("No output file found for PID {}", pid);
-        None
+        let mut rotated = output_dir.join(format!("process_output.{}.log", checkpoint_name));
+        if rotated.exists() {
+            // Restored from the same checkpoint before
+            let stamp = chrono::Utc::now().format("%Y%m%d%H%M%S");
+            rotated = output_dir.join(format!("process_output.{}.{}.log", checkpoint_name, stamp));
+        }
+        std::fs::rename(&live, &rotated)?;
+        File::create(&live)?;
+        Self::append_status(&output_dir, &format!("rotated output log to {}", rotated.display()));
+        Ok(Some(rotated))
     }
 
-    async fn find_output_file_for_restored_process(&self, instance_id: Uuid, pid: u32) -> Option<String> {
-        // For restored processes, we can directly construct the output file path
-        let short_id = instance_id.to_string()[..8].to_string();
-        let output_file = PathBuf::from("instances")
-            .join(format!("instance_{}", short_id))
-            .join("output")
-            .join("process_output.log");
+    /// Combined logs rotated by `restore --replace`, oldest first
+    fn rotated_combined_logs(output_dir: &Path) -> Vec<PathBuf> {
+        let mut logs: Vec<(std::time::SystemTime, PathBuf)> = std::fs::read_dir(output_dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|entry| {
+                        let name = entry.file_name().to_string_lossy().into_owned();
+                        name != COMBINED_LOG && name.starts_with("process_output.") && name.ends_with(".log")
+                    })
+                    .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+                    .collect()
+            })
+            .unwrap_or_default();
+        logs.sort();
+        logs.into_iter().map(|(_, path)| path).collect()
+    }
 
-        if output_file.exists() {
-            info!("Found output file for restored process {} (instance {}): {}", pid, short_id, output_file.display());
-            Some(output_file.to_string_lossy().to_string())
-        } else {
-            warn!("Output file not found for restored process {} (instance {}): {}", pid, short_id, output_file.display());
-            None
+    /// Every line in an instance's combined log files, rotated logs first
+    pub fn stored_output_lines(instance_id: &Uuid) -> Result<Vec<String>> {
+        let output_dir = Self::instance_output_dir(instance_id);
+        let mut files = Self::rotated_combined_logs(&output_dir);
+        files.push(output_dir.join(COMBINED_LOG));
+
+        let mut lines = Vec::new();
+        for file in files.iter().filter(|file| file.exists()) {
+            let content = std::fs::read(file).map_err(CriuCliError::IoError)?;
+            lines.extend(String::from_utf8_lossy(&content).lines().map(str::to_string));
         }
+        Ok(lines)
     }
 
-    pub async fn show_process_output(&self, instance_id: &Uuid, lines: Option<usize>) -> Result<()> {
+    /// Print an instance's output from its log files. A non-empty `window` keeps only lines
+    /// stamped inside it; `started_at` anchors relative stamps.
+    pub async fn show_process_output(
+        &self,
+        instance_id: &Uuid,
+        lines: Option<usize>,
+        stream: Option<LogStream>,
+        all: bool,
+        window: &LogWindow,
+        started_at: DateTime<Utc>,
+    ) -> Result<()> {
         // Try to find output file for this instance
         let short_id = instance_id.to_string()[..8].to_string();
-        let instance_dir = PathBuf::from("instances").join(format!("instance_{}", short_id));
-        let output_file = instance_dir.join("output").join("process_output.log");
+        let output_dir = Self::instance_output_dir(instance_id);
+        let output_file = output_dir.join(stream.map(|s| s.file_name()).unwrap_or(COMBINED_LOG));
+        let now = Utc::now();
+        let in_window = |line: &str| window.is_empty() || window.matches_line(line, started_at, now);
+
+        if all {
+            let mut files = Self::rotated_combined_logs(&output_dir);
+            files.push(output_file);
+            println!("=== Process Output (all lines, {} rotated log(s)) ===", files.len() - 1);
+            // Stream line by line: an --append-only history can be far larger than memory
+            for file in files.iter().filter(|file| file.exists()) {
+                let reader = std::io::BufReader::new(File::open(file).map_err(CriuCliError::IoError)?);
+                for line in std::io::BufRead::lines(reader) {
+                    let line = line.map_err(CriuCliError::IoError)?;
+                    if in_window(&line) {
+                        println!("{}", line);
+                    }
+                }
+            }
+            println!("=== End Output ===");
+            return Ok(());
+        }
 
         if output_file.exists() {
             info!("Reading output from: {:?}", output_file);
 
             match std::fs::read_to_string(&output_file) {
                 Ok(content) => {
-                    let lines_to_show = lines.unwrap_or(50); // Default to last 50 lines
-                    let output_lines: Vec<&str> = content.lines().collect();
+                    let lines_to_show = lines.unwrap_or(if window.is_empty() { 50 } else { usize::MAX }); // Default to last 50 lines
+                    let output_lines: Vec<&str> = content.lines().filter(|line| in_window(line)).collect();
                     let start_index = if output_lines.len() > lines_to_show {
                         output_lines.len() - lines_to_show
                     } else {
                         0
                     };
 
-                    println!("=== Process Output (last {} lines) ===", lines_to_show);
+                    println!("=== Process Output (last {} lines) ===", lines_to_show.min(output_lines.len()));
                     for line in &output_lines[start_index..] {
                         println!("{}", line);
                     }
@@ -1099,3 +1602,463 @@ fi
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    /// Register `command` as a migrated process (not our child) and stop it, returning how it died
+    async fn stop_migrated(command: &str) -> std::process::ExitStatus {
+        crate::test_support::use_scratch_dir();
+        let mut child = std::process::Command::new("sh").arg("-c").arg(command).spawn().unwrap();
+        let pid = child.id();
+        // Signals must not arrive before the shell has set up the command
+        while std::fs::read_to_string(format!("/proc/{}/comm", pid)).unwrap_or_default().trim() != "sleep" {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        // Reap it as its real parent would, so the exit is visible to the manager
+        let reaper = std::thread::spawn(move || child.wait().unwrap());
+
+        let process_manager = ProcessManager::new();
+        let instance_id = Uuid::new_v4();
+        let stamp = LineStamp::new(OutputTimestamps::Off, Utc::now());
+        process_manager
+            .register_migrated_process(instance_id, pid, "sh", &[], &PathBuf::from("."), stamp)
+            .await
+            .unwrap();
+        process_manager.stop_process(&instance_id).await.unwrap();
+
+        reaper.join().unwrap()
+    }
+
+    #[tokio::test]
+    async fn stop_asks_the_process_to_terminate_first() {
+        let status = stop_migrated("exec sleep 30").await;
+        assert_eq!(status.signal(), Some(Signal::SIGTERM as i32));
+    }
+
+    #[tokio::test]
+    async fn stop_kills_a_process_ignoring_sigterm_after_the_grace_period() {
+        let started = std::time::Instant::now();
+        let status = stop_migrated("trap '' TERM; exec sleep 30").await;
+        assert_eq!(status.signal(), Some(Signal::SIGKILL as i32));
+        assert!(started.elapsed() >= STOP_GRACE_PERIOD);
+    }
+
+    #[tokio::test]
+    async fn stop_terminates_children_forked_after_start() {
+        crate::test_support::use_scratch_dir();
+        let mut child = std::process::Command::new("sh").arg("-c").arg("sleep 30 & exec sleep 31").spawn().unwrap();
+        let pid = child.id();
+        // The shell has exec'd into the parent once it runs as `sleep`, after forking the child
+        let forked = loop {
+            let comm = std::fs::read_to_string(format!("/proc/{}/comm", pid)).unwrap_or_default();
+            match crate::criu_compat::descendants(pid).first() {
+                Some(forked) if comm.trim() == "sleep" => break *forked,
+                _ => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        };
+        let reaper = std::thread::spawn(move || child.wait().unwrap());
+
+        let process_manager = ProcessManager::new();
+        let instance_id = Uuid::new_v4();
+        let stamp = LineStamp::new(OutputTimestamps::Off, Utc::now());
+        process_manager
+            .register_migrated_process(instance_id, pid, "sh", &[], &PathBuf::from("."), stamp)
+            .await
+            .unwrap();
+        process_manager.stop_process(&instance_id).await.unwrap();
+        reaper.join().unwrap();
+
+        // The orphan is reaped by whoever adopted it, so a zombie counts as gone
+        let gone = |pid: u32| match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Ok(stat) => stat[stat.rfind(')').unwrap() + 1..].trim_start().starts_with('Z'),
+            Err(_) => true,
+        };
+        let deadline = std::time::Instant::now() + STOP_GRACE_PERIOD;
+        while !gone(forked) {
+            assert!(std::time::Instant::now() < deadline, "child {} survived the stop", forked);
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    /// Lines `read_bounded_line` yields for `input`
+    async fn bounded_lines(input: &[u8], max: usize) -> Vec<(String, bool)> {
+        let mut reader = BufReader::with_capacity(7, input);
+        let mut buf = Vec::new();
+        let mut lines = Vec::new();
+        while let Some(line) = read_bounded_line(&mut reader, &mut buf, max).await.unwrap() {
+            assert!(buf.len() < 4, "{} bytes carried over", buf.len());
+            lines.push(line);
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn long_lines_are_split_at_the_limit() {
+        let input = vec![b'x'; 10_000];
+        let lines = bounded_lines(&input, 64).await;
+        assert_eq!(lines.len(), 157);
+        assert!(lines.iter().all(|(line, _)| line.len() <= 64));
+        assert!(lines[..156].iter().all(|(_, split)| *split));
+        assert_eq!(lines[156], ("x".repeat(10_000 - 156 * 64), false));
+    }
+
+    #[tokio::test]
+    async fn a_line_of_exactly_the_limit_is_not_split() {
+        let lines = bounded_lines(b"abcd\nefgh\r\nij\n", 4).await;
+        let expected = [("abcd", false), ("efgh", false), ("ij", false)];
+        assert_eq!(lines, expected.map(|(line, split)| (line.to_string(), split)));
+    }
+
+    #[tokio::test]
+    async fn splits_keep_multibyte_characters_whole() {
+        let text = "aé€😀".repeat(20);
+        let lines = bounded_lines(format!("{}\n", text).as_bytes(), 5).await;
+        assert!(lines.iter().all(|(line, _)| !line.contains('\u{FFFD}') && line.len() <= 5), "{:?}", lines);
+        assert_eq!(lines.iter().map(|(line, _)| line.as_str()).collect::<String>(), text);
+    }
+
+    #[tokio::test]
+    async fn input_reaches_a_detached_process_and_comes_back_in_its_output() {
+        crate::test_support::use_scratch_dir();
+        let process_manager = ProcessManager::new();
+        let instance_id = Uuid::new_v4();
+        process_manager
+            .start_process_with_mode(instance_id, "/bin/cat", &[], &[], &PathBuf::from("/"), StartMode::Detached, None, false, LineStamp::off(), false)
+            .await
+            .unwrap();
+
+        process_manager.send_input(&instance_id, "hello detached".to_string()).await.unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            let history = process_manager.get_output_history(&instance_id).await.unwrap_or_default();
+            if history.iter().any(|line| line.ends_with("hello detached")) {
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline, "input never came back: {:?}", history);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+
+        // Once the process is gone its stdin can't be written, and sending says so
+        let pid = process_manager.get_process_pid(&instance_id).await.unwrap();
+        signal::kill(Pid::from_raw(pid as i32), Signal::SIGKILL).unwrap();
+        while !process_manager.process_exited(&instance_id).await {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let error = process_manager.send_input(&instance_id, "too late".to_string()).await.unwrap_err();
+        assert!(error.to_string().contains("Cannot send input"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn detached_arguments_with_shell_metacharacters_reach_the_program_verbatim() {
+        crate::test_support::use_scratch_dir();
+        let working_dir = std::env::current_dir().unwrap().join("metachar work dir");
+        std::fs::create_dir_all(&working_dir).unwrap();
+        let process_manager = ProcessManager::new();
+        let instance_id = Uuid::new_v4();
+        let args: Vec<String> = ["two words", "$(touch pwned)", "`touch pwned`", "; touch pwned", "'quoted\" \\"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        process_manager
+            .start_process_with_mode(instance_id, "/bin/echo", &args, &[], &working_dir, StartMode::Detached, None, false, LineStamp::off(), false)
+            .await
+            .unwrap();
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        let expected = args.join(" ");
+        loop {
+            let history = process_manager.get_output_history(&instance_id).await.unwrap_or_default();
+            if history.iter().any(|line| line.ends_with(&expected)) {
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline, "arguments were not passed verbatim: {:?}", history);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        assert!(!working_dir.join("pwned").exists());
+        assert!(!Path::new("pwned").exists());
+    }
+
+    #[tokio::test]
+    async fn launching_another_instance_of_a_program_leaves_the_running_ones_alone() {
+        crate::test_support::use_scratch_dir();
+        let process_manager = ProcessManager::new();
+        let args = ["30".to_string()];
+        let mut instances = Vec::new();
+        for _ in 0..3 {
+            let instance_id = Uuid::new_v4();
+            let pid = process_manager
+                .start_process_with_mode(instance_id, "/bin/sleep", &args, &[], &PathBuf::from("/"), StartMode::Detached, None, false, LineStamp::off(), false)
+                .await
+                .unwrap();
+            instances.push((instance_id, pid));
+        }
+
+        let mut pids: Vec<u32> = instances.iter().map(|(_, pid)| *pid).collect();
+        pids.sort();
+        pids.dedup();
+        assert_eq!(pids.len(), 3, "each launch must track its own process: {:?}", pids);
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        for (instance_id, pid) in &instances {
+            assert!(!process_manager.process_exited(instance_id).await, "process {} was killed", pid);
+            assert_eq!(process_manager.get_process_pid(instance_id).await, Some(*pid));
+        }
+
+        for (instance_id, _) in &instances {
+            process_manager.stop_process(instance_id).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn timestamped_lines_parse_back_in_capture_order() {
+        crate::test_support::use_scratch_dir();
+        let process_manager = ProcessManager::new();
+        let args = ["-c".to_string(), "echo one; sleep 0.2; echo two; sleep 0.2; echo three; sleep 30".to_string()];
+
+        for format in [OutputTimestamps::Rfc3339, OutputTimestamps::Relative] {
+            let instance_id = Uuid::new_v4();
+            let started_at = Utc::now();
+            let output_file = std::env::current_dir().unwrap().join(format!("stamped-{}.log", instance_id));
+            process_manager
+                .start_process_with_mode(instance_id, "/bin/sh", &args, &[], &PathBuf::from("/"), StartMode::Normal, Some(&output_file), false, LineStamp::new(format, started_at), false)
+                .await
+                .unwrap();
+
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+            let history = loop {
+                let history = process_manager.get_output_history(&instance_id).await.unwrap_or_default();
+                if history.len() >= 3 {
+                    break history;
+                }
+                assert!(std::time::Instant::now() < deadline, "{:?} output never captured: {:?}", format, history);
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            };
+
+            // "<stream prefix> <stamp> <line>"
+            let stamped: Vec<(DateTime<Utc>, &str)> = history
+                .iter()
+                .map(|line| {
+                    let mut fields = line.splitn(3, ' ');
+                    let (_prefix, stamp, text) = (fields.next().unwrap(), fields.next().unwrap(), fields.next().unwrap());
+                    (OutputTimestamps::parse_stamp(stamp, started_at).unwrap_or_else(|| panic!("unparsable stamp in {:?}", line)), text)
+                })
+                .collect();
+            assert_eq!(stamped.iter().map(|(_, text)| *text).collect::<Vec<_>>(), ["one", "two", "three"]);
+            for pair in stamped.windows(2) {
+                assert!(pair[1].0 - pair[0].0 >= chrono::Duration::milliseconds(150), "{:?}: {:?}", format, history);
+            }
+            assert!(stamped[0].0 >= started_at - chrono::Duration::seconds(1) && stamped[2].0 <= Utc::now(), "{:?}", history);
+            // The output file keeps the raw lines
+            assert_eq!(std::fs::read_to_string(&output_file).unwrap(), "one\ntwo\nthree\n");
+
+            process_manager.stop_process(&instance_id).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn input_to_a_process_reading_dev_null_is_refused() {
+        crate::test_support::use_scratch_dir();
+        let mut child = std::process::Command::new("sleep").arg("30").stdin(std::process::Stdio::null()).spawn().unwrap();
+        let process_manager = ProcessManager::new();
+        let instance_id = Uuid::new_v4();
+        let stamp = LineStamp::new(OutputTimestamps::Off, Utc::now());
+        process_manager
+            .register_restored_process(instance_id, child.id(), None, stamp, false)
+            .await
+            .unwrap();
+
+        let error = process_manager.send_input(&instance_id, "lost".to_string()).await.unwrap_err();
+        assert!(error.to_string().contains("/dev/null"), "{}", error);
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[tokio::test]
+    async fn migrated_process_without_stream_logs_is_read_from_the_combined_log() {
+        crate::test_support::use_scratch_dir();
+        let mut child = std::process::Command::new("sleep").arg("30").spawn().unwrap();
+        let process_manager = ProcessManager::new();
+        let instance_id = Uuid::new_v4();
+        let stamp = LineStamp::new(OutputTimestamps::Off, Utc::now());
+        process_manager
+            .register_migrated_process(instance_id, child.id(), "sleep", &[], &PathBuf::from("."), stamp)
+            .await
+            .unwrap();
+
+        let combined_log = ProcessManager::instance_output_dir(&instance_id).join(COMBINED_LOG);
+        std::fs::write(&combined_log, "written before the migration\n").unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            let history = process_manager.get_output_history(&instance_id).await.unwrap_or_default();
+            if history.iter().any(|line| line == "written before the migration") {
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline, "combined log never read: {:?}", history);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[tokio::test]
+    async fn stderr_only_output_lands_in_stderr_log_and_is_filterable() {
+        crate::test_support::use_scratch_dir();
+        let process_manager = ProcessManager::new();
+        let instance_id = Uuid::new_v4();
+        let args = ["-c".to_string(), "echo only-on-stderr >&2; sleep 30".to_string()];
+        process_manager
+            .start_process_with_mode(instance_id, "/bin/sh", &args, &[], &PathBuf::from("/"), StartMode::Detached, None, false, LineStamp::off(), false)
+            .await
+            .unwrap();
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        let history = loop {
+            let history = process_manager.get_output_history(&instance_id).await.unwrap_or_default();
+            if !history.is_empty() {
+                break history;
+            }
+            assert!(std::time::Instant::now() < deadline, "no output captured");
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        };
+
+        let output_dir = ProcessManager::instance_output_dir(&instance_id);
+        assert_eq!(std::fs::read_to_string(output_dir.join(LogStream::Stderr.file_name())).unwrap(), "only-on-stderr\n");
+        assert_eq!(std::fs::read_to_string(output_dir.join(LogStream::Stdout.file_name())).unwrap(), "");
+        assert!(std::fs::read_to_string(output_dir.join(COMBINED_LOG)).unwrap().contains("only-on-stderr"));
+        let lines_of = |stream: LogStream| history.iter().filter(|line| line.starts_with(stream.prefix())).count();
+        assert_eq!((lines_of(LogStream::Stderr), lines_of(LogStream::Stdout)), (1, 0), "{:?}", history);
+
+        process_manager.stop_process(&instance_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn append_only_logs_every_line_past_the_history_cap() {
+        crate::test_support::use_scratch_dir();
+        let process_manager = ProcessManager::new();
+        let instance_id = Uuid::new_v4();
+        // Well past the 1000 lines tailed history keeps in memory
+        let args = ["1".to_string(), "2500".to_string()];
+        process_manager
+            .start_process_with_mode(instance_id, "seq", &args, &[], &PathBuf::from("/"), StartMode::Normal, None, false, LineStamp::off(), true)
+            .await
+            .unwrap();
+
+        let log_path = ProcessManager::instance_output_dir(&instance_id).join(COMBINED_LOG);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        let logged = loop {
+            let logged = std::fs::read_to_string(&log_path).unwrap_or_default();
+            if logged.lines().count() >= 2500 {
+                break logged;
+            }
+            assert!(std::time::Instant::now() < deadline, "only {} lines logged", logged.lines().count());
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        };
+        let expected: Vec<String> = (1..=2500).map(|n| format!("[STDOUT] {}", n)).collect();
+        assert_eq!(logged.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[tokio::test]
+    async fn input_to_a_process_not_reading_stdin_is_refused_until_it_drains() {
+        crate::test_support::use_scratch_dir();
+        let process_manager = ProcessManager::new();
+        let instance_id = Uuid::new_v4();
+        let work_dir = std::env::current_dir().unwrap().join(format!("stdin-{}", instance_id));
+        std::fs::create_dir_all(&work_dir).unwrap();
+        // Leaves stdin alone until the `drain` file appears, then reads all of it
+        let args = ["-c".to_string(), "while [ ! -e drain ]; do sleep 0.05; done; cat > /dev/null".to_string()];
+        process_manager
+            .start_process_with_mode(instance_id, "sh", &args, &[], &work_dir, StartMode::Normal, None, false, LineStamp::off(), false)
+            .await
+            .unwrap();
+
+        // Large lines fill the pipe quickly; the queue behind it then fills up
+        let line = "x".repeat(4096);
+        let mut accepted = 0;
+        let error = loop {
+            match process_manager.send_input(&instance_id, line.clone()).await {
+                Ok(()) => accepted += 1,
+                Err(e) => break e.to_string(),
+            }
+            assert!(accepted < 10 * STDIN_BUFFER_CAPACITY, "input was never refused");
+            tokio::task::yield_now().await;
+        };
+        assert!(error.contains("Input buffer full"), "{}", error);
+        assert!(accepted >= STDIN_BUFFER_CAPACITY, "refused after {} lines", accepted);
+
+        std::fs::write(work_dir.join("drain"), "").unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while let Err(e) = process_manager.send_input(&instance_id, "more".to_string()).await {
+            assert!(std::time::Instant::now() < deadline, "input still refused after the process drained stdin: {}", e);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+
+        process_manager.stop_process(&instance_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_larger_output_channel_buffers_more_lines_before_a_viewer_lags() {
+        crate::test_support::use_scratch_dir();
+        // The output starts after a pause, so the viewer subscribes before the first line
+        let args = ["-c".to_string(), "sleep 0.3; seq 1 100; sleep 30".to_string()];
+        let mut skipped_per_capacity = Vec::new();
+        for capacity in [16, 256] {
+            let mut process_manager = ProcessManager::new();
+            process_manager.set_output_channel_capacity(capacity);
+            let instance_id = Uuid::new_v4();
+            process_manager
+                .start_process_with_mode(instance_id, "/bin/sh", &args, &[], &PathBuf::from("/"), StartMode::Normal, None, false, LineStamp::off(), false)
+                .await
+                .unwrap();
+            let mut viewer = process_manager.subscribe_to_output(&instance_id).await.unwrap();
+
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+            while process_manager.get_output_history(&instance_id).await.unwrap_or_default().len() < 100 {
+                assert!(std::time::Instant::now() < deadline, "output never captured");
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+            let (mut received, mut skipped) = (0, 0);
+            loop {
+                match viewer.try_recv() {
+                    Ok(_) => received += 1,
+                    Err(tokio::sync::broadcast::error::TryRecvError::Lagged(n)) => skipped += n,
+                    Err(_) => break,
+                }
+            }
+            assert_eq!(received + skipped, 100);
+            assert_eq!(received, capacity.min(100) as u64);
+            skipped_per_capacity.push(skipped);
+            process_manager.stop_process(&instance_id).await.unwrap();
+        }
+        assert_eq!(skipped_per_capacity, [84, 0]);
+    }
+
+    #[tokio::test]
+    async fn detached_programs_are_launched_natively_without_a_shell() {
+        crate::test_support::use_scratch_dir();
+        let process_manager = ProcessManager::new();
+        let instance_id = Uuid::new_v4();
+        let pid = process_manager
+            .start_process_with_mode(instance_id, "/bin/sleep", &["30".to_string()], &[], &PathBuf::from("/"), StartMode::Detached, None, false, LineStamp::off(), false)
+            .await
+            .unwrap();
+
+        // The program itself is our child, leading its own session; no shell or script in between
+        let exe = std::fs::read_link(format!("/proc/{}/exe", pid)).unwrap();
+        assert_eq!(exe.file_name().unwrap(), "sleep", "launched through {:?}", exe);
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+        let fields: Vec<&str> = stat.rsplit(')').next().unwrap().split_whitespace().collect();
+        let (ppid, session) = (fields[1], fields[3]);
+        assert_eq!(ppid, std::process::id().to_string());
+        assert_eq!(session, pid.to_string());
+        assert!(!ProcessManager::instance_output_dir(&instance_id)
+            .read_dir()
+            .unwrap()
+            .flatten()
+            .any(|entry| entry.path().extension().is_some_and(|ext| ext == "sh")));
+
+        process_manager.stop_process(&instance_id).await.unwrap();
+    }
+}
@@ -1,25 +1,152 @@
+use crate::audit::AuditLog;
+use crate::checkpoint_engine::{CheckpointEngine, EngineOutput, RestoreRequest};
+use crate::cluster_state::ClusterStateManager;
+use crate::distributed_registry::DistributedInstanceRegistry;
 use crate::message_protocol::*;
-use crate::types::{Instance, InstanceStatus};
+use crate::network_manager::OutboundQueue;
+use crate::sudo_utils::PrivilegeCommand;
+use crate::types::{CheckpointStorage, Instance, InstanceStatus, ShadowError, ShadowResult};
 use crate::instance::InstanceManager;
 use crate::process_manager::ProcessManager;
+use crate::streaming_manager::StreamingManager;
 use anyhow::{Result, Context};
 use chrono::{DateTime, Utc};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::os::unix::process::ExitStatusExt;
-use tokio::sync::{RwLock, mpsc};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::RwLock;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+/// Acknowledged instance syncs: sync ID -> node -> shadows it holds
+type SyncAcks = HashMap<Uuid, HashMap<NodeId, Vec<Uuid>>>;
+
 /// Manages shadow instances across the cluster
 pub struct ShadowInstanceManager {
     local_node_id: NodeId,
     instance_manager: Arc<tokio::sync::Mutex<InstanceManager>>,
     process_manager: Arc<ProcessManager>,
     shadow_registry: Arc<RwLock<HashMap<Uuid, ShadowInstanceInfo>>>,
-    network_sender: Option<mpsc::UnboundedSender<NetworkMessage>>,
-    criu_path: PathBuf,
+    network_sender: Option<OutboundQueue>,
+    engine: Arc<dyn CheckpointEngine>,
+    output_buffer_limit: usize,
+    /// Sequences output and checkpoints sent to shadows, and reorders what shadows receive
+    streaming: Arc<StreamingManager>,
+    /// Output collected during the current batch window, per instance
+    pending_output: Arc<Mutex<HashMap<Uuid, Vec<u8>>>>,
+    output_batch_window: Duration,
+    /// Input forwarded from local shadow attach sessions, by input ID
+    input_deliveries: Arc<RwLock<HashMap<Uuid, InputDelivery>>>,
+    /// Acknowledged instance syncs awaited by `cluster connect --wait-ready`
+    sync_acks: Arc<RwLock<SyncAcks>>,
+    /// Failover claims this node made or granted, by instance
+    failover_claims: Arc<RwLock<HashMap<Uuid, FailoverClaim>>>,
+    /// How long a migration restore may run before it is treated as hung
+    restore_timeout: Duration,
+    /// Nodes attached to a live view of a local instance, with when their request lapses
+    live_viewers: Arc<Mutex<HashMap<Uuid, HashMap<NodeId, std::time::Instant>>>>,
+    /// Deduplication and the key that opens sealed checkpoints
+    storage: CheckpointStorage,
+    /// Wrapper for killing a hung restore, which runs with the engine's privileges
+    privilege_command: PrivilegeCommand,
+    audit: AuditLog,
+}
+
+/// This node's part in the failover of an orphaned instance
+#[derive(Debug, Clone)]
+enum FailoverClaim {
+    /// This node claimed the instance and waits for the peers in `waiting` to answer
+    Pending { claim_id: Uuid, waiting: BTreeSet<NodeId>, denied: bool },
+    /// `node_id` (possibly this node) may take over the instance
+    Granted { node_id: NodeId, at: std::time::Instant },
+}
+
+/// Delivery state of input forwarded from a shadow attach session to the source node
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputDelivery {
+    Pending,
+    Delivered,
+    Rejected(String),
+}
+
+/// How long a shadow attach session waits for the source node to acknowledge input
+pub const SHADOW_INPUT_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a failover claim waits for every peer to answer before it is given up
+pub const FAILOVER_CLAIM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a node stands by a failover claim it granted (or won) before it answers other
+/// claimants for the same instance again
+pub const FAILOVER_GRANT_TTL: Duration = Duration::from_secs(60);
+
+/// How long a live output request holds without being renewed
+pub const LIVE_OUTPUT_LEASE: Duration = Duration::from_secs(15);
+
+/// How often a shadow attach session renews its live output request
+pub const LIVE_OUTPUT_RENEW_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Default number of recent output bytes kept in memory per shadow instance
+pub const DEFAULT_SHADOW_OUTPUT_BUFFER_BYTES: usize = 1024 * 1024;
+
+/// Default window over which output is collected into one shadow sync message
+pub const DEFAULT_SHADOW_OUTPUT_BATCH_MS: u64 = 100;
+
+/// Default time a migration restore may take before it is aborted
+pub const DEFAULT_RESTORE_TIMEOUT_SECS: u64 = 10;
+
+/// How the node that takes over an instance is chosen when its source node goes offline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailoverPolicy {
+    /// The online node with the lowest node ID takes over every orphaned instance
+    LowestNodeId,
+    /// Each instance goes to the node with the highest hash of (instance, node), spreading the load
+    Rendezvous,
+}
+
+impl FailoverPolicy {
+    /// Pick the node that should take over `instance_id`; every node computes the same answer
+    /// from the same candidate set
+    pub fn select_node(&self, instance_id: Uuid, candidates: &[NodeId]) -> Option<NodeId> {
+        match self {
+            FailoverPolicy::LowestNodeId => candidates.iter().min().copied(),
+            FailoverPolicy::Rendezvous => candidates
+                .iter()
+                .max_by_key(|node_id| (rendezvous_weight(instance_id, **node_id), **node_id))
+                .copied(),
+        }
+    }
+}
+
+impl std::fmt::Display for FailoverPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailoverPolicy::LowestNodeId => write!(f, "lowest-node-id"),
+            FailoverPolicy::Rendezvous => write!(f, "rendezvous"),
+        }
+    }
+}
+
+impl std::str::FromStr for FailoverPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "lowest-node-id" => Ok(FailoverPolicy::LowestNodeId),
+            "rendezvous" => Ok(FailoverPolicy::Rendezvous),
+            _ => Err(format!("Unknown failover policy '{}' (expected lowest-node-id or rendezvous)", s)),
+        }
+    }
+}
+
+/// FNV-1a over both IDs; stable across builds, unlike the std hasher
+fn rendezvous_weight(instance_id: Uuid, node_id: NodeId) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in instance_id.as_bytes().iter().chain(node_id.as_bytes()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
 }
 
 /// Information about a shadow instance
@@ -30,50 +157,112 @@ pub struct ShadowInstanceInfo {
     pub created_at: DateTime<Utc>,
     pub last_sync_time: DateTime<Utc>,
     pub output_buffer: Vec<u8>,
+    pub output_bytes_total: u64, // Bytes ever appended, including those trimmed from output_buffer
     pub latest_checkpoint: Option<Vec<u8>>,
     pub data_version: u64,
+    pub checkpoint_version: u64, // data_version of latest_checkpoint, 0 before the first
+    pub missing_from_source_since: Option<DateTime<Utc>>, // First complete advertisement of the source without it
 }
 
-impl ShadowInstanceManager {
-    pub fn new(local_node_id: NodeId, instance_manager: Arc<tokio::sync::Mutex<InstanceManager>>, process_manager: Arc<ProcessManager>) -> Self {
-        Self::new_with_criu_path(local_node_id, instance_manager, process_manager, "./criu/bin/criu")
+impl ShadowInstanceInfo {
+    /// Append output, keeping only the last `limit` bytes in memory
+    pub fn append_output(&mut self, data: &[u8], limit: usize) {
+        self.output_buffer.extend_from_slice(data);
+        self.output_bytes_total += data.len() as u64;
+
+        if self.output_buffer.len() > limit {
+            let mut excess = self.output_buffer.len() - limit;
+            // Don't leave a partial UTF-8 sequence at the start of the buffer
+            while excess < self.output_buffer.len() && (self.output_buffer[excess] & 0xC0) == 0x80 {
+                excess += 1;
+            }
+            self.output_buffer.drain(..excess);
+        }
     }
+}
 
-    pub fn new_with_criu_path<P: AsRef<Path>>(
+impl ShadowInstanceManager {
+    /// Create a shadow manager driving the given checkpoint engine
+    pub fn new_with_engine(
         local_node_id: NodeId,
         instance_manager: Arc<tokio::sync::Mutex<InstanceManager>>,
         process_manager: Arc<ProcessManager>,
-        criu_path: P
+        engine: Arc<dyn CheckpointEngine>,
     ) -> Self {
-        let criu_path = PathBuf::from(criu_path.as_ref());
-
-        // Convert relative path to absolute path to avoid working directory issues
-        let criu_path = if criu_path.is_relative() {
-            match std::env::current_dir() {
-                Ok(current_dir) => current_dir.join(criu_path),
-                Err(e) => {
-                    warn!("Failed to get current directory: {}, using relative path", e);
-                    criu_path
-                }
-            }
-        } else {
-            criu_path
-        };
-
         Self {
             local_node_id,
             instance_manager,
             process_manager,
             shadow_registry: Arc::new(RwLock::new(HashMap::new())),
             network_sender: None,
-            criu_path,
+            engine,
+            output_buffer_limit: DEFAULT_SHADOW_OUTPUT_BUFFER_BYTES,
+            streaming: Arc::new(StreamingManager::new(local_node_id)),
+            pending_output: Arc::new(Mutex::new(HashMap::new())),
+            output_batch_window: Duration::from_millis(DEFAULT_SHADOW_OUTPUT_BATCH_MS),
+            input_deliveries: Arc::new(RwLock::new(HashMap::new())),
+            sync_acks: Arc::new(RwLock::new(HashMap::new())),
+            failover_claims: Arc::new(RwLock::new(HashMap::new())),
+            restore_timeout: Duration::from_secs(DEFAULT_RESTORE_TIMEOUT_SECS),
+            live_viewers: Arc::new(Mutex::new(HashMap::new())),
+            storage: CheckpointStorage::default(),
+            privilege_command: PrivilegeCommand::Sudo,
+            audit: AuditLog::default(),
         }
     }
 
-    pub fn set_network_sender(&mut self, sender: mpsc::UnboundedSender<NetworkMessage>) {
+    /// Deduplicate received checkpoints; the key opens sealed ones on restore
+    pub fn set_storage(&mut self, storage: CheckpointStorage) {
+        self.storage = storage;
+    }
+
+    /// Set the wrapper the checkpoint engine runs CRIU through
+    pub fn set_privilege_command(&mut self, command: PrivilegeCommand) {
+        self.privilege_command = command;
+    }
+
+    /// Record failovers, promotions and demotions in this audit log
+    pub fn set_audit_log(&mut self, audit: AuditLog) {
+        self.audit = audit;
+    }
+
+    pub fn set_network_sender(&mut self, sender: OutboundQueue) {
         self.network_sender = Some(sender);
     }
 
+    /// Set how many recent output bytes each shadow keeps in memory (full history stays in the output file)
+    pub fn set_output_buffer_limit(&mut self, limit: usize) {
+        self.output_buffer_limit = limit;
+    }
+
+    /// Set how long output is collected before it is sent to shadows; zero sends every chunk at once
+    pub fn set_output_batch_window(&mut self, window: Duration) {
+        self.output_batch_window = window;
+    }
+
+    /// Set how long a migration restore may run before it is killed and reported as failed
+    pub fn set_restore_timeout(&mut self, timeout: Duration) {
+        self.restore_timeout = timeout;
+    }
+
+    /// How a locally running instance is advertised to other nodes
+    fn instance_info(&self, instance: &Instance) -> InstanceInfo {
+        InstanceInfo {
+            id: instance.id,
+            program: instance.program.clone(),
+            args: instance.args.clone(),
+            status: instance.status.clone(),
+            node_id: self.local_node_id,
+            created_at: instance.created_at,
+            source_node_id: None,
+            affinity: instance.affinity.clone(),
+            output_timestamps: instance.output_timestamps,
+            labels: instance.labels.clone(),
+            has_checkpoint: false,
+            env: instance.env.clone(),
+        }
+    }
+
     /// Broadcast instance creation to all other nodes (they will create shadow instances)
     pub async fn broadcast_instance_creation(&self, instance: &Instance) -> Result<()> {
         if instance.status != InstanceStatus::Running {
@@ -82,25 +271,17 @@ impl ShadowInstanceManager {
 
         // Broadcast instance creation to all nodes
         if let Some(network_sender) = &self.network_sender {
-            let instance_info = InstanceInfo {
-                id: instance.id,
-                program: instance.program.clone(),
-                args: instance.args.clone(),
-                status: instance.status.clone(),
-                node_id: self.local_node_id,
-                created_at: instance.created_at,
-                source_node_id: None,
-            };
-
             let sync_message = InstanceSyncMessage {
                 sender_id: self.local_node_id,
-                instances: vec![instance_info],
+                instances: vec![self.instance_info(instance)],
                 timestamp: Utc::now(),
+                sync_id: None,
+                complete: false,
             };
 
             let network_message = NetworkMessage::InstanceSync(sync_message);
 
-            if let Err(e) = network_sender.send(network_message) {
+            if let Err(e) = network_sender.send(network_message).await {
                 error!("Failed to broadcast instance creation: {}", e);
             } else {
                 info!("Broadcasted instance creation for {}", instance.short_id());
@@ -110,21 +291,155 @@ impl ShadowInstanceManager {
         Ok(())
     }
 
+    /// Re-advertise every locally running instance in one `InstanceSync`. Creation broadcasts
+    /// are fire-and-forget; nodes that missed one create the shadow from this instead.
+    pub async fn advertise_running_instances(&self) -> Result<usize> {
+        let instances = self.running_instance_infos().await;
+        // An empty list is still sent: it tells peers their shadows of this node are orphaned
+        let count = instances.len();
+        self.send_instance_sync(instances, None).await?;
+        debug!("Re-advertised {} running instance(s)", count);
+        Ok(count)
+    }
+
+    /// Advertise every locally running instance and ask receivers to acknowledge once their
+    /// shadows exist. Returns the sync ID to poll `sync_ack` with and the advertised instances;
+    /// sending again under the same ID covers peers that were not connected yet.
+    pub async fn advertise_for_ack(&self, sync_id: Option<Uuid>) -> Result<(Uuid, Vec<Uuid>)> {
+        let sync_id = sync_id.unwrap_or_else(Uuid::new_v4);
+        self.sync_acks.write().await.entry(sync_id).or_default();
+
+        let instances = self.running_instance_infos().await;
+        let instance_ids = instances.iter().map(|info| info.id).collect();
+        if let Err(e) = self.send_instance_sync(instances, Some(sync_id)).await {
+            self.sync_acks.write().await.remove(&sync_id);
+            return Err(e);
+        }
+        Ok((sync_id, instance_ids))
+    }
+
+    /// Shadows `node_id` reported holding in answer to sync `sync_id`, once it has answered
+    pub async fn sync_ack(&self, sync_id: Uuid, node_id: NodeId) -> Option<Vec<Uuid>> {
+        self.sync_acks.read().await.get(&sync_id)?.get(&node_id).cloned()
+    }
+
+    /// Stop collecting acknowledgements for `sync_id`
+    pub async fn forget_sync(&self, sync_id: Uuid) {
+        self.sync_acks.write().await.remove(&sync_id);
+    }
+
+    /// Record a peer's answer to an acknowledged instance sync
+    pub async fn handle_instance_sync_ack(&self, ack: InstanceSyncAckMessage) {
+        if ack.target_node_id != self.local_node_id {
+            return;
+        }
+        // Acks for syncs nobody waits on any more are dropped
+        if let Some(acks) = self.sync_acks.write().await.get_mut(&ack.sync_id) {
+            acks.insert(ack.sender_id, ack.shadowed);
+        }
+    }
+
+    async fn running_instance_infos(&self) -> Vec<InstanceInfo> {
+        let instance_manager = self.instance_manager.lock().await;
+        instance_manager.get_all_instances()
+            .into_iter()
+            .filter(|instance| instance.status == InstanceStatus::Running)
+            .map(|instance| self.instance_info(&instance))
+            .collect()
+    }
+
+    async fn send_instance_sync(&self, instances: Vec<InstanceInfo>, sync_id: Option<Uuid>) -> Result<()> {
+        let network_sender = match &self.network_sender {
+            Some(sender) => sender,
+            None => return Ok(()),
+        };
+        let sync_message = InstanceSyncMessage {
+            sender_id: self.local_node_id,
+            instances,
+            timestamp: Utc::now(),
+            sync_id,
+            complete: true,
+        };
+        network_sender.send(NetworkMessage::InstanceSync(sync_message)).await?;
+        Ok(())
+    }
+
+    /// Ask every peer to re-advertise its running instances
+    pub async fn request_instance_sync(&self) -> Result<()> {
+        if let Some(network_sender) = &self.network_sender {
+            let request = RequestInstanceSyncMessage {
+                sender_id: self.local_node_id,
+                timestamp: Utc::now(),
+            };
+            network_sender.send(NetworkMessage::RequestInstanceSync(request)).await?;
+            info!("Requested instance sync from peers");
+        }
+        Ok(())
+    }
+
     /// Handle incoming instance sync message (create shadow instances)
     pub async fn handle_instance_sync(&self, sync_message: InstanceSyncMessage) -> Result<()> {
         if sync_message.sender_id == self.local_node_id {
             return Ok(()); // Ignore our own messages
         }
 
+        let advertised: Vec<Uuid> = sync_message.instances.iter().map(|info| info.id).collect();
+        if sync_message.complete {
+            self.mark_missing_from_source(sync_message.sender_id, &sync_message.instances).await;
+        }
         for instance_info in sync_message.instances {
-            if instance_info.status == InstanceStatus::Running {
-                self.create_local_shadow_instance(&instance_info, sync_message.sender_id).await?;
+            if instance_info.status != InstanceStatus::Running {
+                continue;
             }
+            // Periodic re-advertisements repeat instances we already shadow from the same node
+            let up_to_date = self.shadow_registry.read().await
+                .get(&instance_info.id)
+                .is_some_and(|shadow| shadow.source_node_id == sync_message.sender_id);
+            if up_to_date {
+                continue;
+            }
+            self.create_local_shadow_instance(&instance_info, sync_message.sender_id).await?;
+        }
+
+        // The sender waits for this answer (cluster connect --wait-ready)
+        if let (Some(sync_id), Some(network_sender)) = (sync_message.sync_id, &self.network_sender) {
+            let shadowed: Vec<Uuid> = {
+                let registry = self.shadow_registry.read().await;
+                advertised.into_iter()
+                    .filter(|id| registry.get(id).is_some_and(|shadow| shadow.source_node_id == sync_message.sender_id))
+                    .collect()
+            };
+            let ack = InstanceSyncAckMessage {
+                sender_id: self.local_node_id,
+                target_node_id: sync_message.sender_id,
+                sync_id,
+                shadowed,
+                timestamp: Utc::now(),
+            };
+            network_sender.send(NetworkMessage::InstanceSyncAck(ack)).await?;
         }
 
         Ok(())
     }
 
+    /// Note which shadows of `source_node_id` its complete advertisement no longer runs
+    async fn mark_missing_from_source(&self, source_node_id: NodeId, advertised: &[InstanceInfo]) {
+        let running: HashSet<Uuid> = advertised.iter()
+            .filter(|info| info.status == InstanceStatus::Running)
+            .map(|info| info.id)
+            .collect();
+        let now = Utc::now();
+        let mut registry = self.shadow_registry.write().await;
+        for shadow in registry.values_mut().filter(|shadow| shadow.source_node_id == source_node_id) {
+            if running.contains(&shadow.instance_id) {
+                shadow.missing_from_source_since = None;
+            } else if shadow.missing_from_source_since.is_none() {
+                debug!("Source node {} no longer runs instance {}", source_node_id, shadow.instance_id);
+                shadow.missing_from_source_since = Some(now);
+            }
+        }
+    }
+
     /// Create a local shadow instance from remote instance info
     async fn create_local_shadow_instance(&self, instance_info: &InstanceInfo, source_node_id: NodeId) -> Result<()> {
         // Check if we already have this instance (running or shadow)
@@ -175,6 +490,16 @@ impl ShadowInstanceManager {
                         info!("✅ [MIGRATION_SYNC] Successfully converted running instance {} to shadow", instance_info.id);
                         return Ok(());
                     }
+                } else if existing_instance.status == InstanceStatus::Shadow
+                    && instance_info.status == InstanceStatus::Running
+                    && existing_instance.source_node_id != Some(source_node_id)
+                {
+                    // The instance was taken over by another node (migration or failover)
+                    info!("🔄 [MIGRATION_SYNC] Shadow {} now follows source node {}", instance_info.id, source_node_id);
+                    drop(instance_manager);
+                    self.retarget_shadow(instance_info.id, source_node_id).await;
+                    self.announce_shadow(instance_info.id).await;
+                    return Ok(());
                 } else {
                     info!("ℹ️ [MIGRATION_SYNC] Instance {} already exists locally with status {:?}, remote status {:?}, skipping",
                            instance_info.id, existing_instance.status, instance_info.status);
@@ -197,9 +522,13 @@ impl ShadowInstanceManager {
 
             // Override the UUID to match the source instance
             shadow_instance.id = instance_info.id;
-            shadow_instance.status = InstanceStatus::Shadow;
+            shadow_instance.set_status(InstanceStatus::Shadow)?;
             shadow_instance.source_node_id = Some(source_node_id);
             shadow_instance.created_at = instance_info.created_at;
+            shadow_instance.affinity = instance_info.affinity.clone();
+            shadow_instance.output_timestamps = instance_info.output_timestamps;
+            shadow_instance.labels = instance_info.labels.clone();
+            shadow_instance.env = instance_info.env.clone();
             shadow_instance.pid = None; // Shadow instances don't have actual processes
 
             // Ensure the instance directory structure is created for shadow instances
@@ -242,28 +571,127 @@ impl ShadowInstanceManager {
                 created_at: Utc::now(),
                 last_sync_time: Utc::now(),
                 output_buffer: Vec::new(),
+                output_bytes_total: 0,
                 latest_checkpoint: None,
                 data_version: 0,
+                checkpoint_version: 0,
+                missing_from_source_since: None,
             };
             registry.insert(instance_info.id, shadow_info);
         }
 
         info!("Created shadow instance {} from source node {}", instance_info.id, source_node_id);
+        self.announce_shadow(instance_info.id).await;
         Ok(())
     }
 
+    /// Tell the cluster this node holds a shadow of the instance, so `locate` can list it and
+    /// failover knows whether it has a checkpoint. Nodes only create shadows from running
+    /// entries, so the announcement creates none.
+    async fn announce_shadow(&self, instance_id: Uuid) {
+        let network_sender = match &self.network_sender {
+            Some(network_sender) => network_sender,
+            None => return,
+        };
+        let has_checkpoint = self.shadow_registry.read().await
+            .get(&instance_id)
+            .is_some_and(|shadow| shadow.latest_checkpoint.is_some());
+        let instance_info = {
+            let instance_manager = self.instance_manager.lock().await;
+            match instance_manager.get_instance_by_id(&instance_id.to_string()) {
+                Some(instance) if instance.status == InstanceStatus::Shadow => InstanceInfo {
+                    id: instance.id,
+                    program: instance.program.clone(),
+                    args: instance.args.clone(),
+                    status: InstanceStatus::Shadow,
+                    node_id: self.local_node_id,
+                    created_at: instance.created_at,
+                    source_node_id: instance.source_node_id,
+                    affinity: instance.affinity.clone(),
+                    output_timestamps: instance.output_timestamps,
+                    labels: instance.labels.clone(),
+                    has_checkpoint,
+                    env: instance.env.clone(),
+                },
+                _ => return,
+            }
+        };
+
+        let sync_message = InstanceSyncMessage {
+            sender_id: self.local_node_id,
+            instances: vec![instance_info],
+            timestamp: Utc::now(),
+            sync_id: None,
+            complete: false,
+        };
+        if let Err(e) = network_sender.send(NetworkMessage::InstanceSync(sync_message)).await {
+            warn!("Failed to announce shadow of instance {}: {}", instance_id, e);
+        }
+    }
+
     /// Handle incoming shadow data synchronization
-    pub async fn handle_shadow_sync(&self, sync_message: ShadowSyncMessage) -> Result<()> {
+    pub async fn handle_shadow_sync(&self, mut sync_message: ShadowSyncMessage) -> Result<()> {
         if sync_message.sender_id == self.local_node_id {
             return Ok(()); // Ignore our own messages
         }
+        sync_message.decompress_output().context("Failed to decompress shadow output")?;
 
-        let instance_id = sync_message.instance_id;
-        let sender_id = sync_message.sender_id;
+        info!("Received shadow sync for instance {} from node {}", sync_message.instance_id, sync_message.sender_id);
+        self.demote_if_running_elsewhere(sync_message.instance_id, sync_message.sender_id).await?;
+        self.apply_shadow_sync(sync_message, true).await
+    }
+
+    /// Handle a chunk of an instance's shadow stream, applying whatever is now in order
+    pub async fn handle_stream_chunk(&self, message: StreamChunkMessage) -> Result<()> {
+        if message.sender_id == self.local_node_id {
+            return Ok(()); // Ignore our own messages
+        }
+        let instance_id = message.chunk.instance_id;
+        let sender_id = message.sender_id;
+        let timestamp = message.timestamp;
+        debug!("Received stream chunk {} for instance {} from node {}", message.chunk.seq, instance_id, sender_id);
+
+        self.demote_if_running_elsewhere(instance_id, sender_id).await?;
+
+        let chunks = self.streaming.receive(message).await;
+        self.apply_stream_chunks(sender_id, timestamp, chunks).await
+    }
 
-        info!("Received shadow sync for instance {} from node {}", instance_id, sender_id);
+    /// Deliver chunks held back by a gap that timed out while no further chunk arrived
+    pub async fn flush_stream_gaps(&self) -> Result<()> {
+        for (sender_id, chunk) in self.streaming.flush_expired().await {
+            self.apply_stream_chunks(sender_id, Utc::now(), vec![chunk]).await?;
+        }
+        Ok(())
+    }
 
-        // Check if we have a running instance that should be demoted to shadow
+    /// Apply in-order stream chunks as shadow syncs
+    async fn apply_stream_chunks(&self, sender_id: NodeId, timestamp: DateTime<Utc>, chunks: Vec<StreamChunk>) -> Result<()> {
+        for chunk in chunks {
+            let instance_id = chunk.instance_id;
+            let (checkpoint_data, output_data) = match chunk.kind {
+                StreamKind::Checkpoint => (Some(chunk.data), None),
+                StreamKind::Output => (None, Some(chunk.data)),
+            };
+            let mut sync_message = ShadowSyncMessage {
+                sender_id,
+                instance_id,
+                data_version: chunk.seq,
+                checkpoint_data,
+                output_data,
+                timestamp,
+                is_migration: false,
+                output_compressed: chunk.compressed,
+            };
+            sync_message.decompress_output().context("Failed to decompress shadow output")?;
+            // The stream is already in order, so the version check does not apply
+            self.apply_shadow_sync(sync_message, false).await?;
+        }
+        Ok(())
+    }
+
+    /// A running instance that another node sends shadow data for was migrated away; demote it
+    async fn demote_if_running_elsewhere(&self, instance_id: Uuid, sender_id: NodeId) -> Result<()> {
         {
             let instance_manager = self.instance_manager.lock().await;
 
@@ -292,13 +720,23 @@ impl ShadowInstanceManager {
                 }
             }
         }
+        Ok(())
+    }
 
+    /// Save a shadow sync's checkpoint and output, creating the shadow if needed. With
+    /// `check_version`, data no newer than what the shadow already has is ignored.
+    async fn apply_shadow_sync(&self, sync_message: ShadowSyncMessage, check_version: bool) -> Result<()> {
+        let instance_id = sync_message.instance_id;
+        let sender_id = sync_message.sender_id;
         let mut registry = self.shadow_registry.write().await;
+        let mut first_checkpoint = false;
 
         if let Some(shadow_info) = registry.get_mut(&instance_id) {
-            // Only update if the version is newer
-            if sync_message.data_version > shadow_info.data_version {
-                shadow_info.data_version = sync_message.data_version;
+            // Only update if the version is newer; migration checkpoints are outside the sequence
+            if sync_message.is_migration || !check_version || sync_message.data_version > shadow_info.data_version {
+                if !sync_message.is_migration {
+                    shadow_info.data_version = sync_message.data_version;
+                }
                 shadow_info.last_sync_time = Utc::now();
 
                 if let Some(checkpoint_data) = sync_message.checkpoint_data {
@@ -306,7 +744,11 @@ impl ShadowInstanceManager {
                     if let Err(e) = self.save_checkpoint_data(instance_id, &checkpoint_data).await {
                         warn!("Failed to save checkpoint data for instance {}: {}", instance_id, e);
                     } else {
+                        first_checkpoint = shadow_info.latest_checkpoint.is_none();
                         shadow_info.latest_checkpoint = Some(checkpoint_data.clone());
+                        if !sync_message.is_migration {
+                            shadow_info.checkpoint_version = sync_message.data_version;
+                        }
                         info!("Updated checkpoint data for shadow instance {}", instance_id);
 
                         // Check if this is a migration checkpoint and auto-restore
@@ -318,7 +760,7 @@ impl ShadowInstanceManager {
 
                 if let Some(output_data) = sync_message.output_data {
                     // Update in-memory buffer
-                    shadow_info.output_buffer.extend_from_slice(&output_data);
+                    shadow_info.append_output(&output_data, self.output_buffer_limit);
                     debug!("Updated output buffer for shadow instance {}", instance_id);
 
                     // Also write to output file for persistence
@@ -341,20 +783,25 @@ impl ShadowInstanceManager {
                     warn!("Failed to save checkpoint data for new shadow instance {}: {}", instance_id, e);
                 } else {
                     latest_checkpoint = Some(checkpoint_data);
+                    first_checkpoint = true;
                     info!("Saved checkpoint data for new shadow instance {}", instance_id);
                 }
             }
 
             let output_buffer = sync_message.output_data.unwrap_or_default();
-            let shadow_info = ShadowInstanceInfo {
+            let mut shadow_info = ShadowInstanceInfo {
                 instance_id,
                 source_node_id: sender_id,
                 created_at: sync_message.timestamp,
                 last_sync_time: sync_message.timestamp,
-                output_buffer: output_buffer.clone(),
+                output_buffer: Vec::new(),
+                output_bytes_total: 0,
                 latest_checkpoint,
                 data_version: sync_message.data_version,
+                checkpoint_version: if first_checkpoint && !sync_message.is_migration { sync_message.data_version } else { 0 },
+                missing_from_source_since: None,
             };
+            shadow_info.append_output(&output_buffer, self.output_buffer_limit);
 
             registry.insert(instance_id, shadow_info);
             info!("Created new shadow instance {} from node {}", instance_id, sender_id);
@@ -368,6 +815,12 @@ impl ShadowInstanceManager {
                 }
             }
         }
+        drop(registry);
+
+        // Failover only picks nodes known to hold a checkpoint
+        if first_checkpoint {
+            self.announce_shadow(instance_id).await;
+        }
 
         Ok(())
     }
@@ -393,153 +846,748 @@ impl ShadowInstanceManager {
 
     /// Stream output data from a running instance to all shadow instances
     pub async fn stream_output_to_shadows(&self, instance_id: Uuid, output_data: Vec<u8>, stream_type: StreamType) -> Result<()> {
+        let network_sender = match &self.network_sender {
+            Some(network_sender) => network_sender.clone(),
+            None => {
+                debug!("No network sender available for streaming output to shadows");
+                return Ok(());
+            }
+        };
+        if output_data.is_empty() {
+            return Ok(());
+        }
+
+        // A live viewer gets every chunk at once; anything still batched goes out first to keep the order
+        if self.has_live_viewer(instance_id) {
+            self.pending_output.lock().unwrap().entry(instance_id).or_default().extend_from_slice(&output_data);
+            Self::flush_output_batch(&self.pending_output, &self.streaming, &network_sender, instance_id).await;
+            return Ok(());
+        }
+
+        if self.output_batch_window.is_zero() {
+            if let Err(e) = self.streaming.send_next(&network_sender, instance_id, StreamKind::Output, || Some(output_data)).await {
+                error!("Failed to stream output to shadows: {}", e);
+            }
+            return Ok(());
+        }
+
+        // The first chunk of a window schedules the flush; later chunks join its batch
+        let first_in_window = {
+            let mut pending = self.pending_output.lock().unwrap();
+            let batch = pending.entry(instance_id).or_default();
+            let first = batch.is_empty();
+            batch.extend_from_slice(&output_data);
+            first
+        };
+        debug!("Queued {} bytes of {:?} output for shadows of instance {}", output_data.len(), stream_type, instance_id);
+
+        if first_in_window {
+            let pending_output = self.pending_output.clone();
+            let streaming = self.streaming.clone();
+            let window = self.output_batch_window;
+            tokio::spawn(async move {
+                tokio::time::sleep(window).await;
+                Self::flush_output_batch(&pending_output, &streaming, &network_sender, instance_id).await;
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Send whatever output is waiting for an instance without waiting for its window to close
+    async fn flush_pending_output(&self, instance_id: Uuid) {
         if let Some(network_sender) = &self.network_sender {
-            let data_version = self.get_next_data_version(instance_id).await;
-            debug!("Streaming output to shadows: {} bytes, version {} for instance {}",
-                   output_data.len(), data_version, instance_id);
+            Self::flush_output_batch(&self.pending_output, &self.streaming, network_sender, instance_id).await;
+        }
+    }
 
-            let sync_message = ShadowSyncMessage {
+    async fn flush_output_batch(
+        pending_output: &Mutex<HashMap<Uuid, Vec<u8>>>,
+        streaming: &StreamingManager,
+        network_sender: &OutboundQueue,
+        instance_id: Uuid,
+    ) {
+        // Take the batch while its sequence number is held so batches stay in order
+        let take = || pending_output.lock().unwrap().remove(&instance_id);
+        if let Err(e) = streaming.send_next(network_sender, instance_id, StreamKind::Output, take).await {
+            error!("Failed to stream output to shadows: {}", e);
+        }
+    }
+
+    /// Stream checkpoint data from a running instance to all shadow instances
+    pub async fn stream_checkpoint_to_shadows(&self, instance_id: Uuid, checkpoint_data: Vec<u8>) -> Result<()> {
+        if let Some(network_sender) = &self.network_sender {
+            match self.streaming.send_next(network_sender, instance_id, StreamKind::Checkpoint, || Some(checkpoint_data)).await {
+                Ok(_) => info!("Streamed checkpoint data for instance {} to shadow instances", instance_id),
+                Err(e) => error!("Failed to stream checkpoint to shadows: {}", e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Latest `data_version` this node has sent to the shadows of an instance it runs
+    pub async fn stream_position(&self, instance_id: Uuid) -> u64 {
+        self.streaming.last_sent(instance_id).await
+    }
+
+    /// Data version of the last checkpoint this node sent to the shadows of an instance it runs
+    pub async fn checkpoint_stream_position(&self, instance_id: Uuid) -> u64 {
+        self.streaming.last_checkpoint_sent(instance_id).await
+    }
+
+    /// Get all shadow instances managed by this node
+    pub async fn get_shadow_instances(&self) -> Vec<ShadowInstanceInfo> {
+        let registry = self.shadow_registry.read().await;
+        registry.values().cloned().collect()
+    }
+
+    /// Get shadow instance info by ID
+    pub async fn get_shadow_instance(&self, instance_id: Uuid) -> Option<ShadowInstanceInfo> {
+        let registry = self.shadow_registry.read().await;
+        registry.get(&instance_id).cloned()
+    }
+
+    /// Forward input from shadow instance to source instance
+    /// Forward input to the source node, returning the ID its acknowledgement will carry
+    /// (see `input_delivery`)
+    pub async fn forward_input_to_source(&self, shadow_instance_id: Uuid, input: String) -> ShadowResult<Uuid> {
+        let source_node_id = self.shadow_registry.read().await
+            .get(&shadow_instance_id)
+            .map(|shadow_info| shadow_info.source_node_id)
+            .ok_or(ShadowError::NoShadow(shadow_instance_id))?;
+        let network_sender = self.network_sender.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Networking is not available"))?;
+
+        let input_id = Uuid::new_v4();
+        self.input_deliveries.write().await.insert(input_id, InputDelivery::Pending);
+
+        let input_message = ShadowInputMessage {
+            sender_id: self.local_node_id,
+            target_node_id: source_node_id,
+            instance_id: shadow_instance_id,
+            input_data: input,
+            timestamp: Utc::now(),
+            input_id,
+        };
+
+        if let Err(e) = network_sender.send(NetworkMessage::ShadowInput(input_message)).await {
+            self.input_deliveries.write().await.remove(&input_id);
+            return Err(anyhow::anyhow!("Failed to forward input to source: {}", e).into());
+        }
+        debug!("Forwarded input {} to source node {} for instance {}", input_id, source_node_id, shadow_instance_id);
+        Ok(input_id)
+    }
+
+    /// Delivery state of forwarded input; a settled state is returned once and then forgotten
+    pub async fn input_delivery(&self, input_id: Uuid) -> Option<InputDelivery> {
+        let mut deliveries = self.input_deliveries.write().await;
+        match deliveries.get(&input_id) {
+            Some(InputDelivery::Pending) => Some(InputDelivery::Pending),
+            Some(_) => deliveries.remove(&input_id),
+            None => None,
+        }
+    }
+
+    /// Stop waiting for an acknowledgement, e.g. after `SHADOW_INPUT_ACK_TIMEOUT`
+    pub async fn forget_input(&self, input_id: Uuid) {
+        self.input_deliveries.write().await.remove(&input_id);
+    }
+
+    /// Hand input forwarded by a shadow to the local process and acknowledge it
+    pub async fn handle_shadow_input(
+        &self,
+        input: ShadowInputMessage,
+        peer_id: NodeId,
+        instance_registry: &DistributedInstanceRegistry,
+    ) -> Result<()> {
+        let result = match self.authorize_input(&input, peer_id, instance_registry).await {
+            Ok(()) => self.process_manager.send_input(&input.instance_id, input.input_data).await
+                .map_err(|e| e.to_string()),
+            Err(reason) => Err(reason),
+        };
+        if let Err(ref e) = result {
+            warn!("Failed to deliver shadow input to instance {}: {}", input.instance_id, e);
+        }
+
+        if let Some(network_sender) = &self.network_sender {
+            let ack = ShadowInputAckMessage {
                 sender_id: self.local_node_id,
-                instance_id,
-                data_version,
-                checkpoint_data: None,
-                output_data: Some(output_data.clone()),
+                target_node_id: input.sender_id,
+                instance_id: input.instance_id,
+                input_id: input.input_id,
+                accepted: result.is_ok(),
+                error: result.err(),
                 timestamp: Utc::now(),
             };
+            network_sender.send(NetworkMessage::ShadowInputAck(ack)).await
+                .context("Failed to acknowledge shadow input")?;
+        }
+        Ok(())
+    }
 
-            let network_message = NetworkMessage::ShadowSync(sync_message);
+    /// Input may only drive a local instance, and only from the peer it claims to come from
+    /// when that peer holds a shadow of the instance
+    async fn authorize_input(
+        &self,
+        input: &ShadowInputMessage,
+        peer_id: NodeId,
+        instance_registry: &DistributedInstanceRegistry,
+    ) -> std::result::Result<(), String> {
+        if input.sender_id != peer_id {
+            return Err(format!("input claims to come from node {} but was sent by node {}", input.sender_id, peer_id));
+        }
+        let is_local = self.instance_manager.lock().await
+            .get_instance_by_id(&input.instance_id.to_string())
+            .is_some_and(|instance| !instance.is_shadow());
+        if !is_local {
+            return Err(format!("instance {} does not run on this node", input.instance_id));
+        }
+        if !instance_registry.holds_shadow(input.instance_id, peer_id).await {
+            return Err(format!("node {} holds no shadow of instance {}", peer_id, input.instance_id));
+        }
+        Ok(())
+    }
 
-            if let Err(e) = network_sender.send(network_message) {
-                error!("Failed to stream output to shadows: {}", e);
+    /// Record the source node's answer to input this node forwarded
+    pub async fn handle_shadow_input_ack(&self, ack: ShadowInputAckMessage) {
+        if ack.target_node_id != self.local_node_id {
+            return;
+        }
+        let mut deliveries = self.input_deliveries.write().await;
+        // Acks arriving after the session gave up are dropped
+        if let Some(delivery) = deliveries.get_mut(&ack.input_id) {
+            *delivery = if ack.accepted {
+                InputDelivery::Delivered
             } else {
-                debug!("Successfully sent shadow sync message for instance {}", instance_id);
+                InputDelivery::Rejected(ack.error.unwrap_or_else(|| "rejected by source node".to_string()))
+            };
+        }
+    }
+
+    /// Ask the source node of a shadow to start (or stop) streaming its output live to this
+    /// node. Returns the source node ID.
+    pub async fn request_live_output(&self, shadow_instance_id: Uuid, enabled: bool) -> ShadowResult<NodeId> {
+        let source_node_id = self.shadow_registry.read().await
+            .get(&shadow_instance_id)
+            .map(|shadow_info| shadow_info.source_node_id)
+            .ok_or(ShadowError::NoShadow(shadow_instance_id))?;
+        let network_sender = self.network_sender.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Networking is not available"))?;
+
+        let request = LiveOutputRequestMessage {
+            sender_id: self.local_node_id,
+            target_node_id: source_node_id,
+            instance_id: shadow_instance_id,
+            enabled,
+            timestamp: Utc::now(),
+        };
+        network_sender.send(NetworkMessage::LiveOutputRequest(request)).await
+            .map_err(|e| anyhow::anyhow!("Failed to request live output from source: {}", e))?;
+        debug!("Requested live output {} from node {} for instance {}",
+               if enabled { "start" } else { "stop" }, source_node_id, shadow_instance_id);
+        Ok(source_node_id)
+    }
+
+    /// Start or stop streaming a local instance's output live for a remote attach session
+    pub async fn handle_live_output_request(&self, request: LiveOutputRequestMessage) {
+        {
+            let mut live_viewers = self.live_viewers.lock().unwrap();
+            if request.enabled {
+                live_viewers.entry(request.instance_id).or_default()
+                    .insert(request.sender_id, std::time::Instant::now() + LIVE_OUTPUT_LEASE);
+            } else if let Some(viewers) = live_viewers.get_mut(&request.instance_id) {
+                viewers.remove(&request.sender_id);
+                if viewers.is_empty() {
+                    live_viewers.remove(&request.instance_id);
+                }
             }
-        } else {
-            debug!("No network sender available for streaming output to shadows");
         }
+        if request.enabled {
+            info!("Streaming output of instance {} live for node {}", request.instance_id, request.sender_id);
+            // Output waiting in its batch window is sent now rather than when the window closes
+            self.flush_pending_output(request.instance_id).await;
+        }
+    }
 
-        Ok(())
+    /// Whether a remote attach session is watching this local instance live; lapsed requests are dropped
+    fn has_live_viewer(&self, instance_id: Uuid) -> bool {
+        let mut live_viewers = self.live_viewers.lock().unwrap();
+        let Some(viewers) = live_viewers.get_mut(&instance_id) else { return false };
+        let now = std::time::Instant::now();
+        viewers.retain(|_, expires_at| *expires_at > now);
+        if viewers.is_empty() {
+            live_viewers.remove(&instance_id);
+            return false;
+        }
+        true
     }
 
-    /// Stream checkpoint data from a running instance to all shadow instances
-    pub async fn stream_checkpoint_to_shadows(&self, instance_id: Uuid, checkpoint_data: Vec<u8>) -> Result<()> {
+    /// Broadcast instance stop to all shadow instances
+    pub async fn broadcast_instance_stop(&self, instance_id: Uuid) -> Result<()> {
+        // Output still inside its batch window must reach the shadows before the stop
+        self.flush_pending_output(instance_id).await;
+
         if let Some(network_sender) = &self.network_sender {
-            let sync_message = ShadowSyncMessage {
+            let stop_message = InstanceStopMessage {
                 sender_id: self.local_node_id,
                 instance_id,
-                data_version: self.get_next_data_version(instance_id).await,
-                checkpoint_data: Some(checkpoint_data),
-                output_data: None,
                 timestamp: Utc::now(),
             };
 
-            let network_message = NetworkMessage::ShadowSync(sync_message);
+            let network_message = NetworkMessage::InstanceStop(stop_message);
 
-            if let Err(e) = network_sender.send(network_message) {
-                error!("Failed to stream checkpoint to shadows: {}", e);
+            if let Err(e) = network_sender.send(network_message).await {
+                error!("Failed to broadcast instance stop: {}", e);
             } else {
-                info!("Streamed checkpoint data for instance {} to shadow instances", instance_id);
+                info!("Broadcasted instance stop for {}", instance_id);
             }
         }
 
+        self.streaming.forget(instance_id).await;
         Ok(())
     }
 
-    /// Get all shadow instances managed by this node
-    pub async fn get_shadow_instances(&self) -> Vec<ShadowInstanceInfo> {
-        let registry = self.shadow_registry.read().await;
-        registry.values().cloned().collect()
+    /// Remove shadow instance when the source instance is stopped
+    pub async fn remove_shadow_instance(&self, instance_id: Uuid) -> ShadowResult<()> {
+        // Remove from shadow registry
+        {
+            let mut registry = self.shadow_registry.write().await;
+            registry.remove(&instance_id);
+        }
+
+        // Remove from instance manager
+        {
+            let mut instance_manager = self.instance_manager.lock().await;
+            instance_manager.remove_instance(&instance_id.to_string());
+        }
+
+        info!("Removed shadow instance {}", instance_id);
+        Ok(())
     }
 
-    /// Get shadow instance info by ID
-    pub async fn get_shadow_instance(&self, instance_id: Uuid) -> Option<ShadowInstanceInfo> {
-        let registry = self.shadow_registry.read().await;
-        registry.get(&instance_id).cloned()
+    /// Remove shadows whose source node has been offline (or unknown), or has stopped advertising
+    /// the instance, for longer than the grace period
+    pub async fn collect_stale_shadows(&self, cluster_state: &ClusterStateManager, grace_period: chrono::Duration) -> Vec<Uuid> {
+        let shadows: Vec<ShadowInstanceInfo> = {
+            let registry = self.shadow_registry.read().await;
+            registry.values().cloned().collect()
+        };
+
+        let now = Utc::now();
+        let mut collected = Vec::new();
+
+        for shadow in shadows {
+            // Source is considered gone from the later of its last heartbeat and our last sync
+            let last_alive = match cluster_state.get_node_info(&shadow.source_node_id).await {
+                // An online source that no longer runs the instance orphans the shadow just the same
+                Some(node) if node.status == NodeStatus::Online => match shadow.missing_from_source_since {
+                    Some(missing_since) => missing_since,
+                    None => continue,
+                },
+                Some(node) => node.last_seen.max(shadow.last_sync_time),
+                None => shadow.last_sync_time,
+            };
+
+            if now - last_alive < grace_period {
+                continue;
+            }
+
+            if shadow.missing_from_source_since.is_some() {
+                warn!("🧹 [SHADOW_GC] Source node {} stopped running instance {} at {}, collecting its shadow",
+                      shadow.source_node_id, shadow.instance_id, last_alive.format("%Y-%m-%d %H:%M:%S UTC"));
+            } else {
+                warn!("🧹 [SHADOW_GC] Source node {} for shadow {} offline since {}, collecting",
+                      shadow.source_node_id, shadow.instance_id, last_alive.format("%Y-%m-%d %H:%M:%S UTC"));
+            }
+
+            let checkpoints_dir = crate::instance::data_dir()
+                .join(format!("instance_{}", &shadow.instance_id.to_string()[..8]))
+                .join("checkpoints");
+
+            if let Err(e) = self.remove_shadow_instance(shadow.instance_id).await {
+                warn!("Failed to remove stale shadow {}: {}", shadow.instance_id, e);
+                continue;
+            }
+
+            if checkpoints_dir.exists() {
+                if let Err(e) = tokio::fs::remove_dir_all(&checkpoints_dir).await {
+                    warn!("Failed to remove checkpoints for stale shadow {}: {}", shadow.instance_id, e);
+                }
+            }
+
+            if let Some(instance_dir) = checkpoints_dir.parent() {
+                if let Err(e) = crate::checkpoint_dedup::prune_blobs(instance_dir) {
+                    warn!("Failed to prune checkpoint blobs for stale shadow {}: {}", shadow.instance_id, e);
+                }
+            }
+
+            collected.push(shadow.instance_id);
+        }
+
+        collected
+    }
+
+    /// Start re-advertising locally running instances every `interval`
+    pub fn start_reconcile_task(shadow_manager: Arc<RwLock<ShadowInstanceManager>>, interval: std::time::Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+
+            loop {
+                ticker.tick().await;
+
+                let manager = shadow_manager.read().await;
+                if let Err(e) = manager.advertise_running_instances().await {
+                    warn!("Failed to re-advertise running instances: {}", e);
+                }
+            }
+        });
+
+        info!("Instance reconciliation started (interval: {:?})", interval);
+    }
+
+    /// Start delivering stream chunks stuck behind lost ones once their gap times out
+    pub fn start_stream_gap_task(shadow_manager: Arc<RwLock<ShadowInstanceManager>>) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(crate::streaming_manager::STREAM_GAP_TIMEOUT / 2);
+
+            loop {
+                ticker.tick().await;
+
+                let manager = shadow_manager.read().await;
+                if let Err(e) = manager.flush_stream_gaps().await {
+                    warn!("Failed to apply stream chunks after a gap: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Start the periodic shadow garbage collector
+    pub fn start_gc_task(
+        shadow_manager: Arc<RwLock<ShadowInstanceManager>>,
+        cluster_state: Arc<ClusterStateManager>,
+        check_interval: std::time::Duration,
+        grace_period: std::time::Duration,
+    ) {
+        let grace_period = chrono::Duration::from_std(grace_period)
+            .unwrap_or_else(|_| chrono::Duration::seconds(600));
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(check_interval);
+
+            loop {
+                interval.tick().await;
+
+                let collected = {
+                    let manager = shadow_manager.read().await;
+                    manager.collect_stale_shadows(&cluster_state, grace_period).await
+                };
+
+                if !collected.is_empty() {
+                    info!("🧹 [SHADOW_GC] Collected {} stale shadow instance(s)", collected.len());
+                }
+            }
+        });
+
+        info!("Shadow GC started (interval: {:?}, grace period: {}s)", check_interval, grace_period.num_seconds());
+    }
+
+    /// Restore and promote shadows whose source node has been offline longer than the grace period.
+    /// Every node evaluates the same policy over its own view of the online nodes and checkpoint
+    /// holders. Views can differ while membership changes, so a node that selects itself first
+    /// claims the instance from every peer (`claim_failover`) and restores it only once all of
+    /// them granted the claim; the others re-point their shadows once the winner broadcasts the
+    /// promotion.
+    pub async fn failover_orphaned_shadows(
+        &self,
+        cluster_state: &ClusterStateManager,
+        instance_registry: &DistributedInstanceRegistry,
+        grace_period: chrono::Duration,
+        policy: FailoverPolicy,
+        attempted: &mut HashSet<Uuid>,
+    ) -> Vec<Uuid> {
+        let shadows: Vec<ShadowInstanceInfo> = {
+            let registry = self.shadow_registry.read().await;
+            registry.values().cloned().collect()
+        };
+
+        let now = Utc::now();
+        let mut promoted = Vec::new();
+
+        for shadow in shadows {
+            let last_alive = match cluster_state.get_node_info(&shadow.source_node_id).await {
+                Some(node) if node.status == NodeStatus::Online => continue,
+                Some(node) => node.last_seen.max(shadow.last_sync_time),
+                None => shadow.last_sync_time,
+            };
+
+            if now - last_alive < grace_period || attempted.contains(&shadow.instance_id) {
+                continue;
+            }
+
+            if shadow.latest_checkpoint.is_none() {
+                debug!("[FAILOVER] Shadow {} has no checkpoint to restore from, skipping", shadow.instance_id);
+                continue;
+            }
+
+            let instance_dir = PathBuf::from("instances")
+                .join(format!("instance_{}", &shadow.instance_id.to_string()[..8]));
+            let checkpoint_dir = Self::latest_sync_checkpoint(&instance_dir.join("checkpoints"));
+
+            // Only nodes that can restore the instance's checkpoint and satisfy its affinity may take it over
+            let affinity = self.instance_manager.lock().await
+                .get_instance_by_id(&shadow.instance_id.to_string())
+                .map(|instance| instance.affinity.clone())
+                .unwrap_or_default();
+            let mut checkpoint_holders = instance_registry.checkpoint_holders(shadow.instance_id).await;
+            if checkpoint_dir.is_some() {
+                checkpoint_holders.insert(self.local_node_id);
+            } else {
+                checkpoint_holders.remove(&self.local_node_id);
+            }
+            let online_nodes = cluster_state.get_online_nodes().await;
+            let candidates = failover_candidates(&online_nodes, shadow.source_node_id, &affinity, &checkpoint_holders);
+            if candidates.is_empty() {
+                debug!("[FAILOVER] No online node can restore instance {} (affinity {})", shadow.instance_id, affinity);
+            }
+
+            match policy.select_node(shadow.instance_id, &candidates) {
+                Some(node_id) if node_id == self.local_node_id => {}
+                Some(node_id) => {
+                    debug!("[FAILOVER] Node {} selected to take over instance {}", node_id, shadow.instance_id);
+                    continue;
+                }
+                None => continue,
+            }
+            // Only selected when the local checkpoint exists
+            let Some(checkpoint_dir) = checkpoint_dir else { continue };
+            let peers = online_nodes.iter()
+                .map(|node| node.node_id)
+                .filter(|node_id| *node_id != self.local_node_id && *node_id != shadow.source_node_id)
+                .collect();
+            if !self.claim_failover(shadow.instance_id, shadow.source_node_id, peers).await {
+                continue;
+            }
+
+            attempted.insert(shadow.instance_id);
+            warn!("🚑 [FAILOVER] Source node {} for instance {} offline since {}, taking over",
+                  shadow.source_node_id, shadow.instance_id, last_alive.format("%Y-%m-%d %H:%M:%S UTC"));
+
+            match self.restore_migration_checkpoint(shadow.instance_id, &checkpoint_dir, &instance_dir).await {
+                Ok(()) => {
+                    self.audit.record("failover", Some(shadow.instance_id), Some(&shadow.source_node_id.to_string()), None);
+                    promoted.push(shadow.instance_id);
+                }
+                Err(e) => error!("❌ [FAILOVER] Failed to take over instance {}: {}", shadow.instance_id, e),
+            }
+        }
+
+        promoted
     }
 
-    /// Forward input from shadow instance to source instance
-    pub async fn forward_input_to_source(&self, shadow_instance_id: Uuid, input: String) -> Result<()> {
-        let registry = self.shadow_registry.read().await;
-        if let Some(shadow_info) = registry.get(&shadow_instance_id) {
-            if let Some(network_sender) = &self.network_sender {
-                let input_message = ShadowInputMessage {
-                    sender_id: self.local_node_id,
-                    target_node_id: shadow_info.source_node_id,
-                    instance_id: shadow_instance_id,
-                    input_data: input,
-                    timestamp: Utc::now(),
-                };
+    /// Ask every peer to let this node take over an orphaned instance. A peer grants one
+    /// claimant per instance, and a node with a claim of its own grants only a claimant with a
+    /// lower node ID, giving up its own; so of two nodes that both selected themselves at most
+    /// one collects every grant. A claim that is denied or not answered in time is retried on
+    /// the next failover check.
+    async fn claim_failover(&self, instance_id: Uuid, source_node_id: NodeId, peers: BTreeSet<NodeId>) -> bool {
+        let claim_id = Uuid::new_v4();
+        {
+            let mut claims = self.failover_claims.write().await;
+            if let Some(FailoverClaim::Granted { node_id, at }) = claims.get(&instance_id) {
+                if *node_id != self.local_node_id && at.elapsed() < FAILOVER_GRANT_TTL {
+                    debug!("[FAILOVER] Instance {} was granted to node {}, not claiming it", instance_id, node_id);
+                    return false;
+                }
+            }
+            if peers.is_empty() {
+                claims.insert(instance_id, FailoverClaim::Granted { node_id: self.local_node_id, at: std::time::Instant::now() });
+                return true;
+            }
+            claims.insert(instance_id, FailoverClaim::Pending { claim_id, waiting: peers, denied: false });
+        }
 
-                let network_message = NetworkMessage::ShadowInput(input_message);
+        let claim = FailoverClaimMessage {
+            sender_id: self.local_node_id,
+            instance_id,
+            source_node_id,
+            claim_id,
+            timestamp: Utc::now(),
+        };
+        let sent = match &self.network_sender {
+            Some(network_sender) => network_sender.send(NetworkMessage::FailoverClaim(claim.clone())).await.is_ok(),
+            None => false,
+        };
+        if !sent {
+            warn!("[FAILOVER] Could not send the claim for instance {}", instance_id);
+            self.failover_claims.write().await.remove(&instance_id);
+            return false;
+        }
+        if self.await_failover_grants(instance_id, claim_id).await {
+            return true;
+        }
+        // Peers that granted the claim would otherwise turn other claimants away until
+        // FAILOVER_GRANT_TTL runs out
+        if let Some(network_sender) = &self.network_sender {
+            let _ = network_sender.send(NetworkMessage::FailoverRelease(claim)).await;
+        }
+        false
+    }
 
-                if let Err(e) = network_sender.send(network_message) {
-                    error!("Failed to forward input to source: {}", e);
-                } else {
-                    debug!("Forwarded input to source node {} for instance {}",
-                           shadow_info.source_node_id, shadow_instance_id);
+    /// Wait until every peer granted the claim, one denied it, or FAILOVER_CLAIM_TIMEOUT passed
+    async fn await_failover_grants(&self, instance_id: Uuid, claim_id: Uuid) -> bool {
+        let deadline = tokio::time::Instant::now() + FAILOVER_CLAIM_TIMEOUT;
+        loop {
+            {
+                let mut claims = self.failover_claims.write().await;
+                match claims.get(&instance_id) {
+                    Some(FailoverClaim::Pending { claim_id: pending, waiting, denied }) if *pending == claim_id => {
+                        if *denied {
+                            debug!("[FAILOVER] Claim for instance {} was denied", instance_id);
+                            claims.remove(&instance_id);
+                            return false;
+                        }
+                        if waiting.is_empty() {
+                            claims.insert(instance_id, FailoverClaim::Granted { node_id: self.local_node_id, at: std::time::Instant::now() });
+                            return true;
+                        }
+                        if tokio::time::Instant::now() >= deadline {
+                            warn!("[FAILOVER] Nodes {:?} did not answer the claim for instance {}", waiting, instance_id);
+                            claims.remove(&instance_id);
+                            return false;
+                        }
+                    }
+                    // This node granted a lower node's claim in the meantime
+                    _ => return false,
                 }
             }
-        } else {
-            return Err(anyhow::anyhow!("Shadow instance not found: {}", shadow_instance_id));
+            tokio::time::sleep(Duration::from_millis(20)).await;
         }
-
-        Ok(())
     }
 
-    /// Broadcast instance stop to all shadow instances
-    pub async fn broadcast_instance_stop(&self, instance_id: Uuid) -> Result<()> {
+    /// Answer another node's failover claim. `source_online` is whether this node still sees the
+    /// instance's source node online, in which case the instance is not orphaned.
+    pub async fn handle_failover_claim(&self, claim: FailoverClaimMessage, source_online: bool) -> Result<()> {
+        let running_here = self.instance_manager.lock().await
+            .get_instance_by_id(&claim.instance_id.to_string())
+            .is_some_and(|instance| !instance.is_shadow());
+        let granted = {
+            let mut claims = self.failover_claims.write().await;
+            let granted = !running_here && !source_online && match claims.get(&claim.instance_id) {
+                Some(FailoverClaim::Granted { node_id, at }) => *node_id == claim.sender_id || at.elapsed() >= FAILOVER_GRANT_TTL,
+                Some(FailoverClaim::Pending { .. }) => claim.sender_id < self.local_node_id,
+                None => true,
+            };
+            if granted {
+                claims.insert(claim.instance_id, FailoverClaim::Granted { node_id: claim.sender_id, at: std::time::Instant::now() });
+            }
+            granted
+        };
+        debug!("[FAILOVER] {} node {}'s claim for instance {}",
+               if granted { "Granted" } else { "Denied" }, claim.sender_id, claim.instance_id);
+
         if let Some(network_sender) = &self.network_sender {
-            let stop_message = InstanceStopMessage {
+            let ack = FailoverClaimAckMessage {
                 sender_id: self.local_node_id,
-                instance_id,
+                target_node_id: claim.sender_id,
+                instance_id: claim.instance_id,
+                claim_id: claim.claim_id,
+                granted,
                 timestamp: Utc::now(),
             };
+            network_sender.send(NetworkMessage::FailoverClaimAck(ack)).await
+                .context("Failed to answer failover claim")?;
+        }
+        Ok(())
+    }
 
-            let network_message = NetworkMessage::InstanceStop(stop_message);
+    /// Forget the grant of a claim its claimant withdrew
+    pub async fn handle_failover_release(&self, claim: FailoverClaimMessage) {
+        let mut claims = self.failover_claims.write().await;
+        if let Some(FailoverClaim::Granted { node_id, .. }) = claims.get(&claim.instance_id) {
+            if *node_id == claim.sender_id {
+                claims.remove(&claim.instance_id);
+            }
+        }
+    }
 
-            if let Err(e) = network_sender.send(network_message) {
-                error!("Failed to broadcast instance stop: {}", e);
-            } else {
-                info!("Broadcasted instance stop for {}", instance_id);
+    /// Record a peer's answer to this node's failover claim
+    pub async fn handle_failover_claim_ack(&self, ack: FailoverClaimAckMessage) {
+        if ack.target_node_id != self.local_node_id {
+            return;
+        }
+        let mut claims = self.failover_claims.write().await;
+        if let Some(FailoverClaim::Pending { claim_id, waiting, denied }) = claims.get_mut(&ack.instance_id) {
+            if *claim_id == ack.claim_id {
+                waiting.remove(&ack.sender_id);
+                *denied |= !ack.granted;
             }
         }
+    }
 
-        Ok(())
+    /// Newest `sync-<timestamp>` checkpoint directory received from the source node
+    fn latest_sync_checkpoint(checkpoints_dir: &Path) -> Option<PathBuf> {
+        std::fs::read_dir(checkpoints_dir).ok()?
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let timestamp = name.strip_prefix("sync-")?.parse::<i64>().ok()?;
+                Some((timestamp, entry.path()))
+            })
+            .max_by_key(|(timestamp, _)| *timestamp)
+            .map(|(_, path)| path)
     }
 
-    /// Remove shadow instance when the source instance is stopped
-    pub async fn remove_shadow_instance(&self, instance_id: Uuid) -> Result<()> {
-        // Remove from shadow registry
-        {
-            let mut registry = self.shadow_registry.write().await;
-            registry.remove(&instance_id);
-        }
+    /// Start the periodic auto-failover supervisor
+    pub fn start_failover_task(
+        shadow_manager: Arc<RwLock<ShadowInstanceManager>>,
+        cluster_state: Arc<ClusterStateManager>,
+        instance_registry: Arc<DistributedInstanceRegistry>,
+        check_interval: std::time::Duration,
+        grace_period: std::time::Duration,
+        policy: FailoverPolicy,
+    ) {
+        let grace_period = chrono::Duration::from_std(grace_period)
+            .unwrap_or_else(|_| chrono::Duration::seconds(30));
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(check_interval);
+            let mut attempted = HashSet::new();
+
+            loop {
+                interval.tick().await;
+
+                let promoted = {
+                    let manager = shadow_manager.read().await;
+                    manager.failover_orphaned_shadows(&cluster_state, &instance_registry, grace_period, policy, &mut attempted).await
+                };
 
-        // Remove from instance manager
-        {
-            let mut instance_manager = self.instance_manager.lock().await;
-            instance_manager.remove_instance(&instance_id.to_string());
-        }
+                if !promoted.is_empty() {
+                    info!("🚑 [FAILOVER] Took over {} instance(s) from offline nodes", promoted.len());
+                }
+            }
+        });
 
-        info!("Removed shadow instance {}", instance_id);
-        Ok(())
+        info!("Auto-failover started (policy: {}, interval: {:?}, grace period: {}s)",
+              policy, check_interval, grace_period.num_seconds());
     }
 
     /// Promote shadow instance to running instance (for migration)
-    pub async fn promote_shadow_to_running(&self, instance_id: Uuid, new_pid: u32) -> Result<()> {
+    pub async fn promote_shadow_to_running(&self, instance_id: Uuid, new_pid: u32) -> ShadowResult<()> {
         // Get instance info before updating
-        let (program, args, working_dir) = {
+        let (program, args, working_dir, stamp) = {
             let instance_manager = self.instance_manager.lock().await;
             if let Some(instance) = instance_manager.get_instance_by_id(&instance_id.to_string()) {
-                (instance.program.clone(), instance.args.clone(), instance.working_dir.clone())
+                let stamp = crate::process_manager::LineStamp::new(instance.output_timestamps, instance.created_at);
+                (instance.program.clone(), instance.args.clone(), instance.working_dir.clone(), stamp)
             } else {
-                return Err(anyhow::anyhow!("Instance {} not found", instance_id));
+                return Err(ShadowError::InstanceNotFound(instance_id));
             }
         };
 
         // Register the migrated process with process_manager
         info!("🔄 [PROMOTE] Registering migrated process {} with process_manager", new_pid);
-        if let Err(e) = self.process_manager.register_migrated_process(instance_id, new_pid, &program, &args, &working_dir).await {
+        if let Err(e) = self.process_manager.register_migrated_process(instance_id, new_pid, &program, &args, &working_dir, stamp).await {
             warn!("⚠️ [PROMOTE] Failed to register migrated process with process_manager: {}", e);
         } else {
             info!("✅ [PROMOTE] Successfully registered migrated process with process_manager");
@@ -549,9 +1597,7 @@ impl ShadowInstanceManager {
         {
             let mut instance_manager = self.instance_manager.lock().await;
             if let Some(instance) = instance_manager.get_instance_by_id_mut(&instance_id.to_string()) {
-                instance.status = InstanceStatus::Running;
-                instance.pid = Some(new_pid);
-                instance.source_node_id = None; // No longer a shadow
+                instance.promote_to_running(new_pid).map_err(anyhow::Error::from)?;
 
                 // Save updated metadata
                 if let Err(e) = instance.save_metadata() {
@@ -560,25 +1606,44 @@ impl ShadowInstanceManager {
             }
         }
 
-        // Remove from shadow registry (it's now a running instance)
-        {
-            let mut registry = self.shadow_registry.write().await;
-            registry.remove(&instance_id);
+        // Remove from shadow registry (it's now a running instance), continuing its stream
+        // sequence from the last chunk it received
+        let removed = self.shadow_registry.write().await.remove(&instance_id);
+        if let Some(shadow_info) = removed {
+            self.streaming.continue_from(instance_id, shadow_info.data_version).await;
         }
 
         info!("Promoted shadow instance {} to running with PID {}", instance_id, new_pid);
+        self.audit.record("promote", Some(instance_id), Some(&self.local_node_id.to_string()), None);
         Ok(())
     }
 
+    /// Point an existing shadow at a new source node
+    async fn retarget_shadow(&self, instance_id: Uuid, new_source_node_id: NodeId) {
+        {
+            let mut instance_manager = self.instance_manager.lock().await;
+            if let Some(instance) = instance_manager.get_instance_by_id_mut(&instance_id.to_string()) {
+                instance.source_node_id = Some(new_source_node_id);
+                if let Err(e) = instance.save_metadata() {
+                    warn!("Failed to save shadow metadata after source change: {}", e);
+                }
+            }
+        }
+
+        let mut registry = self.shadow_registry.write().await;
+        if let Some(shadow_info) = registry.get_mut(&instance_id) {
+            shadow_info.source_node_id = new_source_node_id;
+            shadow_info.last_sync_time = Utc::now();
+        }
+    }
+
     /// Demote running instance to shadow instance (for migration)
     pub async fn demote_running_to_shadow(&self, instance_id: Uuid, new_source_node_id: NodeId) -> Result<()> {
         // Update in instance manager
         {
             let mut instance_manager = self.instance_manager.lock().await;
             if let Some(instance) = instance_manager.get_instance_by_id_mut(&instance_id.to_string()) {
-                instance.status = InstanceStatus::Shadow;
-                instance.pid = None; // Shadow instances don't have processes
-                instance.source_node_id = Some(new_source_node_id);
+                instance.demote_to_shadow(new_source_node_id)?;
 
                 // Save updated metadata
                 if let Err(e) = instance.save_metadata() {
@@ -596,13 +1661,18 @@ impl ShadowInstanceManager {
                 created_at: Utc::now(),
                 last_sync_time: Utc::now(),
                 output_buffer: Vec::new(),
+                output_bytes_total: 0,
                 latest_checkpoint: None,
                 data_version: 0,
+                checkpoint_version: 0,
+                missing_from_source_since: None,
             };
             registry.insert(instance_id, shadow_info);
         }
 
         info!("Demoted running instance {} to shadow for source node {}", instance_id, new_source_node_id);
+        self.audit.record("demote", Some(instance_id), Some(&new_source_node_id.to_string()), None);
+        self.announce_shadow(instance_id).await;
         Ok(())
     }
 
@@ -645,9 +1715,6 @@ impl ShadowInstanceManager {
 
     /// Save checkpoint data to disk for a shadow instance
     async fn save_checkpoint_data(&self, instance_id: Uuid, checkpoint_data: &[u8]) -> Result<()> {
-        use std::io::Read;
-        use flate2::read::GzDecoder;
-
         debug!("Saving checkpoint data for instance {}: {} bytes", instance_id, checkpoint_data.len());
 
         // Create instance directory (same structure as running instances)
@@ -657,44 +1724,27 @@ impl ShadowInstanceManager {
 
         tokio::fs::create_dir_all(&checkpoint_dir).await?;
 
-        // Decompress and extract checkpoint files
-        let mut decoder = GzDecoder::new(checkpoint_data);
-        let mut decompressed_data = Vec::new();
-        decoder.read_to_end(&mut decompressed_data)?;
-
-        // Parse the decompressed data and extract files
-        let mut cursor = std::io::Cursor::new(decompressed_data);
-        let mut file_count = 0;
-
-        while cursor.position() < cursor.get_ref().len() as u64 {
-            // Read file name length
-            let mut name_len_buf = [0u8; 4];
-            if cursor.read_exact(&mut name_len_buf).is_err() {
-                break; // End of data
+        // Decompress and extract checkpoint files; a delta is linked to the base an earlier sync delivered
+        let extracted = {
+            let checkpoint_data = checkpoint_data.to_vec();
+            let checkpoint_dir = checkpoint_dir.clone();
+            tokio::task::spawn_blocking(move || {
+                let file_count = crate::checkpoint_archive::decompress_into(&checkpoint_data, &checkpoint_dir)?;
+                crate::checkpoint_archive::link_received_base(&checkpoint_dir)?;
+                Ok::<_, crate::types::CriuCliError>(file_count)
+            }).await?
+        };
+        let file_count = match extracted {
+            Ok(file_count) => file_count,
+            Err(e) => {
+                // Without its base the checkpoint can't be restored; keep the previous one current
+                tokio::fs::remove_dir_all(&checkpoint_dir).await.ok();
+                return Err(e.into());
             }
-            let name_len = u32::from_le_bytes(name_len_buf) as usize;
-
-            // Read file name
-            let mut name_buf = vec![0u8; name_len];
-            cursor.read_exact(&mut name_buf)?;
-            let file_name = String::from_utf8(name_buf)?;
-
-            // Read file data length
-            let mut data_len_buf = [0u8; 4];
-            cursor.read_exact(&mut data_len_buf)?;
-            let data_len = u32::from_le_bytes(data_len_buf) as usize;
-
-            // Read file data
-            let mut file_data = vec![0u8; data_len];
-            cursor.read_exact(&mut file_data)?;
-
-            // Write file to checkpoint directory
-            let file_path = checkpoint_dir.join(&file_name);
-            tokio::fs::write(&file_path, &file_data).await?;
-            file_count += 1;
-        }
+        };
 
         debug!("Extracted {} checkpoint files for instance {}", file_count, instance_id);
+        crate::checkpoint_dedup::dedup_if_enabled(&checkpoint_dir, self.storage.dedup);
 
         info!("Saved checkpoint data for shadow instance {} to {:?}", instance_short_id, checkpoint_dir);
         Ok(())
@@ -766,52 +1816,15 @@ impl ShadowInstanceManager {
     }
 
     /// Extract checkpoint data to a directory
-    async fn extract_checkpoint_to_dir(&self, checkpoint_data: &[u8], target_dir: &PathBuf) -> Result<()> {
-        use std::io::Read;
-        use flate2::read::GzDecoder;
-
-        // Decompress checkpoint data
-        let mut decoder = GzDecoder::new(checkpoint_data);
-        let mut decompressed_data = Vec::new();
-        decoder.read_to_end(&mut decompressed_data)?;
-
-        // Parse and extract files
-        let mut cursor = std::io::Cursor::new(decompressed_data);
-
-        while cursor.position() < cursor.get_ref().len() as u64 {
-            // Read file name length
-            let mut name_len_buf = [0u8; 4];
-            if cursor.read_exact(&mut name_len_buf).is_err() {
-                break;
-            }
-            let name_len = u32::from_le_bytes(name_len_buf) as usize;
-
-            // Read file name
-            let mut name_buf = vec![0u8; name_len];
-            cursor.read_exact(&mut name_buf)?;
-            let file_name = String::from_utf8(name_buf)?;
-
-            // Read file data length
-            let mut data_len_buf = [0u8; 4];
-            cursor.read_exact(&mut data_len_buf)?;
-            let data_len = u32::from_le_bytes(data_len_buf) as usize;
-
-            // Read file data
-            let mut file_data = vec![0u8; data_len];
-            cursor.read_exact(&mut file_data)?;
-
-            // Write file
-            let file_path = target_dir.join(&file_name);
-            tokio::fs::write(&file_path, &file_data).await?;
-        }
-
+    async fn extract_checkpoint_to_dir(&self, checkpoint_data: &[u8], target_dir: &Path) -> Result<()> {
+        let checkpoint_data = checkpoint_data.to_vec();
+        let target_dir = target_dir.to_path_buf();
+        tokio::task::spawn_blocking(move || crate::checkpoint_archive::decompress_into(&checkpoint_data, &target_dir)).await??;
         Ok(())
     }
 
     /// Restore migration checkpoint and promote shadow to running
-    async fn restore_migration_checkpoint(&self, instance_id: Uuid, checkpoint_dir: &PathBuf, instance_dir: &PathBuf) -> Result<()> {
-        use tokio::process::Command;
-
+    pub(crate) async fn restore_migration_checkpoint(&self, instance_id: Uuid, checkpoint_dir: &PathBuf, instance_dir: &PathBuf) -> Result<()> {
         info!("🔄 [RESTORE] Starting migration checkpoint restore from {:?}", checkpoint_dir);
         info!("🔄 [RESTORE] Instance working directory: {:?}", instance_dir);
 
@@ -835,94 +1848,112 @@ impl ShadowInstanceManager {
         // Ensure instance directory exists
         tokio::fs::create_dir_all(instance_dir).await?;
 
+        // Deduplicated checkpoints may need files relinked from the blob store
+        crate::checkpoint_dedup::reassemble_checkpoint(checkpoint_dir)?;
+
+        // Encrypted images are decrypted for the restore only; the archive stays as received
+        let _unsealed = crate::checkpoint_crypto::unseal_checkpoint(checkpoint_dir, self.storage.key)?;
+
+        // A checkpoint taken as another user can't be restored here without a mapping
+        let id_map_args = crate::criu_compat::check_restore_ids(checkpoint_dir, &Default::default())?;
+
         // Ensure output directory exists for the restored process
         let output_dir = instance_dir.join("output");
         tokio::fs::create_dir_all(&output_dir).await?;
 
-        // Create output file for the restored process
-        let output_file = output_dir.join("process_output.log");
-        if !output_file.exists() {
-            tokio::fs::File::create(&output_file).await?;
-            info!("📄 [RESTORE] Created output file for restored process: {}", output_file.display());
+        // Create output files for the restored process (combined view plus per-stream logs)
+        for file_name in ["process_output.log", "stdout.log", "stderr.log"] {
+            let output_file = output_dir.join(file_name);
+            if !output_file.exists() {
+                tokio::fs::File::create(&output_file).await?;
+                info!("📄 [RESTORE] Created output file for restored process: {}", output_file.display());
+            }
         }
+        // Detached instances read stdin from a FIFO, which CRIU reopens by path
+        ProcessManager::ensure_stdin_fifo(&output_dir)?;
 
         // Create compatible directory structure for file path mapping
-        self.create_compatible_paths(&checkpoint_dir, &instance_dir).await?;
+        self.create_compatible_paths(checkpoint_dir, instance_dir).await?;
 
         // Use CRIU to restore the process with the same parameters as local restore
-        let pidfile_path = checkpoint_dir.canonicalize()?.join("restored.pid");
-        let mut cmd = Command::new("sudo");
-        cmd.arg(&self.criu_path)
-           .arg("restore")
-           .arg("-D").arg(checkpoint_dir.canonicalize()?)  // Use absolute path
-           .arg("-v4")  // Very verbose output
-           .arg("--restore-detached")  // Critical: restore in detached mode
-           .arg("--shell-job")  // Shell job mode
-           .arg("--pidfile").arg(&pidfile_path)  // Use absolute path for PID file
-           .arg("--log-file").arg("/tmp/criu-restore.log")  // Log to file
-           .arg("--log-pid")  // Include PID in logs
-           .current_dir(instance_dir.canonicalize()?);  // Set working directory to absolute instance directory
-
-        info!("🔧 [RESTORE] CRIU command: {:?}", cmd);
-
-        // Use timeout for CRIU restore command since it might hang after successful restore
+        let images_dir = checkpoint_dir.canonicalize()?;
+        let pidfile_path = images_dir.join("restored.pid");
+        let log_path = images_dir.join("restore.log");
+        // A pidfile left by an earlier attempt would pass the liveness check below
+        let _ = tokio::fs::remove_file(&pidfile_path).await;
+        let request = RestoreRequest {
+            images_dir: images_dir.clone(),  // Use absolute path
+            detached: true,  // Critical: restore in detached mode
+            shell_job: true,  // Shell job mode
+            verbose: true,  // Very verbose output
+            pidfile: Some(pidfile_path.clone()),  // Use absolute path for PID file
+            log_file: Some(log_path.clone()),  // Log next to the images
+            log_pid: true,  // Include PID in logs
+            work_dir: Some(instance_dir.canonicalize()?),  // Set working directory to absolute instance directory
+            extra_args: id_map_args,
+            stdin: None,
+        };
+
+        info!("🔧 [RESTORE] {} restore request: {:?}", self.engine.name(), request);
+
+        // CRIU may not exit after a successful detached restore, so bound the wait
+        let engine = self.engine.clone();
         let output = match tokio::time::timeout(
-            tokio::time::Duration::from_secs(10),
-            cmd.output()
+            self.restore_timeout,
+            tokio::task::spawn_blocking(move || engine.restore(&request))
         ).await {
-            Ok(Ok(output)) => output,
-            Ok(Err(e)) => {
+            Ok(Ok(Ok(output))) => output,
+            Ok(Ok(Err(e))) => {
                 error!("❌ [RESTORE] Failed to execute CRIU command: {}", e);
                 return Err(anyhow::anyhow!("Failed to execute CRIU command: {}", e));
             }
+            Ok(Err(e)) => {
+                error!("❌ [RESTORE] CRIU restore task failed: {}", e);
+                return Err(anyhow::anyhow!("CRIU restore task failed: {}", e));
+            }
             Err(_) => {
-                warn!("⚠️ [RESTORE] CRIU restore command timed out after 10 seconds, checking if restore was successful...");
-
-                // Check if restore was successful by reading the log file
-                match tokio::fs::read_to_string("/tmp/criu-restore.log").await {
-                    Ok(log_content) => {
-                        if log_content.contains("Restore finished successfully") {
-                            info!("✅ [RESTORE] CRIU restore appears to have succeeded based on log file");
-                            // Create a fake successful output
-                            std::process::Output {
-                                status: std::process::ExitStatus::from_raw(0),
-                                stdout: Vec::new(),
-                                stderr: Vec::new(),
-                            }
-                        } else {
-                            error!("❌ [RESTORE] CRIU restore timed out and log doesn't show success");
-                            return Err(anyhow::anyhow!("CRIU restore timed out and appears to have failed"));
+                // A restore that completed has written the PID of a live process; anything else is hung
+                match self.get_restored_pid_from_file(&images_dir).await {
+                    Ok(pid) if Path::new(&format!("/proc/{}", pid)).exists() => {
+                        warn!("⚠️ [RESTORE] CRIU did not exit within {:?}, but restored PID {} is running", self.restore_timeout, pid);
+                        EngineOutput {
+                            success: true,
+                            exit_code: None,
+                            stdout: String::new(),
+                            stderr: String::new(),
                         }
                     }
-                    Err(e) => {
-                        error!("❌ [RESTORE] CRIU restore timed out and couldn't read log file: {}", e);
-                        return Err(anyhow::anyhow!("CRIU restore timed out: {}", e));
+                    _ => {
+                        error!("❌ [RESTORE] CRIU restore of instance {} hung for {:?}, aborting", instance_id, self.restore_timeout);
+                        self.abort_hung_restore(&images_dir, &pidfile_path, &log_path).await;
+                        return Err(anyhow::anyhow!(
+                            "CRIU restore of instance {} did not finish within {:?} and was aborted",
+                            instance_id, self.restore_timeout
+                        ));
                     }
                 }
             }
         };
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let stderr = String::from_utf8_lossy(&output.stderr);
-
-        info!("📤 [RESTORE] CRIU stdout ({} bytes): {}", output.stdout.len(), stdout);
-        info!("📤 [RESTORE] CRIU stderr ({} bytes): {}", output.stderr.len(), stderr);
+        info!("📤 [RESTORE] CRIU stdout ({} bytes): {}", output.stdout.len(), output.stdout);
+        info!("📤 [RESTORE] CRIU stderr ({} bytes): {}", output.stderr.len(), output.stderr);
 
         // Log exit status
-        info!("📤 [RESTORE] CRIU exit status: {:?}", output.status);
+        info!("📤 [RESTORE] CRIU exit code: {:?}", output.exit_code);
 
-        if !output.status.success() {
-            error!("❌ [RESTORE] CRIU restore failed with exit code: {:?}", output.status.code());
-            error!("❌ [RESTORE] CRIU stdout: {}", stdout);
-            error!("❌ [RESTORE] CRIU stderr: {}", stderr);
-            return Err(anyhow::anyhow!("CRIU restore failed with exit code {:?}: {}", output.status.code(), stderr));
+        if !output.success {
+            error!("❌ [RESTORE] CRIU restore failed with exit code: {:?}", output.exit_code);
+            error!("❌ [RESTORE] CRIU stdout: {}", output.stdout);
+            error!("❌ [RESTORE] CRIU stderr: {}", output.stderr);
+            return Err(anyhow::anyhow!("CRIU restore failed with exit code {:?}: {}", output.exit_code, output.stderr));
         }
 
         info!("✅ [RESTORE] CRIU restore command completed successfully");
+        crate::criu_stats::record_restore(instance_id, &images_dir);
 
         // Read and display CRIU log file
         info!("📋 [RESTORE] Reading CRIU log file...");
-        match tokio::fs::read_to_string("/tmp/criu-restore.log").await {
+        match tokio::fs::read_to_string(&log_path).await {
             Ok(log_content) => {
                 info!("📋 [RESTORE] CRIU restore log content:");
                 info!("================== CRIU RESTORE LOG START ==================");
@@ -949,7 +1980,7 @@ impl ShadowInstanceManager {
         tokio::time::sleep(tokio::time::Duration::from_millis(1000)).await;
 
         info!("🔍 [RESTORE] Looking for restored process PID...");
-        let new_pid = if let Ok(pid) = self.get_restored_pid_from_file(&checkpoint_dir).await {
+        let new_pid = if let Ok(pid) = self.get_restored_pid_from_file(checkpoint_dir).await {
             info!("✅ [RESTORE] Found PID from pidfile: {}", pid);
             pid
         } else {
@@ -975,7 +2006,7 @@ impl ShadowInstanceManager {
             }
             Err(e) => {
                 error!("❌ [RESTORE] Failed to promote shadow to running: {}", e);
-                return Err(e);
+                return Err(e.into());
             }
         }
 
@@ -1032,12 +2063,8 @@ impl ShadowInstanceManager {
         } else if source_node_id.starts_with("ae7bf5db") {
             base_dir.join("test_node_b")
         } else {
-            // Fallback: try to determine from existing directories
-            if base_dir.join("test_node_a").exists() {
-                base_dir.join("test_node_a")
-            } else {
-                base_dir.join("test_node_a") // Create it anyway
-            }
+            // Fallback: the first test node's directory, created below if missing
+            base_dir.join("test_node_a")
         };
 
         // Create the source directory structure if it doesn't exist
@@ -1051,17 +2078,20 @@ impl ShadowInstanceManager {
             let source_output_dir = source_instance_dir.join("output");
             tokio::fs::create_dir_all(&source_output_dir).await?;
 
-            // Create or copy the output file from target to source
-            let target_output_file = instance_dir.join("output").join("process_output.log");
-            let source_output_file = source_output_dir.join("process_output.log");
+            // Create or copy the output files from target to source
+            for file_name in ["process_output.log", "stdout.log", "stderr.log"] {
+                let target_output_file = instance_dir.join("output").join(file_name);
+                let source_output_file = source_output_dir.join(file_name);
 
-            if target_output_file.exists() {
-                info!("📄 [RESTORE] Copying output file from target to source location");
-                tokio::fs::copy(&target_output_file, &source_output_file).await?;
-            } else {
-                info!("📄 [RESTORE] Creating empty output file at source location");
-                tokio::fs::File::create(&source_output_file).await?;
+                if target_output_file.exists() {
+                    info!("📄 [RESTORE] Copying {} from target to source location", file_name);
+                    tokio::fs::copy(&target_output_file, &source_output_file).await?;
+                } else {
+                    info!("📄 [RESTORE] Creating empty {} at source location", file_name);
+                    tokio::fs::File::create(&source_output_file).await?;
+                }
             }
+            ProcessManager::ensure_stdin_fifo(&source_output_dir)?;
 
             info!("✅ [RESTORE] Created compatible directory structure");
         } else {
@@ -1071,6 +2101,45 @@ impl ShadowInstanceManager {
         Ok(())
     }
 
+    /// Kill the CRIU process stuck restoring `images_dir` along with whatever it had restored
+    /// so far, then remove the pidfile and log so a retry starts clean
+    async fn abort_hung_restore(&self, images_dir: &Path, pidfile: &Path, log_file: &Path) {
+        let mut pids = Vec::new();
+        for criu_pid in crate::criu_compat::restore_processes(images_dir) {
+            pids.extend(crate::criu_compat::descendants(criu_pid));
+            pids.push(criu_pid);
+        }
+
+        if !pids.is_empty() {
+            warn!("🧹 [RESTORE] Killing hung restore processes {:?}", pids);
+            let status = crate::sudo_utils::privileged_command(self.privilege_command, "kill")
+                .arg("-KILL")
+                .args(pids.iter().map(|pid| pid.to_string()))
+                .status()
+                .await;
+            match status {
+                Ok(status) if status.success() => {}
+                Ok(status) => warn!("⚠️ [RESTORE] kill of hung restore exited with {}", status),
+                Err(e) => warn!("⚠️ [RESTORE] Failed to kill hung restore: {}", e),
+            }
+        }
+
+        // Keep the tail of the log in our own log before removing it
+        if let Ok(log_content) = tokio::fs::read_to_string(log_file).await {
+            let lines: Vec<&str> = log_content.lines().collect();
+            for line in &lines[lines.len().saturating_sub(20)..] {
+                warn!("CRIU: {}", line);
+            }
+        }
+        for path in [pidfile, log_file] {
+            if let Err(e) = tokio::fs::remove_file(path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("⚠️ [RESTORE] Failed to remove {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
     /// Get restored PID from pidfile (like local restore does)
     async fn get_restored_pid_from_file(&self, checkpoint_dir: &std::path::Path) -> Result<u32> {
         let pidfile_path = checkpoint_dir.join("restored.pid");
@@ -1095,7 +2164,7 @@ impl ShadowInstanceManager {
 
         // First, let's see all running processes to debug
         let ps_output = tokio::process::Command::new("ps")
-            .args(&["aux"])
+            .args(["aux"])
             .output()
             .await?;
 
@@ -1150,7 +2219,7 @@ impl ShadowInstanceManager {
         // Try alternative search with ps + grep
         info!("🔍 [PID_SEARCH] pgrep failed, trying alternative search...");
         let ps_grep_output = tokio::process::Command::new("sh")
-            .args(&["-c", "ps aux | grep simple_counter | grep -v grep"])
+            .args(["-c", "ps aux | grep simple_counter | grep -v grep"])
             .output()
             .await?;
 
@@ -1174,18 +2243,11 @@ impl ShadowInstanceManager {
         Err(anyhow::anyhow!("Could not find restored PID - no simple_counter processes running"))
     }
 
-    async fn get_next_data_version(&self, instance_id: Uuid) -> u64 {
-        // For source nodes, we need to track data versions separately
-        // since they don't have shadow registry entries for their own instances
-        static DATA_VERSION_COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
-        DATA_VERSION_COUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
-    }
-
     /// Verify that a process is still running and healthy
     async fn verify_process_health(&self, pid: u32) -> Result<bool> {
         // Check if process exists using kill -0
         let output = tokio::process::Command::new("kill")
-            .args(&["-0", &pid.to_string()])
+            .args(["-0", &pid.to_string()])
             .output()
             .await?;
 
@@ -1196,7 +2258,7 @@ impl ShadowInstanceManager {
 
         // Double-check with ps command
         let ps_output = tokio::process::Command::new("ps")
-            .args(&["-p", &pid.to_string()])
+            .args(["-p", &pid.to_string()])
             .output()
             .await?;
 
@@ -1218,19 +2280,20 @@ impl ShadowInstanceManager {
             // First, broadcast that this instance is now running on this node
             let instance_info = {
                 let instance_manager = self.instance_manager.lock().await;
-                if let Some(instance) = instance_manager.get_instance_by_id(&instance_id.to_string()) {
-                    Some(InstanceInfo {
-                        id: instance.id,
-                        program: instance.program.clone(),
-                        args: instance.args.clone(),
-                        status: instance.status.clone(),
-                        node_id: self.local_node_id,
-                        created_at: instance.created_at,
-                        source_node_id: instance.source_node_id,
-                    })
-                } else {
-                    None
-                }
+                instance_manager.get_instance_by_id(&instance_id.to_string()).map(|instance| InstanceInfo {
+                    id: instance.id,
+                    program: instance.program.clone(),
+                    args: instance.args.clone(),
+                    status: instance.status.clone(),
+                    node_id: self.local_node_id,
+                    created_at: instance.created_at,
+                    source_node_id: instance.source_node_id,
+                    affinity: instance.affinity.clone(),
+                    output_timestamps: instance.output_timestamps,
+                    labels: instance.labels.clone(),
+                    has_checkpoint: false,
+                    env: instance.env.clone(),
+                })
             };
 
             if let Some(instance_info) = instance_info {
@@ -1239,11 +2302,13 @@ impl ShadowInstanceManager {
                     sender_id: self.local_node_id,
                     instances: vec![instance_info],
                     timestamp: Utc::now(),
+                    sync_id: None,
+                    complete: false,
                 };
 
                 let network_message = NetworkMessage::InstanceSync(sync_message);
 
-                if let Err(e) = network_sender.send(network_message) {
+                if let Err(e) = network_sender.send(network_message).await {
                     error!("Failed to broadcast migration completion: {}", e);
                     return Err(anyhow::anyhow!("Failed to broadcast migration completion: {}", e));
                 } else {
@@ -1255,3 +2320,623 @@ impl ShadowInstanceManager {
         Ok(())
     }
 }
+
+/// Nodes that may take over an orphaned instance: online, not its source, able to checkpoint
+/// and restore, allowed by its affinity, and holding a checkpoint of it
+fn failover_candidates(
+    online_nodes: &[NodeInfo],
+    source_node_id: NodeId,
+    affinity: &crate::types::Affinity,
+    checkpoint_holders: &BTreeSet<NodeId>,
+) -> Vec<NodeId> {
+    online_nodes.iter()
+        .filter(|node| node.node_id != source_node_id)
+        .filter(|node| node.supports_checkpoint_restore() && node.check_affinity(affinity).is_ok())
+        .filter(|node| checkpoint_holders.contains(&node.node_id))
+        .map(|node| node.node_id)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn shadow_manager() -> ShadowInstanceManager {
+        crate::test_support::use_scratch_dir();
+        ShadowInstanceManager::new_with_engine(
+            Uuid::new_v4(),
+            Arc::new(tokio::sync::Mutex::new(InstanceManager::new())),
+            Arc::new(ProcessManager::new()),
+            Arc::new(crate::checkpoint_engine::CriuEngine::new("./criu/bin/criu")),
+        )
+    }
+
+    fn labeled_instance() -> Instance {
+        crate::test_support::use_scratch_dir();
+        let mut instance = Instance::new("sleep".to_string(), vec!["60".to_string()], PathBuf::from("/"));
+        instance.status = InstanceStatus::Running;
+        instance.labels = BTreeMap::from([("tier".to_string(), "web".to_string())]);
+        instance
+    }
+
+    fn sync_from(sender_id: NodeId, instances: Vec<InstanceInfo>) -> InstanceSyncMessage {
+        InstanceSyncMessage { sender_id, instances, timestamp: Utc::now(), sync_id: None, complete: true }
+    }
+
+    #[tokio::test]
+    async fn migration_restore_refuses_a_checkpoint_of_another_user() {
+        crate::test_support::use_scratch_dir();
+        let engine = Arc::new(crate::checkpoint_engine::MockEngine::new());
+        let manager = ShadowInstanceManager::new_with_engine(
+            Uuid::new_v4(),
+            Arc::new(tokio::sync::Mutex::new(InstanceManager::new())),
+            Arc::new(ProcessManager::new()),
+            engine.clone(),
+        );
+        let instance_id = Uuid::new_v4();
+        let instance_dir = PathBuf::from("instances").join(format!("instance_{}", &instance_id.to_string()[..8]));
+        let checkpoint_dir = instance_dir.join("checkpoints").join("migration-other-user");
+        std::fs::create_dir_all(&checkpoint_dir).unwrap();
+        let other_uid = nix::unistd::getuid().as_raw() + 1;
+        crate::criu_compat::write_original_ids(&checkpoint_dir, other_uid, nix::unistd::getgid().as_raw()).unwrap();
+
+        let result = manager.restore_migration_checkpoint(instance_id, &checkpoint_dir, &instance_dir).await;
+
+        assert!(result.is_err());
+        assert!(engine.calls().is_empty(), "restored despite the UID mismatch: {:?}", engine.calls());
+    }
+
+    #[tokio::test]
+    async fn only_shadow_holders_may_send_input() {
+        let manager = shadow_manager();
+        let instance = labeled_instance();
+        let instance_id = instance.id;
+        manager.instance_manager.lock().await.add_instance(instance);
+        let holder = Uuid::new_v4();
+        let stranger = Uuid::new_v4();
+        let registry = DistributedInstanceRegistry::new();
+        registry.record_shadow(instance_id, holder, Some(manager.local_node_id), false, Utc::now()).await;
+        let input = |sender_id: NodeId, instance_id: Uuid| ShadowInputMessage {
+            sender_id,
+            target_node_id: manager.local_node_id,
+            instance_id,
+            input_data: "ls\n".to_string(),
+            timestamp: Utc::now(),
+            input_id: Uuid::new_v4(),
+        };
+
+        assert!(manager.authorize_input(&input(holder, instance_id), holder, &registry).await.is_ok());
+        assert!(manager.authorize_input(&input(stranger, instance_id), stranger, &registry).await.is_err());
+        // A stranger claiming to be the shadow holder
+        assert!(manager.authorize_input(&input(holder, instance_id), stranger, &registry).await.is_err());
+        // Instances this node does not run
+        let unknown = Uuid::new_v4();
+        registry.record_shadow(unknown, holder, Some(manager.local_node_id), false, Utc::now()).await;
+        assert!(manager.authorize_input(&input(holder, unknown), holder, &registry).await.is_err());
+    }
+
+    /// A restore that writes a PID that is not running and its log, then never returns in time
+    struct HungRestoreEngine;
+
+    impl CheckpointEngine for HungRestoreEngine {
+        fn name(&self) -> &str {
+            "hung"
+        }
+
+        fn check(&self) -> crate::types::Result<EngineOutput> {
+            unimplemented!()
+        }
+
+        fn dump(&self, _request: &crate::checkpoint_engine::DumpRequest) -> crate::types::Result<EngineOutput> {
+            unimplemented!()
+        }
+
+        fn pre_dump(&self, _request: &crate::checkpoint_engine::DumpRequest) -> crate::types::Result<EngineOutput> {
+            unimplemented!()
+        }
+
+        fn restore(&self, request: &RestoreRequest) -> crate::types::Result<EngineOutput> {
+            std::fs::write(request.pidfile.as_ref().unwrap(), u32::MAX.to_string()).unwrap();
+            std::fs::write(request.log_file.as_ref().unwrap(), "Restoring...\n").unwrap();
+            std::thread::sleep(Duration::from_secs(1));
+            Err(crate::types::CriuCliError::CriuError("gave up".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn hung_migration_restore_is_cleaned_up_and_fails() {
+        crate::test_support::use_scratch_dir();
+        let mut manager = ShadowInstanceManager::new_with_engine(
+            Uuid::new_v4(),
+            Arc::new(tokio::sync::Mutex::new(InstanceManager::new())),
+            Arc::new(ProcessManager::new()),
+            Arc::new(HungRestoreEngine),
+        );
+        manager.set_restore_timeout(Duration::from_millis(200));
+        let instance_id = Uuid::new_v4();
+        let instance_dir = PathBuf::from("instances").join(format!("instance_{}", &instance_id.to_string()[..8]));
+        let checkpoint_dir = instance_dir.join("checkpoints").join("migration-hung");
+        std::fs::create_dir_all(&checkpoint_dir).unwrap();
+
+        let result = manager.restore_migration_checkpoint(instance_id, &checkpoint_dir, &instance_dir).await;
+
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("aborted"), "{}", error);
+        assert!(!checkpoint_dir.join("restored.pid").exists());
+        assert!(!checkpoint_dir.join("restore.log").exists());
+    }
+
+    #[tokio::test]
+    async fn shadow_output_buffer_is_bounded_and_keeps_recent_output() {
+        let mut manager = shadow_manager();
+        manager.set_output_buffer_limit(256);
+        let instance_id = Uuid::new_v4();
+        let source = Uuid::new_v4();
+
+        let mut fed = Vec::new();
+        for line in 0..200 {
+            let output = format!("line {:03} é\n", line).into_bytes();
+            fed.extend_from_slice(&output);
+            let sync_message = ShadowSyncMessage {
+                sender_id: source,
+                instance_id,
+                data_version: line + 1,
+                checkpoint_data: None,
+                output_data: Some(output),
+                timestamp: Utc::now(),
+                is_migration: false,
+                output_compressed: false,
+            };
+            manager.apply_shadow_sync(sync_message, true).await.unwrap();
+        }
+
+        let shadow = manager.get_shadow_instance(instance_id).await.unwrap();
+        assert!(shadow.output_buffer.len() <= 256, "{} bytes buffered", shadow.output_buffer.len());
+        assert_eq!(shadow.output_bytes_total, fed.len() as u64);
+        // The trimmed buffer is the tail of the output, cut at a character boundary
+        let buffered = String::from_utf8(shadow.output_buffer.clone()).unwrap();
+        assert!(buffered.ends_with("line 199 é\n"), "{:?}", buffered);
+        assert!(fed.ends_with(&shadow.output_buffer));
+        // Full history stays on disk
+        let on_disk = std::fs::read(PathBuf::from("instances").join(format!("instance_{}", &instance_id.to_string()[..8])).join("output").join("process_output.log")).unwrap();
+        assert_eq!(on_disk, fed);
+    }
+
+    #[tokio::test]
+    async fn migration_syncs_are_applied_outside_the_version_sequence() {
+        let manager = shadow_manager();
+        let instance_id = Uuid::new_v4();
+        let source = Uuid::new_v4();
+        let sync = |data_version: u64, is_migration: bool, output: &str| ShadowSyncMessage {
+            sender_id: source,
+            instance_id,
+            data_version,
+            checkpoint_data: None,
+            output_data: Some(output.as_bytes().to_vec()),
+            timestamp: Utc::now(),
+            is_migration,
+            output_compressed: false,
+        };
+        let state = || async {
+            let shadow = manager.get_shadow_instance(instance_id).await.unwrap();
+            (shadow.data_version, String::from_utf8(shadow.output_buffer).unwrap())
+        };
+
+        manager.handle_shadow_sync(sync(5, false, "a")).await.unwrap();
+        // Migrations are sent at version 0, below the shadow's, and both are applied
+        manager.handle_shadow_sync(sync(0, true, "m")).await.unwrap();
+        manager.handle_shadow_sync(sync(0, true, "n")).await.unwrap();
+        assert_eq!(state().await, (5, "amn".to_string()));
+
+        // The normal sequence carries on from where it was: a repeat is dropped, the next is applied
+        manager.handle_shadow_sync(sync(5, false, "stale")).await.unwrap();
+        manager.handle_shadow_sync(sync(4, false, "older")).await.unwrap();
+        assert_eq!(state().await, (5, "amn".to_string()));
+        manager.handle_shadow_sync(sync(6, false, "b")).await.unwrap();
+        assert_eq!(state().await, (6, "amnb".to_string()));
+    }
+
+    #[tokio::test]
+    async fn output_batched_within_the_window_arrives_as_one_compressed_chunk() {
+        let mut source = shadow_manager();
+        let receiver = shadow_manager();
+        let queue = OutboundQueue::new(16);
+        source.set_network_sender(queue.clone());
+        source.set_output_batch_window(Duration::from_millis(100));
+        let instance_id = Uuid::new_v4();
+
+        let mut burst = Vec::new();
+        for line in 0..60 {
+            let output = format!("progress {} of 60 é\n", line).into_bytes();
+            burst.extend_from_slice(&output);
+            source.stream_output_to_shadows(instance_id, output, StreamType::Stdout).await.unwrap();
+        }
+        let Some(NetworkMessage::StreamChunk(message)) = queue.recv().await else { panic!("output was not streamed") };
+        assert_eq!(message.chunk.seq, 1);
+        assert!(message.chunk.compressed);
+        assert!(message.chunk.data.len() < burst.len());
+        assert_eq!(queue.len(), 0);
+        receiver.handle_stream_chunk(message).await.unwrap();
+        assert_eq!(receiver.get_shadow_instance(instance_id).await.unwrap().output_buffer, burst);
+
+        // A stop sends whatever is still inside the window ahead of itself
+        source.set_output_batch_window(Duration::from_secs(60));
+        source.stream_output_to_shadows(instance_id, b"last words\n".to_vec(), StreamType::Stdout).await.unwrap();
+        source.broadcast_instance_stop(instance_id).await.unwrap();
+        let Some(NetworkMessage::StreamChunk(message)) = queue.recv().await else { panic!("batched output was not flushed") };
+        assert_eq!((message.chunk.data.as_slice(), message.chunk.compressed), (&b"last words\n"[..], false));
+        assert!(matches!(queue.recv().await, Some(NetworkMessage::InstanceStop(_))));
+    }
+
+    #[tokio::test]
+    async fn interleaved_streams_keep_a_monotonic_version_per_instance() {
+        let mut source = shadow_manager();
+        let receiver = shadow_manager();
+        let queue = OutboundQueue::new(16);
+        source.set_network_sender(queue.clone());
+        source.set_output_batch_window(Duration::ZERO);
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let order = [first, second, first, first, second];
+        for (line, instance_id) in order.iter().enumerate() {
+            source.stream_output_to_shadows(*instance_id, format!("line {}\n", line).into_bytes(), StreamType::Stdout).await.unwrap();
+        }
+        let mut sent = Vec::new();
+        let mut versions: HashMap<Uuid, Vec<u64>> = HashMap::new();
+        while sent.len() < order.len() {
+            let Some(NetworkMessage::StreamChunk(message)) = queue.recv().await else { panic!("output was not streamed") };
+            versions.entry(message.chunk.instance_id).or_default().push(message.chunk.seq);
+            sent.push(message);
+        }
+        assert_eq!(versions[&first], [1, 2, 3]);
+        assert_eq!(versions[&second], [1, 2]);
+        assert_eq!((source.stream_position(first).await, source.stream_position(second).await), (3, 2));
+
+        for message in &sent {
+            receiver.handle_stream_chunk(message.clone()).await.unwrap();
+        }
+        let buffered = |shadow: &ShadowInstanceInfo| String::from_utf8_lossy(&shadow.output_buffer).into_owned();
+        let first_shadow = receiver.get_shadow_instance(first).await.unwrap();
+        let second_shadow = receiver.get_shadow_instance(second).await.unwrap();
+        assert_eq!((first_shadow.data_version, second_shadow.data_version), (3, 2));
+        assert_eq!(buffered(&first_shadow), "line 0\nline 2\nline 3\n");
+        assert_eq!(buffered(&second_shadow), "line 1\nline 4\n");
+
+        // A replayed chunk of one instance is stale and changes neither shadow
+        receiver.handle_stream_chunk(sent[0].clone()).await.unwrap();
+        assert_eq!(buffered(&receiver.get_shadow_instance(first).await.unwrap()), "line 0\nline 2\nline 3\n");
+        assert_eq!(receiver.get_shadow_instance(second).await.unwrap().data_version, 2);
+    }
+
+    #[test]
+    fn failover_skips_nodes_without_a_checkpoint_or_restore_support() {
+        let node = |capabilities: &[&str]| {
+            let mut node = NodeInfo::new(Uuid::new_v4(), "node".to_string(), "127.0.0.1:0".parse().unwrap());
+            node.capabilities = capabilities.iter().map(|c| c.to_string()).collect();
+            node
+        };
+        let criu = [crate::capabilities::CAP_CRIU_CHECKPOINT, crate::capabilities::CAP_CRIU_RESTORE];
+        let source = node(&criu);
+        let holder = node(&criu);
+        let no_checkpoint = node(&criu);
+        let no_criu = node(&[]);
+        let online = [source.clone(), holder.clone(), no_checkpoint, no_criu.clone()];
+        let holders = BTreeSet::from([source.node_id, holder.node_id, no_criu.node_id]);
+
+        let candidates = failover_candidates(&online, source.node_id, &Default::default(), &holders);
+        assert_eq!(candidates, vec![holder.node_id]);
+    }
+
+    #[tokio::test]
+    async fn advertised_instances_carry_labels() {
+        let manager = shadow_manager();
+        let info = manager.instance_info(&labeled_instance());
+        assert_eq!(info.labels.get("tier").map(String::as_str), Some("web"));
+    }
+
+    #[tokio::test]
+    async fn shadows_keep_the_labels_of_their_source() {
+        let source = shadow_manager();
+        let target = shadow_manager();
+        let instance = labeled_instance();
+
+        target.handle_instance_sync(sync_from(source.local_node_id, vec![source.instance_info(&instance)])).await.unwrap();
+
+        let instance_manager = target.instance_manager.lock().await;
+        let shadow = instance_manager.get_instance_by_id(&instance.id.to_string()).unwrap();
+        assert_eq!(shadow.status, InstanceStatus::Shadow);
+        // A shadow promoted by migration must still be selected by `stop --label tier=web`
+        assert!(crate::types::matches_labels(shadow, &[("tier".to_string(), "web".to_string())]));
+    }
+
+    #[tokio::test]
+    async fn shadows_the_online_source_stopped_advertising_are_collected() {
+        let source = shadow_manager();
+        let target = shadow_manager();
+        let cluster_state = ClusterStateManager::new(target.local_node_id);
+        cluster_state.add_node(NodeInfo::new(source.local_node_id, "source".to_string(), "127.0.0.1:1".parse().unwrap())).await.unwrap();
+        let instance = labeled_instance();
+
+        target.handle_instance_sync(sync_from(source.local_node_id, vec![source.instance_info(&instance)])).await.unwrap();
+        assert!(target.collect_stale_shadows(&cluster_state, chrono::Duration::zero()).await.is_empty());
+
+        // The source's next complete advertisement no longer lists the instance
+        target.handle_instance_sync(sync_from(source.local_node_id, Vec::new())).await.unwrap();
+        assert!(target.collect_stale_shadows(&cluster_state, chrono::Duration::minutes(10)).await.is_empty());
+        assert_eq!(target.collect_stale_shadows(&cluster_state, chrono::Duration::zero()).await, vec![instance.id]);
+    }
+
+    #[tokio::test]
+    async fn shadows_of_a_source_offline_past_the_retention_window_are_collected() {
+        crate::test_support::use_scratch_dir();
+        let source = shadow_manager();
+        let target = shadow_manager();
+        let cluster_state = ClusterStateManager::new(target.local_node_id);
+        let mut node = NodeInfo::new(source.local_node_id, "source".to_string(), "127.0.0.1:1".parse().unwrap());
+        node.status = NodeStatus::Offline;
+        node.last_seen = Utc::now() - chrono::Duration::hours(3);
+        cluster_state.add_node(node).await.unwrap();
+        let instance = labeled_instance();
+
+        target.handle_instance_sync(sync_from(source.local_node_id, vec![source.instance_info(&instance)])).await.unwrap();
+        // The last sync arrived two hours ago, after the source's last heartbeat
+        target.shadow_registry.write().await.get_mut(&instance.id).unwrap().last_sync_time = Utc::now() - chrono::Duration::hours(2);
+        let checkpoints_dir = crate::instance::data_dir()
+            .join(format!("instance_{}", &instance.id.to_string()[..8]))
+            .join("checkpoints");
+        std::fs::create_dir_all(checkpoints_dir.join("sync_1")).unwrap();
+
+        // Offline for two hours is still inside a three hour window
+        assert!(target.collect_stale_shadows(&cluster_state, chrono::Duration::hours(3)).await.is_empty());
+        assert!(target.get_shadow_instance(instance.id).await.is_some());
+        assert!(checkpoints_dir.exists());
+
+        assert_eq!(target.collect_stale_shadows(&cluster_state, chrono::Duration::hours(1)).await, vec![instance.id]);
+        assert!(target.get_shadow_instance(instance.id).await.is_none());
+        assert!(!checkpoints_dir.exists());
+    }
+
+    #[tokio::test]
+    async fn shadows_keep_the_environment_of_their_source() {
+        let source = shadow_manager();
+        let target = shadow_manager();
+        let mut instance = labeled_instance();
+        instance.env = vec![("MODE".to_string(), "batch".to_string())];
+
+        let info = source.instance_info(&instance);
+        assert_eq!(info.env, instance.env);
+        target.handle_instance_sync(sync_from(source.local_node_id, vec![info])).await.unwrap();
+
+        let instance_manager = target.instance_manager.lock().await;
+        let shadow = instance_manager.get_instance_by_id(&instance.id.to_string()).unwrap();
+        assert_eq!(shadow.env, instance.env);
+    }
+
+    #[test]
+    fn advertisements_round_trip_through_bincode_and_older_layouts_do_not() {
+        // Advertisements as sent before labels and the later fields existed
+        #[derive(serde::Serialize)]
+        struct UnlabeledInstanceInfo {
+            id: Uuid,
+            program: String,
+            args: Vec<String>,
+            status: InstanceStatus,
+            node_id: NodeId,
+            created_at: chrono::DateTime<Utc>,
+            source_node_id: Option<NodeId>,
+        }
+
+        let mut instance = labeled_instance();
+        instance.env = vec![("MODE".to_string(), "batch".to_string())];
+        let info = shadow_manager().instance_info(&instance);
+        let decoded: InstanceInfo = bincode::deserialize(&bincode::serialize(&info).unwrap()).unwrap();
+        assert_eq!(decoded.labels, instance.labels);
+        assert_eq!(decoded.env, instance.env);
+
+        // bincode has no defaults for missing fields, so an older node's advertisement cannot be
+        // read at all; the protocol version exchanged before the handshake keeps such peers out
+        let unlabeled = UnlabeledInstanceInfo {
+            id: info.id,
+            program: info.program.clone(),
+            args: info.args.clone(),
+            status: info.status.clone(),
+            node_id: info.node_id,
+            created_at: info.created_at,
+            source_node_id: info.source_node_id,
+        };
+        assert!(bincode::deserialize::<InstanceInfo>(&bincode::serialize(&unlabeled).unwrap()).is_err());
+    }
+
+    #[tokio::test]
+    async fn forwarded_input_is_acknowledged_by_the_source() {
+        let mut source = shadow_manager();
+        let mut target = shadow_manager();
+        let source_queue = crate::network_manager::OutboundQueue::new(16);
+        let target_queue = crate::network_manager::OutboundQueue::new(16);
+        source.set_network_sender(source_queue.clone());
+        target.set_network_sender(target_queue.clone());
+
+        let started = source.instance_manager.lock().await
+            .start_instance("cat".to_string(), Vec::new(), source.process_manager.clone())
+            .await
+            .unwrap();
+        let (instance_id, info) = {
+            let instance_manager = source.instance_manager.lock().await;
+            let instance = instance_manager.get_instance_by_id(&started).unwrap();
+            (instance.id, source.instance_info(instance))
+        };
+        target.handle_instance_sync(sync_from(source.local_node_id, vec![info])).await.unwrap();
+        let registry = DistributedInstanceRegistry::new();
+        registry.record_shadow(instance_id, target.local_node_id, Some(source.local_node_id), false, Utc::now()).await;
+
+        let input_id = target.forward_input_to_source(instance_id, "hello".to_string()).await.unwrap();
+        assert!(matches!(target.input_delivery(input_id).await, Some(InputDelivery::Pending)));
+
+        // The sync queued its own messages ahead of the input
+        let input = loop {
+            match target_queue.recv().await {
+                Some(NetworkMessage::ShadowInput(input)) => break input,
+                Some(_) => continue,
+                None => panic!("input was not forwarded"),
+            }
+        };
+        source.handle_shadow_input(input, target.local_node_id, &registry).await.unwrap();
+        let Some(NetworkMessage::ShadowInputAck(ack)) = source_queue.recv().await else { panic!("input was not acknowledged") };
+        assert_eq!(ack.input_id, input_id);
+        target.handle_shadow_input_ack(ack).await;
+
+        assert!(matches!(target.input_delivery(input_id).await, Some(InputDelivery::Delivered)));
+        // The attach session reports the settled state once
+        assert!(target.input_delivery(input_id).await.is_none());
+
+        source.instance_manager.lock().await.stop_instance(&started, source.process_manager.clone()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn attaching_to_a_shadow_asks_the_source_to_stream_live() {
+        let mut source = shadow_manager();
+        let mut target = shadow_manager();
+        let source_queue = OutboundQueue::new(16);
+        let target_queue = OutboundQueue::new(16);
+        source.set_network_sender(source_queue.clone());
+        target.set_network_sender(target_queue.clone());
+        // Long enough that batched output would not go out during the test
+        source.set_output_batch_window(Duration::from_secs(60));
+        let instance = labeled_instance();
+        target.handle_instance_sync(sync_from(source.local_node_id, vec![source.instance_info(&instance)])).await.unwrap();
+
+        assert_eq!(target.request_live_output(instance.id, true).await.unwrap(), source.local_node_id);
+        // The sync queued its own messages ahead of the request
+        let request = loop {
+            match target_queue.recv().await {
+                Some(NetworkMessage::LiveOutputRequest(request)) => break request,
+                Some(_) => continue,
+                None => panic!("live output was not requested"),
+            }
+        };
+        assert_eq!((request.sender_id, request.target_node_id, request.instance_id), (target.local_node_id, source.local_node_id, instance.id));
+        assert!(request.enabled);
+
+        // While the request holds, output reaches the shadows without waiting for the batch window
+        source.handle_live_output_request(request.clone()).await;
+        source.stream_output_to_shadows(instance.id, b"live\n".to_vec(), StreamType::Stdout).await.unwrap();
+        let Some(NetworkMessage::StreamChunk(_)) = source_queue.recv().await else { panic!("output was not streamed live") };
+
+        // Detaching stops it, and output is batched again
+        source.handle_live_output_request(LiveOutputRequestMessage { enabled: false, ..request }).await;
+        source.stream_output_to_shadows(instance.id, b"batched\n".to_vec(), StreamType::Stdout).await.unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(200), source_queue.recv()).await.is_err());
+
+        // A shadow this node does not hold has no source to ask
+        assert!(matches!(target.request_live_output(Uuid::new_v4(), true).await, Err(ShadowError::NoShadow(_))));
+    }
+
+    #[tokio::test]
+    async fn a_node_joining_after_an_instance_started_acquires_its_shadow() {
+        let mut source = shadow_manager();
+        let source_queue = OutboundQueue::new(16);
+        source.set_network_sender(source_queue.clone());
+        let instance = labeled_instance();
+        let instance_id = instance.id;
+        source.instance_manager.lock().await.add_instance(instance);
+        let source = Arc::new(RwLock::new(source));
+        ShadowInstanceManager::start_reconcile_task(source.clone(), Duration::from_millis(100));
+
+        // Advertisements sent before the late node joined never reach it
+        let Some(NetworkMessage::InstanceSync(_)) = source_queue.recv().await else { panic!("instance was not advertised") };
+        let late = Arc::new(shadow_manager());
+        assert!(late.get_shadow_instance(instance_id).await.is_none());
+
+        // From now on the source's messages are delivered to it
+        let relay = {
+            let late = late.clone();
+            let queue = source_queue.clone();
+            tokio::spawn(async move {
+                while let Some(message) = queue.recv().await {
+                    if let NetworkMessage::InstanceSync(sync) = message {
+                        late.handle_instance_sync(sync).await.unwrap();
+                    }
+                }
+            })
+        };
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while late.get_shadow_instance(instance_id).await.is_none() {
+            assert!(std::time::Instant::now() < deadline, "no shadow after reconciliation");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let shadow = late.get_shadow_instance(instance_id).await.unwrap();
+        assert_eq!(shadow.source_node_id, source.read().await.local_node_id);
+        relay.abort();
+
+        // A joining node also asks for a snapshot rather than waiting for the next round
+        let mut joining = shadow_manager();
+        let joining_queue = OutboundQueue::new(16);
+        joining.set_network_sender(joining_queue.clone());
+        joining.request_instance_sync().await.unwrap();
+        let Some(NetworkMessage::RequestInstanceSync(request)) = joining_queue.recv().await else { panic!("no instance sync requested") };
+        assert_eq!(request.sender_id, joining.local_node_id);
+    }
+
+    /// Deliver every failover message a node sends to the other nodes, as the cluster would
+    fn relay_failover_messages(nodes: Vec<(Arc<ShadowInstanceManager>, OutboundQueue)>) {
+        for (sender, queue) in &nodes {
+            let sender_id = sender.local_node_id;
+            let queue = queue.clone();
+            let peers: Vec<_> = nodes.iter().map(|(node, _)| node.clone()).filter(|node| node.local_node_id != sender_id).collect();
+            tokio::spawn(async move {
+                while let Some(message) = queue.recv().await {
+                    for peer in &peers {
+                        match message.clone() {
+                            NetworkMessage::FailoverClaim(claim) => peer.handle_failover_claim(claim, false).await.unwrap(),
+                            NetworkMessage::FailoverClaimAck(ack) => peer.handle_failover_claim_ack(ack).await,
+                            NetworkMessage::FailoverRelease(claim) => peer.handle_failover_release(claim).await,
+                            _ => {}
+                        }
+                    }
+                }
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn exactly_one_of_two_self_selected_holders_takes_over() {
+        let mut nodes = Vec::new();
+        for _ in 0..3 {
+            let mut manager = shadow_manager();
+            let queue = OutboundQueue::new(64);
+            manager.set_network_sender(queue.clone());
+            nodes.push((Arc::new(manager), queue));
+        }
+        let (first, second, bystander) = (nodes[0].0.clone(), nodes[1].0.clone(), nodes[2].0.clone());
+        relay_failover_messages(nodes);
+        let (instance_id, source_node_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let peers_of = |node: &Arc<ShadowInstanceManager>| -> BTreeSet<NodeId> {
+            [&first, &second, &bystander].iter().map(|peer| peer.local_node_id).filter(|id| *id != node.local_node_id).collect()
+        };
+
+        // Both holders' views of the cluster make them select themselves, so both claim at once.
+        // A round may end with neither winning; the losers withdraw and the next check retries.
+        let winner = loop {
+            let (first_won, second_won) = tokio::join!(
+                first.claim_failover(instance_id, source_node_id, peers_of(&first)),
+                second.claim_failover(instance_id, source_node_id, peers_of(&second)),
+            );
+            assert!(!(first_won && second_won), "both holders took over the instance");
+            if first_won {
+                break first.clone();
+            }
+            if second_won {
+                break second.clone();
+            }
+        };
+
+        // The other holder and the bystander stand by the grant on later checks
+        for node in [&first, &second, &bystander] {
+            if node.local_node_id == winner.local_node_id {
+                continue;
+            }
+            assert!(!node.claim_failover(instance_id, source_node_id, peers_of(node)).await);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::message_protocol::*;
-use crate::types::{Instance, InstanceStatus};
+use crate::network_manager::OutboundQueue;
+use crate::types::Instance;
 use anyhow::{Result, Context};
 use chrono::{DateTime, Utc};
 use std::collections::HashMap;
@@ -39,7 +40,7 @@ pub struct ShadowStateManager {
     shadow_states: Arc<RwLock<HashMap<Uuid, ShadowState>>>,
     event_sender: mpsc::UnboundedSender<ShadowEvent>,
     event_receiver: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<ShadowEvent>>>,
-    network_sender: Option<mpsc::UnboundedSender<NetworkMessage>>,
+    network_sender: Option<OutboundQueue>,
 }
 
 impl ShadowStateManager {
@@ -56,7 +57,7 @@ impl ShadowStateManager {
     }
 
     /// Set the network sender for broadcasting shadow data
-    pub fn set_network_sender(&mut self, sender: mpsc::UnboundedSender<NetworkMessage>) {
+    pub fn set_network_sender(&mut self, sender: OutboundQueue) {
         self.network_sender = Some(sender);
     }
 
@@ -81,7 +82,8 @@ impl ShadowStateManager {
     }
 
     /// Update shadow state with new data from the running instance
-    pub async fn update_shadow_state(&self, sync_message: ShadowSyncMessage) -> Result<()> {
+    pub async fn update_shadow_state(&self, mut sync_message: ShadowSyncMessage) -> Result<()> {
+        sync_message.decompress_output().context("Failed to decompress shadow output")?;
         let updated = {
             let mut states = self.shadow_states.write().await;
             
@@ -144,7 +146,7 @@ impl ShadowStateManager {
 
             let network_message = NetworkMessage::DataStream(stream_message);
             
-            if let Err(e) = network_sender.send(network_message) {
+            if let Err(e) = network_sender.send(network_message).await {
                 error!("Failed to send data stream message: {}", e);
                 return Err(anyhow::anyhow!("Failed to send data stream: {}", e));
             }
@@ -248,11 +250,13 @@ impl ShadowStateManager {
                                     Some(shadow_state.output_buffer.clone())
                                 },
                                 timestamp: Utc::now(),
+                                is_migration: false,
+                                output_compressed: false,
                             };
 
                             let network_message = NetworkMessage::ShadowSync(sync_message);
                             
-                            if let Err(e) = sender.send(network_message) {
+                            if let Err(e) = sender.send(network_message).await {
                                 error!("Failed to send periodic shadow sync: {}", e);
                             }
                         }
@@ -0,0 +1,168 @@
+//! Termination signals, fanned out over a channel so the command loop can shut down and a
+//! blocking command can stop waiting
+
+use std::future::Future;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// A signal asking nhi to stop what it is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownSignal {
+    Interrupt,
+    Terminate,
+}
+
+impl ShutdownSignal {
+    pub fn name(self) -> &'static str {
+        match self {
+            ShutdownSignal::Interrupt => "SIGINT",
+            ShutdownSignal::Terminate => "SIGTERM",
+        }
+    }
+
+    /// Exit status of a process ended by this signal: 128 plus the signal number
+    pub fn exit_code(self) -> i32 {
+        let signal = match self {
+            ShutdownSignal::Interrupt => nix::sys::signal::Signal::SIGINT,
+            ShutdownSignal::Terminate => nix::sys::signal::Signal::SIGTERM,
+        };
+        128 + signal as i32
+    }
+}
+
+/// Every received signal goes to all subscribers: the command loop, and whichever command is
+/// blocked waiting when it arrives
+#[derive(Debug, Clone)]
+pub struct Signals {
+    sender: broadcast::Sender<ShutdownSignal>,
+}
+
+impl Default for Signals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Signals {
+    pub fn new() -> Self {
+        Self { sender: broadcast::channel(8).0 }
+    }
+
+    /// Deliver a signal to the current subscribers
+    pub fn raise(&self, signal: ShutdownSignal) {
+        let _ = self.sender.send(signal);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ShutdownSignal> {
+        self.sender.subscribe()
+    }
+
+    /// Forward SIGTERM and SIGINT from the OS to the channel
+    pub fn listen(&self) {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let (mut sigterm, mut sigint) = match (signal(SignalKind::terminate()), signal(SignalKind::interrupt())) {
+            (Ok(sigterm), Ok(sigint)) => (sigterm, sigint),
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("Failed to install signal handlers, SIGTERM will not shut down gracefully: {}", e);
+                return;
+            }
+        };
+
+        let signals = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let signal = tokio::select! {
+                    _ = sigterm.recv() => ShutdownSignal::Terminate,
+                    _ = sigint.recv() => ShutdownSignal::Interrupt,
+                };
+                warn!("Received {}", signal.name());
+                signals.raise(signal);
+            }
+        });
+    }
+
+    /// Run `future` unless a signal arrives first. Commands that wait on the cluster use this,
+    /// so Ctrl-C ends the wait and leaves the node running.
+    pub async fn interruptible<F: Future>(&self, future: F) -> Result<F::Output, ShutdownSignal> {
+        let mut receiver = self.subscribe();
+        tokio::select! {
+            output = future => Ok(output),
+            signal = receiver.recv() => Err(signal.unwrap_or(ShutdownSignal::Interrupt)),
+        }
+    }
+}
+
+/// Signal that should end the command loop once a command returns. A SIGINT that arrived while
+/// the command ran was meant for the command, so only SIGTERM shuts down.
+pub fn pending_shutdown(receiver: &mut broadcast::Receiver<ShutdownSignal>) -> Option<ShutdownSignal> {
+    let mut shutdown = None;
+    loop {
+        match receiver.try_recv() {
+            Ok(ShutdownSignal::Terminate) => shutdown = Some(ShutdownSignal::Terminate),
+            Ok(ShutdownSignal::Interrupt) => {}
+            Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+            Err(_) => return shutdown,
+        }
+    }
+}
+
+/// Wait for the next signal; the command loop does this while it waits for input
+pub async fn next_signal(receiver: &mut broadcast::Receiver<ShutdownSignal>) -> ShutdownSignal {
+    loop {
+        match receiver.recv().await {
+            Ok(signal) => return signal,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => std::future::pending().await,
+        }
+    }
+}
+
+/// Terminal settings of stdin, put back on a signal exit that leaves line editing unfinished
+pub struct SavedTerminal(Option<nix::sys::termios::Termios>);
+
+impl SavedTerminal {
+    pub fn save() -> Self {
+        Self(nix::sys::termios::tcgetattr(std::io::stdin()).ok())
+    }
+
+    pub fn restore(&self) {
+        if let Some(termios) = &self.0 {
+            let _ = nix::sys::termios::tcsetattr(std::io::stdin(), nix::sys::termios::SetArg::TCSANOW, termios);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn interrupt_stops_a_blocking_wait_but_not_the_command_loop() {
+        let signals = Signals::new();
+        let mut command_loop = signals.subscribe();
+
+        let waiting = signals.clone();
+        let wait = tokio::spawn(async move { waiting.interruptible(std::future::pending::<()>()).await });
+        tokio::task::yield_now().await;
+        signals.raise(ShutdownSignal::Interrupt);
+
+        assert_eq!(wait.await.unwrap(), Err(ShutdownSignal::Interrupt));
+        assert_eq!(pending_shutdown(&mut command_loop), None);
+    }
+
+    #[tokio::test]
+    async fn only_terminate_ends_the_command_loop_after_a_command() {
+        let signals = Signals::new();
+        let mut command_loop = signals.subscribe();
+
+        signals.raise(ShutdownSignal::Interrupt);
+        assert_eq!(pending_shutdown(&mut command_loop), None);
+
+        signals.raise(ShutdownSignal::Interrupt);
+        signals.raise(ShutdownSignal::Terminate);
+        assert_eq!(pending_shutdown(&mut command_loop), Some(ShutdownSignal::Terminate));
+        assert_eq!(ShutdownSignal::Terminate.exit_code(), 143);
+        assert_eq!(ShutdownSignal::Interrupt.exit_code(), 130);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::message_protocol::{NodeId, NodeInfo, NodeStatus};
+use crate::types::{Instance, InstanceStatus};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Instances hosted or shadowed for one node
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct NodeInstanceCounts {
+    pub name: String,
+    pub online: bool,
+    pub running: usize, // Running or paused instances whose process lives on the node
+    pub shadows: usize, // Shadows held locally of instances running on the node
+}
+
+/// One-shot summary of instance distribution and checkpoint storage
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct ClusterSummary {
+    pub total_instances: usize,
+    pub running: usize,
+    pub paused: usize,
+    pub shadow: usize,
+    pub stopped: usize,
+    pub failed: usize,
+    pub starting: usize,
+    pub nodes: BTreeMap<NodeId, NodeInstanceCounts>,
+    pub active_migrations: usize,
+    pub checkpoint_count: usize,
+    pub checkpoint_bytes: u64,
+}
+
+/// Count instances by status and attribute them to nodes. Local processes count for
+/// `local_node_id`; shadows count for the node their source runs on.
+pub fn summarize(
+    instances: &[Instance],
+    local_node_id: Option<NodeId>,
+    nodes: &[NodeInfo],
+    active_migrations: usize,
+) -> ClusterSummary {
+    let mut summary = ClusterSummary {
+        total_instances: instances.len(),
+        active_migrations,
+        ..Default::default()
+    };
+
+    for node in nodes {
+        summary.nodes.insert(node.node_id, NodeInstanceCounts {
+            name: node.name.clone(),
+            online: node.status == NodeStatus::Online,
+            ..Default::default()
+        });
+    }
+
+    for instance in instances {
+        match instance.status {
+            InstanceStatus::Running => summary.running += 1,
+            InstanceStatus::Paused => summary.paused += 1,
+            InstanceStatus::Shadow => summary.shadow += 1,
+            InstanceStatus::Stopped => summary.stopped += 1,
+            InstanceStatus::Failed => summary.failed += 1,
+            InstanceStatus::Starting => summary.starting += 1,
+        }
+        summary.checkpoint_count += instance.checkpoints.len();
+
+        let node_id = match instance.status {
+            InstanceStatus::Running | InstanceStatus::Paused => local_node_id,
+            InstanceStatus::Shadow => instance.source_node_id,
+            _ => None,
+        };
+        if let Some(node_id) = node_id {
+            let counts = summary.nodes.entry(node_id).or_default();
+            if instance.status == InstanceStatus::Shadow {
+                counts.shadows += 1;
+            } else {
+                counts.running += 1;
+            }
+        }
+    }
+
+    summary
+}
+
+/// Bytes used by every `instances/*/checkpoints` tree. Hard-linked files (deduplicated
+/// checkpoints, staged incremental parents) are counted once; symlinks are not followed.
+pub fn checkpoint_disk_usage(instances_dir: &Path) -> u64 {
+    let mut seen = HashSet::new();
+    let mut total = 0;
+    if let Ok(entries) = fs::read_dir(instances_dir) {
+        for entry in entries.flatten() {
+            total += tree_size(&entry.path().join("checkpoints"), &mut seen);
+        }
+    }
+    total
+}
+
+/// Bytes used by everything below `dir`, hard links counted once
+pub fn dir_disk_usage(dir: &Path) -> u64 {
+    tree_size(dir, &mut HashSet::new())
+}
+
+fn tree_size(dir: &Path, seen: &mut HashSet<(u64, u64)>) -> u64 {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    let mut total = 0;
+    for entry in entries.flatten() {
+        let metadata = match fs::symlink_metadata(entry.path()) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        if metadata.is_dir() {
+            total += tree_size(&entry.path(), seen);
+        } else if metadata.is_file() && seen.insert((metadata.dev(), metadata.ino())) {
+            total += metadata.len();
+        }
+    }
+    total
+}
+
+/// Bytes an unprivileged process can still write on the filesystem holding `dir`
+pub fn disk_available(dir: &Path) -> Option<u64> {
+    let stat = nix::sys::statvfs::statvfs(dir).ok()?;
+    Some(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+}
+
+/// Resident memory of the given processes, the bulk of what a CRIU dump writes
+pub fn resident_bytes(pids: &[u32]) -> u64 {
+    pids.iter()
+        .filter_map(|pid| fs::read_to_string(format!("/proc/{}/status", pid)).ok())
+        .filter_map(|status| {
+            status.lines()
+                .find_map(|line| line.strip_prefix("VmRSS:"))
+                .and_then(|value| value.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        })
+        .map(|kib| kib * 1024)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn instance(status: InstanceStatus, source_node_id: Option<NodeId>) -> Instance {
+        crate::test_support::use_scratch_dir();
+        let mut instance = Instance::new("sleep".to_string(), vec!["30".to_string()], std::path::PathBuf::from("/"));
+        instance.status = status;
+        instance.source_node_id = source_node_id;
+        instance
+    }
+
+    #[test]
+    fn summary_counts_instances_by_status_and_node() {
+        let local = Uuid::new_v4();
+        let remote = NodeInfo::new(Uuid::new_v4(), "remote".to_string(), "127.0.0.1:1".parse().unwrap());
+        let mut offline = NodeInfo::new(Uuid::new_v4(), "offline".to_string(), "127.0.0.1:2".parse().unwrap());
+        offline.status = NodeStatus::Offline;
+
+        let mut checkpointed = instance(InstanceStatus::Running, None);
+        for name in ["first", "second"] {
+            checkpointed.checkpoints.insert(name.to_string(), crate::types::CheckpointInfo {
+                name: name.to_string(),
+                created_at: chrono::Utc::now(),
+                checkpoint_dir: std::path::PathBuf::from(name),
+                original_instance_id: checkpointed.id,
+            });
+        }
+        let instances = [
+            checkpointed,
+            instance(InstanceStatus::Running, None),
+            instance(InstanceStatus::Paused, None),
+            instance(InstanceStatus::Shadow, Some(remote.node_id)),
+            instance(InstanceStatus::Shadow, Some(remote.node_id)),
+            instance(InstanceStatus::Stopped, None),
+            instance(InstanceStatus::Failed, None),
+        ];
+
+        let summary = summarize(&instances, Some(local), &[remote.clone(), offline.clone()], 1);
+        assert_eq!(summary.total_instances, 7);
+        assert_eq!((summary.running, summary.paused, summary.shadow), (2, 1, 2));
+        assert_eq!((summary.stopped, summary.failed, summary.starting), (1, 1, 0));
+        assert_eq!((summary.active_migrations, summary.checkpoint_count), (1, 2));
+        assert_eq!((summary.nodes[&local].running, summary.nodes[&local].shadows), (3, 0));
+        assert_eq!((summary.nodes[&remote.node_id].running, summary.nodes[&remote.node_id].shadows), (0, 2));
+        assert!(summary.nodes[&remote.node_id].online);
+        assert!(!summary.nodes[&offline.node_id].online);
+        assert_eq!(summary.nodes[&offline.node_id].running + summary.nodes[&offline.node_id].shadows, 0);
+    }
+
+    #[test]
+    fn checkpoint_usage_counts_hard_linked_images_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("instance_a").join("checkpoints").join("one");
+        let second = dir.path().join("instance_b").join("checkpoints").join("two");
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        fs::write(first.join("pages-1.img"), vec![0u8; 1000]).unwrap();
+        fs::hard_link(first.join("pages-1.img"), second.join("pages-1.img")).unwrap();
+        fs::write(second.join("core-1.img"), vec![0u8; 24]).unwrap();
+        // Output logs are not checkpoints
+        fs::create_dir_all(dir.path().join("instance_a").join("output")).unwrap();
+        fs::write(dir.path().join("instance_a").join("output").join("stdout.log"), vec![0u8; 500]).unwrap();
+
+        assert_eq!(checkpoint_disk_usage(dir.path()), 1024);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::types::{CriuCliError, Result};
+use std::fmt;
+use std::path::Path;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{info, warn};
+
+/// Tool privileged CRIU calls are wrapped in (--privilege-cmd)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PrivilegeCommand {
+    #[default]
+    Sudo,
+    Doas,
+    Run0,
+    /// Run the program directly; it must already have the required privileges
+    None,
+}
+
+impl PrivilegeCommand {
+    /// Words placed in front of the privileged program; each wrapper is told never to prompt
+    pub fn prefix(&self) -> &'static [&'static str] {
+        match self {
+            PrivilegeCommand::Sudo => &["sudo", "-n"],
+            PrivilegeCommand::Doas => &["doas", "-n"],
+            PrivilegeCommand::Run0 => &["run0", "--no-ask-password"],
+            PrivilegeCommand::None => &[],
+        }
+    }
+}
+
+impl fmt::Display for PrivilegeCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrivilegeCommand::Sudo => write!(f, "sudo"),
+            PrivilegeCommand::Doas => write!(f, "doas"),
+            PrivilegeCommand::Run0 => write!(f, "run0"),
+            PrivilegeCommand::None => write!(f, "none"),
+        }
+    }
+}
+
+impl FromStr for PrivilegeCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sudo" => Ok(PrivilegeCommand::Sudo),
+            "doas" => Ok(PrivilegeCommand::Doas),
+            "run0" => Ok(PrivilegeCommand::Run0),
+            "none" => Ok(PrivilegeCommand::None),
+            _ => Err(format!("unknown privilege command '{}' (expected sudo, doas, run0 or none)", s)),
+        }
+    }
+}
+
+/// A privilege wrapper, remembered as verified once it has run without asking for a password
+#[derive(Debug, Default)]
+pub struct Privilege {
+    command: PrivilegeCommand,
+    verified: AtomicBool,
+}
+
+impl Privilege {
+    pub fn new(command: PrivilegeCommand) -> Self {
+        Self { command, verified: AtomicBool::new(false) }
+    }
+
+    /// Wrapper privileged calls run through
+    pub fn command(&self) -> PrivilegeCommand {
+        self.command
+    }
+
+    /// Verify that the privilege wrapper can run without prompting for a password.
+    ///
+    /// Runs e.g. `sudo -n true` once; a password prompt would otherwise hang the REPL
+    /// or corrupt the attach TUI.
+    pub fn check_available(&self) -> Result<()> {
+        if self.command == PrivilegeCommand::None || self.verified.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let prefix = self.command.prefix();
+        let output = std::process::Command::new(prefix[0])
+            .args(&prefix[1..])
+            .arg("true")
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| CriuCliError::CriuError(format!("Failed to execute {}: {}", self.command, e)))?;
+        self.record_check(output)
+    }
+
+    /// Remember a successful non-interactive run, or turn a refused one into an actionable error
+    pub(crate) fn record_check(&self, output: std::process::Output) -> Result<()> {
+        if output.status.success() {
+            info!("Passwordless {} is available for CRIU", self.command);
+            self.verified.store(true, Ordering::SeqCst);
+            return Ok(());
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        warn!("{} failed: {}", self.command.prefix().join(" "), stderr.trim());
+        Err(sudo_password_required_error(self.command))
+    }
+}
+
+/// Actionable error returned when the privilege wrapper would prompt for a password
+pub fn sudo_password_required_error(command: PrivilegeCommand) -> CriuCliError {
+    CriuCliError::CriuError(format!(
+        "{} requires a password, so CRIU cannot be run non-interactively. \
+         Configure passwordless {} for the CRIU binary, choose another --privilege-cmd, \
+         or start NHI with --privilege-cmd none and a --criu-path that already has the required privileges",
+        command, command
+    ))
+}
+
+/// Build a command running `program` with privileges (e.g. `sudo -n <program>`, or the bare program with `none`)
+pub fn privileged_command<P: AsRef<Path>>(command: PrivilegeCommand, program: P) -> tokio::process::Command {
+    privileged_std_command(command, program).into()
+}
+
+/// Blocking variant of `privileged_command`
+pub fn privileged_std_command<P: AsRef<Path>>(command: PrivilegeCommand, program: P) -> std::process::Command {
+    match command.prefix().split_first() {
+        Some((wrapper, wrapper_args)) => {
+            let mut cmd = std::process::Command::new(wrapper);
+            cmd.args(wrapper_args).arg(program.as_ref());
+            cmd
+        }
+        None => std::process::Command::new(program.as_ref()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output of a wrapper run the way `sudo -n true` ends when a password would be needed
+    fn wrapper_output(script: &str) -> std::process::Output {
+        std::process::Command::new("sh").arg("-c").arg(script).output().unwrap()
+    }
+
+    #[test]
+    fn password_prompt_is_reported_with_a_way_out() {
+        let privilege = Privilege::new(PrivilegeCommand::Sudo);
+        let error = privilege
+            .record_check(wrapper_output("echo 'sudo: a password is required' >&2; exit 1"))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("requires a password"), "{}", error);
+        assert!(error.contains("--privilege-cmd none"), "{}", error);
+        assert!(!privilege.verified.load(Ordering::SeqCst));
+
+        privilege.record_check(wrapper_output("true")).unwrap();
+        assert!(privilege.verified.load(Ordering::SeqCst));
+        // Without a wrapper there is nothing to check
+        assert!(Privilege::new(PrivilegeCommand::None).check_available().is_ok());
+    }
+
+    #[test]
+    fn privileged_commands_use_the_configured_wrapper() {
+        let words = |command: PrivilegeCommand| {
+            let cmd = privileged_std_command(command, "/usr/sbin/criu");
+            std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(|word| word.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(words(PrivilegeCommand::Sudo), ["sudo", "-n", "/usr/sbin/criu"]);
+        assert_eq!(words(PrivilegeCommand::Doas), ["doas", "-n", "/usr/sbin/criu"]);
+        assert_eq!(words(PrivilegeCommand::Run0), ["run0", "--no-ask-password", "/usr/sbin/criu"]);
+        assert_eq!(words(PrivilegeCommand::None), ["/usr/sbin/criu"]);
+
+        assert_eq!("DOAS".parse::<PrivilegeCommand>(), Ok(PrivilegeCommand::Doas));
+        assert!("su".parse::<PrivilegeCommand>().is_err());
+    }
+}
@@ -0,0 +1,15 @@
+//! Helpers shared by the unit tests
+
+use std::sync::Once;
+
+/// Move the test process into a scratch directory, once. Instances keep their state
+/// under a relative `instances/` directory, which must not land in the source tree.
+pub fn use_scratch_dir() {
+    static ENTER: Once = Once::new();
+    ENTER.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("nhi-tests-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create scratch directory");
+        std::env::set_current_dir(&dir).expect("enter scratch directory");
+        let _ = crate::instance::data_dir();
+    });
+}
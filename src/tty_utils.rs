@@ -1,10 +1,17 @@
-use crate::types::{CriuCliError, Result};
+use crate::types::{CriuCliError, RestoreTty, Result};
+use serde::{Deserialize, Serialize};
 use std::fs;
 use std::os::unix::fs::MetadataExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tracing::{info, warn};
 
-#[derive(Debug, Clone)]
+/// TTY file descriptors of the process as it was dumped, kept for `restore --tty`
+pub const TTY_FDS_FILE: &str = "tty_fds.json";
+
+/// Restorer fd that the TTY target is handed to CRIU on (its stdin)
+const RESTORE_TTY_FD: i32 = 0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TtyInfo {
     pub rdev: u64,
     pub dev: u64,
@@ -128,18 +135,88 @@ pub fn generate_criu_tty_args(tty_env: &TtyEnvironment) -> Vec<String> {
     args
 }
 
-pub fn generate_criu_restore_tty_args(tty_env: &TtyEnvironment) -> Vec<String> {
+pub fn generate_criu_restore_tty_args(tty_env: &TtyEnvironment, target: RestoreTty) -> Vec<String> {
     let mut args = Vec::new();
-    
-    // For restore, we need --inherit-fd arguments
-    for (fd, tty_info) in &tty_env.tty_fds {
+
+    if target == RestoreTty::Inherit {
+        // For restore, we need --inherit-fd arguments
+        for (fd, tty_info) in &tty_env.tty_fds {
+            args.push("--inherit-fd".to_string());
+            args.push(format!("fd[{}]:{}", fd, tty_info.tty_format));
+        }
+        return args;
+    }
+
+    // Every dumped terminal is replaced by the target CRIU receives on its stdin
+    let mut unique_ttys = std::collections::BTreeSet::new();
+    for (_, tty_info) in &tty_env.tty_fds {
+        unique_ttys.insert(tty_info.tty_format.clone());
+    }
+    for tty_format in unique_ttys {
         args.push("--inherit-fd".to_string());
-        args.push(format!("fd[{}]:{}", fd, tty_info.tty_format));
+        args.push(format!("fd[{}]:{}", RESTORE_TTY_FD, tty_format));
     }
-    
+
     args
 }
 
+/// Record the TTY fds of a dumped process next to its images
+pub fn save_tty_environment(tty_env: &TtyEnvironment, checkpoint_dir: &Path) {
+    if tty_env.tty_fds.is_empty() {
+        return;
+    }
+    let result = serde_json::to_string(&tty_env.tty_fds)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(checkpoint_dir.join(TTY_FDS_FILE), json).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        warn!("Failed to record TTY fds in {:?}: {}", checkpoint_dir, e);
+    }
+}
+
+/// TTY fds recorded when the checkpoint was dumped, if it had any
+pub fn load_tty_environment(checkpoint_dir: &Path) -> Option<TtyEnvironment> {
+    let content = fs::read_to_string(checkpoint_dir.join(TTY_FDS_FILE)).ok()?;
+    let tty_fds: Vec<(i32, TtyInfo)> = serde_json::from_str(&content).ok()?;
+    Some(TtyEnvironment {
+        is_complex: tty_fds.len() > 3 || tty_fds.iter().any(|(fd, _)| *fd > 2),
+        tty_fds,
+        recommendations: Vec::new(),
+    })
+}
+
+/// Allocate a pseudo-terminal for a restored process, returning its master and the slave's path
+pub fn open_restore_pty() -> Result<(fs::File, PathBuf)> {
+    use std::os::fd::AsRawFd;
+
+    let pty = nix::pty::openpty(None, None).map_err(|e| {
+        CriuCliError::ProcessError(format!("Failed to allocate a pseudo-terminal: {}", e))
+    })?;
+    let slave_path = fs::read_link(format!("/proc/self/fd/{}", pty.slave.as_raw_fd())).map_err(|e| {
+        CriuCliError::ProcessError(format!("Failed to resolve pseudo-terminal slave: {}", e))
+    })?;
+    Ok((fs::File::from(pty.master), slave_path))
+}
+
+/// Copy everything the restored process writes to its new terminal into `log_path`.
+/// Holding the master open keeps the terminal alive; the thread ends once the process closes it.
+pub fn forward_pty_output(mut master: fs::File, log_path: PathBuf) {
+    std::thread::spawn(move || {
+        let mut log = match fs::OpenOptions::new().create(true).append(true).open(&log_path) {
+            Ok(log) => log,
+            Err(e) => {
+                warn!("Failed to open {:?} for pseudo-terminal output: {}", log_path, e);
+                return;
+            }
+        };
+        if let Err(e) = std::io::copy(&mut master, &mut log) {
+            // A master read fails with EIO once the last slave fd is closed
+            if e.raw_os_error() != Some(nix::libc::EIO) {
+                warn!("Pseudo-terminal output forwarding to {:?} stopped: {}", log_path, e);
+            }
+        }
+    });
+}
+
 pub fn print_tty_analysis(tty_env: &TtyEnvironment) {
     println!("=== TTY Environment Analysis ===");
     
@@ -170,7 +247,7 @@ pub fn print_tty_analysis(tty_env: &TtyEnvironment) {
             println!("  For dump: {}", dump_args.join(" "));
         }
         
-        let restore_args = generate_criu_restore_tty_args(tty_env);
+        let restore_args = generate_criu_restore_tty_args(tty_env, RestoreTty::Inherit);
         if !restore_args.is_empty() {
             println!("  For restore: {}", restore_args.join(" "));
         }
@@ -197,3 +274,60 @@ pub fn check_process_tty_compatibility(pid: u32) -> Result<bool> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A job started from one terminal (stdin/stdout/stderr) that also opened a second one
+    fn two_terminal_environment() -> TtyEnvironment {
+        let (console, other) = (TtyInfo::new(0x8800, 0x3), TtyInfo::new(0x8801, 0x3));
+        TtyEnvironment {
+            is_complex: true,
+            tty_fds: vec![(0, console.clone()), (1, console.clone()), (2, console), (5, other)],
+            recommendations: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn restore_tty_args_follow_the_requested_target() {
+        let tty_env = two_terminal_environment();
+        assert_eq!(
+            generate_criu_restore_tty_args(&tty_env, RestoreTty::Inherit),
+            [
+                "--inherit-fd", "fd[0]:tty[8800:3]",
+                "--inherit-fd", "fd[1]:tty[8800:3]",
+                "--inherit-fd", "fd[2]:tty[8800:3]",
+                "--inherit-fd", "fd[5]:tty[8801:3]",
+            ]
+        );
+        // Each dumped terminal is handed the one target CRIU gets on its stdin
+        for target in [RestoreTty::Null, RestoreTty::Pty] {
+            assert_eq!(
+                generate_criu_restore_tty_args(&tty_env, target),
+                ["--inherit-fd", "fd[0]:tty[8800:3]", "--inherit-fd", "fd[0]:tty[8801:3]"]
+            );
+        }
+    }
+
+    #[test]
+    fn tty_fds_recorded_at_dump_are_loaded_for_restore() {
+        let checkpoint_dir = tempfile::tempdir().unwrap();
+        assert!(load_tty_environment(checkpoint_dir.path()).is_none());
+
+        save_tty_environment(&two_terminal_environment(), checkpoint_dir.path());
+        let loaded = load_tty_environment(checkpoint_dir.path()).unwrap();
+        assert!(loaded.is_complex);
+        assert_eq!(
+            generate_criu_restore_tty_args(&loaded, RestoreTty::Null),
+            generate_criu_restore_tty_args(&two_terminal_environment(), RestoreTty::Null)
+        );
+    }
+
+    #[test]
+    fn a_restore_pty_slave_is_a_terminal_device() {
+        let (_master, slave_path) = open_restore_pty().unwrap();
+        assert!(slave_path.starts_with("/dev/pts"), "{:?}", slave_path);
+        assert!(fs::metadata(&slave_path).unwrap().rdev() != 0);
+    }
+}
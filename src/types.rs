@@ -1,6 +1,6 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -29,6 +29,213 @@ pub struct Instance {
     pub source_node_id: Option<Uuid>, // Node ID where the running instance is located
     pub shadow_data_version: u64,     // Version counter for shadow data synchronization
     pub last_sync_time: Option<DateTime<Utc>>, // Last time shadow data was synchronized
+    #[serde(default)]
+    pub sync_enabled: bool, // Opt-in for periodic checkpoint auto-sync
+    #[serde(default)]
+    pub checkpoint_hooks: CheckpointHooks,
+    #[serde(default)]
+    pub output_file: Option<PathBuf>, // User-requested copy of the program output
+    #[serde(default)]
+    pub sync_base: Option<String>, // Checkpoint the next auto-sync dumps incrementally on top of
+    #[serde(default)]
+    pub affinity: Affinity, // Nodes allowed to host the instance
+    #[serde(default)]
+    pub output_timestamps: OutputTimestamps, // Prefix captured output lines with their time
+    #[serde(default)]
+    pub append_only: bool, // Durably append every output line to the combined log
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>, // Operator-chosen `key=value` tags (`start --label`)
+    #[serde(default)]
+    pub child_pids: Vec<u32>, // Descendants of the primary process, dumped and restored with it
+    #[serde(default)]
+    pub last_error: Option<String>, // Error that put the instance into Failed
+    #[serde(default)]
+    pub failed_operation: Option<FailedOperation>, // Operation `retry` re-attempts
+    #[serde(default)]
+    pub env: Vec<(String, String)>, // Extra environment variables set on (re)start
+    #[serde(default)]
+    pub status_history: Vec<StatusTransition>, // Recent status changes, oldest first
+    #[serde(default)]
+    pub snapshot_policy: Option<SnapshotPolicy>, // Scheduled rollback snapshots, independent of auto-sync
+    #[serde(default)]
+    pub last_snapshot_at: Option<DateTime<Utc>>,
+}
+
+/// Take a retained `snapshot-<time>` checkpoint every `interval_secs`, keeping the newest `retain`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotPolicy {
+    pub interval_secs: u64,
+    pub retain: usize,
+}
+
+impl std::fmt::Display for SnapshotPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "every {}s, keep {}", self.interval_secs, self.retain)
+    }
+}
+
+/// Operation that left an instance `Failed`, kept so `retry` can run it again
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum FailedOperation {
+    Start,
+    Stop,
+    Restore { checkpoint_name: String, options: RestoreOptions },
+}
+
+/// Options for starting a new instance
+#[derive(Debug, Clone, Default)]
+pub struct StartOptions {
+    pub sync: bool,                        // Enable periodic checkpoint auto-sync
+    pub checkpoint_hooks: CheckpointHooks, // Commands run around each checkpoint
+    pub output_file: Option<PathBuf>,      // Also write program output to this file
+    pub foreground: bool,                  // Attach and block until the program exits
+    pub start_paused: bool,                // Stop before the first instruction until `resume`
+    pub affinity: Affinity,                // Nodes the instance may be migrated or failed over to
+    pub instance_id: Option<Uuid>,         // Fixed instance ID instead of a random one
+    pub output_timestamps: Option<OutputTimestamps>, // None: use the global default
+    pub append_only: bool,                 // Durably append every output line to the combined log
+    pub labels: BTreeMap<String, String>,  // `key=value` tags to select the instance by
+    pub snapshot_policy: Option<SnapshotPolicy>, // Scheduled named snapshots (--snapshot-interval)
+}
+
+/// Changes `edit` applies to a stopped instance's stored launch config
+#[derive(Debug, Clone, Default)]
+pub struct InstanceEdit {
+    pub args: Option<Vec<String>>, // Replace the program arguments
+    pub env: Vec<(String, String)>, // Set (or override) environment variables
+    pub cwd: Option<PathBuf>,       // New working directory
+}
+
+impl InstanceEdit {
+    pub fn is_empty(&self) -> bool {
+        self.args.is_none() && self.env.is_empty() && self.cwd.is_none()
+    }
+}
+
+/// Constraints on which nodes may host an instance
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Affinity {
+    pub required_capabilities: Vec<String>, // Every one must be advertised by the node
+    pub nodes: Vec<Uuid>,                   // Allowed nodes; empty allows any
+}
+
+impl Affinity {
+    pub fn is_empty(&self) -> bool {
+        self.required_capabilities.is_empty() && self.nodes.is_empty()
+    }
+
+    /// Check a node against the constraints, describing the first one it violates
+    pub fn check(&self, node_id: Uuid, capabilities: &[String]) -> std::result::Result<(), String> {
+        if !self.nodes.is_empty() && !self.nodes.contains(&node_id) {
+            return Err(format!("node {} is not in the instance's node affinity", node_id));
+        }
+        if let Some(missing) = self.required_capabilities.iter().find(|cap| !capabilities.contains(cap)) {
+            return Err(format!("node {} lacks required capability '{}'", node_id, missing));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Affinity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts: Vec<String> = self.required_capabilities.iter().map(|cap| format!("capability:{}", cap)).collect();
+        parts.extend(self.nodes.iter().map(|node| format!("node:{}", &node.to_string()[..8])));
+        if parts.is_empty() {
+            write!(f, "any")
+        } else {
+            write!(f, "{}", parts.join(","))
+        }
+    }
+}
+
+/// Options for restoring a checkpoint
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RestoreOptions {
+    pub uid_map: Option<IdMap>, // Restore a checkpoint taken by another user
+    pub gid_map: Option<IdMap>,
+    #[serde(default)]
+    pub replace: bool, // Rotate the live output log so it only holds post-restore output
+    #[serde(default)]
+    pub tty: RestoreTty, // Terminal the restored process's TTY fds are reattached to
+}
+
+/// Where a restored process's terminal file descriptors point
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RestoreTty {
+    /// Let CRIU reattach to the restoring terminal (--shell-job)
+    #[default]
+    Inherit,
+    /// Reparent the terminal fds to /dev/null
+    Null,
+    /// Reparent the terminal fds to a freshly allocated pseudo-terminal
+    Pty,
+}
+
+impl std::fmt::Display for RestoreTty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestoreTty::Inherit => write!(f, "inherit"),
+            RestoreTty::Null => write!(f, "null"),
+            RestoreTty::Pty => write!(f, "pty"),
+        }
+    }
+}
+
+impl std::str::FromStr for RestoreTty {
+    type Err = CriuCliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "inherit" => Ok(RestoreTty::Inherit),
+            "null" | "/dev/null" => Ok(RestoreTty::Null),
+            "pty" => Ok(RestoreTty::Pty),
+            other => Err(CriuCliError::ParseError(format!(
+                "Invalid restore TTY target: {} (expected inherit, null or pty)",
+                other
+            ))),
+        }
+    }
+}
+
+/// A `<from>:<to>` user or group ID mapping
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct IdMap {
+    pub from: u32,
+    pub to: u32,
+}
+
+impl std::fmt::Display for IdMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.from, self.to)
+    }
+}
+
+impl std::str::FromStr for IdMap {
+    type Err = CriuCliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parsed = s.split_once(':').and_then(|(from, to)| {
+            Some(IdMap { from: from.parse().ok()?, to: to.parse().ok()? })
+        });
+        parsed.ok_or_else(|| CriuCliError::ParseError(format!(
+            "Invalid ID mapping: {} (expected <from>:<to>)",
+            s
+        )))
+    }
+}
+
+/// Application-level commands run around a checkpoint (via `sh -c`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CheckpointHooks {
+    pub pre_checkpoint_cmd: Option<String>,  // Run before the dump; failure aborts the checkpoint
+    pub post_checkpoint_cmd: Option<String>, // Run after the process is resumed
+}
+
+/// How new checkpoints are written to disk
+#[derive(Clone, Copy, Default)]
+pub struct CheckpointStorage {
+    pub dedup: bool,           // Share unchanged files through the instance's blob store (--dedup-checkpoints)
+    pub key: Option<[u8; 32]>, // Encrypt images at rest; also opens sealed checkpoints (--checkpoint-key)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -54,6 +261,340 @@ impl std::fmt::Display for InstanceStatus {
     }
 }
 
+impl InstanceStatus {
+    /// Whether an instance may move from this status to `to`. Staying in the same status is always allowed.
+    pub fn can_transition_to(&self, to: &InstanceStatus) -> bool {
+        use InstanceStatus::*;
+        self == to
+            || matches!(
+                (self, to),
+                (Starting, Running | Paused | Stopped | Failed | Shadow)
+                    | (Running, Paused | Stopped | Failed | Shadow)
+                    | (Paused, Running | Stopped | Failed | Shadow)
+                    | (Stopped, Starting | Running | Failed | Shadow)
+                    | (Failed, Starting | Running | Stopped | Shadow)
+                    | (Shadow, Running | Stopped | Failed)
+            )
+    }
+}
+
+/// One change of an instance's status, kept in `Instance::status_history`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StatusTransition {
+    pub from: InstanceStatus,
+    pub to: InstanceStatus,
+    pub at: DateTime<Utc>,
+}
+
+/// Status changes kept per instance; older ones are dropped
+pub const STATUS_HISTORY_LIMIT: usize = 64;
+
+impl std::str::FromStr for InstanceStatus {
+    type Err = CriuCliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "starting" => Ok(InstanceStatus::Starting),
+            "running" => Ok(InstanceStatus::Running),
+            "paused" => Ok(InstanceStatus::Paused),
+            "stopped" => Ok(InstanceStatus::Stopped),
+            "failed" => Ok(InstanceStatus::Failed),
+            "shadow" => Ok(InstanceStatus::Shadow),
+            other => Err(CriuCliError::ParseError(format!(
+                "Invalid status: {} (expected running, paused, shadow, stopped, starting or failed)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Order of `list` output; ties fall back to creation time, then short ID
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum InstanceSort {
+    #[default]
+    Created,
+    Id,
+    Status,
+    Program,
+}
+
+impl InstanceSort {
+    /// Compare two instances, using `status` as their effective status
+    pub fn compare(&self, a: (&Instance, &str), b: (&Instance, &str)) -> std::cmp::Ordering {
+        let (a, a_status) = a;
+        let (b, b_status) = b;
+        let primary = match self {
+            InstanceSort::Created => std::cmp::Ordering::Equal,
+            InstanceSort::Id => a.id.cmp(&b.id),
+            InstanceSort::Status => a_status.cmp(b_status),
+            InstanceSort::Program => a.program.cmp(&b.program),
+        };
+        primary
+            .then_with(|| a.created_at.cmp(&b.created_at))
+            .then_with(|| a.short_id().cmp(&b.short_id()))
+    }
+}
+
+impl std::str::FromStr for InstanceSort {
+    type Err = CriuCliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "created" => Ok(InstanceSort::Created),
+            "id" => Ok(InstanceSort::Id),
+            "status" => Ok(InstanceSort::Status),
+            "program" => Ok(InstanceSort::Program),
+            other => Err(CriuCliError::ParseError(format!(
+                "Invalid sort key: {} (expected created, id, status or program)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Criteria for narrowing `list` output; unset fields match everything
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InstanceFilter {
+    pub status: Option<InstanceStatus>,
+    pub node: Option<String>,    // Prefix of a shadow's source node ID
+    pub program: Option<String>, // Substring of the program path
+    pub labels: LabelSelector, // Labels the instance must all carry
+}
+
+impl InstanceFilter {
+    pub fn is_empty(&self) -> bool {
+        self.status.is_none() && self.node.is_none() && self.program.is_none() && self.labels.is_empty()
+    }
+
+    /// Check an instance against the filter, using `status` as its effective status
+    pub fn matches(&self, instance: &Instance, status: &str) -> bool {
+        if let Some(ref wanted) = self.status {
+            if !status.eq_ignore_ascii_case(&wanted.to_string()) {
+                return false;
+            }
+        }
+        if let Some(ref node) = self.node {
+            match instance.source_node_id {
+                Some(source) if source.to_string().starts_with(node.as_str()) => {}
+                _ => return false,
+            }
+        }
+        if let Some(ref program) = self.program {
+            if !instance.program.contains(program.as_str()) {
+                return false;
+            }
+        }
+        matches_labels(instance, &self.labels)
+    }
+}
+
+/// `key=value` labels an instance must all carry (`--label` selectors)
+pub type LabelSelector = Vec<(String, String)>;
+
+/// Parse a `key=value` label
+pub fn parse_label(label: &str) -> Result<(String, String)> {
+    match label.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(CriuCliError::ParseError(format!("Invalid label '{}', expected key=value", label))),
+    }
+}
+
+/// Whether the instance carries every one of `labels`
+pub fn matches_labels(instance: &Instance, labels: &[(String, String)]) -> bool {
+    labels.iter().all(|(key, value)| instance.labels.get(key) == Some(value))
+}
+
+/// Labels as `key=value` joined by commas
+pub fn format_labels(labels: &BTreeMap<String, String>) -> String {
+    labels.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>().join(",")
+}
+
+/// Output stream of an instance, for filtering logs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    /// Prefix of this stream's lines in the output history
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            LogStream::Stdout => "[STDOUT]",
+            LogStream::Stderr => "[STDERR]",
+        }
+    }
+
+    /// Log file holding this stream in an instance's `output/` directory
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout.log",
+            LogStream::Stderr => "stderr.log",
+        }
+    }
+}
+
+impl std::str::FromStr for LogStream {
+    type Err = CriuCliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "stdout" => Ok(LogStream::Stdout),
+            "stderr" => Ok(LogStream::Stderr),
+            other => Err(CriuCliError::ParseError(format!(
+                "Invalid stream: {} (expected stdout or stderr)",
+                other
+            ))),
+        }
+    }
+}
+
+/// How captured output lines are timestamped
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum OutputTimestamps {
+    #[default]
+    Off,
+    /// Wall-clock time, e.g. `2024-05-01T12:00:00.123Z`
+    Rfc3339,
+    /// Time since the process started, e.g. `+12.345s`
+    Relative,
+}
+
+impl OutputTimestamps {
+    /// Timestamp to put after the stream prefix of a line captured now, if any
+    pub fn stamp(&self, started_at: DateTime<Utc>) -> Option<String> {
+        let now = Utc::now();
+        match self {
+            OutputTimestamps::Off => None,
+            OutputTimestamps::Rfc3339 => Some(now.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)),
+            OutputTimestamps::Relative => {
+                let elapsed = (now - started_at).num_milliseconds().max(0);
+                Some(format!("+{}.{:03}s", elapsed / 1000, elapsed % 1000))
+            }
+        }
+    }
+
+    /// Time a stamp written by `stamp` stands for, in either format
+    pub fn parse_stamp(stamp: &str, started_at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if let Some(relative) = stamp.strip_prefix('+').and_then(|s| s.strip_suffix('s')) {
+            let seconds: f64 = relative.parse().ok()?;
+            return Some(started_at + chrono::Duration::milliseconds((seconds * 1000.0).round() as i64));
+        }
+        DateTime::parse_from_rfc3339(stamp).ok().map(|time| time.with_timezone(&Utc))
+    }
+}
+
+/// Parse a duration such as `30s`, `5m`, `1h` or `1h30m` (units: s, m, h, d)
+pub fn parse_duration(text: &str) -> Result<chrono::Duration> {
+    let invalid = || CriuCliError::ParseError(format!("Invalid duration '{}' (e.g. 30s, 5m, 1h30m)", text));
+    let mut seconds: i64 = 0;
+    let mut digits = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let amount: i64 = digits.parse().map_err(|_| invalid())?;
+        digits.clear();
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return Err(invalid()),
+        };
+        seconds = amount.checked_mul(unit).and_then(|s| seconds.checked_add(s)).ok_or_else(invalid)?;
+    }
+    if !digits.is_empty() || text.is_empty() {
+        return Err(invalid());
+    }
+    chrono::Duration::try_seconds(seconds).ok_or_else(invalid)
+}
+
+/// A `logs --since/--until` bound: a duration before now or an RFC 3339 time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeBound {
+    Ago(chrono::Duration),
+    At(DateTime<Utc>),
+}
+
+impl TimeBound {
+    pub fn resolve(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            TimeBound::Ago(duration) => now - *duration,
+            TimeBound::At(time) => *time,
+        }
+    }
+}
+
+impl std::str::FromStr for TimeBound {
+    type Err = CriuCliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+            return Ok(TimeBound::At(time.with_timezone(&Utc)));
+        }
+        parse_duration(s).map(TimeBound::Ago).map_err(|_| {
+            CriuCliError::ParseError(format!("Invalid time '{}' (expected a duration like 5m or an RFC 3339 time)", s))
+        })
+    }
+}
+
+/// Time window of `logs --since/--until`; only timestamped lines can fall inside one
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LogWindow {
+    pub since: Option<TimeBound>,
+    pub until: Option<TimeBound>,
+}
+
+impl LogWindow {
+    pub fn is_empty(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+
+    /// Whether a history or log line, with or without its stream prefix, was captured inside
+    /// the window. `started_at` anchors relative timestamps.
+    pub fn matches_line(&self, line: &str, started_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        let unprefixed = [LogStream::Stdout, LogStream::Stderr]
+            .iter()
+            .find_map(|stream| line.strip_prefix(stream.prefix()))
+            .map(str::trim_start)
+            .unwrap_or(line);
+        let Some(at) = unprefixed
+            .split_whitespace()
+            .next()
+            .and_then(|stamp| OutputTimestamps::parse_stamp(stamp, started_at))
+        else {
+            return false;
+        };
+        let after_since = match self.since {
+            Some(since) => at >= since.resolve(now),
+            None => true,
+        };
+        let before_until = match self.until {
+            Some(until) => at <= until.resolve(now),
+            None => true,
+        };
+        after_since && before_until
+    }
+}
+
+impl std::str::FromStr for OutputTimestamps {
+    type Err = CriuCliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "off" => Ok(OutputTimestamps::Off),
+            "rfc3339" => Ok(OutputTimestamps::Rfc3339),
+            "relative" => Ok(OutputTimestamps::Relative),
+            other => Err(CriuCliError::ParseError(format!(
+                "Invalid timestamp format: {} (expected off, rfc3339 or relative)",
+                other
+            ))),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CheckpointInfo {
     pub name: String,
@@ -65,12 +606,12 @@ pub struct CheckpointInfo {
 #[derive(Debug)]
 pub struct ProcessInfo {
     pub pid: u32,
-    pub child: tokio::process::Child,
+    pub child: Option<tokio::process::Child>, // None for restored/migrated processes we did not spawn
     pub output_history: Arc<Mutex<Vec<String>>>,
     pub stdout_handle: Option<tokio::task::JoinHandle<()>>,
     pub stderr_handle: Option<tokio::task::JoinHandle<()>>,
     pub output_sender: Option<tokio::sync::broadcast::Sender<String>>,
-    pub stdin_sender: Option<tokio::sync::mpsc::UnboundedSender<String>>,
+    pub stdin_sender: Option<tokio::sync::mpsc::Sender<String>>, // Bounded by STDIN_BUFFER_CAPACITY
 }
 
 impl Instance {
@@ -79,7 +620,11 @@ impl Instance {
     }
 
     pub fn new_with_mode(program: String, args: Vec<String>, working_dir: PathBuf, start_mode: StartMode) -> Self {
-        let id = Uuid::new_v4();
+        Self::new_with_id(Uuid::new_v4(), program, args, working_dir, start_mode)
+    }
+
+    /// Create an instance with a caller-chosen ID (start `--id`)
+    pub fn new_with_id(id: Uuid, program: String, args: Vec<String>, working_dir: PathBuf, start_mode: StartMode) -> Self {
         let instance_dir = Self::create_instance_directory(&id);
         let metadata_file = instance_dir.join("metadata.json");
 
@@ -98,7 +643,62 @@ impl Instance {
             source_node_id: None,
             shadow_data_version: 0,
             last_sync_time: None,
+            sync_enabled: false,
+            checkpoint_hooks: CheckpointHooks::default(),
+            output_file: None,
+            sync_base: None,
+            affinity: Affinity::default(),
+            output_timestamps: OutputTimestamps::Off,
+            append_only: false,
+            labels: BTreeMap::new(),
+            child_pids: Vec::new(),
+            last_error: None,
+            failed_operation: None,
+            env: Vec::new(),
+            status_history: Vec::new(),
+            snapshot_policy: None,
+            last_snapshot_at: None,
+        }
+    }
+
+    /// Move the instance to `status`, recording the change; fails on moves the status
+    /// model does not allow (e.g. Stopped -> Paused) and leaves the status as it was
+    pub fn set_status(&mut self, status: InstanceStatus) -> Result<()> {
+        if !self.status.can_transition_to(&status) {
+            return Err(CriuCliError::InvalidTransition(self.short_id(), self.status.clone(), status));
+        }
+        self.record_status(status);
+        Ok(())
+    }
+
+    fn record_status(&mut self, status: InstanceStatus) {
+        if self.status == status {
+            return;
+        }
+        self.status_history.push(StatusTransition { from: self.status.clone(), to: status.clone(), at: Utc::now() });
+        if self.status_history.len() > STATUS_HISTORY_LIMIT {
+            self.status_history.remove(0);
         }
+        self.status = status;
+    }
+
+    /// Mark the instance `Failed`, remembering the operation and error for `retry`
+    pub fn mark_failed(&mut self, operation: FailedOperation, error: &CriuCliError) {
+        // Any status can fail
+        self.record_status(InstanceStatus::Failed);
+        self.last_error = Some(error.to_string());
+        self.failed_operation = Some(operation);
+    }
+
+    /// Re-read the primary process's descendants
+    pub fn refresh_child_pids(&mut self) {
+        self.child_pids = self.pid.map(crate::criu_compat::descendants).unwrap_or_default();
+    }
+
+    /// Forget a previous failure once an operation succeeds
+    pub fn clear_failure(&mut self) {
+        self.last_error = None;
+        self.failed_operation = None;
     }
 
     pub fn add_checkpoint(&mut self, name: String, checkpoint_dir: PathBuf) {
@@ -163,8 +763,10 @@ impl Instance {
         let metadata_json = serde_json::to_string_pretty(self)
             .map_err(|e| CriuCliError::ParseError(format!("Failed to serialize metadata: {}", e)))?;
 
-        std::fs::write(&self.metadata_file, metadata_json)
-            .map_err(|e| CriuCliError::IoError(e))?;
+        // Name the file, so a read-only data directory is obvious from the message
+        std::fs::write(&self.metadata_file, metadata_json).map_err(|e| {
+            CriuCliError::IoError(std::io::Error::new(e.kind(), format!("{}: {}", self.metadata_file.display(), e)))
+        })?;
 
         Ok(())
     }
@@ -172,7 +774,7 @@ impl Instance {
     /// Load instance metadata from file
     pub fn load_metadata(metadata_file: &PathBuf) -> Result<Self> {
         let metadata_json = std::fs::read_to_string(metadata_file)
-            .map_err(|e| CriuCliError::IoError(e))?;
+            .map_err(CriuCliError::IoError)?;
 
         let instance: Instance = serde_json::from_str(&metadata_json)
             .map_err(|e| CriuCliError::ParseError(format!("Failed to deserialize metadata: {}", e)))?;
@@ -184,6 +786,7 @@ impl Instance {
     pub fn create_shadow(source_instance: &Instance, source_node_id: Uuid) -> Self {
         let mut shadow = source_instance.clone();
         shadow.status = InstanceStatus::Shadow;
+        shadow.status_history.clear(); // A new instance on this node, not a transition
         shadow.pid = None; // Shadow instances don't have actual processes
         shadow.source_node_id = Some(source_node_id);
         shadow.shadow_data_version = 0;
@@ -208,17 +811,19 @@ impl Instance {
     }
 
     /// Convert shadow instance to running instance (for migration)
-    pub fn promote_to_running(&mut self, new_pid: u32) {
-        self.status = InstanceStatus::Running;
+    pub fn promote_to_running(&mut self, new_pid: u32) -> Result<()> {
+        self.set_status(InstanceStatus::Running)?;
         self.pid = Some(new_pid);
         self.source_node_id = None; // No longer a shadow
+        Ok(())
     }
 
     /// Convert running instance to shadow instance (for migration)
-    pub fn demote_to_shadow(&mut self, source_node_id: Uuid) {
-        self.status = InstanceStatus::Shadow;
+    pub fn demote_to_shadow(&mut self, source_node_id: Uuid) -> Result<()> {
+        self.set_status(InstanceStatus::Shadow)?;
         self.pid = None;
         self.source_node_id = Some(source_node_id);
+        Ok(())
     }
 }
 
@@ -233,6 +838,12 @@ pub enum CriuCliError {
     #[error("Instance is not paused: {0}")]
     InstanceNotPaused(String),
 
+    #[error("Instance {0} is {1}, not {2}")]
+    UnexpectedStatus(String, InstanceStatus, String),
+
+    #[error("Instance {0} cannot go from {1} to {2}")]
+    InvalidTransition(String, InstanceStatus, InstanceStatus),
+
     #[error("Checkpoint not found: {0}")]
     CheckpointNotFound(String),
 
@@ -247,6 +858,145 @@ pub enum CriuCliError {
 
     #[error("Parse error: {0}")]
     ParseError(String),
+
+    #[error("Incompatible checkpoint: {0}")]
+    IncompatibleCheckpoint(String),
+
+    #[error("Corrupt checkpoint: {0}")]
+    CorruptCheckpoint(String),
+
+    #[error("Data directory {0} is not writable: {1}")]
+    DataDirNotWritable(String, std::io::Error),
+
+    #[error("Instance {0} exited with code {1}")]
+    ProgramExited(String, i32),
+}
+
+/// Failures of migration operations, so callers can match on the kind of failure
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    #[error("Instance not found: {0}")]
+    InstanceNotFound(String),
+
+    #[error("Instance is not running: {0}")]
+    InstanceNotRunning(String),
+
+    #[error("Migration not found: {0}")]
+    MigrationNotFound(Uuid),
+
+    #[error("Target node is offline or unreachable: {0}")]
+    TargetOffline(Uuid),
+
+    #[error("Checkpoint failed: {0}")]
+    CheckpointFailed(String),
+
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
+    #[error("Migration failed: {0}")]
+    Failed(String),
+
+    #[error("Migration rejected by the target: {0}")]
+    Rejected(String),
+
+    #[error("Target node is not eligible: {0}")]
+    TargetIneligible(String),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+pub type MigrationResult<T> = std::result::Result<T, MigrationError>;
+
+/// Failures of shadow instance operations
+#[derive(Debug, thiserror::Error)]
+pub enum ShadowError {
+    #[error("Instance not found: {0}")]
+    InstanceNotFound(Uuid),
+
+    #[error("No shadow instance: {0}")]
+    NoShadow(Uuid),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
 }
 
+pub type ShadowResult<T> = std::result::Result<T, ShadowError>;
+
 pub type Result<T> = std::result::Result<T, CriuCliError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_parse_in_any_unit_combination() {
+        assert_eq!(parse_duration("5m").unwrap(), chrono::Duration::minutes(5));
+        assert_eq!(parse_duration("1h30m").unwrap(), chrono::Duration::minutes(90));
+        assert_eq!(parse_duration("2d").unwrap(), chrono::Duration::days(2));
+        for invalid in ["", "5", "m", "5x", "-5m"] {
+            assert!(parse_duration(invalid).is_err(), "accepted {:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn the_log_window_keeps_only_lines_stamped_inside_it() {
+        let now: DateTime<Utc> = "2026-01-01T12:00:00Z".parse().unwrap();
+        let started_at = now - chrono::Duration::hours(1);
+        let at = |minutes_ago: i64| (now - chrono::Duration::minutes(minutes_ago)).to_rfc3339();
+        let lines = [
+            format!("[STDOUT] {} ten minutes ago", at(10)),
+            format!("[STDERR] {} four minutes ago", at(4)),
+            // Relative stamps count from the start, so this is two minutes ago
+            "[STDOUT] +3480.000s two minutes ago".to_string(),
+            format!("{} thirty seconds ago", (now - chrono::Duration::seconds(30)).to_rfc3339()),
+            "[STDOUT] not timestamped".to_string(),
+        ];
+        let shown = |window: LogWindow| -> Vec<usize> {
+            (0..lines.len()).filter(|&i| window.matches_line(&lines[i], started_at, now)).collect()
+        };
+
+        let since: TimeBound = "5m".parse().unwrap();
+        assert_eq!(shown(LogWindow { since: Some(since), until: None }), [1, 2, 3]);
+        let until: TimeBound = (now - chrono::Duration::minutes(1)).to_rfc3339().parse().unwrap();
+        assert_eq!(shown(LogWindow { since: Some(since), until: Some(until) }), [1, 2]);
+        assert_eq!(shown(LogWindow { since: None, until: Some("3m".parse().unwrap()) }), [0, 1]);
+    }
+
+    #[test]
+    fn status_changes_are_validated_and_recorded() {
+        crate::test_support::use_scratch_dir();
+        let mut instance = Instance::new("sleep".to_string(), vec!["30".to_string()], PathBuf::from("/"));
+        for status in [InstanceStatus::Running, InstanceStatus::Paused, InstanceStatus::Running, InstanceStatus::Stopped] {
+            instance.set_status(status).unwrap();
+        }
+        let moves: Vec<(InstanceStatus, InstanceStatus)> =
+            instance.status_history.iter().map(|transition| (transition.from.clone(), transition.to.clone())).collect();
+        assert_eq!(
+            moves,
+            vec![
+                (InstanceStatus::Starting, InstanceStatus::Running),
+                (InstanceStatus::Running, InstanceStatus::Paused),
+                (InstanceStatus::Paused, InstanceStatus::Running),
+                (InstanceStatus::Running, InstanceStatus::Stopped),
+            ]
+        );
+        assert!(instance.status_history.windows(2).all(|pair| pair[0].at <= pair[1].at));
+
+        // Rejected moves leave the instance as it was
+        assert!(matches!(instance.set_status(InstanceStatus::Paused), Err(CriuCliError::InvalidTransition(..))));
+        assert_eq!(instance.status, InstanceStatus::Stopped);
+        assert_eq!(instance.status_history.len(), 4);
+        instance.demote_to_shadow(Uuid::new_v4()).unwrap();
+        assert!(instance.set_status(InstanceStatus::Paused).is_err());
+        assert_eq!(instance.status, InstanceStatus::Shadow);
+
+        for _ in 0..STATUS_HISTORY_LIMIT {
+            instance.promote_to_running(1).unwrap();
+            instance.set_status(InstanceStatus::Stopped).unwrap();
+            instance.set_status(InstanceStatus::Shadow).unwrap();
+        }
+        assert_eq!(instance.status_history.len(), STATUS_HISTORY_LIMIT);
+        assert_eq!(instance.status_history.last().unwrap().to, InstanceStatus::Shadow);
+    }
+}
@@ -1,12 +1,133 @@
 use crossterm::{
     cursor::{MoveTo, Show, Hide},
-    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    event::{self, Event, EventStream, KeyCode, KeyEvent, KeyModifiers},
     execute, queue,
     style::{Color, Print, ResetColor, SetForegroundColor},
     terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use futures::StreamExt;
 use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+
+/// Whether an attach session currently owns the terminal (raw mode, alternate screen)
+static TERMINAL_TAKEN: AtomicBool = AtomicBool::new(false);
+static PANIC_HOOK: Once = Once::new();
+
+/// Leave raw mode and the alternate screen, ignoring errors; safe to call more than once
+pub fn restore_terminal() {
+    if TERMINAL_TAKEN.swap(false, Ordering::SeqCst) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+    }
+}
+
+/// Restore the terminal before the panic message is printed so it stays readable
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            previous(info);
+        }));
+    });
+}
+
+/// Output lines kept for scrollback; the oldest are dropped in chunks beyond this
+const MAX_SCROLLBACK_LINES: usize = 10_000;
+
+/// Scrollback position and search over the retained output lines
+#[derive(Debug, Default)]
+struct ScrollState {
+    top: Option<usize>,          // First visible line when scrolled back; None follows new output
+    query: Option<String>,       // Active search, highlighted in the output
+    editing_query: bool,         // Keys edit the query instead of the input line
+    current_match: Option<usize>, // Line of the match last jumped to
+}
+
+impl ScrollState {
+    fn visible_start(&self, total: usize, page: usize) -> usize {
+        let last_page = total.saturating_sub(page);
+        self.top.map_or(last_page, |top| top.min(last_page))
+    }
+
+    fn page_up(&mut self, total: usize, page: usize) {
+        self.top = Some(self.visible_start(total, page).saturating_sub(page));
+    }
+
+    fn page_down(&mut self, total: usize, page: usize) {
+        let start = self.visible_start(total, page) + page;
+        self.top = if start >= total.saturating_sub(page) { None } else { Some(start) };
+    }
+
+    fn home(&mut self) {
+        self.top = Some(0);
+    }
+
+    fn end(&mut self) {
+        self.top = None;
+    }
+
+    /// Keep the view on the same lines after `count` lines were dropped from the front
+    fn lines_dropped(&mut self, count: usize) {
+        self.top = self.top.map(|top| top.saturating_sub(count));
+        self.current_match = self.current_match.and_then(|line| line.checked_sub(count));
+    }
+
+    fn start_search(&mut self) {
+        self.query = Some(String::new());
+        self.editing_query = true;
+    }
+
+    fn cancel_search(&mut self) {
+        self.query = None;
+        self.editing_query = false;
+        self.current_match = None;
+    }
+
+    fn active_query(&self) -> Option<&str> {
+        self.query.as_deref().filter(|query| !query.is_empty())
+    }
+
+    /// Jump to the nearest match before the current one (or the newest), wrapping around
+    fn search_older(&mut self, lines: &[String], page: usize) -> bool {
+        let before = self.current_match.unwrap_or(lines.len()).min(lines.len());
+        let found = match self.active_query() {
+            Some(query) => lines[..before]
+                .iter()
+                .rposition(|line| line.contains(query))
+                .or_else(|| lines.iter().rposition(|line| line.contains(query))),
+            None => None,
+        };
+        self.jump_to(found, lines.len(), page)
+    }
 
+    /// Jump to the nearest match after the current one, wrapping around
+    fn search_newer(&mut self, lines: &[String], page: usize) -> bool {
+        let after = self.current_match.map_or(0, |line| line + 1).min(lines.len());
+        let found = match self.active_query() {
+            Some(query) => lines[after..]
+                .iter()
+                .position(|line| line.contains(query))
+                .map(|i| after + i)
+                .or_else(|| lines.iter().position(|line| line.contains(query))),
+            None => None,
+        };
+        self.jump_to(found, lines.len(), page)
+    }
+
+    fn jump_to(&mut self, line: Option<usize>, total: usize, page: usize) -> bool {
+        match line {
+            Some(line) => {
+                self.current_match = Some(line);
+                let top = line.saturating_sub(page / 2);
+                self.top = if top >= total.saturating_sub(page) { None } else { Some(top) };
+                true
+            }
+            None => false,
+        }
+    }
+}
 
 pub struct AttachUI {
     terminal_height: u16,
@@ -14,7 +135,16 @@ pub struct AttachUI {
     output_lines: Vec<String>,
     input_buffer: String,
     cursor_pos: usize,
-    max_output_lines: usize,
+    scroll: ScrollState,
+    search_failed: bool,
+}
+
+/// Errors and panics inside the attach loop unwind through here, so the terminal is
+/// never left in raw mode on the alternate screen
+impl Drop for AttachUI {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
 }
 
 impl AttachUI {
@@ -27,12 +157,16 @@ impl AttachUI {
             output_lines: Vec::new(),
             input_buffer: String::new(),
             cursor_pos: 0,
-            max_output_lines: (height as usize).saturating_sub(3), // Reserve 3 lines for input area
+            scroll: ScrollState::default(),
+            search_failed: false,
         })
     }
 
     pub fn enter_attach_mode(&mut self, instance_id: &str) -> io::Result<()> {
+        install_panic_hook();
+
         // Enter alternate screen and hide cursor
+        TERMINAL_TAKEN.store(true, Ordering::SeqCst);
         execute!(io::stdout(), EnterAlternateScreen, Hide)?;
         terminal::enable_raw_mode()?;
 
@@ -44,9 +178,11 @@ impl AttachUI {
     }
 
     pub fn exit_attach_mode(&mut self) -> io::Result<()> {
-        // Restore terminal
-        terminal::disable_raw_mode()?;
-        execute!(io::stdout(), Show, LeaveAlternateScreen)?;
+        // Restore terminal, unless a panic hook or an earlier exit already did
+        if TERMINAL_TAKEN.swap(false, Ordering::SeqCst) {
+            terminal::disable_raw_mode()?;
+            execute!(io::stdout(), Show, LeaveAlternateScreen)?;
+        }
 
         Ok(())
     }
@@ -54,9 +190,11 @@ impl AttachUI {
     pub fn add_output_line(&mut self, line: String) -> io::Result<()> {
         self.output_lines.push(line);
 
-        // Keep only the last N lines to prevent memory issues
-        if self.output_lines.len() > self.max_output_lines * 2 {
-            self.output_lines.drain(0..self.max_output_lines);
+        // Bound the scrollback, dropping old lines in chunks
+        if self.output_lines.len() > MAX_SCROLLBACK_LINES + MAX_SCROLLBACK_LINES / 10 {
+            let excess = self.output_lines.len() - MAX_SCROLLBACK_LINES;
+            self.output_lines.drain(0..excess);
+            self.scroll.lines_dropped(excess);
         }
 
         self.redraw_output()?;
@@ -66,6 +204,15 @@ impl AttachUI {
     pub fn handle_input(&mut self) -> io::Result<Option<String>> {
         if event::poll(std::time::Duration::from_millis(50))? {
             if let Event::Key(key_event) = event::read()? {
+                // Ctrl+C always leaves attach, whatever mode the view is in; raw mode
+                // delivers it as a key rather than SIGINT, so the program keeps running
+                if key_event.code == KeyCode::Char('c') && key_event.modifiers.contains(KeyModifiers::CONTROL) {
+                    self.scroll.cancel_search();
+                    return Ok(Some("detach".to_string()));
+                }
+                if self.handle_view_key(&key_event)? {
+                    return Ok(None);
+                }
                 match key_event {
                     KeyEvent {
                         code: KeyCode::Enter,
@@ -91,39 +238,26 @@ impl AttachUI {
                         code: KeyCode::Backspace,
                         modifiers: KeyModifiers::NONE,
                         ..
-                    } => {
-                        if self.cursor_pos > 0 {
-                            self.cursor_pos -= 1;
-                            self.input_buffer.remove(self.cursor_pos);
-                            self.draw_input_area()?;
-                        }
+                    } if self.cursor_pos > 0 => {
+                        self.cursor_pos -= 1;
+                        self.input_buffer.remove(self.cursor_pos);
+                        self.draw_input_area()?;
                     }
                     KeyEvent {
                         code: KeyCode::Left,
                         modifiers: KeyModifiers::NONE,
                         ..
-                    } => {
-                        if self.cursor_pos > 0 {
-                            self.cursor_pos -= 1;
-                            self.draw_input_area()?;
-                        }
+                    } if self.cursor_pos > 0 => {
+                        self.cursor_pos -= 1;
+                        self.draw_input_area()?;
                     }
                     KeyEvent {
                         code: KeyCode::Right,
                         modifiers: KeyModifiers::NONE,
                         ..
-                    } => {
-                        if self.cursor_pos < self.input_buffer.len() {
-                            self.cursor_pos += 1;
-                            self.draw_input_area()?;
-                        }
-                    }
-                    KeyEvent {
-                        code: KeyCode::Char('c'),
-                        modifiers: KeyModifiers::CONTROL,
-                        ..
-                    } => {
-                        return Ok(Some("detach".to_string()));
+                    } if self.cursor_pos < self.input_buffer.len() => {
+                        self.cursor_pos += 1;
+                        self.draw_input_area()?;
                     }
                     _ => {}
                 }
@@ -132,6 +266,66 @@ impl AttachUI {
         Ok(None)
     }
 
+    /// Scrollback and search keys; returns whether the key was consumed
+    fn handle_view_key(&mut self, key_event: &KeyEvent) -> io::Result<bool> {
+        let total = self.output_lines.len();
+        let page = self.output_area_height();
+
+        if self.scroll.editing_query {
+            match key_event.code {
+                KeyCode::Char(c) => {
+                    if let Some(query) = self.scroll.query.as_mut() {
+                        query.push(c);
+                    }
+                }
+                KeyCode::Backspace => {
+                    if let Some(query) = self.scroll.query.as_mut() {
+                        query.pop();
+                    }
+                }
+                KeyCode::Enter => {
+                    self.scroll.editing_query = false;
+                    self.scroll.current_match = None;
+                    if self.scroll.active_query().is_some() {
+                        self.search_failed = !self.scroll.search_older(&self.output_lines, page);
+                    } else {
+                        self.scroll.cancel_search();
+                    }
+                }
+                KeyCode::Esc => self.scroll.cancel_search(),
+                _ => return Ok(true),
+            }
+        } else {
+            match key_event.code {
+                KeyCode::PageUp => self.scroll.page_up(total, page),
+                KeyCode::PageDown => self.scroll.page_down(total, page),
+                KeyCode::Home => self.scroll.home(),
+                KeyCode::End => self.scroll.end(),
+                // `/` only starts a search on an empty input line so it can still be typed
+                KeyCode::Char('/') if self.input_buffer.is_empty() && key_event.modifiers == KeyModifiers::NONE => {
+                    self.search_failed = false;
+                    self.scroll.start_search();
+                }
+                KeyCode::Up if self.scroll.query.is_some() => {
+                    self.search_failed = !self.scroll.search_older(&self.output_lines, page);
+                }
+                KeyCode::Down if self.scroll.query.is_some() => {
+                    self.search_failed = !self.scroll.search_newer(&self.output_lines, page);
+                }
+                KeyCode::Esc if self.scroll.query.is_some() => self.scroll.cancel_search(),
+                _ => return Ok(false),
+            }
+        }
+
+        self.redraw_output()?;
+        self.draw_input_area()?;
+        Ok(true)
+    }
+
+    fn output_area_height(&self) -> usize {
+        self.terminal_height.saturating_sub(3).saturating_sub(3) as usize
+    }
+
     fn clear_screen(&mut self) -> io::Result<()> {
         execute!(io::stdout(), Clear(ClearType::All))?;
         Ok(())
@@ -151,13 +345,14 @@ impl AttachUI {
         queue!(io::stdout(), Print("─".repeat(remaining)))?;
         queue!(io::stdout(), Print("┐"))?;
 
+        let help = "│ Type 'detach' or Ctrl+C to exit attach mode, PgUp/PgDn/Home/End to scroll, / to search";
         queue!(
             io::stdout(),
             MoveTo(0, 1),
-            Print("│ Type 'detach' or Ctrl+C to exit attach mode"),
+            Print(help),
         )?;
 
-        let spaces = (self.terminal_width as usize).saturating_sub(45);
+        let spaces = (self.terminal_width as usize).saturating_sub(help.chars().count() + 1);
         queue!(io::stdout(), Print(" ".repeat(spaces)))?;
         queue!(io::stdout(), Print("│"))?;
 
@@ -191,12 +386,9 @@ impl AttachUI {
             )?;
         }
 
-        // Display the last N lines of output
-        let start_idx = if self.output_lines.len() > available_lines {
-            self.output_lines.len() - available_lines
-        } else {
-            0
-        };
+        // Display the last N lines of output, or the scrolled-back page
+        let start_idx = self.scroll.visible_start(self.output_lines.len(), available_lines);
+        let query = self.scroll.active_query().filter(|_| !self.scroll.editing_query);
 
         for (i, line) in self.output_lines[start_idx..].iter().enumerate() {
             let y = output_start_line + i as u16;
@@ -204,6 +396,9 @@ impl AttachUI {
                 break;
             }
 
+            if self.scroll.current_match == Some(start_idx + i) {
+                queue!(io::stdout(), MoveTo(1, y), SetForegroundColor(Color::Yellow), Print("▶"), ResetColor)?;
+            }
             queue!(io::stdout(), MoveTo(2, y))?;
 
             // Truncate line if it's too long
@@ -214,13 +409,48 @@ impl AttachUI {
                 line.clone()
             };
 
-            queue!(io::stdout(), Print(display_line))?;
+            match query {
+                Some(query) => Self::print_highlighted(&display_line, query)?,
+                None => queue!(io::stdout(), Print(display_line))?,
+            }
         }
 
         io::stdout().flush()?;
         Ok(())
     }
 
+    /// Print a line with every occurrence of `query` highlighted
+    fn print_highlighted(line: &str, query: &str) -> io::Result<()> {
+        let mut rest = line;
+        while let Some(pos) = rest.find(query) {
+            queue!(
+                io::stdout(),
+                Print(&rest[..pos]),
+                SetForegroundColor(Color::Yellow),
+                Print(&rest[pos..pos + query.len()]),
+                ResetColor,
+            )?;
+            rest = &rest[pos + query.len()..];
+        }
+        queue!(io::stdout(), Print(rest))?;
+        Ok(())
+    }
+
+    /// Scroll position and search state shown in the separator above the input line
+    fn view_status(&self) -> Option<String> {
+        let total = self.output_lines.len();
+        let mut parts = Vec::new();
+        if self.scroll.top.is_some() {
+            let start = self.scroll.visible_start(total, self.output_area_height());
+            parts.push(format!("line {}/{}, End to follow", start + 1, total));
+        }
+        if let (Some(query), false) = (self.scroll.active_query(), self.scroll.editing_query) {
+            let state = if self.search_failed { "no match" } else { "Up/Down for more, Esc to clear" };
+            parts.push(format!("search '{}': {}", query, state));
+        }
+        if parts.is_empty() { None } else { Some(format!(" {} ", parts.join(" | "))) }
+    }
+
     fn draw_input_area(&mut self) -> io::Result<()> {
         let input_line = self.terminal_height - 2;
         let bottom_line = self.terminal_height - 1;
@@ -233,10 +463,17 @@ impl AttachUI {
             Print("├"),
             Print("─".repeat((self.terminal_width as usize).saturating_sub(2))),
             Print("┤"),
-            ResetColor,
         )?;
+        if let Some(status) = self.view_status() {
+            queue!(io::stdout(), MoveTo(2, input_line), Print(status))?;
+        }
+        queue!(io::stdout(), ResetColor)?;
 
-        // Draw input line
+        // Draw input line, or the search query while it is being typed
+        let (line, cursor_pos) = match (&self.scroll.query, self.scroll.editing_query) {
+            (Some(query), true) => (format!("/{}", query), query.chars().count() + 1),
+            _ => (self.input_buffer.clone(), self.cursor_pos),
+        };
         queue!(
             io::stdout(),
             MoveTo(0, bottom_line),
@@ -244,7 +481,7 @@ impl AttachUI {
             Print("│ "),
             ResetColor,
             Clear(ClearType::UntilNewLine),
-            Print(&self.input_buffer),
+            Print(line),
         )?;
 
         // Draw right border
@@ -257,10 +494,367 @@ impl AttachUI {
         )?;
 
         // Position cursor
-        let cursor_x = 2 + self.cursor_pos as u16;
+        let cursor_x = 2 + cursor_pos as u16;
         queue!(io::stdout(), MoveTo(cursor_x, bottom_line), Show)?;
 
         io::stdout().flush()?;
         Ok(())
     }
 }
+
+/// Take over the terminal for a full-screen watch view
+fn enter_watch_screen() -> io::Result<()> {
+    install_panic_hook();
+
+    TERMINAL_TAKEN.store(true, Ordering::SeqCst);
+    execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+    terminal::enable_raw_mode()?;
+    Ok(())
+}
+
+/// Wait for the next terminal event of a watch view without blocking the runtime; true on q, Esc
+/// or Ctrl+C, or when input ends. Resizes update `terminal_width`.
+async fn next_watch_event(events: &mut EventStream, terminal_width: &mut u16) -> io::Result<bool> {
+    match events.next().await {
+        Some(Ok(Event::Key(key_event))) => Ok(match key_event.code {
+            KeyCode::Char('q') | KeyCode::Esc => true,
+            KeyCode::Char('c') => key_event.modifiers.contains(KeyModifiers::CONTROL),
+            _ => false,
+        }),
+        Some(Ok(Event::Resize(width, _))) => {
+            *terminal_width = width;
+            Ok(false)
+        }
+        Some(Ok(_)) => Ok(false),
+        Some(Err(e)) => Err(e),
+        None => Ok(true),
+    }
+}
+
+/// Transitions newer than this are highlighted in the migration watch view
+const TRANSITION_HIGHLIGHT_SECS: i64 = 2;
+
+/// Full-screen table of migrations for `watch migrations`, redrawn in place
+pub struct MigrationWatchUI {
+    terminal_width: u16,
+    events: EventStream,
+}
+
+impl Drop for MigrationWatchUI {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+impl MigrationWatchUI {
+    pub fn new() -> io::Result<Self> {
+        let (width, _) = terminal::size()?;
+        Ok(Self { terminal_width: width, events: EventStream::new() })
+    }
+
+    pub fn enter(&mut self) -> io::Result<()> {
+        enter_watch_screen()
+    }
+
+    pub fn exit(&mut self) -> io::Result<()> {
+        restore_terminal();
+        Ok(())
+    }
+
+    /// Wait for the next key or resize; true when the user asked to leave (q, Esc or Ctrl+C)
+    pub async fn next_quit(&mut self) -> io::Result<bool> {
+        next_watch_event(&mut self.events, &mut self.terminal_width).await
+    }
+
+    pub fn draw(&mut self, watch: &nhi::migration_manager::MigrationWatch) -> io::Result<()> {
+        let now = chrono::Utc::now();
+        let width = self.terminal_width as usize;
+        let mut stdout = io::stdout();
+
+        queue!(
+            stdout,
+            Clear(ClearType::All),
+            MoveTo(0, 0),
+            SetForegroundColor(Color::Cyan),
+            Print(format!("┌─ Migrations ({}) ", watch.migrations().len())),
+            Print("─".repeat(width.saturating_sub(18))),
+            MoveTo(0, 1),
+            Print("│ Press q to return"),
+            MoveTo(0, 2),
+            Print(format!(
+                "{:<10} {:<10} {:<10} {:<20} {:>9} {:>10}  {}",
+                "MIGRATION", "INSTANCE", "TARGET", "STATUS", "PROGRESS", "IN PHASE", "PHASES"
+            )),
+            ResetColor,
+        )?;
+
+        for (row, watched) in watch.migrations().iter().enumerate() {
+            let m = &watched.migration;
+            let status = match m.status {
+                nhi::migration_manager::MigrationStatus::Failed(ref reason) => format!("Failed: {}", reason),
+                nhi::migration_manager::MigrationStatus::Rejected(ref reason) => format!("Rejected: {}", reason),
+                ref other => format!("{:?}", other),
+            };
+            let progress = m.progress_percent().map_or("-".to_string(), |p| format!("{}%", p));
+            let phases: Vec<String> = watched
+                .phases
+                .iter()
+                .map(|(phase, elapsed)| format!("{:?} {}", phase, format_elapsed(*elapsed)))
+                .collect();
+            let line = format!(
+                "{:<10} {:<10} {:<10} {:<20} {:>9} {:>10}  {}",
+                &m.migration_id.to_string()[..8],
+                &m.instance_id.to_string()[..8],
+                &m.target_node_id.to_string()[..8],
+                status.chars().take(20).collect::<String>(),
+                progress,
+                format_elapsed(watched.phase_elapsed(now)),
+                phases.join(", ")
+            );
+
+            let color = match m.status {
+                nhi::migration_manager::MigrationStatus::Completed => Color::Green,
+                nhi::migration_manager::MigrationStatus::Failed(_) | nhi::migration_manager::MigrationStatus::Rejected(_) => Color::Red,
+                _ if (now - watched.phase_started_at).num_seconds() < TRANSITION_HIGHLIGHT_SECS => Color::Yellow,
+                _ => Color::Reset,
+            };
+            queue!(
+                stdout,
+                MoveTo(0, 3 + row as u16),
+                SetForegroundColor(color),
+                Print(line.chars().take(width).collect::<String>()),
+                ResetColor,
+            )?;
+        }
+
+        if watch.migrations().is_empty() {
+            queue!(stdout, MoveTo(0, 3), Print("No migrations yet; new ones appear here as they start."))?;
+        }
+
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// Full-screen peer table and change log for `cluster status --watch`, redrawn in place
+pub struct ClusterWatchUI {
+    terminal_width: u16,
+    events: EventStream,
+}
+
+impl Drop for ClusterWatchUI {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+impl ClusterWatchUI {
+    pub fn new() -> io::Result<Self> {
+        let (width, _) = terminal::size()?;
+        Ok(Self { terminal_width: width, events: EventStream::new() })
+    }
+
+    pub fn enter(&mut self) -> io::Result<()> {
+        enter_watch_screen()
+    }
+
+    pub fn exit(&mut self) -> io::Result<()> {
+        restore_terminal();
+        Ok(())
+    }
+
+    /// Wait for the next key or resize; true when the user asked to leave (q, Esc or Ctrl+C)
+    pub async fn next_quit(&mut self) -> io::Result<bool> {
+        next_watch_event(&mut self.events, &mut self.terminal_width).await
+    }
+
+    pub fn draw(&mut self, watch: &nhi::cluster_state::ClusterWatch, interval_secs: u64) -> io::Result<()> {
+        use nhi::cluster_state::{format_rtt, ClusterChange};
+        use nhi::message_protocol::NodeStatus;
+
+        let width = self.terminal_width as usize;
+        let mut stdout = io::stdout();
+
+        queue!(
+            stdout,
+            Clear(ClearType::All),
+            MoveTo(0, 0),
+            SetForegroundColor(Color::Cyan),
+            Print(format!("┌─ Cluster ({} peers) ", watch.peers().len())),
+            Print("─".repeat(width.saturating_sub(20))),
+            MoveTo(0, 1),
+            Print(format!(
+                "│ Refreshing every {}s, last at {}. Press q to return",
+                interval_secs,
+                watch.refreshed_at().map_or("-".to_string(), |at| at.with_timezone(&chrono::Local).format("%H:%M:%S").to_string())
+            )),
+            MoveTo(0, 2),
+            Print(format!("{:<10} {:<20} {:<22} {:<14} {:>10}", "NODE", "NAME", "ADDRESS", "STATUS", "RTT")),
+            ResetColor,
+        )?;
+
+        let mut row = 3u16;
+        for peer in watch.peers() {
+            let line = format!(
+                "{:<10} {:<20} {:<22} {:<14} {:>10}",
+                &peer.node_id.to_string()[..8],
+                peer.name.chars().take(20).collect::<String>(),
+                peer.address.map_or("not connected".to_string(), |address| address.to_string()),
+                format!("{:?}", peer.status),
+                format_rtt(peer.rtt)
+            );
+            let color = match peer.status {
+                NodeStatus::Online if peer.address.is_some() => Color::Reset,
+                NodeStatus::Online | NodeStatus::Connecting => Color::Yellow,
+                NodeStatus::Offline | NodeStatus::Disconnecting => Color::Red,
+            };
+            queue!(
+                stdout,
+                MoveTo(0, row),
+                SetForegroundColor(color),
+                Print(line.chars().take(width).collect::<String>()),
+                ResetColor,
+            )?;
+            row += 1;
+        }
+        if watch.peers().is_empty() {
+            queue!(stdout, MoveTo(0, row), Print("No peers known; joins appear here as they happen."))?;
+            row += 1;
+        }
+
+        queue!(
+            stdout,
+            MoveTo(0, row + 1),
+            SetForegroundColor(Color::Cyan),
+            Print("Changes:"),
+            ResetColor,
+        )?;
+        row += 2;
+        if watch.events().is_empty() {
+            queue!(stdout, MoveTo(0, row), Print("  none since the watch started"))?;
+        }
+        for event in watch.events().iter().rev() {
+            let color = match event.change {
+                ClusterChange::Joined => Color::Green,
+                ClusterChange::Left => Color::Red,
+                ClusterChange::StatusChanged { .. } | ClusterChange::RttChanged { .. } => Color::Yellow,
+            };
+            let line = format!("  {} {}", event.at.with_timezone(&chrono::Local).format("%H:%M:%S"), event.describe());
+            queue!(
+                stdout,
+                MoveTo(0, row),
+                SetForegroundColor(color),
+                Print(line.chars().take(width).collect::<String>()),
+                ResetColor,
+            )?;
+            row += 1;
+        }
+
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// Elapsed time as e.g. "4.2s" or "3m05s"
+fn format_elapsed(elapsed: chrono::Duration) -> String {
+    let millis = elapsed.num_milliseconds().max(0);
+    if millis < 60_000 {
+        format!("{}.{}s", millis / 1000, (millis % 1000) / 100)
+    } else {
+        format!("{}m{:02}s", millis / 60_000, (millis % 60_000) / 1000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn paging_clamps_at_both_ends_of_the_buffer() {
+        let mut scroll = ScrollState::default();
+        assert_eq!(scroll.visible_start(25, 10), 15);
+
+        scroll.page_up(25, 10);
+        assert_eq!(scroll.visible_start(25, 10), 5);
+        scroll.page_up(25, 10);
+        scroll.page_up(25, 10);
+        assert_eq!(scroll.top, Some(0));
+
+        scroll.page_down(25, 10);
+        assert_eq!(scroll.top, Some(10));
+        // Reaching the last page follows new output again
+        scroll.page_down(25, 10);
+        assert_eq!(scroll.top, None);
+        scroll.page_down(25, 10);
+        assert_eq!(scroll.visible_start(25, 10), 15);
+
+        // A buffer shorter than a page never scrolls
+        scroll.page_up(4, 10);
+        assert_eq!(scroll.visible_start(4, 10), 0);
+        scroll.page_down(4, 10);
+        assert_eq!(scroll.top, None);
+    }
+
+    #[test]
+    fn search_wraps_around_in_both_directions() {
+        let output = lines(&["error one", "ok", "error two", "ok", "error three"]);
+        let mut scroll = ScrollState::default();
+        scroll.start_search();
+        scroll.query = Some("error".to_string());
+
+        // Older matches from the newest, wrapping back to the newest
+        for expected in [4, 2, 0, 4] {
+            assert!(scroll.search_older(&output, 2));
+            assert_eq!(scroll.current_match, Some(expected));
+        }
+        // Newer matches wrap to the oldest
+        for expected in [0, 2, 4, 0] {
+            assert!(scroll.search_newer(&output, 2));
+            assert_eq!(scroll.current_match, Some(expected));
+        }
+
+        scroll.query = Some("missing".to_string());
+        assert!(!scroll.search_newer(&output, 2));
+        assert_eq!(scroll.current_match, Some(0));
+    }
+
+    /// An attach session that has taken the terminal, without needing a real one
+    fn attached_ui() -> AttachUI {
+        TERMINAL_TAKEN.store(true, Ordering::SeqCst);
+        AttachUI {
+            terminal_height: 24,
+            terminal_width: 80,
+            output_lines: Vec::new(),
+            input_buffer: String::new(),
+            cursor_pos: 0,
+            scroll: ScrollState::default(),
+            search_failed: false,
+        }
+    }
+
+    #[test]
+    fn the_terminal_is_restored_when_the_attach_loop_fails_or_panics() {
+        fn attach_loop() -> io::Result<()> {
+            let _ui = attached_ui();
+            Err(io::Error::other("output stream broke"))
+        }
+        assert!(attach_loop().is_err());
+        assert!(!TERMINAL_TAKEN.load(Ordering::SeqCst), "an error left the terminal in attach mode");
+
+        let panicked = std::panic::catch_unwind(|| {
+            let _ui = attached_ui();
+            panic!("attach loop panicked");
+        });
+        assert!(panicked.is_err());
+        assert!(!TERMINAL_TAKEN.load(Ordering::SeqCst), "a panic left the terminal in attach mode");
+
+        // An explicit exit restores it once; the drop that follows has nothing left to do
+        let mut ui = attached_ui();
+        ui.exit_attach_mode().ok();
+        assert!(!TERMINAL_TAKEN.load(Ordering::SeqCst));
+    }
+}
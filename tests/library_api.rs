@@ -0,0 +1,50 @@
+//! Embedding NHI through the public builder API, without CRIU or networking.
+
+use nhi::{InstanceStatus, NhiBuilder, PrivilegeCommand};
+use std::path::Path;
+use std::sync::Once;
+use std::time::Duration;
+
+/// Instances keep their state under a relative `instances/` directory; keep it out of the tree
+fn use_scratch_dir() {
+    static ENTER: Once = Once::new();
+    ENTER.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("nhi-api-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::env::set_current_dir(&dir).unwrap();
+    });
+}
+
+#[tokio::test]
+async fn builder_starts_and_stops_an_instance() {
+    use_scratch_dir();
+    let nhi = NhiBuilder::new().privilege_command(PrivilegeCommand::None).build().unwrap();
+
+    let instance_id = nhi.start_instance("sleep", &["60".to_string()]).await.unwrap();
+    let pid = {
+        let manager = nhi.instance_manager.lock().await;
+        let instance = manager.get_instance_by_id(&instance_id).unwrap();
+        assert_eq!(instance.status, InstanceStatus::Running);
+        instance.pid.unwrap()
+    };
+    assert!(Path::new(&format!("/proc/{}", pid)).exists());
+
+    nhi.stop_instance(&instance_id).await.unwrap();
+    let manager = nhi.instance_manager.lock().await;
+    assert_eq!(manager.get_instance_by_id(&instance_id).unwrap().status, InstanceStatus::Stopped);
+    drop(manager);
+    for _ in 0..50 {
+        if !Path::new(&format!("/proc/{}", pid)).exists() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("process {} outlived its instance", pid);
+}
+
+#[test]
+fn builder_rejects_invalid_criu_args() {
+    assert!(NhiBuilder::new().criu_dump_args(vec!["leave-running".to_string()]).build().is_err());
+    assert!(NhiBuilder::new().criu_restore_args(vec!["-D".to_string(), "/tmp".to_string()]).build().is_err());
+    assert!(NhiBuilder::new().criu_dump_args(vec!["--ext-unix-sk".to_string()]).build().is_ok());
+}
@@ -0,0 +1,257 @@
+//! End-to-end control-plane tests: two in-process nodes over loopback.
+//!
+//! The checkpoint engine is faked, so these run without CRIU. A dump records the PID
+//! and stops the process unless asked to leave it running; a restore starts a fresh
+//! `sleep` and reports it through the pidfile, which is all the orchestration looks at.
+//! Build with `--features criu-e2e` to drive the real CRIU instead (path from `NHI_E2E_CRIU`,
+//! default `/usr/local/bin/criu`); that needs the privileges a CRIU dump needs.
+
+use nhi::checkpoint_engine::{CheckpointEngine, DumpRequest, EngineOutput, RestoreRequest};
+use nhi::message_protocol::NetworkConfig;
+use nhi::migration_manager::{MigrationManager, MigrationOptions};
+use nhi::{InstanceManager, InstanceStatus, NodeManager, ProcessManager, ShadowInstanceManager};
+use std::future::Future;
+use std::sync::{Arc, Once};
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+use uuid::Uuid;
+
+/// Instances keep their state under a relative `instances/` directory; keep it out of the tree
+fn use_scratch_dir() {
+    static ENTER: Once = Once::new();
+    ENTER.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("nhi-e2e-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::env::set_current_dir(&dir).unwrap();
+    });
+}
+
+/// Stands in for CRIU: processes are "dumped" by stopping them and "restored" as a new `sleep`
+#[cfg_attr(feature = "criu-e2e", allow(dead_code))]
+struct FakeEngine;
+
+#[cfg_attr(feature = "criu-e2e", allow(dead_code))]
+impl FakeEngine {
+    fn ok() -> EngineOutput {
+        EngineOutput { success: true, exit_code: Some(0), stdout: String::new(), stderr: String::new() }
+    }
+}
+
+impl CheckpointEngine for FakeEngine {
+    fn name(&self) -> &str {
+        "fake"
+    }
+
+    fn check(&self) -> nhi::types::Result<EngineOutput> {
+        Ok(Self::ok())
+    }
+
+    fn dump(&self, request: &DumpRequest) -> nhi::types::Result<EngineOutput> {
+        std::fs::create_dir_all(&request.images_dir)?;
+        std::fs::write(request.images_dir.join("pstree.img"), request.pid.to_string())?;
+        if !request.leave_running {
+            let _ = nix::sys::signal::kill(nix::unistd::Pid::from_raw(request.pid as i32), nix::sys::signal::Signal::SIGKILL);
+        }
+        Ok(Self::ok())
+    }
+
+    fn pre_dump(&self, request: &DumpRequest) -> nhi::types::Result<EngineOutput> {
+        std::fs::create_dir_all(&request.images_dir)?;
+        Ok(Self::ok())
+    }
+
+    fn restore(&self, request: &RestoreRequest) -> nhi::types::Result<EngineOutput> {
+        let child = std::process::Command::new("sleep").arg("30").spawn()?;
+        if let Some(pidfile) = &request.pidfile {
+            std::fs::write(pidfile, child.id().to_string())?;
+        }
+        Ok(Self::ok())
+    }
+}
+
+/// One node: local managers plus networking, shadows and migration, wired like `main`
+struct Node {
+    instance_manager: Arc<Mutex<InstanceManager>>,
+    process_manager: Arc<ProcessManager>,
+    node_manager: Arc<NodeManager>,
+    shadow_manager: Arc<RwLock<ShadowInstanceManager>>,
+    migration_manager: Arc<MigrationManager>,
+}
+
+impl Node {
+    async fn start(engine: Arc<dyn CheckpointEngine>) -> Node {
+        use_scratch_dir();
+        let instance_manager = Arc::new(Mutex::new(InstanceManager::new()));
+        let process_manager = Arc::new(ProcessManager::new());
+
+        let config = NetworkConfig {
+            listen_addr: "127.0.0.1:0".parse().unwrap(),
+            discovery_enabled: false,
+            ..NetworkConfig::default()
+        };
+        let node_manager = Arc::new(NodeManager::new(config).unwrap());
+        node_manager.start().await.unwrap();
+
+        let mut shadow_manager = ShadowInstanceManager::new_with_engine(
+            node_manager.node_id(),
+            instance_manager.clone(),
+            process_manager.clone(),
+            engine.clone(),
+        );
+        shadow_manager.set_network_sender(node_manager.network_manager().get_sender());
+        let shadow_manager = Arc::new(RwLock::new(shadow_manager));
+
+        let mut migration_manager = MigrationManager::new_with_engine(
+            node_manager.node_id(),
+            node_manager.network_manager().clone(),
+            instance_manager.clone(),
+            process_manager.clone(),
+            engine,
+        );
+        migration_manager.set_shadow_manager(shadow_manager.clone());
+        migration_manager.start().await.unwrap();
+        let migration_manager = Arc::new(migration_manager);
+
+        node_manager.set_shadow_manager(shadow_manager.clone()).await;
+        node_manager.set_migration_manager(migration_manager.clone()).await;
+        process_manager.set_shadow_manager(shadow_manager.clone()).await;
+        // Re-advertisements are what tell a migration source its instance now runs elsewhere
+        ShadowInstanceManager::start_reconcile_task(shadow_manager.clone(), Duration::from_secs(1));
+
+        Node { instance_manager, process_manager, node_manager, shadow_manager, migration_manager }
+    }
+
+    async fn connect(&self, peer: &Node) {
+        let peer_id = self.node_manager.connect_to_peer(peer.node_manager.local_node_info().listen_addr).await.unwrap();
+        assert_eq!(peer_id, peer.node_manager.node_id());
+    }
+
+    /// Start a long-running program and announce it to the cluster
+    async fn start_instance(&self) -> Uuid {
+        let mut instance_manager = self.instance_manager.lock().await;
+        let short_id = instance_manager
+            .start_instance("sleep".to_string(), vec!["30".to_string()], self.process_manager.clone())
+            .await
+            .unwrap();
+        let instance = instance_manager.get_instance_by_id(&short_id).unwrap().clone();
+        drop(instance_manager);
+        self.shadow_manager.read().await.broadcast_instance_creation(&instance).await.unwrap();
+        instance.id
+    }
+
+    async fn status_of(&self, instance_id: Uuid) -> Option<InstanceStatus> {
+        let instance_manager = self.instance_manager.lock().await;
+        instance_manager.get_instance_by_id(&instance_id.to_string()).map(|instance| instance.status.clone())
+    }
+}
+
+/// Poll `check` until it holds or `timeout` passes
+async fn eventually<F, Fut>(timeout: Duration, mut check: F) -> bool
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let deadline = tokio::time::Instant::now() + timeout;
+    while tokio::time::Instant::now() < deadline {
+        if check().await {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    false
+}
+
+#[cfg(feature = "criu-e2e")]
+fn engine() -> Arc<dyn CheckpointEngine> {
+    let criu_path = std::env::var_os("NHI_E2E_CRIU").unwrap_or_else(|| "/usr/local/bin/criu".into());
+    Arc::new(nhi::CriuEngine::privileged(criu_path))
+}
+
+#[cfg(not(feature = "criu-e2e"))]
+fn engine() -> Arc<dyn CheckpointEngine> {
+    Arc::new(FakeEngine)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn running_instance_gets_a_shadow_on_the_peer() {
+    let a = Node::start(engine()).await;
+    let b = Node::start(engine()).await;
+    a.connect(&b).await;
+
+    let instance_id = a.start_instance().await;
+
+    assert!(
+        eventually(Duration::from_secs(10), || async { b.status_of(instance_id).await == Some(InstanceStatus::Shadow) }).await,
+        "node B never created a shadow of {}",
+        instance_id
+    );
+    assert_eq!(a.status_of(instance_id).await, Some(InstanceStatus::Running));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn migration_moves_the_instance_to_the_peer() {
+    let a = Node::start(engine()).await;
+    let b = Node::start(engine()).await;
+    a.connect(&b).await;
+
+    let instance_id = a.start_instance().await;
+    assert!(eventually(Duration::from_secs(10), || async { b.status_of(instance_id).await == Some(InstanceStatus::Shadow) }).await);
+
+    let migration_id = a.migration_manager
+        .migrate_instance(&instance_id.to_string(), b.node_manager.node_id(), MigrationOptions::default())
+        .await
+        .unwrap();
+
+    assert!(
+        eventually(Duration::from_secs(30), || async { b.status_of(instance_id).await == Some(InstanceStatus::Running) }).await,
+        "instance never came up on node B; migration is {:?}",
+        a.migration_manager.get_migration_status(migration_id).await
+    );
+    assert!(eventually(Duration::from_secs(10), || async { a.status_of(instance_id).await == Some(InstanceStatus::Shadow) }).await);
+}
+
+/// A process is up if it exists and is not a zombie waiting to be reaped
+#[cfg(feature = "criu-e2e")]
+fn process_running(pid: u32) -> bool {
+    std::fs::read_to_string(format!("/proc/{}/stat", pid))
+        .ok()
+        .and_then(|stat| stat.rsplit(')').next().map(|rest| !rest.trim_start().starts_with('Z')))
+        .unwrap_or(false)
+}
+
+/// Only a real dump captures the child a shell forked, so this runs with `--features criu-e2e` alone
+#[cfg(feature = "criu-e2e")]
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn checkpoint_and_restore_bring_back_a_forked_child() {
+    use_scratch_dir();
+    let mut instance_manager = InstanceManager::new();
+    let process_manager = Arc::new(ProcessManager::new());
+    let criu_manager = Arc::new(nhi::CriuManager::new_with_engine(engine()));
+    let args = vec!["-c".to_string(), "sleep 30 & wait".to_string()];
+    let short_id = instance_manager.start_instance("sh".to_string(), args, process_manager.clone()).await.unwrap();
+    let pid = instance_manager.get_instance_by_id(&short_id).unwrap().pid.unwrap();
+    let children = format!("/proc/{}/task/{}/children", pid, pid);
+    assert!(
+        eventually(Duration::from_secs(5), || {
+            let forked = std::fs::read_to_string(&children).is_ok_and(|children| !children.trim().is_empty());
+            async move { forked }
+        }).await,
+        "the shell never forked its child"
+    );
+
+    instance_manager
+        .checkpoint_instance(&short_id, "with-child", false, true, criu_manager.clone(), process_manager.clone())
+        .await
+        .unwrap();
+    instance_manager
+        .restore_instance_to_existing(&short_id, "with-child", &nhi::types::RestoreOptions::default(), criu_manager, process_manager.clone())
+        .await
+        .unwrap();
+
+    let restored = instance_manager.get_instance_by_id(&short_id).unwrap().clone();
+    let parent = restored.pid.unwrap();
+    assert_eq!(restored.child_pids.len(), 1, "{:?}", restored.child_pids);
+    assert!(process_running(parent), "restored shell {} is not running", parent);
+    assert!(process_running(restored.child_pids[0]), "restored child {} is not running", restored.child_pids[0]);
+    instance_manager.stop_instance(&short_id, process_manager).await.unwrap();
+}